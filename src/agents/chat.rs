@@ -1,7 +1,40 @@
 use anyhow::Result;
-use crate::llm::{OllamaClient, ChatMessage};
-use crate::storage::MemoryStore;
-use crate::storage::memory::MemoryType;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use crate::embeddings::EmbeddingClient;
+use crate::llm::{estimate_tokens, LlmClient, ChatMessage};
+use crate::prompts::{PromptLibrary, Verbosity};
+use crate::rag::RagRetriever;
+use crate::scoring::{score_memory, MemoryScorer};
+use crate::storage::{CodebaseIndex, MemoryStore, TranscriptStore};
+use crate::storage::memory::{extract_tags, Memory, MemoryType};
+
+/// Memories injected into the system prompt for each message
+const RELEVANT_MEMORIES: usize = 5;
+
+/// Memories less similar to the message than this are left out
+const MIN_MEMORY_SIMILARITY: f32 = 0.5;
+
+/// Memories from other agents embedded per chat turn
+const MEMORY_EMBED_BATCH: usize = 16;
+
+/// Importance of a chat exchange when no scorer rates it
+const CONVERSATION_IMPORTANCE: f32 = 0.5;
+
+/// Opens the message carrying the files pinned with `/pin`
+const PINNED_HEADING: &str = "Files the user pinned to this conversation:";
+
+/// Opens the message carrying code retrieved for the current message
+const CODE_CONTEXT_HEADING: &str = "Code from the project that may be relevant to the next message:";
+
+/// Opens the message that stands in for summarized turns
+const SUMMARY_HEADING: &str = "Summary of the conversation so far:";
+
+const SUMMARIZE_SYSTEM_PROMPT: &str = r#"You summarize the start of a conversation between a user and a coding assistant,
+so it can continue without the full transcript. Keep the user's goals, decisions,
+constraints and preferences, names of files, functions and commands, and any
+open questions. Leave out pleasantries and code that can be asked for again.
+Reply with the summary only, in short bullet points."#;
 
 const CHAT_SYSTEM_PROMPT: &str = r#"You are Sovereign, a local-first AI code assistant.
 You run entirely on the user's machine - their code never leaves their device.
@@ -21,18 +54,55 @@ Important: You are running locally via Ollama. This means:
 - The user owns all data and interactions
 "#;
 
+/// `[chat]` section of `sovereign.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatConfig {
+    /// Summarize the oldest turns once the conversation fills this share of the context window
+    pub summarize_at: f32,
+    /// Latest messages never summarized
+    pub keep_recent: usize,
+    /// Show the model the indexed code most relevant to each message
+    pub rag: bool,
+    /// Most chunks of code shown per message
+    pub rag_chunks: usize,
+    /// Most tokens of code shown per message
+    pub rag_max_tokens: usize,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self { summarize_at: 0.75, keep_recent: 6, rag: true, rag_chunks: 4, rag_max_tokens: 1500 }
+    }
+}
+
 pub struct ChatAgent {
-    pub llm: OllamaClient,
+    pub llm: LlmClient,
     memory: MemoryStore,
     conversation: Vec<ChatMessage>,
     project_context: Option<String>,
+    prompts: PromptLibrary,
+    /// Enables retrieving memories by relevance instead of recency
+    embedding_client: Option<EmbeddingClient>,
+    /// Full exchanges, for `/ask --include-chats`
+    transcripts: Option<TranscriptStore>,
+    project: Option<String>,
+    /// Rates and tags exchanges before they are remembered (`[scoring]`)
+    scorer: Option<MemoryScorer>,
+    /// Finds the indexed code relevant to each message (`[chat] rag`)
+    retriever: Option<RagRetriever>,
+    /// Code retrieved for the last message, for `/context`
+    last_code_context: Option<ChatMessage>,
+    /// Files sent with every message, read afresh each time (`/pin`)
+    pinned: Vec<PathBuf>,
+    config: ChatConfig,
 }
 
 impl ChatAgent {
-    pub fn new(llm: OllamaClient, memory: MemoryStore) -> Self {
+    pub fn new(llm: LlmClient, memory: MemoryStore, prompts: PromptLibrary) -> Self {
         let conversation = vec![ChatMessage {
             role: "system".to_string(),
-            content: CHAT_SYSTEM_PROMPT.to_string(),
+            content: prompts.system("chat", CHAT_SYSTEM_PROMPT),
         }];
 
         Self {
@@ -40,16 +110,81 @@ impl ChatAgent {
             memory,
             conversation,
             project_context: None,
+            prompts,
+            embedding_client: None,
+            transcripts: None,
+            project: None,
+            scorer: None,
+            retriever: None,
+            last_code_context: None,
+            pinned: Vec::new(),
+            config: ChatConfig::default(),
+        }
+    }
+
+    pub fn set_embedding_client(&mut self, client: EmbeddingClient) {
+        self.embedding_client = Some(client);
+    }
+
+    pub fn set_transcripts(&mut self, transcripts: TranscriptStore) {
+        self.transcripts = Some(transcripts);
+    }
+
+    /// Answer with another model, as `/model` switches to; the conversation carries over
+    pub fn set_llm(&mut self, llm: LlmClient) {
+        self.llm = llm;
+    }
+
+    pub fn set_scorer(&mut self, scorer: Option<MemoryScorer>) {
+        self.scorer = scorer;
+    }
+
+    pub fn set_config(&mut self, config: ChatConfig) {
+        self.config = config;
+    }
+
+    pub fn set_retriever(&mut self, retriever: RagRetriever) {
+        self.retriever = Some(retriever);
+    }
+
+    /// Project recorded on memories and transcripts this agent stores
+    pub fn set_project_name(&mut self, name: &str) {
+        self.memory.set_default_project(name);
+        self.project = Some(name.to_string());
+    }
+
+    /// Switch the answer style; the system prompt of the ongoing conversation follows
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.change_prompts(|prompts| prompts.set_verbosity(verbosity));
+    }
+
+    /// Use prompts reloaded from sovereign.toml; the system prompt of the ongoing conversation follows
+    pub fn set_prompts(&mut self, prompts: PromptLibrary) {
+        self.change_prompts(|current| *current = prompts);
+    }
+
+    fn change_prompts(&mut self, change: impl FnOnce(&mut PromptLibrary)) {
+        let old_base = self.base_prompt();
+        change(&mut self.prompts);
+        let new_base = self.base_prompt();
+
+        if let Some(system) = self.conversation.first_mut() {
+            system.content = system.content.replacen(&old_base, &new_base, 1);
         }
     }
 
+    /// The system prompt every conversation starts with
+    fn base_prompt(&self) -> String {
+        self.prompts.system("chat", CHAT_SYSTEM_PROMPT)
+    }
+
     pub fn set_project_context(&mut self, context: String) {
         self.project_context = Some(context.clone());
 
         // Add context to system message
         let system_with_context = format!(
             "{}\n\nCurrent project context:\n{}",
-            CHAT_SYSTEM_PROMPT, context
+            self.base_prompt(), context
         );
 
         if !self.conversation.is_empty() {
@@ -60,21 +195,58 @@ impl ChatAgent {
     pub fn add_memory_context(&mut self) {
         // Add recent memories to context
         if let Ok(memories) = self.memory.get_recent(5) {
-            if !memories.is_empty() {
-                let memory_context: String = memories
-                    .iter()
-                    .map(|m| format!("- {}", m.content))
-                    .collect::<Vec<_>>()
-                    .join("\n");
+            self.conversation[0].content = self.system_prompt(&memories, "Recent memories");
+        }
+    }
+
+    /// Replace the memories in the system prompt with those most relevant to `message`
+    ///
+    /// Keeps the recent memories from `add_memory_context` when no embedding
+    /// model is reachable.
+    async fn add_relevant_memories(&mut self, message: &str) {
+        let Some(client) = self.embedding_client.clone() else {
+            return;
+        };
+        let Ok(query_embedding) = client.embed(message).await else {
+            return;
+        };
+        let model = client.active_model().await;
 
-                let system = &mut self.conversation[0].content;
-                system.push_str(&format!(
-                    "\n\nRecent memories:\n{}",
-                    memory_context
-                ));
+        // Memories written by other agents are embedded on the next turn
+        if let Ok(pending) = self.memory.unembedded(&model, MEMORY_EMBED_BATCH) {
+            for memory in pending {
+                self.embed_memory(&client, &model, &memory).await;
             }
         }
 
+        if let Ok(relevant) = self.memory.search_similar(&query_embedding, &model, RELEVANT_MEMORIES, MIN_MEMORY_SIMILARITY) {
+            let memories: Vec<Memory> = relevant.into_iter().map(|(m, _)| m).collect();
+            self.conversation[0].content = self.system_prompt(&memories, "Relevant memories");
+        }
+    }
+
+    async fn embed_memory(&self, client: &EmbeddingClient, model: &str, memory: &Memory) {
+        if let Ok(embedding) = client.embed(&memory.content).await {
+            let _ = self.memory.store_embedding(&memory.id, model, &embedding);
+        }
+    }
+
+    /// Base prompt plus project context, the given memories and stored preferences
+    fn system_prompt(&self, memories: &[Memory], heading: &str) -> String {
+        let mut system = match self.project_context {
+            Some(ref context) => format!("{}\n\nCurrent project context:\n{}", self.base_prompt(), context),
+            None => self.base_prompt(),
+        };
+
+        if !memories.is_empty() {
+            let memory_context: String = memories
+                .iter()
+                .map(|m| format!("- {}", m.content))
+                .collect::<Vec<_>>()
+                .join("\n");
+            system.push_str(&format!("\n\n{}:\n{}", heading, memory_context));
+        }
+
         // Add user preferences
         if let Ok(preferences) = self.memory.get_by_type(MemoryType::Preference, 5) {
             if !preferences.is_empty() {
@@ -83,25 +255,31 @@ impl ChatAgent {
                     .map(|m| format!("- {}", m.content))
                     .collect::<Vec<_>>()
                     .join("\n");
-
-                let system = &mut self.conversation[0].content;
-                system.push_str(&format!(
-                    "\n\nUser preferences:\n{}",
-                    pref_context
-                ));
+                system.push_str(&format!("\n\nUser preferences:\n{}", pref_context));
             }
         }
+
+        system
     }
 
-    pub async fn chat(&mut self, message: &str) -> Result<String> {
+    /// Answer `message`, showing the model the code of `index` most relevant to it
+    pub async fn chat(&mut self, message: &str, index: Option<&CodebaseIndex>) -> Result<String> {
+        self.add_relevant_memories(message).await;
+        let code_context = self.code_context(message, index).await;
+
         // Add user message
         self.conversation.push(ChatMessage {
             role: "user".to_string(),
             content: message.to_string(),
         });
+        self.last_code_context = code_context;
+        let pinned = self.pinned_context();
+        let extra: Vec<ChatMessage> = pinned.iter().chain(&self.last_code_context).cloned().collect();
+        self.summarize_if_full(estimate_tokens(&extra)).await;
 
-        // Get response
-        let response = self.llm.chat(&self.conversation, true).await?;
+        let response = self.llm
+            .chat_with(&self.request(pinned), true, &self.prompts.options("chat"))
+            .await?;
 
         // Add assistant response to conversation
         self.conversation.push(ChatMessage {
@@ -109,24 +287,218 @@ impl ChatAgent {
             content: response.clone(),
         });
 
-        // Store conversation in memory (condensed)
-        self.memory.remember(
+        // Store conversation in memory (condensed), tagged and scored from the full exchange
+        let exchange_text = format!("User: {}\nAssistant: {}", message, response);
+        let score = score_memory(self.scorer.as_ref(), &exchange_text, MemoryType::Conversation, CONVERSATION_IMPORTANCE).await;
+        let mut tags = vec!["chat".to_string()];
+        tags.extend(extract_tags(&exchange_text, None));
+        score.add_tags(&mut tags);
+        let memory = self.memory.remember(
             &format!("User: {} | Assistant: {}",
                 message.chars().take(100).collect::<String>(),
                 response.chars().take(100).collect::<String>()
             ),
             MemoryType::Conversation,
             None,
-            vec!["chat".to_string()],
-            0.5,
+            tags,
+            score.importance,
         )?;
 
+        // The full exchange is kept too, so the explanation can be retrieved later
+        let exchange = match self.transcripts {
+            Some(ref transcripts) => Some(transcripts.record(self.project.as_deref(), message, &response)?),
+            None => None,
+        };
+
+        // Embed on write so later turns can retrieve it by relevance
+        if let Some(client) = self.embedding_client.clone() {
+            let model = client.active_model().await;
+            self.embed_memory(&client, &model, &memory).await;
+
+            if let (Some(transcripts), Some(exchange)) = (&self.transcripts, &exchange) {
+                if let Ok(embedding) = client.embed(&exchange.embedding_text()).await {
+                    let _ = transcripts.store_embedding(&exchange.id, &model, &embedding);
+                }
+            }
+        }
+
         // Detect and store preferences
         self.detect_preferences(message, &response)?;
 
         Ok(response)
     }
 
+    /// The messages sent for the next reply: the conversation with the pinned files
+    /// after the system prompt, and the code retrieved for the last message before it
+    ///
+    /// Retrieved code goes with one message only; the next one retrieves its own.
+    fn request(&self, pinned: Option<ChatMessage>) -> Vec<ChatMessage> {
+        let mut messages = self.conversation.clone();
+        if let Some(context) = self.last_code_context.clone() {
+            messages.insert(messages.len() - 1, context);
+        }
+        if let Some(pinned) = pinned {
+            messages.insert(1, pinned);
+        }
+        messages
+    }
+
+    /// Send `path` with every message from now on; false if it already is
+    pub fn pin(&mut self, path: &Path) -> bool {
+        if self.pinned.iter().any(|p| p == path) {
+            return false;
+        }
+        self.pinned.push(path.to_path_buf());
+        true
+    }
+
+    /// Stop sending `path`, or every pinned file when `None`; the files unpinned
+    pub fn unpin(&mut self, path: Option<&Path>) -> Vec<PathBuf> {
+        let (unpinned, kept) = self.pinned.drain(..).partition(|p| path.is_none_or(|path| p == path));
+        self.pinned = kept;
+        unpinned
+    }
+
+    /// The pinned files as they are now on disk
+    fn pinned_context(&self) -> Option<ChatMessage> {
+        if self.pinned.is_empty() {
+            return None;
+        }
+        let mut content = PINNED_HEADING.to_string();
+        for path in &self.pinned {
+            match std::fs::read_to_string(path) {
+                Ok(code) => content.push_str(&format!("\n--- {} ---\n{}\n", path.display(), code)),
+                Err(e) => content.push_str(&format!("\n--- {} --- (could not be read: {})\n", path.display(), e)),
+            }
+        }
+        Some(ChatMessage { role: "system".to_string(), content })
+    }
+
+    /// What the next message is sent with, part by part with estimated token counts:
+    /// the system prompt in full (project context, memories and preferences included),
+    /// pinned files, the code retrieved for the last message and the conversation
+    pub fn context_report(&self) -> String {
+        let tokens = |message: &ChatMessage| estimate_tokens(std::slice::from_ref(message));
+        let (_, available) = self.context_usage();
+        let pinned = self.pinned_context();
+
+        let mut report = format!("System prompt (~{} tokens):\n", tokens(&self.conversation[0]));
+        for line in self.conversation[0].content.lines() {
+            report.push_str(&format!("  {}\n", line));
+        }
+
+        if let Some(ref pinned) = pinned {
+            report.push_str(&format!("\nPinned files (~{} tokens):\n", tokens(pinned)));
+            for path in &self.pinned {
+                report.push_str(&format!("  {}\n", path.display()));
+            }
+        }
+
+        if let Some(ref context) = self.last_code_context {
+            report.push_str(&format!("\nRetrieved for the last message (~{} tokens):\n", tokens(context)));
+            for chunk in context.content.lines().filter(|l| l.starts_with("--- ") && l.ends_with(" ---")) {
+                report.push_str(&format!("  {}\n", chunk.trim_matches(|c| c == '-' || c == ' ')));
+            }
+        }
+
+        let history = &self.conversation[1..];
+        report.push_str(&format!(
+            "\nConversation: {} messages (~{} tokens){}\n",
+            history.len(),
+            estimate_tokens(history),
+            if history.first().is_some_and(is_summary) { ", starting with a summary of earlier turns" } else { "" }
+        ));
+
+        let total = estimate_tokens(&self.request(pinned));
+        report.push_str(&format!("\nTotal: ~{} of {} tokens", total, available));
+        report
+    }
+
+    /// A message with the indexed code most relevant to `message`, when `[chat] rag`
+    /// is on and a codebase is indexed
+    ///
+    /// Without embeddings (none built yet, or no embedding model running), keyword
+    /// matches are used on their own.
+    async fn code_context(&self, message: &str, index: Option<&CodebaseIndex>) -> Option<ChatMessage> {
+        let (Some(retriever), Some(index)) = (self.retriever.as_ref(), index) else {
+            return None;
+        };
+        if !self.config.rag {
+            return None;
+        }
+
+        let mut results = match retriever.search(message, index).await {
+            Ok(results) => results,
+            Err(_) => retriever
+                .keyword_search(message, index)
+                .map(|keyword| retriever.rank(message, Vec::new(), keyword))
+                .unwrap_or_default(),
+        };
+        results.truncate(self.config.rag_chunks);
+        let context = retriever.build_context(&results, self.config.rag_max_tokens);
+        if context.trim().is_empty() {
+            return None;
+        }
+        Some(ChatMessage {
+            role: "system".to_string(),
+            content: format!("{}\n{}", CODE_CONTEXT_HEADING, context),
+        })
+    }
+
+    /// Estimated tokens the conversation takes, and the tokens available to it
+    /// once the reply has room
+    pub fn context_usage(&self) -> (usize, usize) {
+        let options = self.prompts.options("chat");
+        let window = self.llm.context_window(&options).saturating_sub(options.max_tokens.unwrap_or(0));
+        (estimate_tokens(&self.conversation), window as usize)
+    }
+
+    /// Fold the oldest turns, and any earlier summary, into one summary message
+    /// once the conversation and `reserved` tokens sent with it fill `summarize_at`
+    /// of the context window
+    ///
+    /// The latest `keep_recent` messages stay word for word. When the model can't
+    /// summarize, the conversation is left whole and Ollama truncates it as before.
+    async fn summarize_if_full(&mut self, reserved: usize) {
+        let (used, available) = self.context_usage();
+        if ((used + reserved) as f32) < available as f32 * self.config.summarize_at {
+            return;
+        }
+        // Cut before a user message, so no reply is kept without its question
+        let mut end = self.conversation.len().saturating_sub(self.config.keep_recent.max(1));
+        while end > 1 && self.conversation[end].role != "user" {
+            end -= 1;
+        }
+        let has_summary = self.conversation.get(1).is_some_and(is_summary);
+        if end <= 1 || (end == 2 && has_summary) {
+            return;
+        }
+
+        let transcript: String = self.conversation[1..end]
+            .iter()
+            .map(|m| match m.role.as_str() {
+                "user" => format!("User: {}", m.content),
+                "assistant" => format!("Assistant: {}", m.content),
+                _ => format!("Earlier summary:\n{}", m.content.trim_start_matches(SUMMARY_HEADING).trim()),
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let prompt = format!("Conversation to summarize:\n\n{}", transcript);
+        let system = self.prompts.system("summarize_chat", SUMMARIZE_SYSTEM_PROMPT);
+
+        match self.llm.generate_with(&prompt, Some(&system), &self.prompts.options("summarize_chat")).await {
+            Ok(summary) if !summary.trim().is_empty() => {
+                let summary = ChatMessage {
+                    role: "system".to_string(),
+                    content: format!("{}\n{}", SUMMARY_HEADING, summary.trim()),
+                };
+                self.conversation.splice(1..end, [summary]);
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("  Could not summarize the earlier conversation: {}", e),
+        }
+    }
+
     fn detect_preferences(&self, user_msg: &str, _response: &str) -> Result<()> {
         let preference_keywords = [
             ("prefer", 0.8),
@@ -154,8 +526,33 @@ impl ChatAgent {
         Ok(())
     }
 
+    /// Put a command and its output into the conversation, so follow-up
+    /// messages can refer to it ("explain all of these callers")
+    pub fn add_command_output(&mut self, command: &str, output: &str) {
+        self.conversation.push(ChatMessage {
+            role: "user".to_string(),
+            content: command.to_string(),
+        });
+        self.conversation.push(ChatMessage {
+            role: "assistant".to_string(),
+            content: output.to_string(),
+        });
+    }
+
     pub fn clear_conversation(&mut self) {
         self.conversation.truncate(1); // Keep system message
+        self.last_code_context = None;
+    }
+
+    /// Messages exchanged so far, including the system prompt
+    pub fn conversation(&self) -> &[ChatMessage] {
+        &self.conversation
+    }
+
+    /// Continue a shared session: replay its messages under this agent's own system prompt
+    pub fn restore_conversation(&mut self, messages: &[ChatMessage]) {
+        self.conversation.truncate(1);
+        self.conversation.extend(messages.iter().filter(|m| m.role != "system" || is_summary(m)).cloned());
     }
 
     #[allow(dead_code)]
@@ -163,3 +560,66 @@ impl ChatAgent {
         self.conversation.len() - 1 // Exclude system message
     }
 }
+
+/// Whether `message` stands in for summarized turns
+fn is_summary(message: &ChatMessage) -> bool {
+    message.role == "system" && message.content.starts_with(SUMMARY_HEADING)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{GenerationOptions, OllamaClient};
+    use crate::test_support;
+
+    #[tokio::test]
+    async fn test_long_chat_rolls_old_turns_into_a_summary() {
+        let dir = tempfile::tempdir().unwrap();
+        test_support::reply_to("Conversation to summarize", "- The user is writing an LRU cache in Rust.");
+
+        // A window the system prompt alone nearly fills
+        let llm = LlmClient::Ollama(OllamaClient::new(test_support::MOCK_MODEL).with_servers(&test_support::servers()))
+            .with_defaults(GenerationOptions { num_ctx: Some(200), ..Default::default() });
+        let mut agent = ChatAgent::new(llm, MemoryStore::new(&dir.path().to_path_buf()).unwrap(), PromptLibrary::default());
+        agent.set_config(ChatConfig { keep_recent: 2, ..Default::default() });
+
+        for question in ["lru question one", "lru question two", "lru question three", "lru question four"] {
+            agent.chat(question, None).await.unwrap();
+        }
+
+        // Two summaries, the second folding in the first; the last two exchanges
+        // are kept as they were
+        let conversation = agent.conversation();
+        assert_eq!(conversation.len(), 6);
+        assert!(is_summary(&conversation[1]));
+        assert!(conversation[1].content.ends_with("- The user is writing an LRU cache in Rust."));
+        assert_eq!(conversation[2].content, "lru question three");
+        assert_eq!(conversation[5].content, test_support::MOCK_REPLY);
+        assert_eq!(test_support::requests_containing("Earlier summary:\n- The user is writing an LRU cache").len(), 1);
+    }
+
+    #[test]
+    fn test_pinned_files_are_sent_and_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.rs");
+        std::fs::write(&file, "// Evict the oldest entry first\n").unwrap();
+        let memory = MemoryStore::new(&dir.path().to_path_buf()).unwrap();
+        let mut agent = ChatAgent::new(LlmClient::Ollama(OllamaClient::new("m")), memory, PromptLibrary::default());
+
+        assert!(agent.pin(&file));
+        assert!(!agent.pin(&file));
+        let request = agent.request(agent.pinned_context());
+        assert_eq!(request.len(), 2);
+        assert!(request[1].content.starts_with(PINNED_HEADING));
+        assert!(request[1].content.contains("Evict the oldest entry first"));
+
+        let report = agent.context_report();
+        assert!(report.starts_with("System prompt (~"));
+        assert!(report.contains("Pinned files (~"));
+        assert!(report.contains(&file.display().to_string()));
+        assert!(report.contains(&format!("of {} tokens", crate::llm::OLLAMA_DEFAULT_NUM_CTX)));
+
+        assert_eq!(agent.unpin(None), vec![file]);
+        assert!(agent.unpin(None).is_empty());
+    }
+}
@@ -1,7 +1,15 @@
 use anyhow::Result;
-use crate::llm::OllamaClient;
-use crate::storage::MemoryStore;
+use crate::llm::LlmClient;
+use crate::git::DiffHunk;
+use crate::postprocess::PostProcessor;
+use crate::prompts::{PromptLibrary, Verbosity};
+use crate::audit::{self, AuditCheck};
+use crate::review::{self, Finding};
+use crate::scoring::{score_memory, MemoryScorer};
+use crate::storage::{CodebaseIndex, MemoryStore};
 use crate::storage::memory::MemoryType;
+use crate::summarize::MapReduce;
+use crate::testgen::{self, TestConventions};
 
 const CODE_SYSTEM_PROMPT: &str = r#"You are an expert code assistant running locally on the user's machine.
 You have access to their codebase and can help with:
@@ -20,14 +28,64 @@ Always be concise but thorough. When writing code:
 You have access to the following context about the codebase.
 "#;
 
+const EDIT_SYSTEM_PROMPT: &str = r#"You edit code by writing a unified diff.
+Reply with the diff only, in a ```diff block: a `--- a/<path>` and `+++ b/<path>` header,
+then `@@` hunks. Copy the unchanged lines around each change exactly, three of them,
+and follow the file's style. Change only what the instruction asks for."#;
+
+const EXPLAIN_ERROR_SYSTEM_PROMPT: &str = r#"You explain compiler and linter errors to the developer who hit them.
+First say in a few sentences what the first error means and what in the code causes it.
+Then give the fix as a unified diff in a ```diff block, with `--- a/<path>` and `+++ b/<path>`
+headers using the paths shown. Copy the unchanged lines around each change exactly, three of them."#;
+
+/// Importance of a code request when no scorer rates it
+const GENERATION_IMPORTANCE: f32 = 0.6;
+
+/// Importance of a refactoring pattern when no scorer rates it
+const REFACTOR_IMPORTANCE: f32 = 0.7;
+
 pub struct CodeAgent {
-    llm: OllamaClient,
+    llm: LlmClient,
     memory: MemoryStore,
+    prompts: PromptLibrary,
+    /// Applied to responses that contain code before they are returned
+    postprocess: PostProcessor,
+    /// Rates and tags requests before they are remembered (`[scoring]`)
+    scorer: Option<MemoryScorer>,
 }
 
 impl CodeAgent {
-    pub fn new(llm: OllamaClient, memory: MemoryStore) -> Self {
-        Self { llm, memory }
+    pub fn new(llm: LlmClient, memory: MemoryStore, prompts: PromptLibrary) -> Self {
+        Self {
+            llm,
+            memory,
+            prompts,
+            postprocess: PostProcessor::for_display(),
+            scorer: None,
+        }
+    }
+
+    /// Answer with another model, as `/model` switches to
+    pub fn set_llm(&mut self, llm: LlmClient) {
+        self.llm = llm;
+    }
+
+    pub fn set_scorer(&mut self, scorer: Option<MemoryScorer>) {
+        self.scorer = scorer;
+    }
+
+    /// Project recorded on memories this agent stores
+    pub fn set_project_name(&mut self, name: &str) {
+        self.memory.set_default_project(name);
+    }
+
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.prompts.set_verbosity(verbosity);
+    }
+
+    /// Use prompts reloaded from sovereign.toml
+    pub fn set_prompts(&mut self, prompts: PromptLibrary) {
+        self.prompts = prompts;
     }
 
     pub async fn generate_code(
@@ -55,41 +113,106 @@ impl CodeAgent {
                 for mem in memories {
                     prompt.push_str(&format!("- {}\n", mem.content));
                 }
-                prompt.push_str("\n");
+                prompt.push('\n');
             }
         }
 
         prompt.push_str(&format!("Request: {}\n\nProvide the code:", request));
 
-        let response = self.llm.generate_streaming(&prompt, Some(CODE_SYSTEM_PROMPT)).await?;
+        let response = self.llm
+            .generate_streaming_with(&prompt, Some(&self.prompts.system("generate", CODE_SYSTEM_PROMPT)), &self.prompts.options("generate"))
+            .await?;
+        let response = self.postprocess.apply(&response);
 
         // Store this interaction as a memory
-        self.memory.remember(
-            &format!("Code request: {} -> Generated code", request),
-            MemoryType::Conversation,
-            None,
-            vec!["code".to_string(), "generation".to_string()],
-            0.6,
-        )?;
+        let content = format!("Code request: {} -> Generated code", request);
+        let score = score_memory(self.scorer.as_ref(), &content, MemoryType::Conversation, GENERATION_IMPORTANCE).await;
+        let mut tags = vec!["code".to_string(), "generation".to_string()];
+        score.add_tags(&mut tags);
+        self.memory.remember(&content, MemoryType::Conversation, None, tags, score.importance)?;
 
         Ok(response)
     }
 
-    pub async fn explain_code(&self, code: &str, language: Option<&str>) -> Result<String> {
+    /// A unified diff making the change `instruction` describes to the file at `path`
+    pub async fn edit_diff(&self, path: &str, code: &str, instruction: &str) -> Result<String> {
+        let prompt = format!(
+            "File {}:\n```\n{}\n```\n\nInstruction: {}\n\nDiff:",
+            path, code, instruction
+        );
+        let response = self.llm
+            .generate_with(&prompt, Some(&self.prompts.system("edit", EDIT_SYSTEM_PROMPT)), &self.prompts.options("edit"))
+            .await?;
+
+        let content = format!("Edit {}: {}", path, instruction);
+        let score = score_memory(self.scorer.as_ref(), &content, MemoryType::Conversation, GENERATION_IMPORTANCE).await;
+        let mut tags = vec!["code".to_string(), "edit".to_string()];
+        score.add_tags(&mut tags);
+        self.memory.remember(&content, MemoryType::Conversation, None, tags, score.importance)?;
+
+        Ok(response)
+    }
+
+    /// Explain build or lint `output` and suggest a fix; `code` shows the lines it reports
+    pub async fn explain_error(&self, output: &str, code: &str) -> Result<String> {
+        let prompt = format!("Output:\n```\n{}\n```\n\n{}Explanation and fix:", output, code);
+        self.llm
+            .generate_with(
+                &prompt,
+                Some(&self.prompts.system("explain_error", EXPLAIN_ERROR_SYSTEM_PROMPT)),
+                &self.prompts.options("explain_error"),
+            )
+            .await
+    }
+
+    /// Explain code; `related` holds definitions it calls from elsewhere in the project
+    pub async fn explain_code(&self, code: &str, language: Option<&str>, related: Option<&str>) -> Result<String> {
         let mut prompt = String::new();
 
         if let Some(lang) = language {
             prompt.push_str(&format!("Language: {}\n\n", lang));
         }
 
-        prompt.push_str(&format!(
-            "Explain the following code in detail:\n```\n{}\n```\n\nExplanation:",
-            code
-        ));
+        if let Some(related) = related {
+            prompt.push_str(&format!(
+                "{}\n\nUse these definitions to describe what the code's calls do; do not guess at code that is not shown.\n\n",
+                related
+            ));
+        }
 
         let system = "You are an expert code explainer. Provide clear, educational explanations that help developers understand code. Break down complex logic into simple steps.";
+        let system = self.prompts.system("explain", system);
+        let options = self.prompts.options("explain");
+
+        // Code too large for one prompt is explained a part at a time, split between definitions
+        let starts_definition =
+            |line: &str| language.is_some_and(|lang| CodebaseIndex::definition_at(line.trim(), lang).is_some());
+        let parts = MapReduce::default()
+            .summarize_parts(code, &starts_definition, |part| {
+                let prompt = format!(
+                    "This is {} of a larger piece of code:\n```\n{}\n```\n\nExplain what this part does and how, in a short paragraph per component.",
+                    part.describe(),
+                    part.text
+                );
+                let (system, options) = (&system, &options);
+                async move { self.llm.generate_with(&prompt, Some(system), options).await }
+            })
+            .await?;
+
+        match parts {
+            None => prompt.push_str(&format!(
+                "Explain the following code in detail:\n```\n{}\n```\n\nExplanation:",
+                code
+            )),
+            Some(parts) => prompt.push_str(&format!(
+                "The code is too large to show whole. These are explanations of its parts, in order:\n\n{}\n\nCombine them into one explanation of the whole code: its overall purpose, how the parts work together, then the details of each.\n\nExplanation:",
+                parts
+            )),
+        }
 
-        self.llm.generate_streaming(&prompt, Some(system)).await
+        self.llm
+            .generate_streaming_with(&prompt, Some(&system), &options)
+            .await
     }
 
     pub async fn refactor_code(
@@ -111,16 +234,20 @@ impl CodeAgent {
 
         let system = "You are an expert code refactorer. Improve code quality while maintaining functionality. Focus on readability, performance, and best practices.";
 
-        let response = self.llm.generate_streaming(&prompt, Some(system)).await?;
+        let response = self.llm
+            .generate_streaming_with(&prompt, Some(&self.prompts.system("refactor", system)), &self.prompts.options("refactor"))
+            .await?;
+        let response = self.postprocess.apply(&response);
 
         // Store refactoring pattern
-        self.memory.remember(
-            &format!("Refactoring: {}", instructions),
-            MemoryType::CodePattern,
-            None,
-            vec!["refactor".to_string()],
-            0.7,
-        )?;
+        let content = format!("Refactoring: {}", instructions);
+        let score = score_memory(self.scorer.as_ref(), &content, MemoryType::CodePattern, REFACTOR_IMPORTANCE).await;
+        let mut tags = vec!["refactor".to_string()];
+        if let Some(lang) = language {
+            tags.push(format!("lang:{}", lang.to_lowercase()));
+        }
+        score.add_tags(&mut tags);
+        self.memory.remember(&content, MemoryType::CodePattern, None, tags, score.importance)?;
 
         Ok(response)
     }
@@ -130,6 +257,7 @@ impl CodeAgent {
         code: &str,
         bug_description: &str,
         language: Option<&str>,
+        diagnostics: Option<&str>,
     ) -> Result<String> {
         let mut prompt = String::new();
 
@@ -137,6 +265,10 @@ impl CodeAgent {
             prompt.push_str(&format!("Language: {}\n\n", lang));
         }
 
+        if let Some(diagnostics) = diagnostics {
+            prompt.push_str(&format!("{}\n\n", diagnostics));
+        }
+
         prompt.push_str(&format!(
             "Buggy code:\n```\n{}\n```\n\nBug description: {}\n\nFixed code with explanation:",
             code, bug_description
@@ -144,7 +276,10 @@ impl CodeAgent {
 
         let system = "You are an expert debugger. Identify the root cause of bugs and provide fixed code with clear explanations of what was wrong and how you fixed it.";
 
-        self.llm.generate_streaming(&prompt, Some(system)).await
+        let response = self.llm
+            .generate_streaming_with(&prompt, Some(&self.prompts.system("fix", system)), &self.prompts.options("fix"))
+            .await?;
+        Ok(self.postprocess.apply(&response))
     }
 
     pub async fn review_code(&self, code: &str, language: Option<&str>) -> Result<String> {
@@ -161,16 +296,86 @@ impl CodeAgent {
 
         let system = "You are a senior code reviewer. Provide constructive, actionable feedback that helps improve code quality. Be specific and cite line numbers when relevant.";
 
-        self.llm.generate_streaming(&prompt, Some(system)).await
+        self.llm
+            .generate_streaming_with(&prompt, Some(&self.prompts.system("review", system)), &self.prompts.options("review"))
+            .await
+    }
+
+    /// Review one diff hunk non-interactively and return line-anchored findings
+    pub async fn review_hunk(&self, hunk: &DiffHunk, file_context: Option<&str>) -> Result<Vec<Finding>> {
+        let mut prompt = format!("File: {}\n\n", hunk.file_path);
+
+        if let Some(ctx) = file_context {
+            prompt.push_str(&format!("{}\n\n", ctx));
+        }
+
+        prompt.push_str(&format!(
+            r#"Review this change. Lines are prefixed with their line number in the new file.
+```
+{}```
+
+Report only real problems introduced by the added lines (bugs, security issues, error handling, performance).
+Respond with a JSON array and nothing else:
+[{{"line": <line number>, "severity": "error|warning|note", "message": "<one sentence>"}}]
+Respond with [] if there are no problems."#,
+            review::numbered_hunk(hunk)
+        ));
+
+        let system = "You are a senior code reviewer running in CI. Be precise and conservative: only flag issues you are confident about.";
+
+        let response = self
+            .llm
+            .generate_with(&prompt, Some(&self.prompts.system("ci_review", system)), &self.prompts.options("ci_review"))
+            .await?;
+
+        Ok(review::parse_findings(&response, hunk))
     }
 
-    pub async fn write_tests(&self, code: &str, language: Option<&str>) -> Result<String> {
+    /// Ask about one vulnerability class in a piece of a file, numbered from line `first`
+    pub async fn audit_chunk(&self, file: &str, first: u32, numbered: &str, check: &AuditCheck) -> Result<Vec<Finding>> {
+        let prompt = format!(
+            r#"File: {}
+Lines are prefixed with their line number.
+```
+{}```
+
+Look only for {}.
+Report only problems an attacker could reach, not style. Respond with a JSON array and nothing else:
+[{{"line": <line number>, "severity": "error|warning|note", "message": "<one sentence: what is exposed and how>"}}]
+Respond with [] if there are none."#,
+            file, numbered, check.focus
+        );
+
+        let system = "You are a security auditor. Be precise and conservative: only flag vulnerabilities you are confident about.";
+
+        let response = self
+            .llm
+            .generate_with(&prompt, Some(&self.prompts.system("audit", system)), &self.prompts.options("audit"))
+            .await?;
+
+        let last = first + numbered.lines().count().saturating_sub(1) as u32;
+        Ok(audit::parse_findings(&response, file, check, first, last))
+    }
+
+    /// Write tests in the project's own framework and style, when `conventions` were detected
+    ///
+    /// Generated Rust tests are checked to parse; a note is appended when they do not.
+    pub async fn write_tests(
+        &self,
+        code: &str,
+        language: Option<&str>,
+        conventions: Option<&TestConventions>,
+    ) -> Result<String> {
         let mut prompt = String::new();
 
         if let Some(lang) = language {
             prompt.push_str(&format!("Language: {}\n\n", lang));
         }
 
+        if let Some(conventions) = conventions {
+            prompt.push_str(&format!("{}\n", conventions.prompt_section()));
+        }
+
         prompt.push_str(&format!(
             "Write comprehensive tests for the following code:\n```\n{}\n```\n\nTests:",
             code
@@ -178,6 +383,20 @@ impl CodeAgent {
 
         let system = "You are a test engineer. Write thorough unit tests that cover edge cases, error conditions, and normal operation. Use the standard testing framework for the language.";
 
-        self.llm.generate_streaming(&prompt, Some(system)).await
+        let response = self.llm
+            .generate_streaming_with(&prompt, Some(&self.prompts.system("test", system)), &self.prompts.options("test"))
+            .await?;
+        let mut tests = self.postprocess.apply(&response);
+
+        if language == Some("rust") {
+            for block in testgen::rust_blocks(&tests) {
+                if let Some(Err(error)) = testgen::check_rust_parses(&block).await {
+                    tests.push_str(&format!("\n\nWarning: the generated tests do not parse ({})", error));
+                    break;
+                }
+            }
+        }
+
+        Ok(tests)
     }
 }
@@ -0,0 +1,1105 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Width of the usage column in the `/help` overview
+const USAGE_WIDTH: usize = 24;
+
+/// Section of `/help` a command is listed under
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandGroup {
+    Code,
+    Generate,
+    Git,
+    Session,
+    Sync,
+}
+
+impl CommandGroup {
+    const ALL: [CommandGroup; 5] = [Self::Code, Self::Generate, Self::Git, Self::Session, Self::Sync];
+
+    fn heading(&self) -> &'static str {
+        match self {
+            Self::Code => "CODE",
+            Self::Generate => "GENERATE",
+            Self::Git => "GIT",
+            Self::Session => "SESSION",
+            Self::Sync => "SYNC (Local-First)",
+        }
+    }
+}
+
+/// What an argument holds: checked before the command runs, and used to
+/// complete it in the REPL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", content = "choices", rename_all = "lowercase")]
+pub enum ArgKind {
+    Text,
+    /// A file of the index, relative to the project
+    File,
+    /// A file or directory on disk
+    Path,
+    Number,
+    /// A command name, for `/help`
+    Command,
+    Choice(&'static [&'static str]),
+    /// `<file>:<line>:<col>`
+    Position,
+    /// `<host:port>`
+    Peer,
+    /// A description followed by code in ``` fences
+    Code,
+}
+
+impl ArgKind {
+    /// Why `value` is not one of these, if it is not
+    fn check(&self, name: &str, value: &str) -> Option<String> {
+        match self {
+            Self::Number if value.parse::<usize>().is_err() => Some(format!("<{}> must be a whole number.", name)),
+            Self::Choice(choices) if !choices.contains(&value) => {
+                Some(format!("Expected {}, not '{}'.", choices.join(", "), value))
+            }
+            Self::Position => {
+                let mut position = value.rsplitn(3, ':');
+                let (Some(column), Some(line), Some(_)) = (position.next(), position.next(), position.next()) else {
+                    return Some(format!("Expected <file>:<line>:<col>, not '{}'.", value));
+                };
+                (line.parse::<usize>().is_err() || column.parse::<usize>().is_err())
+                    .then(|| format!("Line and column of '{}' must be numbers.", value))
+            }
+            Self::Peer => value
+                .rsplit_once(':')
+                .and_then(|(_, port)| port.parse::<u16>().ok())
+                .is_none()
+                .then(|| format!("Expected <host:port>, not '{}'.", value)),
+            Self::Code if !value.contains("```") => Some("Put the code between ``` fences.".to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// A positional argument
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub required: bool,
+}
+
+impl ParamSpec {
+    pub const fn required(name: &'static str, kind: ArgKind) -> Self {
+        Self { name, kind, required: true }
+    }
+
+    pub const fn optional(name: &'static str, kind: ArgKind) -> Self {
+        Self { name, kind, required: false }
+    }
+
+    /// `<file>`, `[n]`, `[terse|normal|teaching]`
+    fn usage(&self) -> String {
+        let inner = match self.kind {
+            ArgKind::Choice(choices) => choices.join("|"),
+            ArgKind::Position => format!("{}>:<line>:<col", self.name),
+            _ => self.name.to_string(),
+        };
+        let usage = if self.required { format!("<{}>", inner) } else { format!("[{}]", inner) };
+        match self.kind {
+            ArgKind::Code => format!("{} ```code```", usage),
+            _ => usage,
+        }
+    }
+}
+
+/// An option given before the arguments: a switch (`--fresh`) or one that
+/// takes a value (`--ref <rev>`)
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FlagSpec {
+    pub long: &'static str,
+    pub short: Option<&'static str>,
+    /// Name of the value it takes, if it takes one
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+impl FlagSpec {
+    pub const fn switch(long: &'static str, short: Option<&'static str>, help: &'static str) -> Self {
+        Self { long, short, value: None, help }
+    }
+
+    pub const fn option(long: &'static str, value: &'static str, help: &'static str) -> Self {
+        Self { long, short: None, value: Some(value), help }
+    }
+
+    /// `-s, --summary`, `--ref <rev>`
+    fn names(&self) -> String {
+        let mut names = match self.short {
+            Some(short) => format!("{}, {}", short, self.long),
+            None => self.long.to_string(),
+        };
+        if let Some(value) = self.value {
+            names.push_str(&format!(" <{}>", value));
+        }
+        names
+    }
+
+    /// `[-s]`, `[--ref <rev>]`
+    fn usage(&self) -> String {
+        let name = self.short.unwrap_or(self.long);
+        match self.value {
+            Some(value) => format!("[{} <{}>]", name, value),
+            None => format!("[{}]", name),
+        }
+    }
+
+    /// Whether this is the switch `-<letter>`
+    fn is_short_switch(&self, letter: char) -> bool {
+        self.value.is_none() && self.short.and_then(|short| short.strip_prefix('-')) == Some(letter.to_string().as_str())
+    }
+}
+
+/// Arguments of a command, checked against its spec
+#[derive(Debug, Default)]
+pub struct CommandArgs {
+    raw: String,
+    /// Values of the flags given, by long name; switches have an empty value
+    flags: HashMap<&'static str, String>,
+    values: HashMap<&'static str, String>,
+}
+
+impl CommandArgs {
+    /// Everything after the command name, flags included
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    /// Whether the flag with this long name was given
+    pub fn flag(&self, long: &str) -> bool {
+        self.flags.contains_key(long)
+    }
+
+    pub fn option(&self, long: &str) -> Option<&str> {
+        self.flags.get(long).map(String::as_str)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    /// The argument `name`, or "" when an optional one was left out
+    pub fn text(&self, name: &str) -> &str {
+        self.get(name).unwrap_or("")
+    }
+
+    /// The number argument `name`; its spec has already checked that it parses
+    pub fn number(&self, name: &str) -> Option<usize> {
+        self.get(name).and_then(|n| n.parse().ok())
+    }
+}
+
+/// The first word of `text` and what follows it
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    let (word, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    (word, rest.trim_start())
+}
+
+/// A chat command: how its arguments are parsed, what `/help` shows for it,
+/// and what the daemon lists for command palettes
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandSpec {
+    /// Name with its slash (`/search`)
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    /// Options, given before the arguments
+    pub flags: &'static [FlagSpec],
+    /// Arguments in order; the last one takes the rest of the line
+    pub params: &'static [ParamSpec],
+    /// One line for the overview
+    pub description: &'static str,
+    /// Behavior beyond the options, for `/help <command>`
+    pub details: &'static str,
+    pub examples: &'static [&'static str],
+    pub group: CommandGroup,
+}
+
+impl CommandSpec {
+    /// Whether `name` (with its slash) is this command or one of its aliases
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name == name || self.aliases.contains(&name)
+    }
+
+    /// `[-s] <name>`
+    fn args_usage(&self) -> String {
+        let flags = self.flags.iter().map(FlagSpec::usage);
+        let params = self.params.iter().map(ParamSpec::usage);
+        flags.chain(params).collect::<Vec<_>>().join(" ")
+    }
+
+    /// `/search <query>`
+    pub fn usage(&self) -> String {
+        let args = self.args_usage();
+        if args.is_empty() {
+            self.name.to_string()
+        } else {
+            format!("{} {}", self.name, args)
+        }
+    }
+
+    /// Detailed help, as shown by `/help <command>`
+    pub fn help(&self) -> String {
+        let mut help = format!("{}\n  {}\n", self.usage(), self.description);
+        if !self.aliases.is_empty() {
+            help.push_str(&format!("\nAliases: {}\n", self.aliases.join(", ")));
+        }
+        if !self.flags.is_empty() {
+            help.push_str("\nOptions:\n");
+            let width = self.flags.iter().map(|flag| flag.names().len()).max().unwrap_or(0);
+            for flag in self.flags {
+                help.push_str(&format!("  {:<width$}  {}\n", flag.names(), flag.help, width = width));
+            }
+        }
+        if !self.details.is_empty() {
+            help.push('\n');
+            for line in self.details.lines() {
+                help.push_str(&format!("  {}\n", line));
+            }
+        }
+        if !self.examples.is_empty() {
+            help.push_str("\nExamples:\n");
+            for example in self.examples {
+                help.push_str(&format!("  {}\n", example));
+            }
+        }
+        help
+    }
+
+    fn overview_line(&self) -> String {
+        let names: Vec<&str> = std::iter::once(self.name).chain(self.aliases.iter().copied()).collect();
+        let mut usage = names.join(", ");
+        let args = self.args_usage();
+        if !args.is_empty() {
+            usage.push(' ');
+            usage.push_str(&args);
+        }
+        format!("  {:<width$} {}", usage, self.description, width = USAGE_WIDTH)
+    }
+
+    fn usage_error(&self, problem: String) -> String {
+        format!("{}\nUsage: {}", problem, self.usage())
+    }
+
+    /// Check `args` (what follows the command name) against the spec
+    ///
+    /// Flags are read up to the first word that is not one, or `--`; short
+    /// switches may be combined (`-Fi`). The error says what is wrong and
+    /// shows the usage.
+    pub fn parse(&self, args: &str) -> Result<CommandArgs, String> {
+        let mut parsed = CommandArgs { raw: args.trim().to_string(), ..Default::default() };
+        let mut rest = args.trim();
+
+        while !self.flags.is_empty() && rest.starts_with('-') {
+            let (word, after) = split_word(rest);
+            rest = after;
+            if word == "--" {
+                break;
+            }
+
+            if let Some(flag) = self.flags.iter().find(|flag| flag.long == word || flag.short == Some(word)) {
+                let value = match flag.value {
+                    Some(value_name) => {
+                        let (value, after) = split_word(rest);
+                        if value.is_empty() {
+                            return Err(self.usage_error(format!("{} needs a <{}>.", flag.long, value_name)));
+                        }
+                        rest = after;
+                        value.to_string()
+                    }
+                    None => String::new(),
+                };
+                parsed.flags.insert(flag.long, value);
+                continue;
+            }
+
+            // Combined short switches
+            let switches: Option<Vec<&FlagSpec>> = word
+                .strip_prefix('-')
+                .filter(|letters| !letters.is_empty() && !letters.starts_with('-'))
+                .and_then(|letters| {
+                    letters
+                        .chars()
+                        .map(|letter| self.flags.iter().find(|flag| flag.is_short_switch(letter)))
+                        .collect()
+                });
+            let Some(switches) = switches else {
+                return Err(self.usage_error(format!("Unknown option {}.", word)));
+            };
+            for flag in switches {
+                parsed.flags.insert(flag.long, String::new());
+            }
+        }
+
+        for (i, param) in self.params.iter().enumerate() {
+            let (value, after) = if i + 1 == self.params.len() { (rest, "") } else { split_word(rest) };
+            rest = after;
+            if value.is_empty() {
+                if param.required {
+                    return Err(self.usage_error(format!("Missing <{}>.", param.name)));
+                }
+                continue;
+            }
+            if let Some(problem) = param.kind.check(param.name, value) {
+                return Err(self.usage_error(problem));
+            }
+            parsed.values.insert(param.name, value.to_string());
+        }
+        Ok(parsed)
+    }
+}
+
+/// The command `name` (with or without its slash) or one of its aliases names
+pub fn find(name: &str) -> Option<&'static CommandSpec> {
+    find_in(COMMANDS, name)
+}
+
+/// The command of `specs` that `name` names
+pub fn find_in<'a>(specs: &'a [CommandSpec], name: &str) -> Option<&'a CommandSpec> {
+    let name = name.trim();
+    let name = format!("/{}", name.strip_prefix('/').unwrap_or(name));
+    specs.iter().find(|spec| spec.is_named(&name))
+}
+
+/// Every command, one line each, by group
+pub fn help_text() -> String {
+    let mut help = String::from("\nSovereign - Local-First Code Assistant\n");
+    for group in CommandGroup::ALL {
+        help.push_str(&format!("\n{}:\n", group.heading()));
+        for spec in COMMANDS.iter().filter(|spec| spec.group == group) {
+            help.push_str(&spec.overview_line());
+            help.push('\n');
+        }
+    }
+    help.push_str("\n/help <command> for options and examples. Or just type naturally to chat!\n");
+    help
+}
+
+/// Every chat command, in the order `/help` lists them
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "/search",
+        aliases: &["/s"],
+        flags: &[],
+        params: &[ParamSpec::required("query", ArgKind::Text)],
+        description: "Search codebase (uses embeddings if available)",
+        details: "Scope a search with language:, path: and symbol: filters; path: and symbol: take * as a\n\
+                  wildcard and any filter takes several values separated by commas. A query of filters\n\
+                  alone lists the files in scope. Results are numbered: use #N as a file (/read #3).",
+        examples: &["/search retry logic", "/search language:rust path:src/agents symbol:Orchestrator retry"],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/grep",
+        aliases: &[],
+        flags: &[
+            FlagSpec::switch("--fixed-strings", Some("-F"), "Match the pattern as plain text"),
+            FlagSpec::switch("--ignore-case", Some("-i"), "Match any case"),
+        ],
+        params: &[ParamSpec::required("regex", ArgKind::Text)],
+        description: "Exact matches in indexed files as file:line (-F literal, -i any case)",
+        details: "Quote a pattern with spaces; the /search filters can follow it.",
+        examples: &["/grep -i \"todo|fixme\" path:src/", "/grep -F unwrap()"],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/symbol",
+        aliases: &["/sym"],
+        flags: &[],
+        params: &[ParamSpec::required("name", ArgKind::Text)],
+        description: "Find symbol definitions",
+        details: "Ignores case and snake_case/camelCase; exact matches rank before prefixes and fuzzy ones.",
+        examples: &["/symbol parse_diff", "/symbol gsd"],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/more",
+        aliases: &[],
+        flags: &[],
+        params: &[],
+        description: "Next page of /search, /grep or /symbol results (#N in place of a file: /read #3)",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/def",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("symbol", ArgKind::Text)],
+        description: "Print a symbol's definition",
+        details: "Type::method and module.func name their last segment.",
+        examples: &["/def Orchestrator::handle_command"],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/refs",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("symbol", ArgKind::Text)],
+        description: "Find uses of a symbol, with context",
+        details: "",
+        examples: &["/refs index_changes"],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/ask",
+        aliases: &["/q"],
+        flags: &[
+            FlagSpec::switch("--fresh", None, "Answer again instead of reusing an earlier answer to the question"),
+            FlagSpec::switch("--include-chats", None, "Also draw on earlier chat explanations"),
+            FlagSpec::option("--ref", "rev", "Answer from the code as it was at a tag, branch or commit"),
+        ],
+        params: &[ParamSpec::required("question", ArgKind::Text)],
+        description: "Ask about codebase (answers cite file:line sources)",
+        details: "",
+        examples: &["/ask how are embeddings invalidated?", "/ask --ref v1.2.0 how did retries work?"],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/regenerate",
+        aliases: &["/regen"],
+        flags: &[],
+        params: &[],
+        description: "Regenerate the last answer",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/read",
+        aliases: &["/cat"],
+        flags: &[],
+        params: &[ParamSpec::required("file", ArgKind::File)],
+        description: "Read file content",
+        details: "",
+        examples: &["/read src/main.rs", "/read #2"],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/open",
+        aliases: &["/o"],
+        flags: &[
+            FlagSpec::switch("--summary", Some("-s"), "Summarize the file instead of printing it"),
+        ],
+        params: &[ParamSpec::required("name", ArgKind::Text)],
+        description: "Read (or -s summarize) the file best matching a fuzzy name",
+        details: "Matches like fzf: the characters of the name in order, ignoring case. Other close\n\
+                  matches are listed as #N.",
+        examples: &["/open orch", "/open -s st/cb"],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/summarize",
+        aliases: &["/sum"],
+        flags: &[],
+        params: &[ParamSpec::required("file", ArgKind::File)],
+        description: "Summarize a file",
+        details: "",
+        examples: &["/summarize src/daemon.rs"],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/summarize-all",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::optional("n", ArgKind::Number)],
+        description: "Store short summaries of files that have none",
+        details: "Resumes where it stopped; n limits how many files are summarized in this run.",
+        examples: &["/summarize-all 50"],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/glossary",
+        aliases: &[],
+        flags: &[],
+        params: &[
+            ParamSpec::optional("action", ArgKind::Choice(&["build"])),
+            ParamSpec::optional("n", ArgKind::Number),
+        ],
+        description: "Show the project glossary, or define its n most used terms",
+        details: "",
+        examples: &["/glossary build 20"],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/deps",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("file", ArgKind::File)],
+        description: "Files this file imports",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/rdeps",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("file", ArgKind::File)],
+        description: "Files that import this file",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/index",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("path", ArgKind::Path)],
+        description: "Index (or re-index) a codebase",
+        details: "Only files whose content changed are parsed again.",
+        examples: &["/index ."],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/whatchanged",
+        aliases: &[],
+        flags: &[],
+        params: &[],
+        description: "Files changed since the session started, with notes",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/embed",
+        aliases: &[],
+        flags: &[
+            FlagSpec::switch("--reembed", None, "Rebuild every embedding with the current model"),
+            FlagSpec::switch("--pending", None, "Re-embed files changed since they were embedded"),
+        ],
+        params: &[],
+        description: "Build embeddings for semantic search",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/stats",
+        aliases: &[],
+        flags: &[],
+        params: &[],
+        description: "Show codebase statistics",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/run",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("command", ArgKind::Text)],
+        description: "Run a build/test command (in the configured container, if any)",
+        details: "",
+        examples: &["/run cargo test"],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/check-edits",
+        aliases: &[],
+        flags: &[],
+        params: &[],
+        description: "Find call sites left behind by renamed/removed symbols in changed files",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/diagnostics",
+        aliases: &["/diag"],
+        flags: &[],
+        params: &[ParamSpec::optional("file", ArgKind::File)],
+        description: "Show language server diagnostics (all files, or one file)",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/definition",
+        aliases: &["/goto"],
+        flags: &[],
+        params: &[ParamSpec::required("file", ArgKind::Position)],
+        description: "Go to definition via the language server",
+        details: "",
+        examples: &["/goto src/main.rs:42:17"],
+        group: CommandGroup::Code,
+    },
+    CommandSpec {
+        name: "/generate",
+        aliases: &["/gen", "/g"],
+        flags: &[],
+        params: &[ParamSpec::required("desc", ArgKind::Text)],
+        description: "Generate code",
+        details: "",
+        examples: &["/generate a function that parses ISO dates"],
+        group: CommandGroup::Generate,
+    },
+    CommandSpec {
+        name: "/explain",
+        aliases: &["/e"],
+        flags: &[],
+        params: &[ParamSpec::required("code|path", ArgKind::Path)],
+        description: "Explain code, or a file with what it imports",
+        details: "",
+        examples: &["/explain src/watcher.rs"],
+        group: CommandGroup::Generate,
+    },
+    CommandSpec {
+        name: "/review",
+        aliases: &["/r"],
+        flags: &[],
+        params: &[ParamSpec::required("code|path", ArgKind::Path)],
+        description: "Review code, or indexed files by path or glob",
+        details: "A path or glob (`src/*.rs`) reviews the indexed files it matches, a part at a time for large files.",
+        examples: &["/review src/daemon.rs", "/review src/agents/*.rs"],
+        group: CommandGroup::Generate,
+    },
+    CommandSpec {
+        name: "/test",
+        aliases: &["/t"],
+        flags: &[],
+        params: &[ParamSpec::required("code|path", ArgKind::Path)],
+        description: "Generate tests in the project's framework",
+        details: "A path or glob (`src/*.rs`) writes tests for the indexed files it matches, a part at a time for large files.",
+        examples: &["/test src/cache.rs"],
+        group: CommandGroup::Generate,
+    },
+    CommandSpec {
+        name: "/fix",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("desc", ArgKind::Code)],
+        description: "Fix a bug",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Generate,
+    },
+    CommandSpec {
+        name: "/refactor",
+        aliases: &["/ref"],
+        flags: &[],
+        params: &[ParamSpec::required("desc", ArgKind::Code)],
+        description: "Refactor code",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Generate,
+    },
+    CommandSpec {
+        name: "/edit",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("file", ArgKind::File), ParamSpec::required("instruction", ArgKind::Text)],
+        description: "Propose a change to a file as a diff, for /apply",
+        details: "Nothing is written until /apply. Asking again replaces the proposal.",
+        examples: &["/edit src/cache.rs make the capacity configurable"],
+        group: CommandGroup::Generate,
+    },
+    CommandSpec {
+        name: "/refactor-all",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("instruction", ArgKind::Text)],
+        description: "Plan a change across files and propose a diff for each, for /apply",
+        details: "The symbol index finds every file defining or using what the change touches; uses the diffs miss are listed. Up to 15 files at once.",
+        examples: &["/refactor-all rename `Cache` to `LruCache`", "/refactor-all add a `timeout: Duration` parameter to `fetch` and pass it at every call site"],
+        group: CommandGroup::Generate,
+    },
+    CommandSpec {
+        name: "/doc",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("target", ArgKind::File)],
+        description: "Propose documentation comments for a file or a definition, for /apply",
+        details: "Comments are written in the style of documented definitions elsewhere in the project.\n\
+                  A diff that changes code as well as comments is refused.",
+        examples: &["/doc src/cache.rs", "/doc evict_least_recent"],
+        group: CommandGroup::Generate,
+    },
+    CommandSpec {
+        name: "/task",
+        aliases: &[],
+        flags: &[
+            FlagSpec::switch("--continue", None, "Go ahead with the step waiting to be confirmed"),
+            FlagSpec::switch("--skip", None, "Leave out the step waiting to be confirmed"),
+            FlagSpec::switch("--stop", None, "End the task"),
+        ],
+        params: &[ParamSpec::optional("goal", ArgKind::Text)],
+        description: "Plan the steps to a goal and carry them out with search, grep, read, edit and run",
+        details: "Steps that only read run straight away. Each edit is shown as a diff and each command\n\
+                  before it runs, waiting for --continue. Without a goal, shows the running task's plan.",
+        examples: &["/task make the cache capacity configurable and check the tests pass", "/task --continue"],
+        group: CommandGroup::Generate,
+    },
+    CommandSpec {
+        name: "/apply",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::optional("file", ArgKind::File)],
+        description: "Write the changes proposed by /edit or /refactor-all, backing up the files first",
+        details: "With a file, writes only its change and keeps the rest pending; each /apply is one /undo.",
+        examples: &["/apply", "/apply src/cache.rs"],
+        group: CommandGroup::Generate,
+    },
+    CommandSpec {
+        name: "/undo",
+        aliases: &[],
+        flags: &[FlagSpec::switch("--force", None, "Put the files back even if they were edited after /apply")],
+        params: &[],
+        description: "Put back the files changed by the last /apply",
+        details: "Backups of the last 20 applied changes are kept in the data directory, across sessions. \
+                  A file edited since /apply is left alone unless --force.",
+        examples: &["/undo", "/undo --force"],
+        group: CommandGroup::Generate,
+    },
+    CommandSpec {
+        name: "/commit",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::optional("repo", ArgKind::Path)],
+        description: "Generate commit message for staged changes",
+        details: "Without a repository path, the one in the current directory.",
+        examples: &[],
+        group: CommandGroup::Git,
+    },
+    CommandSpec {
+        name: "/review-staged",
+        aliases: &[],
+        flags: &[],
+        params: &[],
+        description: "Review staged changes before committing",
+        details: "Each staged hunk is reviewed with the definition it falls in, from the index; findings are listed by severity, file and line.",
+        examples: &[],
+        group: CommandGroup::Git,
+    },
+    CommandSpec {
+        name: "/pr-summary",
+        aliases: &["/pr"],
+        flags: &[],
+        params: &[ParamSpec::optional("base", ArgKind::Text)],
+        description: "Generate PR summary for current branch",
+        details: "Compares against the default branch unless a base ref is given.",
+        examples: &["/pr-summary origin/release-2.0"],
+        group: CommandGroup::Git,
+    },
+    CommandSpec {
+        name: "/memory",
+        aliases: &["/mem"],
+        flags: &[],
+        params: &[ParamSpec::optional("tag", ArgKind::Text)],
+        description: "Show recent memories, or those with a tag",
+        details: "Ids are shortened; any unambiguous start of one names a memory.",
+        examples: &[],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/memory-show",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("id", ArgKind::Text)],
+        description: "Show a memory in full",
+        details: "",
+        examples: &["/memory-show 3f2a9c1e"],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/memory-edit",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("id", ArgKind::Text), ParamSpec::required("text", ArgKind::Text)],
+        description: "Replace the text of a memory",
+        details: "",
+        examples: &["/memory-edit 3f2a9c1e Prefer spaces over tabs"],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/memory-delete",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("id", ArgKind::Text)],
+        description: "Delete a memory from both stores",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/memory-set-importance",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("id", ArgKind::Text), ParamSpec::required("importance", ArgKind::Text)],
+        description: "Set how important a memory is, from 0 to 1",
+        details: "The least important memories are the first to be pruned.",
+        examples: &["/memory-set-importance 3f2a9c1e 0.9"],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/analytics",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::optional("days", ArgKind::Number)],
+        description: "Local usage statistics as JSON (default: 30 days)",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/share",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::optional("file", ArgKind::Path)],
+        description: "Export this session (conversation, context refs, config, model)",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/share-load",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("file", ArgKind::Path)],
+        description: "Continue a shared session",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/context",
+        aliases: &[],
+        flags: &[],
+        params: &[],
+        description: "Show what the next chat message is sent with, and its size in tokens",
+        details: "Lists the system prompt with its memories and preferences, pinned files, the code \
+                  retrieved for the last message and the conversation so far.",
+        examples: &[],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/pin",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("file", ArgKind::File)],
+        description: "Send a file with every chat message",
+        details: "The file is read afresh for each message, so edits show up. Pins last for the session.",
+        examples: &["/pin src/agents/chat.rs"],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/unpin",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::optional("file", ArgKind::File)],
+        description: "Stop sending a pinned file, or all of them",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/checkpoint",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::optional("name", ArgKind::Text)],
+        description: "Save the conversation under a name, or list saved checkpoints",
+        details: "Checkpoints last for the session. Saving under an existing name replaces it.",
+        examples: &["/checkpoint before-redesign"],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/rewind",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("name", ArgKind::Text)],
+        description: "Go back to the conversation saved by /checkpoint",
+        details: "Everything said since is dropped from the context. The checkpoint is kept, so you can rewind to it again.",
+        examples: &["/rewind before-redesign"],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/clear",
+        aliases: &[],
+        flags: &[],
+        params: &[],
+        description: "Clear conversation",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/verbosity",
+        aliases: &[],
+        flags: &[],
+        params: &[
+            ParamSpec::optional("level", ArgKind::Choice(&["terse", "normal", "teaching"])),
+        ],
+        description: "Show or set answer length: terse, normal, teaching",
+        details: "",
+        examples: &["/verbosity terse"],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/model",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::optional("name", ArgKind::Text)],
+        description: "Show the model and what it can do, or switch to another on the same backend",
+        details: "The model must answer first (on Ollama, it must be pulled); until then the current one stays.\n\
+                  The conversation, pins and index carry over.",
+        examples: &["/model llama3.1:8b"],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/backend",
+        aliases: &[],
+        flags: &[],
+        params: &[
+            ParamSpec::optional("name", ArgKind::Choice(&["ollama", "deepseek"])),
+            ParamSpec::optional("model", ArgKind::Text),
+        ],
+        description: "Show the backend, or switch to another with its default model or the one given",
+        details: "The backend must answer first; until then the current one stays.",
+        examples: &["/backend deepseek", "/backend ollama qwen2.5-coder:7b"],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/reload",
+        aliases: &[],
+        flags: &[],
+        params: &[],
+        description: "Re-read sovereign.toml, keeping the model and the conversation",
+        details: "Model options, [models], retrieval, prompts and index excludes take effect at once.\n\
+                  A daemon also starts or stops watching the directories [daemon] watch lists.",
+        examples: &[],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/help",
+        aliases: &["/h"],
+        flags: &[],
+        params: &[ParamSpec::optional("command", ArgKind::Command)],
+        description: "Show this help, or a command's options and examples",
+        details: "",
+        examples: &["/help ask"],
+        group: CommandGroup::Session,
+    },
+    CommandSpec {
+        name: "/sync-export",
+        aliases: &[],
+        flags: &[],
+        params: &[],
+        description: "Export CRDT memories for sync",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Sync,
+    },
+    CommandSpec {
+        name: "/sync-import",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("file", ArgKind::Path)],
+        description: "Import and merge CRDT memories",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Sync,
+    },
+    CommandSpec {
+        name: "/sync-status",
+        aliases: &[],
+        flags: &[],
+        params: &[],
+        description: "Show CRDT and P2P sync status",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Sync,
+    },
+    CommandSpec {
+        name: "/sync-pull",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("host:port", ArgKind::Peer)],
+        description: "Pull memories from a peer",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Sync,
+    },
+    CommandSpec {
+        name: "/sync-push",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("host:port", ArgKind::Peer)],
+        description: "Push memories to a peer",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Sync,
+    },
+    CommandSpec {
+        name: "/sync-live",
+        aliases: &[],
+        flags: &[],
+        params: &[ParamSpec::required("host:port", ArgKind::Peer)],
+        description: "Bidirectional sync with a peer",
+        details: "",
+        examples: &[],
+        group: CommandGroup::Sync,
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_by_name_or_alias() {
+        assert_eq!(find("/search").map(|c| c.name), Some("/search"));
+        assert_eq!(find("sym").map(|c| c.name), Some("/symbol"));
+        assert_eq!(find("/pr").map(|c| c.name), Some("/pr-summary"));
+        assert_eq!(find("gen").map(|c| c.name), Some("/generate"));
+        assert!(find("/nope").is_none());
+    }
+
+    #[test]
+    fn test_parse_checks_arguments_against_the_spec() {
+        let ask = find("/ask").unwrap().parse("--fresh --ref v1.2 how did retries work?").unwrap();
+        assert!(ask.flag("--fresh") && !ask.flag("--include-chats"));
+        assert_eq!(ask.option("--ref"), Some("v1.2"));
+        assert_eq!(ask.text("question"), "how did retries work?");
+
+        let glossary = find("/glossary").unwrap().parse("build 20").unwrap();
+        assert_eq!((glossary.get("action"), glossary.number("n")), (Some("build"), Some(20)));
+        assert!(find("/glossary").unwrap().parse("").unwrap().get("action").is_none());
+
+        let error = |command: &str, args: &str| find(command).unwrap().parse(args).unwrap_err();
+        assert_eq!(error("/read", ""), "Missing <file>.\nUsage: /read <file>");
+        assert_eq!(error("/summarize-all", "ten"), "<n> must be a whole number.\nUsage: /summarize-all [n]");
+        assert!(error("/verbosity", "loud").starts_with("Expected terse, normal, teaching, not 'loud'."));
+        assert!(error("/ask", "--ref").starts_with("--ref needs a <rev>."));
+        assert!(error("/open", "-x orch").starts_with("Unknown option -x."));
+        assert!(error("/goto", "src/main.rs:42").starts_with("Expected <file>:<line>:<col>"));
+        assert!(error("/fix", "off by one").starts_with("Put the code between ``` fences."));
+
+        // Commands without options take a leading dash as an argument
+        assert_eq!(find("/run").unwrap().parse("-v").unwrap().text("command"), "-v");
+        assert_eq!(find("/open").unwrap().parse("-- -weird").unwrap().text("name"), "-weird");
+    }
+
+    #[test]
+    fn test_help_lists_every_command_once() {
+        let help = help_text();
+        for spec in COMMANDS {
+            assert!(help.contains(&format!("  {}", spec.name)), "{} missing", spec.name);
+            assert_eq!(COMMANDS.iter().filter(|other| other.name == spec.name).count(), 1);
+        }
+
+        let ask = find("ask").unwrap().help();
+        assert!(ask.starts_with("/ask [--fresh] [--include-chats] [--ref <rev>] <question>\n"));
+        assert!(ask.contains("Aliases: /q"));
+        assert!(ask.contains("Options:\n  --fresh          Answer again"));
+        assert!(ask.contains("\n  --ref <rev>      Answer from the code"));
+        assert!(ask.contains("Examples:\n  /ask how are embeddings invalidated?"));
+    }
+}
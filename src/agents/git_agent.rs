@@ -1,6 +1,11 @@
 use anyhow::Result;
+use serde::Serialize;
+use std::path::Path;
+use crate::commit_lint;
 use crate::llm::LlmClient;
-use crate::git::{GitOps, DiffAnalysis, Commit, FileChange, FileStatus};
+use crate::prompts::{PromptLibrary, Verbosity};
+use crate::summarize::MapReduce;
+use crate::git::{GitOps, DiffAnalysis, DiffHunk, Commit, FileChange, FileStatus};
 
 const GIT_SYSTEM_PROMPT: &str = r#"You are an expert git assistant running locally on the user's machine.
 You help with:
@@ -23,7 +28,7 @@ When writing PR summaries:
 "#;
 
 /// Analysis result from examining a diff
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DiffInsights {
     pub change_type: ChangeType,
     pub affected_areas: Vec<String>,
@@ -31,19 +36,39 @@ pub struct DiffInsights {
     pub breaking_potential: bool,
     pub suggested_reviewers: Vec<String>,
     pub summary: String,
+    pub files: Vec<FileChange>,
+    pub hunks: Vec<DiffHunk>,
+    pub total_additions: u32,
+    pub total_deletions: u32,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Which changes to analyze
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffSource {
+    /// Changes staged for commit
+    Staged,
+    /// The current branch compared with the default branch
+    Branch,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum ChangeType {
+    #[serde(rename = "feat")]
     Feature,
+    #[serde(rename = "fix")]
     BugFix,
+    #[serde(rename = "refactor")]
     Refactor,
+    #[serde(rename = "docs")]
     Documentation,
+    #[serde(rename = "test")]
     Test,
+    #[serde(rename = "style")]
     Style,
+    #[serde(rename = "perf")]
     Performance,
+    #[serde(rename = "chore")]
     Chore,
-    Mixed,
 }
 
 impl ChangeType {
@@ -57,12 +82,12 @@ impl ChangeType {
             ChangeType::Style => "style",
             ChangeType::Performance => "perf",
             ChangeType::Chore => "chore",
-            ChangeType::Mixed => "chore",
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ChangeComplexity {
     Trivial,   // Simple changes like typos, formatting
     Simple,    // Single file, small changes
@@ -85,11 +110,26 @@ impl ChangeComplexity {
 
 pub struct GitAgent {
     llm: LlmClient,
+    prompts: PromptLibrary,
 }
 
 impl GitAgent {
-    pub fn new(llm: LlmClient) -> Self {
-        Self { llm }
+    pub fn new(llm: LlmClient, prompts: PromptLibrary) -> Self {
+        Self { llm, prompts }
+    }
+
+    /// Answer with another model, as `/model` switches to
+    pub fn set_llm(&mut self, llm: LlmClient) {
+        self.llm = llm;
+    }
+
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.prompts.set_verbosity(verbosity);
+    }
+
+    /// Use prompts reloaded from sovereign.toml
+    pub fn set_prompts(&mut self, prompts: PromptLibrary) {
+        self.prompts = prompts;
     }
 
     /// Generate a commit message for the given diff
@@ -123,11 +163,50 @@ Only output the commit message, nothing else."#,
             truncate_diff(diff, 4000)
         );
 
-        self.llm.generate(&prompt, Some(GIT_SYSTEM_PROMPT)).await
+        self.llm
+            .generate_with(&prompt, Some(&self.prompts.system("commit", GIT_SYSTEM_PROMPT)), &self.prompts.options("commit"))
+            .await
+    }
+
+    /// Which of `subjects` are too vague to tell what changed, by index, with the reason
+    pub async fn vague_subjects(&self, subjects: &[&str]) -> Result<Vec<(usize, String)>> {
+        if subjects.is_empty() {
+            return Ok(Vec::new());
+        }
+        let system = self.prompts.system("commit_lint", commit_lint::VAGUE_SYSTEM_PROMPT);
+        let response = self
+            .llm
+            .generate_with(&commit_lint::vague_prompt(subjects), Some(&system), &self.prompts.options("commit_lint"))
+            .await?;
+        Ok(commit_lint::parse_vague(&response, subjects.len()))
+    }
+
+    /// Notes on what changed in each file since the session started, for a user catching up
+    pub async fn annotate_session_changes(&self, files: &str, diff: &str) -> Result<String> {
+        let prompt = format!(
+            r#"These files changed while the user was away from the chat.
+
+Files:
+{}
+
+Diff against the commit checked out when the session started:
+```
+{}
+```
+
+For each file, write one line "path: what changed". Then add one line on the overall effect of the changes.
+Only output the notes, nothing else."#,
+            files,
+            truncate_diff(diff, 6000)
+        );
+
+        self.llm
+            .generate_with(&prompt, Some(&self.prompts.system("whatchanged", GIT_SYSTEM_PROMPT)), &self.prompts.options("whatchanged"))
+            .await
     }
 
-    /// Generate a PR summary from a list of commits
-    pub async fn generate_pr_summary(&self, commits: &[Commit], diff: &str) -> Result<String> {
+    /// Generate a PR summary from a list of commits, as `template` lays it out if given
+    pub async fn generate_pr_summary(&self, commits: &[Commit], diff: &str, template: Option<&str>) -> Result<String> {
         if commits.is_empty() {
             return Ok("No commits found for PR summary.".to_string());
         }
@@ -140,6 +219,46 @@ Only output the commit message, nothing else."#,
             .collect::<Vec<_>>()
             .join("\n");
 
+        let system = self.prompts.system("pr_summary", GIT_SYSTEM_PROMPT);
+        let options = self.prompts.options("pr_summary");
+
+        // Diffs too large for one prompt are summarized a few files at a time
+        let parts = MapReduce::default()
+            .summarize_parts(diff, &|line| line.starts_with("diff --git"), |part| {
+                let prompt = format!(
+                    "This is {} of a branch diff:\n```diff\n{}\n```\n\nFor each file, describe what changed in one or two bullet points.",
+                    part.describe(),
+                    part.text
+                );
+                let (system, options) = (&system, &options);
+                async move { self.llm.generate_with(&prompt, Some(system), options).await }
+            })
+            .await?;
+        let changes = match parts {
+            None => format!("Full diff:\n```\n{}\n```", diff),
+            Some(parts) => format!("The diff is too large to show whole. Summaries of its parts, in order:\n\n{}", parts),
+        };
+
+        let layout = match template {
+            Some(template) => format!(
+                r#"Write the PR description by filling in this template:
+```markdown
+{}
+```
+
+Keep its headings, their order and any checklists. Replace placeholder text and HTML comments with content about these changes, and write "N/A" under a heading nothing applies to. Output only the filled-in description."#,
+                template.trim()
+            ),
+            None => r#"Write a PR summary with:
+1. A brief overview paragraph
+2. A "Changes" section with bullet points
+3. A "Testing" section with recommended test scenarios
+4. Any "Breaking Changes" if applicable
+
+Format using markdown."#
+                .to_string(),
+        };
+
         let prompt = format!(
             r#"Generate a pull request summary for the following changes.
 
@@ -151,28 +270,20 @@ Files changed: {}
 Total additions: approximately {}
 Total deletions: approximately {}
 
-Full diff (truncated):
-```
 {}
-```
 
-Write a PR summary with:
-1. A brief overview paragraph
-2. A "Changes" section with bullet points
-3. A "Testing" section with recommended test scenarios
-4. Any "Breaking Changes" if applicable
-
-Format using markdown."#,
+{}"#,
             commits.len(),
             commits_text,
             analysis.summary,
             analysis.affected_areas.join(", "),
             count_additions(diff),
             count_deletions(diff),
-            truncate_diff(diff, 3000)
+            changes,
+            layout
         );
 
-        self.llm.generate(&prompt, Some(GIT_SYSTEM_PROMPT)).await
+        self.llm.generate_with(&prompt, Some(&system), &options).await
     }
 
     /// Analyze a diff to understand the changes
@@ -201,7 +312,8 @@ Only output the JSON, nothing else."#,
                 truncate_diff(diff, 3000)
             );
 
-            if let Ok(response) = self.llm.generate(&prompt, Some(GIT_SYSTEM_PROMPT)).await {
+            let options = self.prompts.options("analyze_diff");
+            if let Ok(response) = self.llm.generate_with(&prompt, Some(GIT_SYSTEM_PROMPT), &options).await {
                 // Try to parse the response
                 if let Some(change_type) = extract_json_field(&response, "change_type") {
                     insights.change_type = match change_type.as_str() {
@@ -231,7 +343,7 @@ Only output the JSON, nothing else."#,
     }
 
     /// Perform local analysis of a diff without LLM
-    fn analyze_diff_locally(&self, diff: &str) -> DiffInsights {
+    pub fn analyze_diff_locally(&self, diff: &str) -> DiffInsights {
         let git_ops = GitOps::current_dir().unwrap_or_else(|_| GitOps::new("."));
         let analysis = git_ops.parse_diff(diff).unwrap_or_else(|_| DiffAnalysis {
             files: Vec::new(),
@@ -253,12 +365,45 @@ Only output the JSON, nothing else."#,
             breaking_potential,
             suggested_reviewers: Vec::new(),
             summary: analysis.summary,
+            files: analysis.files,
+            hunks: analysis.hunks,
+            total_additions: analysis.total_additions,
+            total_deletions: analysis.total_deletions,
+        }
+    }
+
+    /// Analyze staged changes or the current branch; `local_only` skips the LLM pass
+    pub async fn analyze_changes(&self, source: DiffSource, local_only: bool) -> Result<DiffInsights> {
+        let git_ops = GitOps::current_dir()?;
+
+        if !git_ops.is_git_repo() {
+            anyhow::bail!("Not a git repository.");
+        }
+
+        let diff = match source {
+            DiffSource::Staged => git_ops.get_staged_diff()?,
+            DiffSource::Branch => {
+                let current_branch = git_ops.get_current_branch()?;
+                let default_branch = git_ops.get_default_branch()?;
+                git_ops.get_diff_between(&default_branch, &current_branch)?
+            }
+        };
+
+        if local_only {
+            Ok(self.analyze_diff_locally(&diff))
+        } else {
+            self.analyze_diff(&diff).await
         }
     }
 
     /// Get staged diff and generate commit message
     pub async fn commit_message_for_staged(&self) -> Result<String> {
-        let git_ops = GitOps::current_dir()?;
+        self.commit_message_for_staged_in(&std::env::current_dir()?).await
+    }
+
+    /// Generate a commit message for what is staged in the repository at `repo`
+    pub async fn commit_message_for_staged_in(&self, repo: &Path) -> Result<String> {
+        let git_ops = GitOps::new(repo);
 
         if !git_ops.is_git_repo() {
             return Ok("Not a git repository.".to_string());
@@ -268,8 +413,21 @@ Only output the JSON, nothing else."#,
         self.generate_commit_message(&diff).await
     }
 
-    /// Generate PR summary for current branch
-    pub async fn pr_summary_for_branch(&self) -> Result<String> {
+    /// Generate a commit message to replace HEAD's, covering its changes and the staged ones
+    pub async fn commit_message_for_amend(&self) -> Result<String> {
+        let git_ops = GitOps::current_dir()?;
+
+        if !git_ops.is_git_repo() {
+            return Ok("Not a git repository.".to_string());
+        }
+
+        let diff = git_ops.get_amend_diff()?;
+        self.generate_commit_message(&diff).await
+    }
+
+    /// Generate a PR summary of the current branch against `base` (the default
+    /// branch when `None`), filling in `template` when given
+    pub async fn pr_summary_against(&self, base: Option<&str>, template: Option<&str>) -> Result<String> {
         let git_ops = GitOps::current_dir()?;
 
         if !git_ops.is_git_repo() {
@@ -277,19 +435,25 @@ Only output the JSON, nothing else."#,
         }
 
         let current_branch = git_ops.get_current_branch()?;
-        let default_branch = git_ops.get_default_branch()?;
+        let base = match base {
+            Some(base) => {
+                git_ops.resolve_commit(base)?;
+                base.to_string()
+            }
+            None => git_ops.get_default_branch()?,
+        };
 
-        if current_branch == default_branch {
+        if current_branch == base {
             return Ok(format!(
                 "You're on the {} branch. Create a feature branch first.",
-                default_branch
+                base
             ));
         }
 
-        let commits = git_ops.get_commits_between(&default_branch, &current_branch)?;
-        let diff = git_ops.get_diff_between(&default_branch, &current_branch)?;
+        let commits = git_ops.get_commits_between(&base, &current_branch)?;
+        let diff = git_ops.get_diff_between(&base, &current_branch)?;
 
-        self.generate_pr_summary(&commits, &diff).await
+        self.generate_pr_summary(&commits, &diff, template).await
     }
 }
 
@@ -486,9 +650,8 @@ impl SimpleRegex {
         let remaining = &text[value_start..];
         let remaining = remaining.trim_start();
 
-        let (value, _) = if remaining.starts_with('"') {
+        let (value, _) = if let Some(after_quote) = remaining.strip_prefix('"') {
             // Quoted string
-            let after_quote = &remaining[1..];
             let end_quote = after_quote.find('"')?;
             (&after_quote[..end_quote], end_quote + 2)
         } else {
@@ -499,6 +662,7 @@ impl SimpleRegex {
 
         Some(SimpleCaptures {
             value: value.to_string(),
+            _phantom: std::marker::PhantomData,
         })
     }
 }
@@ -568,4 +732,11 @@ mod tests {
         };
         assert_eq!(detect_complexity(&trivial), ChangeComplexity::Trivial);
     }
+
+    #[test]
+    fn test_insight_enums_serialize_as_labels() {
+        assert_eq!(serde_json::to_string(&ChangeType::Feature).unwrap(), r#""feat""#);
+        assert_eq!(serde_json::to_string(&ChangeComplexity::Moderate).unwrap(), r#""moderate""#);
+        assert_eq!(serde_json::to_string(&FileStatus::Renamed).unwrap(), r#""renamed""#);
+    }
 }
@@ -1,11 +1,13 @@
 mod code;
+mod commands;
 mod search;
 mod chat;
 mod orchestrator;
 mod git_agent;
 
 pub use code::CodeAgent;
+pub use commands::{ArgKind, CommandSpec, COMMANDS};
 pub use search::SearchAgent;
-pub use chat::ChatAgent;
-pub use orchestrator::Orchestrator;
-pub use git_agent::{GitAgent, DiffInsights, ChangeType, ChangeComplexity};
+pub use chat::{ChatAgent, ChatConfig};
+pub use orchestrator::{default_model, Orchestrator};
+pub use git_agent::{GitAgent, DiffInsights, DiffSource};
@@ -1,15 +1,429 @@
-use anyhow::Result;
-use std::path::PathBuf;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::future::Future;
 use std::pin::Pin;
-use futures::stream::Stream;
 
-use crate::llm::{LlmBackend, LlmClient};
-use crate::storage::{CodebaseIndex, MemoryStore, CrdtMemoryStore};
+use crate::compiler_output::{self, CompilerMessage};
+use crate::config::{Config, Overrides};
+use crate::consistency;
+use crate::consolidate::Consolidated;
+use crate::digest::Digest;
+use crate::docgen;
+use crate::dry_run;
+use crate::memories::Memories;
+use crate::embeddings::EmbeddingClient;
+use crate::git::{FileStatus, GitOps};
+use crate::glossary;
+use crate::jobs;
+use crate::grep::{self, GrepOptions};
+use crate::llm::{estimate_tokens, ChatMessage, LlmBackend, LlmClient};
+use crate::patch::{self, Backups, FileChange};
+use crate::prompts::Verbosity;
+use crate::rag::RagRetriever;
+use crate::refactor;
+use crate::review::{self, Finding};
+use crate::scoring::MemoryScorer;
+use crate::lsp::{self, LspClient, LspDiagnostic};
+use crate::storage::{AnalyticsStore, CodebaseIndex, GlossaryEntry, IndexChanges, IndexedFile, MemoryStore, CrdtMemoryStore, SearchFilters, TranscriptStore};
+use crate::storage::analytics;
+use crate::storage::retention::Pruned;
+use crate::snapshot::Snapshot;
+use crate::share::{ChunkReference, ChunkStatus, ModelInfo, ProjectInfo, Retrieval, ShareBundle, SHARE_FORMAT_VERSION};
+use crate::net;
+use crate::summarize::MapReduce;
 use crate::sync::P2PSync;
+use crate::testgen::{self, TestConventions};
+use crate::task::{self, Task, Tool};
+use crate::tools::ToolRunner;
+use super::commands::{self, CommandArgs, CommandSpec};
 use super::{CodeAgent, SearchAgent, ChatAgent, GitAgent};
+use super::search::{self, Answer, ConfidenceLevel};
 
 const SYNC_PORT: u16 = 7654;
 
+/// Definitions printed by `/def` when a name is defined in several places
+const MAX_DEFINITIONS_SHOWN: usize = 5;
+
+/// Body lines printed per definition by `/def`
+const MAX_DEFINITION_LINES: usize = 80;
+
+/// References printed by `/refs`
+const MAX_REFERENCES_SHOWN: usize = 50;
+
+/// Earlier chat exchanges given to `/ask --include-chats`
+const MAX_CHAT_EXCHANGES: usize = 3;
+
+/// Terms defined by `/glossary build` without a limit
+const DEFAULT_GLOSSARY_TERMS: usize = 30;
+
+/// Results shown per page by `/search`, `/symbol` and `/more`
+const RESULTS_PER_PAGE: usize = 10;
+
+/// Results fetched by `/search` and `/symbol`, to be paged through with `/more`
+const MAX_RESULTS: usize = 100;
+
+/// Close matches `/open` lists besides the file it opens
+const MAX_OPEN_ALTERNATIVES: usize = 4;
+
+/// Lines found by `/grep`, to be paged through with `/more`
+const MAX_GREP_MATCHES: usize = 500;
+
+/// Compiler messages shown with their code to `explain-error`
+const MAX_EXPLAINED_MESSAGES: usize = 3;
+
+/// Characters of compiler output sent to the model; the first errors come first
+const MAX_ERROR_OUTPUT_CHARS: usize = 6000;
+
+/// Documented definitions shown to `/doc` as examples of the project's style
+const MAX_DOC_EXAMPLES: usize = 3;
+
+/// Indexed files one `/review` or `/test` glob works through
+const MAX_GLOB_FILES: usize = 20;
+
+/// What `/review` and `/test` do with each indexed file they are given
+#[derive(Clone, Copy)]
+enum FileAction {
+    Review,
+    Test,
+}
+
+/// Results of the last `/search` or `/symbol`, numbered so later commands can
+/// refer to them (`/read #3`)
+struct ResultPages {
+    /// (file path, display line) of each result; result `#n` is at index n - 1
+    results: Vec<(String, String)>,
+    /// Results shown so far
+    shown: usize,
+}
+
+impl ResultPages {
+    fn new(results: Vec<(String, String)>) -> Self {
+        Self { results, shown: 0 }
+    }
+
+    /// The next page of results with their ids, and how to continue
+    fn next_page(&mut self) -> String {
+        if self.shown >= self.results.len() {
+            return "No more results.".to_string();
+        }
+
+        let start = self.shown;
+        let end = (start + RESULTS_PER_PAGE).min(self.results.len());
+        let mut output: Vec<String> = self.results[start..end]
+            .iter()
+            .enumerate()
+            .map(|(i, (_, line))| format!("  [{}] {}", start + i + 1, line))
+            .collect();
+        self.shown = end;
+
+        let remaining = self.results.len() - end;
+        output.push(if remaining > 0 {
+            format!("Showing {}-{} of {}. /more for the next {}; use #N as a file (/read #{}).",
+                start + 1, end, self.results.len(), remaining.min(RESULTS_PER_PAGE), start + 1)
+        } else {
+            format!("Showing {}-{} of {}. Use #N as a file (/read #{}).", start + 1, end, self.results.len(), start + 1)
+        });
+        output.join("\n")
+    }
+
+    /// File path of result `#n`
+    fn path(&self, id: &str) -> Option<&str> {
+        let n: usize = id.strip_prefix('#')?.parse().ok()?;
+        self.results.get(n.checked_sub(1)?).map(|(path, _)| path.as_str())
+    }
+}
+
+/// Files of one project changed on disk since the session started, as found by
+/// re-indexing (the daemon's watcher re-indexes on every change)
+#[derive(Debug)]
+struct SessionChanges {
+    /// Commit HEAD pointed to when the project was first indexed, if it is a git repo
+    base_commit: Option<String>,
+    files: BTreeMap<String, ChangedFile>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ChangedFile {
+    /// Added, Modified or Deleted relative to the start of the session
+    status: FileStatus,
+    /// Re-indexing runs that saw the file change
+    times: usize,
+    last_changed: DateTime<Utc>,
+}
+
+impl SessionChanges {
+    fn new(base_commit: Option<String>) -> Self {
+        Self {
+            base_commit,
+            files: BTreeMap::new(),
+        }
+    }
+
+    fn record(&mut self, changes: &IndexChanges, at: DateTime<Utc>) {
+        let updates = changes.added.iter().map(|p| (p, FileStatus::Added))
+            .chain(changes.modified.iter().map(|p| (p, FileStatus::Modified)))
+            .chain(changes.removed.iter().map(|p| (p, FileStatus::Deleted)));
+
+        for (path, status) in updates {
+            let previous = self.files.get(path).map(|f| (f.status.clone(), f.times));
+            let status = match (previous.as_ref().map(|(s, _)| s), status) {
+                // Created and deleted again within the session: nothing to report
+                (Some(FileStatus::Added), FileStatus::Deleted) => {
+                    self.files.remove(path);
+                    continue;
+                }
+                (Some(FileStatus::Added), _) => FileStatus::Added,
+                (Some(FileStatus::Deleted), FileStatus::Added) => FileStatus::Modified,
+                (_, status) => status,
+            };
+
+            self.files.insert(path.clone(), ChangedFile {
+                status,
+                times: previous.map(|(_, t)| t).unwrap_or(0) + 1,
+                last_changed: at,
+            });
+        }
+    }
+
+    /// One line per file: path, how it changed, how often and when last
+    fn describe(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .map(|(path, file)| {
+                let times = if file.times == 1 { String::new() } else { format!(", {} times", file.times) };
+                format!(
+                    "{} ({}{}, last at {})",
+                    path,
+                    file.status.as_str(),
+                    times,
+                    file.last_changed.with_timezone(&chrono::Local).format("%H:%M")
+                )
+            })
+            .collect()
+    }
+}
+
+/// The git branch checked out at `path`; None outside a repository or with a detached HEAD
+fn checked_out_branch(path: &Path) -> Option<String> {
+    let git = GitOps::new(path);
+    if !git.is_git_repo() {
+        return None;
+    }
+    git.get_current_branch().ok().filter(|branch| !branch.is_empty())
+}
+
+/// The `/grep` pattern (quoted when it has spaces) with any
+/// `language:`/`path:`/`symbol:` filters, and the `-F`/`-i` switches
+fn parse_grep_args(args: &CommandArgs) -> (String, GrepOptions) {
+    let mut options = GrepOptions {
+        literal: args.flag("--fixed-strings"),
+        ignore_case: args.flag("--ignore-case"),
+        ..Default::default()
+    };
+    let rest = args.text("regex");
+
+    // A quoted pattern is taken as written, spaces and all; filters may follow it
+    let quoted = rest.chars().next().filter(|c| *c == '"' || *c == '\'').and_then(|quote| {
+        let end = rest[1..].rfind(quote)? + 1;
+        Some((rest[1..end].to_string(), &rest[end + 1..]))
+    });
+    let pattern = match quoted {
+        Some((pattern, after)) => {
+            options.filters = search::parse_filters(after).0;
+            pattern
+        }
+        None => {
+            let (filters, pattern) = search::parse_filters(rest);
+            options.filters = filters;
+            pattern
+        }
+    };
+    (pattern, options)
+}
+
+/// One line per diagnostic: `path:line:column [severity] message`, paths relative to `root`
+fn format_diagnostics(diagnostics: &[LspDiagnostic], root: &Path) -> String {
+    diagnostics.iter()
+        .map(|d| format!(
+            "  {}:{}:{} [{}] {}",
+            d.path.strip_prefix(root).unwrap_or(&d.path).display(),
+            d.line,
+            d.column,
+            d.severity.as_str(),
+            d.message.lines().next().unwrap_or("")
+        ))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Split `/fix` and `/refactor` arguments into the description and the code
+/// in ``` fences after it
+fn split_code_block(args: &str) -> (&str, &str) {
+    let code_start = args.find("```").unwrap_or(args.len());
+    let code = args[code_start..]
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+    (args[..code_start].trim(), code)
+}
+
+/// Runs a command with its checked arguments
+pub type CommandHandler =
+    for<'a> fn(&'a mut Orchestrator, &'a CommandArgs) -> Pin<Box<dyn Future<Output = Result<String>> + 'a>>;
+
+/// The handler of each built-in command in [`COMMANDS`]
+const HANDLERS: &[(&str, CommandHandler)] = &[
+    ("/search", |o, args| Box::pin(o.search_command(args))),
+    ("/grep", |o, args| Box::pin(o.grep_command(args))),
+    ("/symbol", |o, args| Box::pin(o.symbol_command(args))),
+    ("/more", |o, args| Box::pin(o.more_command(args))),
+    ("/def", |o, args| Box::pin(o.def_command(args))),
+    ("/refs", |o, args| Box::pin(o.refs_command(args))),
+    ("/ask", |o, args| Box::pin(o.ask_command(args))),
+    ("/regenerate", |o, args| Box::pin(o.regenerate_command(args))),
+    ("/read", |o, args| Box::pin(o.read_command(args))),
+    ("/open", |o, args| Box::pin(o.open_command(args))),
+    ("/summarize", |o, args| Box::pin(o.summarize_command(args))),
+    ("/summarize-all", |o, args| Box::pin(o.summarize_all(args.number("n")))),
+    ("/glossary", |o, args| Box::pin(o.glossary_command(args))),
+    ("/deps", |o, args| Box::pin(o.deps_command(args, false))),
+    ("/rdeps", |o, args| Box::pin(o.deps_command(args, true))),
+    ("/index", |o, args| Box::pin(o.index_command(args))),
+    ("/whatchanged", |o, _| Box::pin(o.what_changed())),
+    ("/embed", |o, args| Box::pin(o.embed_command(args))),
+    ("/stats", |o, args| Box::pin(o.stats_command(args))),
+    ("/run", |o, args| Box::pin(o.run_command(args))),
+    ("/check-edits", |o, args| Box::pin(o.check_edits_command(args))),
+    ("/diagnostics", |o, args| Box::pin(o.diagnostics_command(args))),
+    ("/definition", |o, args| Box::pin(o.definition_command(args))),
+    ("/generate", |o, args| Box::pin(o.code_agent.generate_code(args.text("desc"), None, None))),
+    ("/explain", |o, args| Box::pin(o.explain_command(args))),
+    ("/review", |o, args| Box::pin(o.review_command(args))),
+    ("/test", |o, args| Box::pin(o.test_command(args))),
+    ("/fix", |o, args| Box::pin(o.fix_command(args))),
+    ("/refactor", |o, args| Box::pin(o.refactor_command(args))),
+    ("/edit", |o, args| Box::pin(o.edit_command(args))),
+    ("/refactor-all", |o, args| Box::pin(o.refactor_all_command(args))),
+    ("/doc", |o, args| Box::pin(o.doc_command(args))),
+    ("/task", |o, args| Box::pin(o.task_command(args))),
+    ("/apply", |o, args| Box::pin(o.apply_command(args))),
+    ("/undo", |o, args| Box::pin(o.undo_command(args))),
+    ("/commit", |o, args| Box::pin(o.commit_command(args))),
+    ("/review-staged", |o, _| Box::pin(o.review_staged_command())),
+    ("/pr-summary", |o, args| Box::pin(o.pr_summary_command(args))),
+    ("/memory", |o, args| Box::pin(o.memory_command(args))),
+    ("/memory-show", |o, args| Box::pin(o.memory_show_command(args))),
+    ("/memory-edit", |o, args| Box::pin(o.memory_edit_command(args))),
+    ("/memory-delete", |o, args| Box::pin(o.memory_delete_command(args))),
+    ("/memory-set-importance", |o, args| Box::pin(o.memory_set_importance_command(args))),
+    ("/analytics", |o, args| Box::pin(o.analytics_command(args))),
+    ("/share", |o, args| Box::pin(o.share_command(args))),
+    ("/share-load", |o, args| Box::pin(o.import_share(Path::new(args.text("file"))))),
+    ("/context", |o, args| Box::pin(o.context_command(args))),
+    ("/pin", |o, args| Box::pin(o.pin_command(args))),
+    ("/unpin", |o, args| Box::pin(o.unpin_command(args))),
+    ("/checkpoint", |o, args| Box::pin(o.checkpoint_command(args))),
+    ("/rewind", |o, args| Box::pin(o.rewind_command(args))),
+    ("/clear", |o, args| Box::pin(o.clear_command(args))),
+    ("/verbosity", |o, args| Box::pin(o.verbosity_command(args))),
+    ("/model", |o, args| Box::pin(o.model_command(args))),
+    ("/backend", |o, args| Box::pin(o.backend_command(args))),
+    ("/reload", |o, args| Box::pin(o.reload_command(args))),
+    ("/help", |o, args| Box::pin(o.help_command(args))),
+    ("/sync-export", |o, args| Box::pin(o.sync_export_command(args))),
+    ("/sync-import", |o, args| Box::pin(o.sync_import_command(args))),
+    ("/sync-status", |o, args| Box::pin(o.sync_status_command(args))),
+    ("/sync-pull", |o, args| Box::pin(o.sync_pull_command(args))),
+    ("/sync-push", |o, args| Box::pin(o.sync_push_command(args))),
+    ("/sync-live", |o, args| Box::pin(o.sync_live_command(args))),
+];
+
+/// Model used on `backend` when none is named
+pub fn default_model(backend: LlmBackend) -> &'static str {
+    match backend {
+        LlmBackend::Ollama => "qwen2.5-coder:14b",
+        LlmBackend::DeepSeek => "deepseek-chat",
+    }
+}
+
+/// Client for `model` on `backend`, with the `[ollama]` defaults and what `[models]` says it can do
+fn llm_client(config: &Config, backend: LlmBackend, model: &str, api_key: Option<&str>) -> Result<LlmClient> {
+    let capabilities = config.models.capabilities(model).unwrap_or_default();
+    Ok(LlmClient::new(backend, model, api_key)?
+        .with_servers(&config.servers)
+        .with_defaults(config.ollama.clone())
+        .with_capabilities(capabilities))
+}
+
+/// Retriever for the code shown with each chat message
+fn chat_retriever(config: &Config) -> RagRetriever {
+    let embedding_client = EmbeddingClient::from_config(&config.embeddings).with_servers(&config.servers);
+    RagRetriever::new(config.rag.clone()).with_embedding_client(embedding_client)
+}
+
+/// Conversation state saved by `/checkpoint`
+#[derive(Debug, Clone)]
+struct Checkpoint {
+    /// Messages after the system prompt, which is rebuilt every turn
+    messages: Vec<ChatMessage>,
+    retrievals: Vec<Retrieval>,
+    last_question: Option<String>,
+}
+
+/// A daemon session's conversation and the work it has in hand, put aside while
+/// the orchestrator serves another session
+#[derive(Default)]
+struct SessionState {
+    /// Messages after the system prompt
+    messages: Vec<ChatMessage>,
+    pinned: Vec<PathBuf>,
+    retrievals: Vec<Retrieval>,
+    last_question: Option<String>,
+    checkpoints: BTreeMap<String, Checkpoint>,
+    pending_changes: Vec<FileChange>,
+    task: Option<Task>,
+    last_results: Option<ResultPages>,
+}
+
+impl SessionState {
+    /// Move the session state out of `orchestrator`, leaving it with a fresh conversation
+    fn take(orchestrator: &mut Orchestrator) -> Self {
+        let messages = orchestrator.chat_agent.conversation()[1..].to_vec();
+        orchestrator.chat_agent.clear_conversation();
+        Self {
+            messages,
+            pinned: orchestrator.chat_agent.unpin(None),
+            retrievals: std::mem::take(&mut orchestrator.retrievals),
+            last_question: orchestrator.last_question.take(),
+            checkpoints: std::mem::take(&mut orchestrator.checkpoints),
+            pending_changes: std::mem::take(&mut orchestrator.pending_changes),
+            task: orchestrator.task.take(),
+            last_results: orchestrator.last_results.take(),
+        }
+    }
+
+    /// Carry on the session in `orchestrator`, which must have a fresh conversation
+    fn restore(self, orchestrator: &mut Orchestrator) {
+        orchestrator.chat_agent.restore_conversation(&self.messages);
+        for path in &self.pinned {
+            orchestrator.chat_agent.pin(path);
+        }
+        orchestrator.retrievals = self.retrievals;
+        orchestrator.last_question = self.last_question;
+        orchestrator.checkpoints = self.checkpoints;
+        orchestrator.pending_changes = self.pending_changes;
+        orchestrator.task = self.task;
+        orchestrator.last_results = self.last_results;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.messages.is_empty() && self.pinned.is_empty() && self.checkpoints.is_empty()
+            && self.pending_changes.is_empty() && self.task.is_none() && self.last_results.is_none()
+    }
+}
+
 pub struct Orchestrator {
     pub code_agent: CodeAgent,
     pub search_agent: SearchAgent,
@@ -19,30 +433,77 @@ pub struct Orchestrator {
     pub memory: MemoryStore,
     pub crdt_memory: CrdtMemoryStore,
     pub p2p_sync: P2PSync,
+    tool_runner: Option<ToolRunner>,
+    project_root: Option<PathBuf>,
+    lsp_command: Option<Vec<String>>,
+    lsp: Option<LspClient>,
+    model: String,
+    backend: LlmBackend,
+    /// For the clients of a backend switched to with `/backend`
+    api_key: Option<String>,
+    config: Config,
+    /// Command-line settings applied over every sovereign.toml read
+    overrides: Overrides,
+    /// Code retrieved for questions this session, for `/share`
+    retrievals: Vec<Retrieval>,
+    /// Last `/ask` question, for `/regenerate`
+    last_question: Option<String>,
+    /// Conversations saved this session, for `/rewind`
+    checkpoints: BTreeMap<String, Checkpoint>,
+    /// Changes proposed by `/edit`, for `/apply`
+    pending_changes: Vec<FileChange>,
+    /// Copies of the files `/apply` changed, for `/undo`
+    backups: Backups,
+    /// `/task` paused at a step waiting to be confirmed
+    task: Option<Task>,
+    /// Last search results, for `/more` and `#N` references
+    last_results: Option<ResultPages>,
+    /// Changes re-indexing found since each project was first indexed, for `/whatchanged`
+    session_changes: HashMap<PathBuf, SessionChanges>,
+    /// Commands already warned about this session for needing something the model lacks
+    capability_notes: HashSet<&'static str>,
+    analytics: Option<AnalyticsStore>,
+    /// Full chat exchanges, for `/ask --include-chats`
+    transcripts: TranscriptStore,
+    /// Daemon session being served; the default session is ""
+    session: String,
+    /// Other sessions this orchestrator serves, by id
+    parked_sessions: HashMap<String, SessionState>,
     data_dir: PathBuf,
 }
 
 impl Orchestrator {
-    pub fn new(model: &str, backend: LlmBackend, api_key: Option<&str>, data_dir: PathBuf) -> Result<Self> {
-        let memory = MemoryStore::new(&data_dir)?;
-        let crdt_memory = CrdtMemoryStore::new(&data_dir)?;
-        let p2p_sync = P2PSync::new(data_dir.clone(), SYNC_PORT);
+    pub fn new(model: &str, backend: LlmBackend, api_key: Option<&str>, data_dir: PathBuf, overrides: &Overrides) -> Result<Self> {
+        let config = Config::load(&data_dir, None)?.with_overrides(overrides);
+        let memory = MemoryStore::new(&data_dir)?.with_dry_run(overrides.dry_run);
+        let crdt_memory = CrdtMemoryStore::new(&data_dir)?.with_dry_run(overrides.dry_run);
+        let p2p_sync = P2PSync::new(data_dir.clone(), SYNC_PORT)
+            .with_token(net::resolve_token(None))
+            .with_dry_run(overrides.dry_run);
+        let prompts = config.prompts.clone();
 
-        let code_llm = LlmClient::new(backend, model, api_key)?;
-        let code_memory = MemoryStore::new(&data_dir)?;
-        let code_agent = CodeAgent::new(code_llm, code_memory);
+        let code_llm = llm_client(&config, backend, model, api_key)?;
+        let code_memory = memory.clone();
+        let mut code_agent = CodeAgent::new(code_llm, code_memory, prompts.clone());
+        let scorer = MemoryScorer::from_config(&config.scoring, model, &config.servers, prompts.clone());
+        code_agent.set_scorer(scorer.clone());
 
-        let search_llm = LlmClient::new(backend, model, api_key)?;
-        let search_agent = SearchAgent::new(search_llm);
+        let search_llm = llm_client(&config, backend, model, api_key)?;
+        let search_agent = SearchAgent::new(search_llm, &config);
 
-        let chat_llm = LlmClient::new(backend, model, api_key)?;
-        let chat_memory = MemoryStore::new(&data_dir)?;
-        let chat_agent = ChatAgent::new(chat_llm, chat_memory);
+        let chat_llm = llm_client(&config, backend, model, api_key)?;
+        let chat_memory = memory.clone();
+        let mut chat_agent = ChatAgent::new(chat_llm, chat_memory, prompts.clone());
+        chat_agent.set_embedding_client(EmbeddingClient::from_config(&config.embeddings).with_servers(&config.servers));
+        chat_agent.set_transcripts(TranscriptStore::new(&data_dir)?.with_dry_run(overrides.dry_run));
+        chat_agent.set_scorer(scorer);
+        chat_agent.set_config(config.chat.clone());
+        chat_agent.set_retriever(chat_retriever(&config));
 
-        let git_llm = LlmClient::new(backend, model, api_key)?;
-        let git_agent = GitAgent::new(git_llm);
+        let git_llm = llm_client(&config, backend, model, api_key)?;
+        let git_agent = GitAgent::new(git_llm, prompts);
 
-        Ok(Self {
+        let mut orchestrator = Self {
             code_agent,
             search_agent,
             chat_agent,
@@ -51,16 +512,268 @@ impl Orchestrator {
             memory,
             crdt_memory,
             p2p_sync,
+            tool_runner: None,
+            project_root: None,
+            lsp_command: None,
+            lsp: None,
+            model: model.to_string(),
+            backend,
+            api_key: api_key.map(str::to_string),
+            config,
+            overrides: overrides.clone(),
+            retrievals: Vec::new(),
+            last_question: None,
+            checkpoints: BTreeMap::new(),
+            pending_changes: Vec::new(),
+            backups: Backups::new(&data_dir),
+            task: None,
+            last_results: None,
+            session_changes: HashMap::new(),
+            capability_notes: HashSet::new(),
+            analytics: AnalyticsStore::open_if_enabled(&data_dir, !overrides.no_analytics),
+            transcripts: TranscriptStore::new(&data_dir)?.with_dry_run(overrides.dry_run),
+            session: String::new(),
+            parked_sessions: HashMap::new(),
             data_dir,
-        })
+        };
+
+        // Commands work on the current project's index without re-indexing it first
+        if let Ok(cwd) = std::env::current_dir() {
+            if let Err(e) = orchestrator.load_codebase(&cwd) {
+                eprintln!("  Could not open the index for {}: {}", cwd.display(), e);
+            }
+        }
+
+        Ok(orchestrator)
+    }
+
+    /// Serve the daemon session `id` from now on, putting the current session's
+    /// conversation aside; a session not seen before starts a fresh one
+    pub fn switch_session(&mut self, id: &str) {
+        if self.session == id {
+            return;
+        }
+        let current = SessionState::take(self);
+        if !current.is_empty() {
+            self.parked_sessions.insert(std::mem::take(&mut self.session), current);
+        }
+        if let Some(next) = self.parked_sessions.remove(id) {
+            next.restore(self);
+        }
+        self.session = id.to_string();
+    }
+
+    /// Forget the conversation of session `id`; false if it had none
+    pub fn end_session(&mut self, id: &str) -> bool {
+        if self.session == id {
+            return !SessionState::take(self).is_empty();
+        }
+        self.parked_sessions.remove(id).is_some()
+    }
+
+    /// A note that the model lacks something `command` relies on, given once per session
+    pub fn capability_note(&mut self, command: &'static str) -> Option<String> {
+        let note = self.config.models.warning(&self.model, command)?;
+        self.capability_notes.insert(command).then_some(note)
+    }
+
+    /// `output` with the capability note for `command` in front, if there is one
+    fn with_capability_note(&mut self, command: &'static str, output: String) -> String {
+        match self.capability_note(command) {
+            Some(note) => format!("{}\n\n{}", note, output),
+            None => output,
+        }
+    }
+
+    /// Write the memory digest if `[digest]` scheduling is on and one is due
+    pub fn write_scheduled_digest(&self) -> Result<Option<PathBuf>> {
+        let now = chrono::Utc::now();
+        if !self.config.digest.is_due(&self.data_dir, now) {
+            return Ok(None);
+        }
+
+        let digest = Digest::collect(&self.memory, self.config.digest.interval_days, now)?;
+        let dir = self.config.digest.output_dir(&self.data_dir);
+        if self.dry_run() {
+            dry_run::report(format!("write the scheduled digest to {}", dir.display()));
+            return Ok(None);
+        }
+        let path = digest.write(&dir)?;
+        Ok(Some(path))
+    }
+
+    /// Prune memories by the `[retention]` policy; when `scheduled`, only if `auto_prune` is on
+    pub fn prune_memories(&self, scheduled: bool) -> Result<Vec<Pruned>> {
+        if scheduled && !self.config.retention.auto_prune {
+            return Ok(Vec::new());
+        }
+        self.config.retention.prune(&self.memory, chrono::Utc::now())
+    }
+
+    /// Condense old conversations by the `[consolidation]` policy; when `scheduled`,
+    /// only if the daemon is meant to
+    pub async fn consolidate_memories(&self, scheduled: bool) -> Result<Vec<Consolidated>> {
+        if scheduled && !self.config.consolidation.scheduled {
+            return Ok(Vec::new());
+        }
+        self.config
+            .consolidation
+            .consolidate(&self.memory, &self.chat_agent.llm, &self.config.prompts, chrono::Utc::now())
+            .await
+    }
+
+    pub fn index_codebase(&mut self, path: &Path) -> Result<usize> {
+        // Stored paths are absolute, so the index is found again from any directory
+        let path = &path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        jobs::progress(format!("Indexing codebase at {:?}...", path));
+        let config = Config::load(&self.data_dir, Some(path))?.with_overrides(&self.overrides);
+        let index = CodebaseIndex::with_tokenizer(&self.data_dir, path, &config.rag.fts_tokenizer)?
+            .with_config(&config.index)?
+            .with_branch(checked_out_branch(path))
+            .with_dry_run(config.dry_run);
+
+        // A dry run only plans the changes; following a branch switch is left to a real run
+        if self.dry_run() {
+            let changes = index.index_changes(true)?;
+            self.use_codebase(path, index, config, None);
+            return Ok(changes.added.len() + changes.modified.len());
+        }
+
+        let switched_from = index.switched_from()?;
+        let changes = index.index_changes(true)?;
+        let count = changes.added.len() + changes.modified.len();
+
+        if let Some(previous) = switched_from {
+            self.report_branch_switch(path, &index, &previous, count + changes.removed.len());
+            // Files a checkout changed are not edits made in this session
+            self.session_changes.remove(path);
+        }
+        self.use_codebase(path, index, config, Some(&changes));
+        Ok(count)
+    }
+
+    /// Tell the user the index followed a branch switch, and forget the vectors
+    /// kept for branches deleted since
+    fn report_branch_switch(&self, path: &Path, index: &CodebaseIndex, previous: &str, changed: usize) {
+        let pending = index.pending_embedding_count().unwrap_or(0);
+        let embedding = if pending > 0 { format!(", {} to re-embed", pending) } else { String::new() };
+        println!(
+            "  Switched from {} to {}: {} files differ{}",
+            previous,
+            index.indexed_branch().ok().flatten().unwrap_or_default(),
+            changed,
+            embedding
+        );
+
+        if let Ok(branches) = GitOps::new(path).local_branches() {
+            let _ = index.drop_branch_vectors(&branches);
+        }
+    }
+
+    /// Root of the project commands work on
+    pub fn project_root(&self) -> Option<&PathBuf> {
+        self.project_root.as_ref()
+    }
+
+    /// Open the stored index of the project containing `dir` without re-indexing;
+    /// false when that project was never indexed
+    pub fn load_codebase(&mut self, dir: &Path) -> Result<bool> {
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let Some(root) = CodebaseIndex::find_indexed_root(&self.data_dir, &dir)? else {
+            return Ok(false);
+        };
+
+        let config = Config::load(&self.data_dir, Some(&root))?.with_overrides(&self.overrides);
+        let index = CodebaseIndex::with_tokenizer(&self.data_dir, &root, &config.rag.fts_tokenizer)?
+            .with_config(&config.index)?
+            .with_branch(checked_out_branch(&root))
+            .with_dry_run(config.dry_run);
+
+        // Results from another branch's code would not match the checkout
+        if index.switched_from()?.is_some() {
+            self.index_codebase(&root)?;
+            return Ok(true);
+        }
+        self.use_codebase(&root, index, config, None);
+        Ok(true)
+    }
+
+    /// Explain a file along with the definitions it uses from the files it imports,
+    /// so calls into the rest of the project are described from their code
+    pub async fn explain_file(&mut self, path: &Path) -> Result<String> {
+        let path = path.canonicalize()?;
+        let code = std::fs::read_to_string(&path)?;
+        let path_str = path.to_string_lossy().to_string();
+
+        let in_project = self.codebase.as_ref().is_some_and(|index| index.is_in_project(&path_str));
+        if !in_project {
+            if let Some(dir) = path.parent() {
+                self.load_codebase(dir)?;
+            }
+        }
+
+        let (language, mut related) = match &self.codebase {
+            Some(index) => {
+                let language = index.get_file(&path_str).ok().flatten().map(|file| file.language);
+                // Definitions from imported files, then what the project's own terms mean
+                let related: Vec<String> = search::dependency_context(index, &path_str, &code)
+                    .into_iter()
+                    .chain(glossary::prompt_section(&index.glossary().unwrap_or_default(), &code))
+                    .collect();
+                (language, related)
+            }
+            None => (None, Vec::new()),
+        };
+        // Then what the language server reports about it
+        related.extend(self.diagnostics_context(&path_str).await);
+        let related = Some(related.join("\n\n")).filter(|r| !r.is_empty());
+
+        self.code_agent.explain_code(&code, language.as_deref(), related.as_deref()).await
     }
 
-    pub fn index_codebase(&mut self, path: &PathBuf) -> Result<usize> {
-        println!("  Indexing codebase at {:?}...", path);
-        let index = CodebaseIndex::new(&self.data_dir, path)?;
-        let count = index.index_directory(true)?;
+    /// Make `index` the current project, with `changes` from re-indexing it if it was
+    fn use_codebase(&mut self, path: &PathBuf, index: CodebaseIndex, config: Config, changes: Option<&IndexChanges>) {
         self.codebase = Some(index);
 
+        // Build/test tools run where the project's toolchain lives
+        self.tool_runner = Some(ToolRunner::new(path, &config.tools));
+
+        // A language server is started on first use, for the current project only
+        if self.project_root.as_ref() != Some(path) {
+            self.stop_lsp();
+        }
+
+        // Changes are tracked from the first index of a project in this session;
+        // loading a stored index does not start tracking, as edits since are unknown
+        if let Some(changes) = changes {
+            match self.session_changes.get_mut(path) {
+                Some(session) => session.record(changes, Utc::now()),
+                None => {
+                    let git = GitOps::new(path);
+                    let base_commit = if git.is_git_repo() { git.get_head_commit().ok() } else { None };
+                    self.session_changes.insert(path.clone(), SessionChanges::new(base_commit));
+                }
+            }
+        }
+        self.lsp_command = config.lsp.server_command(path);
+        self.project_root = Some(path.clone());
+
+        // A verbosity switched with /verbosity lasts for the session
+        let verbosity = self.config.prompts.verbosity();
+        self.config = config;
+        self.config.prompts.set_verbosity(verbosity);
+
+        // The project's sovereign.toml can tune chat retrieval or turn it off
+        self.chat_agent.set_config(self.config.chat.clone());
+        self.chat_agent.set_retriever(chat_retriever(&self.config));
+
+        // Memories stored from here on are tagged with the project
+        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+            self.memory.set_default_project(name);
+            self.chat_agent.set_project_name(name);
+            self.code_agent.set_project_name(name);
+        }
+
         // Update chat agent with project context
         if let Some(ref idx) = self.codebase {
             if let Ok(stats) = idx.get_stats() {
@@ -75,348 +788,2078 @@ impl Orchestrator {
                         .join(", ")
                 );
                 self.chat_agent.set_project_context(context);
+
+                if let Some(ref analytics) = self.analytics {
+                    let _ = analytics.record_index_size(stats.total_files, stats.total_lines);
+                }
             }
         }
-
-        Ok(count)
     }
 
-    pub fn get_codebase_stats(&self) -> Option<crate::storage::codebase::CodebaseStats> {
-        self.codebase.as_ref().and_then(|c| c.get_stats().ok())
+    /// Re-embed a batch of changed files; `None` when nothing is indexed
+    pub async fn process_embedding_queue(&self, batch: usize) -> Result<Option<(usize, usize)>> {
+        // Only a real run can queue files, so a dry run leaves the queue as it is
+        if self.dry_run() {
+            return Ok(self.codebase.as_ref().and_then(|index| index.pending_embedding_count().ok()).map(|n| (0, n)));
+        }
+        match self.codebase {
+            Some(ref index) => Ok(Some(self.search_agent.process_embedding_queue(index, batch).await?)),
+            None => Ok(None),
+        }
     }
 
-    pub async fn process_command(&mut self, input: &str) -> Result<String> {
-        let input = input.trim();
+    /// Language server for the indexed project, started on first use
+    async fn lsp_client(&mut self) -> Result<&mut LspClient> {
+        if self.lsp.is_none() {
+            let root = self.project_root.clone()
+                .ok_or_else(|| anyhow::anyhow!("No codebase indexed. Use /index <path> first."))?;
+            let command = self.lsp_command.clone().ok_or_else(|| {
+                anyhow::anyhow!("No language server known for this project. Set [lsp] command in sovereign.toml.")
+            })?;
 
-        // Parse command
-        if input.starts_with('/') {
-            return self.handle_command(input).await;
+            println!("  Starting {}...", command[0]);
+            self.lsp = Some(LspClient::start(&root, &command).await?);
         }
 
-        // Default to chat
-        self.chat_agent.chat(input).await
+        Ok(self.lsp.as_mut().expect("language server started above"))
     }
 
-    /// Process a command with streaming response for WebSocket support
-    pub async fn process_command_streaming(
-        &mut self,
-        input: &str,
-    ) -> Result<Pin<Box<dyn Stream<Item = String> + Send>>> {
-        let input = input.trim();
+    /// Exact diagnostics for one file from the project's language server
+    ///
+    /// Servers publish asynchronously after a file is opened, so this polls
+    /// briefly before returning whatever has arrived.
+    pub async fn lsp_diagnostics(&mut self, file: &str) -> Result<Vec<LspDiagnostic>> {
+        let indexed = self.codebase.as_ref()
+            .and_then(|index| index.get_file(file).ok().flatten())
+            .ok_or_else(|| anyhow::anyhow!("File not found in index: {}", file))?;
 
-        // Commands that don't stream - wrap result in a single-item stream
-        if input.starts_with('/') {
-            let result = self.handle_command(input).await?;
-            let stream = futures::stream::once(async move { result });
-            return Ok(Box::pin(stream));
+        let path = PathBuf::from(&indexed.path);
+        let content = std::fs::read_to_string(&path)?;
+        let client = self.lsp_client().await?;
+        client.open_file(&path, lsp::language_id(&indexed.language), &content).await?;
+
+        for _ in 0..20 {
+            let diagnostics = client.diagnostics(&path);
+            if !diagnostics.is_empty() {
+                return Ok(diagnostics);
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(250)).await;
         }
 
-        // Chat with streaming
-        let mut rx = self.chat_agent.chat_streaming(input).await?;
+        Ok(client.diagnostics(&path))
+    }
 
-        // Convert the mpsc receiver to a stream
-        let stream = async_stream::stream! {
-            let mut full_response = String::new();
-            while let Some(chunk) = rx.recv().await {
-                full_response.push_str(&chunk);
-                yield chunk;
+    /// The language server's diagnostics for an indexed `file`, as prompt context;
+    /// `None` when the project has no server or it reports nothing
+    async fn diagnostics_context(&mut self, file: &str) -> Option<String> {
+        self.lsp_command.as_ref()?;
+        let diagnostics = match self.lsp_diagnostics(file).await {
+            Ok(diagnostics) => diagnostics,
+            Err(e) => {
+                eprintln!("  Could not get diagnostics for {}: {}", file, e);
+                return None;
             }
         };
+        if diagnostics.is_empty() {
+            return None;
+        }
 
-        Ok(Box::pin(stream))
+        let server = self.lsp.as_ref().map_or("the language server", |client| client.server());
+        let root = self.project_root.clone().unwrap_or_default();
+        Some(format!("Diagnostics from {}:\n{}", server, format_diagnostics(&diagnostics, &root)))
     }
 
-    async fn handle_command(&mut self, input: &str) -> Result<String> {
-        let parts: Vec<&str> = input.splitn(2, ' ').collect();
-        let cmd = parts[0];
-        let args = parts.get(1).map(|s| s.trim()).unwrap_or("");
-
-        match cmd {
-            "/search" | "/s" => {
-                if let Some(ref index) = self.codebase {
-                    let results = self.search_agent.semantic_search(index, args, 10).await?;
-                    if results.is_empty() {
-                        Ok("No results found.".to_string())
-                    } else {
-                        Ok(results.iter()
-                            .map(|r| format!("  {} ({})", r.path, r.language))
-                            .collect::<Vec<_>>()
-                            .join("\n"))
-                    }
-                } else {
-                    Ok("No codebase indexed. Use /index <path> first.".to_string())
-                }
+    /// Shut the language server down, without waiting for it to exit
+    fn stop_lsp(&mut self) {
+        if let Some(client) = self.lsp.take() {
+            // Outside a runtime it is left to be killed when dropped
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                runtime.spawn(client.shutdown());
             }
+        }
+    }
 
-            "/symbol" | "/sym" => {
-                if let Some(ref index) = self.codebase {
-                    let results = self.search_agent.find_symbol(index, args).await?;
-                    if results.is_empty() {
-                        Ok("No symbols found.".to_string())
-                    } else {
-                        Ok(results.iter()
-                            .map(|r| format!("  {}: {}", r.path, r.symbols.join(", ")))
-                            .collect::<Vec<_>>()
-                            .join("\n"))
-                    }
-                } else {
-                    Ok("No codebase indexed. Use /index <path> first.".to_string())
-                }
-            }
+    /// Shut the language server down and wait for it to exit; done before the
+    /// runtime ends, which would drop a shutdown left running in the background
+    pub async fn shutdown_lsp(&mut self) {
+        if let Some(client) = self.lsp.take() {
+            let _ = client.shutdown().await;
+        }
+    }
 
-            "/ask" | "/q" => {
-                if let Some(ref index) = self.codebase {
-                    self.search_agent.answer_question(index, args).await
-                } else {
-                    Ok("No codebase indexed. Use /index <path> first.".to_string())
+    /// Write the current session to a share bundle
+    pub async fn export_share(&self, path: &std::path::Path) -> Result<ShareBundle> {
+        let project = match self.project_root {
+            Some(ref root) => {
+                let git = GitOps::new(root);
+                ProjectInfo {
+                    name: root.file_name().map(|n| n.to_string_lossy().to_string()),
+                    branch: git.get_current_branch().ok().filter(|b| !b.is_empty()),
+                    commit: git.get_head_commit().ok(),
                 }
             }
+            None => ProjectInfo::default(),
+        };
 
-            "/explain" | "/e" => {
-                self.code_agent.explain_code(args, None).await
-            }
+        let bundle = ShareBundle {
+            format_version: SHARE_FORMAT_VERSION,
+            created_at: chrono::Utc::now(),
+            sovereign_version: env!("CARGO_PKG_VERSION").to_string(),
+            model: ModelInfo {
+                chat_model: self.model.clone(),
+                backend: format!("{:?}", self.backend).to_lowercase(),
+                embedding_model: self.search_agent.embedding_model().await,
+            },
+            project,
+            config: self.config.clone(),
+            conversation: self.chat_agent.conversation().to_vec(),
+            retrievals: self.retrievals.clone(),
+        };
 
-            "/generate" | "/gen" | "/g" => {
-                self.code_agent.generate_code(args, None, None).await
-            }
+        if !self.dry_run() {
+            bundle.save(path)?;
+        }
+        Ok(bundle)
+    }
 
-            "/review" | "/r" => {
-                self.code_agent.review_code(args, None).await
-            }
+    /// Continue a shared session, reporting where the local setup differs from the original
+    pub async fn import_share(&mut self, path: &std::path::Path) -> Result<String> {
+        let bundle = ShareBundle::load(path)?;
+        self.chat_agent.restore_conversation(&bundle.conversation);
+        self.retrievals = bundle.retrievals.clone();
 
-            "/test" | "/t" => {
-                self.code_agent.write_tests(args, None).await
-            }
+        let mut report = format!(
+            "Restored {} messages from a session of {}",
+            bundle.conversation.iter().filter(|m| m.role != "system").count(),
+            bundle.created_at.format("%Y-%m-%d %H:%M UTC")
+        );
+        if let Some(ref name) = bundle.project.name {
+            report.push_str(&format!(" on {}", name));
+        }
+        if let Some(ref commit) = bundle.project.commit {
+            report.push_str(&format!(" at {}", &commit[..commit.len().min(10)]));
+        }
+        report.push_str(".\n");
 
-            "/fix" => {
-                // Parse: /fix <bug description> ```code```
-                if let Some(code_start) = args.find("```") {
-                    let bug_desc = &args[..code_start].trim();
-                    let code = args[code_start..]
-                        .trim_start_matches("```")
-                        .trim_end_matches("```")
-                        .trim();
-                    self.code_agent.fix_bug(code, bug_desc, None).await
-                } else {
-                    Ok("Usage: /fix <bug description> ```code```".to_string())
-                }
-            }
+        if bundle.model.chat_model != self.model {
+            report.push_str(&format!(
+                "  Note: session used model {}, you are running {}.\n",
+                bundle.model.chat_model, self.model
+            ));
+        }
+        let embedding_model = self.search_agent.embedding_model().await;
+        if bundle.model.embedding_model != embedding_model {
+            report.push_str(&format!(
+                "  Note: session used embeddings from {}, you are running {}.\n",
+                bundle.model.embedding_model, embedding_model
+            ));
+        }
 
-            "/refactor" | "/ref" => {
-                // Parse: /refactor <instructions> ```code```
-                if let Some(code_start) = args.find("```") {
-                    let instructions = &args[..code_start].trim();
-                    let code = args[code_start..]
-                        .trim_start_matches("```")
-                        .trim_end_matches("```")
-                        .trim();
-                    self.code_agent.refactor_code(code, instructions, None).await
-                } else {
-                    Ok("Usage: /refactor <instructions> ```code```".to_string())
-                }
-            }
+        if let Some(ref index) = self.codebase {
+            let statuses = bundle.verify_context(index);
+            let stale: Vec<_> = statuses.iter()
+                .filter(|(_, status)| *status != ChunkStatus::Unchanged)
+                .collect();
 
-            "/read" | "/cat" => {
-                if let Some(ref index) = self.codebase {
-                    if let Ok(Some(content)) = index.get_file_content(args) {
-                        Ok(content)
-                    } else {
-                        Ok(format!("File not found: {}", args))
-                    }
-                } else {
-                    Ok("No codebase indexed.".to_string())
-                }
+            report.push_str(&format!(
+                "  Context: {} of {} referenced files match your checkout.\n",
+                statuses.len() - stale.len(),
+                statuses.len()
+            ));
+            for (chunk, status) in stale {
+                report.push_str(&format!("    {} ({})\n", chunk.path, status.as_str()));
             }
+        } else if !bundle.retrievals.is_empty() {
+            report.push_str("  Index the project to check the referenced code against your checkout.\n");
+        }
 
-            "/summarize" | "/sum" => {
-                if let Some(ref index) = self.codebase {
-                    self.search_agent.summarize_file(index, args).await
-                } else {
-                    Ok("No codebase indexed.".to_string())
-                }
-            }
+        Ok(report)
+    }
 
-            "/embed" => {
-                if let Some(ref index) = self.codebase {
-                    println!("  Building embeddings for semantic search...");
-                    let count = self.search_agent.index_embeddings(index).await?;
-                    Ok(format!("Created embeddings for {} files.", count))
-                } else {
-                    Ok("No codebase indexed. Use /index <path> first.".to_string())
-                }
-            }
+    /// Answer a question about the codebase, reusing an earlier answer unless `fresh`
+    async fn ask(&mut self, question: &str, fresh: bool, include_chats: bool) -> Result<String> {
+        let Some(ref index) = self.codebase else {
+            return Ok("No codebase indexed. Use /index <path> first.".to_string());
+        };
+        self.last_question = Some(question.to_string());
 
-            "/stats" => {
-                if let Some(stats) = self.get_codebase_stats() {
-                    let mut output = format!(
-                        "Codebase Statistics:\n  Files: {}\n  Lines: {}\n  Languages:\n",
-                        stats.total_files, stats.total_lines
-                    );
-                    for (lang, count) in &stats.languages {
-                        output.push_str(&format!("    {}: {} files\n", lang, count));
-                    }
-                    if let Some(last) = stats.last_indexed {
-                        output.push_str(&format!("  Last indexed: {}", last));
-                    }
-                    Ok(output)
-                } else {
-                    Ok("No codebase indexed.".to_string())
+        // A cached answer was generated without the chat history
+        if !fresh && !include_chats {
+            if let Some(cached) = self.search_agent.find_cached_answer(index, question).await? {
+                let chunks = cached.sources.iter()
+                    .map(|(path, hash)| ChunkReference { path: path.clone(), lines: None, hash: hash.clone() })
+                    .collect();
+                for (path, _) in &cached.sources {
+                    let _ = index.record_interaction(path, "ask");
                 }
-            }
+                self.retrievals.push(Retrieval { query: question.to_string(), chunks });
 
-            "/memory" | "/mem" => {
-                let memories = self.memory.get_recent(10)?;
-                if memories.is_empty() {
-                    Ok("No memories stored yet.".to_string())
-                } else {
-                    Ok(memories.iter()
-                        .map(|m| format!("  [{}] {}", m.memory_type.as_str(), m.content.chars().take(80).collect::<String>()))
-                        .collect::<Vec<_>>()
-                        .join("\n"))
-                }
+                return Ok(format!(
+                    "{}\n\n(Earlier answer from {} to \"{}\". Use /regenerate for a fresh one.)",
+                    cached.answer,
+                    cached.created_at.format("%Y-%m-%d %H:%M"),
+                    cached.question
+                ));
             }
+        }
 
-            "/sync-export" => {
-                let export_path = self.data_dir.join("sync_export.automerge");
-                let bytes = self.crdt_memory.export();
-                std::fs::write(&export_path, bytes)?;
-                Ok(format!("Exported CRDT memories to: {}", export_path.display()))
-            }
+        let (answer, chats_used) = self.generate_answer(question, include_chats, true).await?;
 
-            "/sync-import" => {
-                if args.is_empty() {
-                    Ok("Usage: /sync-import <path-to-automerge-file>".to_string())
-                } else {
-                    let import_path = PathBuf::from(args);
-                    if import_path.exists() {
-                        let bytes = std::fs::read(&import_path)?;
-                        self.crdt_memory.merge(&bytes)?;
-                        let count = self.crdt_memory.count()?;
-                        Ok(format!("Merged successfully. Total memories: {}", count))
-                    } else {
-                        Ok(format!("File not found: {}", args))
-                    }
-                }
-            }
+        if chats_used > 0 {
+            return Ok(format!(
+                "{}\n\n(Drew on {} earlier chat explanation{}.)",
+                answer.render(),
+                chats_used,
+                if chats_used == 1 { "" } else { "s" }
+            ));
+        }
 
-            "/sync-status" => {
-                let count = self.crdt_memory.count()?;
-                let heads = self.crdt_memory.get_heads();
-                let conn_info = self.p2p_sync.connection_info();
-                Ok(format!(
-                    "CRDT Memory Status:\n  Memories: {}\n  Document heads: {}\n  Data dir: {}\n\nP2P Sync:\n  {}",
-                    count,
-                    heads.len(),
-                    self.data_dir.display(),
-                    conn_info
-                ))
+        // A "not found" answer should not outlive a better index
+        if answer.confidence.level != ConfidenceLevel::Low {
+            if let Some(ref index) = self.codebase {
+                // A failed cache write should not lose the answer
+                let _ = self.search_agent.cache_answer(index, question, &answer).await;
             }
+        }
 
-            "/sync-pull" => {
-                if args.is_empty() {
-                    Ok("Usage: /sync-pull <host:port>".to_string())
-                } else {
-                    match self.p2p_sync.pull_from_peer(args).await {
-                        Ok((data, result)) => {
-                            if !data.is_empty() {
-                                self.crdt_memory.merge(&data)?;
-                                Ok(format!("{}\nMerged into local CRDT.", result))
-                            } else {
-                                Ok("Received empty data from peer.".to_string())
-                            }
-                        }
-                        Err(e) => Ok(format!("Pull failed: {}", e))
-                    }
-                }
-            }
+        Ok(answer.render())
+    }
 
-            "/sync-push" => {
-                if args.is_empty() {
-                    Ok("Usage: /sync-push <host:port>".to_string())
-                } else {
-                    match self.p2p_sync.push_to_peer(args).await {
-                        Ok(result) => Ok(format!("{}", result)),
-                        Err(e) => Ok(format!("Push failed: {}", e))
-                    }
-                }
-            }
+    /// Answer from the project as it was at a git tag, branch or commit, to compare
+    /// with how the code works now
+    async fn ask_at_ref(&mut self, reference: &str, question: &str) -> Result<String> {
+        let Some(root) = self.project_root.clone() else {
+            return Ok("No codebase indexed. Use /index <path> first.".to_string());
+        };
+        if question.is_empty() {
+            return Ok("Usage: /ask --ref <tag|branch|commit> <question>".to_string());
+        }
 
-            "/sync-live" => {
-                if args.is_empty() {
-                    Ok("Usage: /sync-live <host:port>".to_string())
-                } else {
-                    match self.p2p_sync.sync_with_peer(args).await {
-                        Ok((data, result)) => {
-                            if !data.is_empty() {
-                                self.crdt_memory.merge(&data)?;
-                                Ok(format!("{}\nBidirectional sync complete.", result))
-                            } else {
-                                Ok(format!("{}\nNo remote data to merge.", result))
-                            }
-                        }
-                        Err(e) => Ok(format!("Sync failed: {}", e))
-                    }
-                }
-            }
+        let snapshot = Snapshot::open(&self.data_dir, &root, reference, &self.config.rag.fts_tokenizer, &self.config.index)?;
+        let answer = self.search_agent.answer_question(&snapshot.index, question, &[], true).await?;
+        Ok(format!(
+            "{}\n\n(Answered from {} at {}. Ask without --ref for the current code.)",
+            answer.render(),
+            snapshot.reference,
+            snapshot.short_commit()
+        ))
+    }
 
-            "/clear" => {
-                self.chat_agent.clear_conversation();
-                Ok("Conversation cleared.".to_string())
-            }
+    /// A fresh answer with its sources and retrieval confidence, as JSON
+    pub async fn ask_json(&mut self, question: &str) -> Result<String> {
+        let (answer, _) = self.generate_answer(question, false, false).await?;
+        Ok(serde_json::to_string_pretty(&answer)?)
+    }
 
-            "/commit" => {
-                self.git_agent.commit_message_for_staged().await
-            }
+    /// Generate an answer and record its sources; also returns how many chat exchanges were used
+    async fn generate_answer(&mut self, question: &str, include_chats: bool, stream: bool) -> Result<(Answer, usize)> {
+        let Some(ref index) = self.codebase else {
+            anyhow::bail!("No codebase indexed. Use /index <path> first.");
+        };
 
-            "/pr-summary" | "/pr" => {
-                self.git_agent.pr_summary_for_branch().await
-            }
+        let earlier = if include_chats {
+            let project = self.project_root.as_ref()
+                .and_then(|p| p.file_name())
+                .and_then(|n| n.to_str());
+            self.search_agent
+                .relevant_exchanges(&self.transcripts, project, question, MAX_CHAT_EXCHANGES)
+                .await?
+        } else {
+            Vec::new()
+        };
 
-            "/help" | "/h" => {
-                Ok(HELP_TEXT.to_string())
-            }
+        let answer = self.search_agent.answer_question(index, question, &earlier, stream).await?;
 
-            _ => {
-                Ok(format!("Unknown command: {}. Type /help for available commands.", cmd))
-            }
-        }
+        let chunks = answer.citations.iter()
+            .filter_map(|c| {
+                let file = index.get_file(&c.path).ok().flatten()?;
+                let _ = index.record_interaction(&file.relative_path, "ask");
+                Some(ChunkReference {
+                    path: file.relative_path,
+                    lines: Some((c.start_line, c.end_line)),
+                    hash: file.hash,
+                })
+            })
+            .collect();
+        self.retrievals.push(Retrieval { query: question.to_string(), chunks });
+
+        Ok((answer, earlier.len()))
     }
-}
 
-const HELP_TEXT: &str = r#"
-Sovereign - Local-First Code Assistant
-
-COMMANDS:
-  /search, /s <query>      Search codebase (uses embeddings if available)
-  /symbol, /sym <name>     Find symbol definitions
-  /ask, /q <question>      Ask about codebase
-  /read, /cat <file>       Read file content
-  /summarize, /sum <file>  Summarize a file
-  /embed                   Build embeddings for semantic search
-  /stats                   Show codebase statistics
-
-  /generate, /g <desc>     Generate code
-  /explain, /e <code>      Explain code
-  /review, /r <code>       Review code
-  /test, /t <code>         Generate tests
-  /fix <desc> ```code```   Fix a bug
-  /refactor <desc> ```code```  Refactor code
-
-GIT:
-  /commit                  Generate commit message for staged changes
-  /pr-summary, /pr         Generate PR summary for current branch
-
-  /memory, /mem            Show recent memories
-  /clear                   Clear conversation
-  /help, /h                Show this help
-
-SYNC (Local-First):
-  /sync-export             Export CRDT memories for sync
-  /sync-import <file>      Import and merge CRDT memories
-  /sync-status             Show CRDT and P2P sync status
-  /sync-pull <host:port>   Pull memories from a peer
-  /sync-push <host:port>   Push memories to a peer
-  /sync-live <host:port>   Bidirectional sync with a peer
-
-Or just type naturally to chat!
-"#;
+    /// Files changed since the session started, with model notes on each when git can diff them
+    async fn what_changed(&self) -> Result<String> {
+        let Some(ref root) = self.project_root else {
+            return Ok("No codebase indexed. Use /index <path> first.".to_string());
+        };
+        let Some(session) = self.session_changes.get(root).filter(|s| !s.files.is_empty()) else {
+            return Ok("No changes detected since the session started.".to_string());
+        };
+
+        let files = session.describe();
+        let mut output = format!("Changed since the session started ({} files):\n", files.len());
+        for line in &files {
+            output.push_str(&format!("  {}\n", line));
+        }
+
+        let Some(ref base) = session.base_commit else {
+            return Ok(output.trim_end().to_string());
+        };
+
+        let git = GitOps::new(root);
+        let diff: String = session.files
+            .keys()
+            .filter_map(|path| git.get_file_diff(base, path).ok())
+            .collect();
+        if diff.trim().is_empty() {
+            return Ok(output.trim_end().to_string());
+        }
+
+        match self.git_agent.annotate_session_changes(&files.join("\n"), &diff).await {
+            Ok(notes) => output.push_str(&format!("\n{}", notes.trim())),
+            Err(e) => output.push_str(&format!("\n(No notes: {})", e)),
+        }
+        Ok(output.trim_end().to_string())
+    }
+
+    /// Use `model` on `backend` from now on, once the backend answers and, on Ollama, the
+    /// model is pulled; conversations, pins and the index carry over
+    pub async fn switch_model(&mut self, backend: LlmBackend, model: &str) -> Result<String> {
+        let api_key = self.api_key.as_deref();
+        let client = llm_client(&self.config, backend, model, api_key)?;
+        if !client.is_available().await {
+            anyhow::bail!("{} is not reachable; still using {}", backend.as_str(), self.model);
+        }
+        if let LlmClient::Ollama(ollama) = &client {
+            let pulled = ollama.list_models().await?;
+            if !pulled.iter().any(|name| name == model || name.strip_suffix(":latest") == Some(model)) {
+                anyhow::bail!("Model '{}' is not pulled (`ollama pull {}`); still using {}", model, model, self.model);
+            }
+        }
+
+        self.use_model(backend, model)?;
+        Ok(format!("Now using {} on {}.", model, backend.as_str()))
+    }
+
+    /// Use `model` on `backend` from now on without asking the backend first, as daemon
+    /// workers do once one of them has checked the switch; nothing changes on an error
+    pub fn use_model(&mut self, backend: LlmBackend, model: &str) -> Result<()> {
+        let api_key = self.api_key.as_deref();
+        let code_llm = llm_client(&self.config, backend, model, api_key)?;
+        let search_llm = llm_client(&self.config, backend, model, api_key)?;
+        let chat_llm = llm_client(&self.config, backend, model, api_key)?;
+        let git_llm = llm_client(&self.config, backend, model, api_key)?;
+
+        self.code_agent.set_llm(code_llm);
+        self.search_agent.set_llm(search_llm);
+        self.chat_agent.set_llm(chat_llm);
+        self.git_agent.set_llm(git_llm);
+        let scorer = MemoryScorer::from_config(&self.config.scoring, model, &self.config.servers, self.config.prompts.clone());
+        self.code_agent.set_scorer(scorer.clone());
+        self.chat_agent.set_scorer(scorer);
+        self.model = model.to_string();
+        self.backend = backend;
+        // What the new model lacks is worth a warning again
+        self.capability_notes.clear();
+        Ok(())
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn backend(&self) -> LlmBackend {
+        self.backend
+    }
+
+    /// Whether changes are only described (`--dry-run`)
+    pub fn dry_run(&self) -> bool {
+        self.config.dry_run
+    }
+
+    /// Re-read sovereign.toml, the global one and the current project's, keeping the
+    /// model, the session's verbosity and the conversation; the files that were read
+    pub fn reload_config(&mut self) -> Result<Vec<PathBuf>> {
+        let root = self.project_root.clone();
+        let mut config = Config::load(&self.data_dir, root.as_deref())?.with_overrides(&self.overrides);
+        config.prompts.set_verbosity(self.config.prompts.verbosity());
+
+        let api_key = self.api_key.as_deref();
+        self.code_agent.set_llm(llm_client(&config, self.backend, &self.model, api_key)?);
+        self.code_agent.set_prompts(config.prompts.clone());
+        self.search_agent = SearchAgent::new(llm_client(&config, self.backend, &self.model, api_key)?, &config);
+        self.chat_agent.set_llm(llm_client(&config, self.backend, &self.model, api_key)?);
+        self.chat_agent.set_prompts(config.prompts.clone());
+        self.chat_agent.set_embedding_client(EmbeddingClient::from_config(&config.embeddings).with_servers(&config.servers));
+        self.chat_agent.set_config(config.chat.clone());
+        self.chat_agent.set_retriever(chat_retriever(&config));
+        self.git_agent.set_llm(llm_client(&config, self.backend, &self.model, api_key)?);
+        self.git_agent.set_prompts(config.prompts.clone());
+        let scorer = MemoryScorer::from_config(&config.scoring, &self.model, &config.servers, config.prompts.clone());
+        self.code_agent.set_scorer(scorer.clone());
+        self.chat_agent.set_scorer(scorer);
+        self.config = config;
+        // `[models]` may now say something else about the model
+        self.capability_notes.clear();
+
+        // The index takes the new excludes, and tools and the language server the project's settings
+        if let Some(root) = &root {
+            self.load_codebase(root)?;
+        }
+        let global = Config::global_path(&self.data_dir);
+        let project = root.as_deref().map(Config::project_path);
+        Ok(std::iter::once(global).chain(project).filter(|path| path.exists()).collect())
+    }
+
+    /// Answer length and style for every agent, for the rest of the session
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.config.prompts.set_verbosity(verbosity);
+        self.code_agent.set_verbosity(verbosity);
+        self.search_agent.set_verbosity(verbosity);
+        self.chat_agent.set_verbosity(verbosity);
+        self.git_agent.set_verbosity(verbosity);
+    }
+
+    pub fn get_codebase_stats(&self) -> Option<crate::storage::codebase::CodebaseStats> {
+        self.codebase.as_ref().and_then(|c| c.get_stats().ok())
+    }
+
+    /// The indexed project's most common programming language, ignoring markup and config
+    fn primary_language(&self) -> Option<String> {
+        const NOT_CODE: &[&str] = &["json", "markdown", "yaml", "toml", "html", "css", "sql", "shell"];
+        self.get_codebase_stats()?
+            .languages
+            .into_iter()
+            .map(|(language, _)| language)
+            .find(|language| !NOT_CODE.contains(&language.as_str()))
+    }
+
+    /// Drop indexed files that indexing would no longer visit (e.g. newly ignored)
+    pub fn prune_index(&self) -> Result<usize> {
+        match self.codebase {
+            Some(ref index) => index.prune_unindexable(),
+            None => Ok(0),
+        }
+    }
+
+    /// Write a short summary for each file of the current project that has none,
+    /// at most `limit` of them
+    ///
+    /// Each summary is stored as soon as it is written, so an interrupted run
+    /// resumes where it stopped. A file loses its summary when it changes.
+    pub async fn summarize_all(&self, limit: Option<usize>) -> Result<String> {
+        let index = self.codebase.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No codebase indexed. Use /index <path> first."))?;
+
+        let pending = index.unsummarized_files()?;
+        if pending.is_empty() {
+            return Ok("Every indexed file already has a summary.".to_string());
+        }
+        let total = limit.map_or(pending.len(), |limit| limit.min(pending.len()));
+        if self.dry_run() {
+            let files: Vec<String> = pending.iter().take(total).map(|(_, relative)| format!("  {}", relative)).collect();
+            return Ok(dry_run::would(format!("summarize {} files:\n{}", total, files.join("\n"))));
+        }
+
+        let mut summarized = 0;
+        for (done, (path, relative_path)) in pending.into_iter().take(total).enumerate() {
+            let Some(content) = index.get_file_content(&path)? else { continue };
+            match self.search_agent.brief_summary(&relative_path, &content).await {
+                Ok(summary) if !summary.is_empty() => {
+                    index.set_summary(&path, &summary)?;
+                    summarized += 1;
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("  Could not summarize {}: {}", relative_path, e),
+            }
+            if (done + 1) % 10 == 0 {
+                jobs::progress(format!("Summarized {}/{} files...", done + 1, total));
+            }
+        }
+
+        let remaining = index.unsummarized_files()?.len();
+        let mut report = format!("Summarized {} files.", summarized);
+        if remaining > 0 {
+            report.push_str(&format!(" {} still have no summary; run /summarize-all again to continue.", remaining));
+        }
+        Ok(report)
+    }
+
+    /// Define the project's most widely used domain terms from their code and store them as its glossary
+    pub async fn build_glossary(&self, limit: usize) -> Result<String> {
+        let index = self.codebase.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No codebase indexed. Use /index <path> first."))?;
+
+        let candidates = glossary::candidates(index, limit)?;
+        if candidates.is_empty() {
+            return Ok("No recurring domain terms found.".to_string());
+        }
+        if self.dry_run() {
+            let terms: Vec<&str> = candidates.iter().map(|c| c.term.as_str()).collect();
+            return Ok(dry_run::would(format!("define {} terms: {}", terms.len(), terms.join(", "))));
+        }
+
+        let mut defined = 0;
+        for candidate in &candidates {
+            let (evidence, sources) = glossary::evidence(index, candidate)?;
+            match self.search_agent.define_term(&candidate.term, &evidence).await {
+                Ok(definition) if !definition.is_empty() => {
+                    index.store_glossary_entry(&GlossaryEntry { term: candidate.term.clone(), definition, sources })?;
+                    defined += 1;
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("  Could not define {}: {}", candidate.term, e),
+            }
+        }
+
+        Ok(format!("Defined {} of {} terms.\n\n{}", defined, candidates.len(), self.glossary_report()?))
+    }
+
+    /// The stored glossary of the current project, one term per line
+    pub fn glossary_report(&self) -> Result<String> {
+        let index = self.codebase.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No codebase indexed. Use /index <path> first."))?;
+
+        let entries = index.glossary()?;
+        if entries.is_empty() {
+            return Ok("No glossary yet. Build one with /glossary build or `sovereign glossary`.".to_string());
+        }
+        let lines: Vec<String> = entries
+            .iter()
+            .map(|entry| format!("  {}: {}\n      ({})", entry.term, entry.definition, entry.sources.join(", ")))
+            .collect();
+        Ok(format!("Glossary ({} terms):\n{}", entries.len(), lines.join("\n")))
+    }
+
+    /// Files the user asks about, views and edits most, with interaction counts
+    pub fn most_used_files(&self, limit: usize) -> Vec<(String, usize)> {
+        self.codebase.as_ref()
+            .and_then(|c| c.most_used_files(limit).ok())
+            .unwrap_or_default()
+    }
+
+    pub async fn process_command(&mut self, input: &str) -> Result<String> {
+        let input = input.trim();
+        let started = std::time::Instant::now();
+
+        // Parse command
+        let result = if input.starts_with('/') {
+            self.handle_command(input).await
+        } else {
+            // Default to chat
+            self.chat_agent.chat(input, self.codebase.as_ref()).await
+        };
+
+        self.record_usage(input, started, result.is_ok());
+        result
+    }
+
+    fn record_usage(&self, input: &str, started: std::time::Instant, success: bool) {
+        if let Some(ref store) = self.analytics {
+            let latency = started.elapsed().as_millis() as u64;
+            let _ = store.record_command(analytics::command_name(input), latency, success);
+        }
+    }
+
+    async fn handle_command(&mut self, input: &str) -> Result<String> {
+        let (cmd, args) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+        let args = args.trim();
+
+        // `#3` stands for the file of result 3 of the last search
+        let resolved = self.last_results.as_ref().and_then(|r| r.path(args)).map(String::from);
+        let args = resolved.as_deref().unwrap_or(args);
+
+        let Some((spec, handler)) = self.command(cmd) else {
+            return Ok(format!("Unknown command: {}. Type /help for available commands.", cmd));
+        };
+        match spec.parse(args) {
+            Ok(args) => handler(self, &args).await,
+            Err(usage) => Ok(usage),
+        }
+    }
+
+    /// The spec and handler of the command `name` names
+    fn command(&self, name: &str) -> Option<(CommandSpec, CommandHandler)> {
+        let spec = commands::find(name)?;
+        let (_, handler) = HANDLERS.iter().find(|(handled, _)| *handled == spec.name)?;
+        Some((spec.clone(), *handler))
+    }
+
+    async fn search_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let Some(ref index) = self.codebase else {
+            return Ok("No codebase indexed. Use /index <path> first.".to_string());
+        };
+        let results = self.search_agent.semantic_search(index, args.text("query"), MAX_RESULTS).await?;
+        if results.is_empty() {
+            return Ok("No results found.".to_string());
+        }
+
+        let results = results.into_iter()
+            .map(|r| {
+                let mut line = match r.line_range {
+                    Some((start, end)) => format!("{}:{}-{} ({})", r.path, start, end, r.language),
+                    None => format!("{} ({})", r.path, r.language),
+                };
+                // Full-text hits show the lines that matched
+                if let (Some(excerpt), Some((start, _))) = (&r.excerpt, r.line_range) {
+                    for (i, text) in excerpt.lines().enumerate() {
+                        line.push_str(&format!("\n      {:>5} | {}", start + i, text.trim()));
+                    }
+                }
+                (r.path, line)
+            })
+            .collect();
+        Ok(self.last_results.insert(ResultPages::new(results)).next_page())
+    }
+
+    async fn grep_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let Some(ref index) = self.codebase else {
+            return Ok("No codebase indexed. Use /index <path> first.".to_string());
+        };
+        let (pattern, options) = parse_grep_args(args);
+        if pattern.is_empty() {
+            return Ok("Missing <regex>.\nUsage: /grep [-F] [-i] <regex> [language:..] [path:..]".to_string());
+        }
+
+        let matches = grep::grep(index, &pattern, &options, MAX_GREP_MATCHES)?;
+        if matches.is_empty() {
+            return Ok("No matches.".to_string());
+        }
+        let results = matches.into_iter()
+            .map(|m| {
+                let line = format!("{}:{}: {}", m.path, m.line, m.text);
+                (m.path, line)
+            })
+            .collect();
+        Ok(self.last_results.insert(ResultPages::new(results)).next_page())
+    }
+
+    async fn symbol_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let Some(ref index) = self.codebase else {
+            return Ok("No codebase indexed. Use /index <path> first.".to_string());
+        };
+        let results = self.search_agent.find_symbol(index, args.text("name"), MAX_RESULTS).await?;
+        if results.is_empty() {
+            return Ok("No symbols found.".to_string());
+        }
+        let results = results.into_iter()
+            .map(|r| {
+                let line = format!("{}: {}", r.path, r.symbols.join(", "));
+                (r.path, line)
+            })
+            .collect();
+        Ok(self.last_results.insert(ResultPages::new(results)).next_page())
+    }
+
+    async fn more_command(&mut self, _args: &CommandArgs) -> Result<String> {
+        match self.last_results {
+            Some(ref mut pages) => Ok(pages.next_page()),
+            None => Ok("Nothing to page through. Run /search or /symbol first.".to_string()),
+        }
+    }
+
+    async fn def_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let Some(ref index) = self.codebase else {
+            return Ok("No codebase indexed. Use /index <path> first.".to_string());
+        };
+        // `Type::method` and `module.func` name their last segment
+        let symbol = args.text("symbol");
+        let name = symbol.rsplit([':', '.']).next().unwrap_or(symbol).trim();
+        if name.is_empty() {
+            return Ok("Usage: /def <symbol>".to_string());
+        }
+
+        let definitions = index.find_definitions(name)?;
+        if definitions.is_empty() {
+            return Ok(format!("No definition of {} found. Try /symbol {} for partial matches.", name, name));
+        }
+
+        let mut output = String::new();
+        for symbol in definitions.iter().take(MAX_DEFINITIONS_SHOWN) {
+            let _ = index.record_interaction(&symbol.path, "view");
+            output.push_str(&format!(
+                "{}:{}-{} ({})\n",
+                symbol.relative_path, symbol.start_line, symbol.end_line, symbol.kind
+            ));
+
+            let content = index.get_file_content(&symbol.path)?.unwrap_or_default();
+            let body: Vec<&str> = content
+                .lines()
+                .skip(symbol.start_line - 1)
+                .take(symbol.end_line + 1 - symbol.start_line)
+                .collect();
+            for line in body.iter().take(MAX_DEFINITION_LINES) {
+                output.push_str(&format!("  {}\n", line));
+            }
+            if body.len() > MAX_DEFINITION_LINES {
+                output.push_str(&format!("  ... ({} more lines)\n", body.len() - MAX_DEFINITION_LINES));
+            }
+            output.push('\n');
+        }
+        if definitions.len() > MAX_DEFINITIONS_SHOWN {
+            output.push_str(&format!("({} more definitions)\n", definitions.len() - MAX_DEFINITIONS_SHOWN));
+        }
+        Ok(output.trim_end().to_string())
+    }
+
+    async fn refs_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let Some(ref index) = self.codebase else {
+            return Ok("No codebase indexed. Use /index <path> first.".to_string());
+        };
+        let symbol = args.text("symbol");
+        let name = symbol.rsplit([':', '.']).next().unwrap_or(symbol).trim();
+        if name.is_empty() {
+            return Ok("Usage: /refs <symbol>".to_string());
+        }
+
+        let references = consistency::find_references(index, name)?;
+        if references.is_empty() {
+            return Ok(format!("No references to {} found.", name));
+        }
+
+        let mut output = format!("{} references to {}:\n", references.len(), name);
+        for reference in references.iter().take(MAX_REFERENCES_SHOWN) {
+            output.push_str(&format!("\n{}\n", reference.describe()));
+        }
+        if references.len() > MAX_REFERENCES_SHOWN {
+            output.push_str(&format!("\n({} more)\n", references.len() - MAX_REFERENCES_SHOWN));
+        }
+        let output = output.trim_end().to_string();
+
+        // Follow-up questions ("explain all callers") see the references
+        self.chat_agent.add_command_output(&format!("/refs {}", args.raw()), &output);
+        Ok(output)
+    }
+
+    async fn ask_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let question = args.text("question");
+        match args.option("--ref") {
+            Some(reference) => self.ask_at_ref(reference, question).await,
+            None => self.ask(question, args.flag("--fresh"), args.flag("--include-chats")).await,
+        }
+    }
+
+    async fn regenerate_command(&mut self, _args: &CommandArgs) -> Result<String> {
+        match self.last_question.clone() {
+            Some(question) => self.ask(&question, true, false).await,
+            None => Ok("No question to regenerate. Use /ask <question> first.".to_string()),
+        }
+    }
+
+    async fn explain_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let code = args.text("code|path");
+        let path = Path::new(code);
+        if path.is_file() {
+            self.explain_file(path).await
+        } else {
+            self.code_agent.explain_code(code, None, None).await
+        }
+    }
+
+    async fn review_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let code = args.text("code|path");
+        match self.indexed_files(code)? {
+            Some(files) => self.run_on_files(FileAction::Review, code, &files).await,
+            None => self.code_agent.review_code(code, None).await,
+        }
+    }
+
+    async fn test_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let code = args.text("code|path");
+        if let Some(files) = self.indexed_files(code)? {
+            return self.run_on_files(FileAction::Test, code, &files).await;
+        }
+        let language = testgen::guess_language(code)
+            .map(String::from)
+            .or_else(|| self.primary_language());
+        let conventions = match (&self.codebase, &language) {
+            (Some(index), Some(language)) => TestConventions::detect(index, language),
+            _ => None,
+        };
+        self.code_agent.write_tests(code, language.as_deref(), conventions.as_ref()).await
+    }
+
+    /// The indexed files `arg` names, as a path or a glob (`src/*.rs`); `None`
+    /// when it is not a path, so it is taken as code
+    fn indexed_files(&self, arg: &str) -> Result<Option<Vec<IndexedFile>>> {
+        let Some(ref index) = self.codebase else {
+            return Ok(None);
+        };
+        let arg = arg.trim().trim_start_matches("./");
+        if arg.is_empty() || arg.contains(char::is_whitespace) {
+            return Ok(None);
+        }
+
+        if arg.contains('*') {
+            let filters = SearchFilters { paths: vec![arg.to_string()], ..Default::default() };
+            let mut files = Vec::new();
+            for path in index.filtered_paths(&filters)? {
+                files.extend(index.get_file(&path)?);
+            }
+            files.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+            return Ok(Some(files));
+        }
+
+        if let Some(file) = index.get_file(arg)? {
+            return Ok(Some(vec![file]));
+        }
+        match std::fs::canonicalize(arg) {
+            Ok(path) => Ok(index.get_file(&path.to_string_lossy())?.map(|file| vec![file])),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Review or write tests for each of `files` from its indexed content, a part at
+    /// a time for files too large for one prompt, with a section per part
+    async fn run_on_files(&self, action: FileAction, arg: &str, files: &[IndexedFile]) -> Result<String> {
+        let Some(ref index) = self.codebase else {
+            return Ok("No codebase indexed. Use /index <path> first.".to_string());
+        };
+        if files.is_empty() {
+            return Ok(format!("No indexed file matches {}.", arg));
+        }
+
+        let mut sections = Vec::new();
+        for file in files.iter().take(MAX_GLOB_FILES) {
+            let Some(content) = index.get_file_content(&file.path)? else {
+                continue;
+            };
+            let language = file.language.as_str();
+            let map_reduce = MapReduce::default();
+            let parts = map_reduce.split(&content, &|line| CodebaseIndex::definition_at(line.trim(), language).is_some());
+            let conventions = match action {
+                FileAction::Test => TestConventions::detect(index, language),
+                FileAction::Review => None,
+            };
+
+            for part in parts {
+                let heading = if part.total > 1 {
+                    format!("{} ({})", file.relative_path, part.describe())
+                } else {
+                    file.relative_path.clone()
+                };
+                let result = match action {
+                    FileAction::Review => {
+                        println!("  Reviewing {}...", heading);
+                        self.code_agent.review_code(&part.text, Some(language)).await?
+                    }
+                    FileAction::Test => {
+                        println!("  Writing tests for {}...", heading);
+                        self.code_agent.write_tests(&part.text, Some(language), conventions.as_ref()).await?
+                    }
+                };
+                sections.push(format!("## {}\n\n{}", heading, result.trim()));
+            }
+        }
+
+        if files.len() > MAX_GLOB_FILES {
+            sections.push(format!(
+                "Stopped after {} of {} matching files; narrow the pattern for the rest.",
+                MAX_GLOB_FILES,
+                files.len()
+            ));
+        }
+        Ok(sections.join("\n\n"))
+    }
+
+    async fn fix_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let (bug_desc, code) = split_code_block(args.text("desc"));
+
+        // A description naming an indexed file brings what its language server reports
+        let named = self.codebase.as_ref().and_then(|index| {
+            bug_desc.split_whitespace().find_map(|word| index.get_file(word).ok().flatten().map(|file| (word, file.language)))
+        });
+        let (language, diagnostics) = match named {
+            Some((file, language)) => (Some(language), self.diagnostics_context(file).await),
+            None => (None, None),
+        };
+
+        self.code_agent.fix_bug(code, bug_desc, language.as_deref(), diagnostics.as_deref()).await
+    }
+
+    async fn refactor_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let (instructions, code) = split_code_block(args.text("desc"));
+        self.code_agent.refactor_code(code, instructions, None).await
+    }
+
+    async fn edit_command(&mut self, args: &CommandArgs) -> Result<String> {
+        self.pending_changes = self.propose_edit(Path::new(args.text("file")), args.text("instruction")).await?;
+        if self.pending_changes.is_empty() {
+            return Ok("The proposed diff changes nothing.".to_string());
+        }
+        Ok(self.pending_preview())
+    }
+
+    async fn refactor_all_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let instruction = args.text("instruction");
+        let Some(ref index) = self.codebase else {
+            return Ok("No codebase indexed. Use /index <path> first.".to_string());
+        };
+
+        println!("  Planning...");
+        let plan = refactor::plan(index, &self.chat_agent.llm, &self.config.prompts, instruction).await?;
+        if plan.file_count() == 0 {
+            return Ok("Found no indexed definition the change touches. Name the functions or types, e.g. /refactor-all rename `load_config` to `read_config`.".to_string());
+        }
+        if plan.file_count() > refactor::MAX_REFACTOR_FILES {
+            return Ok(format!(
+                "{}\nThat changes {} files, more than {} at once; split it into smaller refactors.",
+                plan.summary,
+                plan.file_count(),
+                refactor::MAX_REFACTOR_FILES
+            ));
+        }
+
+        let root = self.project_root.clone().unwrap_or_else(|| PathBuf::from("."));
+        let mut targets: Vec<(PathBuf, &str, &[usize])> = plan
+            .files
+            .iter()
+            .map(|f| (PathBuf::from(&f.path), f.relative_path.as_str(), f.lines.as_slice()))
+            .collect();
+        targets.extend(plan.new_files.iter().map(|f| (root.join(f), f.as_str(), &[][..])));
+
+        let mut changes = Vec::new();
+        let mut failures = Vec::new();
+        for (n, (path, relative, lines)) in targets.iter().enumerate() {
+            println!("  Editing {} ({}/{})...", relative, n + 1, targets.len());
+            match self.propose_edit(path, &plan.instruction_for(instruction, relative, lines)).await {
+                Ok(file_changes) => changes.extend(file_changes),
+                Err(e) => failures.push(format!("- {}: {}", relative, e.to_string().lines().next().unwrap_or_default())),
+            }
+        }
+
+        // Uses of the symbols the patch set still leaves behind
+        let edits: Vec<consistency::ProposedEdit> = changes
+            .iter()
+            .map(|c| consistency::ProposedEdit { path: c.path.display().to_string(), new_content: c.updated.clone() })
+            .collect();
+        let warnings = consistency::check_edits(index, &edits)?;
+
+        self.pending_changes = changes;
+        let mut output = format!("{}\n\n", plan.summary);
+        if self.pending_changes.is_empty() {
+            output.push_str("The proposed diffs change nothing.");
+        } else {
+            output.push_str(&self.pending_preview());
+        }
+        if !failures.is_empty() {
+            output.push_str(&format!("\n\nNo usable change for {} file(s):\n{}", failures.len(), failures.join("\n")));
+        }
+        if !warnings.is_empty() {
+            let lines: Vec<String> = warnings.iter().map(|w| format!("- {}", w.describe())).collect();
+            output.push_str(&format!("\n\nStill referring to the old names:\n{}", lines.join("\n")));
+        }
+        Ok(output)
+    }
+
+    async fn doc_command(&mut self, args: &CommandArgs) -> Result<String> {
+        self.pending_changes = self.propose_docs(args.text("target")).await?;
+        if self.pending_changes.is_empty() {
+            return Ok("The model found nothing to document.".to_string());
+        }
+        Ok(self.pending_preview())
+    }
+
+    /// Ask the model for documentation comments, in the style of the project's
+    /// documented code, for a file or for the definition named `target`; nothing is written
+    pub async fn propose_docs(&self, target: &str) -> Result<Vec<FileChange>> {
+        let Some(ref index) = self.codebase else {
+            anyhow::bail!("No codebase indexed. Use /index <path> first.");
+        };
+        let file = match index.get_file(target)? {
+            Some(file) => Some(file),
+            None => match Path::new(target).canonicalize() {
+                Ok(path) => index.get_file(&path.to_string_lossy())?,
+                Err(_) => None,
+            },
+        };
+        let (path, symbol) = match file {
+            Some(file) => (file.path, None),
+            None => {
+                let symbol = index
+                    .find_definitions(target)?
+                    .into_iter()
+                    .find(|s| s.kind != "impl")
+                    .ok_or_else(|| anyhow::anyhow!("No indexed file or definition named {}", target))?;
+                (symbol.path.clone(), Some(symbol))
+            }
+        };
+        let language = index.get_file(&path)?.map(|f| f.language).unwrap_or_default();
+
+        let mut instruction = match symbol {
+            Some(ref symbol) => format!(
+                "Add a documentation comment to `{}` (lines {}-{}) only, or improve the one it has.",
+                symbol.name, symbol.start_line, symbol.end_line
+            ),
+            None => "Add documentation comments to the module and to each definition that has none.".to_string(),
+        };
+        instruction.push_str(" Say what each does and why, not how. Change nothing but comments.");
+        let examples = docgen::style_examples(index, &language, &path, MAX_DOC_EXAMPLES)?;
+        if !examples.is_empty() {
+            instruction.push_str(&format!(
+                "\n\nWrite them like the project's existing documentation:\n```\n{}\n```",
+                examples.join("\n\n")
+            ));
+        }
+
+        let changes = self.propose_edit(Path::new(&path), &instruction).await?;
+        for change in &changes {
+            let original = change.original.as_deref().unwrap_or_default();
+            if docgen::changes_code(&language, original, &change.updated) == Some(true) {
+                anyhow::bail!(
+                    "The model's diff changes code as well as comments, so it is not offered:\n```diff\n{}```",
+                    change.diff(&self.display_path(&change.path))
+                );
+            }
+        }
+        Ok(changes)
+    }
+
+    /// The pending changes as a diff, with how to write them
+    fn pending_preview(&self) -> String {
+        let count = self.pending_changes.len();
+        let diff = self.changes_diff(&self.pending_changes);
+        let added = diff.lines().filter(|l| l.starts_with('+') && !l.starts_with("+++")).count();
+        let removed = diff.lines().filter(|l| l.starts_with('-') && !l.starts_with("---")).count();
+        let apply = if count == 1 {
+            "/apply writes them"
+        } else {
+            "/apply writes them, /apply <file> one file at a time"
+        };
+        format!(
+            "Proposed changes to {} file{} (+{} -{}):\n```diff\n{}```\n{}; /undo puts the files back afterwards.",
+            count,
+            if count == 1 { "" } else { "s" },
+            added,
+            removed,
+            diff,
+            apply
+        )
+    }
+
+    /// Ask the model for a diff making the change `instruction` describes to `path`,
+    /// checked to apply to the file as it is; nothing is written. A missing file
+    /// is created.
+    pub async fn propose_edit(&self, path: &Path, instruction: &str) -> Result<Vec<FileChange>> {
+        let code = if path.exists() {
+            std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Could not read {}: {}", path.display(), e))?
+        } else {
+            String::new()
+        };
+        let reply = self.code_agent.edit_diff(&self.display_path(path), &code, instruction).await?;
+
+        let patches = patch::parse(&reply);
+        if patches.is_empty() {
+            anyhow::bail!("The model answered without a diff:\n\n{}", reply);
+        }
+        let root = self.project_root.clone().unwrap_or_else(|| PathBuf::from("."));
+        FileChange::from_patches(&patches, &root, Some(path))
+    }
+
+    /// Review the staged changes, or the unstaged ones, a hunk at a time with what the
+    /// index holds around each; findings come most severe first, with notes on hunks
+    /// the model could not review
+    pub async fn review_changes(&self, staged: bool) -> Result<(Vec<Finding>, Vec<String>)> {
+        let root = match self.project_root {
+            Some(ref root) => root.clone(),
+            None => std::env::current_dir()?,
+        };
+        let git = GitOps::new(&root);
+        if !git.is_git_repo() {
+            anyhow::bail!("{} is not in a git repository", root.display());
+        }
+        let diff = if staged { git.get_staged_diff()? } else { git.get_unstaged_diff()? };
+        if diff.trim().is_empty() {
+            anyhow::bail!(if staged { "Nothing is staged; stage changes with git add first" } else { "No unstaged changes" });
+        }
+
+        let analysis = git.parse_diff(&diff)?;
+        let hunks: Vec<_> = analysis.hunks.iter().filter(|h| h.content.lines().any(|l| l.starts_with('+'))).collect();
+        let mut findings: Vec<Finding> = hunks.iter().flat_map(|h| review::check_hunk(h)).collect();
+        let mut notes = Vec::new();
+
+        for hunk in hunks {
+            let context = match self.codebase {
+                Some(ref index) => review::hunk_context(index, &root.join(&hunk.file_path).to_string_lossy(), hunk)?,
+                None => None,
+            };
+            println!("  Reviewing {}:{}...", hunk.file_path, hunk.new_start);
+            match self.code_agent.review_hunk(hunk, context.as_deref()).await {
+                Ok(mut hunk_findings) => findings.append(&mut hunk_findings),
+                Err(e) => notes.push(format!("{}:{} could not be reviewed: {}", hunk.file_path, hunk.new_start, e)),
+            }
+        }
+
+        findings.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.file.cmp(&b.file)).then(a.line.cmp(&b.line)));
+        Ok((findings, notes))
+    }
+
+    async fn commit_command(&mut self, args: &CommandArgs) -> Result<String> {
+        match args.get("repo") {
+            Some(repo) => self.git_agent.commit_message_for_staged_in(Path::new(repo)).await,
+            None => self.git_agent.commit_message_for_staged().await,
+        }
+    }
+
+    async fn review_staged_command(&mut self) -> Result<String> {
+        let (findings, notes) = self.review_changes(true).await?;
+        Ok(review::to_markdown("Review of staged changes", &findings, &notes))
+    }
+
+    /// Explain compiler or linter output, showing the model the code at the lines it
+    /// reports, and propose a fix checked to apply; nothing is written
+    pub async fn explain_error(&self, output: &str) -> Result<(String, Vec<FileChange>)> {
+        let messages = compiler_output::parse(output);
+        // Warnings are explained only when nothing failed
+        let mut shown: Vec<&CompilerMessage> = messages.iter().filter(|m| m.is_error()).collect();
+        if shown.is_empty() {
+            shown = messages.iter().collect();
+        }
+        shown.truncate(MAX_EXPLAINED_MESSAGES);
+
+        // Tools print paths relative to where they ran, usually here
+        let base = std::env::current_dir()?;
+        let mut code = String::new();
+        let mut files: Vec<PathBuf> = Vec::new();
+        for message in shown {
+            let candidates = [Some(base.join(&message.file)), self.project_root.as_ref().map(|root| root.join(&message.file))];
+            let Some(path) = candidates.into_iter().flatten().find(|p| p.is_file()) else {
+                continue;
+            };
+            let Ok(content) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            code.push_str(&format!(
+                "{}\n```\n{}```\n\n",
+                message.describe(),
+                compiler_output::excerpt(&content, message.line, 10)
+            ));
+            if !files.contains(&path) {
+                files.push(path);
+            }
+        }
+
+        let output: String = output.chars().take(MAX_ERROR_OUTPUT_CHARS).collect();
+        let reply = self.code_agent.explain_error(output.trim(), &code).await?;
+
+        let patches = patch::parse(&reply);
+        if patches.is_empty() {
+            return Ok((reply, Vec::new()));
+        }
+        let explanation = reply[..reply.find("```").unwrap_or(reply.len())].trim();
+        let target = match files.as_slice() {
+            [file] => Some(file.as_path()),
+            _ => None,
+        };
+        match FileChange::from_patches(&patches, &base, target) {
+            Ok(changes) if !changes.is_empty() => {
+                let diff = self.changes_diff(&changes);
+                Ok((format!("{}\n\n```diff\n{}```", explanation, diff), changes))
+            }
+            Ok(_) => Ok((format!("{}\n\nThe suggested diff changes nothing.", explanation), Vec::new())),
+            Err(e) => Ok((format!("{}\n\n(The suggested diff does not apply to the files as they are: {})", reply.trim(), e), Vec::new())),
+        }
+    }
+
+    /// `changes` as one unified diff, with paths relative to the project
+    pub fn changes_diff(&self, changes: &[FileChange]) -> String {
+        changes.iter().map(|change| change.diff(&self.display_path(&change.path))).collect()
+    }
+
+    /// Write `changes`, keeping copies of the files for `/undo`, and re-index them
+    pub fn apply_changes(&mut self, changes: &[FileChange]) -> Result<()> {
+        self.backups.apply(changes)?;
+        self.reindex_after_write();
+        Ok(())
+    }
+
+    async fn apply_command(&mut self, args: &CommandArgs) -> Result<String> {
+        if self.pending_changes.is_empty() {
+            return Ok("No changes to apply. /edit <file> <instruction> proposes some.".to_string());
+        }
+        let names: Vec<String> = self.pending_changes.iter().map(|c| self.display_path(&c.path)).collect();
+        let selected = match args.get("file") {
+            Some(file) => {
+                let path = Path::new(file);
+                match self.pending_changes.iter().zip(&names).position(|(c, name)| name == file || c.path == path) {
+                    Some(i) => vec![i],
+                    None => return Ok(format!("No pending change to {}. Pending: {}", file, names.join(", "))),
+                }
+            }
+            None => (0..names.len()).collect(),
+        };
+        let written: Vec<&str> = selected.iter().map(|&i| names[i].as_str()).collect();
+        if self.dry_run() {
+            return Ok(dry_run::would(format!("write {}", written.join(", "))));
+        }
+
+        let written = written.join(", ");
+        let mut changes: Vec<FileChange> = selected.iter().rev().map(|&i| self.pending_changes.remove(i)).collect();
+        changes.reverse();
+        self.apply_changes(&changes)?;
+        if self.pending_changes.is_empty() {
+            Ok(format!("Wrote {}. /undo puts them back.", written))
+        } else {
+            let left: Vec<String> = self.pending_changes.iter().map(|c| self.display_path(&c.path)).collect();
+            Ok(format!("Wrote {}. /undo puts it back. Still pending: {}", written, left.join(", ")))
+        }
+    }
+
+    async fn undo_command(&mut self, args: &CommandArgs) -> Result<String> {
+        if self.dry_run() {
+            return Ok(dry_run::would("put back the files changed by the last /apply"));
+        }
+        let Some(restored) = self.backups.undo(args.flag("--force"))? else {
+            return Ok("Nothing to undo.".to_string());
+        };
+        self.reindex_after_write();
+        let names: Vec<String> = restored.iter().map(|p| self.display_path(p)).collect();
+        Ok(format!("Restored {}.", names.join(", ")))
+    }
+
+    /// `path` relative to the project root when it is inside it
+    fn display_path(&self, path: &Path) -> String {
+        let absolute = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.project_root
+            .as_ref()
+            .and_then(|root| absolute.strip_prefix(root).ok())
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    }
+
+    /// Bring the index up to date with files sovereign just wrote
+    fn reindex_after_write(&mut self) {
+        if let Some(root) = self.project_root.clone() {
+            if let Err(e) = self.index_codebase(&root) {
+                eprintln!("  Could not re-index {}: {}", root.display(), e);
+            }
+        }
+    }
+
+    async fn read_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let Some(ref index) = self.codebase else {
+            return Ok("No codebase indexed.".to_string());
+        };
+        let file = args.text("file");
+        match index.get_file_content(file) {
+            Ok(Some(content)) => Ok(content),
+            _ => Ok(format!("File not found: {}", file)),
+        }
+    }
+
+    async fn open_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let Some(ref index) = self.codebase else {
+            return Ok("No codebase indexed.".to_string());
+        };
+        let query = args.text("name");
+        let matches = index.match_paths(query, MAX_RESULTS)?;
+        let Some((best, _)) = matches.first().cloned() else {
+            return Ok(format!("No indexed file matches '{}'.", query));
+        };
+        let _ = index.record_interaction(&best, "view");
+
+        let mut header = best.clone();
+        if matches.len() > 1 {
+            let others: Vec<String> = matches.iter()
+                .enumerate()
+                .skip(1)
+                .take(MAX_OPEN_ALTERNATIVES)
+                .map(|(i, (path, _))| format!("#{} {}", i + 1, path))
+                .collect();
+            header.push_str(&format!("\n(also: {})", others.join(", ")));
+        }
+        // The other matches stay reachable as #N, and /more lists them all
+        let results = matches.into_iter().map(|(path, _)| (path.clone(), path)).collect();
+        self.last_results = Some(ResultPages::new(results));
+
+        let body = if args.flag("--summary") {
+            let summary = self.search_agent.summarize_file(index, &best).await?;
+            self.with_capability_note("summarize", summary)
+        } else {
+            index.get_file_content(&best)?.unwrap_or_default()
+        };
+        Ok(format!("{}\n\n{}", header, body))
+    }
+
+    async fn index_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let count = self.index_codebase(Path::new(args.text("path")))?;
+        Ok(format!("Indexed {} changed files.", count))
+    }
+
+    async fn verbosity_command(&mut self, args: &CommandArgs) -> Result<String> {
+        // The spec only lets the three levels through
+        let Some(verbosity) = args.get("level").and_then(Verbosity::parse) else {
+            return Ok(format!(
+                "Verbosity: {} (terse, normal or teaching)",
+                self.config.prompts.verbosity().as_str()
+            ));
+        };
+        self.set_verbosity(verbosity);
+        Ok(format!("Verbosity set to {}.", verbosity.as_str()))
+    }
+
+    async fn model_command(&mut self, args: &CommandArgs) -> Result<String> {
+        match args.get("name") {
+            Some(model) => self.switch_model(self.backend, model).await,
+            None => {
+                let described = self.config.models.capabilities(&self.model).map(|c| c.describe());
+                Ok(match described.filter(|d| !d.is_empty()) {
+                    Some(described) => format!("Model: {} on {} ({})", self.model, self.backend.as_str(), described),
+                    None => format!("Model: {} on {}", self.model, self.backend.as_str()),
+                })
+            }
+        }
+    }
+
+    async fn backend_command(&mut self, args: &CommandArgs) -> Result<String> {
+        // The spec only lets known backends through
+        let Some(backend) = args.get("name").and_then(LlmBackend::from_str) else {
+            return Ok(format!("Backend: {} (ollama or deepseek)", self.backend.as_str()));
+        };
+        let model = args.get("model").unwrap_or(default_model(backend));
+        self.switch_model(backend, model).await
+    }
+
+    async fn reload_command(&mut self, _args: &CommandArgs) -> Result<String> {
+        let files = self.reload_config()?;
+        if files.is_empty() {
+            return Ok("No sovereign.toml to reload; using the defaults.".to_string());
+        }
+        let files: Vec<String> = files.iter().map(|path| path.display().to_string()).collect();
+        Ok(format!("Reloaded {}.", files.join(" and ")))
+    }
+
+    /// `/deps`, or `/rdeps` when `reverse`
+    async fn deps_command(&mut self, args: &CommandArgs, reverse: bool) -> Result<String> {
+        let Some(ref index) = self.codebase else {
+            return Ok("No codebase indexed.".to_string());
+        };
+        let file = args.text("file");
+        let _ = index.record_interaction(file, "view");
+        let (files, relation) = if reverse {
+            (index.dependents(file)?, "is imported by")
+        } else {
+            (index.dependencies(file)?, "imports")
+        };
+        if files.is_empty() {
+            Ok(format!("{} {} no indexed files.", file, relation))
+        } else {
+            Ok(format!(
+                "{} {} {} file(s):\n{}",
+                file,
+                relation,
+                files.len(),
+                files.iter().map(|f| format!("  {}", f)).collect::<Vec<_>>().join("\n")
+            ))
+        }
+    }
+
+    async fn glossary_command(&mut self, args: &CommandArgs) -> Result<String> {
+        match (args.get("action"), args.number("n")) {
+            (None, None) => self.glossary_report(),
+            (Some(_), limit) => self.build_glossary(limit.unwrap_or(DEFAULT_GLOSSARY_TERMS)).await,
+            (None, Some(_)) => Ok("Usage: /glossary [build] [n]".to_string()),
+        }
+    }
+
+    async fn summarize_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let Some(ref index) = self.codebase else {
+            return Ok("No codebase indexed.".to_string());
+        };
+        let file = args.text("file");
+        let _ = index.record_interaction(file, "view");
+        let summary = self.search_agent.summarize_file(index, file).await?;
+        Ok(self.with_capability_note("summarize", summary))
+    }
+
+    async fn embed_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let Some(ref index) = self.codebase else {
+            return Ok("No codebase indexed. Use /index <path> first.".to_string());
+        };
+        if self.dry_run() {
+            let due = if args.flag("--pending") {
+                index.pending_embedding_count()?
+            } else {
+                let reembed = args.flag("--reembed");
+                index
+                    .list_files(None, 1000)?
+                    .iter()
+                    .filter(|file| (reembed || !index.has_embedding(&file.path)) && !index.is_excluded(&file.path))
+                    .count()
+            };
+            let clearing = if args.flag("--reembed") { " after removing every stored embedding" } else { "" };
+            return Ok(dry_run::would(format!("embed {} files{}", due, clearing)));
+        }
+        if args.flag("--pending") {
+            let (processed, remaining) = self.search_agent
+                .process_embedding_queue(index, usize::MAX)
+                .await?;
+            return Ok(format!("Re-embedded {} changed files ({} still pending).", processed, remaining));
+        }
+
+        jobs::progress("Building embeddings for semantic search...");
+        let count = self.search_agent.index_embeddings(index, args.flag("--reembed")).await?;
+        Ok(format!("Created embeddings for {} files.", count))
+    }
+
+    async fn run_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let Some(ref runner) = self.tool_runner else {
+            return Ok("No codebase indexed. Use /index <path> first.".to_string());
+        };
+        println!("  Running on {}...", runner.target().describe());
+        let output = runner.run(args.text("command")).await?;
+        let status = match output.exit_code {
+            Some(code) => format!("exit code {}", code),
+            None => "terminated by signal".to_string(),
+        };
+        let header = if output.success { "Succeeded" } else { "Failed" };
+        Ok(format!("{} ({}):\n{}", header, status, output.combined()))
+    }
+
+    async fn task_command(&mut self, args: &CommandArgs) -> Result<String> {
+        if args.flag("--stop") {
+            return Ok(match self.task.take() {
+                Some(task) => format!("Stopped the task after {} of {} steps:\n{}", task.next, task.steps.len(), task.report()),
+                None => "No task is running.".to_string(),
+            });
+        }
+        if args.flag("--continue") || args.flag("--skip") {
+            let Some(mut task) = self.task.take() else {
+                return Ok("No step is waiting. /task <goal> starts a task.".to_string());
+            };
+            if args.flag("--skip") {
+                task.record("skipped", "");
+            } else {
+                self.run_confirmed_step(&mut task).await;
+            }
+            return self.run_task(task).await;
+        }
+
+        let goal = args.text("goal");
+        if goal.is_empty() {
+            return Ok(match self.task {
+                Some(ref task) => format!("Working on: {}\n{}", task.goal, task.plan_listing()),
+                None => "No task is running. /task <goal> starts one.".to_string(),
+            });
+        }
+        let Some(ref index) = self.codebase else {
+            return Ok("No codebase indexed. Use /index <path> first.".to_string());
+        };
+
+        println!("  Planning...");
+        let files: Vec<String> = index.list_files(None, MAX_RESULTS)?.into_iter().map(|f| f.relative_path).collect();
+        let steps = task::plan(&self.chat_agent.llm, &self.config.prompts, goal, &files).await?;
+        if steps.is_empty() {
+            return Ok("The model gave no plan that uses the available tools; try putting the goal differently.".to_string());
+        }
+        let task = Task::new(goal, steps);
+        println!("  Plan:\n{}", task.plan_listing());
+        self.run_task(task).await
+    }
+
+    /// Run the task's steps until it ends or reaches one to confirm, which is kept
+    /// in `self.task` for `/task --continue`
+    async fn run_task(&mut self, mut task: Task) -> Result<String> {
+        let total = task.steps.len();
+        while let Some(step) = task.current().cloned() {
+            println!("  [{}/{}] {}", task.next + 1, total, step.describe());
+            if !step.tool.needs_confirmation() {
+                match self.run_task_step(&step).await {
+                    Ok((outcome, output)) => task.record(&outcome, &output),
+                    Err(e) => task.record(&format!("failed: {}", e), ""),
+                }
+                continue;
+            }
+
+            // Edits are proposed before asking, so the question shows the diff
+            let preview = if step.tool == Tool::Edit {
+                let path = self.task_path(&step.input);
+                match self.propose_edit(&path, &task.edit_instruction(&step)).await {
+                    Ok(changes) if changes.is_empty() => {
+                        task.record("no change needed", "");
+                        continue;
+                    }
+                    Ok(changes) => {
+                        let diff = self.changes_diff(&changes);
+                        task.pending = changes;
+                        format!("```diff\n{}```", diff)
+                    }
+                    Err(e) => {
+                        task.record(&format!("failed: {}", e.to_string().lines().next().unwrap_or_default()), "");
+                        continue;
+                    }
+                }
+            } else {
+                format!("$ {}", step.input)
+            };
+
+            let message = format!(
+                "Step {}/{} of \"{}\": {}\n{}\n/task --continue to go ahead, /task --skip to leave it out, /task --stop to end the task.",
+                task.next + 1,
+                total,
+                task.goal,
+                step.describe(),
+                preview
+            );
+            self.task = Some(task);
+            return Ok(message);
+        }
+        Ok(format!("Finished \"{}\":\n{}", task.goal, task.report()))
+    }
+
+    /// Run a step that reads without changing anything, returning its outcome and output
+    async fn run_task_step(&mut self, step: &task::TaskStep) -> Result<(String, String)> {
+        let Some(ref index) = self.codebase else {
+            anyhow::bail!("no codebase indexed");
+        };
+        let lines: Vec<String> = match step.tool {
+            Tool::Search => self.search_agent
+                .semantic_search(index, &step.input, MAX_RESULTS)
+                .await?
+                .into_iter()
+                .take(10)
+                .map(|r| match r.line_range {
+                    Some((start, end)) => format!("{}:{}-{}", r.path, start, end),
+                    None => r.path,
+                })
+                .collect(),
+            Tool::Grep => grep::grep(index, &step.input, &GrepOptions::default(), 50)?
+                .into_iter()
+                .map(|m| format!("{}:{}: {}", m.path, m.line, m.text))
+                .collect(),
+            Tool::Read => {
+                let path = self.task_path(&step.input);
+                let content = std::fs::read_to_string(&path)
+                    .map_err(|e| anyhow::anyhow!("could not read {}: {}", path.display(), e))?;
+                return Ok((format!("{} lines", content.lines().count()), content));
+            }
+            Tool::Edit | Tool::Run => anyhow::bail!("{} needs to be confirmed", step.tool.as_str()),
+        };
+        Ok((format!("{} found", lines.len()), lines.join("\n")))
+    }
+
+    /// Run the step the user confirmed: write its edit or run its command
+    async fn run_confirmed_step(&mut self, task: &mut Task) {
+        let Some(step) = task.current().cloned() else {
+            return;
+        };
+        if self.dry_run() {
+            let action = match step.tool {
+                Tool::Edit => format!("write {}", step.input),
+                _ => format!("run `{}`", step.input),
+            };
+            task.record(&dry_run::would(action), "");
+            return;
+        }
+
+        match step.tool {
+            Tool::Edit => {
+                let changes = std::mem::take(&mut task.pending);
+                let diff = self.changes_diff(&changes);
+                match self.apply_changes(&changes) {
+                    Ok(()) => task.record("written; /undo puts it back", &diff),
+                    Err(e) => task.record(&format!("failed: {}", e), ""),
+                }
+            }
+            _ => {
+                let Some(ref runner) = self.tool_runner else {
+                    task.record("failed: no codebase indexed", "");
+                    return;
+                };
+                match runner.run(&step.input).await {
+                    Ok(output) => {
+                        let outcome = match output.exit_code {
+                            Some(0) => "succeeded".to_string(),
+                            Some(code) => format!("failed with exit code {}", code),
+                            None => "terminated by signal".to_string(),
+                        };
+                        task.record(&outcome, &output.combined());
+                    }
+                    Err(e) => task.record(&format!("failed: {}", e), ""),
+                }
+            }
+        }
+    }
+
+    /// A path a plan names, relative to the project root
+    fn task_path(&self, file: &str) -> PathBuf {
+        match self.project_root {
+            Some(ref root) => root.join(file),
+            None => PathBuf::from(file),
+        }
+    }
+
+    async fn diagnostics_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let diagnostics = match args.get("file") {
+            Some(file) => self.lsp_diagnostics(file).await?,
+            None => self.lsp_client().await?.all_diagnostics(),
+        };
+
+        if diagnostics.is_empty() {
+            let server = self.lsp.as_ref().map_or("the language server", |client| client.server());
+            return Ok(format!("No diagnostics reported by {}.", server));
+        }
+
+        let root = self.project_root.clone().unwrap_or_default();
+        Ok(format_diagnostics(&diagnostics, &root))
+    }
+
+    async fn definition_command(&mut self, args: &CommandArgs) -> Result<String> {
+        // <file>:<line>:<column>, checked by the spec
+        let mut position = args.text("file").rsplitn(3, ':');
+        let (Some(column), Some(line), Some(file)) = (position.next(), position.next(), position.next()) else {
+            return Ok("Usage: /definition <file>:<line>:<col>".to_string());
+        };
+        let (Ok(line), Ok(column)) = (line.parse::<usize>(), column.parse::<usize>()) else {
+            return Ok("Usage: /definition <file>:<line>:<col>".to_string());
+        };
+
+        // Opening the file makes sure the server has its current content
+        self.lsp_diagnostics(file).await?;
+        let root = self.project_root.clone().unwrap_or_default();
+        let path = self.codebase.as_ref()
+            .and_then(|index| {
+                let _ = index.record_interaction(file, "view");
+                index.get_file(file).ok().flatten()
+            })
+            .map(|f| PathBuf::from(f.path))
+            .unwrap_or_else(|| root.join(file));
+        let locations = self.lsp_client().await?.definition(&path, line, column).await?;
+
+        if locations.is_empty() {
+            return Ok("No definition found.".to_string());
+        }
+
+        Ok(locations.iter()
+            .map(|l| {
+                let source = std::fs::read_to_string(&l.path).ok()
+                    .and_then(|c| c.lines().nth(l.line - 1).map(|s| s.trim().to_string()))
+                    .unwrap_or_default();
+                format!(
+                    "  {}:{}:{}  {}",
+                    l.path.strip_prefix(&root).unwrap_or(&l.path).display(),
+                    l.line,
+                    l.column,
+                    source
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    async fn check_edits_command(&mut self, _args: &CommandArgs) -> Result<String> {
+        let Some(ref index) = self.codebase else {
+            return Ok("No codebase indexed. Use /index <path> first.".to_string());
+        };
+        let edits = consistency::working_tree_edits(index)?;
+        if edits.is_empty() {
+            return Ok("No files changed since the last index.".to_string());
+        }
+
+        let warnings = consistency::check_edits(index, &edits)?;
+        if warnings.is_empty() {
+            return Ok(format!("{} changed files, no missed references found.", edits.len()));
+        }
+        let mut output = format!(
+            "{} changed files leave {} references to removed or renamed symbols:\n",
+            edits.len(),
+            warnings.len()
+        );
+        for warning in &warnings {
+            output.push_str(&format!("  {}\n", warning.describe()));
+        }
+        Ok(output)
+    }
+
+    async fn stats_command(&mut self, _args: &CommandArgs) -> Result<String> {
+        let Some(stats) = self.get_codebase_stats() else {
+            return Ok("No codebase indexed.".to_string());
+        };
+        let mut output = format!(
+            "Codebase Statistics:\n  Files: {}\n  Lines: {}\n  Languages:\n",
+            stats.total_files, stats.total_lines
+        );
+        for (lang, count) in &stats.languages {
+            output.push_str(&format!("    {}: {} files\n", lang, count));
+        }
+        if let Some(pending) = self.codebase.as_ref().and_then(|c| c.pending_embedding_count().ok()) {
+            if pending > 0 {
+                output.push_str(&format!("  Embeddings pending: {} changed files\n", pending));
+            }
+        }
+        let most_used = self.most_used_files(5);
+        if !most_used.is_empty() {
+            output.push_str("  Most used files:\n");
+            for (path, count) in &most_used {
+                output.push_str(&format!("    {} ({} interactions)\n", path, count));
+            }
+        }
+        if let Some(last) = stats.last_indexed {
+            output.push_str(&format!("  Last indexed: {}", last));
+        }
+        Ok(output)
+    }
+
+    async fn memory_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let memories = match args.get("tag") {
+            Some(tag) => self.memory.get_by_tag(tag, 10)?,
+            None => self.memory.get_recent(10)?,
+        };
+        if memories.is_empty() {
+            return match args.get("tag") {
+                Some(tag) => Ok(format!("No memories tagged '{}'.", tag)),
+                None => Ok("No memories stored yet.".to_string()),
+            };
+        }
+        Ok(memories.iter()
+            .map(|m| format!(
+                "  {}  [{}] {}\n      tags: {}",
+                &m.id[..m.id.len().min(8)],
+                m.memory_type.as_str(),
+                m.content.chars().take(80).collect::<String>(),
+                m.tags.join(", ")
+            ))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// Both memory stores, for commands that correct memories
+    pub fn memories(&mut self) -> Memories<'_> {
+        Memories::new(&self.memory, &mut self.crdt_memory)
+    }
+
+    async fn memory_show_command(&mut self, args: &CommandArgs) -> Result<String> {
+        self.memories().show(args.text("id"))
+    }
+
+    async fn memory_edit_command(&mut self, args: &CommandArgs) -> Result<String> {
+        self.memories().edit(args.text("id"), args.text("text"))
+    }
+
+    async fn memory_delete_command(&mut self, args: &CommandArgs) -> Result<String> {
+        self.memories().delete(args.text("id"))
+    }
+
+    async fn memory_set_importance_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let Ok(importance) = args.text("importance").parse::<f32>() else {
+            return Ok(format!("Importance must be a number from 0 to 1, not '{}'.", args.text("importance")));
+        };
+        self.memories().set_importance(args.text("id"), importance)
+    }
+
+    async fn sync_export_command(&mut self, _args: &CommandArgs) -> Result<String> {
+        let export_path = self.data_dir.join("sync_export.automerge");
+        let bytes = self.crdt_memory.export();
+        if self.dry_run() {
+            return Ok(dry_run::would(format!("write {} bytes of memories to {}", bytes.len(), export_path.display())));
+        }
+        std::fs::write(&export_path, bytes)?;
+        Ok(format!("Exported CRDT memories to: {}", export_path.display()))
+    }
+
+    async fn sync_import_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let import_path = PathBuf::from(args.text("file"));
+        if !import_path.exists() {
+            return Ok(format!("File not found: {}", import_path.display()));
+        }
+        let bytes = std::fs::read(&import_path)?;
+        self.crdt_memory.merge(&bytes)?;
+        let count = self.crdt_memory.count()?;
+        Ok(format!("Merged successfully. Total memories: {}", count))
+    }
+
+    async fn sync_status_command(&mut self, _args: &CommandArgs) -> Result<String> {
+        let count = self.crdt_memory.count()?;
+        let heads = self.crdt_memory.get_heads();
+        let conn_info = self.p2p_sync.connection_info();
+        Ok(format!(
+            "CRDT Memory Status:\n  Memories: {}\n  Document heads: {}\n  Data dir: {}\n\nP2P Sync:\n  {}",
+            count,
+            heads.len(),
+            self.data_dir.display(),
+            conn_info
+        ))
+    }
+
+    async fn sync_pull_command(&mut self, args: &CommandArgs) -> Result<String> {
+        match self.p2p_sync.pull_from_peer(args.text("host:port")).await {
+            Ok((data, result)) => {
+                if !data.is_empty() {
+                    self.crdt_memory.merge(&data)?;
+                    Ok(format!(
+                        "{}\n{}",
+                        result,
+                        if self.dry_run() { dry_run::would("merge into local CRDT.") } else { "Merged into local CRDT.".to_string() }
+                    ))
+                } else {
+                    Ok("Received empty data from peer.".to_string())
+                }
+            }
+            Err(e) => Ok(format!("Pull failed: {}", e))
+        }
+    }
+
+    async fn sync_push_command(&mut self, args: &CommandArgs) -> Result<String> {
+        match self.p2p_sync.push_to_peer(args.text("host:port")).await {
+            Ok(result) => Ok(format!("{}", result)),
+            Err(e) => Ok(format!("Push failed: {}", e))
+        }
+    }
+
+    async fn sync_live_command(&mut self, args: &CommandArgs) -> Result<String> {
+        match self.p2p_sync.sync_with_peer(args.text("host:port")).await {
+            Ok((data, result)) => {
+                if !data.is_empty() {
+                    self.crdt_memory.merge(&data)?;
+                    Ok(format!(
+                        "{}\n{}",
+                        result,
+                        if self.dry_run() { dry_run::would("merge into local CRDT.") } else { "Bidirectional sync complete.".to_string() }
+                    ))
+                } else {
+                    Ok(format!("{}\nNo remote data to merge.", result))
+                }
+            }
+            Err(e) => Ok(format!("Sync failed: {}", e))
+        }
+    }
+
+    async fn share_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let path = match args.get("file") {
+            Some(file) => PathBuf::from(file),
+            None => {
+                let name = format!("session-{}.json", chrono::Utc::now().format("%Y%m%d-%H%M%S"));
+                self.data_dir.join("shares").join(name)
+            }
+        };
+
+        let bundle = self.export_share(&path).await?;
+        Ok(format!(
+            "{} {} messages and {} retrievals to: {}",
+            if self.dry_run() { "[dry run] Would share" } else { "Shared" },
+            bundle.conversation.iter().filter(|m| m.role != "system").count(),
+            bundle.retrievals.len(),
+            path.display()
+        ))
+    }
+
+    async fn analytics_command(&mut self, args: &CommandArgs) -> Result<String> {
+        match self.analytics {
+            Some(ref analytics) => {
+                let days = args.number("days").unwrap_or(30) as u32;
+                Ok(serde_json::to_string_pretty(&analytics.summary(days)?)?)
+            }
+            None => Ok("Analytics are disabled (--no-analytics).".to_string()),
+        }
+    }
+
+    async fn context_command(&mut self, _args: &CommandArgs) -> Result<String> {
+        Ok(self.chat_agent.context_report())
+    }
+
+    /// `file` as given on the command line, taken relative to the project root when there is one
+    fn project_path(&self, file: &str) -> PathBuf {
+        match self.project_root {
+            Some(ref root) => root.join(file),
+            None => PathBuf::from(file),
+        }
+    }
+
+    async fn pin_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let file = args.text("file");
+        let path = self.project_path(file);
+        // A file that can't be read now would only fail every later message
+        let content = std::fs::read_to_string(&path).with_context(|| format!("Could not read {}", file))?;
+        if !self.chat_agent.pin(&path) {
+            return Ok(format!("{} is already pinned.", file));
+        }
+        let tokens = estimate_tokens(&[ChatMessage { role: "system".to_string(), content }]);
+        let (_, available) = self.chat_agent.context_usage();
+        Ok(format!("Pinned {} (~{} of {} tokens).", file, tokens, available))
+    }
+
+    async fn unpin_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let path = args.get("file").map(|file| self.project_path(file));
+        let unpinned = self.chat_agent.unpin(path.as_deref());
+        if unpinned.is_empty() {
+            return Ok(match args.get("file") {
+                Some(file) => format!("{} is not pinned.", file),
+                None => "No files are pinned.".to_string(),
+            });
+        }
+        let names: Vec<String> = unpinned.iter().map(|p| self.display_path(p)).collect();
+        Ok(format!("Unpinned {}.", names.join(", ")))
+    }
+
+    async fn checkpoint_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let Some(name) = args.get("name") else {
+            if self.checkpoints.is_empty() {
+                return Ok("No checkpoints yet. /checkpoint <name> saves one.".to_string());
+            }
+            return Ok(self
+                .checkpoints
+                .iter()
+                .map(|(name, checkpoint)| format!("  {} ({} messages)", name, checkpoint.messages.len()))
+                .collect::<Vec<_>>()
+                .join("\n"));
+        };
+
+        let checkpoint = Checkpoint {
+            messages: self.chat_agent.conversation()[1..].to_vec(),
+            retrievals: self.retrievals.clone(),
+            last_question: self.last_question.clone(),
+        };
+        let count = checkpoint.messages.len();
+        let replaced = self.checkpoints.insert(name.to_string(), checkpoint).is_some();
+        Ok(format!(
+            "{} checkpoint '{}' at {} messages. /rewind {} comes back here.",
+            if replaced { "Replaced" } else { "Saved" },
+            name,
+            count,
+            name
+        ))
+    }
+
+    async fn rewind_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let name = args.text("name");
+        let Some(checkpoint) = self.checkpoints.get(name) else {
+            let names: Vec<&str> = self.checkpoints.keys().map(String::as_str).collect();
+            if names.is_empty() {
+                return Ok(format!("No checkpoint '{}'. None saved this session.", name));
+            }
+            return Ok(format!("No checkpoint '{}'. Saved: {}", name, names.join(", ")));
+        };
+
+        self.chat_agent.restore_conversation(&checkpoint.messages);
+        self.retrievals = checkpoint.retrievals.clone();
+        self.last_question = checkpoint.last_question.clone();
+        Ok(format!("Rewound to '{}' ({} messages).", name, checkpoint.messages.len()))
+    }
+
+    async fn clear_command(&mut self, _args: &CommandArgs) -> Result<String> {
+        self.chat_agent.clear_conversation();
+        self.retrievals.clear();
+        Ok("Conversation cleared.".to_string())
+    }
+
+    async fn pr_summary_command(&mut self, args: &CommandArgs) -> Result<String> {
+        let summary = self.git_agent.pr_summary_against(args.get("base"), None).await?;
+        Ok(self.with_capability_note("pr_summary", summary))
+    }
+
+    async fn help_command(&mut self, args: &CommandArgs) -> Result<String> {
+        match args.get("command") {
+            None => Ok(commands::help_text()),
+            Some(name) => match self.command(name) {
+                Some((spec, _)) => Ok(spec.help()),
+                None => Ok(format!("Unknown command: {}. Type /help for available commands.", name)),
+            },
+        }
+    }
+}
+
+impl Drop for Orchestrator {
+    fn drop(&mut self) {
+        self.stop_lsp();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::commands::COMMANDS;
+    use crate::review::Severity;
+
+    #[test]
+    fn test_diagnostics_are_listed_relative_to_the_project() {
+        let diagnostic = |path: &str, line, severity, message: &str| LspDiagnostic {
+            path: PathBuf::from(path),
+            line,
+            column: 5,
+            severity,
+            message: message.to_string(),
+            source: None,
+        };
+        let diagnostics = [
+            diagnostic("/work/app/src/main.rs", 12, Severity::Error, "mismatched types\nexpected `u32`"),
+            diagnostic("/elsewhere/lib.rs", 3, Severity::Warning, "unused variable"),
+        ];
+
+        assert_eq!(
+            format_diagnostics(&diagnostics, Path::new("/work/app")),
+            "  src/main.rs:12:5 [error] mismatched types\n  /elsewhere/lib.rs:3:5 [warning] unused variable"
+        );
+    }
+
+    #[test]
+    fn test_result_pages() {
+        let results = (1..=12)
+            .map(|i| (format!("src/f{}.rs", i), format!("src/f{}.rs (rust)", i)))
+            .collect();
+        let mut pages = ResultPages::new(results);
+
+        let first = pages.next_page();
+        assert!(first.starts_with("  [1] src/f1.rs (rust)"));
+        assert!(first.contains("Showing 1-10 of 12. /more for the next 2"));
+
+        let second = pages.next_page();
+        assert!(second.starts_with("  [11] src/f11.rs (rust)"));
+        assert_eq!(pages.next_page(), "No more results.");
+
+        assert_eq!(pages.path("#12"), Some("src/f12.rs"));
+        assert_eq!(pages.path("#0"), None);
+        assert_eq!(pages.path("src/main.rs"), None);
+    }
+
+    #[test]
+    fn test_parse_grep_args() {
+        let grep = |args: &str| parse_grep_args(&commands::find("/grep").unwrap().parse(args).unwrap());
+
+        let (pattern, options) = grep("-F -i \"a.b(\" path:src/ lang:rust");
+        assert_eq!(pattern, "a.b(");
+        assert!(options.literal && options.ignore_case);
+        assert_eq!(options.filters.paths, vec!["src/"]);
+        assert_eq!(options.filters.languages, vec!["rust"]);
+
+        let (pattern, options) = grep(r"fn \w+_test");
+        assert_eq!(pattern, r"fn \w+_test");
+        assert!(!options.literal && !options.ignore_case && options.filters.is_empty());
+
+        let (pattern, options) = grep("-iF 'TODO:  fix' symbol:parse");
+        assert_eq!(pattern, "TODO:  fix");
+        assert!(options.literal && options.ignore_case);
+        assert_eq!(options.filters.symbols, vec!["parse"]);
+    }
+
+    #[test]
+    fn test_every_command_has_a_handler() {
+        for spec in COMMANDS {
+            assert_eq!(HANDLERS.iter().filter(|(name, _)| *name == spec.name).count(), 1, "{}", spec.name);
+        }
+        assert_eq!(HANDLERS.len(), COMMANDS.len());
+    }
+
+    #[test]
+    fn test_session_changes_merge_per_file() {
+        let changes = |added: &[&str], modified: &[&str], removed: &[&str]| IndexChanges {
+            added: added.iter().map(|p| p.to_string()).collect(),
+            modified: modified.iter().map(|p| p.to_string()).collect(),
+            removed: removed.iter().map(|p| p.to_string()).collect(),
+        };
+        let mut session = SessionChanges::new(None);
+
+        session.record(&changes(&["src/new.rs", "scratch.rs"], &["src/lib.rs"], &[]), Utc::now());
+        session.record(&changes(&[], &["src/new.rs", "src/lib.rs"], &["scratch.rs", "src/old.rs"]), Utc::now());
+
+        let status: Vec<(&str, FileStatus, usize)> = session.files
+            .iter()
+            .map(|(path, file)| (path.as_str(), file.status.clone(), file.times))
+            .collect();
+        assert_eq!(status, vec![
+            ("src/lib.rs", FileStatus::Modified, 2),
+            ("src/new.rs", FileStatus::Added, 2),
+            ("src/old.rs", FileStatus::Deleted, 1),
+        ]);
+        assert!(session.describe()[0].starts_with("src/lib.rs (modified, 2 times, last at "));
+    }
+}
@@ -1,35 +1,169 @@
 use anyhow::Result;
-use crate::llm::OllamaClient;
-use crate::storage::CodebaseIndex;
-use crate::embeddings::{EmbeddingClient, find_similar};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::config::Config;
+use crate::embeddings::{cosine_similarity, EmbeddingClient};
+use crate::glossary;
+use crate::jobs;
+use crate::llm::LlmClient;
+use crate::prompts::{PromptLibrary, Verbosity};
+use crate::rag::{interaction_boost, RagConfig, RagRetriever};
+use crate::storage::{CachedAnswer, ChunkEmbedding, CodebaseIndex, Exchange, IndexedFile, SearchFilters, TranscriptStore};
+use crate::storage::codebase::line_span;
+use crate::storage::memory::STOPWORDS;
+use crate::summarize::MapReduce;
+
+/// Embedding input is capped so oversized chunks stay within the model context
+const MAX_EMBED_CHARS: usize = 2000;
+
+/// Chunks ranked when looking for more of the files an answer cites
+const FURTHER_CHUNK_CANDIDATES: usize = 20;
+
+/// Most related terms taken from the model when expanding a query
+const MAX_EXPANSIONS: usize = 8;
+
+/// Relevance given to files found only through an expanded term
+const EXPANSION_RELEVANCE: f32 = 0.4;
+
+/// Question embeddings at least this similar are treated as the same question
+const ANSWER_CACHE_SIMILARITY: f32 = 0.92;
+
+/// Most cached answers compared against a new question
+const ANSWER_CACHE_SIZE: usize = 200;
+
+/// Chat exchanges less similar to the question than this are left out
+const MIN_EXCHANGE_SIMILARITY: f32 = 0.6;
+
+/// Chat exchanges embedded per `/ask --include-chats`, oldest backlog last
+const EXCHANGE_EMBED_BATCH: usize = 32;
+
+/// Characters of an earlier chat answer included in a prompt
+const MAX_EXCHANGE_CHARS: usize = 1500;
+
+/// Best retrieval score and share of question terms found needed for high confidence
+const HIGH_CONFIDENCE_SCORE: f32 = 0.6;
+const HIGH_CONFIDENCE_COVERAGE: f32 = 0.6;
+
+/// Files at least this large get their stored summary in the prompt, since a
+/// snippet shows only a small part of them
+const SUMMARY_CONTEXT_BYTES: u64 = 8000;
+
+/// Characters of a file the model reads to summarize it
+const MAX_SUMMARY_INPUT_CHARS: usize = 6000;
+
+/// Imported files whose definitions are shown when explaining a file
+const MAX_DEPENDENCY_FILES: usize = 5;
+
+/// Definitions from imported files shown when explaining a file
+const MAX_DEPENDENCY_SYMBOLS: usize = 15;
+
+/// Lines of each imported definition shown, starting at its signature
+const DEPENDENCY_SNIPPET_LINES: usize = 8;
+
+/// Below either of these the evidence is treated as weak
+const LOW_CONFIDENCE_SCORE: f32 = 0.35;
+const LOW_CONFIDENCE_COVERAGE: f32 = 0.3;
+
+/// Question words that say nothing about what is being asked for
+const QUESTION_WORDS: &[&str] = &["how", "why", "who", "the", "can", "did", "are", "and", "for", "not"];
+
+/// What the model is told to answer when the code does not contain the answer
+const NOT_FOUND_ANSWER: &str = "Not found in the indexed code.";
+
+const ASK_SYSTEM_PROMPT: &str = "You are a code expert answering questions about a codebase. Be specific and reference file names and code when relevant. Cite the numbered snippets you rely on as [1], [2]. Only state what the snippets show; if they do not answer the question, reply \"Not found in the indexed code.\" and say what was looked at instead of guessing.";
+
+const EXPANSION_SYSTEM_PROMPT: &str = "You expand code search queries. Reply with related search terms only, one per line: synonyms, and identifier names the code is likely to use. No numbering or explanations.";
 
 pub struct SearchAgent {
-    llm: OllamaClient,
+    llm: LlmClient,
     embedding_client: EmbeddingClient,
+    retriever: RagRetriever,
+    prompts: PromptLibrary,
+    rag_config: RagConfig,
 }
 
 impl SearchAgent {
-    pub fn new(llm: OllamaClient) -> Self {
+    pub fn new(llm: LlmClient, config: &Config) -> Self {
+        let embedding_client = EmbeddingClient::from_config(&config.embeddings).with_servers(&config.servers);
+        let retriever = RagRetriever::new(config.rag.clone())
+            .with_embedding_client(embedding_client.clone());
+
         Self {
             llm,
-            embedding_client: EmbeddingClient::new(),
+            embedding_client,
+            retriever,
+            prompts: config.prompts.clone(),
+            rag_config: config.rag.clone(),
         }
     }
 
+    /// Answer with another model, as `/model` switches to
+    pub fn set_llm(&mut self, llm: LlmClient) {
+        self.llm = llm;
+    }
+
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.prompts.set_verbosity(verbosity);
+    }
+
     pub async fn semantic_search(
         &self,
         index: &CodebaseIndex,
         query: &str,
         limit: usize,
     ) -> Result<Vec<SearchResult>> {
+        let (filters, query) = parse_filters(query);
+        let query = query.as_str();
+
+        // Results from sources that cannot filter in SQL are checked against the files in scope
+        let in_scope = if filters.is_empty() { None } else { Some(index.filtered_paths(&filters)?) };
+        let keep = |path: &str| in_scope.as_ref().is_none_or(|paths| paths.contains(path));
+
+        // A query of filters alone lists the files in scope
+        if query.is_empty() {
+            let mut files: Vec<IndexedFile> = in_scope
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|path| index.get_file(path).ok().flatten())
+                .collect();
+            files.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+            return Ok(files
+                .into_iter()
+                .take(limit)
+                .map(|file| SearchResult {
+                    path: file.relative_path,
+                    language: file.language,
+                    symbols: file.symbols,
+                    relevance: 0.5,
+                    snippet: None,
+                    line_range: None,
+                    excerpt: None,
+                })
+                .collect());
+        }
+
         // First, try direct text search
-        let direct_results = index.search(query, limit)?;
+        let direct_results = index.search_filtered(query, &filters, limit)?;
 
         // Also try symbol search
-        let symbol_results = index.search_by_symbol(query, limit)?;
+        let symbol_results: Vec<IndexedFile> = index
+            .search_by_symbol(query, limit * 4)?
+            .into_iter()
+            .filter(|file| keep(&file.path))
+            .take(limit)
+            .collect();
+
+        // Related terms let the query match code that never uses its words
+        let expansions = self.expand_query(query).await;
+        let embedding_query = if expansions.is_empty() {
+            query.to_string()
+        } else {
+            format!("{}\n{}", query, expansions.join(" "))
+        };
 
         // Try embedding-based search if embeddings exist
-        let embedding_results = self.embedding_search(index, query, limit).await.ok();
+        let embedding_results = self.embedding_search(index, &embedding_query, limit, 1, in_scope.as_ref()).await.ok();
 
         // Combine and deduplicate results
         let mut results: Vec<SearchResult> = Vec::new();
@@ -37,24 +171,30 @@ impl SearchAgent {
 
         // Add embedding results first (higher relevance)
         if let Some(emb_results) = embedding_results {
-            for (path, relevance) in emb_results {
-                if seen_paths.insert(path.clone()) {
+            for (chunk, relevance) in emb_results {
+                if seen_paths.insert(chunk.path.clone()) {
                     // Get file details from index by exact path
-                    if let Ok(Some(file)) = index.get_file(&path) {
+                    if let Ok(Some(file)) = index.get_file(&chunk.path) {
+                        let content = index.get_file_content(&chunk.path).ok().flatten();
+                        let end_line = chunk.last_line(file.lines);
+
                         results.push(SearchResult {
                             path: file.relative_path.clone(),
                             language: file.language.clone(),
                             symbols: file.symbols.clone(),
                             relevance,
-                            snippet: None,
+                            snippet: content.map(|c| chunk.text_of(&c)),
+                            line_range: Some((chunk.start_line, end_line)),
+                            excerpt: None,
                         });
                     }
                 }
             }
         }
 
-        // Add direct text search results
-        for file in direct_results.into_iter().chain(symbol_results.into_iter()) {
+        // Add direct text search results, best bm25 rank first
+        for text_match in direct_results {
+            let file = text_match.file;
             if seen_paths.insert(file.path.clone()) {
                 results.push(SearchResult {
                     path: file.relative_path,
@@ -62,10 +202,60 @@ impl SearchAgent {
                     symbols: file.symbols,
                     relevance: 0.5, // Lower relevance for text match
                     snippet: None,
+                    line_range: Some((text_match.start_line, text_match.end_line)),
+                    excerpt: Some(text_match.excerpt),
+                });
+            }
+        }
+        for file in symbol_results {
+            if seen_paths.insert(file.path.clone()) {
+                results.push(SearchResult {
+                    path: file.relative_path,
+                    language: file.language,
+                    symbols: file.symbols,
+                    relevance: 0.5,
+                    snippet: None,
+                    line_range: None,
+                    excerpt: None,
                 });
             }
         }
 
+        // Files matched only by an expanded term rank below direct matches
+        for term in &expansions {
+            let quoted = format!("\"{}\"", term);
+            let matches = index.search_filtered(&quoted, &filters, limit).unwrap_or_default()
+                .into_iter()
+                .map(|m| m.file)
+                .chain(index.search_by_symbol(term, limit).unwrap_or_default());
+
+            for file in matches.filter(|file| keep(&file.path)) {
+                if seen_paths.insert(file.path.clone()) {
+                    results.push(SearchResult {
+                        path: file.relative_path,
+                        language: file.language,
+                        symbols: file.symbols,
+                        relevance: EXPANSION_RELEVANCE,
+                        snippet: None,
+                        line_range: None,
+                        excerpt: None,
+                    });
+                }
+            }
+        }
+
+        // Files the user keeps coming back to are more likely what they mean
+        let counts = index.interaction_counts().unwrap_or_default();
+        let max = counts.values().copied().max().unwrap_or(0);
+        if max > 0 {
+            for result in &mut results {
+                if let Ok(Some(file)) = index.get_file(&result.path) {
+                    let count = counts.get(&file.path).copied().unwrap_or(0);
+                    result.relevance = (result.relevance + interaction_boost(&self.rag_config, count, max)).min(1.0);
+                }
+            }
+        }
+
         // Sort by relevance
         results.sort_by(|a, b| b.relevance.partial_cmp(&a.relevance).unwrap_or(std::cmp::Ordering::Equal));
         results.truncate(limit);
@@ -73,55 +263,165 @@ impl SearchAgent {
         Ok(results)
     }
 
+    /// Files imported by `results` that are not among them, scored at half their importer
+    fn imported_files(&self, index: &CodebaseIndex, results: &[SearchResult]) -> Vec<SearchResult> {
+        let mut seen: HashSet<String> = results.iter().map(|r| r.path.clone()).collect();
+        let mut extra = Vec::new();
+
+        for result in results {
+            for path in index.dependencies(&result.path).unwrap_or_default() {
+                if extra.len() >= self.rag_config.import_context {
+                    return extra;
+                }
+                if !seen.insert(path.clone()) {
+                    continue;
+                }
+                if let Ok(Some(file)) = index.get_file(&path) {
+                    extra.push(SearchResult {
+                        path: file.relative_path,
+                        language: file.language,
+                        symbols: file.symbols,
+                        relevance: result.relevance * 0.5,
+                        snippet: None,
+                        line_range: None,
+                        excerpt: None,
+                    });
+                }
+            }
+        }
+
+        extra
+    }
+
+    /// `results` with more of each cited file: its other matching chunks, up to
+    /// `max_chunks_per_file` ranges, with chunks next to each other cited as one
+    ///
+    /// The best chunk alone may be a type while the code that answers the
+    /// question is the method right after it.
+    async fn with_further_chunks(&self, index: &CodebaseIndex, question: &str, results: Vec<SearchResult>) -> Vec<SearchResult> {
+        if results.iter().all(|r| r.line_range.is_none()) {
+            return results;
+        }
+        let per_file = match self.rag_config.max_chunks_per_file {
+            0 => usize::MAX,
+            n => n,
+        };
+        let (_, query) = parse_filters(question);
+        let chunks = self
+            .embedding_search(index, &query, FURTHER_CHUNK_CANDIDATES, usize::MAX, None)
+            .await
+            .unwrap_or_default();
+
+        let mut widened = Vec::new();
+        for result in results {
+            let Some(range) = result.line_range else {
+                widened.push(result);
+                continue;
+            };
+            let Ok(Some(file)) = index.get_file(&result.path) else {
+                widened.push(result);
+                continue;
+            };
+
+            // Best first, so a range taken for a weaker chunk never crowds out a better one
+            let mut ranges = vec![(range, result.relevance)];
+            for (chunk, relevance) in chunks.iter().filter(|(chunk, _)| chunk.path == file.path) {
+                let end_line = chunk.last_line(file.lines);
+                if ranges.iter().any(|((start, end), _)| chunk.start_line >= *start && end_line <= *end) {
+                    continue;
+                }
+                let touches = ranges.iter().any(|((start, end), _)| chunk.start_line <= end + 1 && end_line + 1 >= *start);
+                if touches || ranges.len() < per_file {
+                    ranges.push(((chunk.start_line, end_line), *relevance));
+                    merge_adjacent(&mut ranges);
+                }
+            }
+
+            let content = index.get_file_content(&file.path).ok().flatten();
+            for ((start, end), relevance) in ranges {
+                widened.push(SearchResult {
+                    relevance,
+                    snippet: content.as_deref().map(|c| line_span(c, start, end)).or(result.snippet.clone()),
+                    line_range: Some((start, end)),
+                    ..result.clone()
+                });
+            }
+        }
+        widened
+    }
+
+    /// Synonyms and likely identifiers for `query`; empty when expansion is off or fails
+    async fn expand_query(&self, query: &str) -> Vec<String> {
+        if !self.rag_config.query_expansion {
+            return Vec::new();
+        }
+
+        let prompt = format!("Code search query: {}\n\nRelated search terms:", query);
+        match self.llm
+            .generate_with(&prompt, Some(EXPANSION_SYSTEM_PROMPT), &self.prompts.options("expand_query"))
+            .await
+        {
+            Ok(response) => parse_expansions(&response, query),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Best matching chunks, at most `per_file` of each file, most similar first
     async fn embedding_search(
         &self,
         index: &CodebaseIndex,
         query: &str,
         limit: usize,
-    ) -> Result<Vec<(String, f32)>> {
+        per_file: usize,
+        in_scope: Option<&HashSet<String>>,
+    ) -> Result<Vec<(ChunkEmbedding, f32)>> {
         // Get query embedding
         let query_embedding = self.embedding_client.embed(query).await?;
 
-        // Get all stored embeddings
-        let all_embeddings = index.get_all_embeddings()?;
+        // Vectors from another model are not comparable
+        let model = self.embedding_client.active_model().await;
+        index.check_embedding_model(&model, query_embedding.len())?;
 
-        if all_embeddings.is_empty() {
-            return Ok(vec![]);
-        }
+        // Several chunks of one file may rank highly; keep the best of each
+        // Scoped searches rank every chunk, since the best ones may all be out of scope
+        let candidates = if in_scope.is_some() { usize::MAX } else { limit * 4 };
+        let mut taken: HashMap<String, usize> = HashMap::new();
+        let best = index
+            .search_semantic(&query_embedding, candidates)?
+            .into_iter()
+            .filter(|(chunk, _)| in_scope.is_none_or(|paths| paths.contains(&chunk.path)))
+            .filter(|(chunk, _)| {
+                let count = taken.entry(chunk.path.clone()).or_insert(0);
+                *count += 1;
+                *count <= per_file
+            })
+            .take(limit)
+            .collect();
 
-        // Find similar
-        let similar = find_similar(&query_embedding, &all_embeddings, limit);
-        Ok(similar)
+        Ok(best)
     }
 
-    pub async fn index_embeddings(&self, index: &CodebaseIndex) -> Result<usize> {
+    pub async fn index_embeddings(&self, index: &CodebaseIndex, reembed: bool) -> Result<usize> {
+        if reembed {
+            let removed = index.clear_embeddings()?;
+            println!("  Removed {} embeddings, re-embedding...", removed);
+        }
+
+        let model = self.embedding_client.active_model().await;
         let files = index.list_files(None, 1000)?;
         let mut count = 0;
 
         for file in files {
-            // Skip if already has embedding
-            if index.has_embedding(&file.path) {
+            // Skip if already has embedding, or excluded since it was indexed
+            if index.has_embedding(&file.path) || index.is_excluded(&file.path) {
                 continue;
             }
 
-            // Get file content
-            if let Ok(Some(content)) = index.get_file_content(&file.path) {
-                // Create embedding text: path + symbols + first 1000 chars
-                let embed_text = format!(
-                    "{}\n{}\n{}",
-                    file.relative_path,
-                    file.symbols.join(" "),
-                    content.chars().take(1000).collect::<String>()
-                );
+            if self.embed_file(index, &file, &model).await? {
+                count += 1;
 
-                // Get embedding
-                if let Ok(embedding) = self.embedding_client.embed(&embed_text).await {
-                    index.store_embedding(&file.path, &embedding)?;
-                    count += 1;
-
-                    if count % 10 == 0 {
-                        println!("  Embedded {} files...", count);
-                    }
+                if count % 10 == 0 {
+                    jobs::progress(format!("Embedded {} files...", count));
                 }
             }
         }
@@ -129,63 +429,327 @@ impl SearchAgent {
         Ok(count)
     }
 
-    pub async fn find_symbol(&self, index: &CodebaseIndex, symbol: &str) -> Result<Vec<SearchResult>> {
-        let files = index.search_by_symbol(symbol, 20)?;
+    /// Re-embed up to `batch` files whose content changed since they were embedded
+    ///
+    /// Returns the number of files processed and how many are still queued.
+    pub async fn process_embedding_queue(&self, index: &CodebaseIndex, batch: usize) -> Result<(usize, usize)> {
+        let model = self.embedding_client.active_model().await;
+        let mut processed = 0;
 
-        let results = files
-            .into_iter()
-            .map(|f| SearchResult {
-                path: f.relative_path,
-                language: f.language,
-                symbols: f.symbols.into_iter().filter(|s| s.contains(symbol)).collect(),
-                relevance: 1.0,
-                snippet: None,
-            })
-            .collect();
+        for path in index.pending_embeddings(batch)? {
+            // Files removed from the index or excluded since they were queued are simply dropped
+            if let Some(file) = index.get_file(&path)?.filter(|f| !index.is_excluded(&f.path)) {
+                // Leave the file queued if the embedding backend is unavailable
+                if !self.embed_file(index, &file, &model).await? && file.lines > 0 {
+                    break;
+                }
+            }
+            index.dequeue_embedding(&path)?;
+            processed += 1;
+        }
+
+        Ok((processed, index.pending_embedding_count()?))
+    }
+
+    /// Chunk and embed one file, replacing its stored vectors; false if nothing was stored
+    ///
+    /// Chunks whose text is unchanged since the last embedding keep their vector,
+    /// so editing one function in a large file only re-embeds that function.
+    async fn embed_file(&self, index: &CodebaseIndex, file: &IndexedFile, model: &str) -> Result<bool> {
+        let content = match index.get_file_content(&file.path)? {
+            Some(content) => content,
+            None => return Ok(false),
+        };
+
+        let chunks = self.retriever.chunk_content(&content, &file.path, &file.language);
+        let mut previous = index.chunk_embeddings(&file.path)?;
+        let mut embedded = Vec::new();
+
+        for (chunk_index, chunk) in chunks.iter().enumerate() {
+            let content_hash = CodebaseIndex::compute_hash(&chunk.content);
+
+            let embedding = match previous.remove(&content_hash) {
+                Some(embedding) => embedding,
+                None => {
+                    // Embedding text: path + line range + section + chunk code
+                    let embed_text = format!(
+                        "{} (lines {}-{}{})\n{}",
+                        file.relative_path,
+                        chunk.start_line,
+                        chunk.end_line,
+                        chunk.section.as_ref().map(|s| format!(", {}", s)).unwrap_or_default(),
+                        chunk.content.chars().take(MAX_EMBED_CHARS).collect::<String>()
+                    );
+                    match self.embedding_client.embed(&embed_text).await {
+                        Ok(embedding) => embedding,
+                        Err(_) => continue,
+                    }
+                }
+            };
+
+            embedded.push(ChunkEmbedding {
+                path: file.path.clone(),
+                chunk_index,
+                start_line: chunk.start_line,
+                end_line: chunk.end_line,
+                content_hash,
+                embedding,
+            });
+        }
+
+        // Keep the old vectors (and their reusable chunks) if the backend is unavailable
+        if embedded.is_empty() {
+            return Ok(false);
+        }
+
+        index.delete_embeddings(&file.path)?;
+        for chunk in &embedded {
+            index.store_embedding(chunk, model)?;
+        }
+        index.dequeue_embedding(&file.path)?;
+
+        Ok(true)
+    }
+
+    /// Files defining symbols that match `symbol`, best match first, with the matching symbols
+    pub async fn find_symbol(&self, index: &CodebaseIndex, symbol: &str, limit: usize) -> Result<Vec<SearchResult>> {
+        let mut results: Vec<SearchResult> = Vec::new();
+
+        for (matched, score) in index.match_symbols(symbol, limit * 5)? {
+            let label = format!("{}:{}", matched.kind, matched.name);
+            match results.iter().position(|r| r.path == matched.relative_path) {
+                Some(i) if !results[i].symbols.contains(&label) => results[i].symbols.push(label),
+                Some(_) => {}
+                None if results.len() < limit => results.push(SearchResult {
+                    path: matched.relative_path,
+                    language: index.get_file(&matched.path)?.map(|f| f.language).unwrap_or_default(),
+                    symbols: vec![label],
+                    relevance: score,
+                    snippet: None,
+                    line_range: None,
+                    excerpt: None,
+                }),
+                None => {}
+            }
+        }
 
         Ok(results)
     }
 
+    /// Earlier chat exchanges of the project that explain the question, most relevant first
+    pub async fn relevant_exchanges(
+        &self,
+        transcripts: &TranscriptStore,
+        project: Option<&str>,
+        question: &str,
+        limit: usize,
+    ) -> Result<Vec<Exchange>> {
+        let model = self.embedding_client.active_model().await;
+
+        // Exchanges from before an embedding model was available are embedded on demand
+        for exchange in transcripts.unembedded(project, &model, EXCHANGE_EMBED_BATCH)? {
+            if let Ok(embedding) = self.embedding_client.embed(&exchange.embedding_text()).await {
+                transcripts.store_embedding(&exchange.id, &model, &embedding)?;
+            }
+        }
+
+        let query_embedding = self.embedding_client.embed(question).await?;
+        Ok(transcripts
+            .search_similar(project, &query_embedding, &model, limit, MIN_EXCHANGE_SIMILARITY)?
+            .into_iter()
+            .map(|(exchange, _)| exchange)
+            .collect())
+    }
+
+    /// Answer from retrieved code, plus any earlier chat explanations given as context
+    ///
+    /// Streaming prints the answer as it is generated.
     pub async fn answer_question(
         &self,
         index: &CodebaseIndex,
         question: &str,
-    ) -> Result<String> {
+        earlier: &[Exchange],
+        stream: bool,
+    ) -> Result<Answer> {
         // Use semantic search to find relevant files
         let results = self.semantic_search(index, question, 5).await?;
+        let mut results = self.with_further_chunks(index, question, results).await;
+        results.extend(self.imported_files(index, &results));
 
         let mut context = String::new();
+        let mut citations = Vec::new();
         for result in &results {
-            if let Ok(Some(content)) = index.get_file_content(&result.path) {
+            let number = citations.len() + 1;
+            let about = large_file_summary(index, &result.path)
+                .map(|summary| format!("(File summary: {})\n", summary))
+                .unwrap_or_default();
+            if let (Some(snippet), Some((start, end))) = (&result.snippet, result.line_range) {
+                // Matching chunk from the embedding index
+                let snippet = snippet.chars().take(1500).collect::<String>();
+                context.push_str(&format!("\n[{}] --- {} lines {}-{} (relevance: {:.2}) ---\n{}{}\n",
+                    number, result.path, start, end, result.relevance, about, snippet));
+                citations.push(Citation {
+                    number,
+                    path: result.path.clone(),
+                    start_line: start,
+                    end_line: end,
+                    relevance: result.relevance,
+                });
+            } else if let Ok(Some(content)) = index.get_file_content(&result.path) {
                 // Take first 500 chars of each file
                 let snippet = content.chars().take(500).collect::<String>();
-                context.push_str(&format!("\n--- {} (relevance: {:.2}) ---\n{}\n",
-                    result.path, result.relevance, snippet));
+                context.push_str(&format!("\n[{}] --- {} (relevance: {:.2}) ---\n{}{}\n",
+                    number, result.path, result.relevance, about, snippet));
+                citations.push(Citation {
+                    number,
+                    path: result.path.clone(),
+                    start_line: 1,
+                    end_line: snippet.lines().count().max(1),
+                    relevance: result.relevance,
+                });
             }
         }
 
+        let mut prompt = format!("Based on the following code from the project:\n{}\n", context);
+        if !earlier.is_empty() {
+            prompt.push_str("\nEarlier explanations from chat about this project (reuse them where they still match the code):\n");
+            for exchange in earlier {
+                let answer = exchange.answer.chars().take(MAX_EXCHANGE_CHARS).collect::<String>();
+                prompt.push_str(&format!("\nQ: {}\nA: {}\n", exchange.question, answer));
+            }
+        }
+        if let Some(glossary) = glossary::prompt_section(&index.glossary().unwrap_or_default(), &format!("{}\n{}", question, context)) {
+            prompt.push_str(&format!("\n{}", glossary));
+        }
+        prompt.push_str(&format!("\nAnswer this question: {}\n\nAnswer:", question));
+
+        let confidence = Confidence::assess(question, &citations, &context);
+
+        // With nothing retrieved there is nothing to ground an answer in
+        if citations.is_empty() && earlier.is_empty() {
+            return Ok(Answer { text: NOT_FOUND_ANSWER.to_string(), citations, confidence });
+        }
+
+        if confidence.level == ConfidenceLevel::Low {
+            prompt.insert_str(0, &format!(
+                "Retrieval found only weak matches for this question. Unless the snippets clearly answer it, reply \"{}\"\n\n",
+                NOT_FOUND_ANSWER
+            ));
+        }
+
+        let options = self.prompts.options("ask");
+        let system = self.prompts.system("ask", ASK_SYSTEM_PROMPT);
+        let text = if stream {
+            self.llm.generate_streaming_with(&prompt, Some(&system), &options).await?
+        } else {
+            self.llm.generate_with(&prompt, Some(&system), &options).await?
+        };
+
+        Ok(Answer { text, citations, confidence })
+    }
+
+    /// An earlier answer to the same or a near-identical question, if its sources are unchanged
+    pub async fn find_cached_answer(&self, index: &CodebaseIndex, question: &str) -> Result<Option<CachedAnswer>> {
+        let cached = index.cached_answers(ANSWER_CACHE_SIZE)?;
+        if cached.is_empty() {
+            return Ok(None);
+        }
+
+        let embedding = self.embedding_client.embed(question).await.ok();
+        Ok(best_cached_answer(cached, question, embedding.as_deref())
+            .filter(|answer| index.answer_is_current(answer)))
+    }
+
+    /// Keep an answer so a repeat of the question can be served without generation
+    pub async fn cache_answer(&self, index: &CodebaseIndex, question: &str, answer: &Answer) -> Result<()> {
+        let sources = answer
+            .citations
+            .iter()
+            .filter_map(|c| index.get_file(&c.path).ok().flatten().map(|f| (c.path.clone(), f.hash)))
+            .collect();
+
+        index.store_answer(&CachedAnswer {
+            question: question.to_string(),
+            answer: answer.render(),
+            embedding: self.embedding_client.embed(question).await.ok(),
+            sources,
+            created_at: chrono::Utc::now(),
+        })
+    }
+
+    /// Model used for embeddings, for reporting
+    pub async fn embedding_model(&self) -> String {
+        self.embedding_client.active_model().await
+    }
+
+    /// One or two sentences on what a file is for, to store in the index
+    pub async fn brief_summary(&self, path: &str, content: &str) -> Result<String> {
+        let excerpt: String = content.chars().take(MAX_SUMMARY_INPUT_CHARS).collect();
         let prompt = format!(
-            "Based on the following code from the project:\n{}\n\nAnswer this question: {}\n\nAnswer:",
-            context, question
+            "File: {}\n```\n{}\n```\n\nIn one or two sentences, say what this file is for and name its main components. Reply with the summary only.",
+            path, excerpt
         );
+        let system = "You write short summaries of source files for a code search index.";
 
-        let system = "You are a code expert answering questions about a codebase. Be specific and reference file names and code when relevant.";
+        let summary = self.llm
+            .generate_with(&prompt, Some(&self.prompts.system("file_summary", system)), &self.prompts.options("file_summary"))
+            .await?;
+        Ok(summary.trim().to_string())
+    }
+
+    /// Define a project term from code that defines and uses it, for the glossary
+    pub async fn define_term(&self, term: &str, evidence: &str) -> Result<String> {
+        let prompt = format!(
+            "{}\nFrom this code only, define the term \"{}\" as it is used in this project, in one or two sentences. \
+             Say what it represents in the project's domain, not how it is implemented. Reply with the definition only.",
+            evidence, term
+        );
+        let system = "You write glossary entries for a software project, grounded in its code.";
 
-        self.llm.generate_streaming(&prompt, Some(system)).await
+        let definition = self.llm
+            .generate_with(&prompt, Some(&self.prompts.system("glossary", system)), &self.prompts.options("glossary"))
+            .await?;
+        Ok(definition.trim().to_string())
     }
 
     pub async fn summarize_file(&self, index: &CodebaseIndex, path: &str) -> Result<String> {
-        let content = index.get_file_content(path)?
+        let file = index.get_file(path)?
             .ok_or_else(|| anyhow::anyhow!("File not found in index"))?;
-
-        let prompt = format!(
-            "Summarize the following code file:\n```\n{}\n```\n\nProvide a brief summary covering:\n1. Purpose of the file\n2. Main components/functions\n3. Dependencies\n4. Key logic\n\nSummary:",
-            content
-        );
+        let content = index.get_file_content(path)?.unwrap_or_default();
 
         let system = "You are a code documentation expert. Provide clear, concise summaries that help developers understand code quickly.";
+        let system = self.prompts.system("summarize", system);
+        let options = self.prompts.options("summarize");
 
-        self.llm.generate_streaming(&prompt, Some(system)).await
+        // Files too large for one prompt are summarized a part at a time, split between definitions
+        let starts_definition = |line: &str| CodebaseIndex::definition_at(line.trim(), &file.language).is_some();
+        let parts = MapReduce::default()
+            .summarize_parts(&content, &starts_definition, |part| {
+                let prompt = format!(
+                    "This is {} of {}:\n```\n{}\n```\n\nList the components it defines and what each does, in a few bullet points.",
+                    part.describe(),
+                    file.relative_path,
+                    part.text
+                );
+                let (system, options) = (&system, &options);
+                async move { self.llm.generate_with(&prompt, Some(system), options).await }
+            })
+            .await?;
+
+        let prompt = match parts {
+            None => format!(
+                "Summarize the following code file:\n```\n{}\n```\n\nProvide a brief summary covering:\n1. Purpose of the file\n2. Main components/functions\n3. Dependencies\n4. Key logic\n\nSummary:",
+                content
+            ),
+            Some(parts) => format!(
+                "{} is too large to show whole. These are summaries of its parts, in order:\n\n{}\n\nCombine them into one summary of the file covering:\n1. Purpose of the file\n2. Main components/functions\n3. Dependencies\n4. Key logic\n\nSummary:",
+                file.relative_path, parts
+            ),
+        };
+
+        self.llm
+            .generate_streaming_with(&prompt, Some(&system), &options)
+            .await
     }
 }
 
@@ -195,8 +759,308 @@ pub struct SearchResult {
     pub language: String,
     pub symbols: Vec<String>,
     pub relevance: f32,
-    #[allow(dead_code)]
     pub snippet: Option<String>,
+    /// Lines of the matching chunk or passage
+    pub line_range: Option<(usize, usize)>,
+    /// Lines that matched the query text, terms in `**bold**`, for full-text hits
+    pub excerpt: Option<String>,
+}
+
+/// An answer and the code it was based on
+#[derive(Debug, Clone, Serialize)]
+pub struct Answer {
+    pub text: String,
+    pub citations: Vec<Citation>,
+    pub confidence: Confidence,
+}
+
+impl Answer {
+    /// The answer followed by a numbered source list and the retrieval confidence
+    pub fn render(&self) -> String {
+        if self.citations.is_empty() {
+            return self.text.clone();
+        }
+
+        let sources: Vec<String> = self.citations.iter().map(|c| format!("  {}", c)).collect();
+        format!(
+            "{}\n\nSources:\n{}\n\n{}",
+            self.text.trim_end(),
+            sources.join("\n"),
+            self.confidence
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfidenceLevel {
+    High,
+    Medium,
+    Low,
+}
+
+/// How well the retrieved code matches a question
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Confidence {
+    pub level: ConfidenceLevel,
+    /// Relevance of the best retrieved snippet
+    pub top_score: f32,
+    /// Share of the question's terms that appear in the retrieved code
+    pub coverage: f32,
+}
+
+impl Confidence {
+    pub fn assess(question: &str, citations: &[Citation], context: &str) -> Self {
+        let top_score = citations.iter().map(|c| c.relevance).fold(0.0, f32::max);
+
+        let terms = question_terms(question);
+        let context = context.to_lowercase();
+        let coverage = if terms.is_empty() {
+            0.0
+        } else {
+            terms.iter().filter(|t| context.contains(t.as_str())).count() as f32 / terms.len() as f32
+        };
+
+        let level = if citations.is_empty() || top_score < LOW_CONFIDENCE_SCORE || coverage < LOW_CONFIDENCE_COVERAGE {
+            ConfidenceLevel::Low
+        } else if top_score >= HIGH_CONFIDENCE_SCORE && coverage >= HIGH_CONFIDENCE_COVERAGE {
+            ConfidenceLevel::High
+        } else {
+            ConfidenceLevel::Medium
+        };
+
+        Self { level, top_score, coverage }
+    }
+}
+
+impl std::fmt::Display for Confidence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let level = match self.level {
+            ConfidenceLevel::High => "high",
+            ConfidenceLevel::Medium => "medium",
+            ConfidenceLevel::Low => "low",
+        };
+        write!(
+            f,
+            "Confidence: {} (best match {:.0}%, {:.0}% of question terms found)",
+            level,
+            self.top_score * 100.0,
+            self.coverage * 100.0
+        )
+    }
+}
+
+/// Lowercase content words of a question
+fn question_terms(question: &str) -> Vec<String> {
+    let mut terms: Vec<String> = question
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .map(|w| w.to_lowercase())
+        .filter(|w| w.len() > 2 && !STOPWORDS.contains(&w.as_str()) && !QUESTION_WORDS.contains(&w.as_str()))
+        .collect();
+    terms.sort();
+    terms.dedup();
+    terms
+}
+
+/// A snippet given to the model as context, numbered as it appeared in the prompt
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Citation {
+    pub number: usize,
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub relevance: f32,
+}
+
+impl std::fmt::Display for Citation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}] {}:{}-{} ({:.0}%)",
+            self.number, self.path, self.start_line, self.end_line, self.relevance * 100.0
+        )
+    }
+}
+
+/// The cached answer whose question matches `question` best
+///
+/// Wording that only differs in case, spacing or punctuation always matches;
+/// otherwise the question embeddings must be nearly identical.
+fn best_cached_answer(cached: Vec<CachedAnswer>, question: &str, embedding: Option<&[f32]>) -> Option<CachedAnswer> {
+    let normalized = normalize_question(question);
+    let mut best: Option<(CachedAnswer, f32)> = None;
+
+    for candidate in cached {
+        let similarity = if normalize_question(&candidate.question) == normalized {
+            1.0
+        } else {
+            match (embedding, candidate.embedding.as_deref()) {
+                (Some(a), Some(b)) if a.len() == b.len() => cosine_similarity(a, b),
+                _ => 0.0,
+            }
+        };
+
+        if similarity >= ANSWER_CACHE_SIMILARITY && best.as_ref().map(|(_, s)| similarity > *s).unwrap_or(true) {
+            best = Some((candidate, similarity));
+        }
+    }
+
+    best.map(|(answer, _)| answer)
+}
+
+fn normalize_question(question: &str) -> String {
+    question
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Split `language:`, `path:` and `symbol:` filters from a search query, returning the
+/// filters and the remaining text; a filter takes several values separated by commas
+///
+/// `language:rust path:src/agents symbol:Orchestrator retry logic` searches for
+/// "retry logic" in Rust files under `src/agents` that define `Orchestrator`.
+pub fn parse_filters(query: &str) -> (SearchFilters, String) {
+    let mut filters = SearchFilters::default();
+    let mut words = Vec::new();
+
+    for word in query.split_whitespace() {
+        let filter = match word.split_once(':') {
+            Some(("language" | "lang", values)) => Some((&mut filters.languages, values)),
+            Some(("path", values)) => Some((&mut filters.paths, values)),
+            Some(("symbol" | "sym", values)) => Some((&mut filters.symbols, values)),
+            _ => None,
+        };
+        match filter {
+            Some((list, values)) => list.extend(values.split(',').filter(|v| !v.is_empty()).map(String::from)),
+            None => words.push(word),
+        }
+    }
+
+    (filters, words.join(" "))
+}
+
+/// Terms from a model's expansion reply, plus snake_case and camelCase forms of phrases
+fn parse_expansions(response: &str, query: &str) -> Vec<String> {
+    let query = query.to_lowercase();
+    let mut terms: Vec<String> = Vec::new();
+
+    let candidates = response
+        .lines()
+        .flat_map(|line| line.split(','))
+        .map(|term| {
+            term.trim()
+                .trim_start_matches(|c: char| c.is_ascii_digit() || matches!(c, '-' | '*' | '.' | ')'))
+                .trim_matches(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '`'))
+                .to_string()
+        })
+        .filter(|term| !term.is_empty() && term.len() <= 40 && !term.ends_with(':'))
+        .filter(|term| term.to_lowercase() != query);
+
+    for term in candidates.take(MAX_EXPANSIONS) {
+        let words: Vec<&str> = term.split_whitespace().collect();
+        let mut forms = vec![term.clone()];
+        if words.len() > 1 {
+            forms.push(words.iter().map(|w| w.to_lowercase()).collect::<Vec<_>>().join("_"));
+            forms.push(
+                words.iter()
+                    .enumerate()
+                    .map(|(i, w)| if i == 0 { w.to_lowercase() } else { capitalize(w) })
+                    .collect(),
+            );
+        }
+
+        for form in forms {
+            if !terms.contains(&form) {
+                terms.push(form);
+            }
+        }
+    }
+
+    terms
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars.flat_map(|c| c.to_lowercase())).collect(),
+        None => String::new(),
+    }
+}
+
+/// The stored summary of a file too large for its snippet to show much of it
+fn large_file_summary(index: &CodebaseIndex, path: &str) -> Option<String> {
+    let file = index.get_file(path).ok()??;
+    if file.size < SUMMARY_CONTEXT_BYTES {
+        return None;
+    }
+    file.summary
+}
+
+/// Definitions a file uses from the files it imports, with their first lines
+///
+/// Only definitions whose names appear in `content` are kept, so an explanation
+/// of glue code can say what the called functions do instead of guessing.
+/// None when the file imports nothing indexed or uses none of it.
+pub fn dependency_context(index: &CodebaseIndex, path: &str, content: &str) -> Option<String> {
+    let used: HashSet<&str> = content
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    let mut sections = Vec::new();
+    let mut shown: HashSet<String> = HashSet::new();
+
+    for dependency in index.dependencies(path).ok()?.into_iter().take(MAX_DEPENDENCY_FILES) {
+        let Ok(Some(dep_content)) = index.get_file_content(&dependency) else { continue };
+        let mut section = String::new();
+
+        for symbol in index.file_symbols(&dependency).unwrap_or_default() {
+            if shown.len() >= MAX_DEPENDENCY_SYMBOLS {
+                break;
+            }
+            if !used.contains(symbol.name.as_str()) || !shown.insert(symbol.name.clone()) {
+                continue;
+            }
+
+            let end = symbol.end_line.min(symbol.start_line + DEPENDENCY_SNIPPET_LINES - 1);
+            section.push_str(&line_span(&dep_content, symbol.start_line, end));
+            section.push('\n');
+            if end < symbol.end_line {
+                section.push_str("    ...\n");
+            }
+        }
+
+        if !section.is_empty() {
+            sections.push(format!("{}:\n```\n{}```", dependency, section));
+        }
+    }
+
+    if sections.is_empty() {
+        None
+    } else {
+        Some(format!(
+            "Definitions it uses from the files it imports:\n\n{}",
+            sections.join("\n\n")
+        ))
+    }
+}
+
+/// Join line ranges that overlap or touch, keeping the order and relevance of the first
+fn merge_adjacent(ranges: &mut Vec<((usize, usize), f32)>) {
+    let mut i = 0;
+    while i < ranges.len() {
+        let ((start, end), _) = ranges[i];
+        match (i + 1..ranges.len()).find(|&j| ranges[j].0 .0 <= end + 1 && ranges[j].0 .1 + 1 >= start) {
+            Some(j) => {
+                let ((other_start, other_end), _) = ranges.remove(j);
+                ranges[i].0 = (start.min(other_start), end.max(other_end));
+            }
+            None => i += 1,
+        }
+    }
 }
 
 impl std::fmt::Display for SearchResult {
@@ -208,3 +1072,140 @@ impl std::fmt::Display for SearchResult {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters_are_split_from_the_query() {
+        let (filters, text) = parse_filters("language:rust path:src/agents,src/rag.rs symbol:Orchestrator retry logic");
+        assert_eq!(text, "retry logic");
+        assert_eq!(filters.languages, vec!["rust"]);
+        assert_eq!(filters.paths, vec!["src/agents", "src/rag.rs"]);
+        assert_eq!(filters.symbols, vec!["Orchestrator"]);
+
+        let (filters, text) = parse_filters("error: timeout in http://host");
+        assert!(filters.is_empty());
+        assert_eq!(text, "error: timeout in http://host");
+    }
+
+    #[test]
+    fn test_dependency_context_shows_used_definitions() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("src")).unwrap();
+        let body: String = (0..12).map(|i| format!("    let x{} = {};\n", i, i)).collect();
+        std::fs::write(
+            root.path().join("src/util.rs"),
+            format!("pub fn parse_config(path: &str) -> u32 {{\n{}    0\n}}\n\npub fn unused() {{}}\n", body),
+        ).unwrap();
+        let main = "use crate::util::parse_config;\n\nfn main() {\n    parse_config(\"a\");\n}\n";
+        std::fs::write(root.path().join("src/main.rs"), main).unwrap();
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+
+        let context = dependency_context(&index, "src/main.rs", main).unwrap();
+        assert!(context.contains("src/util.rs:"));
+        assert!(context.contains("pub fn parse_config(path: &str) -> u32 {"));
+        assert!(context.contains("    ...\n"));
+        assert!(!context.contains("x7"));
+        assert!(!context.contains("unused"));
+
+        assert!(dependency_context(&index, "src/util.rs", "").is_none());
+    }
+
+    #[test]
+    fn test_answer_lists_sources() {
+        let citations = vec![Citation {
+            number: 1,
+            path: "src/storage/codebase.rs".to_string(),
+            start_line: 40,
+            end_line: 92,
+            relevance: 0.87,
+        }];
+        let answer = Answer {
+            text: "The index lives in SQLite [1].\n".to_string(),
+            confidence: Confidence::assess("How is the index stored?", &citations, "let index = Connection::open(db)?; // stored in codebase.db"),
+            citations,
+        };
+
+        assert_eq!(
+            answer.render(),
+            "The index lives in SQLite [1].\n\nSources:\n  [1] src/storage/codebase.rs:40-92 (87%)\n\nConfidence: high (best match 87%, 100% of question terms found)"
+        );
+    }
+
+    #[test]
+    fn test_adjacent_chunks_are_cited_as_one_range() {
+        let mut ranges = vec![((1, 6), 0.43), ((30, 40), 0.3), ((7, 19), 0.4), ((20, 23), 0.28)];
+        merge_adjacent(&mut ranges);
+        assert_eq!(ranges, vec![((1, 23), 0.43), ((30, 40), 0.3)]);
+    }
+
+    #[test]
+    fn test_weak_retrieval_is_low_confidence() {
+        let citation = |relevance: f32| Citation {
+            number: 1,
+            path: "src/sync.rs".to_string(),
+            start_line: 1,
+            end_line: 20,
+            relevance,
+        };
+        let context = "fn sync_peers(peers: &[Peer]) { for peer in peers { peer.push(); } }";
+
+        let unrelated = Confidence::assess("How are OAuth tokens refreshed?", &[citation(0.8)], context);
+        assert_eq!(unrelated.level, ConfidenceLevel::Low);
+        assert_eq!(unrelated.coverage, 0.0);
+
+        let weak = Confidence::assess("How are peers synced?", &[citation(0.2)], context);
+        assert_eq!(weak.level, ConfidenceLevel::Low);
+
+        let partial = Confidence::assess("How are peers synced?", &[citation(0.5)], context);
+        assert_eq!(partial.level, ConfidenceLevel::Medium);
+
+        assert_eq!(Confidence::assess("How are peers synced?", &[], context).level, ConfidenceLevel::Low);
+    }
+
+    #[test]
+    fn test_near_identical_question_reuses_answer() {
+        let cached = |question: &str, embedding: Vec<f32>| CachedAnswer {
+            question: question.to_string(),
+            answer: format!("answer to {}", question),
+            embedding: Some(embedding),
+            sources: Vec::new(),
+            created_at: chrono::Utc::now(),
+        };
+        let answers = vec![
+            cached("How is the index stored?", vec![1.0, 0.0]),
+            cached("What does the daemon do?", vec![0.0, 1.0]),
+        ];
+
+        let exact = best_cached_answer(answers.clone(), "how is the index  stored", None).unwrap();
+        assert_eq!(exact.question, "How is the index stored?");
+
+        let close = best_cached_answer(answers.clone(), "where's the index kept", Some(&[0.99, 0.05])).unwrap();
+        assert_eq!(close.question, "How is the index stored?");
+
+        assert!(best_cached_answer(answers, "how are files chunked", Some(&[0.7, 0.7])).is_none());
+    }
+
+    #[test]
+    fn test_parse_expansions() {
+        let response = "1. backoff\n- with_retries, RetryPolicy\n* \"exponential backoff\"\n\nretry http requests";
+        let terms = parse_expansions(response, "retry http requests");
+
+        assert_eq!(
+            terms,
+            vec![
+                "backoff",
+                "with_retries",
+                "RetryPolicy",
+                "exponential backoff",
+                "exponential_backoff",
+                "exponentialBackoff",
+            ]
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//! Security audit of indexed code: each file is checked by the local pattern rules,
+//! and asked about by the model for each vulnerability class its code could have
+
+use std::time::Duration;
+
+use crate::review::{self, Finding, ReportFormat, Severity};
+
+/// Lines of a file the model is shown at once
+pub const AUDIT_CHUNK_LINES: usize = 200;
+
+/// A vulnerability class the model is asked about
+#[derive(Debug)]
+pub struct AuditCheck {
+    /// Rule its findings are reported under
+    pub rule_id: &'static str,
+    pub name: &'static str,
+    /// What to look for, as told to the model
+    pub focus: &'static str,
+    /// Lowercase text a file must contain for the check to be worth a prompt
+    pub triggers: &'static [&'static str],
+}
+
+/// Vulnerability classes, most serious first
+pub const CHECKS: &[AuditCheck] = &[
+    AuditCheck {
+        rule_id: "sovereign/injection",
+        name: "injection",
+        focus: "SQL, shell command or markup injection: untrusted input built into a query, a command line or HTML \
+                without bound parameters, argument lists or escaping",
+        triggers: &["select ", "insert ", "execute", "query", "command", "subprocess", "system(", "exec(", "popen", "innerhtml"],
+    },
+    AuditCheck {
+        rule_id: "sovereign/unsafe-deserialization",
+        name: "unsafe deserialization",
+        focus: "untrusted data turned into objects or code: pickle, yaml.load, Marshal, Java serialization, \
+                unserialize, or eval of received data",
+        triggers: &["pickle", "yaml.load", "marshal", "unserialize", "objectinputstream", "readobject", "eval("],
+    },
+    AuditCheck {
+        rule_id: "sovereign/path-traversal",
+        name: "path traversal",
+        focus: "file paths built from untrusted input without checking that they stay inside the intended \
+                directory (`..`, absolute paths, symlinks)",
+        triggers: &["open(", "read_to_string", "readfile", "sendfile", "join(", "unlink", "remove_file"],
+    },
+    AuditCheck {
+        rule_id: "sovereign/hardcoded-secret",
+        name: "secrets",
+        focus: "credentials, API keys, tokens or private keys written into the code or its default configuration",
+        triggers: &["key", "token", "secret", "password", "passwd", "credential"],
+    },
+];
+
+/// Pattern rules that point at security problems, run on every line
+const SECURITY_RULES: &[&str] = &["sovereign/hardcoded-secret", "sovereign/sql-string-building", "sovereign/dynamic-eval"];
+
+/// Settings for `sovereign audit`
+#[derive(Debug, Clone)]
+pub struct AuditOptions {
+    /// Only audit files in this language
+    pub language: Option<String>,
+    pub format: ReportFormat,
+    /// Fail the run if any finding is at least this severe (`None` never fails)
+    pub fail_on: Option<Severity>,
+    /// Total time budget for model calls
+    pub timeout: Duration,
+    /// Most files sent to the model
+    pub max_files: usize,
+    /// Only run the local pattern rules
+    pub local_only: bool,
+}
+
+impl AuditOptions {
+    /// Whether the findings should fail the run
+    pub fn should_fail(&self, findings: &[Finding]) -> bool {
+        self.fail_on.is_some_and(|threshold| findings.iter().any(|f| f.severity >= threshold))
+    }
+}
+
+/// The checks worth asking the model about for a file with `content`
+pub fn checks_for(content: &str) -> Vec<&'static AuditCheck> {
+    let lower = content.to_lowercase();
+    CHECKS.iter().filter(|check| check.triggers.iter().any(|t| lower.contains(t))).collect()
+}
+
+/// Findings of the security pattern rules in a whole file
+pub fn local_findings(file: &str, content: &str) -> Vec<Finding> {
+    content
+        .lines()
+        .enumerate()
+        .flat_map(|(i, line)| review::check_line(file, i as u32 + 1, line))
+        .filter(|f| SECURITY_RULES.contains(&f.rule_id.as_str()))
+        .collect()
+}
+
+/// `content` in pieces of [`AUDIT_CHUNK_LINES`], each line prefixed with its number;
+/// returned with the number of each piece's first line
+pub fn numbered_chunks(content: &str) -> Vec<(u32, String)> {
+    let lines: Vec<&str> = content.lines().collect();
+    lines
+        .chunks(AUDIT_CHUNK_LINES)
+        .enumerate()
+        .map(|(n, chunk)| {
+            let first = n * AUDIT_CHUNK_LINES + 1;
+            let numbered: String = chunk
+                .iter()
+                .enumerate()
+                .map(|(i, line)| format!("{:>6} {}\n", first + i, line))
+                .collect();
+            (first as u32, numbered)
+        })
+        .collect()
+}
+
+/// Parse the JSON array of findings the model gave for lines `first..=last` of `file`
+///
+/// Lines outside the piece are moved to its first line.
+pub fn parse_findings(response: &str, file: &str, check: &AuditCheck, first: u32, last: u32) -> Vec<Finding> {
+    #[derive(serde::Deserialize)]
+    struct RawFinding {
+        line: Option<u32>,
+        severity: Option<String>,
+        message: String,
+    }
+
+    let (start, end) = match (response.find('['), response.rfind(']')) {
+        (Some(start), Some(end)) if start < end => (start, end),
+        _ => return Vec::new(),
+    };
+    let raw: Vec<RawFinding> = serde_json::from_str(&response[start..=end]).unwrap_or_default();
+
+    raw.into_iter()
+        .filter(|f| !f.message.trim().is_empty())
+        .map(|f| Finding {
+            rule_id: check.rule_id.to_string(),
+            severity: f.severity.as_deref().and_then(Severity::from_str).unwrap_or(Severity::Warning),
+            file: file.to_string(),
+            line: f.line.filter(|l| (first..=last).contains(l)).unwrap_or(first),
+            message: f.message.trim().to_string(),
+        })
+        .collect()
+}
+
+/// Most serious first: by severity, then vulnerability class, then place; a rule
+/// reported twice for one line is kept once
+pub fn rank(findings: &mut Vec<Finding>) {
+    let class = |f: &Finding| CHECKS.iter().position(|c| c.rule_id == f.rule_id).unwrap_or(CHECKS.len());
+    findings.sort_by(|a, b| {
+        b.severity
+            .cmp(&a.severity)
+            .then(class(a).cmp(&class(b)))
+            .then(a.file.cmp(&b.file))
+            .then(a.line.cmp(&b.line))
+    });
+    let mut seen = std::collections::HashSet::new();
+    findings.retain(|f| seen.insert((f.rule_id.clone(), f.file.clone(), f.line)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_findings_are_ranked_by_severity_then_class() {
+        let content = "fn load(name: &str) -> String {\n    let api_key = \"sk-9f8e7d6c5b4a\";\n    \
+                       std::fs::read_to_string(format!(\"data/{}\", name)).unwrap()\n}\n";
+        let names: Vec<&str> = checks_for(content).iter().map(|c| c.name).collect();
+        assert_eq!(names, vec!["path traversal", "secrets"]);
+
+        let mut findings = local_findings("src/load.rs", content);
+        assert_eq!(findings.len(), 1, "only security rules run: {:?}", findings);
+        assert_eq!(findings[0].line, 2);
+
+        let response = r#"[{"line": 3, "severity": "error", "message": "name may contain ../"},
+                           {"line": 40, "severity": "warning", "message": "outside the piece"}]"#;
+        findings.extend(parse_findings(response, "src/load.rs", &CHECKS[2], 1, 4));
+        findings.extend(parse_findings(response, "src/load.rs", &CHECKS[2], 1, 4));
+        rank(&mut findings);
+
+        let ranked: Vec<(&str, u32, Severity)> = findings.iter().map(|f| (f.rule_id.as_str(), f.line, f.severity)).collect();
+        assert_eq!(
+            ranked,
+            vec![
+                ("sovereign/path-traversal", 3, Severity::Error),
+                ("sovereign/hardcoded-secret", 2, Severity::Error),
+                ("sovereign/path-traversal", 1, Severity::Warning),
+            ]
+        );
+    }
+}
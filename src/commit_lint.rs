@@ -0,0 +1,176 @@
+//! Conventional commit rules for commit messages: `type(scope): subject`, a blank
+//! line, then a wrapped body
+
+use serde::{Deserialize, Serialize};
+
+/// Commit types a subject may start with
+pub const TYPES: &[&str] = &["feat", "fix", "docs", "style", "refactor", "perf", "test", "build", "ci", "chore", "revert"];
+
+/// Longest subject line, type and scope included
+pub const MAX_SUBJECT_CHARS: usize = 72;
+
+/// Longest body line; longer lines should be wrapped
+pub const MAX_BODY_LINE_CHARS: usize = 72;
+
+/// Rule for subjects the model judges too vague to tell what changed
+pub const VAGUE_RULE: &str = "subject-vague";
+
+pub const VAGUE_SYSTEM_PROMPT: &str = r#"You review commit subjects. A subject is vague when a reader could not tell from it what changed,
+e.g. "fix: bug", "chore: updates", "feat: improvements", "refactor: cleanup", "fix: address review comments".
+Reply with JSON only: {"vague": [{"commit": <number>, "reason": "<what the subject leaves out>"}]}
+List only vague subjects; reply {"vague": []} if none are."#;
+
+/// Where `git commit --verbose` starts the diff it shows below the message
+const SCISSORS: &str = "# ------------------------ >8 ------------------------";
+
+/// One broken rule
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Problem {
+    /// e.g. `type-enum` or `subject-length`
+    pub rule: &'static str,
+    /// Line of the message, from 1
+    pub line: usize,
+    pub message: String,
+}
+
+/// The message git will record from an edited commit message file: comment lines
+/// and the diff of `--verbose` left out, trailing blank lines trimmed
+pub fn message_text(raw: &str) -> String {
+    let kept: Vec<&str> = raw
+        .lines()
+        .take_while(|line| *line != SCISSORS)
+        .filter(|line| !line.starts_with('#'))
+        .map(str::trim_end)
+        .collect();
+    kept.join("\n").trim().to_string()
+}
+
+/// The rules `message` breaks; merges, reverts made by git and fixups are not checked
+pub fn check(message: &str) -> Vec<Problem> {
+    let lines: Vec<&str> = message.lines().collect();
+    let subject = lines.first().copied().unwrap_or_default();
+    if ["Merge ", "Revert \"", "fixup! ", "squash! ", "amend! "].iter().any(|p| subject.starts_with(p)) {
+        return Vec::new();
+    }
+
+    let mut problems = Vec::new();
+    let mut problem = |rule: &'static str, line: usize, message: String| problems.push(Problem { rule, line, message });
+
+    match split_header(subject) {
+        None => problem("header-format", 1, "Subject should look like `type(scope): description`".to_string()),
+        Some((kind, _, description)) => {
+            if !TYPES.contains(&kind) {
+                problem("type-enum", 1, format!("`{}` is not a commit type; use one of {}", kind, TYPES.join(", ")));
+            }
+            if description.trim().is_empty() {
+                problem("subject-empty", 1, "Subject has no description after the type".to_string());
+            } else if description.trim_end().ends_with('.') {
+                problem("subject-full-stop", 1, "Subject should not end with a full stop".to_string());
+            }
+        }
+    }
+    let length = subject.chars().count();
+    if length > MAX_SUBJECT_CHARS {
+        problem("subject-length", 1, format!("Subject is {} characters; keep it to {}", length, MAX_SUBJECT_CHARS));
+    }
+
+    if lines.get(1).is_some_and(|line| !line.trim().is_empty()) {
+        problem("body-leading-blank", 2, "Leave a blank line between the subject and the body".to_string());
+    }
+    for (i, line) in lines.iter().enumerate().skip(1) {
+        // Links and trailers cannot be wrapped
+        let unbreakable = line.contains("://") || !line.contains(' ');
+        let length = line.chars().count();
+        if length > MAX_BODY_LINE_CHARS && !unbreakable {
+            problem("body-line-length", i + 1, format!("Line is {} characters; wrap the body at {}", length, MAX_BODY_LINE_CHARS));
+        }
+    }
+    problems
+}
+
+/// A commit and the rules its message breaks
+#[derive(Debug, Clone, Serialize)]
+pub struct CommitReport {
+    pub hash: String,
+    pub subject: String,
+    pub problems: Vec<Problem>,
+}
+
+/// The prompt asking which of `subjects` are vague, numbered from 1
+pub fn vague_prompt(subjects: &[&str]) -> String {
+    let mut prompt = String::from("Commit subjects:\n");
+    for (i, subject) in subjects.iter().enumerate() {
+        prompt.push_str(&format!("{}. {}\n", i + 1, subject));
+    }
+    prompt
+}
+
+/// The subjects the model called vague, as indexes into the `count` subjects asked
+/// about (from 0) with its reason
+pub fn parse_vague(response: &str, count: usize) -> Vec<(usize, String)> {
+    #[derive(Deserialize)]
+    struct Judgement {
+        commit: usize,
+        #[serde(default)]
+        reason: String,
+    }
+    #[derive(Deserialize, Default)]
+    struct Judgements {
+        #[serde(default)]
+        vague: Vec<Judgement>,
+    }
+
+    let (start, end) = match (response.find('{'), response.rfind('}')) {
+        (Some(start), Some(end)) if start < end => (start, end),
+        _ => return Vec::new(),
+    };
+    let judgements: Judgements = serde_json::from_str(&response[start..=end]).unwrap_or_default();
+    judgements
+        .vague
+        .into_iter()
+        .filter(|j| (1..=count).contains(&j.commit))
+        .map(|j| (j.commit - 1, j.reason.trim().to_string()))
+        .collect()
+}
+
+/// `feat(parser)!: description` as type, scope and description
+fn split_header(subject: &str) -> Option<(&str, Option<&str>, &str)> {
+    let (head, description) = subject.split_once(':')?;
+    if !description.is_empty() && !description.starts_with(' ') {
+        return None;
+    }
+    let head = head.strip_suffix('!').unwrap_or(head);
+    let (kind, scope) = match head.split_once('(') {
+        Some((kind, rest)) => (kind, Some(rest.strip_suffix(')')?)),
+        None => (head, None),
+    };
+    if kind.is_empty() || !kind.chars().all(|c| c.is_ascii_lowercase()) || scope.is_some_and(|s| s.trim().is_empty()) {
+        return None;
+    }
+    Some((kind, scope, description.trim_start()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_conventional_commit_rules() {
+        let rules = |message: &str| -> Vec<&'static str> { check(message).into_iter().map(|p| p.rule).collect() };
+
+        assert!(rules("feat(parser)!: accept trailing commas\n\nOlder files have them.").is_empty());
+        assert_eq!(rules("Fixed the parser"), vec!["header-format"]);
+        assert_eq!(rules("feature: add x."), vec!["type-enum", "subject-full-stop"]);
+        assert_eq!(rules("fix: a\nsecond line"), vec!["body-leading-blank"]);
+        assert_eq!(rules(&format!("fix: {}", "x".repeat(80))), vec!["subject-length"]);
+        assert_eq!(rules(&format!("fix: a\n\n{}", "word ".repeat(20))), vec!["body-line-length"]);
+        assert!(rules("Merge branch 'main' into topic").is_empty());
+
+        let response = r#"{"vague": [{"commit": 2, "reason": "does not say which bug"}, {"commit": 9, "reason": "none"}]}"#;
+        assert_eq!(parse_vague(response, 3), vec![(1, "does not say which bug".to_string())]);
+        assert_eq!(vague_prompt(&["feat: add x", "fix: bug"]), "Commit subjects:\n1. feat: add x\n2. fix: bug\n");
+
+        let raw = "fix: a\n\n# Please enter the commit message\nBody\n# ------------------------ >8 ------------------------\ndiff --git a/x b/x\n";
+        assert_eq!(message_text(raw), "fix: a\n\nBody");
+    }
+}
@@ -0,0 +1,283 @@
+//! Splitting a large staged change into several commits: hunks are grouped by
+//! where they are and which names they touch, and each group becomes a patch
+//! that is staged and committed on its own
+
+use std::collections::HashSet;
+
+/// Most commits one split proposes; smaller groups are merged into their closest
+pub const MAX_GROUPS: usize = 6;
+
+/// Similarity at which two pieces of the change belong in one commit
+const JOIN_THRESHOLD: f64 = 0.5;
+
+/// Shortest identifier counted when comparing what pieces touch
+const MIN_IDENTIFIER_CHARS: usize = 4;
+
+/// Words too common in code to say two pieces are related
+const COMMON_WORDS: &[&str] = &[
+    "self", "Self", "return", "String", "usize", "None", "Some", "true", "false", "const", "impl", "struct",
+    "async", "await", "this", "function", "import", "from", "with", "Result", "string", "void", "null",
+];
+
+/// The smallest piece of a staged change that can be committed on its own: one
+/// hunk of a changed file, or all of a file that is added, deleted, renamed or binary
+#[derive(Debug, Clone, PartialEq)]
+pub struct Piece {
+    pub path: String,
+    /// `diff --git` line down to `+++`, shared by the file's pieces
+    header: String,
+    /// The `@@` hunk, or the whole section after the header for a file kept whole
+    body: String,
+    identifiers: HashSet<String>,
+}
+
+impl Piece {
+    fn names(&self) -> HashSet<&str> {
+        self.identifiers.iter().map(String::as_str).collect()
+    }
+}
+
+/// Pieces that go into one commit, in the order they were staged
+#[derive(Debug, Clone, Default)]
+pub struct CommitGroup {
+    pub pieces: Vec<Piece>,
+}
+
+impl CommitGroup {
+    /// The group as a patch `git apply --cached` takes
+    pub fn patch(&self) -> String {
+        let mut patch = String::new();
+        let mut last_header: Option<&str> = None;
+        for piece in &self.pieces {
+            if last_header != Some(piece.header.as_str()) {
+                patch.push_str(&piece.header);
+                last_header = Some(&piece.header);
+            }
+            patch.push_str(&piece.body);
+        }
+        patch
+    }
+
+    /// `src/cache.rs (2 hunks)` for each file, in order
+    pub fn describe_files(&self) -> Vec<String> {
+        let mut files: Vec<(&str, usize)> = Vec::new();
+        for piece in &self.pieces {
+            match files.iter_mut().find(|(path, _)| *path == piece.path) {
+                Some((_, count)) => *count += 1,
+                None => files.push((&piece.path, 1)),
+            }
+        }
+        files
+            .into_iter()
+            .map(|(path, count)| match count {
+                1 => path.to_string(),
+                n => format!("{} ({} hunks)", path, n),
+            })
+            .collect()
+    }
+
+    fn identifiers(&self) -> HashSet<&str> {
+        self.pieces.iter().flat_map(Piece::names).collect()
+    }
+}
+
+/// The pieces of a `git diff --cached` output, in order
+pub fn pieces(diff: &str) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    let mut sections: Vec<String> = Vec::new();
+    for line in diff.split_inclusive('\n') {
+        if line.starts_with("diff --git ") || sections.is_empty() {
+            sections.push(String::new());
+        }
+        if let Some(section) = sections.last_mut() {
+            section.push_str(line);
+        }
+    }
+
+    for section in sections.iter().filter(|s| s.starts_with("diff --git ")) {
+        let Some(path) = section_path(section) else {
+            continue;
+        };
+        let (header, rest) = match section.find("\n@@ ") {
+            Some(at) => section.split_at(at + 1),
+            None => (section.as_str(), ""),
+        };
+        let whole = ["new file mode", "deleted file mode", "rename from", "Binary files", "GIT binary patch"]
+            .iter()
+            .any(|marker| header.contains(marker));
+        if whole || rest.is_empty() {
+            let (header, body) = match header.find("\n--- ") {
+                Some(at) => section.split_at(at + 1),
+                None => (section.as_str(), ""),
+            };
+            pieces.push(piece(&path, header, body));
+            continue;
+        }
+
+        let mut hunk = String::new();
+        for line in rest.split_inclusive('\n') {
+            if line.starts_with("@@ ") && !hunk.is_empty() {
+                pieces.push(piece(&path, header, &hunk));
+                hunk.clear();
+            }
+            hunk.push_str(line);
+        }
+        if !hunk.is_empty() {
+            pieces.push(piece(&path, header, &hunk));
+        }
+    }
+    pieces
+}
+
+/// Group `pieces` into at most [`MAX_GROUPS`] commits: pieces join when they touch
+/// the same names, helped by being in the same file or directory
+pub fn group(pieces: Vec<Piece>) -> Vec<CommitGroup> {
+    let mut groups: Vec<CommitGroup> = Vec::new();
+    for piece in pieces {
+        let closest = groups
+            .iter()
+            .enumerate()
+            .map(|(i, g)| (i, g.pieces.iter().map(|p| similarity(p, &piece)).fold(0.0, f64::max)))
+            .filter(|(_, score)| *score >= JOIN_THRESHOLD)
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        match closest {
+            Some((i, _)) => groups[i].pieces.push(piece),
+            None => groups.push(CommitGroup { pieces: vec![piece] }),
+        }
+    }
+
+    while groups.len() > MAX_GROUPS {
+        let smallest = (0..groups.len()).min_by_key(|&i| groups[i].pieces.len()).unwrap_or(0);
+        let group = groups.remove(smallest);
+        let names = group.identifiers();
+        let closest = (0..groups.len())
+            .max_by(|&a, &b| overlap(&names, &groups[a].identifiers()).total_cmp(&overlap(&names, &groups[b].identifiers())))
+            .unwrap_or(0);
+        groups[closest].pieces.extend(group.pieces);
+    }
+    groups
+}
+
+/// How alike two pieces are, from 0: shared names, plus a little for a shared file,
+/// a file named after the other's (`cache_test.rs` and `cache.rs`) or a shared directory
+fn similarity(a: &Piece, b: &Piece) -> f64 {
+    let mut score = overlap(&a.names(), &b.names());
+    let (stem_a, stem_b) = (stem(&a.path), stem(&b.path));
+    if a.path == b.path || (!stem_a.is_empty() && !stem_b.is_empty() && (stem_a.contains(stem_b) || stem_b.contains(stem_a))) {
+        score += 0.3;
+    } else if area(&a.path) == area(&b.path) {
+        score += 0.15;
+    }
+    score
+}
+
+/// Share of the smaller set of names found in the other
+fn overlap(a: &HashSet<&str>, b: &HashSet<&str>) -> f64 {
+    let smaller = a.len().min(b.len());
+    if smaller == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / smaller as f64
+}
+
+/// A file name without directory or extension, e.g. `cache_test`
+fn stem(path: &str) -> &str {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.split('.').next().unwrap_or(name)
+}
+
+/// The directory a path is in, e.g. `src/agents`
+fn area(path: &str) -> &str {
+    path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("")
+}
+
+fn piece(path: &str, header: &str, body: &str) -> Piece {
+    // Names on the lines the piece changes
+    let mut identifiers: HashSet<String> = body
+        .lines()
+        .filter(|l| (l.starts_with('+') && !l.starts_with("+++")) || (l.starts_with('-') && !l.starts_with("---")))
+        .flat_map(|l| l[1..].split(|c: char| !(c.is_alphanumeric() || c == '_')))
+        .filter(|w| w.len() >= MIN_IDENTIFIER_CHARS && !COMMON_WORDS.contains(w) && !w.chars().all(|c| c.is_ascii_digit()))
+        .map(str::to_string)
+        .collect();
+    // A file kept whole is known by its name as well
+    if !body.starts_with("@@") && !stem(path).is_empty() {
+        identifiers.insert(stem(path).to_string());
+    }
+    Piece {
+        path: path.to_string(),
+        header: header.to_string(),
+        body: body.to_string(),
+        identifiers,
+    }
+}
+
+/// The new path of a `diff --git a/old b/new` section
+fn section_path(section: &str) -> Option<String> {
+    section
+        .lines()
+        .find_map(|l| l.strip_prefix("+++ b/").or_else(|| l.strip_prefix("rename to ")))
+        .or_else(|| section.lines().find_map(|l| l.strip_prefix("--- a/")))
+        .or_else(|| section.lines().next()?.rsplit_once(" b/").map(|(_, path)| path))
+        .map(|p| p.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFF: &str = r#"diff --git a/src/cache.rs b/src/cache.rs
+index 1111111..2222222 100644
+--- a/src/cache.rs
++++ b/src/cache.rs
+@@ -1,3 +1,3 @@
+ pub struct Cache {
+-    capacity: usize,
++    max_entries: usize,
+ }
+@@ -40,2 +40,3 @@
+ fn log() {
++    println!("verbose logging enabled");
+ }
+diff --git a/README.md b/README.md
+index 3333333..4444444 100644
+--- a/README.md
++++ b/README.md
+@@ -10,2 +10,3 @@
+ ## Install
++Run the installer script first.
+ 
+diff --git a/tests/cache_test.rs b/tests/cache_test.rs
+new file mode 100644
+index 0000000..5555555
+--- /dev/null
++++ b/tests/cache_test.rs
+@@ -0,0 +1,2 @@
++// Cache keeps at most max_entries
++fn fills_to_max_entries() {}
+"#;
+
+    #[test]
+    fn test_split_groups_related_hunks_into_patches() {
+        let pieces = pieces(DIFF);
+        let paths: Vec<&str> = pieces.iter().map(|p| p.path.as_str()).collect();
+        assert_eq!(paths, vec!["src/cache.rs", "src/cache.rs", "README.md", "tests/cache_test.rs"]);
+
+        let groups = group(pieces);
+        let files: Vec<Vec<String>> = groups.iter().map(CommitGroup::describe_files).collect();
+        assert_eq!(
+            files,
+            vec![
+                vec!["src/cache.rs".to_string(), "tests/cache_test.rs".to_string()],
+                vec!["src/cache.rs".to_string()],
+                vec!["README.md".to_string()],
+            ]
+        );
+        assert_eq!(
+            groups[1].patch(),
+            "diff --git a/src/cache.rs b/src/cache.rs\nindex 1111111..2222222 100644\n--- a/src/cache.rs\n+++ b/src/cache.rs\n\
+             @@ -40,2 +40,3 @@\n fn log() {\n+    println!(\"verbose logging enabled\");\n }\n"
+        );
+        assert!(groups[0].patch().contains("new file mode 100644\nindex 0000000..5555555\n--- /dev/null\n"));
+    }
+}
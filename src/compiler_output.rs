@@ -0,0 +1,245 @@
+//! Errors and warnings read from compiler and linter output (cargo, clippy, rustc,
+//! tsc, eslint, gcc and anything else printing `file:line:col: message`)
+
+/// One located message from a build or lint
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompilerMessage {
+    /// `error`, `warning` or `note`
+    pub severity: String,
+    /// Error code or lint rule, e.g. `E0308` or `TS2322`
+    pub code: Option<String>,
+    pub message: String,
+    /// As printed, usually relative to where the tool ran
+    pub file: String,
+    /// 1-based
+    pub line: usize,
+    pub column: Option<usize>,
+}
+
+impl CompilerMessage {
+    pub fn is_error(&self) -> bool {
+        self.severity == "error"
+    }
+
+    /// `src/main.rs:4:5: error[E0308]: mismatched types`
+    pub fn describe(&self) -> String {
+        let column = self.column.map(|c| format!(":{}", c)).unwrap_or_default();
+        let code = self.code.as_ref().map(|c| format!("[{}]", c)).unwrap_or_default();
+        format!("{}:{}{}: {}{}: {}", self.file, self.line, column, self.severity, code, self.message)
+    }
+}
+
+/// The located messages in `output`, in the order printed
+pub fn parse(output: &str) -> Vec<CompilerMessage> {
+    let mut messages = Vec::new();
+    // rustc prints the message first and its location on a later `-->` line
+    let mut header: Option<(String, Option<String>, String)> = None;
+    // eslint's default format names a file, then lists its messages indented
+    let mut listed_file: Option<String> = None;
+
+    for raw in output.lines() {
+        let line = strip_ansi(raw);
+        let trimmed = line.trim();
+
+        if let Some(location) = trimmed.strip_prefix("--> ") {
+            if let (Some((severity, code, message)), Some((file, line, column, _))) = (header.take(), split_location(location)) {
+                messages.push(CompilerMessage { severity, code, message, file, line, column });
+            }
+            continue;
+        }
+        if let Some(parsed) = rust_header(trimmed) {
+            header = Some(parsed);
+            continue;
+        }
+        if let Some(message) = tsc_message(trimmed).or_else(|| located_message(trimmed)) {
+            messages.push(message);
+            continue;
+        }
+        if !line.starts_with(char::is_whitespace) && looks_like_path(trimmed) {
+            listed_file = Some(trimmed.to_string());
+            continue;
+        }
+        if let Some(ref file) = listed_file {
+            if let Some(message) = listed_message(file, trimmed) {
+                messages.push(message);
+            }
+        }
+    }
+    messages
+}
+
+/// Lines `line - radius ..= line + radius` of `content`, numbered, with the
+/// reported line marked by `>`
+pub fn excerpt(content: &str, line: usize, radius: usize) -> String {
+    let first = line.saturating_sub(radius).max(1);
+    content
+        .lines()
+        .enumerate()
+        .skip(first - 1)
+        .take(line + radius + 1 - first)
+        .map(|(i, text)| format!("{}{:>5} {}\n", if i + 1 == line { ">" } else { " " }, i + 1, text))
+        .collect()
+}
+
+/// `error[E0308]: mismatched types` or `warning: unused variable: `x``
+fn rust_header(line: &str) -> Option<(String, Option<String>, String)> {
+    let severity = ["error", "warning"].into_iter().find(|s| line.starts_with(s))?;
+    let rest = &line[severity.len()..];
+    let (code, rest) = match rest.strip_prefix('[') {
+        Some(bracketed) => {
+            let (code, rest) = bracketed.split_once(']')?;
+            (Some(code.to_string()), rest)
+        }
+        None => (None, rest),
+    };
+    let message = rest.strip_prefix(": ")?;
+    Some((severity.to_string(), code, message.trim().to_string()))
+}
+
+/// `src/app.ts(10,5): error TS2322: Type 'string' is not assignable to type 'number'.`
+fn tsc_message(line: &str) -> Option<CompilerMessage> {
+    let (file, rest) = line.split_once('(')?;
+    let (position, rest) = rest.split_once("): ")?;
+    let (line_no, column) = position.split_once(',')?;
+    let (severity, message) = rest.split_once(' ')?;
+    let (code, message) = message.split_once(": ")?;
+    if !looks_like_path(file) || !matches!(severity, "error" | "warning") {
+        return None;
+    }
+    Some(CompilerMessage {
+        severity: severity.to_string(),
+        code: Some(code.to_string()),
+        message: message.trim().to_string(),
+        file: file.to_string(),
+        line: line_no.parse().ok()?,
+        column: column.parse().ok(),
+    })
+}
+
+/// `file:line:col: error: message`, `file:line:col - error TS2322: message`
+/// (tsc --pretty) or `file:line:col: message [Error/rule]` (eslint -f unix)
+fn located_message(line: &str) -> Option<CompilerMessage> {
+    let (file, line_no, column, rest) = split_location(line)?;
+    let rest = rest.trim_start_matches([':', '-', ' ']);
+    if rest.is_empty() {
+        return None;
+    }
+
+    let (severity, code, message) = match ["error", "warning", "note"].into_iter().find(|s| rest.starts_with(s)) {
+        Some(severity) => {
+            let after = &rest[severity.len()..];
+            let (code, message) = match after.split_once(": ") {
+                Some((code, message)) => (code.trim().trim_matches(['[', ']']), message),
+                None => ("", after.trim_start_matches(':')),
+            };
+            (severity, (!code.is_empty()).then(|| code.to_string()), message)
+        }
+        None if rest.ends_with("[Warning") || rest.contains("[Warning/") => ("warning", None, rest),
+        None => ("error", None, rest),
+    };
+    Some(CompilerMessage {
+        severity: severity.to_string(),
+        code,
+        message: message.trim().to_string(),
+        file,
+        line: line_no,
+        column,
+    })
+}
+
+/// `  10:5  error  'x' is assigned a value but never used  no-unused-vars`, under `file`
+fn listed_message(file: &str, line: &str) -> Option<CompilerMessage> {
+    let mut parts = line.split_whitespace();
+    let (line_no, column) = parts.next()?.split_once(':')?;
+    let severity = parts.next().filter(|s| matches!(*s, "error" | "warning"))?;
+    let mut words: Vec<&str> = parts.collect();
+    // The rule id comes last, e.g. `no-unused-vars` or `@typescript-eslint/no-explicit-any`
+    let code = match words.last() {
+        Some(last) if words.len() > 1 && (last.contains('-') || last.contains('/')) => words.pop().map(str::to_string),
+        _ => None,
+    };
+    Some(CompilerMessage {
+        severity: severity.to_string(),
+        code,
+        message: words.join(" "),
+        file: file.to_string(),
+        line: line_no.parse().ok()?,
+        column: column.parse().ok(),
+    })
+}
+
+/// `path:line[:col]` at the start of `text`, with what follows it
+fn split_location(text: &str) -> Option<(String, usize, Option<usize>, &str)> {
+    let mut parts = text.splitn(3, ':');
+    let file = parts.next()?;
+    let line_no = parts.next()?;
+    if !looks_like_path(file) {
+        return None;
+    }
+    let line_no: usize = line_no.trim().parse().ok()?;
+    let rest = parts.next().unwrap_or_default();
+
+    let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    let column = rest[..digits].parse().ok();
+    Some((file.to_string(), line_no, column, &rest[digits..]))
+}
+
+/// A file name with an extension and no spaces
+fn looks_like_path(text: &str) -> bool {
+    !text.is_empty()
+        && !text.contains(char::is_whitespace)
+        && text.rsplit(['/', '\\']).next().is_some_and(|name| {
+            name.rsplit_once('.').is_some_and(|(stem, ext)| !stem.is_empty() && !ext.is_empty() && ext.chars().all(char::is_alphanumeric))
+        })
+}
+
+/// `text` without terminal colour codes
+fn strip_ansi(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // ESC [ ... final letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cargo_tsc_and_eslint_output() {
+        let output = "   Compiling sample v0.1.0\n\
+            \u{1b}[1;31merror[E0308]\u{1b}[0m: mismatched types\n \
+             --> src/main.rs:4:37\n  |\n4 |     let mut cache = cache::Cache::new(\"2\");\n\
+            warning: unused variable: `x`\n  --> src/cache.rs:12:9\n\
+            error: could not compile `sample` due to previous error\n\
+            src/app.ts(10,5): error TS2322: Type 'string' is not assignable to type 'number'.\n\
+            src/util.ts:3:1 - error TS2304: Cannot find name 'foo'.\n\
+            /home/me/web/src/index.js\n  \
+              7:10  error  'x' is assigned a value but never used  no-unused-vars\n\n\
+            ✖ 1 problem (1 error, 0 warnings)\n";
+
+        let found: Vec<String> = parse(output).iter().map(CompilerMessage::describe).collect();
+        assert_eq!(
+            found,
+            vec![
+                "src/main.rs:4:37: error[E0308]: mismatched types",
+                "src/cache.rs:12:9: warning: unused variable: `x`",
+                "src/app.ts:10:5: error[TS2322]: Type 'string' is not assignable to type 'number'.",
+                "src/util.ts:3:1: error[TS2304]: Cannot find name 'foo'.",
+                "/home/me/web/src/index.js:7:10: error[no-unused-vars]: 'x' is assigned a value but never used",
+            ]
+        );
+        assert_eq!(excerpt("a\nb\nc\nd\n", 2, 1), "     1 a\n>    2 b\n     3 c\n");
+    }
+}
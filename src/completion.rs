@@ -0,0 +1,146 @@
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+use crate::agents::{ArgKind, CommandSpec};
+
+/// What the word under the cursor completes to
+#[derive(Debug, PartialEq)]
+enum Completion {
+    /// Candidates for the word starting at this byte
+    Words(usize, Vec<String>),
+    Files,
+    Nothing,
+}
+
+/// Tab completion for the REPL: command names, their options, choices and
+/// file arguments, from the same specs that check the arguments
+pub struct CommandCompleter {
+    commands: Vec<CommandSpec>,
+    files: FilenameCompleter,
+}
+
+impl CommandCompleter {
+    pub fn new(commands: Vec<CommandSpec>) -> Self {
+        Self { commands, files: FilenameCompleter::new() }
+    }
+
+    fn completion(&self, before: &str) -> Completion {
+        if !before.starts_with('/') {
+            return Completion::Nothing;
+        }
+        let Some((name, _)) = before.split_once(char::is_whitespace) else {
+            let names = self.commands
+                .iter()
+                .flat_map(|spec| std::iter::once(spec.name).chain(spec.aliases.iter().copied()))
+                .filter(|name| name.starts_with(before))
+                .map(|name| format!("{} ", name))
+                .collect();
+            return Completion::Words(0, names);
+        };
+        let Some(spec) = self.commands.iter().find(|spec| spec.is_named(name)) else {
+            return Completion::Nothing;
+        };
+
+        let word_start = before.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &before[word_start..];
+        let mut earlier = before[name.len()..word_start].split_whitespace();
+
+        // Flags come first; count the arguments typed after them
+        let mut in_flags = true;
+        let mut position = 0;
+        while let Some(earlier_word) = earlier.next() {
+            let flag = spec.flags.iter().find(|flag| flag.long == earlier_word || flag.short == Some(earlier_word));
+            if in_flags && earlier_word.starts_with('-') {
+                if flag.is_some_and(|flag| flag.value.is_some()) {
+                    earlier.next();
+                }
+                continue;
+            }
+            in_flags = false;
+            position += 1;
+        }
+
+        if in_flags && word.starts_with('-') {
+            let flags = spec.flags
+                .iter()
+                .map(|flag| flag.long)
+                .filter(|long| long.starts_with(word))
+                .map(|long| format!("{} ", long))
+                .collect();
+            return Completion::Words(word_start, flags);
+        }
+        // The last argument takes the rest of the line, so only its first word completes
+        let Some(param) = spec.params.get(position) else {
+            return Completion::Nothing;
+        };
+        match param.kind {
+            ArgKind::File | ArgKind::Path => Completion::Files,
+            ArgKind::Choice(choices) => Completion::Words(
+                word_start,
+                choices.iter().filter(|choice| choice.starts_with(word)).map(|choice| choice.to_string()).collect(),
+            ),
+            ArgKind::Command => Completion::Words(
+                word_start,
+                self.commands
+                    .iter()
+                    .map(|spec| spec.name.trim_start_matches('/'))
+                    .filter(|name| name.starts_with(word.trim_start_matches('/')))
+                    .map(String::from)
+                    .collect(),
+            ),
+            _ => Completion::Nothing,
+        }
+    }
+}
+
+impl Completer for CommandCompleter {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        match self.completion(&line[..pos]) {
+            Completion::Words(start, words) => Ok((
+                start,
+                words.into_iter().map(|word| Pair { display: word.trim_end().to_string(), replacement: word }).collect(),
+            )),
+            Completion::Files => self.files.complete(line, pos, ctx),
+            Completion::Nothing => Ok((pos, Vec::new())),
+        }
+    }
+}
+
+impl Hinter for CommandCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for CommandCompleter {}
+
+impl Validator for CommandCompleter {}
+
+impl Helper for CommandCompleter {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::COMMANDS;
+
+    #[test]
+    fn test_completes_names_flags_and_arguments() {
+        let completer = CommandCompleter::new(COMMANDS.to_vec());
+        let words = |before: &str| match completer.completion(before) {
+            Completion::Words(_, words) => words,
+            other => panic!("{:?} for {}", other, before),
+        };
+
+        assert_eq!(words("/sym"), vec!["/symbol ", "/sym "]);
+        assert_eq!(words("/ask --f"), vec!["--fresh "]);
+        assert_eq!(words("/verbosity te"), vec!["terse", "teaching"]);
+        assert_eq!(words("/help glo"), vec!["glossary"]);
+        assert_eq!(completer.completion("/read src/ma"), Completion::Files);
+        assert_eq!(completer.completion("/open -s src/ma"), Completion::Nothing);
+        assert_eq!(completer.completion("/ask --ref v1 how"), Completion::Nothing);
+        assert_eq!(completer.completion("how do I"), Completion::Nothing);
+    }
+}
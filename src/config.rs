@@ -0,0 +1,214 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::agents::ChatConfig;
+use crate::consolidate::ConsolidationConfig;
+use crate::daemon::DaemonConfig;
+use crate::digest::DigestConfig;
+use crate::embeddings::EmbeddingConfig;
+use crate::llm::{GenerationOptions, Servers};
+use crate::lsp::LspConfig;
+use crate::models::ModelRegistry;
+use crate::prompts::{PromptLibrary, Verbosity};
+use crate::rag::RagConfig;
+use crate::scoring::ScoringConfig;
+use crate::storage::excludes::IndexConfig;
+use crate::storage::retention::RetentionConfig;
+use crate::tools::ToolsConfig;
+
+const CONFIG_FILE: &str = "sovereign.toml";
+
+/// User configuration loaded from `sovereign.toml`
+///
+/// The global file lives in the data directory; a `sovereign.toml` at the
+/// project root overrides it key by key, so per-repo tuning only needs to
+/// contain the values that differ.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Answer length and style for every agent: `terse`, `normal` or `teaching`
+    pub verbosity: Verbosity,
+    pub rag: RagConfig,
+    pub prompts: PromptLibrary,
+    pub embeddings: EmbeddingConfig,
+    pub tools: ToolsConfig,
+    pub lsp: LspConfig,
+    pub digest: DigestConfig,
+    pub daemon: DaemonConfig,
+    pub index: IndexConfig,
+    /// How memories fade and when they are pruned
+    pub retention: RetentionConfig,
+    /// Scoring and tagging new memories with a model
+    pub scoring: ScoringConfig,
+    /// Condensing old conversations into facts and decisions
+    pub consolidation: ConsolidationConfig,
+    /// When long chats are summarized to fit the context window
+    pub chat: ChatConfig,
+    /// Ollama options for every request (`num_ctx`, `num_gpu`, `num_thread`,
+    /// `repeat_penalty`, `stop`); `[prompts.<command>]` overrides them per command
+    pub ollama: GenerationOptions,
+    /// Capabilities of models the built-in registry doesn't know, or describes wrongly
+    pub models: ModelRegistry,
+    /// Changes are only described (`--dry-run`); never read from the file
+    #[serde(skip)]
+    pub dry_run: bool,
+    /// Where the backends are reached; never read from the file
+    #[serde(skip)]
+    pub servers: Servers,
+}
+
+impl Config {
+    /// Load the global config, overlaid with the project config if present
+    pub fn load(data_dir: &Path, project_root: Option<&Path>) -> Result<Self> {
+        let mut merged = read_table(&Self::global_path(data_dir))?;
+
+        if let Some(root) = project_root {
+            let project = read_table(&Self::project_path(root))?;
+            merge_tables(&mut merged, project);
+        }
+
+        let mut config: Self = toml::Value::Table(merged)
+            .try_into()
+            .context("Invalid sovereign.toml")?;
+        config.prompts.set_verbosity(config.verbosity);
+        Ok(config)
+    }
+
+    /// This config with the command-line settings in place of the file's
+    pub fn with_overrides(mut self, overrides: &Overrides) -> Self {
+        if let Some(model) = &overrides.embedding_model {
+            self.embeddings.model = Some(model.clone());
+        }
+        self.dry_run = overrides.dry_run;
+        self.servers = overrides.servers.clone();
+        self
+    }
+
+    /// Path of the global config file
+    pub fn global_path(data_dir: &Path) -> PathBuf {
+        data_dir.join(CONFIG_FILE)
+    }
+
+    /// Path of the per-project config file
+    pub fn project_path(project_root: &Path) -> PathBuf {
+        project_root.join(CONFIG_FILE)
+    }
+
+    /// Replace one `[section]` of a config file, keeping everything else intact
+    pub fn write_section<T: Serialize>(path: &Path, section: &str, value: &T) -> Result<()> {
+        let mut table = read_table(path)?;
+        let value = toml::Value::try_from(value)
+            .with_context(|| format!("Failed to serialize [{}]", section))?;
+        table.insert(section.to_string(), value);
+
+        let content = toml::to_string_pretty(&table)?;
+        std::fs::write(path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+}
+
+fn read_table(path: &Path) -> Result<toml::Table> {
+    if !path.exists() {
+        return Ok(toml::Table::new());
+    }
+
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    content
+        .parse::<toml::Table>()
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Recursively overlay `overlay` onto `base`; nested tables are merged, other values replaced
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(overlay_table)) => {
+                merge_tables(base_table, overlay_table);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Settings given on the command line, which take precedence over `sovereign.toml`
+/// however often it is re-read
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    /// `--embedding-model`
+    pub embedding_model: Option<String>,
+    /// `--dry-run`
+    pub dry_run: bool,
+    /// `--no-analytics`
+    pub no_analytics: bool,
+    /// Servers to use instead of the ones the environment names
+    pub servers: Servers,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_tables_overrides_nested_keys() {
+        let mut base: toml::Table = "[rag]\ntop_k = 10\nsemantic_weight = 0.7".parse().unwrap();
+        let overlay: toml::Table = "[rag]\nsemantic_weight = 0.4".parse().unwrap();
+        merge_tables(&mut base, overlay);
+
+        let config: Config = toml::Value::Table(base).try_into().unwrap();
+        assert_eq!(config.rag.top_k, 10);
+        assert!((config.rag.semantic_weight - 0.4).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn test_ollama_options_are_defaults_for_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(CONFIG_FILE),
+            "[ollama]\nnum_ctx = 8192\nnum_thread = 8\nstop = [\"<|end|>\"]\n\n[prompts.commit]\nnum_ctx = 4096\n",
+        )
+        .unwrap();
+
+        let config = Config::load(dir.path(), None).unwrap();
+        let commit = config.prompts.options("commit").with_defaults(&config.ollama);
+        assert_eq!(commit.num_ctx, Some(4096));
+        assert_eq!(commit.num_thread, Some(8));
+        assert_eq!(commit.stop, vec!["<|end|>".to_string()]);
+
+        let other = config.prompts.options("explain").with_defaults(&config.ollama);
+        assert_eq!(other.num_ctx, Some(8192));
+        assert_eq!(other.repeat_penalty, None);
+    }
+
+    #[test]
+    fn test_command_line_overrides_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(CONFIG_FILE), "[embeddings]\nmodel = \"nomic-embed-text\"\n").unwrap();
+
+        let config = Config::load(dir.path(), None).unwrap();
+        assert_eq!(config.with_overrides(&Overrides::default()).embeddings.model.as_deref(), Some("nomic-embed-text"));
+
+        let overrides = Overrides { embedding_model: Some("mxbai-embed-large".to_string()), ..Default::default() };
+        let config = Config::load(dir.path(), None).unwrap().with_overrides(&overrides);
+        assert_eq!(config.embeddings.model.as_deref(), Some("mxbai-embed-large"));
+    }
+
+    #[test]
+    fn test_write_section_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+
+        let rag = RagConfig {
+            min_similarity: 0.25,
+            ..RagConfig::default()
+        };
+        Config::write_section(&path, "rag", &rag).unwrap();
+
+        let config = Config::load(dir.path(), None).unwrap();
+        assert!((config.rag.min_similarity - 0.25).abs() < f32::EPSILON);
+    }
+}
@@ -0,0 +1,350 @@
+use anyhow::Result;
+use std::collections::HashSet;
+
+use crate::storage::CodebaseIndex;
+
+/// Symbol kinds that are references, not definitions
+const NON_DEFINITION_KINDS: &[&str] = &["impl"];
+
+/// Languages whose mentions of a symbol are prose, not call sites
+const PROSE_LANGUAGES: &[&str] = &["markdown", "json", "yaml", "toml"];
+
+/// New content proposed for one file of a multi-file edit
+#[derive(Debug, Clone)]
+pub struct ProposedEdit {
+    /// Absolute or project-relative path, as stored in the index
+    pub path: String,
+    pub new_content: String,
+}
+
+/// A reference to a removed or renamed symbol that the edit plan leaves behind
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsistencyWarning {
+    pub symbol: String,
+    /// New name when the edit looks like a rename
+    pub renamed_to: Option<String>,
+    /// File (relative path) where the symbol was defined
+    pub defined_in: String,
+    /// File (relative path) that still references the old name
+    pub file: String,
+    /// 1-based line of the reference
+    pub line: usize,
+    pub text: String,
+}
+
+impl ConsistencyWarning {
+    pub fn describe(&self) -> String {
+        let change = match self.renamed_to {
+            Some(ref new_name) => format!("`{}` renamed to `{}`", self.symbol, new_name),
+            None => format!("`{}` removed", self.symbol),
+        };
+        format!(
+            "{}:{}: {} in {} but still used here: {}",
+            self.file, self.line, change, self.defined_in, self.text.trim()
+        )
+    }
+}
+
+/// A use of a symbol found by `find_references`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    /// Relative path of the file
+    pub file: String,
+    /// 1-based line of the use
+    pub line: usize,
+    /// Kind and name of the innermost definition containing the use, e.g. `fn:main`
+    pub enclosing: Option<String>,
+    /// The line with one line of context on either side, as `(line number, text)`
+    pub context: Vec<(usize, String)>,
+}
+
+impl Reference {
+    pub fn describe(&self) -> String {
+        let mut out = format!("{}:{}", self.file, self.line);
+        if let Some(ref enclosing) = self.enclosing {
+            out.push_str(&format!(" (in {})", enclosing.replacen(':', " ", 1)));
+        }
+        for (number, text) in &self.context {
+            let marker = if *number == self.line { ">" } else { " " };
+            out.push_str(&format!("\n  {} {:>5} | {}", marker, number, text));
+        }
+        out
+    }
+}
+
+/// Definitions removed by each edit, with a likely new name
+#[derive(Debug, Clone, PartialEq)]
+struct RemovedSymbol {
+    name: String,
+    renamed_to: Option<String>,
+    defined_in: String,
+}
+
+/// Find references to symbols the edits remove or rename that the edits do not update
+///
+/// Symbols are compared with the index's own extraction, so this is as precise
+/// as `/symbol`: good at catching a missed call site, not a type checker.
+pub fn check_edits(index: &CodebaseIndex, edits: &[ProposedEdit]) -> Result<Vec<ConsistencyWarning>> {
+    let mut removed = Vec::new();
+    let mut edited_paths = HashSet::new();
+
+    for edit in edits {
+        let Some(file) = index.get_file(&edit.path)? else {
+            continue;
+        };
+        edited_paths.insert(file.path.clone());
+
+        let new_symbols = CodebaseIndex::extract_symbols(&edit.new_content, &file.language);
+        removed.extend(removed_symbols(&file.symbols, &new_symbols, &file.relative_path));
+    }
+
+    // Symbols still defined somewhere in the new content were moved, not removed
+    let redefined: HashSet<String> = edits
+        .iter()
+        .filter_map(|edit| {
+            let language = index.get_file(&edit.path).ok().flatten()?.language;
+            Some(CodebaseIndex::extract_symbols(&edit.new_content, &language))
+        })
+        .flatten()
+        .filter_map(|s| symbol_name(&s).map(String::from))
+        .collect();
+    removed.retain(|r| !redefined.contains(&r.name));
+
+    let mut warnings = Vec::new();
+
+    for symbol in removed {
+        // The edit plan's own version of each file is what will be on disk
+        for edit in edits {
+            let label = index.get_file(&edit.path)?.map(|f| f.relative_path);
+            warnings.extend(references(&edit.new_content, &symbol.name).into_iter().map(|(line, text)| {
+                ConsistencyWarning {
+                    symbol: symbol.name.clone(),
+                    renamed_to: symbol.renamed_to.clone(),
+                    defined_in: symbol.defined_in.clone(),
+                    file: label.clone().unwrap_or_else(|| edit.path.clone()),
+                    line,
+                    text,
+                }
+            }));
+        }
+
+        // FTS narrows the candidates; the word scan below confirms them
+        let query = format!("\"{}\"", symbol.name);
+        for file in index.search(&query, 500)?.into_iter().map(|m| m.file) {
+            if edited_paths.contains(&file.path) || PROSE_LANGUAGES.contains(&file.language.as_str()) {
+                continue;
+            }
+            let Some(content) = index.get_file_content(&file.path)? else {
+                continue;
+            };
+
+            for (line, text) in references(&content, &symbol.name) {
+                warnings.push(ConsistencyWarning {
+                    symbol: symbol.name.clone(),
+                    renamed_to: symbol.renamed_to.clone(),
+                    defined_in: symbol.defined_in.clone(),
+                    file: file.relative_path.clone(),
+                    line,
+                    text,
+                });
+            }
+        }
+    }
+
+    warnings.sort_by(|a, b| a.file.cmp(&b.file).then(a.line.cmp(&b.line)));
+    Ok(warnings)
+}
+
+/// Uses of `name` across this project's indexed code, excluding its definitions and comments
+///
+/// Like `check_edits`, FTS narrows the candidate files and a whole-word scan
+/// finds the lines; the symbol table tells definitions from uses.
+pub fn find_references(index: &CodebaseIndex, name: &str) -> Result<Vec<Reference>> {
+    let definitions: HashSet<(String, usize)> = index
+        .find_definitions(name)?
+        .into_iter()
+        .map(|s| (s.path, s.start_line))
+        .collect();
+
+    let mut found = Vec::new();
+    let query = format!("\"{}\"", name.replace('"', ""));
+    for file in index.search(&query, 500)?.into_iter().map(|m| m.file) {
+        if PROSE_LANGUAGES.contains(&file.language.as_str()) || !index.is_in_project(&file.path) {
+            continue;
+        }
+        let Some(content) = index.get_file_content(&file.path)? else {
+            continue;
+        };
+        let lines: Vec<&str> = content.lines().collect();
+
+        for (line, text) in references(&content, name) {
+            if definitions.contains(&(file.path.clone(), line)) || is_comment(&text, &file.language) {
+                continue;
+            }
+
+            let context = (line.saturating_sub(1).max(1)..=(line + 1).min(lines.len()))
+                .map(|n| (n, lines[n - 1].to_string()))
+                .collect();
+            found.push(Reference {
+                file: file.relative_path.clone(),
+                line,
+                enclosing: index.enclosing_symbol(&file.path, line)?.map(|s| format!("{}:{}", s.kind, s.name)),
+                context,
+            });
+        }
+    }
+
+    found.sort_by(|a, b| a.file.cmp(&b.file).then(a.line.cmp(&b.line)));
+    Ok(found)
+}
+
+fn is_comment(line: &str, language: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed.starts_with("//")
+        || trimmed.starts_with("/*")
+        || trimmed.starts_with("* ")
+        || (language == "python" && trimmed.starts_with('#'))
+}
+
+/// Indexed files whose content on disk differs from the index, as an edit plan
+///
+/// Lets the check run against edits that were already written to the working
+/// tree but not yet re-indexed.
+pub fn working_tree_edits(index: &CodebaseIndex) -> Result<Vec<ProposedEdit>> {
+    let mut edits = Vec::new();
+
+    for file in index.get_all_files()? {
+        let Ok(on_disk) = std::fs::read_to_string(&file.path) else {
+            continue;
+        };
+        if index.get_file_content(&file.path)?.as_deref() != Some(on_disk.as_str()) {
+            edits.push(ProposedEdit {
+                path: file.path,
+                new_content: on_disk,
+            });
+        }
+    }
+
+    Ok(edits)
+}
+
+/// Definitions present before but not after; a single same-kind swap is treated as a rename
+fn removed_symbols(old: &[String], new: &[String], defined_in: &str) -> Vec<RemovedSymbol> {
+    let is_definition = |s: &&String| {
+        s.split_once(':')
+            .map(|(kind, _)| !NON_DEFINITION_KINDS.contains(&kind))
+            .unwrap_or(false)
+    };
+    let old: HashSet<&String> = old.iter().filter(is_definition).collect();
+    let new: HashSet<&String> = new.iter().filter(is_definition).collect();
+
+    let gone: Vec<&String> = old.difference(&new).copied().collect();
+    let added: Vec<&String> = new.difference(&old).copied().collect();
+
+    gone.iter()
+        .filter_map(|symbol| {
+            let (kind, name) = symbol.split_once(':')?;
+            let same_kind: Vec<&&String> = added
+                .iter()
+                .filter(|a| a.split_once(':').map(|(k, _)| k == kind).unwrap_or(false))
+                .collect();
+            let renamed_to = match (gone.len(), same_kind.as_slice()) {
+                (1, [only]) => symbol_name(only).map(String::from),
+                _ => None,
+            };
+
+            Some(RemovedSymbol {
+                name: name.to_string(),
+                renamed_to,
+                defined_in: defined_in.to_string(),
+            })
+        })
+        .collect()
+}
+
+fn symbol_name(symbol: &str) -> Option<&str> {
+    symbol.split_once(':').map(|(_, name)| name)
+}
+
+/// Lines containing `name` as a whole identifier
+fn references(content: &str, name: &str) -> Vec<(usize, String)> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| contains_identifier(line, name))
+        .map(|(i, line)| (i + 1, line.to_string()))
+        .collect()
+}
+
+fn contains_identifier(line: &str, name: &str) -> bool {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+
+    line.match_indices(name).any(|(start, _)| {
+        let before = line[..start].chars().next_back();
+        let after = line[start + name.len()..].chars().next();
+        !before.map(is_ident).unwrap_or(false) && !after.map(is_ident).unwrap_or(false)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identifier_boundaries() {
+        assert!(contains_identifier("let x = parse_config(path);", "parse_config"));
+        assert!(!contains_identifier("let x = parse_config_file(path);", "parse_config"));
+        assert!(!contains_identifier("reparse_config()", "parse_config"));
+    }
+
+    #[test]
+    fn test_missed_call_site_after_rename() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("lib.rs"), "pub fn load_config() {}\n").unwrap();
+        std::fs::write(root.path().join("main.rs"), "fn main() {\n    load_config();\n}\n").unwrap();
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+
+        let edits = vec![ProposedEdit {
+            path: "lib.rs".to_string(),
+            new_content: "pub fn read_config() {}\n".to_string(),
+        }];
+        let warnings = check_edits(&index, &edits).unwrap();
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].file, "main.rs");
+        assert_eq!(warnings[0].line, 2);
+        assert_eq!(warnings[0].renamed_to.as_deref(), Some("read_config"));
+
+        // Updating the call site in the same plan resolves it
+        let mut complete = edits.clone();
+        complete.push(ProposedEdit {
+            path: "main.rs".to_string(),
+            new_content: "fn main() {\n    read_config();\n}\n".to_string(),
+        });
+        assert!(check_edits(&index, &complete).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_find_references_skips_definitions_and_comments() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("lib.rs"), "pub fn parse_diff() {}\n").unwrap();
+        std::fs::write(
+            root.path().join("main.rs"),
+            "fn main() {\n    // parse_diff is slow\n    parse_diff();\n}\n",
+        ).unwrap();
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+
+        let refs = find_references(&index, "parse_diff").unwrap();
+        assert_eq!(refs.len(), 1);
+        assert_eq!(refs[0].file, "main.rs");
+        assert_eq!(refs[0].line, 3);
+        assert_eq!(refs[0].enclosing.as_deref(), Some("fn:main"));
+        assert_eq!(refs[0].context.len(), 3);
+    }
+}
@@ -0,0 +1,294 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::llm::LlmClient;
+use crate::prompts::PromptLibrary;
+use crate::storage::memory::{Memory, MemoryType};
+use crate::storage::MemoryStore;
+
+/// Tags every agent puts on what it stores, which say nothing about the topic
+const GENERIC_TAGS: &[&str] = &["chat", "code", "generation", "refactor", "consolidated"];
+
+/// Characters of each conversation shown to the model
+const MAX_MEMORY_CHARS: usize = 300;
+
+const CONSOLIDATE_SYSTEM_PROMPT: &str = r#"You condense a coding assistant's notes on earlier conversations.
+Keep only what will still matter later: facts about the project and the user's decisions.
+Reply with JSON only: {"memories": [{"type": "fact" or "decision", "content": "..."}]}
+Write at most three memories, each one self-contained sentence. Reply {"memories": []}
+if nothing is worth keeping."#;
+
+/// `[consolidation]` section of `sovereign.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConsolidationConfig {
+    /// Let the daemon consolidate once a day
+    pub scheduled: bool,
+    /// Conversations younger than this are left as they are
+    pub min_age_days: u32,
+    /// Fewest conversations on one topic worth condensing
+    pub min_cluster: usize,
+    /// Most conversations condensed in one prompt; the oldest go first
+    pub max_cluster: usize,
+    /// Clusters condensed per run
+    pub max_clusters: usize,
+    /// Move condensed conversations to the archive instead of deleting them
+    pub archive: bool,
+}
+
+impl Default for ConsolidationConfig {
+    fn default() -> Self {
+        Self {
+            scheduled: false,
+            min_age_days: 30,
+            min_cluster: 3,
+            max_cluster: 20,
+            max_clusters: 10,
+            archive: true,
+        }
+    }
+}
+
+/// Old conversations of one project sharing a topic
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    pub project: Option<String>,
+    /// The tag the conversations share (`topic:sync`, `symbol:CodebaseIndex`, `automerge`)
+    pub topic: String,
+    /// Oldest first
+    pub memories: Vec<Memory>,
+}
+
+/// A cluster condensed into fact and decision memories
+#[derive(Debug, Clone)]
+pub struct Consolidated {
+    pub cluster: Cluster,
+    /// The memories written in its place; empty on a dry run
+    pub created: Vec<Memory>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawSummary {
+    memories: Vec<RawMemory>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawMemory {
+    #[serde(rename = "type")]
+    memory_type: String,
+    content: String,
+}
+
+impl ConsolidationConfig {
+    /// Conversations older than `min_age_days` grouped by project and topic,
+    /// largest first, up to `max_clusters`
+    ///
+    /// Each conversation's topic is whichever of its tags the most old conversations
+    /// of its project share, so a conversation joins one cluster only.
+    pub fn clusters(&self, memories: &[Memory], now: DateTime<Utc>) -> Vec<Cluster> {
+        let cutoff = now - Duration::days(self.min_age_days as i64);
+        let mut old: Vec<&Memory> = memories
+            .iter()
+            .filter(|m| m.memory_type == MemoryType::Conversation && m.created_at < cutoff)
+            .collect();
+        old.sort_by_key(|m| m.created_at);
+
+        let mut counts: HashMap<(Option<&str>, &str), usize> = HashMap::new();
+        for memory in &old {
+            for tag in topic_tags(memory) {
+                *counts.entry((memory.project.as_deref(), tag)).or_default() += 1;
+            }
+        }
+
+        let mut clusters: Vec<Cluster> = Vec::new();
+        for memory in old {
+            let project = memory.project.as_deref();
+            let Some(topic) = topic_tags(memory).max_by(|a, b| {
+                let count = |tag: &str| counts.get(&(project, tag)).copied().unwrap_or(0);
+                count(a).cmp(&count(b)).then_with(|| b.cmp(a))
+            }) else {
+                continue;
+            };
+
+            match clusters.iter_mut().find(|c| c.project.as_deref() == project && c.topic == topic) {
+                Some(cluster) => cluster.memories.push(memory.clone()),
+                None => clusters.push(Cluster {
+                    project: memory.project.clone(),
+                    topic: topic.to_string(),
+                    memories: vec![memory.clone()],
+                }),
+            }
+        }
+
+        clusters.retain(|c| c.memories.len() >= self.min_cluster.max(2));
+        clusters.sort_by_key(|c| std::cmp::Reverse(c.memories.len()));
+        clusters.truncate(self.max_clusters);
+        for cluster in &mut clusters {
+            cluster.memories.truncate(self.max_cluster);
+        }
+        clusters
+    }
+
+    /// Condense the clusters of `store` with `llm`, replacing each cluster's
+    /// conversations by the memories the model wrote
+    ///
+    /// A cluster whose reply can't be read is left for the next run. A dry run
+    /// only finds the clusters.
+    pub async fn consolidate(
+        &self,
+        store: &MemoryStore,
+        llm: &LlmClient,
+        prompts: &PromptLibrary,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Consolidated>> {
+        let clusters = self.clusters(&store.all()?, now);
+        if store.dry_run() {
+            return Ok(clusters.into_iter().map(|cluster| Consolidated { cluster, created: Vec::new() }).collect());
+        }
+
+        let system = prompts.system("consolidate", CONSOLIDATE_SYSTEM_PROMPT);
+        let options = prompts.options("consolidate");
+        let mut consolidated = Vec::new();
+        for cluster in clusters {
+            let response = match llm.generate_with(&summary_prompt(&cluster), Some(&system), &options).await {
+                Ok(response) => response,
+                Err(e) => {
+                    eprintln!("  Could not condense conversations about {}: {}", cluster.topic, e);
+                    continue;
+                }
+            };
+            let Some(summary) = parse_summary(&response) else {
+                continue;
+            };
+
+            // Condensed knowledge is worth as much as the best conversation it came from
+            let importance = cluster.memories.iter().map(|m| m.importance).fold(0.0f32, f32::max);
+            let mut created = Vec::new();
+            for (memory_type, content) in summary {
+                let tags = vec!["consolidated".to_string(), cluster.topic.clone()];
+                created.push(store.remember(&content, memory_type, cluster.project.as_deref(), tags, importance)?);
+            }
+
+            let ids: Vec<&str> = cluster.memories.iter().map(|m| m.id.as_str()).collect();
+            store.remove(&ids, self.archive)?;
+            consolidated.push(Consolidated { cluster, created });
+        }
+        Ok(consolidated)
+    }
+}
+
+/// Tags of `memory` that could name its topic
+fn topic_tags(memory: &Memory) -> impl Iterator<Item = &str> {
+    memory.tags.iter().map(String::as_str).filter(|tag| {
+        !GENERIC_TAGS.contains(tag) && !tag.starts_with("project:") && !tag.starts_with("lang:")
+    })
+}
+
+fn summary_prompt(cluster: &Cluster) -> String {
+    let project = cluster.project.as_deref().map(|p| format!(" in project {}", p)).unwrap_or_default();
+    let mut prompt = format!("Earlier conversations about {}{}:\n", cluster.topic, project);
+    for memory in &cluster.memories {
+        let content: String = memory.content.chars().take(MAX_MEMORY_CHARS).collect();
+        prompt.push_str(&format!("- ({}) {}\n", memory.created_at.format("%Y-%m-%d"), content));
+    }
+    prompt
+}
+
+/// The fact and decision memories in the model's reply; `None` when it can't be read
+pub fn parse_summary(response: &str) -> Option<Vec<(MemoryType, String)>> {
+    let (start, end) = match (response.find('{'), response.rfind('}')) {
+        (Some(start), Some(end)) if start < end => (start, end),
+        _ => return None,
+    };
+    let raw: RawSummary = serde_json::from_str(&response[start..=end]).ok()?;
+
+    Some(
+        raw.memories
+            .into_iter()
+            .filter(|m| !m.content.trim().is_empty())
+            .map(|m| {
+                let memory_type = match m.memory_type.trim().to_lowercase().as_str() {
+                    "decision" => MemoryType::Decision,
+                    _ => MemoryType::Fact,
+                };
+                (memory_type, m.content.trim().to_string())
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::OllamaClient;
+    use crate::test_support;
+
+    fn conversation(content: &str, tags: &[&str], days_old: i64) -> Memory {
+        Memory {
+            id: content.to_string(),
+            content: content.to_string(),
+            memory_type: MemoryType::Conversation,
+            project: Some("sovereign".to_string()),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            created_at: Utc::now() - Duration::days(days_old),
+            importance: 0.5,
+        }
+    }
+
+    #[test]
+    fn test_clusters_group_old_conversations_by_shared_topic() {
+        let config = ConsolidationConfig { min_cluster: 2, ..Default::default() };
+        let memories = vec![
+            conversation("sync a", &["chat", "topic:sync", "automerge"], 60),
+            conversation("sync b", &["chat", "topic:sync"], 50),
+            conversation("sync c", &["chat", "automerge", "topic:sync"], 40),
+            conversation("lone", &["chat", "topic:lsp"], 40),
+            conversation("recent sync", &["chat", "topic:sync"], 1),
+        ];
+
+        let clusters = config.clusters(&memories, Utc::now());
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].topic, "topic:sync");
+        let ids: Vec<&str> = clusters[0].memories.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["sync a", "sync b", "sync c"]);
+
+        assert_eq!(
+            parse_summary(r#"{"memories": [{"type": "Decision", "content": "Sync uses automerge."}, {"type": "x", "content": " "}]}"#),
+            Some(vec![(MemoryType::Decision, "Sync uses automerge.".to_string())])
+        );
+        assert_eq!(parse_summary("Nothing to add."), None);
+    }
+
+    #[tokio::test]
+    async fn test_consolidate_replaces_a_cluster_with_its_summary() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MemoryStore::new(&dir.path().to_path_buf()).unwrap();
+        for memory in [
+            conversation("User: how do peers merge? | Assistant: automerge", &["chat", "topic:peer-merge"], 45),
+            conversation("User: do merges conflict? | Assistant: no", &["chat", "topic:peer-merge"], 40),
+            conversation("User: merge order? | Assistant: any", &["chat", "topic:peer-merge"], 35),
+        ] {
+            store.store(&memory).unwrap();
+        }
+
+        test_support::reply_to(
+            "Earlier conversations about topic:peer-merge",
+            r#"{"memories": [{"type": "fact", "content": "Peers merge with automerge, in any order, without conflicts."}]}"#,
+        );
+        let llm = LlmClient::Ollama(OllamaClient::new(test_support::MOCK_MODEL).with_servers(&test_support::servers()));
+        let consolidated = ConsolidationConfig::default()
+            .consolidate(&store, &llm, &PromptLibrary::default(), Utc::now())
+            .await
+            .unwrap();
+
+        assert_eq!(consolidated.len(), 1);
+        assert_eq!(consolidated[0].cluster.memories.len(), 3);
+        let remaining = store.all().unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].memory_type, MemoryType::Fact);
+        assert!(remaining[0].tags.contains(&"consolidated".to_string()));
+        assert_eq!(store.archived_count().unwrap(), 3);
+    }
+}
@@ -1,41 +1,205 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use futures::stream::StreamExt;
 use futures::SinkExt;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::thread;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{mpsc, oneshot};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{broadcast, mpsc, oneshot, watch, Mutex, Notify};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
 use tokio_tungstenite::tungstenite::Message;
 
 #[cfg(unix)]
 use tokio::net::{UnixListener, UnixStream};
 
-use crate::agents::Orchestrator;
+use crate::agents::{Orchestrator, COMMANDS};
+use crate::config::{Config, Overrides};
+use crate::http_api;
+use crate::jobs::{self, JobState, Jobs};
+use crate::llm::{self, LlmBackend};
+use crate::metrics;
+use crate::net;
+use crate::render::OutputFormat;
+use crate::tls::{self, Connection, Tls};
 use crate::watcher::FileWatcher;
 
 const DEFAULT_PORT: u16 = 7655;
 const DEFAULT_WS_PORT: u16 = 7656;
+/// 7657 is the web UI's
+const DEFAULT_HTTP_PORT: u16 = 7658;
+const DEFAULT_METRICS_PORT: u16 = 7659;
 const SOCKET_NAME: &str = "sovereign.sock";
+/// How often the orchestrator thread works through the re-embed queue when idle
+const REEMBED_INTERVAL: Duration = Duration::from_secs(5);
+/// Files re-embedded per tick, so queued requests are not held up for long
+const REEMBED_BATCH: usize = 8;
+/// How often to check whether a scheduled memory digest is due
+const DIGEST_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+/// How often memories are pruned by the `[retention]` policy
+const PRUNE_INTERVAL: Duration = Duration::from_secs(6 * 3600);
+/// How often old conversations are condensed, when `[consolidation] scheduled` is on
+const CONSOLIDATE_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+/// How long `shutdown` waits for the orchestrators to finish the commands they are running
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+/// Most orchestrators the daemon keeps, whatever `[daemon] workers` asks for
+const MAX_WORKERS: usize = 8;
+/// How often sessions are checked for having been idle too long
+const SESSION_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+/// Commands that only read the index or memories, leaving a session's conversation
+/// and numbered results alone, so whichever worker is idle may run them
+const STATELESS_COMMANDS: &[&str] = &[
+    "/stats", "/deps", "/rdeps", "/read", "/summarize", "/memory", "/memory-show", "/analytics", "/help", "/sync-status",
+];
+/// Request for the chat commands as JSON (name, aliases, args, description,
+/// details, examples, group), answered without the orchestrator
+const COMMANDS_REQUEST: &str = "commands";
+/// Admin command listing the sessions (`/sessions`) or ending one (`/sessions end <id>`),
+/// answered by the router
+const SESSIONS_COMMAND: &str = "/sessions";
+/// Request stopping the request whose id is its args
+const CANCEL_REQUEST: &str = "cancel";
+/// What a `cancel` request is sent to the router as, with the id after it
+const CANCEL_COMMAND: &str = "/cancel";
+/// Request for the background jobs as JSON, or for one job when its id is the args
+const JOBS_REQUEST: &str = "jobs";
+/// Request stopping the daemon once the requests it has queued are answered
+const SHUTDOWN_REQUEST: &str = "shutdown";
+/// What a `shutdown` request is sent to the router as
+const SHUTDOWN_COMMAND: &str = "/shutdown";
+/// Chat commands switching the model or backend, which the router has every worker run
+const MODEL_COMMAND: &str = "/model";
+const BACKEND_COMMAND: &str = "/backend";
+/// Chat command re-reading sovereign.toml, which every worker runs and after which the
+/// router updates the directories watched
+const RELOAD_COMMAND: &str = "/reload";
+/// Notices held for WebSocket connections that fall behind
+const NOTICE_CAPACITY: usize = 16;
+/// Request for how the daemon is doing as JSON ([`DaemonStatus`])
+const STATUS_REQUEST: &str = "status";
+/// What a `status` request is sent to the router as
+const STATUS_COMMAND: &str = "/status";
+/// How long `DaemonClient::stop` waits for the daemon to go
+const STOP_WAIT: Duration = Duration::from_secs(15);
+
+/// `[daemon]` section of `sovereign.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DaemonConfig {
+    /// Orchestrators kept warm, each serving its own sessions, so one client's
+    /// long `/index` does not hold up another's chat (at most 8)
+    pub workers: usize,
+    /// Minutes a session may go without a request before its conversation is
+    /// forgotten; 0 keeps sessions until the daemon stops
+    pub session_idle_minutes: u64,
+    /// Directories watched and re-indexed on change, besides those given with `--watch`
+    pub watch: Vec<PathBuf>,
+    /// Web pages allowed to call the HTTP API and the WebSocket, such as the web UI's
+    /// `http://localhost:7657`; browsers sending any other `Origin` are refused
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            workers: 1,
+            session_idle_minutes: 60,
+            watch: Vec::new(),
+            allowed_origins: vec!["http://localhost:7657".to_string(), "http://127.0.0.1:7657".to_string()],
+        }
+    }
+}
 
-/// Message sent to the orchestrator thread
+/// Message sent to the orchestrators
 pub struct OrchestratorMessage {
     pub input: String,
+    /// Requests of one session always go to the same orchestrator; requests
+    /// without one share the default session
+    pub session: Option<String>,
+    /// Gets the model's text as it is generated, for clients that stream
+    pub tokens: Option<mpsc::UnboundedSender<String>>,
+    /// The client's name for the request, which a `cancel` request gives to stop it
+    pub id: Option<String>,
+    /// Gets the progress lines of a request run as a background job
+    pub progress: Option<mpsc::UnboundedSender<String>>,
     pub response_tx: oneshot::Sender<Result<String, String>>,
 }
 
+/// A request on its way to a worker, with what fires if the client cancels it
+type Routed = (OrchestratorMessage, Option<oneshot::Receiver<()>>);
+
+/// Message to one worker of the pool
+enum WorkerMessage {
+    Request(Routed),
+    /// Answer from the project another worker just switched to
+    Attach(PathBuf),
+    /// Forget a session's conversation
+    EndSession(String),
+    /// Run `/model`, `/backend` or `/reload`; the reply, with what the worker used
+    /// before and uses from now on
+    Reconfigure(String, oneshot::Sender<Result<(String, Using, Using), String>>),
+    /// Use a model another worker has checked, or go back to the one used before
+    UseModel(Using, oneshot::Sender<Result<(), String>>),
+}
+
+/// The backend and model a worker uses
+type Using = (LlmBackend, String);
+
 /// Daemon server for background Sovereign operation
 pub struct Daemon {
     request_tx: mpsc::Sender<OrchestratorMessage>,
-    watcher: Option<FileWatcher>,
+    /// Shared with the router, which changes what is watched on `/reload`
+    watching: Arc<Mutex<Watching>>,
     data_dir: PathBuf,
+    /// Directories the watcher re-indexes, for `status`
+    watched: Arc<std::sync::Mutex<Vec<PathBuf>>>,
+    /// Tells the workers to stop, since connections hold `request_tx` clones
+    shutdown_tx: Option<watch::Sender<bool>>,
+    /// Notified when a client asks the daemon to shut down
+    stop: Arc<Notify>,
+    worker_threads: Vec<thread::JoinHandle<()>>,
+    /// Address the TCP and WebSocket listeners bind to
+    bind: String,
+    /// Token TCP and WebSocket clients must send, required off loopback
+    token: Option<Arc<str>>,
+    /// How results are rendered for requests that don't pick a format
+    format: OutputFormat,
+    /// Certificate the TCP, WebSocket and HTTP listeners encrypt with
+    tls: Option<Tls>,
+    /// Requests run in the background
+    jobs: Jobs,
+    /// Changes every WebSocket client is told of, such as a switch of model
+    notices: broadcast::Sender<String>,
+    /// `[daemon] allowed_origins`, read when the daemon starts
+    origins: Arc<[String]>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DaemonRequest {
     pub command: String,
     pub args: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// markdown, plain, ansi, json or html; the daemon's default when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// Keeps the client's conversation on one orchestrator; the default session when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// Send the model's text as it is generated, one `chunk` line at a time, before the response
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
+    /// Names the request, so a `cancel` request with this id as its args can stop it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Answer at once with the id of a job running the request; `jobs` tells how it goes
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub background: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -43,6 +207,15 @@ pub struct DaemonResponse {
     pub success: bool,
     pub result: Option<String>,
     pub error: Option<String>,
+    /// Text the model just generated, on the lines a streaming request gets before its response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<String>,
+}
+
+impl DaemonResponse {
+    pub(crate) fn failure(error: impl Into<String>) -> Self {
+        Self { success: false, result: None, error: Some(error.into()), chunk: None }
+    }
 }
 
 /// WebSocket request message
@@ -51,57 +224,145 @@ pub struct WsRequest {
     pub id: String,
     pub command: String,
     pub args: Option<String>,
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default)]
+    pub format: Option<String>,
+    #[serde(default)]
+    pub session: Option<String>,
+    /// Run as a job, reporting its id in a `job` event and its progress in `progress` events
+    #[serde(default)]
+    pub background: bool,
 }
 
 /// WebSocket response message
 #[derive(Debug, Serialize, Deserialize)]
+///
+/// `token` events carry the model's text as it is generated; the rendered result
+/// follows as a `chunk`, then `complete`. `model` events, with an empty id, tell
+/// every client of a switch of model or backend.
 pub struct WsResponse {
     pub id: String,
-    pub event: String, // "chunk", "complete", "error"
+    pub event: String, // "token", "chunk", "complete", "error", "job", "progress", "model"
     pub data: Option<String>,
 }
 
 impl Daemon {
-    pub fn new(model: &str, data_dir: PathBuf) -> Result<Self> {
-        // Create channel for communicating with orchestrator thread
+    /// Start `[daemon] workers` orchestrators for `model` on `backend`, each on its own
+    /// thread, and the task routing requests to them; must be called within the Tokio runtime
+    pub fn new(model: &str, backend: LlmBackend, api_key: Option<&str>, data_dir: PathBuf, overrides: &Overrides) -> Result<Self> {
+        let config = Config::load(&data_dir, None)?.daemon;
+        let workers = config.workers.clamp(1, MAX_WORKERS);
+        let session_idle = (config.session_idle_minutes > 0).then(|| Duration::from_secs(config.session_idle_minutes * 60));
         let (request_tx, request_rx) = mpsc::channel::<OrchestratorMessage>(100);
+        let (stateless_tx, stateless_rx) = mpsc::channel::<Routed>(100);
+        let stateless_rx = Arc::new(Mutex::new(stateless_rx));
+        let pending_embeddings = Arc::new(AtomicUsize::new(0));
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        let (worker_txs, worker_rxs): (Vec<_>, Vec<_>) =
+            (0..workers).map(|_| mpsc::channel::<WorkerMessage>(100)).unzip();
+        let worker_threads = worker_rxs
+            .into_iter()
+            .enumerate()
+            .map(|(id, inbox)| {
+                let worker = Worker {
+                    id,
+                    model: model.to_string(),
+                    backend,
+                    api_key: api_key.map(str::to_string),
+                    data_dir: data_dir.clone(),
+                    overrides: overrides.clone(),
+                    inbox,
+                    stateless: stateless_rx.clone(),
+                    peers: worker_txs.clone(),
+                    shutdown: shutdown_rx.clone(),
+                    pending: pending_embeddings.clone(),
+                };
+                thread::spawn(move || worker.run())
+            })
+            .collect();
+        let watched = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let status = StatusSource {
+            model: Arc::new(std::sync::Mutex::new(model.to_string())),
+            data_dir: data_dir.clone(),
+            started_at: Utc::now(),
+            pending_embeddings,
+            watched: watched.clone(),
+        };
 
-        // Spawn a dedicated blocking thread for the orchestrator
-        let model = model.to_string();
-        let data_dir_clone = data_dir.clone();
+        let daemon = Self {
+            request_tx,
+            watching: Arc::new(Mutex::new(Watching::default())),
+            data_dir,
+            watched,
+            shutdown_tx: Some(shutdown_tx),
+            stop: Arc::new(Notify::new()),
+            worker_threads,
+            bind: net::DEFAULT_BIND.to_string(),
+            token: None,
+            format: OutputFormat::Markdown,
+            tls: None,
+            jobs: Jobs::new(),
+            notices: broadcast::channel(NOTICE_CAPACITY).0,
+            origins: config.allowed_origins.into(),
+        };
+        let stop = daemon.stop.clone();
+        tokio::spawn(route(request_rx, worker_txs, stateless_tx, session_idle, stop, status, daemon.reconfigurer()));
+        Ok(daemon)
+    }
 
-        thread::spawn(move || {
-            // Create a new runtime for this thread
-            let rt = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .expect("Failed to create runtime");
+    /// Render results in `format` for requests that don't ask for one
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
 
-            rt.block_on(async {
-                let mut orchestrator = match Orchestrator::new(&model, data_dir_clone) {
-                    Ok(o) => o,
-                    Err(e) => {
-                        eprintln!("Failed to create orchestrator: {}", e);
-                        return;
-                    }
-                };
+    /// Listen on `host` instead of loopback; any other address needs a `token`
+    pub fn with_bind(mut self, host: &str, token: Option<String>) -> Result<Self> {
+        net::check_bind(host, token.as_deref())?;
+        self.bind = host.to_string();
+        self.token = token.map(Arc::from);
+        Ok(self)
+    }
 
-                let mut request_rx = request_rx;
-                while let Some(msg) = request_rx.recv().await {
-                    let result = match orchestrator.process_command(&msg.input).await {
-                        Ok(r) => Ok(r),
-                        Err(e) => Err(e.to_string()),
-                    };
-                    let _ = msg.response_tx.send(result);
-                }
-            });
-        });
+    /// Encrypt the TCP, WebSocket and HTTP listeners with `tls`
+    pub fn with_tls(mut self, tls: Tls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
 
-        Ok(Self {
-            request_tx,
-            watcher: None,
-            data_dir,
-        })
+    /// Resolves once a client has sent a `shutdown` request
+    pub async fn stop_requested(&self) {
+        self.stop.notified().await
+    }
+
+    /// Stop the file watcher and the orchestrators, waiting at most `SHUTDOWN_TIMEOUT`
+    /// for them to answer the requests they have queued and save their memories
+    ///
+    /// Requests arriving meanwhile get an error reply.
+    pub async fn shutdown(mut self) {
+        let watcher = self.watching.lock().await.watcher.take();
+        if let Some(watcher) = watcher {
+            watcher.shutdown().await;
+        }
+
+        if let Some(shutdown_tx) = self.shutdown_tx.take() {
+            let _ = shutdown_tx.send(true);
+        }
+
+        let threads = std::mem::take(&mut self.worker_threads);
+        let join = tokio::task::spawn_blocking(move || threads.into_iter().map(|t| t.join()).all(|r| r.is_ok()));
+        match tokio::time::timeout(SHUTDOWN_TIMEOUT, join).await {
+            Ok(Ok(true)) => {}
+            Ok(_) => eprintln!("  An orchestrator thread panicked"),
+            Err(_) => eprintln!("  Orchestrator threads did not stop in time, leaving them behind"),
+        }
+
+        #[cfg(unix)]
+        {
+            let _ = std::fs::remove_file(self.data_dir.join(SOCKET_NAME));
+        }
     }
 
     /// Start the daemon with Unix socket (preferred on Unix systems)
@@ -121,8 +382,10 @@ impl Daemon {
             match listener.accept().await {
                 Ok((stream, _)) => {
                     let request_tx = self.request_tx.clone();
+                    let format = self.format;
+                    let jobs = self.jobs.clone();
                     tokio::spawn(async move {
-                        if let Err(e) = handle_unix_connection(stream, request_tx).await {
+                        if let Err(e) = handle_unix_connection(stream, request_tx, format, jobs).await {
                             eprintln!("Connection error: {}", e);
                         }
                     });
@@ -136,19 +399,24 @@ impl Daemon {
 
     /// Start the daemon with TCP (cross-platform)
     pub async fn start_tcp(&self, port: Option<u16>) -> Result<()> {
-        let port = port.unwrap_or(DEFAULT_PORT);
-        let addr = format!("127.0.0.1:{}", port);
-
-        let listener = TcpListener::bind(&addr).await?;
-        println!("Sovereign daemon listening on {}", addr);
+        let listener = net::bind_tcp(&self.bind, port.unwrap_or(DEFAULT_PORT), "Daemon").await?;
+        println!("Sovereign daemon listening on {}", listener.local_addr()?);
 
         loop {
             match listener.accept().await {
                 Ok((stream, peer)) => {
                     println!("Connection from {}", peer);
                     let request_tx = self.request_tx.clone();
+                    let token = self.token.clone();
+                    let format = self.format;
+                    let tls = self.tls.clone();
+                    let jobs = self.jobs.clone();
                     tokio::spawn(async move {
-                        if let Err(e) = handle_tcp_connection(stream, request_tx).await {
+                        let served = match tls::accept(stream, tls.as_ref()).await {
+                            Ok(stream) => handle_tcp_connection(stream, request_tx, token, format, jobs).await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = served {
                             eprintln!("Connection error: {}", e);
                         }
                     });
@@ -162,19 +430,26 @@ impl Daemon {
 
     /// Start the daemon with WebSocket support for real-time streaming
     pub async fn start_websocket(&self, port: Option<u16>) -> Result<()> {
-        let port = port.unwrap_or(DEFAULT_WS_PORT);
-        let addr = format!("127.0.0.1:{}", port);
-
-        let listener = TcpListener::bind(&addr).await?;
-        println!("Sovereign WebSocket server listening on ws://{}", addr);
+        let listener = net::bind_tcp(&self.bind, port.unwrap_or(DEFAULT_WS_PORT), "WebSocket").await?;
+        println!("Sovereign WebSocket server listening on ws://{}", listener.local_addr()?);
 
         loop {
             match listener.accept().await {
                 Ok((stream, peer)) => {
                     println!("WebSocket connection from {}", peer);
                     let request_tx = self.request_tx.clone();
+                    let token = self.token.clone();
+                    let format = self.format;
+                    let tls = self.tls.clone();
+                    let jobs = self.jobs.clone();
+                    let notices = self.notices.clone();
+                    let origins = self.origins.clone();
                     tokio::spawn(async move {
-                        if let Err(e) = handle_websocket_connection(stream, request_tx).await {
+                        let served = match tls::accept(stream, tls.as_ref()).await {
+                            Ok(stream) => handle_websocket_connection(stream, request_tx, token, origins, format, jobs, notices).await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = served {
                             eprintln!("WebSocket error: {}", e);
                         }
                     });
@@ -186,54 +461,696 @@ impl Daemon {
         }
     }
 
-    /// Start file watcher for auto-reindex
-    pub async fn start_watcher(&mut self, paths: Vec<PathBuf>) -> Result<()> {
-        let request_tx = self.request_tx.clone();
-        let mut watcher = FileWatcher::new(request_tx)?;
+    /// Start the HTTP API, for clients that would rather not speak the line protocol
+    pub async fn start_http(&self, port: Option<u16>) -> Result<()> {
+        let listener = net::bind_tcp(&self.bind, port.unwrap_or(DEFAULT_HTTP_PORT), "HTTP API").await?;
+        println!("Sovereign HTTP API listening on http://{}", listener.local_addr()?);
+        // Any web page can reach loopback, so the API wants a token even there
+        let token: Arc<str> = match &self.token {
+            Some(token) => token.clone(),
+            None => {
+                let token = net::local_token(&self.data_dir)?;
+                println!("  Token in {}", self.data_dir.join(net::LOCAL_TOKEN_FILE).display());
+                token.into()
+            }
+        };
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let request_tx = self.request_tx.clone();
+                    let token = token.clone();
+                    let origins = self.origins.clone();
+                    let format = self.format;
+                    let tls = self.tls.clone();
+                    let jobs = self.jobs.clone();
+                    tokio::spawn(async move {
+                        let served = match tls::accept(stream, tls.as_ref()).await {
+                            Ok(stream) => http_api::handle_connection(stream, request_tx, token, origins, format, jobs).await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = served {
+                            eprintln!("HTTP error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    eprintln!("HTTP accept error: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Serve Prometheus metrics at `/metrics` on `port`, with the same token and TLS as the API
+    pub async fn start_metrics(&self, port: Option<u16>) -> Result<()> {
+        let listener = net::bind_tcp(&self.bind, port.unwrap_or(DEFAULT_METRICS_PORT), "Metrics").await?;
+        println!("Sovereign metrics at http://{}/metrics", listener.local_addr()?);
 
-        for path in paths {
-            watcher.watch(&path)?;
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let token = self.token.clone();
+                    let tls = self.tls.clone();
+                    let data_dir = self.data_dir.clone();
+                    tokio::spawn(async move {
+                        let served = match tls::accept(stream, tls.as_ref()).await {
+                            Ok(stream) => http_api::handle_metrics_connection(stream, token, data_dir).await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = served {
+                            eprintln!("Metrics error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    eprintln!("Metrics accept error: {}", e);
+                }
+            }
         }
+    }
 
-        self.watcher = Some(watcher);
+    /// Watch `paths` and the directories `[daemon] watch` lists, re-indexing them on change
+    pub async fn start_watcher(&self, paths: Vec<PathBuf>) -> Result<()> {
+        for path in &paths {
+            if !path.is_dir() {
+                anyhow::bail!("Cannot watch {}: not a directory", path.display());
+            }
+        }
+        self.watching.lock().await.requested = paths;
+        self.reconfigurer().update_watches().await?;
         Ok(())
     }
 
-    /// Get the request channel for sending commands
-    pub fn request_channel(&self) -> mpsc::Sender<OrchestratorMessage> {
-        self.request_tx.clone()
+    /// Have every worker re-read sovereign.toml and update the directories watched, as
+    /// a `/reload` request does; what changed
+    pub async fn reload(&self) -> Result<String> {
+        let (response_tx, response_rx) = oneshot::channel();
+        let msg = OrchestratorMessage {
+            input: RELOAD_COMMAND.to_string(),
+            session: None,
+            tokens: None,
+            id: None,
+            progress: None,
+            response_tx,
+        };
+        self.request_tx.send(msg).await.map_err(|_| anyhow::anyhow!("The daemon is shutting down"))?;
+        response_rx.await?.map_err(anyhow::Error::msg)
+    }
+
+    fn reconfigurer(&self) -> Reconfigurer {
+        Reconfigurer {
+            notices: self.notices.clone(),
+            watching: self.watching.clone(),
+            watched: self.watched.clone(),
+            data_dir: self.data_dir.clone(),
+            requests: self.request_tx.downgrade(),
+        }
     }
+}
+
+/// An orchestrator of the pool, with its queue of session requests
+struct Worker {
+    id: usize,
+    model: String,
+    backend: LlmBackend,
+    api_key: Option<String>,
+    data_dir: PathBuf,
+    overrides: Overrides,
+    inbox: mpsc::Receiver<WorkerMessage>,
+    /// Stateless requests, taken by whichever worker is idle
+    stateless: Arc<Mutex<mpsc::Receiver<Routed>>>,
+    /// Every worker's queue, this one's included
+    peers: Vec<mpsc::Sender<WorkerMessage>>,
+    shutdown: watch::Receiver<bool>,
+    pending: Arc<AtomicUsize>,
+}
+
+impl Worker {
+    fn run(self) {
+        // Each orchestrator gets its own thread and runtime, as it is not Send
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to create runtime");
+
+        let Worker { id, model, backend, api_key, data_dir, overrides, mut inbox, stateless, peers, mut shutdown, pending } = self;
+        rt.block_on(async {
+            let mut orchestrator = match Orchestrator::new(&model, backend, api_key.as_deref(), data_dir, &overrides) {
+                Ok(o) => o,
+                Err(e) => {
+                    eprintln!("Failed to create orchestrator: {}", e);
+                    return;
+                }
+            };
+
+            // Upkeep is the first worker's job, so it is not done once per worker
+            let upkeep = id == 0;
+            let mut reembed_tick = tokio::time::interval(REEMBED_INTERVAL);
+            let mut digest_tick = tokio::time::interval(DIGEST_CHECK_INTERVAL);
+            let mut prune_tick = tokio::time::interval(PRUNE_INTERVAL);
+            let mut consolidate_tick = tokio::time::interval(CONSOLIDATE_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    _ = shutdown.changed() => {
+                        // Answer what is queued, but take nothing more
+                        inbox.close();
+                        while let Some(msg) = inbox.recv().await {
+                            if let WorkerMessage::Request((msg, cancel)) = msg {
+                                orchestrator.switch_session(msg.session.as_deref().unwrap_or_default());
+                                run_request(&mut orchestrator, msg, cancel, id, &peers).await;
+                            }
+                        }
+                        break;
+                    }
+                    msg = inbox.recv() => match msg {
+                        Some(WorkerMessage::Request((msg, cancel))) => {
+                            orchestrator.switch_session(msg.session.as_deref().unwrap_or_default());
+                            run_request(&mut orchestrator, msg, cancel, id, &peers).await
+                        }
+                        Some(WorkerMessage::Attach(root)) => {
+                            if let Err(e) = orchestrator.load_codebase(&root) {
+                                eprintln!("  Worker {} could not open {}: {}", id, root.display(), e);
+                            }
+                        }
+                        Some(WorkerMessage::EndSession(session)) => {
+                            orchestrator.end_session(&session);
+                        }
+                        Some(WorkerMessage::Reconfigure(input, reply)) => {
+                            let before = (orchestrator.backend(), orchestrator.model().to_string());
+                            let done = orchestrator.process_command(&input).await.map_err(|e| e.to_string());
+                            let after = (orchestrator.backend(), orchestrator.model().to_string());
+                            let _ = reply.send(done.map(|text| (text, before, after)));
+                        }
+                        Some(WorkerMessage::UseModel((backend, model), reply)) => {
+                            let _ = reply.send(orchestrator.use_model(backend, &model).map_err(|e| e.to_string()));
+                        }
+                        None => break,
+                    },
+                    Some((msg, cancel)) = next_stateless(&stateless) => {
+                        run_request(&mut orchestrator, msg, cancel, id, &peers).await
+                    }
+                    _ = reembed_tick.tick(), if upkeep => {
+                        // Keep vectors in sync with files the watcher re-indexed
+                        match orchestrator.process_embedding_queue(REEMBED_BATCH).await {
+                            Ok(Some((processed, remaining))) => {
+                                pending.store(remaining, Ordering::Relaxed);
+                                if processed > 0 {
+                                    println!("  Re-embedded {} changed file(s), {} pending", processed, remaining);
+                                }
+                            }
+                            Ok(None) => {}
+                            Err(e) => eprintln!("  Re-embed error: {}", e),
+                        }
+                    }
+                    _ = digest_tick.tick(), if upkeep => {
+                        match orchestrator.write_scheduled_digest() {
+                            Ok(Some(path)) => println!("  Memory digest written to {}", path.display()),
+                            Ok(None) => {}
+                            Err(e) => eprintln!("  Digest error: {}", e),
+                        }
+                    }
+                    _ = prune_tick.tick(), if upkeep => {
+                        match orchestrator.prune_memories(true) {
+                            Ok(pruned) if !pruned.is_empty() && !overrides.dry_run => {
+                                println!("  Pruned {} faded memories", pruned.len())
+                            }
+                            Ok(_) => {}
+                            Err(e) => eprintln!("  Memory pruning error: {}", e),
+                        }
+                    }
+                    _ = consolidate_tick.tick(), if upkeep => {
+                        match orchestrator.consolidate_memories(true).await {
+                            Ok(consolidated) if !consolidated.is_empty() && !overrides.dry_run => {
+                                let replaced: usize = consolidated.iter().map(|c| c.cluster.memories.len()).sum();
+                                println!("  Condensed {} old conversations into {} topics", replaced, consolidated.len())
+                            }
+                            Ok(_) => {}
+                            Err(e) => eprintln!("  Memory consolidation error: {}", e),
+                        }
+                    }
+                }
+            }
+
+            if let Err(e) = orchestrator.crdt_memory.save() {
+                eprintln!("  Worker {} could not save memories: {}", id, e);
+            }
+            orchestrator.shutdown_lsp().await;
+        });
+    }
+}
+
+/// Run a request and reply, unless the client cancels it first; when it switched
+/// projects, the other workers follow
+async fn run_request(
+    orchestrator: &mut Orchestrator,
+    msg: OrchestratorMessage,
+    cancel: Option<oneshot::Receiver<()>>,
+    id: usize,
+    peers: &[mpsc::Sender<WorkerMessage>],
+) {
+    let root = orchestrator.project_root().cloned();
+    let started = Instant::now();
+    let command = orchestrator.process_command(&msg.input);
+    let (tokens, progress) = (msg.tokens, msg.progress);
+    let work = async move {
+        let command = async move {
+            match tokens {
+                Some(tokens) => llm::stream_tokens_to(tokens, command).await,
+                None => command.await,
+            }
+        };
+        match progress {
+            Some(progress) => jobs::report_progress_to(progress, command).await,
+            None => command.await,
+        }
+    };
+    // Dropping the command stops it where it is, the model's reply included
+    let result = tokio::select! {
+        result = work => result,
+        _ = cancelled(cancel) => Err(anyhow::anyhow!("Cancelled")),
+    };
+    metrics::record_request(&msg.input, started.elapsed(), result.is_ok());
+    let _ = msg.response_tx.send(result.map_err(|e| e.to_string()));
+
+    let Some(new_root) = orchestrator.project_root().filter(|r| Some(*r) != root.as_ref()) else {
+        return;
+    };
+    for (peer_id, peer) in peers.iter().enumerate() {
+        // A full queue means a busy worker; it opens the project on its next `/index`
+        if peer_id != id && peer.try_send(WorkerMessage::Attach(new_root.clone())).is_err() {
+            eprintln!("  Worker {} is busy and keeps its project", peer_id);
+        }
+    }
+}
+
+async fn next_stateless(queue: &Mutex<mpsc::Receiver<Routed>>) -> Option<Routed> {
+    queue.lock().await.recv().await
+}
+
+/// Resolves when the client cancels; never for requests that cannot be cancelled
+async fn cancelled(cancel: Option<oneshot::Receiver<()>>) {
+    if let Some(cancel) = cancel {
+        if cancel.await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+/// Send each request to the worker of its session, or to any idle worker if it is
+/// stateless; answer `/sessions`, `/cancel`, `/status` and `/shutdown`, have every
+/// worker run `/model`, `/backend` and `/reload`, and end sessions idle for longer
+/// than `session_idle`
+async fn route(
+    mut requests: mpsc::Receiver<OrchestratorMessage>,
+    workers: Vec<mpsc::Sender<WorkerMessage>>,
+    stateless: mpsc::Sender<Routed>,
+    session_idle: Option<Duration>,
+    stop: Arc<Notify>,
+    status: StatusSource,
+    reconfigurer: Reconfigurer,
+) {
+    let mut sessions = Sessions::new(workers.len());
+    // Requests with an id, queued or running, and how to cancel each
+    let mut in_flight: HashMap<String, oneshot::Sender<()>> = HashMap::new();
+    let mut expiry_tick = tokio::time::interval(SESSION_EXPIRY_INTERVAL);
+    loop {
+        let msg = tokio::select! {
+            msg = requests.recv() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = expiry_tick.tick(), if session_idle.is_some() => {
+                let idle = session_idle.unwrap_or_default();
+                for (session, worker) in sessions.expire(idle, Instant::now()) {
+                    let _ = workers[worker].send(WorkerMessage::EndSession(session)).await;
+                }
+                continue;
+            }
+        };
+
+        let mut words = msg.input.split_whitespace();
+        if words.next() == Some(SESSIONS_COMMAND) {
+            let reply = match (words.next(), words.next()) {
+                (None, _) => Ok(sessions.describe(Instant::now())),
+                (Some("end"), Some(session)) => match sessions.end(session) {
+                    Some(worker) => {
+                        let _ = workers[worker].send(WorkerMessage::EndSession(session.to_string())).await;
+                        Ok(format!("Ended session '{}'.", session))
+                    }
+                    None => Err(format!("No session '{}'.", session)),
+                },
+                _ => Err("Usage: /sessions, or /sessions end <id>".to_string()),
+            };
+            let _ = msg.response_tx.send(reply);
+            continue;
+        }
+
+        if msg.input.trim() == STATUS_COMMAND {
+            // Requests sent to a worker but not yet taken from its queue
+            let queued = workers.iter().map(|w| w.max_capacity() - w.capacity()).sum::<usize>()
+                + stateless.max_capacity()
+                - stateless.capacity();
+            let reply = serde_json::to_string(&status.report(workers.len(), queued, sessions.assigned.len()));
+            let _ = msg.response_tx.send(reply.map_err(|e| e.to_string()));
+            continue;
+        }
+
+        if msg.input.trim() == SHUTDOWN_COMMAND {
+            let _ = msg.response_tx.send(Ok("Shutting down.".to_string()));
+            stop.notify_one();
+            continue;
+        }
+
+        let mut words = msg.input.split_whitespace();
+        let switch = matches!(words.next(), Some(MODEL_COMMAND | BACKEND_COMMAND)) && words.next().is_some();
+        if switch || msg.input.trim() == RELOAD_COMMAND {
+            // Not awaited, so requests keep being routed while the workers finish what they have queued
+            tokio::spawn(reconfigurer.clone().run(msg, workers.clone(), status.model.clone()));
+            continue;
+        }
+
+        let mut words = msg.input.split_whitespace();
+        if words.next() == Some(CANCEL_COMMAND) {
+            let id = words.next().unwrap_or_default();
+            let reply = match in_flight.remove(id).map(|cancel| cancel.send(())) {
+                Some(Ok(())) => Ok(format!("Cancelled request '{}'.", id)),
+                _ => Err(format!("No request '{}' in flight.", id)),
+            };
+            let _ = msg.response_tx.send(reply);
+            continue;
+        }
+
+        // A worker drops its end once it has replied
+        in_flight.retain(|_, cancel| !cancel.is_closed());
+        let cancel = msg.id.clone().map(|id| {
+            let (cancel_tx, cancel_rx) = oneshot::channel();
+            in_flight.insert(id, cancel_tx);
+            cancel_rx
+        });
+
+        // Dropping a message closes its response channel, which the client is told
+        if workers.len() > 1 && is_stateless(&msg.input) {
+            let _ = stateless.send((msg, cancel)).await;
+            continue;
+        }
+        let worker = sessions.worker_for(msg.session.as_deref().unwrap_or_default(), Instant::now());
+        let _ = workers[worker].send(WorkerMessage::Request((msg, cancel))).await;
+    }
+}
+
+/// Run `command` on the first worker, then on the others: a reload is run on each in
+/// turn, while a switch of model the first worker checked and made is made on the
+/// others as it is, and undone on every worker should one of them refuse it. On
+/// success `model` is updated and the first worker's reply returned
+async fn on_every_worker(
+    command: &str,
+    workers: &[mpsc::Sender<WorkerMessage>],
+    model: &std::sync::Mutex<String>,
+) -> Result<String, String> {
+    let Some((first, others)) = workers.split_first() else {
+        return Err("No orchestrator is running".to_string());
+    };
+    let (reply, before, after) = ask(first, |reply| WorkerMessage::Reconfigure(command.to_string(), reply)).await?;
+    if command.trim() == RELOAD_COMMAND {
+        for worker in others {
+            ask(worker, |reply| WorkerMessage::Reconfigure(command.to_string(), reply)).await?;
+        }
+        return Ok(reply);
+    }
+
+    for (i, worker) in others.iter().enumerate() {
+        if let Err(e) = ask(worker, |reply| WorkerMessage::UseModel(after.clone(), reply)).await {
+            // The first worker and the others before this one have switched
+            for switched in &workers[..=i] {
+                let _ = ask(switched, |reply| WorkerMessage::UseModel(before.clone(), reply)).await;
+            }
+            return Err(format!("{}; still using {}", e, before.1));
+        }
+    }
+    *model.lock().unwrap_or_else(|e| e.into_inner()) = after.1;
+    Ok(reply)
+}
+
+/// Send `worker` the message `message` makes of a reply channel, and wait for the reply
+async fn ask<T>(
+    worker: &mpsc::Sender<WorkerMessage>,
+    message: impl FnOnce(oneshot::Sender<Result<T, String>>) -> WorkerMessage,
+) -> Result<T, String> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    if worker.send(message(reply_tx)).await.is_err() {
+        return Err("Orchestrator thread terminated".to_string());
+    }
+    reply_rx.await.map_err(|_| "Response channel closed".to_string())?
+}
+
+/// The file watcher, and the directories it watches whatever `[daemon] watch` says
+#[derive(Default)]
+struct Watching {
+    watcher: Option<FileWatcher>,
+    /// Given to `start_watcher`, as with `--watch`
+    requested: Vec<PathBuf>,
+}
+
+/// What the router needs to have the daemon take another model or a reloaded config
+#[derive(Clone)]
+struct Reconfigurer {
+    /// Told of a switch of model, for WebSocket clients
+    notices: broadcast::Sender<String>,
+    watching: Arc<Mutex<Watching>>,
+    watched: Arc<std::sync::Mutex<Vec<PathBuf>>>,
+    data_dir: PathBuf,
+    /// Weak, so the router still stops once every client has gone
+    requests: mpsc::WeakSender<OrchestratorMessage>,
+}
+
+impl Reconfigurer {
+    /// Have every worker run the `/model`, `/backend` or `/reload` of `msg`, then tell
+    /// WebSocket clients of a switch, or update the directories watched after a reload
+    async fn run(self, msg: OrchestratorMessage, workers: Vec<mpsc::Sender<WorkerMessage>>, model: Arc<std::sync::Mutex<String>>) {
+        let reply = match on_every_worker(&msg.input, &workers, &model).await {
+            Ok(text) if msg.input.trim() == RELOAD_COMMAND => match self.update_watches().await {
+                Ok(changes) => Ok(std::iter::once(text).chain(changes).collect::<Vec<_>>().join("\n")),
+                Err(e) => Err(format!("{}\n{}", text, e)),
+            },
+            Ok(text) => {
+                let _ = self.notices.send(text.clone());
+                Ok(text)
+            }
+            Err(e) => Err(e),
+        };
+        let _ = msg.response_tx.send(reply);
+    }
+
+    /// Watch the requested directories and those `[daemon] watch` lists, stop watching
+    /// any no longer listed, and take up changed `index.exclude` patterns; a line for
+    /// each directory added or dropped
+    async fn update_watches(&self) -> Result<Vec<String>> {
+        let config = Config::load(&self.data_dir, None)?;
+        let mut changes = Vec::new();
+        let mut watching = self.watching.lock().await;
+        let mut wanted: Vec<PathBuf> = Vec::new();
+        for path in watching.requested.iter().chain(&config.daemon.watch) {
+            match path.canonicalize() {
+                Ok(path) if !wanted.contains(&path) => wanted.push(path),
+                Ok(_) => {}
+                // One directory gone missing should not stop the others being watched
+                Err(e) => {
+                    let problem = format!("Not watching {}: {}", path.display(), e);
+                    eprintln!("  {}", problem);
+                    changes.push(problem);
+                }
+            }
+        }
+
+        let Some(requests) = self.requests.upgrade() else {
+            anyhow::bail!("The daemon is shutting down");
+        };
+        if watching.watcher.is_none() && !wanted.is_empty() {
+            watching.watcher = Some(FileWatcher::new(requests.clone())?);
+        }
+        let Some(watcher) = watching.watcher.as_mut() else {
+            return Ok(changes);
+        };
+        for path in watcher.watched_paths() {
+            if !wanted.contains(&path) {
+                watcher.unwatch(&path)?;
+                changes.push(format!("Stopped watching {}", path.display()));
+            }
+        }
+        let mut added = Vec::new();
+        for path in &wanted {
+            let project = Config::load(&self.data_dir, Some(path))?;
+            if watcher.watched_paths().contains(path) {
+                watcher.set_excludes(path, &project.index.exclude)?;
+            } else {
+                watcher.watch(path, &project.index.exclude).with_context(|| format!("Cannot watch {}", path.display()))?;
+                added.push(path.clone());
+            }
+        }
+        *self.watched.lock().unwrap_or_else(|e| e.into_inner()) = wanted;
+        drop(watching);
+
+        for path in added {
+            // Index up front, so `/whatchanged` reports every change from here on
+            let (response_tx, response_rx) = oneshot::channel();
+            let msg = OrchestratorMessage {
+                input: format!("/index {}", path.display()),
+                session: None,
+                tokens: None,
+                id: None,
+                progress: None,
+                response_tx,
+            };
+            if requests.send(msg).await.is_ok() {
+                if let Ok(Err(e)) = response_rx.await {
+                    eprintln!("  Index error: {}", e);
+                }
+            }
+            changes.push(format!("Watching {}", path.display()));
+        }
+        Ok(changes)
+    }
+}
+
+fn is_stateless(input: &str) -> bool {
+    let command = input.split_whitespace().next().unwrap_or_default();
+    STATELESS_COMMANDS.contains(&command)
+}
 
-    /// Get daemon status
-    pub fn status(&self) -> DaemonStatus {
+/// A session the router has seen
+struct Session {
+    worker: usize,
+    requests: usize,
+    last_seen: Instant,
+}
+
+/// Which worker serves each session: the one with the fewest sessions when it is first seen
+struct Sessions {
+    assigned: HashMap<String, Session>,
+    /// Sessions per worker
+    load: Vec<usize>,
+}
+
+impl Sessions {
+    fn new(workers: usize) -> Self {
+        Self { assigned: HashMap::new(), load: vec![0; workers] }
+    }
+
+    fn worker_for(&mut self, session: &str, now: Instant) -> usize {
+        if let Some(seen) = self.assigned.get_mut(session) {
+            seen.requests += 1;
+            seen.last_seen = now;
+            return seen.worker;
+        }
+        let worker = (0..self.load.len()).min_by_key(|&w| self.load[w]).unwrap_or(0);
+        self.load[worker] += 1;
+        self.assigned.insert(session.to_string(), Session { worker, requests: 1, last_seen: now });
+        worker
+    }
+
+    /// Forget `session`; the worker that served it, if it was known
+    fn end(&mut self, session: &str) -> Option<usize> {
+        let ended = self.assigned.remove(session)?;
+        self.load[ended.worker] -= 1;
+        Some(ended.worker)
+    }
+
+    /// End the sessions idle for longer than `idle`, but the default one; each with its worker
+    fn expire(&mut self, idle: Duration, now: Instant) -> Vec<(String, usize)> {
+        let idle_sessions: Vec<String> = self
+            .assigned
+            .iter()
+            .filter(|(session, seen)| !session.is_empty() && now.duration_since(seen.last_seen) > idle)
+            .map(|(session, _)| session.clone())
+            .collect();
+        idle_sessions
+            .into_iter()
+            .filter_map(|session| self.end(&session).map(|worker| (session, worker)))
+            .collect()
+    }
+
+    /// One line per session: its worker, requests and how long it has been idle
+    fn describe(&self, now: Instant) -> String {
+        if self.assigned.is_empty() {
+            return "No sessions yet.".to_string();
+        }
+        let mut sessions: Vec<(&String, &Session)> = self.assigned.iter().collect();
+        sessions.sort_by_key(|(_, seen)| std::cmp::Reverse(seen.last_seen));
+        sessions
+            .into_iter()
+            .map(|(session, seen)| {
+                format!(
+                    "  {} (worker {}, {} requests, idle {}s)",
+                    if session.is_empty() { "(default)" } else { session.as_str() },
+                    seen.worker,
+                    seen.requests,
+                    now.duration_since(seen.last_seen).as_secs()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// What the router reports in a `status` besides its own queues and sessions
+struct StatusSource {
+    /// Changed by `/model` and `/backend`
+    model: Arc<std::sync::Mutex<String>>,
+    data_dir: PathBuf,
+    started_at: DateTime<Utc>,
+    pending_embeddings: Arc<AtomicUsize>,
+    watched: Arc<std::sync::Mutex<Vec<PathBuf>>>,
+}
+
+impl StatusSource {
+    fn report(&self, workers: usize, queued: usize, sessions: usize) -> DaemonStatus {
         DaemonStatus {
-            running: true,
-            watching: self.watcher.is_some(),
+            model: self.model.lock().unwrap_or_else(|e| e.into_inner()).clone(),
             data_dir: self.data_dir.clone(),
+            started_at: self.started_at,
+            uptime_secs: (Utc::now() - self.started_at).num_seconds().max(0) as u64,
+            watching: self.watched.lock().unwrap_or_else(|e| e.into_inner()).clone(),
+            workers,
+            queued,
+            sessions,
+            pending_embeddings: self.pending_embeddings.load(Ordering::Relaxed),
         }
     }
 }
 
-#[derive(Debug, Serialize)]
+/// Answer to a `status` request
+#[derive(Debug, Serialize, Deserialize)]
 pub struct DaemonStatus {
-    pub running: bool,
-    pub watching: bool,
+    pub model: String,
     pub data_dir: PathBuf,
+    pub started_at: DateTime<Utc>,
+    pub uptime_secs: u64,
+    /// Directories the watcher re-indexes on change
+    pub watching: Vec<PathBuf>,
+    pub workers: usize,
+    /// Requests waiting for a worker to take them
+    pub queued: usize,
+    pub sessions: usize,
+    /// Changed files still waiting to be re-embedded
+    pub pending_embeddings: usize,
 }
 
 #[cfg(unix)]
 async fn handle_unix_connection(
     stream: UnixStream,
     request_tx: mpsc::Sender<OrchestratorMessage>,
+    format: OutputFormat,
+    jobs: Jobs,
 ) -> Result<()> {
     let (reader, mut writer) = stream.into_split();
     let mut reader = BufReader::new(reader);
     let mut line = String::new();
 
     while reader.read_line(&mut line).await? > 0 {
-        let response = process_request(&line, &request_tx).await;
-        let json = serde_json::to_string(&response)? + "\n";
-        writer.write_all(json.as_bytes()).await?;
+        // The socket file's permissions already limit who can connect
+        answer_line(&line, &mut writer, &request_tx, None, format, &jobs).await?;
         line.clear();
     }
 
@@ -241,76 +1158,197 @@ async fn handle_unix_connection(
 }
 
 async fn handle_tcp_connection(
-    stream: TcpStream,
+    stream: Box<dyn Connection>,
     request_tx: mpsc::Sender<OrchestratorMessage>,
+    token: Option<Arc<str>>,
+    format: OutputFormat,
+    jobs: Jobs,
 ) -> Result<()> {
-    let (reader, mut writer) = stream.into_split();
+    let (reader, mut writer) = tokio::io::split(stream);
     let mut reader = BufReader::new(reader);
     let mut line = String::new();
 
     while reader.read_line(&mut line).await? > 0 {
-        let response = process_request(&line, &request_tx).await;
-        let json = serde_json::to_string(&response)? + "\n";
-        writer.write_all(json.as_bytes()).await?;
+        // A web page posting to this port sends HTTP, whose body must not be read as requests
+        let trimmed = line.trim_end();
+        if trimmed.ends_with(" HTTP/1.1") || trimmed.ends_with(" HTTP/1.0") {
+            return Ok(());
+        }
+        answer_line(&line, &mut writer, &request_tx, token.as_deref(), format, &jobs).await?;
         line.clear();
     }
 
     Ok(())
 }
 
-async fn process_request(
-    request_str: &str,
+/// Write the response to the request on `line`, after a `chunk` line for each piece
+/// of text the model generates if the request streams
+async fn answer_line<W: AsyncWrite + Unpin>(
+    line: &str,
+    writer: &mut W,
     request_tx: &mpsc::Sender<OrchestratorMessage>,
-) -> DaemonResponse {
-    let request: DaemonRequest = match serde_json::from_str(request_str) {
-        Ok(r) => r,
-        Err(e) => {
-            return DaemonResponse {
-                success: false,
-                result: None,
-                error: Some(format!("Invalid request: {}", e)),
+    token: Option<&str>,
+    format: OutputFormat,
+    jobs: &Jobs,
+) -> Result<()> {
+    let (tokens_tx, mut tokens) = mpsc::unbounded_channel();
+    let response = process_request(line, request_tx, token, format, tokens_tx, jobs);
+    tokio::pin!(response);
+
+    // The worker sends every token before it replies, so none are left behind
+    let response = loop {
+        tokio::select! {
+            biased;
+            Some(text) = tokens.recv() => {
+                let chunk = DaemonResponse { success: true, result: None, error: None, chunk: Some(text) };
+                writer.write_all((serde_json::to_string(&chunk)? + "\n").as_bytes()).await?;
             }
+            response = &mut response => break response,
         }
     };
+    writer.write_all((serde_json::to_string(&response)? + "\n").as_bytes()).await?;
+    Ok(())
+}
+
+async fn process_request(
+    request_str: &str,
+    request_tx: &mpsc::Sender<OrchestratorMessage>,
+    token: Option<&str>,
+    default_format: OutputFormat,
+    tokens: mpsc::UnboundedSender<String>,
+    jobs: &Jobs,
+) -> DaemonResponse {
+    match serde_json::from_str(request_str) {
+        Ok(request) => dispatch(request, request_tx, token, default_format, tokens, jobs).await,
+        Err(e) => DaemonResponse::failure(format!("Invalid request: {}", e)),
+    }
+}
+
+/// Check a request's token and format, then answer it: `commands` and `jobs` here,
+/// anything else by the orchestrators, with generated text going to `tokens` if it
+/// streams; a `background` request is answered with the id of its job
+pub(crate) async fn dispatch(
+    request: DaemonRequest,
+    request_tx: &mpsc::Sender<OrchestratorMessage>,
+    token: Option<&str>,
+    default_format: OutputFormat,
+    tokens: mpsc::UnboundedSender<String>,
+    jobs: &Jobs,
+) -> DaemonResponse {
+    if !net::token_matches(token, request.token.as_deref()) {
+        return DaemonResponse::failure("Invalid or missing token");
+    }
 
-    let input = if let Some(args) = &request.args {
-        format!("{} {}", request.command, args)
-    } else {
-        request.command.clone()
+    let format = match OutputFormat::requested(request.format.as_deref(), default_format) {
+        Ok(format) => format,
+        Err(e) => return DaemonResponse::failure(e),
     };
 
+    if request.command == COMMANDS_REQUEST {
+        return match serde_json::to_string(COMMANDS) {
+            Ok(json) => DaemonResponse { success: true, result: Some(json), error: None, chunk: None },
+            Err(e) => DaemonResponse::failure(e.to_string()),
+        };
+    }
+
+    if request.command == JOBS_REQUEST {
+        return match jobs_json(jobs, request.args.as_deref()) {
+            Ok(json) => DaemonResponse { success: true, result: Some(json), error: None, chunk: None },
+            Err(e) => DaemonResponse::failure(e),
+        };
+    }
+
+    let input = request_input(&request.command, request.args.as_deref());
+
+    if request.background {
+        return match submit_job(input, request.session, format, request_tx, jobs).await {
+            Ok(id) => DaemonResponse { success: true, result: Some(id), error: None, chunk: None },
+            Err(e) => DaemonResponse::failure(e),
+        };
+    }
+
     // Send request through channel and wait for response
     let (response_tx, response_rx) = oneshot::channel();
     let msg = OrchestratorMessage {
         input,
+        session: request.session,
+        tokens: request.stream.then_some(tokens),
+        id: request.id,
+        progress: None,
         response_tx,
     };
 
     if request_tx.send(msg).await.is_err() {
-        return DaemonResponse {
-            success: false,
-            result: None,
-            error: Some("Orchestrator thread terminated".to_string()),
-        };
+        return DaemonResponse::failure("Orchestrator thread terminated");
     }
 
     match response_rx.await {
         Ok(Ok(result)) => DaemonResponse {
             success: true,
-            result: Some(result),
+            result: Some(render_result(&request.command, format, result)),
             error: None,
+            chunk: None,
         },
-        Ok(Err(e)) => DaemonResponse {
-            success: false,
-            result: None,
-            error: Some(e),
-        },
-        Err(_) => DaemonResponse {
-            success: false,
-            result: None,
-            error: Some("Response channel closed".to_string()),
-        },
+        Ok(Err(e)) => DaemonResponse::failure(e),
+        Err(_) => DaemonResponse::failure("Response channel closed"),
+    }
+}
+
+/// Start a job running `input`, recording its progress and rendered result in `jobs`;
+/// the job's id
+async fn submit_job(
+    input: String,
+    session: Option<String>,
+    format: OutputFormat,
+    request_tx: &mpsc::Sender<OrchestratorMessage>,
+    jobs: &Jobs,
+) -> Result<String, String> {
+    let id = jobs.start(&input);
+    let (response_tx, response_rx) = oneshot::channel();
+    let (progress_tx, mut progress) = mpsc::unbounded_channel();
+    let msg = OrchestratorMessage {
+        input,
+        session,
+        tokens: None,
+        // So `cancel` stops the job by its id
+        id: Some(id.clone()),
+        progress: Some(progress_tx),
+        response_tx,
+    };
+    if request_tx.send(msg).await.is_err() {
+        jobs.finish(&id, Err("Orchestrator thread terminated".to_string()));
+        return Err("Orchestrator thread terminated".to_string());
     }
+
+    let (job, jobs) = (id.clone(), jobs.clone());
+    tokio::spawn(async move {
+        // The worker drops its end of `progress` once the command is done
+        let record = async {
+            while let Some(line) = progress.recv().await {
+                jobs.progress(&job, line);
+            }
+        };
+        let ((), response) = tokio::join!(record, response_rx);
+        let result = match response {
+            Ok(Ok(result)) => Ok(format.renderer().render(&result)),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err("Response channel closed".to_string()),
+        };
+        jobs.finish(&job, result);
+    });
+    Ok(id)
+}
+
+/// Every job as a JSON array, or the job `id` as a JSON object
+fn jobs_json(jobs: &Jobs, id: Option<&str>) -> Result<String, String> {
+    let json = match id.map(str::trim).filter(|id| !id.is_empty()) {
+        None => serde_json::to_string(&jobs.list()),
+        Some(id) => match jobs.get(id) {
+            Some(job) => serde_json::to_string(&job),
+            None => return Err(format!("No job '{}'", id)),
+        },
+    };
+    json.map_err(|e| e.to_string())
 }
 
 /// Client for connecting to the daemon
@@ -318,177 +1356,258 @@ pub struct DaemonClient {
     #[cfg(unix)]
     socket_path: Option<PathBuf>,
     tcp_addr: Option<String>,
+    /// SHA-256 of the certificate a `--tls` daemon presents
+    fingerprint: Option<String>,
 }
 
 impl DaemonClient {
     #[cfg(unix)]
-    pub fn unix(data_dir: &PathBuf) -> Self {
+    pub fn unix(data_dir: &Path) -> Self {
         Self {
             socket_path: Some(data_dir.join(SOCKET_NAME)),
             tcp_addr: None,
+            fingerprint: None,
         }
     }
 
-    pub fn tcp(port: Option<u16>) -> Self {
+    /// The daemon listening on `host`, as its `--bind` gave it; one listening on every
+    /// address is reached on loopback
+    pub fn tcp(host: &str, port: Option<u16>) -> Self {
+        let host = match host {
+            "0.0.0.0" => "127.0.0.1",
+            "::" => "::1",
+            host => host,
+        };
         let port = port.unwrap_or(DEFAULT_PORT);
         Self {
             #[cfg(unix)]
             socket_path: None,
-            tcp_addr: Some(format!("127.0.0.1:{}", port)),
+            tcp_addr: Some(if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) }),
+            fingerprint: None,
         }
     }
 
+    /// Speak TLS to a `--tls` daemon, trusting only the certificate with this SHA-256
+    pub fn with_tls(mut self, fingerprint: String) -> Self {
+        self.fingerprint = Some(fingerprint);
+        self
+    }
+
     pub async fn send(&self, request: DaemonRequest) -> Result<DaemonResponse> {
+        self.send_streaming(request, |_| {}).await
+    }
+
+    /// Send `request`, handing the text of each `chunk` line to `on_chunk` until the
+    /// response arrives; only requests with `stream` set get chunks
+    pub async fn send_streaming(&self, request: DaemonRequest, mut on_chunk: impl FnMut(&str)) -> Result<DaemonResponse> {
         let request_json = serde_json::to_string(&request)? + "\n";
 
         #[cfg(unix)]
         if let Some(ref socket_path) = self.socket_path {
             let stream = UnixStream::connect(socket_path).await?;
-            return self.send_to_unix_stream(stream, &request_json).await;
+            return self.send_to_unix_stream(stream, &request_json, &mut on_chunk).await;
         }
 
         if let Some(ref addr) = self.tcp_addr {
-            let stream = TcpStream::connect(addr).await?;
-            return self.send_to_tcp_stream(stream, &request_json).await;
+            let stream = tls::connect(addr, self.fingerprint.as_deref()).await?;
+            return self.send_to_tcp_stream(stream, &request_json, &mut on_chunk).await;
         }
 
         Err(anyhow::anyhow!("No connection method specified"))
     }
 
     #[cfg(unix)]
-    async fn send_to_unix_stream(&self, stream: UnixStream, request: &str) -> Result<DaemonResponse> {
+    async fn send_to_unix_stream(&self, stream: UnixStream, request: &str, on_chunk: &mut impl FnMut(&str)) -> Result<DaemonResponse> {
         let (reader, mut writer) = stream.into_split();
         writer.write_all(request.as_bytes()).await?;
-
-        let mut reader = BufReader::new(reader);
-        let mut response_line = String::new();
-        reader.read_line(&mut response_line).await?;
-
-        let response: DaemonResponse = serde_json::from_str(&response_line)?;
-        Ok(response)
+        read_response(BufReader::new(reader), on_chunk).await
     }
 
-    async fn send_to_tcp_stream(&self, stream: TcpStream, request: &str) -> Result<DaemonResponse> {
-        let (reader, mut writer) = stream.into_split();
+    async fn send_to_tcp_stream(&self, stream: Box<dyn Connection>, request: &str, on_chunk: &mut impl FnMut(&str)) -> Result<DaemonResponse> {
+        let (reader, mut writer) = tokio::io::split(stream);
         writer.write_all(request.as_bytes()).await?;
+        read_response(BufReader::new(reader), on_chunk).await
+    }
 
-        let mut reader = BufReader::new(reader);
-        let mut response_line = String::new();
-        reader.read_line(&mut response_line).await?;
+    /// Ask the running daemon to shut down and wait for it to go; false if none was running
+    pub async fn stop(&self, token: Option<String>) -> Result<bool> {
+        if !self.answers(token.clone()).await {
+            return Ok(false);
+        }
+        let request = DaemonRequest {
+            command: SHUTDOWN_REQUEST.to_string(),
+            args: None,
+            token: token.clone(),
+            format: None,
+            session: None,
+            stream: false,
+            id: None,
+            background: false,
+        };
+        let response = self.send(request).await?;
+        if !response.success {
+            anyhow::bail!(response.error.unwrap_or_else(|| "The daemon refused to stop".to_string()));
+        }
 
-        let response: DaemonResponse = serde_json::from_str(&response_line)?;
-        Ok(response)
+        let deadline = tokio::time::Instant::now() + STOP_WAIT;
+        while self.answers(token.clone()).await {
+            if tokio::time::Instant::now() > deadline {
+                anyhow::bail!("The daemon is still running after {} seconds", STOP_WAIT.as_secs());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        Ok(true)
+    }
+
+    /// How the running daemon is doing
+    pub async fn status(&self, token: Option<String>) -> Result<DaemonStatus> {
+        let request = DaemonRequest {
+            command: STATUS_REQUEST.to_string(),
+            args: None,
+            token,
+            format: None,
+            session: None,
+            stream: false,
+            id: None,
+            background: false,
+        };
+        let response = self.send(request).await?;
+        match (response.success, response.result) {
+            (true, Some(json)) => Ok(serde_json::from_str(&json)?),
+            _ => anyhow::bail!(response.error.unwrap_or_else(|| "The daemon gave no status".to_string())),
+        }
     }
 
     pub async fn is_running(&self) -> bool {
+        self.answers(net::resolve_token(None)).await
+    }
+
+    /// Whether a daemon answers requests sending `token`
+    async fn answers(&self, token: Option<String>) -> bool {
         let request = DaemonRequest {
             command: "/stats".to_string(),
             args: None,
+            token,
+            format: None,
+            session: None,
+            stream: false,
+            id: None,
+            background: false,
         };
         self.send(request).await.is_ok()
     }
 }
 
-/// Handle a WebSocket connection
+/// Read response lines until the one that is not a `chunk`
+async fn read_response<R: AsyncBufRead + Unpin>(mut reader: R, on_chunk: &mut impl FnMut(&str)) -> Result<DaemonResponse> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            anyhow::bail!("The daemon closed the connection without a response");
+        }
+        let response: DaemonResponse = serde_json::from_str(&line)?;
+        match response.chunk {
+            Some(ref text) => on_chunk(text),
+            None => return Ok(response),
+        }
+    }
+}
+
+/// Handle a WebSocket connection; its requests run side by side, so a `cancel`
+/// can stop one while it is still generating
 async fn handle_websocket_connection(
-    stream: TcpStream,
+    stream: Box<dyn Connection>,
     request_tx: mpsc::Sender<OrchestratorMessage>,
+    token: Option<Arc<str>>,
+    origins: Arc<[String]>,
+    default_format: OutputFormat,
+    jobs: Jobs,
+    notices: broadcast::Sender<String>,
 ) -> Result<()> {
-    let ws_stream = tokio_tungstenite::accept_async(stream).await?;
+    // A page the user visits may open a WebSocket to loopback; only listed origins get one.
+    // The error type is tungstenite's
+    #[allow(clippy::result_large_err)]
+    let check_origin = |request: &Request, response: Response| {
+        let origin = request.headers().get("origin").map(|o| o.to_str().unwrap_or_default());
+        if net::origin_allowed(origin, &origins) {
+            return Ok(response);
+        }
+        let mut refused = ErrorResponse::new(Some("Origin not allowed".to_string()));
+        *refused.status_mut() = StatusCode::FORBIDDEN;
+        Err(refused)
+    };
+    let ws_stream = tokio_tungstenite::accept_hdr_async(stream, check_origin).await?;
     let (mut write, mut read) = ws_stream.split();
 
+    // Requests answer through one writer, so their events do not interleave mid-message
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel::<Message>();
+    let writer = tokio::spawn(async move {
+        while let Some(message) = out_rx.recv().await {
+            if write.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Notices go to clients that could send requests: all of them, unless a token is required
+    let mut forwarder = token.is_none().then(|| tokio::spawn(forward_notices(notices.subscribe(), out_tx.clone())));
+
     while let Some(msg) = read.next().await {
         match msg {
             Ok(Message::Text(text)) => {
                 let ws_request: WsRequest = match serde_json::from_str(&text) {
                     Ok(r) => r,
                     Err(e) => {
-                        let error_response = WsResponse {
-                            id: "unknown".to_string(),
-                            event: "error".to_string(),
-                            data: Some(format!("Invalid request: {}", e)),
-                        };
-                        let json = serde_json::to_string(&error_response)?;
-                        write.send(Message::Text(json)).await?;
+                        let _ = out_tx.send(ws_event("unknown", "error", Some(format!("Invalid request: {}", e)))?);
                         continue;
                     }
                 };
 
-                let input = if let Some(args) = &ws_request.args {
-                    format!("{} {}", ws_request.command, args)
-                } else {
-                    ws_request.command.clone()
-                };
+                if !net::token_matches(token.as_deref(), ws_request.token.as_deref()) {
+                    let _ = out_tx.send(ws_event(&ws_request.id, "error", Some("Invalid or missing token".to_string()))?);
+                    continue;
+                }
+                if forwarder.is_none() {
+                    forwarder = Some(tokio::spawn(forward_notices(notices.subscribe(), out_tx.clone())));
+                }
 
-                // Send request through channel and wait for response
-                let (response_tx, response_rx) = oneshot::channel();
-                let msg = OrchestratorMessage {
-                    input,
-                    response_tx,
+                let format = match OutputFormat::requested(ws_request.format.as_deref(), default_format) {
+                    Ok(format) => format,
+                    Err(e) => {
+                        let _ = out_tx.send(ws_event(&ws_request.id, "error", Some(e))?);
+                        continue;
+                    }
                 };
 
-                if request_tx.send(msg).await.is_err() {
-                    let error_response = WsResponse {
-                        id: ws_request.id.clone(),
-                        event: "error".to_string(),
-                        data: Some("Orchestrator thread terminated".to_string()),
-                    };
-                    let json = serde_json::to_string(&error_response)?;
-                    write.send(Message::Text(json)).await?;
+                if ws_request.command == COMMANDS_REQUEST {
+                    let _ = out_tx.send(ws_event(&ws_request.id, "chunk", Some(serde_json::to_string(COMMANDS)?))?);
+                    let _ = out_tx.send(ws_event(&ws_request.id, "complete", None)?);
                     continue;
                 }
 
-                match response_rx.await {
-                    Ok(Ok(result)) => {
-                        // Send result in chunks for streaming effect
-                        let chunk_size = 100;
-                        let chunks: Vec<&str> = result
-                            .as_bytes()
-                            .chunks(chunk_size)
-                            .map(|c| std::str::from_utf8(c).unwrap_or(""))
-                            .collect();
-
-                        for chunk in chunks {
-                            let chunk_response = WsResponse {
-                                id: ws_request.id.clone(),
-                                event: "chunk".to_string(),
-                                data: Some(chunk.to_string()),
-                            };
-                            let json = serde_json::to_string(&chunk_response)?;
-                            write.send(Message::Text(json)).await?;
+                if ws_request.command == JOBS_REQUEST {
+                    match jobs_json(&jobs, ws_request.args.as_deref()) {
+                        Ok(json) => {
+                            let _ = out_tx.send(ws_event(&ws_request.id, "chunk", Some(json))?);
+                            let _ = out_tx.send(ws_event(&ws_request.id, "complete", None)?);
+                        }
+                        Err(e) => {
+                            let _ = out_tx.send(ws_event(&ws_request.id, "error", Some(e))?);
                         }
-
-                        let complete_response = WsResponse {
-                            id: ws_request.id.clone(),
-                            event: "complete".to_string(),
-                            data: None,
-                        };
-                        let json = serde_json::to_string(&complete_response)?;
-                        write.send(Message::Text(json)).await?;
-                    }
-                    Ok(Err(e)) => {
-                        let error_response = WsResponse {
-                            id: ws_request.id.clone(),
-                            event: "error".to_string(),
-                            data: Some(e),
-                        };
-                        let json = serde_json::to_string(&error_response)?;
-                        write.send(Message::Text(json)).await?;
-                    }
-                    Err(_) => {
-                        let error_response = WsResponse {
-                            id: ws_request.id.clone(),
-                            event: "error".to_string(),
-                            data: Some("Response channel closed".to_string()),
-                        };
-                        let json = serde_json::to_string(&error_response)?;
-                        write.send(Message::Text(json)).await?;
                     }
+                    continue;
+                }
+
+                if ws_request.background {
+                    tokio::spawn(follow_ws_job(ws_request, request_tx.clone(), format, out_tx.clone(), jobs.clone()));
+                    continue;
                 }
+                tokio::spawn(answer_ws_request(ws_request, request_tx.clone(), format, out_tx.clone()));
             }
             Ok(Message::Close(_)) => break,
             Ok(Message::Ping(data)) => {
-                write.send(Message::Pong(data)).await?;
+                let _ = out_tx.send(Message::Pong(data));
             }
             Err(e) => {
                 eprintln!("WebSocket message error: {}", e);
@@ -498,5 +1617,194 @@ async fn handle_websocket_connection(
         }
     }
 
+    writer.abort();
+    if let Some(forwarder) = forwarder {
+        forwarder.abort();
+    }
     Ok(())
 }
+
+/// Send each notice as a `model` event with an empty id, as no request asked for it
+async fn forward_notices(mut notices: broadcast::Receiver<String>, out: mpsc::UnboundedSender<Message>) {
+    loop {
+        match notices.recv().await {
+            Ok(notice) => {
+                if let Ok(event) = ws_event("", "model", Some(notice)) {
+                    let _ = out.send(event);
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Run one WebSocket request, sending its `token` events, then the result as a
+/// `chunk` and `complete`, or an `error`
+async fn answer_ws_request(
+    ws_request: WsRequest,
+    request_tx: mpsc::Sender<OrchestratorMessage>,
+    format: OutputFormat,
+    out: mpsc::UnboundedSender<Message>,
+) -> Result<()> {
+    let id = ws_request.id;
+    let input = request_input(&ws_request.command, ws_request.args.as_deref());
+
+    // Send request through channel and wait for response
+    let (response_tx, response_rx) = oneshot::channel();
+    let (tokens_tx, mut tokens) = mpsc::unbounded_channel();
+    let msg = OrchestratorMessage {
+        input,
+        session: ws_request.session,
+        tokens: Some(tokens_tx),
+        id: Some(id.clone()),
+        progress: None,
+        response_tx,
+    };
+
+    if request_tx.send(msg).await.is_err() {
+        let _ = out.send(ws_event(&id, "error", Some("Orchestrator thread terminated".to_string()))?);
+        return Ok(());
+    }
+
+    // The worker drops its end once the command is done, just before replying
+    while let Some(text) = tokens.recv().await {
+        let _ = out.send(ws_event(&id, "token", Some(text))?);
+    }
+
+    match response_rx.await {
+        Ok(Ok(result)) => {
+            let _ = out.send(ws_event(&id, "chunk", Some(render_result(&ws_request.command, format, result)))?);
+            let _ = out.send(ws_event(&id, "complete", None)?);
+        }
+        Ok(Err(e)) => {
+            let _ = out.send(ws_event(&id, "error", Some(e))?);
+        }
+        Err(_) => {
+            let _ = out.send(ws_event(&id, "error", Some("Response channel closed".to_string()))?);
+        }
+    }
+    Ok(())
+}
+
+/// Run a WebSocket request as a job: a `job` event with the job's id, a `progress`
+/// event for each line it reports, then the result as a `chunk` and `complete`, or an `error`
+async fn follow_ws_job(
+    ws_request: WsRequest,
+    request_tx: mpsc::Sender<OrchestratorMessage>,
+    format: OutputFormat,
+    out: mpsc::UnboundedSender<Message>,
+    jobs: Jobs,
+) -> Result<()> {
+    let id = ws_request.id;
+    let input = request_input(&ws_request.command, ws_request.args.as_deref());
+    // Before submitting, so no update is missed
+    let mut updates = jobs.subscribe();
+    let job = match submit_job(input, ws_request.session, format, &request_tx, &jobs).await {
+        Ok(job) => job,
+        Err(e) => {
+            let _ = out.send(ws_event(&id, "error", Some(e))?);
+            return Ok(());
+        }
+    };
+    let _ = out.send(ws_event(&id, "job", Some(job.clone()))?);
+
+    loop {
+        let update = match updates.recv().await {
+            Ok(update) if update.id == job => update,
+            Ok(_) => continue,
+            // Updates were missed; the job as it is now stands in for them
+            Err(broadcast::error::RecvError::Lagged(_)) => match jobs.get(&job) {
+                Some(update) => update,
+                None => break,
+            },
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        match update.state {
+            JobState::Running => {
+                if let Some(line) = update.progress.last() {
+                    let _ = out.send(ws_event(&id, "progress", Some(line.clone()))?);
+                }
+            }
+            JobState::Done => {
+                let _ = out.send(ws_event(&id, "chunk", update.result)?);
+                let _ = out.send(ws_event(&id, "complete", None)?);
+                break;
+            }
+            JobState::Failed | JobState::Cancelled => {
+                let _ = out.send(ws_event(&id, "error", update.error)?);
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether `message` would run as a command rather than be chatted about: a chat
+/// command, or one of the requests the daemon answers itself
+pub(crate) fn is_command(message: &str) -> bool {
+    let message = message.trim();
+    message.starts_with('/')
+        || [COMMANDS_REQUEST, CANCEL_REQUEST, JOBS_REQUEST, SHUTDOWN_REQUEST, STATUS_REQUEST].contains(&message)
+}
+
+/// The orchestrator input for `command` and its args; `cancel`, `status` and
+/// `shutdown` go to the router
+fn request_input(command: &str, args: Option<&str>) -> String {
+    let command = match command {
+        CANCEL_REQUEST => CANCEL_COMMAND,
+        STATUS_REQUEST => STATUS_COMMAND,
+        SHUTDOWN_REQUEST => SHUTDOWN_COMMAND,
+        command => command,
+    };
+    match args {
+        Some(args) => format!("{} {}", command, args),
+        None => command.to_string(),
+    }
+}
+
+/// `result` rendered in `format`, but for `status`, whose JSON is sent as it is
+fn render_result(command: &str, format: OutputFormat, result: String) -> String {
+    if command == STATUS_REQUEST {
+        return result;
+    }
+    format.renderer().render(&result)
+}
+
+fn ws_event(id: &str, event: &str, data: Option<String>) -> Result<Message> {
+    let response = WsResponse { id: id.to_string(), event: event.to_string(), data };
+    Ok(Message::Text(serde_json::to_string(&response)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sessions_stick_to_the_least_loaded_worker() {
+        let start = Instant::now();
+        let mut sessions = Sessions::new(3);
+        // The default session is the first seen, so it stays on the first worker
+        assert_eq!(sessions.worker_for("", start), 0);
+        assert_eq!(sessions.worker_for("vscode-1", start), 1);
+        assert_eq!(sessions.worker_for("web-1", start), 2);
+        assert_eq!(sessions.worker_for("vscode-1", start), 1);
+        assert_eq!(sessions.worker_for("web-2", start), 0);
+
+        // Idle sessions end and free their worker; the default session stays
+        let later = start + Duration::from_secs(120);
+        sessions.worker_for("vscode-1", later);
+        let mut expired = sessions.expire(Duration::from_secs(60), later);
+        expired.sort();
+        assert_eq!(expired, vec![("web-1".to_string(), 2), ("web-2".to_string(), 0)]);
+        assert_eq!(sessions.worker_for("web-3", later), 2);
+        assert!(sessions.describe(later).contains("  vscode-1 (worker 1, 3 requests, idle 0s)"));
+        assert_eq!(sessions.end("vscode-1"), Some(1));
+        assert_eq!(sessions.end("vscode-1"), None);
+
+        assert!(is_stateless("/stats"));
+        assert!(is_stateless("/deps src/main.rs"));
+        assert!(!is_stateless("/search config loading"));
+        assert!(!is_stateless("how does indexing work?"));
+    }
+}
@@ -2,9 +2,24 @@ use anyhow::{Context, Result};
 use futures::StreamExt;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::io::{self, Write};
+
+use crate::llm::{ChatMessage, GenerationOptions, LimitedStream, Servers};
+use crate::metrics;
 
 const DEEPSEEK_BASE_URL: &str = "https://api.deepseek.com";
+/// Tokens the DeepSeek chat and coder models see of a request
+pub const DEEPSEEK_CONTEXT_TOKENS: u32 = 65_536;
+/// Environment variable pointing the client at another OpenAI-compatible endpoint
+pub const DEEPSEEK_BASE_URL_ENV: &str = "DEEPSEEK_BASE_URL";
+
+/// `DEEPSEEK_BASE_URL` if set, otherwise the DeepSeek API
+fn base_url() -> String {
+    std::env::var(DEEPSEEK_BASE_URL_ENV)
+        .ok()
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| DEEPSEEK_BASE_URL.to_string())
+}
 
 /// DeepSeek model options
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -32,15 +47,11 @@ impl DeepSeekModel {
     }
 }
 
-impl Default for DeepSeekModel {
-    fn default() -> Self {
-        DeepSeekModel::DeepSeekChat
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct DeepSeekClient {
     client: Client,
+    /// Base URL of the API
+    base_url: String,
     api_key: String,
     model: String,
 }
@@ -54,18 +65,13 @@ struct ChatRequest {
     temperature: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     max_tokens: Option<u32>,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ChatMessage {
-    pub role: String,
-    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize)]
 struct ChatResponse {
     choices: Vec<Choice>,
-    #[allow(dead_code)]
     usage: Option<Usage>,
 }
 
@@ -84,17 +90,24 @@ struct DeltaMessage {
 
 #[derive(Debug, Deserialize)]
 struct Usage {
-    #[allow(dead_code)]
     prompt_tokens: u32,
-    #[allow(dead_code)]
     completion_tokens: u32,
     #[allow(dead_code)]
     total_tokens: u32,
 }
 
+impl Usage {
+    fn record(&self) {
+        metrics::record_llm_tokens(self.prompt_tokens.into(), self.completion_tokens.into());
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct StreamChunk {
     choices: Vec<Choice>,
+    /// On the last chunk
+    #[serde(default)]
+    usage: Option<Usage>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -115,12 +128,13 @@ impl DeepSeekClient {
     ///
     /// # Arguments
     /// * `api_key` - DeepSeek API key
-    /// * `model` - Model name (e.g., "deepseek-chat", "deepseek-coder")
+    /// * `model` - Model name (e.g., "deepseek-chat", "deepseek-coder", or just "chat" or "coder")
     pub fn new(api_key: &str, model: &str) -> Self {
         Self {
             client: Client::new(),
+            base_url: base_url(),
             api_key: api_key.to_string(),
-            model: model.to_string(),
+            model: DeepSeekModel::from_str(model).map_or(model, |m| m.as_str()).to_string(),
         }
     }
 
@@ -131,26 +145,21 @@ impl DeepSeekClient {
         Ok(Self::new(&api_key, model))
     }
 
-    /// Get the current model name
-    pub fn model(&self) -> &str {
-        &self.model
-    }
-
-    /// Switch to a different model
-    pub fn set_model(&mut self, model: &str) {
-        self.model = model.to_string();
-    }
-
-    /// List available models
-    pub fn list_models() -> Vec<String> {
-        vec![
-            "deepseek-chat".to_string(),
-            "deepseek-coder".to_string(),
-        ]
+    /// Ask the API in `servers`, if it names one
+    pub fn with_servers(mut self, servers: &Servers) -> Self {
+        if let Some(url) = &servers.deepseek {
+            self.base_url = url.clone();
+        }
+        self
     }
 
-    /// Generate a response (non-streaming)
-    pub async fn generate(&self, prompt: &str, system: Option<&str>) -> Result<String> {
+    /// Generate a response with length limits and stop sequences
+    pub async fn generate_with(
+        &self,
+        prompt: &str,
+        system: Option<&str>,
+        options: &GenerationOptions,
+    ) -> Result<String> {
         let mut messages = Vec::new();
 
         if let Some(sys) = system {
@@ -165,11 +174,16 @@ impl DeepSeekClient {
             content: prompt.to_string(),
         });
 
-        self.chat(&messages, false).await
+        self.chat_with(&messages, false, options).await
     }
 
-    /// Generate a response with streaming output
-    pub async fn generate_streaming(&self, prompt: &str, system: Option<&str>) -> Result<String> {
+    /// Generate a streaming response with length limits and stop sequences
+    pub async fn generate_streaming_with(
+        &self,
+        prompt: &str,
+        system: Option<&str>,
+        options: &GenerationOptions,
+    ) -> Result<String> {
         let mut messages = Vec::new();
 
         if let Some(sys) = system {
@@ -184,30 +198,40 @@ impl DeepSeekClient {
             content: prompt.to_string(),
         });
 
-        self.chat(&messages, true).await
+        self.chat_with(&messages, true, options).await
     }
 
-    /// Chat with the model
-    pub async fn chat(&self, messages: &[ChatMessage], stream: bool) -> Result<String> {
+    /// Chat with length limits and stop sequences
+    pub async fn chat_with(
+        &self,
+        messages: &[ChatMessage],
+        stream: bool,
+        options: &GenerationOptions,
+    ) -> Result<String> {
         let request = ChatRequest {
             model: self.model.clone(),
             messages: messages.to_vec(),
             stream,
             temperature: None,
-            max_tokens: None,
+            max_tokens: options.max_tokens,
+            stop: if options.stop.is_empty() { None } else { Some(options.stop.clone()) },
         };
 
         if stream {
-            self.chat_streaming(&request).await
+            self.chat_streaming(&request, options).await
         } else {
-            self.chat_non_streaming(&request).await
+            self.chat_non_streaming(&request, options).await
         }
     }
 
-    async fn chat_non_streaming(&self, request: &ChatRequest) -> Result<String> {
+    async fn chat_non_streaming(
+        &self,
+        request: &ChatRequest,
+        options: &GenerationOptions,
+    ) -> Result<String> {
         let response = self
             .client
-            .post(format!("{}/chat/completions", DEEPSEEK_BASE_URL))
+            .post(format!("{}/chat/completions", self.base_url))
             .header("Authorization", format!("Bearer {}", self.api_key))
             .header("Content-Type", "application/json")
             .json(request)
@@ -227,19 +251,27 @@ impl DeepSeekClient {
 
         let result: ChatResponse = serde_json::from_str(&body)
             .context("Failed to parse DeepSeek response")?;
+        if let Some(usage) = &result.usage {
+            usage.record();
+        }
 
-        Ok(result
+        let content = result
             .choices
             .first()
             .and_then(|c| c.message.as_ref())
             .map(|m| m.content.clone())
-            .unwrap_or_default())
+            .unwrap_or_default();
+        Ok(options.apply(&content))
     }
 
-    async fn chat_streaming(&self, request: &ChatRequest) -> Result<String> {
+    async fn chat_streaming(
+        &self,
+        request: &ChatRequest,
+        options: &GenerationOptions,
+    ) -> Result<String> {
         let response = self
             .client
-            .post(format!("{}/chat/completions", DEEPSEEK_BASE_URL))
+            .post(format!("{}/chat/completions", self.base_url))
             .header("Authorization", format!("Bearer {}", self.api_key))
             .header("Content-Type", "application/json")
             .json(request)
@@ -257,10 +289,10 @@ impl DeepSeekClient {
         }
 
         let mut stream = response.bytes_stream();
-        let mut full_response = String::new();
+        let mut reply = LimitedStream::new(options);
         let mut buffer = String::new();
 
-        while let Some(chunk) = stream.next().await {
+        'stream: while let Some(chunk) = stream.next().await {
             let chunk = chunk?;
             if let Ok(text) = std::str::from_utf8(&chunk) {
                 buffer.push_str(text);
@@ -279,12 +311,15 @@ impl DeepSeekClient {
                         }
 
                         if let Ok(chunk) = serde_json::from_str::<StreamChunk>(data) {
+                            if let Some(usage) = &chunk.usage {
+                                usage.record();
+                            }
                             for choice in chunk.choices {
                                 if let Some(delta) = choice.delta {
                                     if let Some(content) = delta.content {
-                                        print!("{}", content);
-                                        io::stdout().flush()?;
-                                        full_response.push_str(&content);
+                                        if reply.push(&content)? {
+                                            break 'stream;
+                                        }
                                     }
                                 }
                             }
@@ -302,9 +337,8 @@ impl DeepSeekClient {
                 }
             }
         }
-        println!();
 
-        Ok(full_response)
+        reply.finish()
     }
 
     /// Check if the API is available and the key is valid
@@ -319,10 +353,11 @@ impl DeepSeekClient {
             stream: false,
             temperature: Some(0.0),
             max_tokens: Some(1),
+            stop: None,
         };
 
         self.client
-            .post(format!("{}/chat/completions", DEEPSEEK_BASE_URL))
+            .post(format!("{}/chat/completions", self.base_url))
             .header("Authorization", format!("Bearer {}", self.api_key))
             .header("Content-Type", "application/json")
             .json(&request)
@@ -331,91 +366,6 @@ impl DeepSeekClient {
             .map(|r| r.status().is_success())
             .unwrap_or(false)
     }
-
-    /// Chat with streaming that returns a receiver for chunks instead of printing
-    pub async fn chat_stream(
-        &self,
-        messages: &[ChatMessage],
-    ) -> Result<tokio::sync::mpsc::Receiver<String>> {
-        let (tx, rx) = tokio::sync::mpsc::channel::<String>(100);
-
-        let request = ChatRequest {
-            model: self.model.clone(),
-            messages: messages.to_vec(),
-            stream: true,
-            temperature: None,
-            max_tokens: None,
-        };
-
-        let response = self
-            .client
-            .post(format!("{}/chat/completions", DEEPSEEK_BASE_URL))
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await
-            .context("Failed to connect to DeepSeek API")?;
-
-        let status = response.status();
-        if !status.is_success() {
-            let body = response.text().await?;
-            if let Ok(error_response) = serde_json::from_str::<ErrorResponse>(&body) {
-                anyhow::bail!("DeepSeek API error: {}", error_response.error.message);
-            }
-            anyhow::bail!("DeepSeek API error ({}): {}", status, body);
-        }
-
-        let mut stream = response.bytes_stream();
-
-        tokio::spawn(async move {
-            let mut buffer = String::new();
-
-            while let Some(chunk) = stream.next().await {
-                if let Ok(chunk) = chunk {
-                    if let Ok(text) = std::str::from_utf8(&chunk) {
-                        buffer.push_str(text);
-
-                        // Process SSE data lines
-                        for line in buffer.lines() {
-                            let line = line.trim();
-                            if line.is_empty() {
-                                continue;
-                            }
-
-                            // SSE format: data: {...}
-                            if let Some(data) = line.strip_prefix("data: ") {
-                                if data == "[DONE]" {
-                                    continue;
-                                }
-
-                                if let Ok(chunk) = serde_json::from_str::<StreamChunk>(data) {
-                                    for choice in chunk.choices {
-                                        if let Some(delta) = choice.delta {
-                                            if let Some(content) = delta.content {
-                                                if tx.send(content).await.is_err() {
-                                                    return;
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-
-                        // Clear processed content
-                        if buffer.ends_with('\n') {
-                            buffer.clear();
-                        } else if let Some(last_newline) = buffer.rfind('\n') {
-                            buffer = buffer[last_newline + 1..].to_string();
-                        }
-                    }
-                }
-            }
-        });
-
-        Ok(rx)
-    }
 }
 
 #[cfg(test)]
@@ -435,12 +385,7 @@ mod tests {
     fn test_model_as_str() {
         assert_eq!(DeepSeekModel::DeepSeekChat.as_str(), "deepseek-chat");
         assert_eq!(DeepSeekModel::DeepSeekCoder.as_str(), "deepseek-coder");
-    }
-
-    #[test]
-    fn test_list_models() {
-        let models = DeepSeekClient::list_models();
-        assert!(models.contains(&"deepseek-chat".to_string()));
-        assert!(models.contains(&"deepseek-coder".to_string()));
+        assert_eq!(DeepSeekClient::new("key", "coder").model, "deepseek-coder");
+        assert_eq!(DeepSeekClient::new("key", "my-finetune").model, "my-finetune");
     }
 }
@@ -0,0 +1,299 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::storage::memory::{Memory, MemoryType, STOPWORDS};
+use crate::storage::MemoryStore;
+
+/// Most memories read into one digest
+const MAX_DIGEST_MEMORIES: usize = 5000;
+
+/// Topics listed in a digest
+const MAX_TOPICS: usize = 10;
+
+/// Words that differ between rephrasings of the same question
+const QUESTION_WORDS: &[&str] = &["how", "why", "who", "the", "can", "did", "are"];
+
+/// File in the digest directory recording when the last digest was written
+const LAST_DIGEST_FILE: &str = ".last-digest";
+
+/// `[digest]` section of `sovereign.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DigestConfig {
+    /// Let the daemon write a digest every `interval_days`
+    pub scheduled: bool,
+    pub interval_days: u32,
+    /// Where notes are written (default: `digests/` in the data directory)
+    pub output_dir: Option<PathBuf>,
+}
+
+impl Default for DigestConfig {
+    fn default() -> Self {
+        Self {
+            scheduled: false,
+            interval_days: 7,
+            output_dir: None,
+        }
+    }
+}
+
+impl DigestConfig {
+    pub fn output_dir(&self, data_dir: &Path) -> PathBuf {
+        self.output_dir
+            .clone()
+            .unwrap_or_else(|| data_dir.join("digests"))
+    }
+
+    /// Whether a scheduled digest is due; never true unless scheduling is on
+    pub fn is_due(&self, data_dir: &Path, now: DateTime<Utc>) -> bool {
+        if !self.scheduled {
+            return false;
+        }
+
+        let marker = self.output_dir(data_dir).join(LAST_DIGEST_FILE);
+        let last = std::fs::read_to_string(marker)
+            .ok()
+            .and_then(|s| DateTime::parse_from_rfc3339(s.trim()).ok())
+            .map(|dt| dt.with_timezone(&Utc));
+
+        match last {
+            Some(last) => now - last >= Duration::days(self.interval_days as i64),
+            None => true,
+        }
+    }
+}
+
+/// A question asked more than once in the period
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecurringQuestion {
+    /// The first wording seen
+    pub question: String,
+    pub count: usize,
+}
+
+/// What memory captured over a period, grouped for reading
+#[derive(Debug, Clone)]
+pub struct Digest {
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub decisions: Vec<Memory>,
+    pub preferences: Vec<Memory>,
+    pub code_patterns: Vec<Memory>,
+    pub recurring_questions: Vec<RecurringQuestion>,
+    /// Most frequent language and symbol tags, with counts
+    pub topics: Vec<(String, usize)>,
+    pub total_memories: usize,
+}
+
+impl Digest {
+    /// Digest of the memories created in the last `days` days
+    pub fn collect(store: &MemoryStore, days: u32, now: DateTime<Utc>) -> Result<Self> {
+        let since = now - Duration::days(days as i64);
+        let memories = store.get_since(since, MAX_DIGEST_MEMORIES)?;
+        Ok(Self::from_memories(&memories, since, now))
+    }
+
+    pub fn from_memories(memories: &[Memory], since: DateTime<Utc>, until: DateTime<Utc>) -> Self {
+        let of_type = |t: MemoryType| -> Vec<Memory> {
+            let mut selected: Vec<Memory> = Vec::new();
+            for memory in memories.iter().filter(|m| m.memory_type == t) {
+                // The same preference is often stated several times
+                if !selected.iter().any(|s| s.content == memory.content) {
+                    selected.push(memory.clone());
+                }
+            }
+            selected
+        };
+
+        Self {
+            since,
+            until,
+            decisions: of_type(MemoryType::Decision),
+            preferences: of_type(MemoryType::Preference),
+            code_patterns: of_type(MemoryType::CodePattern),
+            recurring_questions: recurring_questions(memories),
+            topics: topics(memories),
+            total_memories: memories.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total_memories == 0
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!(
+            "# Sovereign digest: {} to {}\n\n{} new memories.\n",
+            self.since.format("%Y-%m-%d"),
+            self.until.format("%Y-%m-%d"),
+            self.total_memories
+        );
+
+        let section = |out: &mut String, title: &str, items: &[Memory]| {
+            if items.is_empty() {
+                return;
+            }
+            out.push_str(&format!("\n## {}\n\n", title));
+            for memory in items {
+                let project = memory.project.as_deref().map(|p| format!(" _({})_", p)).unwrap_or_default();
+                out.push_str(&format!(
+                    "- {}{} — {}\n",
+                    single_line(&memory.content),
+                    project,
+                    memory.created_at.format("%Y-%m-%d")
+                ));
+            }
+        };
+
+        section(&mut out, "Decisions", &self.decisions);
+        section(&mut out, "Preferences", &self.preferences);
+
+        if !self.recurring_questions.is_empty() {
+            out.push_str("\n## Recurring questions\n\n");
+            for q in &self.recurring_questions {
+                out.push_str(&format!("- {} (asked {} times)\n", single_line(&q.question), q.count));
+            }
+        }
+
+        section(&mut out, "Code patterns", &self.code_patterns);
+
+        if !self.topics.is_empty() {
+            out.push_str("\n## Topics\n\n");
+            let topics: Vec<String> = self.topics.iter().map(|(t, c)| format!("`{}` ({})", t, c)).collect();
+            out.push_str(&topics.join(", "));
+            out.push('\n');
+        }
+
+        out
+    }
+
+    /// Write the digest as `digest-YYYY-MM-DD.md` and record it as the latest
+    pub fn write(&self, dir: &Path) -> Result<PathBuf> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        let path = dir.join(format!("digest-{}.md", self.until.format("%Y-%m-%d")));
+        std::fs::write(&path, self.to_markdown())
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        std::fs::write(dir.join(LAST_DIGEST_FILE), self.until.to_rfc3339())?;
+
+        Ok(path)
+    }
+}
+
+/// User questions from chat memories that were asked at least twice
+///
+/// Questions match when they share the same content words, so "how is the
+/// index stored" and "where is the index stored?" count as one.
+fn recurring_questions(memories: &[Memory]) -> Vec<RecurringQuestion> {
+    let mut groups: Vec<(String, RecurringQuestion)> = Vec::new();
+
+    for memory in memories.iter().filter(|m| m.memory_type == MemoryType::Conversation) {
+        let Some(question) = user_message(&memory.content) else {
+            continue;
+        };
+        let key = question_key(question);
+        if key.is_empty() {
+            continue;
+        }
+
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, group)) => group.count += 1,
+            None => groups.push((
+                key,
+                RecurringQuestion {
+                    question: question.to_string(),
+                    count: 1,
+                },
+            )),
+        }
+    }
+
+    let mut recurring: Vec<RecurringQuestion> = groups
+        .into_iter()
+        .map(|(_, q)| q)
+        .filter(|q| q.count > 1)
+        .collect();
+    recurring.sort_by_key(|q| std::cmp::Reverse(q.count));
+    recurring
+}
+
+/// The user's side of a condensed chat memory ("User: ... | Assistant: ...")
+fn user_message(content: &str) -> Option<&str> {
+    let rest = content.strip_prefix("User: ")?;
+    let message = rest.split(" | Assistant:").next().unwrap_or(rest).trim();
+    (!message.is_empty()).then_some(message)
+}
+
+fn question_key(question: &str) -> String {
+    let mut words: Vec<String> = question
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .map(|w| w.to_lowercase())
+        .filter(|w| w.len() > 2 && !STOPWORDS.contains(&w.as_str()) && !QUESTION_WORDS.contains(&w.as_str()))
+        .collect();
+    words.sort();
+    words.dedup();
+    words.join(" ")
+}
+
+fn topics(memories: &[Memory]) -> Vec<(String, usize)> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for tag in memories.iter().flat_map(|m| m.tags.iter()) {
+        if tag.starts_with("lang:") || tag.starts_with("symbol:") {
+            *counts.entry(tag.as_str()).or_default() += 1;
+        }
+    }
+
+    let mut topics: Vec<(String, usize)> = counts.into_iter().map(|(t, c)| (t.to_string(), c)).collect();
+    topics.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    topics.truncate(MAX_TOPICS);
+    topics
+}
+
+fn single_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest_groups_memories() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MemoryStore::new(&dir.path().to_path_buf()).unwrap();
+
+        store.remember("Preference: always use anyhow in main.rs", MemoryType::Preference, None, vec![], 0.9).unwrap();
+        store.remember("Preference: always use anyhow in main.rs", MemoryType::Preference, None, vec![], 0.9).unwrap();
+        store.remember("User: How is the index stored? | Assistant: SQLite", MemoryType::Conversation, None, vec![], 0.5).unwrap();
+        store.remember("User: where is the index stored | Assistant: In codebase.db", MemoryType::Conversation, None, vec![], 0.5).unwrap();
+        store.remember("User: what is MMR? | Assistant: ...", MemoryType::Conversation, None, vec![], 0.5).unwrap();
+
+        let digest = Digest::collect(&store, 7, Utc::now()).unwrap();
+        assert_eq!(digest.total_memories, 5);
+        assert_eq!(digest.preferences.len(), 1);
+        assert_eq!(
+            digest.recurring_questions,
+            vec![RecurringQuestion {
+                question: "How is the index stored?".to_string(),
+                count: 2,
+            }]
+        );
+        assert!(digest.topics.contains(&("lang:rust".to_string(), 2)));
+
+        let path = digest.write(&dir.path().join("digests")).unwrap();
+        let markdown = std::fs::read_to_string(path).unwrap();
+        assert!(markdown.contains("## Recurring questions"));
+
+        let config = DigestConfig {
+            scheduled: true,
+            output_dir: Some(dir.path().join("digests")),
+            ..Default::default()
+        };
+        assert!(!config.is_due(dir.path(), Utc::now()));
+        assert!(config.is_due(dir.path(), Utc::now() + Duration::days(8)));
+    }
+}
@@ -0,0 +1,134 @@
+//! Documentation comments in the style a project already uses
+
+use anyhow::Result;
+
+use crate::storage::CodebaseIndex;
+
+/// Indexed files of the language searched for documented definitions
+const MAX_STYLE_FILES: usize = 30;
+
+/// Most lines of one documentation example
+const MAX_EXAMPLE_LINES: usize = 12;
+
+/// Up to `limit` documented definitions in `language`, each with its comment, so
+/// the model can match how the project writes documentation
+///
+/// `skip` is the absolute path of a file to leave out, usually the one being documented.
+pub fn style_examples(index: &CodebaseIndex, language: &str, skip: &str, limit: usize) -> Result<Vec<String>> {
+    let mut examples = Vec::new();
+    for file in index.list_files(Some(language), MAX_STYLE_FILES)? {
+        if file.path == skip {
+            continue;
+        }
+        let Some(content) = index.get_file_content(&file.path)? else {
+            continue;
+        };
+        let lines: Vec<&str> = content.lines().collect();
+        for symbol in index.file_symbols(&file.path)? {
+            if symbol.kind == "impl" || symbol.start_line == 0 || symbol.start_line > lines.len() {
+                continue;
+            }
+            if let Some(example) = documented(language, &lines, symbol.start_line - 1) {
+                examples.push(example);
+                if examples.len() == limit {
+                    return Ok(examples);
+                }
+            }
+        }
+    }
+    Ok(examples)
+}
+
+/// The definition on line `at` (0-based) with its documentation, if it has any
+fn documented(language: &str, lines: &[&str], at: usize) -> Option<String> {
+    let definition = lines[at];
+
+    // Python documents a definition with the string right after it
+    if language == "python" {
+        let first = lines.get(at + 1)?.trim();
+        let quote = ["\"\"\"", "'''"].into_iter().find(|q| first.starts_with(q))?;
+        let mut end = at + 1;
+        if first.len() == 3 || !first[3..].contains(quote) {
+            end = (at + 2..lines.len()).find(|&i| lines[i].contains(quote))?;
+        }
+        return (end - at < MAX_EXAMPLE_LINES).then(|| lines[at..=end].join("\n"));
+    }
+
+    let prefixes = comment_prefixes(language)?;
+    let mut start = at;
+    let mut attributes = 0;
+    while start > 0 {
+        let line = lines[start - 1].trim();
+        let is_attribute = line.starts_with("#[") || (line.starts_with('@') && language != "php");
+        if is_attribute && start + attributes == at {
+            attributes += 1;
+        } else if line.is_empty() || !prefixes.iter().any(|p| line.starts_with(p)) {
+            break;
+        }
+        start -= 1;
+    }
+    let comment_lines = at - start - attributes;
+    if comment_lines == 0 || comment_lines > MAX_EXAMPLE_LINES {
+        return None;
+    }
+    let mut example = lines[start..at].to_vec();
+    example.push(definition);
+    Some(example.join("\n"))
+}
+
+/// How lines of comment start in `language`; `None` where documentation is not
+/// made of comments
+fn comment_prefixes(language: &str) -> Option<&'static [&'static str]> {
+    match language {
+        "rust" | "go" => Some(&["//"]),
+        "javascript" | "typescript" | "java" | "kotlin" | "c" | "cpp" | "csharp" | "swift" | "scala" | "php" => {
+            Some(&["//", "/*", "*"])
+        }
+        "ruby" | "shell" => Some(&["#"]),
+        _ => None,
+    }
+}
+
+/// Whether `updated` changes more than comments and blank lines of `original`;
+/// `None` for languages where that can't be told line by line
+pub fn changes_code(language: &str, original: &str, updated: &str) -> Option<bool> {
+    let prefixes = comment_prefixes(language)?;
+    let code = |text: &str| -> Vec<String> {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !prefixes.iter().any(|p| line.starts_with(p)))
+            .map(str::to_string)
+            .collect()
+    };
+    Some(code(original) != code(updated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_style_examples_come_from_documented_definitions() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(
+            root.path().join("lib.rs"),
+            "/// Parsed settings\n#[derive(Debug)]\npub struct Settings {}\n\npub fn bare() {}\n\n\
+             // Not attached\n\nfn loose() {}\n",
+        )
+        .unwrap();
+        std::fs::write(root.path().join("util.py"), "def f():\n    \"\"\"Do the thing.\"\"\"\n    return 1\n").unwrap();
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+
+        let rust = style_examples(&index, "rust", "", 5).unwrap();
+        assert_eq!(rust, vec!["/// Parsed settings\n#[derive(Debug)]\npub struct Settings {}"]);
+        let python = style_examples(&index, "python", "", 5).unwrap();
+        assert_eq!(python, vec!["def f():\n    \"\"\"Do the thing.\"\"\""]);
+
+        let original = "fn main() {\n    run();\n}\n";
+        assert_eq!(changes_code("rust", original, "/// Entry point\nfn main() {\n    run();\n}\n"), Some(false));
+        assert_eq!(changes_code("rust", original, "fn main() {\n    run(1);\n}\n"), Some(true));
+        assert_eq!(changes_code("python", original, original), None);
+    }
+}
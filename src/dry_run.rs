@@ -0,0 +1,32 @@
+//! Reporting the changes `--dry-run` skips
+//!
+//! The flag reaches the orchestrator through `config::Overrides`, and each store
+//! changing the index, memories, transcripts or peers is opened `with_dry_run`.
+
+use std::fmt::Display;
+use std::path::Path;
+
+/// `[dry run] Would <action>`
+pub fn would(action: impl Display) -> String {
+    format!("[dry run] Would {}", action)
+}
+
+/// Tell the user about a change a dry run skipped, on stderr so output meant
+/// for other programs stays intact
+pub fn report(action: impl Display) {
+    eprintln!("{}", would(action));
+}
+
+/// `write 12 lines to src/lib.rs (replacing 30)`, for the report of a file write
+pub fn describe_write(path: &Path, content: &str) -> String {
+    let lines = content.lines().count();
+    match std::fs::read_to_string(path) {
+        Ok(existing) => format!(
+            "write {} lines to {} (replacing {})",
+            lines,
+            path.display(),
+            existing.lines().count()
+        ),
+        Err(_) => format!("create {} with {} lines", path.display(), lines),
+    }
+}
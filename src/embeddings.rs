@@ -1,14 +1,60 @@
 use anyhow::{Context, Result};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+use crate::llm::{ollama_url, Servers};
 
-const OLLAMA_BASE_URL: &str = "http://localhost:11434";
 const EMBEDDING_MODEL: &str = "nomic-embed-text";
+const LOCAL_EMBEDDING_MODEL: &str = "all-MiniLM-L6-v2";
+
+/// `[embeddings]` section of `sovereign.toml`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmbeddingConfig {
+    /// Ollama embedding model (default: nomic-embed-text)
+    pub model: Option<String>,
+}
+
+/// Where embeddings are computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmbeddingBackend {
+    /// Use Ollama if it is reachable, otherwise fall back to the local engine
+    Auto,
+    /// Ollama's /api/embeddings endpoint
+    Ollama,
+    /// In-process ONNX model via fastembed (requires the `local-embeddings` feature)
+    Local,
+}
+
+impl EmbeddingBackend {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "auto" => Some(EmbeddingBackend::Auto),
+            "ollama" => Some(EmbeddingBackend::Ollama),
+            "local" | "fastembed" | "onnx" => Some(EmbeddingBackend::Local),
+            _ => None,
+        }
+    }
+
+    /// Read the backend from `SOVEREIGN_EMBEDDINGS`, defaulting to auto-detection
+    pub fn from_env() -> Self {
+        std::env::var("SOVEREIGN_EMBEDDINGS")
+            .ok()
+            .and_then(|s| Self::from_str(&s))
+            .unwrap_or(EmbeddingBackend::Auto)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct EmbeddingClient {
     client: Client,
+    /// Base URL of the Ollama server
+    ollama_url: String,
     model: String,
+    backend: EmbeddingBackend,
+    resolved: Arc<OnceCell<EmbeddingBackend>>,
 }
 
 #[derive(Debug, Serialize)]
@@ -24,21 +70,96 @@ struct EmbeddingResponse {
 
 impl EmbeddingClient {
     pub fn new() -> Self {
+        Self::from_config(&EmbeddingConfig::default())
+    }
+
+    /// Create a client from config, with `--embedding-model` already applied to it
+    pub fn from_config(config: &EmbeddingConfig) -> Self {
+        let model = config
+            .model
+            .clone()
+            .filter(|m| !m.trim().is_empty())
+            .unwrap_or_else(|| EMBEDDING_MODEL.to_string());
+
+        Self {
+            model,
+            ..Self::with_backend(EmbeddingBackend::from_env())
+        }
+    }
+
+    /// Create a client that always uses the given backend
+    pub fn with_backend(backend: EmbeddingBackend) -> Self {
         Self {
             client: Client::new(),
+            ollama_url: ollama_url(),
             model: EMBEDDING_MODEL.to_string(),
+            backend,
+            resolved: Arc::new(OnceCell::new()),
+        }
+    }
+
+    /// Ask the Ollama server in `servers`, if it names one
+    pub fn with_servers(mut self, servers: &Servers) -> Self {
+        if let Some(url) = &servers.ollama {
+            self.ollama_url = url.clone();
         }
+        self
     }
 
     #[allow(dead_code)]
     pub fn with_model(model: &str) -> Self {
         Self {
-            client: Client::new(),
             model: model.to_string(),
+            ..Self::with_backend(EmbeddingBackend::Ollama)
+        }
+    }
+
+    /// Resolve `Auto` to a concrete backend, probing Ollama once per client
+    pub async fn active_backend(&self) -> EmbeddingBackend {
+        *self
+            .resolved
+            .get_or_init(|| async {
+                match self.backend {
+                    EmbeddingBackend::Auto => {
+                        let ollama_up = self
+                            .client
+                            .get(format!("{}/api/tags", self.ollama_url))
+                            .send()
+                            .await
+                            .is_ok();
+                        if ollama_up || !cfg!(feature = "local-embeddings") {
+                            EmbeddingBackend::Ollama
+                        } else {
+                            EmbeddingBackend::Local
+                        }
+                    }
+                    other => other,
+                }
+            })
+            .await
+    }
+
+    /// Name of the model that produces this client's vectors
+    pub async fn active_model(&self) -> String {
+        match self.active_backend().await {
+            EmbeddingBackend::Local => LOCAL_EMBEDDING_MODEL.to_string(),
+            _ => self.model.clone(),
         }
     }
 
     pub async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        match self.active_backend().await {
+            EmbeddingBackend::Local => {
+                let mut embeddings = embed_local(vec![text.to_string()]).await?;
+                embeddings
+                    .pop()
+                    .ok_or_else(|| anyhow::anyhow!("Local embedding model returned no vector"))
+            }
+            _ => self.embed_ollama(text).await,
+        }
+    }
+
+    async fn embed_ollama(&self, text: &str) -> Result<Vec<f32>> {
         let request = EmbeddingRequest {
             model: self.model.clone(),
             prompt: text.to_string(),
@@ -46,7 +167,7 @@ impl EmbeddingClient {
 
         let response = self
             .client
-            .post(format!("{}/api/embeddings", OLLAMA_BASE_URL))
+            .post(format!("{}/api/embeddings", self.ollama_url))
             .json(&request)
             .send()
             .await
@@ -58,15 +179,65 @@ impl EmbeddingClient {
 
     #[allow(dead_code)]
     pub async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        if self.active_backend().await == EmbeddingBackend::Local {
+            return embed_local(texts.to_vec()).await;
+        }
+
         let mut embeddings = Vec::with_capacity(texts.len());
         for text in texts {
-            let embedding = self.embed(text).await?;
+            let embedding = self.embed_ollama(text).await?;
             embeddings.push(embedding);
         }
         Ok(embeddings)
     }
 }
 
+/// Load the in-process embedding model once per process
+#[cfg(feature = "local-embeddings")]
+fn local_model() -> Result<&'static fastembed::TextEmbedding> {
+    use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
+    use std::sync::OnceLock;
+
+    static MODEL: OnceLock<TextEmbedding> = OnceLock::new();
+
+    if let Some(model) = MODEL.get() {
+        return Ok(model);
+    }
+
+    let cache_dir = dirs::cache_dir()
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+        .join("sovereign")
+        .join("fastembed");
+
+    println!("  Loading local embedding model ({})...", LOCAL_EMBEDDING_MODEL);
+    let model = TextEmbedding::try_new(
+        InitOptions::new(EmbeddingModel::AllMiniLML6V2)
+            .with_cache_dir(cache_dir)
+            .with_show_download_progress(true),
+    )
+    .map_err(|e| anyhow::anyhow!("Failed to load local embedding model: {}", e))?;
+
+    Ok(MODEL.get_or_init(|| model))
+}
+
+#[cfg(feature = "local-embeddings")]
+async fn embed_local(texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+    // ONNX inference is CPU-bound, keep it off the async runtime
+    tokio::task::spawn_blocking(move || {
+        local_model()?
+            .embed(texts, None)
+            .map_err(|e| anyhow::anyhow!("Local embedding failed: {}", e))
+    })
+    .await?
+}
+
+#[cfg(not(feature = "local-embeddings"))]
+async fn embed_local(_texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+    anyhow::bail!(
+        "Local embeddings are not available in this build. Rebuild with `--features local-embeddings` or start Ollama."
+    )
+}
+
 /// Calculate cosine similarity between two vectors
 pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
     if a.len() != b.len() || a.is_empty() {
@@ -84,22 +255,6 @@ pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
     dot_product / (norm_a * norm_b)
 }
 
-/// Find most similar items from a collection
-pub fn find_similar(
-    query_embedding: &[f32],
-    embeddings: &[(String, Vec<f32>)],
-    top_k: usize,
-) -> Vec<(String, f32)> {
-    let mut similarities: Vec<(String, f32)> = embeddings
-        .iter()
-        .map(|(id, emb)| (id.clone(), cosine_similarity(query_embedding, emb)))
-        .collect();
-
-    similarities.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
-    similarities.truncate(top_k);
-    similarities
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -113,4 +268,13 @@ mod tests {
         let c = vec![0.0, 1.0, 0.0];
         assert!((cosine_similarity(&a, &c) - 0.0).abs() < 0.001);
     }
+
+    #[test]
+    fn test_backend_from_str() {
+        assert_eq!(EmbeddingBackend::from_str("ollama"), Some(EmbeddingBackend::Ollama));
+        assert_eq!(EmbeddingBackend::from_str("Local"), Some(EmbeddingBackend::Local));
+        assert_eq!(EmbeddingBackend::from_str("fastembed"), Some(EmbeddingBackend::Local));
+        assert_eq!(EmbeddingBackend::from_str("auto"), Some(EmbeddingBackend::Auto));
+        assert_eq!(EmbeddingBackend::from_str("openai"), None);
+    }
 }
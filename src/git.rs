@@ -1,9 +1,13 @@
 use anyhow::{Context, Result};
+use serde::Serialize;
 use std::process::Command;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// The tree of a repository with no files, to diff a root commit against
+const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
 
 /// Represents a parsed git diff hunk
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DiffHunk {
     pub file_path: String,
     pub old_start: u32,
@@ -14,46 +18,33 @@ pub struct DiffHunk {
 }
 
 /// Statistics about a file change
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct FileChange {
     pub path: String,
     pub status: FileStatus,
     pub additions: u32,
     pub deletions: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub old_path: Option<String>, // For renames
 }
 
 /// File status in git
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum FileStatus {
     Added,
     Modified,
     Deleted,
     Renamed,
-    Copied,
-    Unknown,
 }
 
 impl FileStatus {
-    pub fn from_char(c: char) -> Self {
-        match c {
-            'A' => FileStatus::Added,
-            'M' => FileStatus::Modified,
-            'D' => FileStatus::Deleted,
-            'R' => FileStatus::Renamed,
-            'C' => FileStatus::Copied,
-            _ => FileStatus::Unknown,
-        }
-    }
-
     pub fn as_str(&self) -> &str {
         match self {
             FileStatus::Added => "added",
             FileStatus::Modified => "modified",
             FileStatus::Deleted => "deleted",
             FileStatus::Renamed => "renamed",
-            FileStatus::Copied => "copied",
-            FileStatus::Unknown => "unknown",
         }
     }
 }
@@ -61,9 +52,7 @@ impl FileStatus {
 /// Represents a git commit
 #[derive(Debug, Clone)]
 pub struct Commit {
-    pub hash: String,
     pub short_hash: String,
-    pub author: String,
     pub date: String,
     pub message: String,
 }
@@ -156,12 +145,31 @@ impl GitOps {
         Ok(String::from_utf8_lossy(&output.stdout).to_string())
     }
 
-    /// Get list of staged files with their status
-    pub fn get_staged_files(&self) -> Result<Vec<FileChange>> {
+    /// Directory git runs hooks from, following `core.hooksPath` and worktrees
+    pub fn hooks_dir(&self) -> Result<PathBuf> {
+        let output = Command::new("git")
+            .args(["-C", &self.repo_path, "rev-parse", "--git-path", "hooks"])
+            .output()
+            .context("Failed to run git rev-parse")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "git rev-parse failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        // Relative to the repository path unless core.hooksPath is absolute
+        Ok(Path::new(&self.repo_path).join(String::from_utf8_lossy(&output.stdout).trim()))
+    }
+
+    /// What `git commit --amend` would commit: HEAD's own changes plus the staged ones
+    pub fn get_amend_diff(&self) -> Result<String> {
+        let parent = self.resolve_commit("HEAD^").unwrap_or_else(|_| EMPTY_TREE.to_string());
         let output = Command::new("git")
-            .args(["-C", &self.repo_path, "diff", "--cached", "--numstat", "--name-status"])
+            .args(["-C", &self.repo_path, "diff", "--cached", &parent])
             .output()
-            .context("Failed to run git diff --cached --numstat")?;
+            .context("Failed to run git diff --cached")?;
 
         if !output.status.success() {
             anyhow::bail!(
@@ -170,7 +178,69 @@ impl GitOps {
             );
         }
 
-        self.parse_numstat_output(&String::from_utf8_lossy(&output.stdout))
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// Unstage everything, leaving the working tree as it is
+    pub fn unstage_all(&self) -> Result<()> {
+        let output = Command::new("git")
+            .args(["-C", &self.repo_path, "reset", "-q"])
+            .output()
+            .context("Failed to run git reset")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "git reset failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(())
+    }
+
+    /// Stage `patch` without touching the working tree
+    pub fn apply_to_index(&self, patch: &str) -> Result<()> {
+        use std::io::Write;
+
+        let mut child = Command::new("git")
+            .args(["-C", &self.repo_path, "apply", "--cached", "--recount", "-"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .context("Failed to run git apply")?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(patch.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "git apply failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(())
+    }
+
+    /// Commit what is staged with `message`, replacing HEAD when `amend` is set
+    ///
+    /// With `edit`, git opens the user's editor on the message first, so this runs
+    /// attached to the terminal.
+    pub fn commit(&self, message: &str, amend: bool, edit: bool) -> Result<()> {
+        let mut command = Command::new("git");
+        command.args(["-C", &self.repo_path, "commit", "-m", message]);
+        if amend {
+            command.arg("--amend");
+        }
+        if edit {
+            command.arg("--edit");
+        }
+        let status = command.status().context("Failed to run git commit")?;
+
+        if !status.success() {
+            anyhow::bail!("git commit failed ({})", status);
+        }
+        Ok(())
     }
 
     /// Get commits between two refs
@@ -179,7 +249,7 @@ impl GitOps {
             .args([
                 "-C", &self.repo_path,
                 "log",
-                "--format=%H|%h|%an|%ad|%s",
+                "--format=%h|%ad|%s",
                 "--date=short",
                 &format!("{}..{}", base, head),
             ])
@@ -195,14 +265,12 @@ impl GitOps {
 
         let mut commits = Vec::new();
         for line in String::from_utf8_lossy(&output.stdout).lines() {
-            let parts: Vec<&str> = line.splitn(5, '|').collect();
-            if parts.len() == 5 {
+            let parts: Vec<&str> = line.splitn(3, '|').collect();
+            if parts.len() == 3 {
                 commits.push(Commit {
-                    hash: parts[0].to_string(),
-                    short_hash: parts[1].to_string(),
-                    author: parts[2].to_string(),
-                    date: parts[3].to_string(),
-                    message: parts[4].to_string(),
+                    short_hash: parts[0].to_string(),
+                    date: parts[1].to_string(),
+                    message: parts[2].to_string(),
                 });
             }
         }
@@ -210,6 +278,28 @@ impl GitOps {
         Ok(commits)
     }
 
+    /// Hash and full message of each non-merge commit `range` selects (as `git log`
+    /// takes it, e.g. `origin/main..HEAD`), newest first
+    pub fn get_commit_messages(&self, range: &str) -> Result<Vec<(String, String)>> {
+        let output = Command::new("git")
+            .args(["-C", &self.repo_path, "log", "--no-merges", "--format=%H%x00%B%x1e", range])
+            .output()
+            .context("Failed to run git log")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "git log failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .split('\x1e')
+            .filter_map(|record| record.trim_start().split_once('\0'))
+            .map(|(hash, message)| (hash.to_string(), message.trim().to_string()))
+            .collect())
+    }
+
     /// Get the current branch name
     pub fn get_current_branch(&self) -> Result<String> {
         let output = Command::new("git")
@@ -227,6 +317,45 @@ impl GitOps {
         Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
     }
 
+    /// Names of the local branches
+    pub fn local_branches(&self) -> Result<Vec<String>> {
+        let output = Command::new("git")
+            .args(["-C", &self.repo_path, "branch", "--format=%(refname:short)"])
+            .output()
+            .context("Failed to run git branch")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "git branch failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
+
+    /// Path of the file naming the checked-out branch (`.git/HEAD`, or the
+    /// worktree's own), which changes whenever another branch is checked out
+    pub fn head_file(&self) -> Result<PathBuf> {
+        let output = Command::new("git")
+            .args(["-C", &self.repo_path, "rev-parse", "--path-format=absolute", "--git-path", "HEAD"])
+            .output()
+            .context("Failed to run git rev-parse")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "git rev-parse failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        Ok(PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+    }
+
     /// Get the default branch (main or master)
     pub fn get_default_branch(&self) -> Result<String> {
         // Try to get from remote
@@ -259,16 +388,33 @@ impl GitOps {
         Ok("main".to_string()) // Default fallback
     }
 
-    /// Get the merge base between current branch and default branch
-    pub fn get_merge_base(&self, branch1: &str, branch2: &str) -> Result<String> {
+    /// Get the diff of one file in the working tree against a commit
+    pub fn get_file_diff(&self, base: &str, path: &str) -> Result<String> {
+        let output = Command::new("git")
+            .args(["-C", &self.repo_path, "diff", base, "--", path])
+            .output()
+            .context("Failed to run git diff")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "git diff failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// Get the commit hash HEAD points to
+    pub fn get_head_commit(&self) -> Result<String> {
         let output = Command::new("git")
-            .args(["-C", &self.repo_path, "merge-base", branch1, branch2])
+            .args(["-C", &self.repo_path, "rev-parse", "HEAD"])
             .output()
-            .context("Failed to run git merge-base")?;
+            .context("Failed to run git rev-parse")?;
 
         if !output.status.success() {
             anyhow::bail!(
-                "git merge-base failed: {}",
+                "git rev-parse failed: {}",
                 String::from_utf8_lossy(&output.stderr)
             );
         }
@@ -276,6 +422,58 @@ impl GitOps {
         Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
     }
 
+    /// Full hash of the commit a tag, branch or other revision points to
+    pub fn resolve_commit(&self, reference: &str) -> Result<String> {
+        let output = Command::new("git")
+            .args(["-C", &self.repo_path, "rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", reference)])
+            .output()
+            .context("Failed to run git rev-parse")?;
+
+        if !output.status.success() {
+            anyhow::bail!("Unknown git revision: {}", reference);
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Paths of the files in a commit's tree under the repository path, relative to it
+    pub fn list_files_at(&self, commit: &str) -> Result<Vec<String>> {
+        let output = Command::new("git")
+            .args(["-C", &self.repo_path, "ls-tree", "-r", "-z", "--name-only", commit])
+            .output()
+            .context("Failed to run git ls-tree")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "git ls-tree failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .filter(|path| !path.is_empty())
+            .map(String::from)
+            .collect())
+    }
+
+    /// Contents of a file (relative to the repository path) as it was at a commit
+    pub fn show_file_at(&self, commit: &str, path: &str) -> Result<Vec<u8>> {
+        let output = Command::new("git")
+            .args(["-C", &self.repo_path, "show", &format!("{}:./{}", commit, path)])
+            .output()
+            .context("Failed to run git show")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "git show failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        Ok(output.stdout)
+    }
+
     /// Parse git diff output to extract hunks
     pub fn parse_diff(&self, diff: &str) -> Result<DiffAnalysis> {
         let mut files = Vec::new();
@@ -414,40 +612,6 @@ impl GitOps {
         })
     }
 
-    /// Parse the numstat/name-status output
-    fn parse_numstat_output(&self, output: &str) -> Result<Vec<FileChange>> {
-        let mut files = Vec::new();
-        let lines: Vec<&str> = output.lines().collect();
-
-        for line in lines {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.is_empty() {
-                continue;
-            }
-
-            // Name-status format: M path or R100 old new
-            if parts.len() >= 2 && parts[0].len() <= 4 {
-                let status_char = parts[0].chars().next().unwrap_or('M');
-                let status = FileStatus::from_char(status_char);
-
-                let (path, old_path) = if status == FileStatus::Renamed && parts.len() >= 3 {
-                    (parts[2].to_string(), Some(parts[1].to_string()))
-                } else {
-                    (parts[1].to_string(), None)
-                };
-
-                files.push(FileChange {
-                    path,
-                    status,
-                    additions: 0,
-                    deletions: 0,
-                    old_path,
-                });
-            }
-        }
-
-        Ok(files)
-    }
 }
 
 /// Parse a hunk range like "10,5" or "10" into (start, count)
@@ -505,12 +669,41 @@ mod tests {
     }
 
     #[test]
-    fn test_file_status() {
-        assert_eq!(FileStatus::from_char('A'), FileStatus::Added);
-        assert_eq!(FileStatus::from_char('M'), FileStatus::Modified);
-        assert_eq!(FileStatus::from_char('D'), FileStatus::Deleted);
-        assert_eq!(FileStatus::from_char('R'), FileStatus::Renamed);
-        assert_eq!(FileStatus::from_char('X'), FileStatus::Unknown);
+    fn test_commit_amend_and_restage() {
+        let repo = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| -> String {
+            let output = Command::new("git").arg("-C").arg(repo.path()).args(args).output().unwrap();
+            assert!(output.status.success(), "git {:?}", args);
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        };
+        git(&["init", "-q"]);
+        git(&["config", "user.name", "t"]);
+        git(&["config", "user.email", "t@t"]);
+        std::fs::write(repo.path().join("a.txt"), "one\n").unwrap();
+        git(&["add", "a.txt"]);
+
+        let ops = GitOps::new(repo.path());
+        ops.commit("feat: add a", false, false).unwrap();
+        assert!(ops.get_amend_diff().unwrap().contains("+one"), "a root commit is diffed against nothing");
+
+        std::fs::write(repo.path().join("b.txt"), "two\n").unwrap();
+        git(&["add", "b.txt"]);
+        ops.commit("feat: add a and b", true, false).unwrap();
+        assert_eq!(git(&["log", "--format=%s"]), "feat: add a and b");
+        let diff = ops.get_amend_diff().unwrap();
+        assert!(diff.contains("+one") && diff.contains("+two"), "{}", diff);
+
+        std::fs::write(repo.path().join("a.txt"), "one\nthree\n").unwrap();
+        git(&["add", "a.txt"]);
+        let staged = ops.get_staged_diff().unwrap();
+        ops.unstage_all().unwrap();
+        assert_eq!(ops.get_staged_diff().unwrap(), "");
+        ops.apply_to_index(&staged).unwrap();
+        assert_eq!(ops.get_staged_diff().unwrap(), staged);
+
+        let messages = ops.get_commit_messages("HEAD").unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].1, "feat: add a and b");
     }
 
     #[test]
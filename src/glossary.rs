@@ -0,0 +1,231 @@
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+
+use crate::storage::codebase::split_identifier;
+use crate::storage::{CodebaseIndex, GlossaryEntry, Symbol};
+
+/// Fewest files of the project a term must appear in to be worth defining
+const MIN_FILES: usize = 2;
+
+/// Glossary entries added to one prompt
+const MAX_PROMPT_ENTRIES: usize = 8;
+
+/// Lines of a definition shown to the model
+const DEFINITION_LINES: usize = 15;
+
+/// Files whose use of a term is shown to the model
+const USAGE_FILES: usize = 3;
+
+/// Type names too generic to say anything about the project's domain
+const GENERIC_TERMS: &[&str] = &[
+    "Args", "Builder", "Cli", "Config", "Context", "Data", "Error", "Inner", "Item", "Kind", "Note", "Options",
+    "Result", "State", "Tests", "Type", "Value",
+];
+
+/// A term the project uses widely enough to define
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub term: String,
+    /// Files of the project mentioning the term
+    pub files: usize,
+    /// Where the project defines the term, when it is a type
+    pub definitions: Vec<Symbol>,
+}
+
+/// Domain terms of the indexed project, most widely used first: type names
+/// used across files, and terms the README highlights
+pub fn candidates(index: &CodebaseIndex, limit: usize) -> Result<Vec<Candidate>> {
+    let mut definitions: HashMap<String, Vec<Symbol>> = HashMap::new();
+    for symbol in index.type_definitions()? {
+        definitions.entry(symbol.name.clone()).or_default().push(symbol);
+    }
+
+    let mut terms: Vec<String> = definitions.keys().cloned().collect();
+    if let Some(readme) = index.get_file_content("README.md")? {
+        terms.extend(readme_terms(&readme));
+    }
+
+    let mut seen = HashSet::new();
+    let mut candidates = Vec::new();
+    for term in terms {
+        if term.len() < 3 || GENERIC_TERMS.contains(&term.as_str()) || !seen.insert(term.to_lowercase()) {
+            continue;
+        }
+        let files = index.count_files_containing(&term)?;
+        if files >= MIN_FILES {
+            let definitions = definitions.remove(&term).unwrap_or_default();
+            candidates.push(Candidate { term, files, definitions });
+        }
+    }
+
+    candidates.sort_by(|a, b| b.files.cmp(&a.files).then_with(|| a.term.cmp(&b.term)));
+    candidates.truncate(limit);
+    Ok(candidates)
+}
+
+/// Terms a README highlights: **bold** phrases of up to three words and
+/// `CamelCase` names in backticks, outside code blocks
+pub fn readme_terms(readme: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut in_code = false;
+
+    for line in readme.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+        for (marker, is_term) in [("**", is_phrase as fn(&str) -> bool), ("`", is_type_name)] {
+            // Odd pieces lie between a pair of markers
+            for span in line.split(marker).skip(1).step_by(2) {
+                let span = span.trim();
+                if is_term(span) && !terms.iter().any(|t: &String| t == span) {
+                    terms.push(span.to_string());
+                }
+            }
+        }
+    }
+
+    terms
+}
+
+/// Code showing what a candidate means (where it is defined and lines that use
+/// it), with the relative paths it came from
+pub fn evidence(index: &CodebaseIndex, candidate: &Candidate) -> Result<(String, Vec<String>)> {
+    let mut evidence = String::new();
+    let mut sources: Vec<String> = Vec::new();
+
+    for symbol in candidate.definitions.iter().take(2) {
+        let Some(content) = index.get_file_content(&symbol.path)? else { continue };
+        let start = symbol.start_line.max(1);
+        let end = symbol.end_line.max(start).min(start + DEFINITION_LINES - 1);
+        let lines: Vec<&str> = content.lines().skip(start - 1).take(end + 1 - start).collect();
+        evidence.push_str(&format!(
+            "Definition in {} (line {}):\n```\n{}\n```\n\n",
+            symbol.relative_path,
+            start,
+            lines.join("\n")
+        ));
+        if !sources.contains(&symbol.relative_path) {
+            sources.push(symbol.relative_path.clone());
+        }
+    }
+
+    let mut usages = 0;
+    for hit in index.search(&candidate.term, USAGE_FILES * 2)? {
+        if usages == USAGE_FILES {
+            break;
+        }
+        if sources.contains(&hit.file.relative_path) {
+            continue;
+        }
+        evidence.push_str(&format!(
+            "Used in {} (lines {}-{}):\n{}\n\n",
+            hit.file.relative_path, hit.start_line, hit.end_line, hit.excerpt
+        ));
+        sources.push(hit.file.relative_path);
+        usages += 1;
+    }
+
+    Ok((evidence, sources))
+}
+
+/// Glossary entries for the terms `text` mentions, as a prompt section; None when it mentions none
+pub fn prompt_section(entries: &[GlossaryEntry], text: &str) -> Option<String> {
+    let text = text.to_lowercase();
+    let relevant: Vec<&GlossaryEntry> = entries
+        .iter()
+        .filter(|entry| mentions(&text, &entry.term))
+        .take(MAX_PROMPT_ENTRIES)
+        .collect();
+    if relevant.is_empty() {
+        return None;
+    }
+
+    let mut section = "Project glossary (what these terms mean in this codebase):\n".to_string();
+    for entry in relevant {
+        section.push_str(&format!("- {}: {}\n", entry.term, entry.definition));
+    }
+    Some(section)
+}
+
+/// Whether lowercase `text` mentions `term` as written, in snake_case, or as separate words
+fn mentions(text: &str, term: &str) -> bool {
+    let words = split_identifier(term);
+    [term.to_lowercase(), words.join("_"), words.join(" ")]
+        .iter()
+        .any(|form| !form.is_empty() && text.contains(form.as_str()))
+}
+
+fn is_phrase(span: &str) -> bool {
+    let words: Vec<&str> = span.split_whitespace().collect();
+    (1..=3).contains(&words.len())
+        && span.len() <= 40
+        && words.iter().all(|word| {
+            word.starts_with(|c: char| c.is_alphabetic())
+                && word.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+fn is_type_name(span: &str) -> bool {
+    span.starts_with(|c: char| c.is_ascii_uppercase())
+        && span.chars().any(|c| c.is_ascii_lowercase())
+        && span.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readme_terms() {
+        let readme = "# Shop\n\n- **Order Book** - matches `LimitOrder`s\n- `cargo run` or `--path`\n\
+                      **Note:** see below\n```rust\nlet x = `NotATerm`;\n```\n**Settlement** runs nightly";
+        assert_eq!(readme_terms(readme), vec!["Order Book", "LimitOrder", "Settlement"]);
+    }
+
+    #[test]
+    fn test_candidates_are_recurring_types() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("book.rs"), "pub struct OrderBook {\n    bids: Vec<u64>,\n}\n\nstruct Scratch;\n").unwrap();
+        std::fs::write(root.path().join("engine.rs"), "fn run(book: &mut OrderBook) {}\n").unwrap();
+        std::fs::write(root.path().join("README.md"), "Runs **Settlement** nightly.\n").unwrap();
+        std::fs::write(root.path().join("settle.rs"), "// Settlement of matched orders\n").unwrap();
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+
+        let found = candidates(&index, 10).unwrap();
+        let terms: Vec<(&str, usize)> = found.iter().map(|c| (c.term.as_str(), c.files)).collect();
+        assert_eq!(terms, vec![("OrderBook", 2), ("Settlement", 2)]);
+
+        let (evidence, sources) = evidence(&index, &found[0]).unwrap();
+        assert!(evidence.contains("Definition in book.rs (line 1):\n```\npub struct OrderBook {"));
+        assert_eq!(sources, vec!["book.rs", "engine.rs"]);
+
+        let entry = GlossaryEntry { term: "OrderBook".to_string(), definition: "Bids per market.".to_string(), sources };
+        index.store_glossary_entry(&entry).unwrap();
+        assert_eq!(index.glossary().unwrap(), vec![entry]);
+    }
+
+    #[test]
+    fn test_prompt_section_picks_mentioned_terms() {
+        let entry = |term: &str, definition: &str| GlossaryEntry {
+            term: term.to_string(),
+            definition: definition.to_string(),
+            sources: vec![],
+        };
+        let entries = vec![
+            entry("OrderBook", "Resting limit orders per market."),
+            entry("Settlement", "The nightly transfer of matched funds."),
+        ];
+
+        let section = prompt_section(&entries, "How does the order_book handle cancels?").unwrap();
+        assert!(section.contains("- OrderBook: Resting limit orders per market."));
+        assert!(!section.contains("Settlement"));
+        assert!(prompt_section(&entries, "Where is logging set up?").is_none());
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::{Context, Result};
+use regex_automata::meta::Regex;
+use regex_automata::util::syntax;
+
+use crate::storage::{CodebaseIndex, SearchFilters};
+
+/// Characters of a matching line shown; longer lines (minified code) are cut
+const MAX_LINE_CHARS: usize = 200;
+
+/// Characters with a meaning in a regular expression, escaped in literal patterns
+const REGEX_META: &str = "\\.+*?()|[]{}^$#&-~";
+
+/// How `/grep` matches lines
+#[derive(Debug, Clone, Default)]
+pub struct GrepOptions {
+    /// Match the pattern as plain text rather than as a regular expression
+    pub literal: bool,
+    pub ignore_case: bool,
+    pub filters: SearchFilters,
+}
+
+/// A line of an indexed file matching a `/grep` pattern
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrepMatch {
+    /// Relative path
+    pub path: String,
+    /// Line number (1-based)
+    pub line: usize,
+    pub text: String,
+}
+
+/// Lines of the project's indexed files matching `pattern`, in path and line
+/// order, at most `limit` of them
+pub fn grep(index: &CodebaseIndex, pattern: &str, options: &GrepOptions, limit: usize) -> Result<Vec<GrepMatch>> {
+    let regex = compile(pattern, options)?;
+    let mut matches = Vec::new();
+
+    // A case-sensitive literal narrows the files in SQL before any line is read
+    let needle = (options.literal && !options.ignore_case).then_some(pattern);
+    index.each_file_content(&options.filters, needle, |path, content| {
+        for (i, line) in content.lines().enumerate() {
+            if !regex.is_match(line) {
+                continue;
+            }
+            matches.push(GrepMatch {
+                path: path.to_string(),
+                line: i + 1,
+                text: line.trim().chars().take(MAX_LINE_CHARS).collect(),
+            });
+            if matches.len() >= limit {
+                return false;
+            }
+        }
+        true
+    })?;
+
+    Ok(matches)
+}
+
+fn compile(pattern: &str, options: &GrepOptions) -> Result<Regex> {
+    let pattern = if options.literal { escape(pattern) } else { pattern.to_string() };
+    Regex::builder()
+        .syntax(syntax::Config::new().case_insensitive(options.ignore_case))
+        .build(&pattern)
+        .with_context(|| format!("Invalid regular expression: {}", pattern))
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if REGEX_META.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grep_regex_and_literal() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("a.rs"), "fn retry() {}\nlet n = retries(3);\n").unwrap();
+        std::fs::write(root.path().join("b.py"), "def Retry(): pass\nx = a.b(c)\n").unwrap();
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+        let lines = |pattern: &str, options: &GrepOptions| -> Vec<String> {
+            grep(&index, pattern, options, 10)
+                .unwrap()
+                .into_iter()
+                .map(|m| format!("{}:{}: {}", m.path, m.line, m.text))
+                .collect()
+        };
+
+        let options = GrepOptions::default();
+        assert_eq!(lines(r"retr(y|ies)\b", &options), vec!["a.rs:1: fn retry() {}", "a.rs:2: let n = retries(3);"]);
+
+        let ignore_case = GrepOptions { ignore_case: true, ..Default::default() };
+        assert_eq!(lines(r"^def retry", &ignore_case), vec!["b.py:1: def Retry(): pass"]);
+
+        let literal = GrepOptions { literal: true, ..Default::default() };
+        assert_eq!(lines("a.b(c)", &literal), vec!["b.py:2: x = a.b(c)"]);
+
+        let rust_only = GrepOptions { filters: SearchFilters { languages: vec!["rust".to_string()], ..Default::default() }, ..ignore_case };
+        assert_eq!(lines("retry", &rust_only).len(), 1);
+
+        assert_eq!(grep(&index, "retr", &options, 1).unwrap().len(), 1);
+        assert!(grep(&index, "(unclosed", &options, 10).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! Git hooks that fill in commit messages from the staged diff and check them
+//! against the conventional commit rules
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// First comment of every hook this installs, so reinstalling knows it may replace them
+const MARKER: &str = "# Installed by sovereign install-hooks";
+
+/// Suffix of the copy kept of a hook that `--force` replaced
+const BACKUP_SUFFIX: &str = ".sovereign-backup";
+
+/// How a hook script runs sovereign: the binary and the global options it was installed with
+#[derive(Debug, Clone)]
+pub struct HookCommand {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+}
+
+impl HookCommand {
+    /// The command line, quoted for `sh`
+    fn shell_words(&self) -> String {
+        std::iter::once(self.program.to_string_lossy().to_string())
+            .chain(self.args.iter().cloned())
+            .map(|word| format!("'{}'", word.replace('\'', r"'\''")))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// The prepare-commit-msg hook; it gives up after `timeout_secs` and never stops a commit
+pub fn prepare_commit_msg_script(command: &HookCommand, timeout_secs: u64) -> String {
+    script(
+        command,
+        "Fills in a message from the staged diff; the commit goes ahead whatever happens.",
+        &format!("hook prepare-commit-msg \"$1\" \"$2\" --timeout {} || true", timeout_secs),
+    )
+}
+
+/// The commit-msg hook, which rejects messages that break the conventional commit rules
+pub fn commit_msg_script(command: &HookCommand) -> String {
+    script(
+        command,
+        "Checks the message against the conventional commit rules; skip with --no-verify.",
+        "hook commit-msg \"$1\"",
+    )
+}
+
+/// A hook running `command` with `invocation`, doing nothing once sovereign is uninstalled
+fn script(command: &HookCommand, comment: &str, invocation: &str) -> String {
+    let program = command.program.to_string_lossy().replace('\'', r"'\''");
+    format!(
+        "#!/bin/sh\n{}\n# {}\n[ -x '{}' ] || exit 0\n{} {}\n",
+        MARKER,
+        comment,
+        program,
+        command.shell_words(),
+        invocation
+    )
+}
+
+/// Whether git leaves the message to the hook: not for `-m`, `-F`, merges, squashes
+/// or amends, which already have one
+pub fn should_prefill(source: Option<&str>) -> bool {
+    source.is_none_or(str::is_empty)
+}
+
+/// The message file git opens in the editor, with `message` above its comments
+pub fn prefill(existing: &str, message: &str) -> String {
+    format!("{}\n{}", message.trim(), existing)
+}
+
+/// Write hook `name` into `hooks_dir`; a hook this did not install is only replaced
+/// with `force`, and is kept beside it
+pub fn install(hooks_dir: &Path, name: &str, script: &str, force: bool) -> Result<PathBuf> {
+    std::fs::create_dir_all(hooks_dir)?;
+    let path = hooks_dir.join(name);
+    if let Ok(current) = std::fs::read_to_string(&path) {
+        if !current.contains(MARKER) {
+            if !force {
+                anyhow::bail!("{} already exists; pass --force to replace it (a copy is kept)", path.display());
+            }
+            std::fs::copy(&path, hooks_dir.join(format!("{}{}", name, BACKUP_SUFFIX)))?;
+        }
+    }
+
+    std::fs::write(&path, script).with_context(|| format!("Failed to write {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install_keeps_hooks_it_did_not_write() {
+        let hooks = tempfile::tempdir().unwrap();
+        let command = HookCommand {
+            program: PathBuf::from("/opt/sovereign/bin/sovereign"),
+            args: vec!["--data-dir".to_string(), "/home/me/it's data".to_string()],
+        };
+        let script = prepare_commit_msg_script(&command, 20);
+        assert!(script.contains(
+            "'/opt/sovereign/bin/sovereign' '--data-dir' '/home/me/it'\\''s data' hook prepare-commit-msg \"$1\" \"$2\" --timeout 20 || true"
+        ));
+
+        std::fs::write(hooks.path().join("prepare-commit-msg"), "#!/bin/sh\nexec husky\n").unwrap();
+        assert!(install(hooks.path(), "prepare-commit-msg", &script, false).is_err());
+        install(hooks.path(), "prepare-commit-msg", &script, true).unwrap();
+        let backup = std::fs::read_to_string(hooks.path().join("prepare-commit-msg.sovereign-backup")).unwrap();
+        assert_eq!(backup, "#!/bin/sh\nexec husky\n");
+        // Its own hooks are replaced without asking
+        install(hooks.path(), "prepare-commit-msg", &script, false).unwrap();
+
+        assert!(should_prefill(None));
+        assert!(!should_prefill(Some("message")));
+        assert_eq!(prefill("\n# Please enter the commit message\n", "fix: a\n"), "fix: a\n\n# Please enter the commit message\n");
+    }
+}
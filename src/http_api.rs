@@ -0,0 +1,390 @@
+//! The daemon's HTTP API: JSON requests to fixed endpoints, answered with JSON or,
+//! for streaming requests, server-sent events
+
+use anyhow::Result;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+
+use crate::daemon::{self, DaemonRequest, DaemonResponse, OrchestratorMessage};
+use crate::jobs::Jobs;
+use crate::metrics;
+use crate::net;
+use crate::render::OutputFormat;
+use crate::storage::CodebaseIndex;
+use crate::tls::Connection;
+
+/// Largest request body read
+const MAX_BODY_BYTES: usize = 1 << 20;
+
+/// An endpoint and the chat command it runs, given the request's `args`
+struct Route {
+    path: &'static str,
+    /// Empty for chat, whose `args` are the message itself
+    command: &'static str,
+    /// Whether it only reads, and so may be a GET with its fields in the query string
+    get: bool,
+}
+
+/// Only these fixed routes run commands; `/api/chat` refuses messages that would
+const ROUTES: &[Route] = &[
+    Route { path: "/api/chat", command: "", get: false },
+    Route { path: "/api/ask", command: "/ask", get: false },
+    Route { path: "/api/search", command: "/search", get: true },
+    Route { path: "/api/index", command: "/index", get: false },
+    Route { path: "/api/stats", command: "/stats", get: true },
+    Route { path: "/api/memory", command: "/memory", get: true },
+    Route { path: "/api/commands", command: "commands", get: true },
+    Route { path: "/api/git/commit-message", command: "/commit", get: false },
+    Route { path: "/api/git/review", command: "/review-staged", get: false },
+    Route { path: "/api/git/pr-summary", command: "/pr-summary", get: false },
+    Route { path: "/api/cancel", command: "cancel", get: false },
+    Route { path: "/api/jobs", command: "jobs", get: true },
+];
+
+/// Body of a request, or the query string of a GET
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ApiRequest {
+    /// What follows the command: the message, question, query, path, tag, base ref,
+    /// the id of the request to stop for `/api/cancel`, or of the job for `/api/jobs`
+    #[serde(
+        alias = "message",
+        alias = "question",
+        alias = "query",
+        alias = "path",
+        alias = "tag",
+        alias = "base",
+        alias = "request",
+        alias = "job"
+    )]
+    pub args: Option<String>,
+    pub session: Option<String>,
+    pub format: Option<String>,
+    /// Names the request, for `/api/cancel`
+    pub id: Option<String>,
+    /// Answer at once with the id of a job running the request, to be followed at `/api/jobs`
+    pub background: bool,
+    /// Answer with server-sent events: a `token` event for each piece of generated
+    /// text, then a `result` event with the response
+    pub stream: bool,
+}
+
+/// What was asked of the API, read off the connection
+#[derive(Debug)]
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: String,
+    bearer: Option<String>,
+    accepts_events: bool,
+    /// The page that made the request, sent by browsers
+    origin: Option<String>,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Answer the one request on `stream`, then close it
+///
+/// Browsers may only call from `origins`, which are the only ones sent CORS headers.
+pub async fn handle_connection(
+    stream: Box<dyn Connection>,
+    request_tx: mpsc::Sender<OrchestratorMessage>,
+    token: Arc<str>,
+    origins: Arc<[String]>,
+    default_format: OutputFormat,
+    jobs: Jobs,
+) -> Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let Some(http) = read_request(BufReader::new(reader)).await? else {
+        return Ok(());
+    };
+
+    if !net::origin_allowed(http.origin.as_deref(), &origins) {
+        return write_error(&mut writer, "403 Forbidden", "Origin not allowed", None).await;
+    }
+    let cors = http.origin.as_deref();
+    if http.method == "OPTIONS" {
+        return write_response(&mut writer, "204 No Content", "text/plain", b"", cors).await;
+    }
+    let Some(route) = ROUTES.iter().find(|r| r.path == http.path) else {
+        return write_error(&mut writer, "404 Not Found", format!("No endpoint {}", http.path), cors).await;
+    };
+    if !(http.method == "POST" || (http.method == "GET" && route.get)) {
+        let error = format!("{} {} is not supported", http.method, route.path);
+        return write_error(&mut writer, "405 Method Not Allowed", error, cors).await;
+    }
+
+    let given = http.bearer.clone().or_else(|| query_value(&http.query, "token"));
+    if !net::token_matches(Some(&token), given.as_deref()) {
+        return write_error(&mut writer, "401 Unauthorized", "Invalid or missing token", cors).await;
+    }
+    // Forms can post other types without a preflight
+    if http.method == "POST" && !http.content_type.as_deref().is_some_and(is_json) {
+        return write_error(&mut writer, "415 Unsupported Media Type", "Send the body as application/json", cors).await;
+    }
+
+    let api = match parse_request(&http) {
+        Ok(api) => api,
+        Err(e) => return write_error(&mut writer, "400 Bad Request", e, cors).await,
+    };
+    if let Err(e) = OutputFormat::requested(api.format.as_deref(), default_format) {
+        return write_error(&mut writer, "400 Bad Request", e, cors).await;
+    }
+    let args = api.args.filter(|a| !a.trim().is_empty());
+    if route.command.is_empty() {
+        match &args {
+            None => return write_error(&mut writer, "400 Bad Request", "A message is required", cors).await,
+            Some(message) if daemon::is_command(message) => {
+                let error = "/api/chat only takes chat messages; commands have endpoints of their own";
+                return write_error(&mut writer, "400 Bad Request", error, cors).await;
+            }
+            Some(_) => {}
+        }
+    }
+
+    let stream = api.stream || http.accepts_events;
+    let (command, args) = match route.command {
+        "" => (args.unwrap_or_default(), None),
+        command => (command.to_string(), args),
+    };
+    let request = DaemonRequest { command, args, token: given, format: api.format, session: api.session, stream, id: api.id, background: api.background };
+
+    let (tokens_tx, mut tokens) = mpsc::unbounded_channel();
+    let response = daemon::dispatch(request, &request_tx, Some(&token), default_format, tokens_tx, &jobs);
+    if !stream {
+        let response = response.await;
+        let status = if response.success { "200 OK" } else { "500 Internal Server Error" };
+        return write_response(&mut writer, status, "application/json", &serde_json::to_vec(&response)?, cors).await;
+    }
+
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n{}\r\n",
+        cors_headers(cors)
+    );
+    writer.write_all(head.as_bytes()).await?;
+    tokio::pin!(response);
+    // The worker sends every token before it replies, so none are left behind
+    let response = loop {
+        tokio::select! {
+            biased;
+            Some(text) = tokens.recv() => {
+                write_event(&mut writer, "token", &serde_json::to_string(&text)?).await?;
+            }
+            response = &mut response => break response,
+        }
+    };
+    write_event(&mut writer, "result", &serde_json::to_string(&response)?).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// Answer a scrape of `GET /metrics` on the metrics port with the Prometheus text format,
+/// the index size read from `data_dir` at the time
+pub async fn handle_metrics_connection(stream: Box<dyn Connection>, token: Option<Arc<str>>, data_dir: PathBuf) -> Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let Some(http) = read_request(BufReader::new(reader)).await? else {
+        return Ok(());
+    };
+
+    if http.path != "/metrics" {
+        return write_error(&mut writer, "404 Not Found", format!("No endpoint {}", http.path), None).await;
+    }
+    if http.method != "GET" {
+        return write_error(&mut writer, "405 Method Not Allowed", format!("{} /metrics is not supported", http.method), None).await;
+    }
+    let given = http.bearer.clone().or_else(|| query_value(&http.query, "token"));
+    if !net::token_matches(token.as_deref(), given.as_deref()) {
+        return write_error(&mut writer, "401 Unauthorized", "Invalid or missing token", None).await;
+    }
+
+    // A scrape still counts requests when the index cannot be read
+    let index = tokio::task::spawn_blocking(move || CodebaseIndex::size(&data_dir)).await?.ok();
+    let body = metrics::render(index.as_ref());
+    write_response(&mut writer, "200 OK", "text/plain; version=0.0.4", body.as_bytes(), None).await
+}
+
+/// The JSON body of a POST, or the query string of a GET
+fn parse_request(http: &HttpRequest) -> Result<ApiRequest, String> {
+    if http.method == "GET" {
+        return Ok(ApiRequest {
+            args: ["args", "message", "question", "query", "q", "path", "tag", "base", "job"]
+                .iter()
+                .find_map(|name| query_value(&http.query, name)),
+            session: query_value(&http.query, "session"),
+            format: query_value(&http.query, "format"),
+            id: query_value(&http.query, "id"),
+            background: false,
+            stream: query_value(&http.query, "stream").is_some_and(|s| s == "true" || s == "1"),
+        });
+    }
+    if http.body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(ApiRequest::default());
+    }
+    serde_json::from_slice(&http.body).map_err(|e| format!("Invalid request: {}", e))
+}
+
+/// The request line, the headers this cares about and the body; `None` if the client
+/// closed the connection without sending anything
+async fn read_request<R: AsyncBufRead + Unpin>(mut reader: R) -> Result<Option<HttpRequest>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        anyhow::bail!("Malformed request line: {}", line.trim());
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = HttpRequest {
+        method: method.to_ascii_uppercase(),
+        path: path.to_string(),
+        query: query.to_string(),
+        bearer: None,
+        accepts_events: false,
+        origin: None,
+        content_type: None,
+        body: Vec::new(),
+    };
+
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => length = value.parse().unwrap_or(0),
+            "authorization" => request.bearer = value.strip_prefix("Bearer ").map(|t| t.trim().to_string()),
+            "accept" => request.accepts_events = value.contains("text/event-stream"),
+            "origin" => request.origin = Some(value.to_string()),
+            "content-type" => request.content_type = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    if length > MAX_BODY_BYTES {
+        anyhow::bail!("Request body of {} bytes is over the {} byte limit", length, MAX_BODY_BYTES);
+    }
+
+    request.body = vec![0; length];
+    reader.read_exact(&mut request.body).await?;
+    Ok(Some(request))
+}
+
+/// The decoded value of `name` in a query string like `q=cache%20eviction&format=plain`
+fn query_value(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+}
+
+/// `cache%20eviction+policy` as `cache eviction policy`
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() && bytes[i + 1..i + 3].iter().all(u8::is_ascii_hexdigit) => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                decoded.push(u8::from_str_radix(hex, 16).unwrap_or_default());
+                i += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// `application/json`, with or without a charset
+fn is_json(content_type: &str) -> bool {
+    content_type.split(';').next().is_some_and(|t| t.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// The CORS headers letting the allowed page `origin` read the response; none without one
+fn cors_headers(origin: Option<&str>) -> String {
+    match origin {
+        Some(origin) => format!(
+            "Access-Control-Allow-Origin: {}\r\nVary: Origin\r\nAccess-Control-Allow-Headers: Authorization, Content-Type\r\n\
+             Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n",
+            origin
+        ),
+        None => String::new(),
+    }
+}
+
+async fn write_error<W: AsyncWrite + Unpin>(writer: &mut W, status: &str, error: impl Into<String>, cors: Option<&str>) -> Result<()> {
+    let body = serde_json::to_vec(&DaemonResponse::failure(error))?;
+    write_response(writer, status, "application/json", &body, cors).await
+}
+
+async fn write_response<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+    cors: Option<&str>,
+) -> Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n",
+        status,
+        content_type,
+        body.len(),
+        cors_headers(cors)
+    );
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(body).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// One server-sent event; `data` must be a single line, as JSON is
+async fn write_event<W: AsyncWrite + Unpin>(writer: &mut W, event: &str, data: &str) -> Result<()> {
+    writer.write_all(format!("event: {}\ndata: {}\n\n", event, data).as_bytes()).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_requests_are_read_from_body_or_query() {
+        let body = r#"{"question": "where is auth?", "stream": 1}"#;
+        let raw = format!(
+            "POST /api/ask HTTP/1.1\r\nHost: localhost\r\nauthorization: Bearer s3cret\r\nOrigin: http://localhost:7657\r\n\
+             Content-Type: application/json; charset=utf-8\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let http = read_request(BufReader::new(raw.as_bytes())).await.unwrap().unwrap();
+        assert_eq!((http.method.as_str(), http.path.as_str()), ("POST", "/api/ask"));
+        assert_eq!(http.bearer.as_deref(), Some("s3cret"));
+        assert_eq!(http.origin.as_deref(), Some("http://localhost:7657"));
+        assert!(http.content_type.as_deref().is_some_and(is_json));
+        assert!(!is_json("text/plain"));
+        // `stream` must be a boolean
+        assert!(parse_request(&http).unwrap_err().starts_with("Invalid request"));
+
+        let raw = "get /api/search?q=cache%20eviction+policy&format=plain&stream=true HTTP/1.1\r\n\r\n";
+        let http = read_request(BufReader::new(raw.as_bytes())).await.unwrap().unwrap();
+        let api = parse_request(&http).unwrap();
+        assert_eq!(api.args.as_deref(), Some("cache eviction policy"));
+        assert_eq!(api.format.as_deref(), Some("plain"));
+        assert!(api.stream);
+
+        assert_eq!(percent_decode("100%25%2"), "100%%2");
+        assert!(read_request(BufReader::new(&b""[..])).await.unwrap().is_none());
+    }
+}
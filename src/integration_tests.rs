@@ -0,0 +1,1031 @@
+//! End-to-end tests of indexing, search, the daemon protocol and the file
+//! watcher, run against the mock model server in `test_support`
+
+use futures::{SinkExt, StreamExt};
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+use crate::agents::Orchestrator;
+use crate::config::Overrides;
+use crate::daemon::{Daemon, DaemonClient, DaemonRequest, DaemonResponse, OrchestratorMessage, WsResponse};
+use crate::llm::LlmBackend;
+use crate::net;
+use crate::storage::{CodebaseIndex, MemoryStore, MemoryType};
+use crate::test_support::{self, MOCK_EMBEDDING_MODEL, MOCK_MODEL};
+use crate::tls::{self, Tls};
+use crate::watcher::FileWatcher;
+
+/// Longest a test waits for the watcher to notice a change
+const WATCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+const MAIN_RS: &str = r#"mod cache;
+
+fn main() {
+    let mut cache = cache::Cache::new(2);
+    cache.insert(1);
+}
+"#;
+
+const CACHE_RS: &str = r#"/// Least recently used cache
+pub struct Cache {
+    entries: Vec<u32>,
+    capacity: usize,
+}
+
+impl Cache {
+    pub fn new(capacity: usize) -> Self {
+        Self { entries: Vec::new(), capacity }
+    }
+
+    /// Insert an entry, evicting the least recent entry when full
+    pub fn insert(&mut self, entry: u32) {
+        if self.entries.len() == self.capacity {
+            self.evict_least_recent();
+        }
+        self.entries.push(entry);
+    }
+
+    fn evict_least_recent(&mut self) {
+        self.entries.remove(0);
+    }
+}
+"#;
+
+/// A small crate with one module worth asking about
+fn sample_project(root: &Path) {
+    std::fs::create_dir_all(root.join("src")).unwrap();
+    std::fs::write(root.join("Cargo.toml"), "[package]\nname = \"sample\"\nversion = \"0.1.0\"\n").unwrap();
+    std::fs::write(root.join("src/main.rs"), MAIN_RS).unwrap();
+    std::fs::write(root.join("src/cache.rs"), CACHE_RS).unwrap();
+}
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .args(["-c", "user.name=t", "-c", "user.email=t@t", "-C"])
+        .arg(dir)
+        .args(args)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success(), "git {:?}", args);
+}
+
+fn orchestrator(data_dir: &Path) -> Orchestrator {
+    Orchestrator::new(MOCK_MODEL, LlmBackend::Ollama, None, data_dir.to_path_buf(), &test_support::overrides()).unwrap()
+}
+
+fn request(command: &str, args: Option<String>) -> DaemonRequest {
+    DaemonRequest {
+        command: command.to_string(),
+        args,
+        token: None,
+        format: Some("plain".to_string()),
+        session: None,
+        stream: false,
+        id: None,
+        background: false,
+    }
+}
+
+/// A port nothing is listening on
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+#[tokio::test]
+async fn test_index_embed_search_and_ask() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+    let mut orchestrator = orchestrator(data_dir.path());
+
+    let indexed = orchestrator.process_command(&format!("/index {}", project.path().display())).await.unwrap();
+    assert!(indexed.starts_with("Indexed") && !indexed.contains("Indexed 0"), "{}", indexed);
+
+    let embedded = orchestrator.process_command("/embed").await.unwrap();
+    assert!(embedded.starts_with("Created embeddings"), "{}", embedded);
+    assert!(test_support::requests_containing("evict_least_recent").iter().any(|r| r.path == "/api/embeddings"));
+
+    let found = orchestrator.process_command("/search evict the least recent entry").await.unwrap();
+    assert!(found.contains("cache.rs"), "{}", found);
+
+    test_support::reply_to("How does the cache evict entries?", "It removes the oldest entry first.");
+    let answer = orchestrator.process_command("/ask How does the cache evict entries?").await.unwrap();
+    assert!(answer.starts_with("It removes the oldest entry first."), "{}", answer);
+    assert!(answer.contains("cache.rs"), "{}", answer);
+
+    // The model was shown the retrieved code, and its reply streamed back whole
+    let asked = test_support::requests_containing("How does the cache evict entries?");
+    let generate = asked.iter().find(|r| r.path == "/api/generate").expect("the question reached the model");
+    assert!(generate.prompt().contains("fn evict_least_recent"));
+    assert_eq!(generate.body["stream"], true);
+}
+
+#[tokio::test]
+async fn test_dry_run_leaves_the_index_and_memories_alone() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+    let kept = MemoryStore::new(&data_dir.path().to_path_buf())
+        .unwrap()
+        .remember("Prefer small modules", MemoryType::Preference, None, vec![], 0.6)
+        .unwrap();
+
+    let overrides = Overrides { dry_run: true, ..test_support::overrides() };
+    let mut orchestrator = Orchestrator::new(MOCK_MODEL, LlmBackend::Ollama, None, data_dir.path().to_path_buf(), &overrides).unwrap();
+
+    orchestrator.process_command(&format!("/index {}", project.path().display())).await.unwrap();
+    let deleted = orchestrator.process_command(&format!("/memory-delete {}", kept.id)).await.unwrap();
+    assert!(deleted.starts_with("[dry run] Would delete"), "{}", deleted);
+
+    let index = CodebaseIndex::new(data_dir.path(), project.path()).unwrap();
+    assert_eq!(index.get_stats().unwrap().total_files, 0);
+    let memories = MemoryStore::new(&data_dir.path().to_path_buf()).unwrap().all().unwrap();
+    assert_eq!(memories.iter().map(|m| m.id.as_str()).collect::<Vec<_>>(), vec![kept.id.as_str()]);
+}
+
+#[tokio::test]
+async fn test_chat_is_shown_relevant_code() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+    let mut orchestrator = orchestrator(data_dir.path());
+    orchestrator.process_command(&format!("/index {}", project.path().display())).await.unwrap();
+    orchestrator.process_command("/embed").await.unwrap();
+
+    orchestrator.process_command("Which entry does insert evict from a full cache?").await.unwrap();
+
+    let asked = test_support::requests_containing("Which entry does insert evict from a full cache?");
+    let chat = asked.iter().find(|r| r.path == "/api/chat").expect("the message reached the model");
+    assert!(chat.prompt().contains("fn evict_least_recent"));
+
+    // The code went with that message only
+    let conversation = orchestrator.chat_agent.conversation();
+    assert!(!conversation.iter().any(|m| m.content.contains("fn evict_least_recent")));
+}
+
+#[tokio::test]
+async fn test_pin_takes_files_in_the_project_and_skips_missing_ones() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+    let mut orchestrator = orchestrator(data_dir.path());
+    orchestrator.process_command(&format!("/index {}", project.path().display())).await.unwrap();
+
+    let missing = orchestrator.process_command("/pin src/missing.rs").await.unwrap_err().to_string();
+    assert_eq!(missing, "Could not read src/missing.rs");
+    let pinned = orchestrator.process_command("/pin src/cache.rs").await.unwrap();
+    assert!(pinned.starts_with("Pinned src/cache.rs (~"), "{}", pinned);
+
+    // Only the file that could be read was pinned
+    assert_eq!(orchestrator.process_command("/unpin").await.unwrap(), "Unpinned src/cache.rs.");
+}
+
+#[tokio::test]
+async fn test_edit_apply_and_undo() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+    let mut orchestrator = orchestrator(data_dir.path());
+    orchestrator.process_command(&format!("/index {}", project.path().display())).await.unwrap();
+
+    // Line numbers are off, as a model's often are
+    test_support::reply_to(
+        "Instruction: start the cache with room for four entries",
+        "```diff\n--- a/src/main.rs\n+++ b/src/main.rs\n@@ -3,3 +3,3 @@\n fn main() {\n-    let mut cache = cache::Cache::new(2);\n+    let mut cache = cache::Cache::new(4);\n     cache.insert(1);\n```",
+    );
+    let main_rs = project.path().join("src/main.rs");
+    let preview = orchestrator
+        .process_command(&format!("/edit {} start the cache with room for four entries", main_rs.display()))
+        .await
+        .unwrap();
+    assert!(preview.starts_with("Proposed changes to 1 file (+1 -1):\n```diff\n--- a/src/main.rs\n"), "{}", preview);
+    assert_eq!(std::fs::read_to_string(&main_rs).unwrap(), MAIN_RS, "nothing is written before /apply");
+
+    let applied = orchestrator.process_command("/apply").await.unwrap();
+    assert!(applied.starts_with("Wrote src/main.rs."), "{}", applied);
+    assert!(std::fs::read_to_string(&main_rs).unwrap().contains("Cache::new(4)"));
+    assert!(orchestrator.process_command("/apply").await.unwrap().starts_with("No changes to apply."));
+
+    assert_eq!(orchestrator.process_command("/undo").await.unwrap(), "Restored src/main.rs.");
+    assert_eq!(std::fs::read_to_string(&main_rs).unwrap(), MAIN_RS);
+    assert_eq!(orchestrator.process_command("/undo").await.unwrap(), "Nothing to undo.");
+}
+
+#[tokio::test]
+async fn test_doc_follows_the_project_style_and_leaves_code_alone() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+    std::fs::write(project.path().join("src/store.rs"), "/// Where entries are kept between runs\npub struct Store {}\n").unwrap();
+    let mut orchestrator = orchestrator(data_dir.path());
+    orchestrator.process_command(&format!("/index {}", project.path().display())).await.unwrap();
+
+    test_support::reply_to(
+        "Add a documentation comment to `evict_least_recent`",
+        "```diff\n--- a/src/cache.rs\n+++ b/src/cache.rs\n@@ -19,3 +19,4 @@\n \n+    /// Drop the oldest entry to make room\n     fn evict_least_recent(&mut self) {\n         self.entries.remove(0);\n```",
+    );
+    let preview = orchestrator.process_command("/doc evict_least_recent").await.unwrap();
+    assert!(preview.starts_with("Proposed changes to 1 file (+1 -0):"), "{}", preview);
+    let request = test_support::requests_containing("Add a documentation comment to `evict_least_recent`");
+    assert!(
+        request[0].prompt().contains("/// Where entries are kept between runs\npub struct Store {}"),
+        "documented code from other files shows the style"
+    );
+
+    test_support::reply_to(
+        "Add documentation comments to the module and to each definition that has none",
+        "```diff\n--- a/src/main.rs\n+++ b/src/main.rs\n@@ -3,2 +3,3 @@\n+/// Entry point\n fn main() {\n-    let mut cache = cache::Cache::new(2);\n+    let mut cache = cache::Cache::new(3);\n```",
+    );
+    let refused = orchestrator.process_command("/doc src/main.rs").await.unwrap_err();
+    let message = format!("{:#}", refused);
+    assert!(message.starts_with("The model's diff changes code as well as comments"), "{}", message);
+}
+
+#[tokio::test]
+async fn test_refactor_all_proposes_a_diff_per_file() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+    let mut orchestrator = orchestrator(data_dir.path());
+    orchestrator.process_command(&format!("/index {}", project.path().display())).await.unwrap();
+
+    test_support::reply_to(
+        "Refactoring to plan: rename the Cache type to LruCache",
+        r#"{"summary": "Rename the Cache type to LruCache.", "symbols": ["Cache"], "new_files": []}"#,
+    );
+    test_support::reply_to(
+        "this is src/cache.rs, where Cache appear",
+        "```diff\n--- a/src/cache.rs\n+++ b/src/cache.rs\n@@ -1,3 +1,3 @@\n /// Least recently used cache\n-pub struct Cache {\n+pub struct LruCache {\n     entries: Vec<u32>,\n@@ -6,3 +6,3 @@\n \n-impl Cache {\n+impl LruCache {\n     pub fn new(capacity: usize) -> Self {\n```",
+    );
+    test_support::reply_to(
+        "this is src/main.rs, where Cache appear",
+        "```diff\n--- a/src/main.rs\n+++ b/src/main.rs\n@@ -3,2 +3,2 @@\n fn main() {\n-    let mut cache = cache::Cache::new(2);\n+    let mut cache = cache::LruCache::new(2);\n```",
+    );
+
+    let preview = orchestrator.process_command("/refactor-all rename the Cache type to LruCache").await.unwrap();
+    assert!(preview.starts_with("Rename the Cache type to LruCache.\n\nProposed changes to 2 files (+3 -3):"), "{}", preview);
+    assert!(!preview.contains("Still referring"), "{}", preview);
+    let main_edit = test_support::requests_containing("this is src/main.rs, where Cache appear on lines 4.");
+    assert_eq!(main_edit.len(), 1, "main.rs is edited once, pointed at its use of Cache");
+
+    // One file at a time, each its own /undo
+    let applied = orchestrator.process_command("/apply src/main.rs").await.unwrap();
+    assert_eq!(applied, "Wrote src/main.rs. /undo puts it back. Still pending: src/cache.rs");
+    assert!(std::fs::read_to_string(project.path().join("src/main.rs")).unwrap().contains("cache::LruCache::new(2)"));
+    assert_eq!(std::fs::read_to_string(project.path().join("src/cache.rs")).unwrap(), CACHE_RS);
+
+    assert!(orchestrator.process_command("/apply").await.unwrap().starts_with("Wrote src/cache.rs."));
+    assert!(std::fs::read_to_string(project.path().join("src/cache.rs")).unwrap().contains("impl LruCache {"));
+}
+
+#[tokio::test]
+async fn test_task_pauses_before_each_edit_and_command() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+    let mut orchestrator = orchestrator(data_dir.path());
+    orchestrator.process_command(&format!("/index {}", project.path().display())).await.unwrap();
+
+    test_support::reply_to(
+        "Goal: give the cache room for eight entries",
+        r#"{"steps": [
+            {"tool": "read", "input": "src/main.rs"},
+            {"tool": "edit", "input": "src/main.rs", "instruction": "pass eight instead of two to Cache::new"},
+            {"tool": "run", "input": "echo checks passed"}
+        ]}"#,
+    );
+    test_support::reply_to(
+        "Instruction: pass eight instead of two to Cache::new",
+        "```diff\n--- a/src/main.rs\n+++ b/src/main.rs\n@@ -4,1 +4,1 @@\n-    let mut cache = cache::Cache::new(2);\n+    let mut cache = cache::Cache::new(8);\n```",
+    );
+    let main_rs = project.path().join("src/main.rs");
+
+    let paused = orchestrator.process_command("/task give the cache room for eight entries").await.unwrap();
+    assert!(paused.starts_with("Step 2/3 of \"give the cache room for eight entries\": edit src/main.rs"), "{}", paused);
+    assert!(paused.contains("+    let mut cache = cache::Cache::new(8);"), "{}", paused);
+    assert_eq!(std::fs::read_to_string(&main_rs).unwrap(), MAIN_RS, "nothing is written before --continue");
+    let edit = test_support::requests_containing("Instruction: pass eight instead of two to Cache::new");
+    assert!(edit[0].prompt().contains("read src/main.rs:\nmod cache;"), "the edit sees what the read found");
+
+    let paused = orchestrator.process_command("/task --continue").await.unwrap();
+    assert!(paused.starts_with("Step 3/3"), "{}", paused);
+    assert!(paused.contains("$ echo checks passed"), "{}", paused);
+    assert!(std::fs::read_to_string(&main_rs).unwrap().contains("Cache::new(8)"));
+
+    let finished = orchestrator.process_command("/task --continue").await.unwrap();
+    assert_eq!(
+        finished,
+        "Finished \"give the cache room for eight entries\":\n\
+         1. read src/main.rs - 6 lines\n\
+         2. edit src/main.rs: pass eight instead of two to Cache::new - written; /undo puts it back\n\
+         3. run echo checks passed - succeeded"
+    );
+    assert_eq!(orchestrator.process_command("/task --continue").await.unwrap(), "No step is waiting. /task <goal> starts a task.");
+}
+
+#[tokio::test]
+async fn test_explain_error_shows_the_reported_code_and_checks_the_fix() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+    let main_rs = project.path().join("src/main.rs");
+    let broken = MAIN_RS.replace("Cache::new(2)", "Cache::new(\"two\")");
+    std::fs::write(&main_rs, &broken).unwrap();
+    let mut orchestrator = orchestrator(data_dir.path());
+    orchestrator.process_command(&format!("/index {}", project.path().display())).await.unwrap();
+
+    test_support::reply_to(
+        "Cache::new(\"two\")",
+        "`Cache::new` takes a `usize`, but it is given a string.\n\n```diff\n--- a/src/main.rs\n+++ b/src/main.rs\n@@ -3,3 +3,3 @@\n fn main() {\n-    let mut cache = cache::Cache::new(\"two\");\n+    let mut cache = cache::Cache::new(2);\n     cache.insert(1);\n```",
+    );
+    let output = format!(
+        "   Compiling sample v0.1.0\nerror[E0308]: mismatched types\n --> {}:4:39\n  |\nerror: could not compile `sample`\n",
+        main_rs.display()
+    );
+    let (explanation, changes) = orchestrator.explain_error(&output).await.unwrap();
+
+    let request = test_support::requests_containing("Cache::new(\"two\")");
+    let described = format!("{}:4:39: error[E0308]: mismatched types\n```", main_rs.display());
+    assert!(request[0].prompt().contains(&described), "{}", request[0].prompt());
+    assert!(request[0].prompt().contains(">    4     let mut cache = cache::Cache::new(\"two\");"), "{}", request[0].prompt());
+    assert!(explanation.starts_with("`Cache::new` takes a `usize`, but it is given a string.\n\n```diff\n"), "{}", explanation);
+    assert!(explanation.contains("+    let mut cache = cache::Cache::new(2);"), "{}", explanation);
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].updated, MAIN_RS);
+    assert_eq!(std::fs::read_to_string(&main_rs).unwrap(), broken, "nothing is written");
+}
+
+#[tokio::test]
+async fn test_review_takes_indexed_files_in_parts() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+    let ledger: String = (0..120)
+        .map(|i| format!("pub fn settle_account_{}(balance: i64) -> i64 {{\n    balance - {}\n}}\n\n", i, i))
+        .collect();
+    std::fs::write(project.path().join("src/ledger.rs"), ledger).unwrap();
+    let mut orchestrator = orchestrator(data_dir.path());
+    orchestrator.process_command(&format!("/index {}", project.path().display())).await.unwrap();
+
+    test_support::reply_to("pub fn settle_account_", "Subtraction may overflow.");
+    let review = orchestrator.process_command("/review src/ledger.rs").await.unwrap();
+    assert!(review.starts_with("## src/ledger.rs (part 1 of 2, lines 1-"), "{}", review);
+    assert!(review.contains("## src/ledger.rs (part 2 of 2, lines "), "{}", review);
+    let requests = test_support::requests_containing("pub fn settle_account_");
+    assert!(requests.iter().all(|r| r.prompt().contains("Language: rust")));
+
+    let missing = orchestrator.process_command("/review src/missing*.rs").await.unwrap();
+    assert_eq!(missing, "No indexed file matches src/missing*.rs.");
+}
+
+#[tokio::test]
+async fn test_review_staged_reports_findings_by_line() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+    git(project.path(), &["init", "-q"]);
+    git(project.path(), &["add", "."]);
+    git(project.path(), &["commit", "-q", "-m", "Initial"]);
+    let changed = CACHE_RS.replace(
+        "        self.entries.remove(0);\n",
+        "        self.entries.remove(0);\n        self.entries.truncate(STAGED_REVIEW_LIMIT);\n",
+    );
+    std::fs::write(project.path().join("src/cache.rs"), changed).unwrap();
+    git(project.path(), &["add", "src/cache.rs"]);
+    let mut orchestrator = orchestrator(data_dir.path());
+    orchestrator.process_command(&format!("/index {}", project.path().display())).await.unwrap();
+
+    test_support::reply_to(
+        "self.entries.truncate(STAGED_REVIEW_LIMIT)",
+        r#"[{"line": 22, "severity": "error", "message": "Truncating right after removing drops a second entry."}]"#,
+    );
+    let review = orchestrator.process_command("/review-staged").await.unwrap();
+    assert!(review.starts_with("## Review of staged changes"), "{}", review);
+    assert!(review.contains("| error | `src/cache.rs:22` | Truncating right after removing"), "{}", review);
+    let request = test_support::requests_containing("self.entries.truncate(STAGED_REVIEW_LIMIT)");
+    assert!(request[0].prompt().contains("The change is in this definition"), "{}", request[0].prompt());
+}
+
+#[tokio::test]
+async fn test_daemon_protocol_over_tcp() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+
+    let port = free_port();
+    let daemon = Daemon::new(MOCK_MODEL, LlmBackend::Ollama, None, data_dir.path().to_path_buf(), &test_support::overrides()).unwrap();
+    let client = DaemonClient::tcp(net::DEFAULT_BIND, Some(port));
+
+    let exchange = async {
+        while !client.is_running().await {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let indexed = client.send(request("/index", Some(project.path().display().to_string()))).await.unwrap();
+        let commands = client.send(request("commands", None)).await.unwrap();
+        let bad_format = client
+            .send(DaemonRequest { format: Some("pdf".to_string()), ..request("/stats", None) })
+            .await
+            .unwrap();
+
+        // One JSON request per line, one JSON response per line
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream.write_all(b"not json\n").await.unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await.unwrap();
+        let invalid: DaemonResponse = serde_json::from_str(&line).unwrap();
+
+        // A streaming request gets the model's words ahead of the response
+        test_support::reply_to("Which cache entry goes first?", "The least recent entry goes first.");
+        let mut streamed = Vec::new();
+        let chat = client
+            .send_streaming(DaemonRequest { stream: true, ..request("Which cache entry goes first?", None) }, |text| {
+                streamed.push(text.to_string())
+            })
+            .await
+            .unwrap();
+
+        (indexed, commands, bad_format, invalid, chat, streamed)
+    };
+
+    let (indexed, commands, bad_format, invalid, chat, streamed) = tokio::select! {
+        served = daemon.start_tcp(Some(port)) => panic!("daemon stopped serving: {:?}", served.err()),
+        responses = exchange => responses,
+    };
+    daemon.shutdown().await;
+
+    assert!(indexed.success, "{:?}", indexed.error);
+    assert!(indexed.result.unwrap().starts_with("Indexed"));
+
+    let commands: Vec<serde_json::Value> = serde_json::from_str(&commands.result.unwrap()).unwrap();
+    assert!(commands.iter().any(|c| c["name"] == "/ask"));
+
+    assert!(!bad_format.success);
+    assert!(!invalid.success);
+    assert!(invalid.error.unwrap().starts_with("Invalid request"));
+
+    assert!(streamed.len() > 1, "{:?}", streamed);
+    assert_eq!(streamed.concat(), "The least recent entry goes first.");
+    assert!(chat.result.unwrap().starts_with("The least recent entry goes first."));
+}
+
+#[tokio::test]
+async fn test_daemon_http_api() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+
+    let port = free_port();
+    let daemon = Daemon::new(MOCK_MODEL, LlmBackend::Ollama, None, data_dir.path().to_path_buf(), &test_support::overrides()).unwrap();
+    // Without --token the API wants the one made in the data directory
+    let token = net::local_token(data_dir.path()).unwrap();
+    let base = format!("http://127.0.0.1:{}", port);
+    let http = reqwest::Client::new();
+
+    let exchange = async {
+        while http.get(format!("{}/api/stats", base)).send().await.is_err() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let status = |request: reqwest::RequestBuilder| async { request.send().await.unwrap().status() };
+
+        let body = serde_json::json!({ "path": project.path(), "format": "plain" });
+        let indexed: DaemonResponse =
+            http.post(format!("{}/api/index", base)).bearer_auth(&token).json(&body).send().await.unwrap().json().await.unwrap();
+        let missing = status(http.get(format!("{}/api/nothing", base)).bearer_auth(&token)).await;
+        let wrong_method = status(http.get(format!("{}/api/index", base)).bearer_auth(&token)).await;
+
+        // What a page the user visits could send to loopback
+        let run = serde_json::json!({ "message": "/run touch pwned" }).to_string();
+        let refused = [
+            status(http.get(format!("{}/api/stats", base))).await,
+            status(http.post(format!("{}/api/chat", base)).bearer_auth(&token).header("Origin", "https://example.com").body(run.clone())).await,
+            status(http.post(format!("{}/api/chat", base)).bearer_auth(&token).header("Content-Type", "text/plain").body(run.clone())).await,
+            status(http.post(format!("{}/api/chat", base)).bearer_auth(&token).header("Content-Type", "application/json").body(run)).await,
+        ];
+        let from_web_ui = http.get(format!("{}/api/stats", base)).bearer_auth(&token).header("Origin", "http://localhost:7657").send().await.unwrap();
+        let allowed_origin = from_web_ui.headers().get("access-control-allow-origin").cloned();
+
+        test_support::reply_to("Which cache entry is evicted over HTTP?", "The oldest entry is evicted.");
+        let body = serde_json::json!({ "message": "Which cache entry is evicted over HTTP?", "stream": true });
+        let events = http.post(format!("{}/api/chat", base)).bearer_auth(&token).json(&body).send().await.unwrap().text().await.unwrap();
+
+        (indexed, missing, wrong_method, refused, allowed_origin, events)
+    };
+
+    let (indexed, missing, wrong_method, refused, allowed_origin, events) = tokio::select! {
+        served = daemon.start_http(Some(port)) => panic!("daemon stopped serving: {:?}", served.err()),
+        responses = exchange => responses,
+    };
+    daemon.shutdown().await;
+
+    assert!(indexed.success, "{:?}", indexed.error);
+    assert!(indexed.result.unwrap().starts_with("Indexed"));
+    assert_eq!(missing, reqwest::StatusCode::NOT_FOUND);
+    assert_eq!(wrong_method, reqwest::StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(refused.map(|s| s.as_u16()), [401, 403, 415, 400]);
+    assert_eq!(allowed_origin.unwrap(), "http://localhost:7657");
+
+    // Server-sent events: the model's words as they come, then the response
+    assert!(events.matches("event: token\n").count() > 1, "{}", events);
+    let (_, result) = events.rsplit_once("event: result\ndata: ").expect("a result event");
+    let result: DaemonResponse = serde_json::from_str(result.trim()).unwrap();
+    assert!(result.result.unwrap().starts_with("The oldest entry is evicted."));
+}
+
+#[tokio::test]
+async fn test_daemon_serves_tls_with_a_self_signed_certificate() {
+    let data_dir = tempfile::tempdir().unwrap();
+
+    let port = free_port();
+    let tls = Tls::self_signed(data_dir.path(), &tls::default_hosts("127.0.0.1")).unwrap();
+    let daemon = Daemon::new(MOCK_MODEL, LlmBackend::Ollama, None, data_dir.path().to_path_buf(), &test_support::overrides()).unwrap().with_tls(tls);
+    let token = net::local_token(data_dir.path()).unwrap();
+    let https = reqwest::Client::builder().danger_accept_invalid_certs(true).build().unwrap();
+    let http = reqwest::Client::new();
+
+    let exchange = async {
+        let stats = loop {
+            match https.get(format!("https://127.0.0.1:{}/api/stats", port)).bearer_auth(&token).send().await {
+                Ok(response) => break response.status(),
+                Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        };
+        // Plain HTTP gets no answer
+        let plain = http.get(format!("http://127.0.0.1:{}/api/stats", port)).send().await;
+        (stats, plain.is_err())
+    };
+
+    let (stats, plain_refused) = tokio::select! {
+        served = daemon.start_http(Some(port)) => panic!("daemon stopped serving: {:?}", served.err()),
+        responses = exchange => responses,
+    };
+    daemon.shutdown().await;
+
+    assert_eq!(stats, reqwest::StatusCode::OK);
+    assert!(plain_refused);
+}
+
+#[tokio::test]
+async fn test_watcher_reindexes_changed_files() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+    let root = project.path().canonicalize().unwrap();
+    let mut orchestrator = orchestrator(data_dir.path());
+    orchestrator.process_command(&format!("/index {}", root.display())).await.unwrap();
+
+    // The watcher asks whoever holds the receiver, the daemon's workers in production
+    let (request_tx, mut requests) = mpsc::channel::<OrchestratorMessage>(8);
+    let mut watcher = FileWatcher::new(request_tx).unwrap();
+    watcher.watch(&root, &[]).unwrap();
+
+    std::fs::write(root.join("src/retry.rs"), "/// Retry with exponential backoff\npub fn retry_with_backoff() {}\n").unwrap();
+    let message = tokio::time::timeout(WATCH_TIMEOUT, requests.recv())
+        .await
+        .expect("the watcher asked for a re-index")
+        .unwrap();
+    assert_eq!(message.input, format!("/index {}", root.display()));
+
+    let reindexed = orchestrator.process_command(&message.input).await.map_err(|e| e.to_string());
+    assert_eq!(reindexed.as_deref(), Ok("Indexed 1 changed files."));
+    let _ = message.response_tx.send(reindexed);
+    watcher.shutdown().await;
+
+    orchestrator.process_command("/embed").await.unwrap();
+    let found = orchestrator.process_command("/search retry with exponential backoff").await.unwrap();
+    assert!(found.contains("retry.rs"), "{}", found);
+}
+
+#[tokio::test]
+async fn test_rewind_restores_the_checkpointed_conversation() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let mut orchestrator = orchestrator(data_dir.path());
+
+    orchestrator.process_command("Should the cache be bounded by entries or bytes?").await.unwrap();
+    let saved = orchestrator.process_command("/checkpoint sizing").await.unwrap();
+    assert!(saved.starts_with("Saved checkpoint 'sizing' at 2 messages"), "{}", saved);
+
+    orchestrator.process_command("What if entries were evicted by age instead?").await.unwrap();
+    assert_eq!(orchestrator.chat_agent.conversation().len(), 5);
+
+    let rewound = orchestrator.process_command("/rewind sizing").await.unwrap();
+    assert_eq!(rewound, "Rewound to 'sizing' (2 messages).");
+    let conversation = orchestrator.chat_agent.conversation();
+    assert_eq!(conversation.len(), 3);
+    assert!(!conversation.iter().any(|m| m.content.contains("evicted by age")));
+
+    let missing = orchestrator.process_command("/rewind elsewhere").await.unwrap();
+    assert_eq!(missing, "No checkpoint 'elsewhere'. Saved: sizing");
+}
+
+#[tokio::test]
+async fn test_daemon_sessions_keep_their_own_conversations() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let mut orchestrator = orchestrator(data_dir.path());
+
+    orchestrator.switch_session("vscode-1");
+    orchestrator.process_command("Should the session cache be keyed by user?").await.unwrap();
+    orchestrator.switch_session("web-1");
+    assert_eq!(orchestrator.chat_agent.conversation().len(), 1);
+    orchestrator.process_command("How long do web sessions last?").await.unwrap();
+
+    orchestrator.switch_session("vscode-1");
+    let conversation = orchestrator.chat_agent.conversation();
+    assert_eq!(conversation.len(), 3);
+    assert!(conversation[1].content.contains("keyed by user"));
+    assert!(!conversation.iter().any(|m| m.content.contains("web sessions")));
+
+    assert!(orchestrator.end_session("web-1"));
+    assert!(!orchestrator.end_session("web-1"));
+    orchestrator.switch_session("web-1");
+    assert_eq!(orchestrator.chat_agent.conversation().len(), 1);
+}
+
+#[tokio::test]
+async fn test_daemon_cancels_a_request_in_flight() {
+    let data_dir = tempfile::tempdir().unwrap();
+    test_support::stall_on("Rewrite the whole cache module in assembly");
+
+    let port = free_port();
+    let daemon = Daemon::new(MOCK_MODEL, LlmBackend::Ollama, None, data_dir.path().to_path_buf(), &test_support::overrides()).unwrap();
+    let client = DaemonClient::tcp(net::DEFAULT_BIND, Some(port));
+
+    let exchange = async {
+        while !client.is_running().await {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let slow = DaemonRequest { id: Some("slow-1".to_string()), ..request("Rewrite the whole cache module in assembly", None) };
+        let cancel = async {
+            while test_support::requests_containing("Rewrite the whole cache module in assembly").is_empty() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            let cancelled = client.send(request("cancel", Some("slow-1".to_string()))).await.unwrap();
+            let again = client.send(request("cancel", Some("slow-1".to_string()))).await.unwrap();
+            (cancelled, again)
+        };
+        let (slow, (cancelled, again)) = tokio::join!(client.send(slow), cancel);
+
+        // The orchestrator is free for the next request
+        let stats = client.send(request("/stats", None)).await.unwrap();
+        (slow.unwrap(), cancelled, again, stats)
+    };
+
+    let (slow, cancelled, again, stats) = tokio::select! {
+        served = daemon.start_tcp(Some(port)) => panic!("daemon stopped serving: {:?}", served.err()),
+        responses = tokio::time::timeout(Duration::from_secs(30), exchange) => responses.expect("the request was not cancelled"),
+    };
+    daemon.shutdown().await;
+
+    assert!(!slow.success);
+    assert_eq!(slow.error.as_deref(), Some("Cancelled"));
+    assert_eq!(cancelled.result.as_deref(), Some("Cancelled request 'slow-1'."));
+    assert_eq!(again.error.as_deref(), Some("No request 'slow-1' in flight."));
+    assert!(stats.success, "{:?}", stats.error);
+}
+
+#[tokio::test]
+async fn test_daemon_runs_background_jobs() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+
+    let port = free_port();
+    let daemon = Daemon::new(MOCK_MODEL, LlmBackend::Ollama, None, data_dir.path().to_path_buf(), &test_support::overrides()).unwrap();
+    let client = DaemonClient::tcp(net::DEFAULT_BIND, Some(port));
+
+    let exchange = async {
+        while !client.is_running().await {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let index = DaemonRequest { background: true, ..request("/index", Some(project.path().display().to_string())) };
+        let submitted = client.send(index).await.unwrap();
+        let job = submitted.result.clone().unwrap();
+        let finished = loop {
+            let status = client.send(request("jobs", Some(job.clone()))).await.unwrap();
+            let status: serde_json::Value = serde_json::from_str(&status.result.unwrap()).unwrap();
+            if status["state"] != "running" {
+                break status;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        };
+        let listed = client.send(request("jobs", None)).await.unwrap();
+        let missing = client.send(request("jobs", Some("job-999".to_string()))).await.unwrap();
+        (job, finished, listed, missing)
+    };
+
+    let (job, finished, listed, missing) = tokio::select! {
+        served = daemon.start_tcp(Some(port)) => panic!("daemon stopped serving: {:?}", served.err()),
+        responses = tokio::time::timeout(Duration::from_secs(30), exchange) => responses.expect("the job did not finish"),
+    };
+    daemon.shutdown().await;
+
+    assert!(job.starts_with("job-"), "{}", job);
+    assert_eq!(finished["state"], "done", "{}", finished);
+    assert!(finished["result"].as_str().unwrap().starts_with("Indexed"), "{}", finished);
+    assert!(finished["progress"][0].as_str().unwrap().starts_with("Indexing codebase at"), "{}", finished);
+    let listed: Vec<serde_json::Value> = serde_json::from_str(&listed.result.unwrap()).unwrap();
+    assert!(listed.iter().any(|j| j["id"] == job.as_str()));
+    assert_eq!(missing.error.as_deref(), Some("No job 'job-999'"));
+}
+
+#[tokio::test]
+async fn test_daemon_stops_when_asked() {
+    let data_dir = tempfile::tempdir().unwrap();
+
+    let port = free_port();
+    let daemon = Daemon::new(MOCK_MODEL, LlmBackend::Ollama, None, data_dir.path().to_path_buf(), &test_support::overrides()).unwrap();
+    let stopping = tokio::spawn(async move {
+        let client = DaemonClient::tcp(net::DEFAULT_BIND, Some(port));
+        while !client.is_running().await {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        client.stop(None).await
+    });
+
+    tokio::select! {
+        served = daemon.start_tcp(Some(port)) => panic!("daemon stopped serving: {:?}", served.err()),
+        _ = daemon.stop_requested() => {}
+    }
+    daemon.shutdown().await;
+
+    assert!(stopping.await.unwrap().unwrap(), "the daemon was running");
+    assert!(!DaemonClient::tcp(net::DEFAULT_BIND, Some(port)).stop(None).await.unwrap(), "nothing is left to stop");
+    assert!(!data_dir.path().join("memories.automerge.partial").exists());
+}
+
+#[tokio::test]
+async fn test_daemon_reports_its_status() {
+    let data_dir = tempfile::tempdir().unwrap();
+
+    let port = free_port();
+    let daemon = Daemon::new(MOCK_MODEL, LlmBackend::Ollama, None, data_dir.path().to_path_buf(), &test_support::overrides()).unwrap();
+    let exchange = async {
+        let client = DaemonClient::tcp(net::DEFAULT_BIND, Some(port));
+        while !client.is_running().await {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        client.status(None).await
+    };
+
+    let status = tokio::select! {
+        served = daemon.start_tcp(Some(port)) => panic!("daemon stopped serving: {:?}", served.err()),
+        status = exchange => status.unwrap(),
+    };
+    daemon.shutdown().await;
+
+    assert_eq!(status.model, MOCK_MODEL);
+    assert_eq!(status.data_dir, data_dir.path());
+    assert!(status.watching.is_empty());
+    assert_eq!((status.workers, status.queued), (1, 0));
+    // The `/stats` requests of `is_running` came from the default session
+    assert_eq!(status.sessions, 1);
+}
+
+#[tokio::test]
+async fn test_daemon_serves_prometheus_metrics() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+
+    let (api_port, metrics_port) = (free_port(), free_port());
+    let daemon = Daemon::new(MOCK_MODEL, LlmBackend::Ollama, None, data_dir.path().to_path_buf(), &test_support::overrides()).unwrap();
+    let token = net::local_token(data_dir.path()).unwrap();
+    let http = reqwest::Client::new();
+
+    let exchange = async {
+        let metrics_url = format!("http://127.0.0.1:{}/metrics", metrics_port);
+        let stats_url = format!("http://127.0.0.1:{}/api/stats", api_port);
+        while http.get(&metrics_url).send().await.is_err() || http.get(&stats_url).send().await.is_err() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let body = serde_json::json!({ "path": project.path() });
+        http.post(format!("http://127.0.0.1:{}/api/index", api_port)).bearer_auth(&token).json(&body).send().await.unwrap();
+
+        let scraped = http.get(&metrics_url).send().await.unwrap();
+        let content_type = scraped.headers()["content-type"].to_str().unwrap().to_string();
+        let missing = http.get(format!("http://127.0.0.1:{}/api/stats", metrics_port)).send().await.unwrap().status();
+        (content_type, scraped.text().await.unwrap(), missing)
+    };
+
+    let (content_type, text, missing) = tokio::select! {
+        served = daemon.start_http(Some(api_port)) => panic!("daemon stopped serving: {:?}", served.err()),
+        served = daemon.start_metrics(Some(metrics_port)) => panic!("metrics stopped serving: {:?}", served.err()),
+        scraped = exchange => scraped,
+    };
+    daemon.shutdown().await;
+
+    assert!(content_type.starts_with("text/plain; version=0.0.4"), "{}", content_type);
+    assert_eq!(missing, reqwest::StatusCode::NOT_FOUND);
+    assert!(text.contains("sovereign_requests_total{command=\"/index\",outcome=\"ok\"}"), "{}", text);
+    assert!(text.contains("sovereign_request_duration_seconds_bucket{command=\"/stats\",le=\"+Inf\"}"));
+    assert!(text.contains("# TYPE sovereign_llm_tokens_total counter"));
+    let files = text.lines().find_map(|l| l.strip_prefix("sovereign_index_files ")).expect("the index size");
+    assert!(files.parse::<usize>().unwrap() > 0, "{}", text);
+}
+
+#[tokio::test]
+async fn test_model_switch_is_checked_and_told_to_websocket_clients() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let mut orchestrator = orchestrator(data_dir.path());
+    let missing = orchestrator.process_command("/model qwen-unpulled").await.unwrap_err().to_string();
+    assert!(missing.starts_with("Model 'qwen-unpulled' is not pulled"), "{}", missing);
+    assert_eq!(orchestrator.process_command("/model").await.unwrap(), format!("Model: {} on ollama", MOCK_MODEL));
+    drop(orchestrator);
+
+    let (tcp_port, ws_port) = (free_port(), free_port());
+    let daemon = Daemon::new(MOCK_MODEL, LlmBackend::Ollama, None, data_dir.path().to_path_buf(), &test_support::overrides()).unwrap();
+    let client = DaemonClient::tcp(net::DEFAULT_BIND, Some(tcp_port));
+
+    let exchange = async {
+        while !client.is_running().await {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let url = format!("ws://127.0.0.1:{}", ws_port);
+        let from = |origin: &str| {
+            let mut request = url.as_str().into_client_request().unwrap();
+            request.headers_mut().insert("Origin", origin.parse().unwrap());
+            request
+        };
+        let mut ws = loop {
+            match tokio_tungstenite::connect_async(from("http://localhost:7657")).await {
+                Ok((ws, _)) => break ws,
+                Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        };
+        assert!(tokio_tungstenite::connect_async(from("https://example.com")).await.is_err());
+        // Answered once the connection is served, so the switch below is sure to be told
+        let ping = serde_json::json!({ "id": "ping", "command": "/stats", "args": null });
+        ws.send(tokio_tungstenite::tungstenite::Message::Text(ping.to_string())).await.unwrap();
+        while let Some(Ok(message)) = ws.next().await {
+            let event: WsResponse = serde_json::from_str(message.to_text().unwrap()).unwrap();
+            if event.event == "complete" {
+                break;
+            }
+        }
+
+        let refused = client.send(request("/model", Some("qwen-unpulled".to_string()))).await.unwrap();
+        let switched = client.send(request("/model", Some(MOCK_EMBEDDING_MODEL.to_string()))).await.unwrap();
+        let notice = loop {
+            let message = ws.next().await.unwrap().unwrap();
+            let event: WsResponse = serde_json::from_str(message.to_text().unwrap()).unwrap();
+            if event.event == "model" {
+                break event;
+            }
+        };
+        let status = client.status(None).await.unwrap();
+        (refused, switched, notice, status)
+    };
+
+    let (refused, switched, notice, status) = tokio::select! {
+        served = daemon.start_tcp(Some(tcp_port)) => panic!("daemon stopped serving: {:?}", served.err()),
+        served = daemon.start_websocket(Some(ws_port)) => panic!("WebSocket stopped serving: {:?}", served.err()),
+        responses = tokio::time::timeout(Duration::from_secs(30), exchange) => responses.expect("no model event"),
+    };
+    daemon.shutdown().await;
+
+    assert!(refused.error.unwrap().contains("still using mock-coder"));
+    let now_using = format!("Now using {} on ollama.", MOCK_EMBEDDING_MODEL);
+    assert_eq!(switched.result.as_deref(), Some(now_using.as_str()));
+    assert_eq!((notice.id.as_str(), notice.data.as_deref()), ("", Some(now_using.as_str())));
+    assert_eq!(status.model, MOCK_EMBEDDING_MODEL);
+}
+
+#[tokio::test]
+async fn test_daemon_reloads_its_config_keeping_sessions() {
+    let project = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    sample_project(project.path());
+    let root = project.path().canonicalize().unwrap();
+
+    let port = free_port();
+    let daemon = Daemon::new(MOCK_MODEL, LlmBackend::Ollama, None, data_dir.path().to_path_buf(), &test_support::overrides()).unwrap();
+    let client = DaemonClient::tcp(net::DEFAULT_BIND, Some(port));
+    let config = data_dir.path().join("sovereign.toml");
+    let chat = |message: &str| DaemonRequest { session: Some("reloading".to_string()), ..request(message, None) };
+
+    let exchange = async {
+        while !client.is_running().await {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        client.send(chat("Should the reload keep this conversation?")).await.unwrap();
+
+        std::fs::write(&config, format!("[ollama]\nnum_ctx = 12288\n\n[daemon]\nwatch = [{:?}]\n", root.display().to_string())).unwrap();
+        let reloaded = client.send(request("/reload", None)).await.unwrap();
+        client.send(chat("And after the reload?")).await.unwrap();
+        let status = client.status(None).await.unwrap();
+
+        std::fs::write(&config, "[ollama]\nnum_ctx = 12288\n").unwrap();
+        let unwatched = client.send(request("/reload", None)).await.unwrap();
+        (reloaded, status, unwatched)
+    };
+
+    let (reloaded, status, unwatched) = tokio::select! {
+        served = daemon.start_tcp(Some(port)) => panic!("daemon stopped serving: {:?}", served.err()),
+        responses = tokio::time::timeout(Duration::from_secs(30), exchange) => responses.expect("the reload did not finish"),
+    };
+    daemon.shutdown().await;
+
+    let reloaded = reloaded.result.unwrap();
+    assert!(reloaded.starts_with(&format!("Reloaded {}", config.display())), "{}", reloaded);
+    assert!(reloaded.ends_with(&format!("Watching {}", root.display())), "{}", reloaded);
+    assert_eq!(status.watching, vec![root.clone()]);
+    // The exchange is embedded as a memory after the reply, in a request without options
+    let after = test_support::requests_containing("And after the reload?");
+    let after = after.iter().rfind(|r| r.path == "/api/chat").unwrap();
+    assert_eq!(after.body["options"]["num_ctx"], 12288);
+    assert!(after.prompt().contains("Should the reload keep this conversation?"));
+    assert!(unwatched.result.unwrap().ends_with(&format!("Stopped watching {}", root.display())));
+}
+
+#[tokio::test]
+async fn test_web_ui_serves_only_its_own_files_and_wants_the_token_off_loopback() {
+    let root = tempfile::tempdir().unwrap();
+    let data_dir = root.path().to_path_buf();
+    let dir = root.path().join("web-ui");
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(dir.join("index.html"), "<h1>Sovereign</h1>").unwrap();
+    std::fs::write(root.path().join("id_rsa"), "PRIVATE KEY").unwrap();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let get = |target: &str, headers: &str| {
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n{}\r\n", target, headers);
+        async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut response).await.unwrap();
+            response
+        }
+    };
+
+    let exchange = async {
+        (
+            get("/", "").await,
+            get("/?token=s3cret", "").await,
+            get("/index.html", "Cookie: theme=dark; sovereign_token=s3cret\r\n").await,
+            get("/../id_rsa", "Authorization: Bearer s3cret\r\n").await,
+        )
+    };
+    // Served as if bound off loopback
+    let (anonymous, with_query, with_cookie, traversal) = tokio::select! {
+        served = crate::serve_web_ui(listener, &dir, &data_dir, Some("s3cret".to_string()), true, true) => {
+            panic!("web UI stopped serving: {:?}", served.err())
+        }
+        responses = exchange => responses,
+    };
+
+    assert!(anonymous.starts_with("HTTP/1.1 401"), "{}", anonymous);
+    assert!(with_query.starts_with("HTTP/1.1 200") && with_query.ends_with("<h1>Sovereign</h1>"), "{}", with_query);
+    assert!(with_query.contains("Set-Cookie: sovereign_token=s3cret; HttpOnly; SameSite=Strict; Path=/\r\n"));
+    assert!(with_cookie.starts_with("HTTP/1.1 200"), "{}", with_cookie);
+    assert!(traversal.starts_with("HTTP/1.1 404") && !traversal.contains("PRIVATE KEY"), "{}", traversal);
+}
+
+#[tokio::test]
+async fn test_client_reaches_a_tls_daemon_trusting_only_its_certificate() {
+    let data_dir = tempfile::tempdir().unwrap();
+
+    let port = free_port();
+    let tls = Tls::self_signed(data_dir.path(), &tls::default_hosts("0.0.0.0")).unwrap();
+    let fingerprint = tls::cert_fingerprint(&tls::self_signed_cert(data_dir.path())).unwrap();
+    assert_eq!(fingerprint, tls.fingerprint);
+    let daemon = Daemon::new(MOCK_MODEL, LlmBackend::Ollama, None, data_dir.path().to_path_buf(), &test_support::overrides())
+        .unwrap()
+        .with_bind("0.0.0.0", Some("s3cret".to_string()))
+        .unwrap()
+        .with_tls(tls);
+
+    let token = Some("s3cret".to_string());
+    let stopping = tokio::spawn(async move {
+        let client = DaemonClient::tcp("0.0.0.0", Some(port)).with_tls(fingerprint.to_lowercase());
+        let status = loop {
+            match client.status(token.clone()).await {
+                Ok(status) => break status,
+                Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        };
+        let impostor = DaemonClient::tcp("127.0.0.1", Some(port)).with_tls(["00"; 32].join(":"));
+        let plain = DaemonClient::tcp(net::DEFAULT_BIND, Some(port));
+        let refused = (impostor.status(token.clone()).await.is_err(), plain.status(token.clone()).await.is_err());
+        (status, refused, client.stop(token).await)
+    });
+
+    tokio::select! {
+        served = daemon.start_tcp(Some(port)) => panic!("daemon stopped serving: {:?}", served.err()),
+        _ = daemon.stop_requested() => {}
+    }
+    daemon.shutdown().await;
+
+    let (status, refused, stopped) = stopping.await.unwrap();
+    assert_eq!(status.model, MOCK_MODEL);
+    assert_eq!(refused, (true, true));
+    assert!(stopped.unwrap(), "the daemon was running");
+}
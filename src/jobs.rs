@@ -0,0 +1,202 @@
+//! Background jobs of the daemon: a long command submitted as a job is answered at
+//! once with the job's id, and its progress and result are kept to be asked for later
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc};
+
+/// Finished jobs kept for their results; older ones are forgotten
+const MAX_FINISHED_JOBS: usize = 50;
+/// Progress lines kept per job, the latest last
+const MAX_PROGRESS_LINES: usize = 20;
+/// Updates held for subscribers that fall behind
+const UPDATE_CAPACITY: usize = 64;
+
+tokio::task_local! {
+    static PROGRESS: mpsc::UnboundedSender<String>;
+}
+
+/// Run `work`, sending the progress lines it reports to `sink` rather than printing them
+pub async fn report_progress_to<F: Future>(sink: mpsc::UnboundedSender<String>, work: F) -> F::Output {
+    PROGRESS.scope(sink, work).await
+}
+
+/// Report how far a long command has got: to its job when it runs as one, else printed
+pub fn progress(line: impl Into<String>) {
+    let line = line.into();
+    if PROGRESS.try_with(|sink| sink.send(line.clone())).is_err() {
+        println!("  {}", line);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    /// `job-1`, `job-2`, ...; also the request id a `cancel` gives to stop it
+    pub id: String,
+    /// The command as submitted, args included
+    pub command: String,
+    pub state: JobState,
+    /// Latest progress lines, oldest first
+    pub progress: Vec<String>,
+    pub result: Option<String>,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// The daemon's jobs, shared by its connections
+#[derive(Clone)]
+pub struct Jobs {
+    table: Arc<Mutex<Table>>,
+    /// Every change to a job, for clients that follow their jobs
+    updates: broadcast::Sender<Job>,
+}
+
+#[derive(Default)]
+struct Table {
+    started: usize,
+    /// Oldest first
+    jobs: Vec<Job>,
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Jobs {
+    pub fn new() -> Self {
+        Self { table: Arc::new(Mutex::new(Table::default())), updates: broadcast::channel(UPDATE_CAPACITY).0 }
+    }
+
+    /// Record a job running `command`; its id
+    pub fn start(&self, command: &str) -> String {
+        let mut table = self.lock();
+        table.started += 1;
+        let job = Job {
+            id: format!("job-{}", table.started),
+            command: command.to_string(),
+            state: JobState::Running,
+            progress: Vec::new(),
+            result: None,
+            error: None,
+            started_at: Utc::now(),
+            finished_at: None,
+        };
+        let id = job.id.clone();
+        let _ = self.updates.send(job.clone());
+        table.jobs.push(job);
+        id
+    }
+
+    pub fn progress(&self, id: &str, line: String) {
+        self.update(id, |job| {
+            job.progress.push(line);
+            if job.progress.len() > MAX_PROGRESS_LINES {
+                job.progress.remove(0);
+            }
+        });
+    }
+
+    /// Record how the job ended, forgetting the oldest finished jobs past [`MAX_FINISHED_JOBS`]
+    pub fn finish(&self, id: &str, result: Result<String, String>) {
+        self.update(id, |job| {
+            job.finished_at = Some(Utc::now());
+            match result {
+                Ok(result) => {
+                    job.state = JobState::Done;
+                    job.result = Some(result);
+                }
+                Err(error) => {
+                    job.state = if error == "Cancelled" { JobState::Cancelled } else { JobState::Failed };
+                    job.error = Some(error);
+                }
+            }
+        });
+
+        let mut table = self.lock();
+        let finished = table.jobs.iter().filter(|j| j.state != JobState::Running).count();
+        let mut excess = finished.saturating_sub(MAX_FINISHED_JOBS);
+        table.jobs.retain(|job| {
+            let forget = excess > 0 && job.state != JobState::Running;
+            excess -= forget as usize;
+            !forget
+        });
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        self.lock().jobs.iter().find(|j| j.id == id).cloned()
+    }
+
+    /// Every job kept, oldest first
+    pub fn list(&self) -> Vec<Job> {
+        self.lock().jobs.clone()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Job> {
+        self.updates.subscribe()
+    }
+
+    fn update(&self, id: &str, change: impl FnOnce(&mut Job)) {
+        let mut table = self.lock();
+        if let Some(job) = table.jobs.iter_mut().find(|j| j.id == id) {
+            change(job);
+            let _ = self.updates.send(job.clone());
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Table> {
+        self.table.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_jobs_keep_progress_and_results() {
+        let jobs = Jobs::new();
+        let mut updates = jobs.subscribe();
+
+        let id = jobs.start("/index /tmp/project");
+        assert_eq!(id, "job-1");
+        let (sink, mut lines) = mpsc::unbounded_channel();
+        report_progress_to(sink, async { progress("Indexed 100 files...") }).await;
+        jobs.progress(&id, lines.recv().await.unwrap());
+        jobs.finish(&id, Ok("Indexed 120 files".to_string()));
+
+        let job = jobs.get(&id).unwrap();
+        assert_eq!(job.state, JobState::Done);
+        assert_eq!(job.progress, vec!["Indexed 100 files..."]);
+        assert_eq!(job.result.as_deref(), Some("Indexed 120 files"));
+        let states: Vec<JobState> = std::iter::from_fn(|| updates.try_recv().ok()).map(|j| j.state).collect();
+        assert_eq!(states, vec![JobState::Running, JobState::Running, JobState::Done]);
+
+        let cancelled = jobs.start("/embed");
+        jobs.finish(&cancelled, Err("Cancelled".to_string()));
+        assert_eq!(jobs.get(&cancelled).unwrap().state, JobState::Cancelled);
+
+        for _ in 0..MAX_FINISHED_JOBS {
+            let id = jobs.start("/stats");
+            jobs.finish(&id, Ok(String::new()));
+        }
+        let running = jobs.start("/summarize-all");
+        let kept = jobs.list();
+        assert_eq!(kept.len(), MAX_FINISHED_JOBS + 1);
+        assert_eq!(kept[0].id, "job-3");
+        assert_eq!(kept.last().unwrap().id, running);
+    }
+}
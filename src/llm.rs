@@ -3,14 +3,190 @@ use futures::StreamExt;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::io::{self, Write};
-use std::path::Path;
+use std::future::Future;
+use tokio::sync::mpsc;
+
+use crate::deepseek::{DeepSeekClient, DEEPSEEK_CONTEXT_TOKENS};
+use crate::metrics;
+use crate::models::{ModelCapabilities, ModelRegistry};
+
+/// Environment variable naming the Ollama server, as the `ollama` CLI reads it
+pub const OLLAMA_HOST_ENV: &str = "OLLAMA_HOST";
+const DEFAULT_OLLAMA_HOST: &str = "localhost";
+const DEFAULT_OLLAMA_PORT: u16 = 11434;
+
+/// Context window Ollama gives a request that doesn't set `num_ctx`
+pub const OLLAMA_DEFAULT_NUM_CTX: u32 = 2048;
+
+/// Rough characters per token for code and English prose
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Tokens each chat message costs beyond its content (role and separators)
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Where the backends are reached, in place of `OLLAMA_HOST` and `DEEPSEEK_BASE_URL`
+#[derive(Debug, Clone, Default)]
+pub struct Servers {
+    /// Base URL of the Ollama server
+    pub ollama: Option<String>,
+    /// Base URL of the DeepSeek, or another OpenAI-compatible, API
+    pub deepseek: Option<String>,
+}
 
-const OLLAMA_BASE_URL: &str = "http://localhost:11434";
+/// Base URL of the Ollama server: `OLLAMA_HOST` (`host`, `host:port` or a URL) if set,
+/// otherwise `http://localhost:11434`
+pub fn ollama_url() -> String {
+    let host = std::env::var(OLLAMA_HOST_ENV).unwrap_or_default();
+    let host = host.trim().trim_end_matches('/');
+    let host = if host.is_empty() { DEFAULT_OLLAMA_HOST } else { host };
+
+    let (scheme, address) = host.split_once("://").unwrap_or(("http", host));
+    if address.contains(':') {
+        format!("{}://{}", scheme, address)
+    } else {
+        format!("{}://{}:{}", scheme, address, DEFAULT_OLLAMA_PORT)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct OllamaClient {
     client: Client,
+    /// Base URL of the Ollama server
+    url: String,
     model: String,
+    /// Options every request starts from (`[ollama]` in sovereign.toml)
+    defaults: GenerationOptions,
+    /// What the model can do; unknown models are assumed to do nothing special
+    capabilities: ModelCapabilities,
+}
+
+/// Per-request generation constraints (length limits and stop sequences) and
+/// Ollama tuning for local inference
+///
+/// The same keys set defaults for every request under `[ollama]` and override
+/// them for one command under `[prompts.<command>]`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationOptions {
+    /// Maximum number of tokens to generate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// Stop as soon as any of these sequences is produced
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    /// Stop once the first complete ``` fenced block has been produced
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stop_after_code_block: bool,
+    /// The response is parsed as JSON; constrained to valid JSON on models with JSON mode
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub json: bool,
+    /// Context window in tokens (Ollama's default is 2048, which cuts long prompts)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u32>,
+    /// Layers offloaded to the GPU; 0 runs on the CPU only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_gpu: Option<u32>,
+    /// CPU threads used for generation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_thread: Option<u32>,
+    /// Penalty for repeated tokens; 1.0 turns it off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f32>,
+}
+
+impl GenerationOptions {
+    /// These options with unset values taken from `defaults`; stop sequences from both apply
+    pub fn with_defaults(&self, defaults: &GenerationOptions) -> Self {
+        let mut stop = defaults.stop.clone();
+        stop.extend(self.stop.iter().filter(|s| !defaults.stop.contains(s)).cloned());
+
+        Self {
+            max_tokens: self.max_tokens.or(defaults.max_tokens),
+            stop,
+            stop_after_code_block: self.stop_after_code_block || defaults.stop_after_code_block,
+            json: self.json || defaults.json,
+            num_ctx: self.num_ctx.or(defaults.num_ctx),
+            num_gpu: self.num_gpu.or(defaults.num_gpu),
+            num_thread: self.num_thread.or(defaults.num_thread),
+            repeat_penalty: self.repeat_penalty.or(defaults.repeat_penalty),
+        }
+    }
+
+    /// Byte offset where output must be cut, if a stop condition has been reached
+    pub fn cut_point(&self, text: &str) -> Option<usize> {
+        let mut cut = self
+            .stop
+            .iter()
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| text.find(s.as_str()))
+            .min();
+
+        if self.stop_after_code_block {
+            if let Some(open) = text.find("```") {
+                // The block body starts after the opening fence line (```lang)
+                if let Some(newline) = text[open..].find('\n') {
+                    let body_start = open + newline + 1;
+                    if let Some(close) = text[body_start..].find("```") {
+                        let end = body_start + close + 3;
+                        cut = Some(cut.map_or(end, |c| c.min(end)));
+                    }
+                }
+            }
+        }
+
+        cut
+    }
+
+    /// Length of the end of `text` that could be the start of a stop sequence
+    pub fn partial_stop_len(&self, text: &str) -> usize {
+        let fence = self.stop_after_code_block.then_some("```");
+        self.stop
+            .iter()
+            .map(String::as_str)
+            .chain(fence)
+            .flat_map(|stop| (1..stop.len()).filter(move |&n| stop.is_char_boundary(n)).map(move |n| &stop[..n]))
+            .filter(|prefix| text.ends_with(prefix))
+            .map(str::len)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Apply stop rules to a complete response
+    pub fn apply(&self, text: &str) -> String {
+        match self.cut_point(text) {
+            Some(cut) => text[..cut].to_string(),
+            None => text.to_string(),
+        }
+    }
+
+    fn to_ollama(&self) -> Option<OllamaOptions> {
+        let options = OllamaOptions {
+            num_predict: self.max_tokens.map(|n| n as i32),
+            stop: if self.stop.is_empty() { None } else { Some(self.stop.clone()) },
+            num_ctx: self.num_ctx,
+            num_gpu: self.num_gpu,
+            num_thread: self.num_thread,
+            repeat_penalty: self.repeat_penalty,
+        };
+        (options != OllamaOptions::default()).then_some(options)
+    }
+}
+
+/// Subset of Ollama's model `options` we set per request
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct OllamaOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_ctx: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_gpu: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_thread: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repeat_penalty: Option<f32>,
 }
 
 #[derive(Debug, Serialize)]
@@ -21,7 +197,9 @@ struct GenerateRequest {
     system: Option<String>,
     context: Option<Vec<i64>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    images: Option<Vec<String>>,
+    format: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -31,13 +209,36 @@ struct GenerateResponse {
     done: bool,
     #[allow(dead_code)]
     context: Option<Vec<i64>>,
+    #[serde(flatten)]
+    usage: OllamaUsage,
+}
+
+/// Tokens read and generated, on Ollama's last response of a request
+#[derive(Debug, Default, Deserialize)]
+struct OllamaUsage {
+    #[serde(default)]
+    prompt_eval_count: u64,
+    #[serde(default)]
+    eval_count: u64,
+}
+
+impl OllamaUsage {
+    fn record(&self) {
+        if self.prompt_eval_count + self.eval_count > 0 {
+            metrics::record_llm_tokens(self.prompt_eval_count, self.eval_count);
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 struct ChatRequest {
     model: String,
-    messages: Vec<ChatMessageRequest>,
+    messages: Vec<ChatMessage>,
     stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -46,12 +247,13 @@ pub struct ChatMessage {
     pub content: String,
 }
 
-#[derive(Debug, Serialize, Clone)]
-struct ChatMessageRequest {
-    role: String,
-    content: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    images: Option<Vec<String>>,
+/// Rough token count of `messages`; no tokenizer is at hand, so this
+/// overestimates slightly rather than let a conversation overflow unnoticed
+pub fn estimate_tokens(messages: &[ChatMessage]) -> usize {
+    messages
+        .iter()
+        .map(|m| m.content.chars().count().div_ceil(CHARS_PER_TOKEN) + MESSAGE_OVERHEAD_TOKENS)
+        .sum()
 }
 
 #[derive(Debug, Deserialize)]
@@ -59,6 +261,8 @@ struct ChatResponse {
     message: Option<ChatMessage>,
     #[allow(dead_code)]
     done: bool,
+    #[serde(flatten)]
+    usage: OllamaUsage,
 }
 
 #[derive(Debug, Deserialize)]
@@ -75,90 +279,119 @@ struct ModelsResponse {
     models: Vec<ModelInfo>,
 }
 
-/// Image data for multi-modal requests
-#[derive(Debug, Clone)]
-pub struct ImageInput {
-    /// Base64 encoded image data
-    pub data: String,
+tokio::task_local! {
+    /// Where streamed text goes instead of stdout, for a daemon client watching it arrive
+    static TOKEN_SINK: mpsc::UnboundedSender<String>;
 }
 
-impl ImageInput {
-    /// Create from a file path
-    pub fn from_file(path: &Path) -> Result<Self> {
-        let data = std::fs::read(path)
-            .with_context(|| format!("Failed to read image file: {}", path.display()))?;
-        Ok(Self {
-            data: base64_encode(&data),
-        })
-    }
-
-    /// Create from raw bytes
-    pub fn from_bytes(bytes: &[u8]) -> Self {
-        Self {
-            data: base64_encode(bytes),
-        }
-    }
-
-    /// Create from base64 string
-    pub fn from_base64(data: String) -> Self {
-        Self { data }
-    }
+/// Run `work`, sending the text of every streamed generation in it to `sink` as it
+/// is generated rather than printing it
+pub async fn stream_tokens_to<F: Future>(sink: mpsc::UnboundedSender<String>, work: F) -> F::Output {
+    TOKEN_SINK.scope(sink, work).await
 }
 
-fn base64_encode(data: &[u8]) -> String {
-    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
-    let mut result = String::new();
+/// A reply being streamed, shown as it arrives except for what the stop rules cut
+///
+/// A tail that could be the start of a stop sequence is held back until the next
+/// piece shows whether it is one, so a stop sequence split across pieces is
+/// never shown in part.
+pub(crate) struct LimitedStream<'a> {
+    text: String,
+    shown: usize,
+    options: &'a GenerationOptions,
+}
 
-    for chunk in data.chunks(3) {
-        let n = match chunk.len() {
-            3 => ((chunk[0] as u32) << 16) | ((chunk[1] as u32) << 8) | (chunk[2] as u32),
-            2 => ((chunk[0] as u32) << 16) | ((chunk[1] as u32) << 8),
-            1 => (chunk[0] as u32) << 16,
-            _ => unreachable!(),
-        };
+impl<'a> LimitedStream<'a> {
+    pub fn new(options: &'a GenerationOptions) -> Self {
+        Self { text: String::new(), shown: 0, options }
+    }
 
-        result.push(CHARSET[(n >> 18) as usize & 63] as char);
-        result.push(CHARSET[(n >> 12) as usize & 63] as char);
+    /// Append a streamed piece; true once generation should stop
+    pub fn push(&mut self, piece: &str) -> Result<bool> {
+        self.text.push_str(piece);
 
-        if chunk.len() > 1 {
-            result.push(CHARSET[(n >> 6) as usize & 63] as char);
-        } else {
-            result.push('=');
+        match self.options.cut_point(&self.text) {
+            Some(cut) => {
+                self.text.truncate(cut);
+                self.show(cut)?;
+                Ok(true)
+            }
+            None => {
+                let end = self.text.len() - self.options.partial_stop_len(&self.text);
+                self.show(end)?;
+                Ok(false)
+            }
         }
+    }
 
-        if chunk.len() > 2 {
-            result.push(CHARSET[n as usize & 63] as char);
-        } else {
-            result.push('=');
+    /// Show the held-back tail, which turned out not to be a stop sequence, and
+    /// finish the line the reply was printed on; the reply
+    pub fn finish(mut self) -> Result<String> {
+        self.show(self.text.len())?;
+        if TOKEN_SINK.try_with(|_| ()).is_err() {
+            println!();
         }
+        Ok(self.text)
     }
 
-    result
+    /// Show the text up to `end` not shown yet
+    fn show(&mut self, end: usize) -> Result<()> {
+        if end <= self.shown {
+            return Ok(());
+        }
+        let text = &self.text[self.shown..end];
+        self.shown = end;
+        // A client that went away just stops getting tokens; the reply is still returned
+        if TOKEN_SINK.try_with(|sink| sink.send(text.to_string())).is_err() {
+            print!("{}", text);
+            io::stdout().flush()?;
+        }
+        Ok(())
+    }
 }
 
+
 impl OllamaClient {
     pub fn new(model: &str) -> Self {
         Self {
             client: Client::new(),
+            url: ollama_url(),
             model: model.to_string(),
+            defaults: GenerationOptions::default(),
+            capabilities: ModelRegistry::default().capabilities(model).unwrap_or_default(),
+        }
+    }
+
+    /// Describe the model with `[models]` config rather than the built-in registry
+    pub fn with_capabilities(mut self, capabilities: ModelCapabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Ask the Ollama server in `servers`, if it names one
+    pub fn with_servers(mut self, servers: &Servers) -> Self {
+        if let Some(url) = &servers.ollama {
+            self.url = url.clone();
         }
+        self
     }
 
-    /// Get the current model name
-    pub fn model(&self) -> &str {
-        &self.model
+    /// Tokens the model sees of a request made with `options`
+    pub fn context_window(&self, options: &GenerationOptions) -> u32 {
+        options.with_defaults(&self.defaults).num_ctx.unwrap_or(OLLAMA_DEFAULT_NUM_CTX)
     }
 
-    /// Switch to a different model
-    pub fn set_model(&mut self, model: &str) {
-        self.model = model.to_string();
+    /// Use `defaults` for every request; per-request options override them key by key
+    pub fn with_defaults(mut self, defaults: GenerationOptions) -> Self {
+        self.defaults = defaults;
+        self
     }
 
     /// List available models
     pub async fn list_models(&self) -> Result<Vec<String>> {
         let response = self
             .client
-            .get(format!("{}/api/tags", OLLAMA_BASE_URL))
+            .get(format!("{}/api/tags", self.url))
             .send()
             .await
             .context("Failed to connect to Ollama")?;
@@ -167,88 +400,73 @@ impl OllamaClient {
         Ok(result.models.into_iter().map(|m| m.name).collect())
     }
 
-    /// Check if current model supports vision (images)
-    pub fn is_vision_model(&self) -> bool {
-        let vision_models = [
-            "llava",
-            "llava-llama3",
-            "llava-phi3",
-            "bakllava",
-            "moondream",
-            "minicpm-v",
-        ];
-        vision_models.iter().any(|vm| self.model.starts_with(vm))
-    }
-
-    pub async fn generate(&self, prompt: &str, system: Option<&str>) -> Result<String> {
-        self.generate_with_images(prompt, system, None).await
+    /// Ollama's `format` for a request: JSON when it is wanted and the model can be held to it
+    fn format(&self, options: &GenerationOptions) -> Option<&'static str> {
+        (options.json && self.capabilities.json_mode).then_some("json")
     }
 
-    /// Generate with optional images (for vision models)
-    pub async fn generate_with_images(
+    /// Generate with length limits and stop sequences
+    pub async fn generate_with(
         &self,
         prompt: &str,
         system: Option<&str>,
-        images: Option<&[ImageInput]>,
+        options: &GenerationOptions,
     ) -> Result<String> {
+        let options = &options.with_defaults(&self.defaults);
         let request = GenerateRequest {
             model: self.model.clone(),
             prompt: prompt.to_string(),
             stream: false,
             system: system.map(|s| s.to_string()),
             context: None,
-            images: images.map(|imgs| imgs.iter().map(|i| i.data.clone()).collect()),
+            format: self.format(options),
+            options: options.to_ollama(),
         };
 
         let response = self
             .client
-            .post(format!("{}/api/generate", OLLAMA_BASE_URL))
+            .post(format!("{}/api/generate", self.url))
             .json(&request)
             .send()
             .await
             .context("Failed to connect to Ollama")?;
 
         let result: GenerateResponse = response.json().await?;
-        Ok(result.response)
-    }
-
-    pub async fn generate_streaming(
-        &self,
-        prompt: &str,
-        system: Option<&str>,
-    ) -> Result<String> {
-        self.generate_streaming_with_images(prompt, system, None).await
+        result.usage.record();
+        Ok(options.apply(&result.response))
     }
 
-    /// Generate with streaming and optional images
-    pub async fn generate_streaming_with_images(
+    /// Generate with streaming, length limits and stop sequences
+    pub async fn generate_streaming_with(
         &self,
         prompt: &str,
         system: Option<&str>,
-        images: Option<&[ImageInput]>,
+        options: &GenerationOptions,
     ) -> Result<String> {
+        let options = &options.with_defaults(&self.defaults);
         let request = GenerateRequest {
             model: self.model.clone(),
             prompt: prompt.to_string(),
             stream: true,
             system: system.map(|s| s.to_string()),
             context: None,
-            images: images.map(|imgs| imgs.iter().map(|i| i.data.clone()).collect()),
+            format: self.format(options),
+            options: options.to_ollama(),
         };
 
         let response = self
             .client
-            .post(format!("{}/api/generate", OLLAMA_BASE_URL))
+            .post(format!("{}/api/generate", self.url))
             .json(&request)
             .send()
             .await
             .context("Failed to connect to Ollama")?;
 
         let mut stream = response.bytes_stream();
-        let mut full_response = String::new();
+        let mut reply = LimitedStream::new(options);
         let mut buffer = String::new();
 
-        while let Some(chunk) = stream.next().await {
+        'stream: while let Some(chunk) = stream.next().await {
             let chunk = chunk?;
             if let Ok(text) = std::str::from_utf8(&chunk) {
                 buffer.push_str(text);
@@ -256,9 +474,11 @@ impl OllamaClient {
                 // Process complete JSON objects in buffer
                 for line in buffer.lines() {
                     if let Ok(resp) = serde_json::from_str::<GenerateResponse>(line) {
-                        print!("{}", resp.response);
-                        io::stdout().flush()?;
-                        full_response.push_str(&resp.response);
+                        resp.usage.record();
+                        if reply.push(&resp.response)? {
+                            // Dropping the response stream tells Ollama to stop generating
+                            break 'stream;
+                        }
                     }
                 }
 
@@ -272,60 +492,40 @@ impl OllamaClient {
                 }
             }
         }
-        println!();
 
-        Ok(full_response)
+        reply.finish()
     }
 
-    pub async fn chat(&self, messages: &[ChatMessage], stream: bool) -> Result<String> {
-        self.chat_with_images(messages, stream, None).await
-    }
-
-    /// Chat with optional images in the last message (for vision models)
-    pub async fn chat_with_images(
+    /// Chat with length limits and stop sequences
+    pub async fn chat_with(
         &self,
         messages: &[ChatMessage],
         stream: bool,
-        images: Option<&[ImageInput]>,
+        options: &GenerationOptions,
     ) -> Result<String> {
-        // Convert messages to request format, adding images to last user message
-        let messages_req: Vec<ChatMessageRequest> = messages
-            .iter()
-            .enumerate()
-            .map(|(i, m)| {
-                let is_last_user = i == messages.len() - 1 && m.role == "user";
-                ChatMessageRequest {
-                    role: m.role.clone(),
-                    content: m.content.clone(),
-                    images: if is_last_user {
-                        images.map(|imgs| imgs.iter().map(|i| i.data.clone()).collect())
-                    } else {
-                        None
-                    },
-                }
-            })
-            .collect();
-
+        let options = &options.with_defaults(&self.defaults);
         let request = ChatRequest {
             model: self.model.clone(),
-            messages: messages_req,
+            messages: messages.to_vec(),
             stream,
+            format: self.format(options),
+            options: options.to_ollama(),
         };
 
         if stream {
             let response = self
                 .client
-                .post(format!("{}/api/chat", OLLAMA_BASE_URL))
+                .post(format!("{}/api/chat", self.url))
                 .json(&request)
                 .send()
                 .await
                 .context("Failed to connect to Ollama")?;
 
             let mut stream = response.bytes_stream();
-            let mut full_response = String::new();
+            let mut reply = LimitedStream::new(options);
             let mut buffer = String::new();
 
-            while let Some(chunk) = stream.next().await {
+            'stream: while let Some(chunk) = stream.next().await {
                 let chunk = chunk?;
                 if let Ok(text) = std::str::from_utf8(&chunk) {
                     buffer.push_str(text);
@@ -334,10 +534,11 @@ impl OllamaClient {
                     let lines: Vec<&str> = buffer.lines().collect();
                     for line in &lines {
                         if let Ok(resp) = serde_json::from_str::<ChatResponse>(line) {
+                            resp.usage.record();
                             if let Some(msg) = resp.message {
-                                print!("{}", msg.content);
-                                io::stdout().flush()?;
-                                full_response.push_str(&msg.content);
+                                if reply.push(&msg.content)? {
+                                    break 'stream;
+                                }
                             }
                         }
                     }
@@ -350,47 +551,178 @@ impl OllamaClient {
                     }
                 }
             }
-            println!();
 
-            Ok(full_response)
+            reply.finish()
         } else {
             let response = self
                 .client
-                .post(format!("{}/api/chat", OLLAMA_BASE_URL))
+                .post(format!("{}/api/chat", self.url))
                 .json(&request)
                 .send()
                 .await
                 .context("Failed to connect to Ollama")?;
 
             let result: ChatResponse = response.json().await?;
-            Ok(result.message.map(|m| m.content).unwrap_or_default())
+            result.usage.record();
+            Ok(options.apply(&result.message.map(|m| m.content).unwrap_or_default()))
         }
     }
 
-    /// Analyze an image and describe its contents
-    pub async fn analyze_image(&self, image: &ImageInput, prompt: Option<&str>) -> Result<String> {
-        let default_prompt = "Describe this image in detail. If it contains code, explain what the code does.";
-        let prompt = prompt.unwrap_or(default_prompt);
+    pub async fn is_available(&self) -> bool {
+        self.client
+            .get(format!("{}/api/tags", self.url))
+            .send()
+            .await
+            .is_ok()
+    }
+}
+
+/// Where requests go: a local Ollama server or the DeepSeek API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LlmBackend {
+    Ollama,
+    DeepSeek,
+}
 
-        self.generate_with_images(prompt, None, Some(&[image.clone()])).await
+impl LlmBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LlmBackend::Ollama => "ollama",
+            LlmBackend::DeepSeek => "deepseek",
+        }
     }
 
-    /// Analyze code from a screenshot
-    pub async fn analyze_code_screenshot(&self, image: &ImageInput) -> Result<String> {
-        let prompt = r#"Analyze this code screenshot. Provide:
-1. The programming language
-2. A summary of what the code does
-3. Any potential issues or improvements
-4. Key functions or classes visible"#;
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "ollama" => Some(LlmBackend::Ollama),
+            "deepseek" => Some(LlmBackend::DeepSeek),
+            _ => None,
+        }
+    }
+}
 
-        self.generate_with_images(prompt, None, Some(&[image.clone()])).await
+/// A client for the model on either backend, which the agents talk to
+#[derive(Debug, Clone)]
+pub enum LlmClient {
+    Ollama(OllamaClient),
+    DeepSeek(DeepSeekClient),
+}
+
+impl LlmClient {
+    /// Client for `model` on `backend`; DeepSeek takes `api_key`, or `DEEPSEEK_API_KEY` without one
+    pub fn new(backend: LlmBackend, model: &str, api_key: Option<&str>) -> Result<Self> {
+        Ok(match backend {
+            LlmBackend::Ollama => LlmClient::Ollama(OllamaClient::new(model)),
+            LlmBackend::DeepSeek => LlmClient::DeepSeek(match api_key {
+                Some(key) => DeepSeekClient::new(key, model),
+                None => DeepSeekClient::from_env(model)?,
+            }),
+        })
+    }
+
+    /// Ask the server `servers` names for this client's backend, if it names one
+    pub fn with_servers(self, servers: &Servers) -> Self {
+        match self {
+            LlmClient::Ollama(client) => LlmClient::Ollama(client.with_servers(servers)),
+            LlmClient::DeepSeek(client) => LlmClient::DeepSeek(client.with_servers(servers)),
+        }
+    }
+
+    /// Use `defaults` for every request to Ollama; `[ollama]` tunes local inference only
+    pub fn with_defaults(self, defaults: GenerationOptions) -> Self {
+        match self {
+            LlmClient::Ollama(client) => LlmClient::Ollama(client.with_defaults(defaults)),
+            client => client,
+        }
+    }
+
+    /// Describe an Ollama model with `[models]` config rather than the built-in registry
+    pub fn with_capabilities(self, capabilities: ModelCapabilities) -> Self {
+        match self {
+            LlmClient::Ollama(client) => LlmClient::Ollama(client.with_capabilities(capabilities)),
+            client => client,
+        }
+    }
+
+    /// Tokens the model sees of a request made with `options`
+    pub fn context_window(&self, options: &GenerationOptions) -> u32 {
+        match self {
+            LlmClient::Ollama(client) => client.context_window(options),
+            LlmClient::DeepSeek(_) => DEEPSEEK_CONTEXT_TOKENS,
+        }
+    }
+
+    /// Generate with length limits and stop sequences
+    pub async fn generate_with(
+        &self,
+        prompt: &str,
+        system: Option<&str>,
+        options: &GenerationOptions,
+    ) -> Result<String> {
+        match self {
+            LlmClient::Ollama(client) => client.generate_with(prompt, system, options).await,
+            LlmClient::DeepSeek(client) => client.generate_with(prompt, system, options).await,
+        }
+    }
+
+    /// Generate with streaming, length limits and stop sequences
+    pub async fn generate_streaming_with(
+        &self,
+        prompt: &str,
+        system: Option<&str>,
+        options: &GenerationOptions,
+    ) -> Result<String> {
+        match self {
+            LlmClient::Ollama(client) => client.generate_streaming_with(prompt, system, options).await,
+            LlmClient::DeepSeek(client) => client.generate_streaming_with(prompt, system, options).await,
+        }
+    }
+
+    /// Chat with length limits and stop sequences
+    pub async fn chat_with(
+        &self,
+        messages: &[ChatMessage],
+        stream: bool,
+        options: &GenerationOptions,
+    ) -> Result<String> {
+        match self {
+            LlmClient::Ollama(client) => client.chat_with(messages, stream, options).await,
+            LlmClient::DeepSeek(client) => client.chat_with(messages, stream, options).await,
+        }
     }
 
     pub async fn is_available(&self) -> bool {
-        self.client
-            .get(format!("{}/api/tags", OLLAMA_BASE_URL))
-            .send()
-            .await
-            .is_ok()
+        match self {
+            LlmClient::Ollama(client) => client.is_available().await,
+            LlmClient::DeepSeek(client) => client.is_available().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stop_sequence_split_across_pieces_is_never_shown() {
+        let options = GenerationOptions { stop: vec!["###".to_string()], ..Default::default() };
+        let (sink, mut shown) = mpsc::unbounded_channel();
+
+        let reply = stream_tokens_to(sink, async {
+            let mut reply = LimitedStream::new(&options);
+            assert!(!reply.push("Done #").unwrap());
+            assert!(!reply.push("# really").unwrap());
+            assert!(!reply.push(" #").unwrap());
+            assert!(reply.push("##\nmore").unwrap());
+            reply.finish().unwrap()
+        })
+        .await;
+
+        assert_eq!(reply, "Done ## really ");
+        let mut pieces = Vec::new();
+        while let Ok(piece) = shown.try_recv() {
+            pieces.push(piece);
+        }
+        assert_eq!(pieces, vec!["Done ", "## really", " "]);
     }
 }
@@ -0,0 +1,445 @@
+use anyhow::{Context, Result};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::oneshot;
+
+use crate::review::Severity;
+
+/// Language servers can take a while to load a workspace before answering
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `[lsp]` section of `sovereign.toml`
+///
+/// Without a `command`, a server is picked from the project's build files
+/// (rust-analyzer, pyright, typescript-language-server, gopls).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LspConfig {
+    /// Server command line, e.g. `["pyright-langserver", "--stdio"]`
+    pub command: Option<Vec<String>>,
+}
+
+impl LspConfig {
+    /// Command line of the server to use for `root`, if one applies
+    pub fn server_command(&self, root: &Path) -> Option<Vec<String>> {
+        if let Some(ref command) = self.command {
+            return Some(command.clone());
+        }
+
+        let command: &[&str] = if root.join("Cargo.toml").exists() {
+            &["rust-analyzer"]
+        } else if root.join("pyproject.toml").exists()
+            || root.join("setup.py").exists()
+            || root.join("requirements.txt").exists()
+        {
+            &["pyright-langserver", "--stdio"]
+        } else if root.join("tsconfig.json").exists() || root.join("package.json").exists() {
+            &["typescript-language-server", "--stdio"]
+        } else if root.join("go.mod").exists() {
+            &["gopls"]
+        } else {
+            return None;
+        };
+
+        Some(command.iter().map(|s| s.to_string()).collect())
+    }
+}
+
+/// A problem reported by the language server
+#[derive(Debug, Clone, Serialize)]
+pub struct LspDiagnostic {
+    pub path: PathBuf,
+    /// 1-based line
+    pub line: usize,
+    /// 1-based column
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+    pub source: Option<String>,
+}
+
+/// A source position returned by go-to-definition
+#[derive(Debug, Clone, Serialize)]
+pub struct LspLocation {
+    pub path: PathBuf,
+    /// 1-based line
+    pub line: usize,
+    /// 1-based column
+    pub column: usize,
+}
+
+type PendingRequests = Arc<Mutex<HashMap<i64, oneshot::Sender<Result<Value, String>>>>>;
+type DiagnosticMap = Arc<Mutex<HashMap<PathBuf, Vec<LspDiagnostic>>>>;
+
+/// Minimal JSON-RPC client for an external language server over stdio
+pub struct LspClient {
+    child: Child,
+    stdin: ChildStdin,
+    next_id: AtomicI64,
+    pending: PendingRequests,
+    diagnostics: DiagnosticMap,
+    opened: HashMap<PathBuf, i32>,
+    server: String,
+}
+
+impl LspClient {
+    /// Spawn the server for `root` and complete the initialize handshake
+    pub async fn start(root: &Path, command: &[String]) -> Result<Self> {
+        let (program, args) = command
+            .split_first()
+            .context("Empty language server command")?;
+
+        let mut child = Command::new(program)
+            .args(args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start language server '{}'", program))?;
+
+        let stdin = child.stdin.take().context("Language server has no stdin")?;
+        let stdout = child.stdout.take().context("Language server has no stdout")?;
+
+        let pending: PendingRequests = Arc::new(Mutex::new(HashMap::new()));
+        let diagnostics: DiagnosticMap = Arc::new(Mutex::new(HashMap::new()));
+        tokio::spawn(read_messages(stdout, pending.clone(), diagnostics.clone()));
+
+        let mut client = Self {
+            child,
+            stdin,
+            next_id: AtomicI64::new(1),
+            pending,
+            diagnostics,
+            opened: HashMap::new(),
+            server: program.clone(),
+        };
+
+        let root_uri = path_to_uri(root)?;
+        client
+            .request(
+                "initialize",
+                json!({
+                    "processId": std::process::id(),
+                    "rootUri": root_uri,
+                    "workspaceFolders": [{ "uri": root_uri, "name": "root" }],
+                    "capabilities": {
+                        "textDocument": {
+                            "publishDiagnostics": {},
+                            "definition": { "linkSupport": false },
+                            "synchronization": {}
+                        }
+                    }
+                }),
+            )
+            .await?;
+        client.notify("initialized", json!({})).await?;
+
+        Ok(client)
+    }
+
+    /// Name of the server binary
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// Send a file's current content so the server analyzes it
+    pub async fn open_file(&mut self, path: &Path, language: &str, text: &str) -> Result<()> {
+        let uri = path_to_uri(path)?;
+
+        if let Some(version) = self.opened.get_mut(path) {
+            *version += 1;
+            let version = *version;
+            return self
+                .notify(
+                    "textDocument/didChange",
+                    json!({
+                        "textDocument": { "uri": uri, "version": version },
+                        "contentChanges": [{ "text": text }]
+                    }),
+                )
+                .await;
+        }
+
+        self.opened.insert(path.to_path_buf(), 1);
+        self.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": uri,
+                    "languageId": language,
+                    "version": 1,
+                    "text": text
+                }
+            }),
+        )
+        .await
+    }
+
+    /// Diagnostics the server has published for one file
+    pub fn diagnostics(&self, path: &Path) -> Vec<LspDiagnostic> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.diagnostics
+            .lock()
+            .map(|d| d.get(&path).cloned().unwrap_or_default())
+            .unwrap_or_default()
+    }
+
+    /// All published diagnostics, most severe first
+    pub fn all_diagnostics(&self) -> Vec<LspDiagnostic> {
+        let mut all: Vec<LspDiagnostic> = self
+            .diagnostics
+            .lock()
+            .map(|d| d.values().flatten().cloned().collect())
+            .unwrap_or_default();
+
+        all.sort_by(|a, b| {
+            b.severity
+                .cmp(&a.severity)
+                .then_with(|| a.path.cmp(&b.path))
+                .then_with(|| a.line.cmp(&b.line))
+        });
+        all
+    }
+
+    /// Resolve the definition of the symbol at a 1-based line/column
+    pub async fn definition(&mut self, path: &Path, line: usize, column: usize) -> Result<Vec<LspLocation>> {
+        let result = self
+            .request(
+                "textDocument/definition",
+                json!({
+                    "textDocument": { "uri": path_to_uri(path)? },
+                    "position": {
+                        "line": line.saturating_sub(1),
+                        "character": column.saturating_sub(1)
+                    }
+                }),
+            )
+            .await?;
+
+        // Location | Location[] | LocationLink[] | null
+        let items = match result {
+            Value::Array(items) => items,
+            Value::Null => Vec::new(),
+            single => vec![single],
+        };
+
+        Ok(items.iter().filter_map(parse_location).collect())
+    }
+
+    /// Ask the server to exit
+    pub async fn shutdown(mut self) -> Result<()> {
+        let _ = self.request("shutdown", Value::Null).await;
+        let _ = self.notify("exit", Value::Null).await;
+        let _ = tokio::time::timeout(Duration::from_secs(2), self.child.wait()).await;
+        Ok(())
+    }
+
+    async fn request(&mut self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.insert(id, tx);
+        }
+
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+            .await?;
+
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, rx)
+            .await
+            .with_context(|| format!("{} did not answer {} in time", self.server, method))?
+            .context("Language server exited")?;
+
+        response.map_err(|e| anyhow::anyhow!("{} failed: {}", method, e))
+    }
+
+    async fn notify(&mut self, method: &str, params: Value) -> Result<()> {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+            .await
+    }
+
+    async fn send(&mut self, message: Value) -> Result<()> {
+        let body = serde_json::to_string(&message)?;
+        let frame = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        self.stdin.write_all(frame.as_bytes()).await?;
+        self.stdin.flush().await?;
+        Ok(())
+    }
+}
+
+/// Route responses to waiting requests and collect published diagnostics
+async fn read_messages(stdout: ChildStdout, pending: PendingRequests, diagnostics: DiagnosticMap) {
+    let mut reader = BufReader::new(stdout);
+
+    while let Ok(Some(message)) = read_frame(&mut reader).await {
+        if let Some(id) = message.get("id").and_then(Value::as_i64) {
+            if message.get("method").is_some() {
+                // Server-to-client requests (e.g. workDoneProgress/create) are not needed
+                continue;
+            }
+
+            let result = match message.get("error") {
+                Some(error) => Err(error
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or("unknown error")
+                    .to_string()),
+                None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+            };
+
+            if let Some(tx) = pending.lock().ok().and_then(|mut p| p.remove(&id)) {
+                let _ = tx.send(result);
+            }
+        } else if message.get("method").and_then(Value::as_str) == Some("textDocument/publishDiagnostics") {
+            if let Some((path, items)) = parse_diagnostics(&message["params"]) {
+                if let Ok(mut map) = diagnostics.lock() {
+                    map.insert(path, items);
+                }
+            }
+        }
+    }
+}
+
+async fn read_frame(reader: &mut BufReader<ChildStdout>) -> Result<Option<Value>> {
+    let mut content_length = None;
+
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            content_length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+
+    let length = content_length.context("Missing Content-Length header")?;
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).await?;
+
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn parse_diagnostics(params: &Value) -> Option<(PathBuf, Vec<LspDiagnostic>)> {
+    let path = uri_to_path(params.get("uri")?.as_str()?)?;
+
+    let items = params
+        .get("diagnostics")?
+        .as_array()?
+        .iter()
+        .filter_map(|d| {
+            let start = &d["range"]["start"];
+            Some(LspDiagnostic {
+                path: path.clone(),
+                line: start["line"].as_u64()? as usize + 1,
+                column: start["character"].as_u64()? as usize + 1,
+                // LSP: 1 = error, 2 = warning, 3 = information, 4 = hint
+                severity: match d.get("severity").and_then(Value::as_u64) {
+                    Some(1) | None => Severity::Error,
+                    Some(2) => Severity::Warning,
+                    _ => Severity::Note,
+                },
+                message: d["message"].as_str()?.to_string(),
+                source: d.get("source").and_then(Value::as_str).map(String::from),
+            })
+        })
+        .collect();
+
+    Some((path, items))
+}
+
+fn parse_location(value: &Value) -> Option<LspLocation> {
+    // LocationLink uses targetUri/targetSelectionRange
+    let uri = value
+        .get("uri")
+        .or_else(|| value.get("targetUri"))?
+        .as_str()?;
+    let start = &value
+        .get("range")
+        .or_else(|| value.get("targetSelectionRange"))?["start"];
+
+    Some(LspLocation {
+        path: uri_to_path(uri)?,
+        line: start["line"].as_u64()? as usize + 1,
+        column: start["character"].as_u64()? as usize + 1,
+    })
+}
+
+fn path_to_uri(path: &Path) -> Result<String> {
+    let absolute = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    Url::from_file_path(&absolute)
+        .map(|u| u.to_string())
+        .map_err(|_| anyhow::anyhow!("Cannot convert {} to a file URI", path.display()))
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    Url::parse(uri).ok()?.to_file_path().ok()
+}
+
+/// LSP language identifier for an indexed file's language
+pub fn language_id(language: &str) -> &str {
+    match language {
+        "shell" => "shellscript",
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_published_diagnostics() {
+        let params = json!({
+            "uri": "file:///repo/src/main.rs",
+            "diagnostics": [
+                {
+                    "range": { "start": { "line": 4, "character": 8 }, "end": { "line": 4, "character": 12 } },
+                    "severity": 2,
+                    "message": "unused variable: `x`",
+                    "source": "rustc"
+                }
+            ]
+        });
+
+        let (path, items) = parse_diagnostics(&params).unwrap();
+        assert_eq!(path, PathBuf::from("/repo/src/main.rs"));
+        assert_eq!(items.len(), 1);
+        assert_eq!((items[0].line, items[0].column), (5, 9));
+        assert_eq!(items[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_parse_location_and_link() {
+        let location = json!({
+            "uri": "file:///repo/src/lib.rs",
+            "range": { "start": { "line": 0, "character": 4 }, "end": { "line": 0, "character": 9 } }
+        });
+        let link = json!({
+            "targetUri": "file:///repo/src/lib.rs",
+            "targetRange": { "start": { "line": 9, "character": 0 }, "end": { "line": 12, "character": 1 } },
+            "targetSelectionRange": { "start": { "line": 9, "character": 7 }, "end": { "line": 9, "character": 10 } }
+        });
+
+        let parsed = parse_location(&location).unwrap();
+        assert_eq!((parsed.line, parsed.column), (1, 5));
+
+        let parsed = parse_location(&link).unwrap();
+        assert_eq!((parsed.line, parsed.column), (10, 8));
+    }
+}
@@ -2,22 +2,67 @@ mod llm;
 mod deepseek;
 mod storage;
 mod agents;
+mod audit;
 mod embeddings;
 mod sync;
 mod daemon;
+mod net;
 mod watcher;
 mod rag;
 mod git;
-
-use anyhow::Result;
+mod completion;
+mod commit_lint;
+mod commit_split;
+mod compiler_output;
+mod config;
+mod consistency;
+mod consolidate;
+mod digest;
+mod docgen;
+mod dry_run;
+mod glossary;
+mod memories;
+mod metrics;
+mod grep;
+mod hooks;
+mod http_api;
+mod jobs;
+mod patch;
+mod pidfile;
+mod postprocess;
+mod prompts;
+mod refactor;
+mod render;
+mod lsp;
+mod models;
+mod review;
+mod scoring;
+mod service;
+mod share;
+mod snapshot;
+mod task;
+mod summarize;
+mod testgen;
+mod tls;
+mod tools;
+
+#[cfg(test)]
+mod integration_tests;
+#[cfg(test)]
+mod test_support;
+
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 use colored::Colorize;
 use rustyline::error::ReadlineError;
-use rustyline::DefaultEditor;
-use std::path::PathBuf;
+use rustyline::history::DefaultHistory;
+use rustyline::{DefaultEditor, Editor};
+use std::path::{Path, PathBuf};
 
-use agents::Orchestrator;
+use agents::{default_model, DiffInsights, DiffSource, Orchestrator, COMMANDS};
+use completion::CommandCompleter;
 use llm::LlmBackend;
+use render::OutputFormat;
 
 const BANNER: &str = r#"
   ____                            _
@@ -29,6 +74,8 @@ const BANNER: &str = r#"
 "#;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Cookie holding the web UI token once a page was opened with `?token=`
+const WEB_UI_TOKEN_COOKIE: &str = "sovereign_token";
 
 #[derive(Parser)]
 #[command(name = "sovereign")]
@@ -53,6 +100,24 @@ struct Cli {
     /// Data directory for storage
     #[arg(short, long)]
     data_dir: Option<PathBuf>,
+
+    /// Embedding model for semantic search (default: nomic-embed-text)
+    #[arg(long)]
+    embedding_model: Option<String>,
+
+    /// Don't record local usage statistics
+    #[arg(long)]
+    no_analytics: bool,
+
+    /// Report what indexing, memory writes, file edits, git operations and sync
+    /// would change, without changing anything
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Output format: markdown, plain, ansi, json or html (default: ansi on a
+    /// terminal, markdown when piped or served by the daemon)
+    #[arg(long, value_parser = OutputFormat::NAMES)]
+    render: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -68,6 +133,11 @@ enum Commands {
     Index {
         /// Path to codebase
         path: PathBuf,
+
+        /// Also drop indexed files that are now ignored or unsupported
+        /// (deleted files are always dropped)
+        #[arg(long)]
+        prune: bool,
     },
 
     /// Search the indexed codebase
@@ -76,6 +146,24 @@ enum Commands {
         query: String,
     },
 
+    /// Print the lines of indexed files matching a regular expression, as file:line
+    Grep {
+        /// Regular expression (plain text with -F)
+        pattern: String,
+
+        /// Match the pattern as plain text
+        #[arg(short = 'F', long)]
+        fixed_strings: bool,
+
+        /// Match regardless of case
+        #[arg(short, long)]
+        ignore_case: bool,
+
+        /// Path to codebase (default: the project of the current directory)
+        #[arg(short, long)]
+        path: Option<PathBuf>,
+    },
+
     /// Ask a question about the codebase
     Ask {
         /// Question to ask
@@ -84,12 +172,73 @@ enum Commands {
         /// Path to codebase
         #[arg(short, long)]
         path: Option<PathBuf>,
+
+        /// Output format (json includes the sources and retrieval confidence)
+        #[arg(long, default_value = "text", value_parser = ["text", "json"])]
+        format: String,
+
+        /// Answer from the code as it was at this tag, branch or commit
+        #[arg(long = "ref")]
+        reference: Option<String>,
+    },
+
+    /// Store a short summary of each indexed file that has none (resumes where it stopped)
+    SummarizeAll {
+        /// Path to codebase (default: the project of the current directory)
+        #[arg(short, long)]
+        path: Option<PathBuf>,
+
+        /// Summarize at most this many files
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+
+    /// Define the project's domain terms from its code and store them as a glossary
+    Glossary {
+        /// Path to codebase (default: the project of the current directory)
+        #[arg(short, long)]
+        path: Option<PathBuf>,
+
+        /// Define at most this many terms, most widely used first
+        #[arg(long, default_value = "30")]
+        limit: usize,
+
+        /// Show the stored glossary instead of building it
+        #[arg(long)]
+        list: bool,
     },
 
     /// Generate code
     Generate {
         /// Code generation request
         request: String,
+
+        /// Write the generated code (its single code block) to this file
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+
+    /// Make a described change to a file and write it back (--dry-run prints the diff instead)
+    Edit {
+        /// File to change
+        file: PathBuf,
+
+        /// The change to make, e.g. "make the capacity configurable"
+        instruction: String,
+    },
+
+    /// Write documentation comments for a file or a definition (--dry-run prints the diff instead)
+    Doc {
+        /// File, or name of a definition in the indexed project
+        target: String,
+    },
+
+    /// Explain compiler or linter output read from stdin and suggest a fix,
+    /// e.g. `cargo build 2>&1 | sovereign explain-error`
+    ExplainError {
+        /// Write the suggested fix (the REPL's /undo puts the files back)
+        #[arg(long)]
+        apply: bool,
     },
 
     /// Explain code from stdin or file
@@ -101,15 +250,33 @@ enum Commands {
     /// Show codebase statistics
     Stats,
 
-    /// Show stored memories
+    /// Show stored memories, or prune them
     Memory {
+        #[command(subcommand)]
+        action: Option<MemoryCommands>,
+
         /// Number of memories to show
         #[arg(short, long, default_value = "10")]
         limit: usize,
     },
 
+    /// Write a markdown digest of recent decisions, preferences and recurring questions
+    Digest {
+        /// Number of days to cover
+        #[arg(short, long, default_value = "7")]
+        days: u32,
+
+        /// Directory to write the note to (default: [digest] output_dir, or digests/ in the data dir)
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+    },
+
     /// Start background daemon
     Daemon {
+        /// `stop` the running daemon, or `restart` it with these options
+        #[arg(value_parser = ["stop", "restart"])]
+        action: Option<String>,
+
         /// Use TCP instead of Unix socket
         #[arg(long)]
         tcp: bool,
@@ -126,9 +293,115 @@ enum Commands {
         #[arg(long, default_value = "7656")]
         ws_port: u16,
 
+        /// Also serve the HTTP API (JSON endpoints, server-sent events for streaming)
+        #[arg(long)]
+        http: bool,
+
+        /// HTTP API port (default: 7658)
+        #[arg(long, default_value = "7658")]
+        http_port: u16,
+
+        /// Serve Prometheus metrics at /metrics on their own port
+        #[arg(long)]
+        metrics: bool,
+
+        /// Metrics port (default: 7659)
+        #[arg(long, default_value = "7659")]
+        metrics_port: u16,
+
+        /// Address for the TCP, WebSocket and HTTP listeners; anything but loopback needs --token
+        #[arg(long, default_value = net::DEFAULT_BIND)]
+        bind: String,
+
+        /// Encrypt the TCP, WebSocket and HTTP listeners, with a self-signed certificate
+        /// kept in the data directory unless --tls-cert is given
+        #[arg(long)]
+        tls: bool,
+
+        /// Certificate to present (PEM); implies --tls
+        #[arg(long, requires = "tls_key")]
+        tls_cert: Option<PathBuf>,
+
+        /// Private key of --tls-cert (PEM)
+        #[arg(long, requires = "tls_cert")]
+        tls_key: Option<PathBuf>,
+
+        /// Token clients must send (can also use SOVEREIGN_TOKEN env var)
+        #[arg(long)]
+        token: Option<String>,
+
         /// Watch directories for auto-reindex
         #[arg(short, long)]
         watch: Vec<PathBuf>,
+
+        /// Run in the background, logging to daemon.log in the data directory
+        #[arg(long)]
+        detach: bool,
+    },
+
+    /// Show how the running daemon is doing: uptime, model, watched paths, index, queue
+    Status {
+        /// Reach the daemon over TCP instead of its Unix socket
+        #[arg(long)]
+        tcp: bool,
+
+        /// TCP port (default: 7655)
+        #[arg(short, long)]
+        port: Option<u16>,
+
+        /// Address the daemon listens on, as its --bind gave it
+        #[arg(long, default_value = net::DEFAULT_BIND)]
+        bind: String,
+
+        /// The daemon runs with --tls; only the certificate in the data directory is trusted
+        #[arg(long)]
+        tls: bool,
+
+        /// The certificate the daemon presents (PEM), when it was given --tls-cert; implies --tls
+        #[arg(long)]
+        tls_cert: Option<PathBuf>,
+
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Run a command through the running daemon, e.g. `send /search config loading`
+    Send {
+        /// Chat command, or a question
+        command: String,
+
+        /// Its arguments
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+
+        /// Reach the daemon over TCP instead of its Unix socket
+        #[arg(long)]
+        tcp: bool,
+
+        /// TCP port (default: 7655)
+        #[arg(short, long)]
+        port: Option<u16>,
+
+        /// Address the daemon listens on, as its --bind gave it
+        #[arg(long, default_value = net::DEFAULT_BIND)]
+        bind: String,
+
+        /// The daemon runs with --tls; only the certificate in the data directory is trusted
+        #[arg(long)]
+        tls: bool,
+
+        /// The certificate the daemon presents (PEM), when it was given --tls-cert; implies --tls
+        #[arg(long)]
+        tls_cert: Option<PathBuf>,
+
+        /// Keep the conversation of this session, as the daemon's clients do
+        #[arg(long)]
+        session: Option<String>,
+
+        /// Print the model's text as it is generated
+        #[arg(long)]
+        stream: bool,
     },
 
     /// Watch directories for changes and auto-reindex
@@ -137,22 +410,292 @@ enum Commands {
         paths: Vec<PathBuf>,
     },
 
+    /// Run the daemon on login, as a systemd user service (Linux) or launchd agent (macOS)
+    InstallService {
+        /// Directories to watch besides those `[daemon] watch` lists
+        #[arg(long)]
+        watch: Vec<PathBuf>,
+
+        /// Replace a unit sovereign did not install, keeping a copy
+        #[arg(long)]
+        force: bool,
+
+        /// More daemon options, after `--` (e.g. `-- --tcp --websocket`)
+        #[arg(last = true)]
+        daemon_args: Vec<String>,
+    },
+
+    /// Stop the daemon service and remove it
+    UninstallService {
+        /// Remove a unit sovereign did not install, keeping a copy
+        #[arg(long)]
+        force: bool,
+    },
+
     /// Serve the web UI dashboard
     Serve {
         /// Port to serve web UI on (default: 7657)
         #[arg(short, long, default_value = "7657")]
         port: u16,
 
+        /// Address to listen on; anything but loopback needs --token
+        #[arg(long, default_value = net::DEFAULT_BIND)]
+        bind: String,
+
+        /// Token the dashboard API requires (can also use SOVEREIGN_TOKEN env var)
+        #[arg(long)]
+        token: Option<String>,
+
         /// Path to web-ui directory (default: ./web-ui)
         #[arg(long)]
         dir: Option<PathBuf>,
     },
 
     /// Generate a commit message for staged changes
-    Commit,
+    Commit {
+        /// Commit with the generated message once confirmed
+        #[arg(long)]
+        commit: bool,
+
+        /// Open $EDITOR on the generated message, then commit
+        #[arg(long)]
+        edit: bool,
+
+        /// Replace the last commit, with a message covering its changes and the staged ones
+        #[arg(long)]
+        amend: bool,
+
+        /// Split unrelated staged changes into several commits, each with its own message
+        #[arg(long, conflicts_with_all = ["edit", "amend"])]
+        split: bool,
+    },
+
+    /// Install git hooks that fill in commit messages from the staged diff
+    InstallHooks {
+        /// Also install a commit-msg hook checking messages against the conventional commit rules
+        #[arg(long)]
+        lint: bool,
+
+        /// Seconds the prepare-commit-msg hook waits for a message before leaving it blank
+        #[arg(long, default_value = "20")]
+        timeout: u64,
+
+        /// Replace hooks that sovereign did not install (they are kept as *.sovereign-backup)
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Run an installed git hook (called by the hooks themselves)
+    #[command(hide = true)]
+    Hook {
+        #[arg(value_parser = ["prepare-commit-msg", "commit-msg"])]
+        name: String,
+
+        /// The commit message file git passes
+        file: PathBuf,
+
+        /// Where the message comes from, for prepare-commit-msg (message, merge, commit, ...)
+        source: Option<String>,
+
+        #[arg(long, default_value = "20")]
+        timeout: u64,
+    },
+
+    /// Check commit messages against the conventional commit rules
+    LintCommits {
+        /// Commits to check, e.g. origin/main..HEAD; a single ref means <ref>..HEAD
+        /// (default: the default branch..HEAD)
+        range: Option<String>,
+
+        /// Output format
+        #[arg(long, default_value = "text", value_parser = ["text", "json"])]
+        format: String,
+
+        /// Only run the rule checks, without asking the model about vague subjects
+        #[arg(long)]
+        local_only: bool,
+    },
 
     /// Generate a PR summary for the current branch
-    PrSummary,
+    PrSummary {
+        /// Ref the branch is compared against (default: the default branch)
+        #[arg(long)]
+        base: Option<String>,
+
+        /// Write the summary to a file instead of stdout
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+
+        /// PR description template to fill in, e.g. .github/pull_request_template.md
+        #[arg(long)]
+        template: Option<PathBuf>,
+    },
+
+    /// Analyze staged changes or the current branch (change type, complexity, hunks)
+    AnalyzeDiff {
+        /// Analyze staged changes (default)
+        #[arg(long, conflicts_with = "branch")]
+        staged: bool,
+
+        /// Analyze the current branch against the default branch
+        #[arg(long)]
+        branch: bool,
+
+        /// Output format
+        #[arg(long, default_value = "text", value_parser = ["text", "json"])]
+        format: String,
+
+        /// Skip the LLM pass and only use the built-in heuristics
+        #[arg(long)]
+        local: bool,
+    },
+
+    /// Review staged (or unstaged) changes before committing
+    Review {
+        /// Review what is staged for the next commit rather than the working tree
+        #[arg(long)]
+        staged: bool,
+
+        /// Report format
+        #[arg(long, default_value = "markdown", value_parser = ["markdown", "sarif"])]
+        format: String,
+
+        /// Exit non-zero if a finding is at least this severe
+        #[arg(long, default_value = "never", value_parser = ["note", "warning", "error", "never"])]
+        fail_on: String,
+    },
+
+    /// Review the current branch in CI (non-interactive, SARIF or Markdown report)
+    CiReview {
+        /// Base ref to compare HEAD against
+        #[arg(long, default_value = "origin/main")]
+        base: String,
+
+        /// Report format
+        #[arg(long, default_value = "markdown", value_parser = ["markdown", "sarif"])]
+        format: String,
+
+        /// Write the report to a file instead of stdout
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+
+        /// Exit non-zero if a finding is at least this severe
+        #[arg(long, default_value = "error", value_parser = ["note", "warning", "error", "never"])]
+        fail_on: String,
+
+        /// Total time budget for model calls, in seconds
+        #[arg(long, default_value = "600")]
+        timeout: u64,
+
+        /// Maximum number of diff hunks to review
+        #[arg(long, default_value = "50")]
+        max_hunks: usize,
+
+        /// Only run the local pattern rules (no model calls)
+        #[arg(long)]
+        local_only: bool,
+    },
+
+    /// Audit the indexed code for security problems (ranked Markdown or SARIF report)
+    Audit {
+        /// Project to audit (default: current directory)
+        #[arg(short, long)]
+        path: Option<PathBuf>,
+
+        /// Only audit files in this language, e.g. rust or python
+        #[arg(short, long)]
+        language: Option<String>,
+
+        /// Report format
+        #[arg(long, default_value = "markdown", value_parser = ["markdown", "sarif"])]
+        format: String,
+
+        /// Write the report to a file instead of stdout
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+
+        /// Exit non-zero if a finding is at least this severe
+        #[arg(long, default_value = "never", value_parser = ["note", "warning", "error", "never"])]
+        fail_on: String,
+
+        /// Total time budget for model calls, in seconds
+        #[arg(long, default_value = "1800")]
+        timeout: u64,
+
+        /// Maximum number of files sent to the model
+        #[arg(long, default_value = "100")]
+        max_files: usize,
+
+        /// Only run the local pattern rules (no model calls)
+        #[arg(long)]
+        local_only: bool,
+    },
+
+    /// Retrieval (RAG) utilities
+    Rag {
+        #[command(subcommand)]
+        action: RagCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum MemoryCommands {
+    /// List memories of both stores, newest first
+    List {
+        /// Only memories of this type
+        #[arg(short = 't', long = "type", value_parser = memories::MEMORY_TYPES.to_vec())]
+        memory_type: Option<String>,
+
+        /// Number of memories to show
+        #[arg(short, long, default_value = "10")]
+        limit: usize,
+    },
+
+    /// Show a memory in full
+    Show {
+        /// Id of the memory, or its first characters
+        id: String,
+    },
+
+    /// Delete a memory from both stores
+    Delete {
+        /// Id of the memory, or its first characters
+        id: String,
+    },
+
+    /// Replace the text of a memory (opens $EDITOR when no text is given)
+    Edit {
+        /// Id of the memory, or its first characters
+        id: String,
+
+        /// New text
+        #[arg(trailing_var_arg = true)]
+        text: Vec<String>,
+    },
+
+    /// Set how important a memory is, from 0 to 1; the least important are pruned first
+    SetImportance {
+        /// Id of the memory, or its first characters
+        id: String,
+
+        importance: f32,
+    },
+
+    /// Archive (or delete) memories that faded or exceed the per-type limits of [retention]
+    Prune,
+
+    /// Condense clusters of old conversations into fact and decision memories
+    Consolidate,
+}
+
+#[derive(Subcommand)]
+enum RagCommands {
+    /// Calibrate hybrid search weights by labeling results as relevant
+    Tune {
+        /// Path to the indexed codebase (default: current directory)
+        #[arg(short, long)]
+        path: Option<PathBuf>,
+    },
 }
 
 #[tokio::main]
@@ -168,6 +711,14 @@ async fn main() -> Result<()> {
 
     std::fs::create_dir_all(&data_dir)?;
 
+    // Flags that take precedence over sovereign.toml, however often it is read
+    let overrides = config::Overrides {
+        embedding_model: cli.embedding_model.clone(),
+        dry_run: cli.dry_run,
+        no_analytics: cli.no_analytics,
+        ..Default::default()
+    };
+
     // Parse backend
     let backend = LlmBackend::from_str(&cli.backend).unwrap_or_else(|| {
         eprintln!("{}", format!("Unknown backend: {}. Using 'ollama'.", cli.backend).yellow());
@@ -175,18 +726,28 @@ async fn main() -> Result<()> {
     });
 
     // Determine default model based on backend
-    let model = cli.model.unwrap_or_else(|| {
-        match backend {
-            LlmBackend::Ollama => "qwen2.5-coder:14b".to_string(),
-            LlmBackend::DeepSeek => "deepseek-chat".to_string(),
-        }
-    });
-
-    // Check if backend is available
+    let model = cli.model.unwrap_or_else(|| default_model(backend).to_string());
+
+    // Validated by clap; the daemon only overrides its default when asked to
+    let render = cli.render.as_deref().and_then(OutputFormat::parse);
+    let output = render.unwrap_or_else(OutputFormat::for_terminal).renderer();
+
+    // Check if backend is available; git hooks must not wait on it or fail a commit for it
+    let needs_model = !matches!(
+        cli.command,
+        Some(
+            Commands::InstallHooks { .. }
+                | Commands::Hook { .. }
+                | Commands::Status { .. }
+                | Commands::Send { .. }
+                | Commands::InstallService { .. }
+                | Commands::UninstallService { .. }
+        )
+    );
     let test_client = llm::LlmClient::new(backend, &model, cli.api_key.as_deref());
     match test_client {
         Ok(client) => {
-            if !client.is_available().await {
+            if needs_model && !client.is_available().await {
                 match backend {
                     LlmBackend::Ollama => {
                         eprintln!("{}", "Error: Ollama is not running.".red());
@@ -201,27 +762,40 @@ async fn main() -> Result<()> {
                 std::process::exit(1);
             }
         }
-        Err(e) => {
+        Err(e) if needs_model => {
             eprintln!("{}", format!("Error initializing LLM client: {}", e).red());
             std::process::exit(1);
         }
+        Err(_) => {}
     }
 
     match cli.command {
         Some(Commands::Chat { path }) => {
-            run_chat(&model, backend, cli.api_key.as_deref(), &data_dir, path).await?;
+            run_chat(&model, backend, cli.api_key.as_deref(), &data_dir, &overrides, path, &*output).await?;
         }
 
-        Some(Commands::Index { path }) => {
-            let mut orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir)?;
+        Some(Commands::Index { path, prune }) => {
+            let mut orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
             println!("{}", "Indexing codebase...".cyan());
             let count = orchestrator.index_codebase(&path)?;
-            println!("{}", format!("Indexed {} files.", count).green());
+            if !overrides.dry_run {
+                println!("{}", format!("Indexed {} files.", count).green());
+            }
+
+            if prune {
+                let pruned = orchestrator.prune_index()?;
+                if !overrides.dry_run {
+                    println!("Pruned {} files that are no longer indexed.", pruned);
+                }
+            }
 
             if let Some(stats) = orchestrator.get_codebase_stats() {
                 println!("\nStatistics:");
                 println!("  Files: {}", stats.total_files);
                 println!("  Lines: {}", stats.total_lines);
+                if stats.metadata_only_files > 0 {
+                    println!("  Binary or oversized (not searched): {}", stats.metadata_only_files);
+                }
                 println!("  Languages:");
                 for (lang, count) in &stats.languages {
                     println!("    {}: {} files", lang, count);
@@ -230,117 +804,478 @@ async fn main() -> Result<()> {
         }
 
         Some(Commands::Search { query }) => {
-            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir)?;
-            // Need to have indexed first
+            // Searches the stored index of the project the current directory is in
+            let mut orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
             println!("{}", "Searching...".cyan());
-            let result = orchestrator.chat_agent.llm.generate(&query, None).await?;
-            println!("{}", result);
+            let result = orchestrator.process_command(&format!("/search {}", query)).await?;
+            println!("{}", output.render(&result));
+        }
+
+        Some(Commands::Grep { pattern, fixed_strings, ignore_case, path }) => {
+            let mut orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
+            if let Some(p) = path {
+                if !orchestrator.load_codebase(&p)? {
+                    orchestrator.index_codebase(&p)?;
+                }
+            }
+
+            let mut command = "/grep".to_string();
+            if fixed_strings {
+                command.push_str(" -F");
+            }
+            if ignore_case {
+                command.push_str(" -i");
+            }
+            // Quoted so a pattern starting with a dash is not taken for a flag
+            command.push_str(&format!(" \"{}\"", pattern));
+            let result = orchestrator.process_command(&command).await?;
+            println!("{}", output.render(&result));
         }
 
-        Some(Commands::Ask { question, path }) => {
-            let mut orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir.clone())?;
+        Some(Commands::Ask { question, path, format, reference }) => {
+            let mut orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir.clone(), &overrides)?;
+
+            // A project indexed before is used as stored; run `sovereign index` to refresh it
+            if let Some(p) = path {
+                if !orchestrator.load_codebase(&p)? {
+                    orchestrator.index_codebase(&p)?;
+                }
+            }
+
+            if format == "json" {
+                if reference.is_some() {
+                    anyhow::bail!("--ref answers are shown as text only");
+                }
+                println!("{}", orchestrator.ask_json(&question).await?);
+            } else {
+                println!("{}", "Thinking...".cyan());
+                let command = match reference {
+                    Some(reference) => format!("/ask --ref {} {}", reference, question),
+                    None => format!("/ask {}", question),
+                };
+                let result = orchestrator.process_command(&command).await?;
+                println!("\n{}", output.render(&result));
+            }
+        }
 
+        Some(Commands::SummarizeAll { path, limit }) => {
+            let mut orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
             if let Some(p) = path {
-                orchestrator.index_codebase(&p)?;
+                if !orchestrator.load_codebase(&p)? {
+                    orchestrator.index_codebase(&p)?;
+                }
             }
 
-            println!("{}", "Thinking...".cyan());
-            let result = orchestrator.process_command(&format!("/ask {}", question)).await?;
-            println!("\n{}", result);
+            println!("{}", "Summarizing files...".cyan());
+            let report = orchestrator.summarize_all(limit).await?;
+            println!("{}", report.green());
         }
 
-        Some(Commands::Generate { request }) => {
-            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir)?;
+        Some(Commands::Glossary { path, limit, list }) => {
+            let mut orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
+            if let Some(p) = path {
+                if !orchestrator.load_codebase(&p)? {
+                    orchestrator.index_codebase(&p)?;
+                }
+            }
+
+            if list {
+                println!("{}", orchestrator.glossary_report()?);
+            } else {
+                println!("{}", "Building glossary...".cyan());
+                println!("{}", orchestrator.build_glossary(limit).await?);
+            }
+        }
+
+        Some(Commands::Generate { request, out }) => {
+            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
             println!("{}", "Generating...".cyan());
             // generate_code uses streaming which prints directly to stdout
-            orchestrator.code_agent.generate_code(&request, None, None).await?;
+            let response = orchestrator.code_agent.generate_code(&request, None, None).await?;
             println!();
+
+            if let Some(out) = out {
+                let code = postprocess::extract_code(&response)
+                    .ok_or_else(|| anyhow::anyhow!("The response contains no code to write"))?;
+                if overrides.dry_run {
+                    println!("{}", dry_run::would(dry_run::describe_write(&out, &code)));
+                } else {
+                    std::fs::write(&out, code)?;
+                    println!("{} {}", "Code written to".green(), out.display());
+                }
+            }
+        }
+
+        Some(Commands::Edit { file, instruction }) => {
+            let mut orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
+            // Paths in the diff are read relative to the file's project, when it is indexed
+            if let Some(dir) = file.canonicalize()?.parent() {
+                orchestrator.load_codebase(dir)?;
+            }
+
+            let changes = orchestrator.propose_edit(&file, &instruction).await?;
+            if changes.is_empty() {
+                eprintln!("The model's diff changes nothing.");
+            } else if overrides.dry_run {
+                print!("{}", orchestrator.changes_diff(&changes));
+            } else {
+                orchestrator.apply_changes(&changes)?;
+                for change in &changes {
+                    eprintln!("{} {}", "Edited".green(), change.path.display());
+                }
+            }
+        }
+
+        Some(Commands::Doc { target }) => {
+            let mut orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
+            // A file is documented within its own project, a name within the current directory's
+            if let Ok(file) = std::path::Path::new(&target).canonicalize() {
+                if let Some(dir) = file.parent() {
+                    orchestrator.load_codebase(dir)?;
+                }
+            }
+
+            let changes = orchestrator.propose_docs(&target).await?;
+            if changes.is_empty() {
+                eprintln!("The model found nothing to document.");
+            } else if overrides.dry_run {
+                print!("{}", orchestrator.changes_diff(&changes));
+            } else {
+                orchestrator.apply_changes(&changes)?;
+                for change in &changes {
+                    eprintln!("{} {}", "Documented".green(), change.path.display());
+                }
+            }
+        }
+
+        Some(Commands::ExplainError { apply }) => {
+            use std::io::Read;
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            if input.trim().is_empty() {
+                anyhow::bail!("No output to explain; pipe it in, e.g. `cargo build 2>&1 | sovereign explain-error`");
+            }
+
+            let mut orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
+            eprintln!("{}", "Explaining...".cyan());
+            let (explanation, changes) = orchestrator.explain_error(&input).await?;
+            println!("{}", output.render(&explanation));
+
+            if apply && !changes.is_empty() {
+                if overrides.dry_run {
+                    let names: Vec<String> = changes.iter().map(|c| c.path.display().to_string()).collect();
+                    eprintln!("{}", dry_run::would(format!("write {}", names.join(", "))));
+                } else {
+                    orchestrator.apply_changes(&changes)?;
+                    for change in &changes {
+                        eprintln!("{} {}", "Fixed".green(), change.path.display());
+                    }
+                }
+            }
         }
 
         Some(Commands::Explain { file }) => {
-            let code = if let Some(f) = file {
-                std::fs::read_to_string(f)?
+            let mut orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
+            println!("{}", "Explaining...".cyan());
+            // explain_code uses streaming which prints directly to stdout
+            if let Some(f) = file {
+                orchestrator.explain_file(&f).await?;
             } else {
                 // Read from stdin
                 use std::io::Read;
                 let mut buffer = String::new();
                 std::io::stdin().read_to_string(&mut buffer)?;
-                buffer
-            };
-
-            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir)?;
-            println!("{}", "Explaining...".cyan());
-            // explain_code uses streaming which prints directly to stdout
-            orchestrator.code_agent.explain_code(&code, None).await?;
+                orchestrator.code_agent.explain_code(&buffer, None, None).await?;
+            }
+            orchestrator.shutdown_lsp().await;
             println!();
         }
 
         Some(Commands::Stats) => {
-            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir)?;
+            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
             if let Some(stats) = orchestrator.get_codebase_stats() {
                 println!("Codebase Statistics:");
                 println!("  Files: {}", stats.total_files);
                 println!("  Lines: {}", stats.total_lines);
+                if stats.metadata_only_files > 0 {
+                    println!("  Binary or oversized (not searched): {}", stats.metadata_only_files);
+                }
                 println!("  Languages:");
                 for (lang, count) in &stats.languages {
                     println!("    {}: {} files", lang, count);
                 }
+                let most_used = orchestrator.most_used_files(5);
+                if !most_used.is_empty() {
+                    println!("  Most used files:");
+                    for (path, count) in &most_used {
+                        println!("    {} ({} interactions)", path, count);
+                    }
+                }
             } else {
                 println!("No codebase indexed. Run: sovereign index <path>");
             }
         }
 
-        Some(Commands::Memory { limit }) => {
-            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir)?;
-            let memories = orchestrator.memory.get_recent(limit)?;
+        Some(Commands::Memory { action: Some(MemoryCommands::Prune), .. }) => {
+            let config = config::Config::load(&data_dir, None)?;
+            let memory = storage::MemoryStore::new(&data_dir)?.with_dry_run(overrides.dry_run);
+            let pruned = config.retention.prune(&memory, chrono::Utc::now())?;
 
-            if memories.is_empty() {
-                println!("No memories stored yet.");
+            if pruned.is_empty() {
+                println!("No memories to prune.");
             } else {
-                println!("Recent Memories:");
-                for mem in memories {
+                for p in &pruned {
                     println!(
-                        "  [{}] {}",
-                        mem.memory_type.as_str().cyan(),
-                        mem.content.chars().take(80).collect::<String>()
+                        "  [{}] {} {}",
+                        p.memory.memory_type.as_str().cyan(),
+                        format!("({}, {:.2})", p.reason.as_str(), p.importance).bright_black(),
+                        p.memory.content.chars().take(70).collect::<String>()
                     );
                 }
+                if !overrides.dry_run {
+                    let action = if config.retention.archive { "Archived" } else { "Deleted" };
+                    println!("{}", format!("{} {} memories.", action, pruned.len()).green());
+                }
             }
         }
 
-        Some(Commands::Daemon { tcp, port, websocket, ws_port, watch }) => {
+        Some(Commands::Memory { action: Some(MemoryCommands::Consolidate), .. }) => {
+            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
+            println!("{}", "Condensing old conversations...".cyan());
+            let consolidated = orchestrator.consolidate_memories(false).await?;
+
+            if consolidated.is_empty() {
+                println!("No old conversations to condense.");
+            }
+            for c in &consolidated {
+                let topic = format!("[{}]", c.cluster.topic).cyan();
+                let count = c.cluster.memories.len();
+                if overrides.dry_run {
+                    println!("{}", dry_run::would(format!("condense {} conversations about {}", count, topic)));
+                    continue;
+                }
+                println!("  {} {} conversations -> {} memories", topic, count, c.created.len());
+                for memory in &c.created {
+                    println!("    {} {}", format!("{}:", memory.memory_type.as_str()).bright_black(), memory.content);
+                }
+            }
+        }
+
+        Some(Commands::Memory { action, limit }) => {
+            let local = storage::MemoryStore::new(&data_dir)?.with_dry_run(overrides.dry_run);
+            let mut synced = storage::CrdtMemoryStore::new(&data_dir)?.with_dry_run(overrides.dry_run);
+            let mut memories = memories::Memories::new(&local, &mut synced);
+
+            match action.unwrap_or(MemoryCommands::List { memory_type: None, limit }) {
+                MemoryCommands::List { memory_type, limit } => {
+                    let entries = memories.list(memory_type.as_deref(), limit)?;
+                    if entries.is_empty() {
+                        println!("No memories stored yet.");
+                    } else {
+                        println!("Recent Memories:");
+                        for entry in entries {
+                            println!("{}", entry.line());
+                        }
+                    }
+                }
+                MemoryCommands::Show { id } => println!("{}", memories.show(&id)?),
+                MemoryCommands::Delete { id } => println!("{}", memories.delete(&id)?),
+                MemoryCommands::Edit { id, text } => {
+                    let text = if text.is_empty() {
+                        edit_in_editor(&memories.content(&id)?)?
+                    } else {
+                        text.join(" ")
+                    };
+                    println!("{}", memories.edit(&id, &text)?);
+                }
+                MemoryCommands::SetImportance { id, importance } => {
+                    println!("{}", memories.set_importance(&id, importance)?)
+                }
+                MemoryCommands::Prune | MemoryCommands::Consolidate => unreachable!("handled above"),
+            }
+        }
+
+        Some(Commands::Digest { days, out }) => {
+            let config = config::Config::load(&data_dir, None)?;
+            let memory = storage::MemoryStore::new(&data_dir)?;
+            let digest = digest::Digest::collect(&memory, days, chrono::Utc::now())?;
+
+            if digest.is_empty() {
+                println!("No memories in the last {} days.", days);
+            } else {
+                let dir = out.unwrap_or_else(|| config.digest.output_dir(&data_dir));
+                if overrides.dry_run {
+                    println!("{}", dry_run::would(format!("write a digest of {} days to {}", days, dir.display())));
+                } else {
+                    let path = digest.write(&dir)?;
+                    println!("{} {}", "Digest written to".green(), path.display());
+                }
+            }
+        }
+
+        Some(Commands::Daemon { action: Some(action), tcp, port, bind, tls, tls_cert, token, .. }) if action == "stop" => {
+            if stop_daemon(&data_dir, tcp, port, &bind, tls, tls_cert.as_deref(), token).await? {
+                println!("{}", "Daemon stopped.".green());
+            } else {
+                println!("{}", "No daemon is running.".yellow());
+            }
+        }
+
+        Some(Commands::Daemon {
+            action,
+            tcp,
+            port,
+            websocket,
+            ws_port,
+            http,
+            http_port,
+            metrics,
+            metrics_port,
+            bind,
+            tls,
+            tls_cert,
+            tls_key,
+            token,
+            watch,
+            detach,
+        }) => {
+            if action.is_some() && stop_daemon(&data_dir, tcp, port, &bind, tls, tls_cert.as_deref(), token.clone()).await? {
+                println!("{}", "Stopped the running daemon.".yellow());
+            }
+            if detach {
+                let pid = pidfile::detach(&data_dir)?;
+                println!("{} (pid {})", "Daemon started in the background".green(), pid);
+                println!("Log: {}", pidfile::log_path(&data_dir).display());
+                return Ok(());
+            }
+            let _pidfile = pidfile::Pidfile::claim(&data_dir)?;
             println!("{}", BANNER.cyan());
             println!("{}", "Starting Sovereign daemon...".green());
 
-            let mut daemon = daemon::Daemon::new(&model, backend, cli.api_key.as_deref(), data_dir.clone())?;
+            let mut daemon = daemon::Daemon::new(&model, backend, cli.api_key.as_deref(), data_dir.clone(), &overrides)?
+                .with_bind(&bind, net::resolve_token(token))?;
+            if let Some(format) = render {
+                daemon = daemon.with_format(format);
+            }
+            if tls || tls_cert.is_some() {
+                let tls = match (tls_cert, tls_key) {
+                    (Some(cert), Some(key)) => tls::Tls::load(&cert, &key)?,
+                    _ => tls::Tls::self_signed(&data_dir, &tls::default_hosts(&bind))?,
+                };
+                println!("TLS certificate {} (SHA-256 {})", tls.cert_path.display(), tls.fingerprint);
+                daemon = daemon.with_tls(tls);
+            }
+
+            // Watch the directories given and those `[daemon] watch` lists
+            if !watch.is_empty() {
+                println!("Starting file watcher...");
+            }
+            daemon.start_watcher(watch).await?;
+
+            // Start the daemon server, until interrupted; the servers borrow the daemon,
+            // so they are dropped before it shuts down
+            let served = {
+                let serve = async {
+                    if tcp {
+                        return daemon.start_tcp(port).await;
+                    }
+                    #[cfg(unix)]
+                    {
+                        daemon.start_unix().await
+                    }
+                    #[cfg(not(unix))]
+                    {
+                        daemon.start_tcp(port).await
+                    }
+                };
+                let http_api = async {
+                    if http {
+                        daemon.start_http(Some(http_port)).await
+                    } else {
+                        std::future::pending().await
+                    }
+                };
+                let websocket = async {
+                    if websocket {
+                        daemon.start_websocket(Some(ws_port)).await
+                    } else {
+                        std::future::pending().await
+                    }
+                };
+                let metrics = async {
+                    if metrics {
+                        daemon.start_metrics(Some(metrics_port)).await
+                    } else {
+                        std::future::pending().await
+                    }
+                };
+                tokio::pin!(serve, websocket, http_api, metrics);
+                loop {
+                    tokio::select! {
+                        result = &mut serve => break result,
+                        result = &mut websocket => break result,
+                        result = &mut http_api => break result,
+                        result = &mut metrics => break result,
+                        _ = stop_signal() => break Ok(()),
+                        _ = daemon.stop_requested() => break Ok(()),
+                        // Connections and sessions carry on meanwhile
+                        _ = reload_signal() => match daemon.reload().await {
+                            Ok(reloaded) => println!("{}", reloaded),
+                            Err(e) => eprintln!("{} {}", "Reload failed:".red(), e),
+                        },
+                    }
+                }
+            };
 
-            // Start file watcher if paths provided
-            if !watch.is_empty() {
-                println!("Starting file watcher...");
-                daemon.start_watcher(watch).await?;
-            }
+            println!("\n{}", "Stopping daemon...".yellow());
+            daemon.shutdown().await;
+            served?;
+        }
 
-            // Start WebSocket server if enabled (runs in background)
-            if websocket {
-                let daemon_clone = daemon.clone();
-                tokio::spawn(async move {
-                    if let Err(e) = daemon_clone.start_websocket(Some(ws_port)).await {
-                        eprintln!("WebSocket server error: {}", e);
-                    }
-                });
+        Some(Commands::Status { tcp, port, bind, tls, tls_cert, json }) => {
+            let client = daemon_client(&data_dir, tcp, port, &bind, tls, tls_cert.as_deref())?;
+            if !client.is_running().await {
+                eprintln!("{}", "No daemon is running.".yellow());
+                std::process::exit(1);
             }
-
-            // Start the daemon server
-            if tcp {
-                daemon.start_tcp(port).await?;
+            let token = net::resolve_token(None);
+            let status = client.status(token.clone()).await?;
+            let stats = client.send(daemon_request("/stats", None, token)).await?;
+            if json {
+                let mut value = serde_json::to_value(&status)?;
+                value["index"] = serde_json::Value::from(stats.result.unwrap_or_default());
+                println!("{}", serde_json::to_string_pretty(&value)?);
             } else {
-                #[cfg(unix)]
-                {
-                    daemon.start_unix().await?;
+                print_daemon_status(&status);
+                if let Some(stats) = stats.result {
+                    println!("\n{}", stats);
                 }
-                #[cfg(not(unix))]
-                {
-                    daemon.start_tcp(port).await?;
+            }
+        }
+
+        Some(Commands::Send { command, args, tcp, port, bind, tls, tls_cert, session, stream }) => {
+            let args = (!args.is_empty()).then(|| args.join(" "));
+            let request = daemon::DaemonRequest {
+                format: Some(render.unwrap_or_else(OutputFormat::for_terminal).as_str().to_string()),
+                session,
+                stream,
+                ..daemon_request(&command, args, net::resolve_token(None))
+            };
+            let mut streamed = false;
+            let response = daemon_client(&data_dir, tcp, port, &bind, tls, tls_cert.as_deref())?
+                .send_streaming(request, |text| {
+                    streamed = true;
+                    print!("{}", text);
+                    let _ = std::io::Write::flush(&mut std::io::stdout());
+                })
+                .await
+                .map_err(|e| anyhow::anyhow!("Could not reach the daemon ({}); is `sovereign daemon` running?", e))?;
+            match response.result {
+                _ if streamed && response.success => println!(),
+                Some(result) if response.success => println!("{}", result),
+                _ => {
+                    eprintln!("{}", response.error.unwrap_or_else(|| "The daemon gave no answer".to_string()).red());
+                    std::process::exit(1);
                 }
             }
         }
@@ -355,45 +1290,277 @@ async fn main() -> Result<()> {
             println!("{}", "Starting Sovereign with file watcher...".green());
 
             // Start daemon with watcher enabled
-            let mut daemon = daemon::Daemon::new(&model, backend, cli.api_key.as_deref(), data_dir.clone())?;
+            let daemon = daemon::Daemon::new(&model, backend, cli.api_key.as_deref(), data_dir.clone(), &overrides)?;
             daemon.start_watcher(paths).await?;
 
             println!("{}", "Watching for changes. Press Ctrl+C to stop.".green());
 
             // Keep running until interrupted
-            tokio::signal::ctrl_c().await?;
+            stop_signal().await;
+            daemon.shutdown().await;
             println!("\n{}", "Stopped watching.".yellow());
         }
 
-        Some(Commands::Commit) => {
-            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir)?;
+        Some(Commands::InstallService { watch, force, daemon_args }) => {
+            let manager = service::Manager::current()?;
+            if daemon_args.iter().any(|arg| arg == "--detach") {
+                anyhow::bail!("The service manager keeps the daemon running; leave out --detach");
+            }
+
+            // The service runs this binary with the options it was installed with
+            let mut args = vec![
+                "--data-dir".to_string(),
+                data_dir.to_string_lossy().to_string(),
+                "--backend".to_string(),
+                backend.as_str().to_string(),
+                "--model".to_string(),
+                model.clone(),
+                "daemon".to_string(),
+            ];
+            for path in watch {
+                let path = path.canonicalize().with_context(|| format!("Cannot watch {}", path.display()))?;
+                args.extend(["--watch".to_string(), path.to_string_lossy().to_string()]);
+            }
+            args.extend(daemon_args);
+            // In the unit's environment rather than its command line, which `ps` shows
+            let mut env = Vec::new();
+            if matches!(backend, LlmBackend::DeepSeek) {
+                let key = cli
+                    .api_key
+                    .clone()
+                    .or_else(|| std::env::var("DEEPSEEK_API_KEY").ok())
+                    .context("A DeepSeek service needs the API key; pass --api-key or set DEEPSEEK_API_KEY")?;
+                env.push(("DEEPSEEK_API_KEY".to_string(), key));
+            }
+            let command = service::ServiceCommand { program: std::env::current_exe()?, args, env };
+
+            if overrides.dry_run {
+                println!("{}", dry_run::would(format!("install and start {}", manager.unit_path()?.display())));
+                return Ok(());
+            }
+            let path = service::install(manager, &command, &pidfile::log_path(&data_dir), force)?;
+            println!("{} {}", "Installed".green(), path.display());
+            println!("The daemon is running and starts on every login; `sovereign uninstall-service` removes it.");
+        }
+
+        Some(Commands::UninstallService { force }) => {
+            let manager = service::Manager::current()?;
+            if overrides.dry_run {
+                println!("{}", dry_run::would(format!("stop and remove {}", manager.unit_path()?.display())));
+                return Ok(());
+            }
+            match service::uninstall(manager, force)? {
+                Some(path) => println!("{} {}", "Removed".green(), path.display()),
+                None => println!("{}", "No service is installed.".yellow()),
+            }
+        }
+
+        Some(Commands::Commit { split: true, .. }) => {
+            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
+            split_staged_commits(&orchestrator, &git::GitOps::current_dir()?).await?;
+        }
+
+        Some(Commands::Commit { commit, edit, amend, .. }) => {
+            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
+            let git_ops = git::GitOps::current_dir()?;
+            let committing = commit || edit;
+            if committing && !amend && git_ops.get_staged_diff()?.trim().is_empty() {
+                anyhow::bail!("Nothing is staged; stage changes with git add first");
+            }
+
             println!("{}", "Analyzing staged changes...".cyan());
-            match orchestrator.git_agent.commit_message_for_staged().await {
+            let generated = if amend {
+                orchestrator.git_agent.commit_message_for_amend().await
+            } else {
+                orchestrator.git_agent.commit_message_for_staged().await
+            };
+            match generated {
                 Ok(message) => {
                     println!("\n{}\n", "Suggested commit message:".green());
                     println!("{}", message);
+                    if committing {
+                        commit_with_message(&git_ops, message.trim(), amend, edit, overrides.dry_run)?;
+                    }
                 }
+                Err(e) if committing => return Err(e.context("Could not generate a commit message")),
                 Err(e) => {
                     println!("{}", format!("Error: {}", e).red());
                 }
             }
         }
 
-        Some(Commands::PrSummary) => {
-            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir)?;
-            println!("{}", "Analyzing branch changes...".cyan());
-            match orchestrator.git_agent.pr_summary_for_branch().await {
-                Ok(summary) => {
-                    println!("\n{}\n", "PR Summary:".green());
-                    println!("{}", summary);
+        Some(Commands::InstallHooks { lint, timeout, force }) => {
+            let git_ops = git::GitOps::current_dir()?;
+            if !git_ops.is_git_repo() {
+                anyhow::bail!("install-hooks must run inside a git repository");
+            }
+            let hooks_dir = git_ops.hooks_dir()?;
+
+            // The hooks run this binary with the options it was installed with
+            let args = vec![
+                "--data-dir".to_string(),
+                data_dir.to_string_lossy().to_string(),
+                "--backend".to_string(),
+                backend.as_str().to_string(),
+                "--model".to_string(),
+                model.clone(),
+            ];
+            let command = hooks::HookCommand { program: std::env::current_exe()?, args };
+
+            let mut scripts = vec![("prepare-commit-msg", hooks::prepare_commit_msg_script(&command, timeout))];
+            if lint {
+                scripts.push(("commit-msg", hooks::commit_msg_script(&command)));
+            }
+            for (name, script) in scripts {
+                if overrides.dry_run {
+                    println!("{}", dry_run::would(format!("write {}", hooks_dir.join(name).display())));
+                    continue;
+                }
+                let path = hooks::install(&hooks_dir, name, &script, force)?;
+                println!("{} {}", "Installed".green(), path.display());
+            }
+        }
+
+        Some(Commands::Hook { name, file, source, timeout }) => {
+            if name == "commit-msg" {
+                let message = commit_lint::message_text(&std::fs::read_to_string(&file)?);
+                let problems = commit_lint::check(&message);
+                for problem in &problems {
+                    eprintln!("{}:{}: {} ({})", file.display(), problem.line, problem.message, problem.rule);
+                }
+                if !problems.is_empty() {
+                    eprintln!("Commit message rejected; fix it, or commit with --no-verify to skip the check.");
+                    std::process::exit(1);
+                }
+            } else if hooks::should_prefill(source.as_deref()) {
+                let generate = commit_message_for_hook(&model, backend, cli.api_key.as_deref(), &data_dir, &overrides);
+                match tokio::time::timeout(std::time::Duration::from_secs(timeout), generate).await {
+                    Ok(Ok(Some(message))) => {
+                        let existing = std::fs::read_to_string(&file).unwrap_or_default();
+                        std::fs::write(&file, hooks::prefill(&existing, &message))?;
+                    }
+                    Ok(Ok(None)) => {}
+                    Ok(Err(e)) => eprintln!("sovereign: no commit message suggested ({})", e),
+                    Err(_) => eprintln!("sovereign: no commit message suggested within {}s", timeout),
                 }
+            }
+        }
+
+        Some(Commands::LintCommits { range, format, local_only }) => {
+            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
+            if !lint_commits(&orchestrator, range.as_deref(), &format, local_only).await? {
+                std::process::exit(1);
+            }
+        }
+
+        Some(Commands::PrSummary { base, out, template }) => {
+            let template = match template {
+                Some(path) => Some(
+                    std::fs::read_to_string(&path).with_context(|| format!("Failed to read template {}", path.display()))?,
+                ),
+                None => None,
+            };
+            let mut orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
+            if let Some(note) = orchestrator.capability_note("pr_summary") {
+                eprintln!("{}", note.yellow());
+            }
+            println!("{}", "Analyzing branch changes...".cyan());
+            match orchestrator.git_agent.pr_summary_against(base.as_deref(), template.as_deref()).await {
+                Ok(summary) => match out {
+                    Some(out) if overrides.dry_run => println!("{}", dry_run::would(dry_run::describe_write(&out, &summary))),
+                    Some(out) => {
+                        std::fs::write(&out, format!("{}\n", summary.trim_end()))?;
+                        println!("{} {}", "PR summary written to".green(), out.display());
+                    }
+                    None => {
+                        println!("\n{}\n", "PR Summary:".green());
+                        println!("{}", summary);
+                    }
+                },
+                Err(e) if out.is_some() => return Err(e),
                 Err(e) => {
                     println!("{}", format!("Error: {}", e).red());
                 }
             }
         }
 
-        Some(Commands::Serve { port, dir }) => {
+        Some(Commands::AnalyzeDiff { staged: _, branch, format, local }) => {
+            let mut orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
+            if !local {
+                if let Some(note) = orchestrator.capability_note("analyze_diff") {
+                    eprintln!("{}", note.yellow());
+                }
+            }
+            let source = if branch { DiffSource::Branch } else { DiffSource::Staged };
+            let insights = orchestrator.git_agent.analyze_changes(source, local).await?;
+
+            if format == "json" {
+                println!("{}", serde_json::to_string_pretty(&insights)?);
+            } else {
+                print_diff_insights(&insights);
+            }
+        }
+
+        Some(Commands::Review { staged, format, fail_on }) => {
+            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir, &overrides)?;
+            let (findings, notes) = orchestrator.review_changes(staged).await?;
+            let report = match review::ReportFormat::from_str(&format) {
+                Some(review::ReportFormat::Sarif) => review::to_sarif(&findings, VERSION)?,
+                _ => {
+                    let title = if staged { "Review of staged changes" } else { "Review of unstaged changes" };
+                    review::to_markdown(title, &findings, &notes)
+                }
+            };
+            println!("{}", report);
+
+            let threshold = review::Severity::from_str(&fail_on);
+            if threshold.is_some_and(|threshold| findings.iter().any(|f| f.severity >= threshold)) {
+                std::process::exit(1);
+            }
+        }
+
+        Some(Commands::CiReview { base, format, out, fail_on, timeout, max_hunks, local_only }) => {
+            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir.clone(), &overrides)?;
+            let options = review::CiReviewOptions {
+                base,
+                format: review::ReportFormat::from_str(&format).unwrap_or(review::ReportFormat::Markdown),
+                fail_on: review::Severity::from_str(&fail_on),
+                timeout: std::time::Duration::from_secs(timeout),
+                max_hunks,
+                local_only,
+            };
+
+            let failed = run_ci_review(&orchestrator, &data_dir, &options, out.as_deref()).await?;
+            if failed {
+                std::process::exit(1);
+            }
+        }
+
+        Some(Commands::Audit { path, language, format, out, fail_on, timeout, max_files, local_only }) => {
+            let orchestrator = Orchestrator::new(&model, backend, cli.api_key.as_deref(), data_dir.clone(), &overrides)?;
+            let options = audit::AuditOptions {
+                language: language.map(|l| l.to_lowercase()),
+                format: review::ReportFormat::from_str(&format).unwrap_or(review::ReportFormat::Markdown),
+                fail_on: review::Severity::from_str(&fail_on),
+                timeout: std::time::Duration::from_secs(timeout),
+                max_files,
+                local_only,
+            };
+            let root = match path {
+                Some(p) => p.canonicalize()?,
+                None => std::env::current_dir()?,
+            };
+
+            let failed = run_audit(&orchestrator, &data_dir, &root, &options, out.as_deref()).await?;
+            if failed {
+                std::process::exit(1);
+            }
+        }
+
+        Some(Commands::Serve { port, dir, bind, token }) => {
+            let token = net::resolve_token(token);
+            net::check_bind(&bind, token.as_deref())?;
+
             println!("{}", BANNER.cyan());
             println!("{}", "Starting Sovereign Web UI server...".green());
 
@@ -410,18 +1577,29 @@ async fn main() -> Result<()> {
                 std::process::exit(1);
             }
 
+            let listener = net::bind_tcp(&bind, port, "Web UI").await?;
             println!("Serving: {}", web_ui_dir.display().to_string().green());
-            println!("URL:     {}", format!("http://localhost:{}", port).cyan());
+            println!("URL:     {}", format!("http://{}", listener.local_addr()?).cyan());
             println!();
             println!("{}", "Press Ctrl+C to stop.".bright_black());
 
             // Start simple HTTP server for static files
-            serve_web_ui(&web_ui_dir, port).await?;
+            serve_web_ui(listener, &web_ui_dir, &data_dir, token, !net::is_loopback(&bind), !overrides.no_analytics).await?;
         }
 
+        Some(Commands::Rag { action }) => match action {
+            RagCommands::Tune { path } => {
+                let root = match path {
+                    Some(p) => p,
+                    None => std::env::current_dir()?,
+                };
+                run_rag_tune(&data_dir, &root, &overrides).await?;
+            }
+        },
+
         None => {
             // Default to chat mode
-            run_chat(&model, backend, cli.api_key.as_deref(), &data_dir, None).await?;
+            run_chat(&model, backend, cli.api_key.as_deref(), &data_dir, &overrides, None, &*output).await?;
         }
     }
 
@@ -432,8 +1610,10 @@ async fn run_chat(
     model: &str,
     backend: LlmBackend,
     api_key: Option<&str>,
-    data_dir: &PathBuf,
+    data_dir: &Path,
+    overrides: &config::Overrides,
     codebase_path: Option<PathBuf>,
+    output: &dyn render::Renderer,
 ) -> Result<()> {
     println!("{}", BANNER.cyan());
     println!(
@@ -445,24 +1625,39 @@ async fn run_chat(
     println!("Model: {}", model.green());
     println!("Backend: {}", backend.as_str().green());
     println!("Data:  {}", data_dir.display().to_string().green());
+    if overrides.dry_run {
+        println!("{}", "Dry run: changes are described, not made.".yellow());
+    }
     println!();
     println!("Type {} for commands, or just chat!", "/help".cyan());
     println!("{}", "─".repeat(50).bright_black());
 
-    let mut orchestrator = Orchestrator::new(model, backend, api_key, data_dir.clone())?;
+    let mut orchestrator = Orchestrator::new(model, backend, api_key, data_dir.to_path_buf(), overrides)?;
 
     // Index codebase if provided
     if let Some(path) = codebase_path {
         println!("\n{}", "Indexing codebase...".cyan());
         let count = orchestrator.index_codebase(&path)?;
-        println!("{}\n", format!("Indexed {} files.", count).green());
+        if !overrides.dry_run {
+            println!("{}\n", format!("Indexed {} files.", count).green());
+        }
+    }
+
+    // Faded memories would otherwise crowd the context
+    match orchestrator.prune_memories(true) {
+        Ok(pruned) if !pruned.is_empty() && !overrides.dry_run => {
+            println!("{}", format!("Pruned {} faded memories.", pruned.len()).bright_black());
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("{}", format!("Memory pruning failed: {}", e).yellow()),
     }
 
     // Add memory context to chat
     orchestrator.chat_agent.add_memory_context();
 
-    // Setup readline
-    let mut rl = DefaultEditor::new()?;
+    // Setup readline, with tab completion of commands and their arguments
+    let mut rl = Editor::<CommandCompleter, DefaultHistory>::new()?;
+    rl.set_helper(Some(CommandCompleter::new(COMMANDS.to_vec())));
     let history_path = data_dir.join("history.txt");
     let _ = rl.load_history(&history_path);
 
@@ -487,7 +1682,10 @@ async fn run_chat(
                     let path = PathBuf::from(line.trim_start_matches("/index ").trim());
                     println!("{}", "Indexing...".cyan());
                     match orchestrator.index_codebase(&path) {
-                        Ok(count) => println!("{}", format!("Indexed {} files.", count).green()),
+                        Ok(count) if !overrides.dry_run => {
+                            println!("{}", format!("Indexed {} files.", count).green())
+                        }
+                        Ok(_) => {}
                         Err(e) => println!("{}", format!("Error: {}", e).red()),
                     }
                     continue;
@@ -500,11 +1698,11 @@ async fn run_chat(
                         if !response.is_empty() && !line.starts_with('/') {
                             // Response was already streamed for chat
                         } else if !response.is_empty() {
-                            println!("{}", response);
+                            println!("{}", output.render(&response));
                         }
                     }
                     Err(e) => {
-                        println!("{}", format!("Error: {}", e).red());
+                        println!("{}", output.render_error(&e.to_string()));
                     }
                 }
                 println!();
@@ -525,22 +1723,689 @@ async fn run_chat(
 
     // Save history
     let _ = rl.save_history(&history_path);
+    orchestrator.shutdown_lsp().await;
 
     Ok(())
 }
 
-/// Serve static files from the web-ui directory
-async fn serve_web_ui(dir: &PathBuf, port: u16) -> Result<()> {
-    use tokio::net::TcpListener;
-    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+fn print_diff_insights(insights: &DiffInsights) {
+    println!("{}", "Diff Analysis".cyan());
+    println!("  Summary:    {}", insights.summary);
+    println!("  Type:       {}", insights.change_type.as_str());
+    println!("  Complexity: {}", insights.complexity.as_str());
+    println!(
+        "  Breaking:   {}",
+        if insights.breaking_potential { "possibly".yellow() } else { "no".green() }
+    );
+    println!("  Areas:      {}", insights.affected_areas.join(", "));
+    println!(
+        "  Changes:    +{} -{} in {} file(s), {} hunk(s)",
+        insights.total_additions,
+        insights.total_deletions,
+        insights.files.len(),
+        insights.hunks.len()
+    );
+    for file in &insights.files {
+        println!(
+            "    {} {} (+{} -{})",
+            file.status.as_str().bright_black(),
+            file.path,
+            file.additions,
+            file.deletions
+        );
+    }
+}
+
+/// Client of the daemon `sovereign daemon` starts with these options: its Unix socket,
+/// or its port on `bind`, trusting only its certificate when it uses TLS
+fn daemon_client(
+    data_dir: &Path,
+    tcp: bool,
+    port: Option<u16>,
+    bind: &str,
+    tls: bool,
+    tls_cert: Option<&Path>,
+) -> Result<daemon::DaemonClient> {
+    #[cfg(unix)]
+    if !tcp {
+        return Ok(daemon::DaemonClient::unix(data_dir));
+    }
+    #[cfg(not(unix))]
+    let _ = tcp;
+    let client = daemon::DaemonClient::tcp(bind, port);
+    if !tls && tls_cert.is_none() {
+        return Ok(client);
+    }
+    let cert = tls_cert.map(Path::to_path_buf).unwrap_or_else(|| tls::self_signed_cert(data_dir));
+    Ok(client.with_tls(tls::cert_fingerprint(&cert)?))
+}
+
+/// Stop the running daemon: the one named in the pidfile, else whichever answers on
+/// the socket or port; false if none was running
+async fn stop_daemon(
+    data_dir: &Path,
+    tcp: bool,
+    port: Option<u16>,
+    bind: &str,
+    tls: bool,
+    tls_cert: Option<&Path>,
+    token: Option<String>,
+) -> Result<bool> {
+    let dir = data_dir.to_path_buf();
+    if tokio::task::spawn_blocking(move || pidfile::stop(&dir)).await?? {
+        return Ok(true);
+    }
+    daemon_client(data_dir, tcp, port, bind, tls, tls_cert)?.stop(net::resolve_token(token)).await
+}
+
+/// A request for `command` with the usual defaults
+fn daemon_request(command: &str, args: Option<String>, token: Option<String>) -> daemon::DaemonRequest {
+    daemon::DaemonRequest {
+        command: command.to_string(),
+        args,
+        token,
+        format: None,
+        session: None,
+        stream: false,
+        id: None,
+        background: false,
+    }
+}
+
+fn print_daemon_status(status: &daemon::DaemonStatus) {
+    let uptime = status.uptime_secs;
+    println!("{}", "Sovereign daemon".cyan().bold());
+    println!(
+        "  {} {}h {}m {}s (since {})",
+        "Uptime:".bright_black(),
+        uptime / 3600,
+        uptime / 60 % 60,
+        uptime % 60,
+        status.started_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M")
+    );
+    println!("  {} {}", "Model:".bright_black(), status.model);
+    println!("  {} {}", "Data dir:".bright_black(), status.data_dir.display());
+    if status.watching.is_empty() {
+        println!("  {} nothing", "Watching:".bright_black());
+    }
+    for (i, path) in status.watching.iter().enumerate() {
+        let label = if i == 0 { "Watching:" } else { "         " };
+        println!("  {} {}", label.bright_black(), path.display());
+    }
+    println!(
+        "  {} {} workers, {} queued, {} sessions",
+        "Requests:".bright_black(),
+        status.workers,
+        status.queued,
+        status.sessions
+    );
+    println!("  {} {} files waiting to be re-embedded", "Embeddings:".bright_black(), status.pending_embeddings);
+}
+
+/// Resolves on Ctrl+C, or when the process is sent SIGTERM
+async fn stop_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Resolves on SIGHUP, with which the daemon is asked to reload sovereign.toml; never
+/// where there is no such signal
+async fn reload_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut hangup) = signal(SignalKind::hangup()) {
+            hangup.recv().await;
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+/// A message for what is staged in the current repository, from the running daemon
+/// when there is one and from the model directly otherwise; `None` when nothing is staged
+async fn commit_message_for_hook(model: &str, backend: LlmBackend, api_key: Option<&str>, data_dir: &Path, overrides: &config::Overrides) -> Result<Option<String>> {
+    let repo = std::env::current_dir()?;
+    if git::GitOps::new(&repo).get_staged_diff()?.trim().is_empty() {
+        return Ok(None);
+    }
+
+    #[cfg(unix)]
+    let client = daemon::DaemonClient::unix(data_dir);
+    #[cfg(not(unix))]
+    let client = daemon::DaemonClient::tcp(net::DEFAULT_BIND, None);
+    let request = daemon::DaemonRequest {
+        command: "/commit".to_string(),
+        args: Some(repo.to_string_lossy().to_string()),
+        token: net::resolve_token(None),
+        format: Some("plain".to_string()),
+        session: None,
+        stream: false,
+        id: None,
+        background: false,
+    };
+    let message = match client.send(request).await {
+        Ok(response) if response.success => response.result.unwrap_or_default(),
+        Ok(response) => anyhow::bail!(response.error.unwrap_or_else(|| "the daemon gave no message".to_string())),
+        // Not running: ask the model from here
+        Err(_) => {
+            let orchestrator = Orchestrator::new(model, backend, api_key, data_dir.to_path_buf(), overrides)?;
+            orchestrator.git_agent.commit_message_for_staged_in(&repo).await?
+        }
+    };
+    Ok(Some(message).filter(|m| !m.trim().is_empty()))
+}
+
+/// Check the messages of the commits in `range` and print a report; returns whether
+/// they all pass
+async fn lint_commits(orchestrator: &Orchestrator, range: Option<&str>, format: &str, local_only: bool) -> Result<bool> {
+    let git_ops = git::GitOps::current_dir()?;
+    if !git_ops.is_git_repo() {
+        anyhow::bail!("lint-commits must run inside a git repository");
+    }
+    let range = match range {
+        Some(range) if range.contains("..") => range.to_string(),
+        Some(base) => format!("{}..HEAD", base),
+        None => format!("{}..HEAD", git_ops.get_default_branch()?),
+    };
+
+    let mut reports: Vec<commit_lint::CommitReport> = git_ops
+        .get_commit_messages(&range)?
+        .into_iter()
+        .map(|(hash, message)| commit_lint::CommitReport {
+            subject: message.lines().next().unwrap_or_default().to_string(),
+            problems: commit_lint::check(&message),
+            hash,
+        })
+        .collect();
+
+    // Subjects that already break a rule are not worth asking about
+    if !local_only {
+        let asked: Vec<usize> = (0..reports.len()).filter(|&i| reports[i].problems.is_empty()).collect();
+        let subjects: Vec<&str> = asked.iter().map(|&i| reports[i].subject.as_str()).collect();
+        match orchestrator.git_agent.vague_subjects(&subjects).await {
+            Ok(vague) => {
+                for (n, reason) in vague {
+                    reports[asked[n]].problems.push(commit_lint::Problem {
+                        rule: commit_lint::VAGUE_RULE,
+                        line: 1,
+                        message: format!("Subject is vague: {}", reason),
+                    });
+                }
+            }
+            Err(e) => eprintln!("{}", format!("Vague subjects not checked: {}", e).yellow()),
+        }
+    }
+
+    let failed = reports.iter().filter(|r| !r.problems.is_empty()).count();
+    if format == "json" {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "range": range,
+                "commits": reports.len(),
+                "failed": failed,
+                "reports": reports,
+            }))?
+        );
+    } else {
+        for report in reports.iter().filter(|r| !r.problems.is_empty()) {
+            println!("{} {}", &report.hash[..report.hash.len().min(7)], report.subject.bold());
+            for problem in &report.problems {
+                println!("  {} line {}: {}", problem.rule.yellow(), problem.line, problem.message);
+            }
+        }
+        let summary = format!("{} of {} commits in {} pass", reports.len() - failed, reports.len(), range);
+        println!("{}", if failed == 0 { summary.green() } else { summary.red() });
+    }
+    Ok(failed == 0)
+}
+
+/// Propose a commit for each group of related staged hunks and, once confirmed, make
+/// them in order; if one fails, what was not committed is staged again
+async fn split_staged_commits(orchestrator: &Orchestrator, git_ops: &git::GitOps) -> Result<()> {
+    let diff = git_ops.get_staged_diff()?;
+    if diff.trim().is_empty() {
+        anyhow::bail!("Nothing is staged; stage changes with git add first");
+    }
+    let groups = commit_split::group(commit_split::pieces(&diff));
+    if groups.len() < 2 {
+        println!("The staged changes belong together; commit them with `sovereign commit --commit`.");
+        return Ok(());
+    }
+
+    println!("{}", format!("Writing messages for {} commits...", groups.len()).cyan());
+    let mut messages = Vec::new();
+    for (i, group) in groups.iter().enumerate() {
+        let message = orchestrator.git_agent.generate_commit_message(&group.patch()).await?;
+        let subject = message.trim().lines().next().unwrap_or_default().to_string();
+        println!("\n{} {}", format!("Commit {} of {}:", i + 1, groups.len()).green(), subject);
+        for file in group.describe_files() {
+            println!("  {}", file);
+        }
+        messages.push(message.trim().to_string());
+    }
+
+    if orchestrator.dry_run() {
+        eprintln!("{}", dry_run::would(format!("make these {} commits", groups.len())));
+        return Ok(());
+    }
+    let mut rl = DefaultEditor::new()?;
+    let confirm = rl.readline(&format!("\nMake these {} commits? [y/N] ", groups.len())).unwrap_or_default();
+    if !confirm.trim().eq_ignore_ascii_case("y") {
+        println!("Not committed.");
+        return Ok(());
+    }
+
+    git_ops.unstage_all()?;
+    for (i, (group, message)) in groups.iter().zip(&messages).enumerate() {
+        let committed = git_ops.apply_to_index(&group.patch()).and_then(|_| git_ops.commit(message, false, false));
+        if let Err(e) = committed {
+            git_ops.unstage_all()?;
+            for rest in &groups[i..] {
+                git_ops.apply_to_index(&rest.patch())?;
+            }
+            return Err(e.context(format!(
+                "Commit {} of {} failed; the changes not yet committed are staged again",
+                i + 1,
+                groups.len()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Run `git commit` with a generated message: after a yes at the prompt, or with
+/// `edit`, after the user has changed it in their editor
+fn commit_with_message(git_ops: &git::GitOps, message: &str, amend: bool, edit: bool, dry_run: bool) -> Result<()> {
+    let action = if amend { "amend the last commit with this message" } else { "commit with this message" };
+    if dry_run {
+        eprintln!("{}", dry_run::would(action));
+        return Ok(());
+    }
+    if !edit {
+        let mut rl = DefaultEditor::new()?;
+        let question = if amend { "Amend the last commit with this message?" } else { "Commit with this message?" };
+        let confirm = rl.readline(&format!("\n{} [y/N] ", question)).unwrap_or_default();
+        if !confirm.trim().eq_ignore_ascii_case("y") {
+            println!("Not committed.");
+            return Ok(());
+        }
+    }
+    git_ops.commit(message, amend, edit)
+}
+
+/// Let the user rewrite `text` in $EDITOR (vi if unset); returns what they saved
+fn edit_in_editor(text: &str) -> Result<String> {
+    let path = std::env::temp_dir().join(format!("sovereign-memory-{}.txt", std::process::id()));
+    std::fs::write(&path, text)?;
+
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(&path)
+        .status()
+        .with_context(|| format!("Failed to start {}", editor))?;
+    let edited = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+
+    if !status.success() {
+        anyhow::bail!("{} exited with {}", editor, status);
+    }
+    Ok(edited?)
+}
+
+/// Review HEAD against a base ref without prompts or streaming output.
+/// Progress goes to stderr so stdout carries only the report. Returns whether
+/// the findings cross the failure threshold.
+async fn run_ci_review(
+    orchestrator: &Orchestrator,
+    data_dir: &Path,
+    options: &review::CiReviewOptions,
+    out: Option<&std::path::Path>,
+) -> Result<bool> {
+    use std::time::Instant;
+
+    let root = std::env::current_dir()?;
+    let git_ops = git::GitOps::new(&root);
+    if !git_ops.is_git_repo() {
+        anyhow::bail!("ci-review must run inside a git repository");
+    }
+
+    // The index lives in the data dir, so caching it between CI runs makes this incremental
+    let config = config::Config::load(data_dir, Some(&root))?;
+    let index = storage::CodebaseIndex::with_tokenizer(data_dir, &root, &config.rag.fts_tokenizer)?
+        .with_config(&config.index)?
+        .with_dry_run(orchestrator.dry_run());
+    let reindexed = index.index_directory(false)?;
+    eprintln!("Index updated ({} changed files)", reindexed);
+
+    let diff = git_ops.get_diff_between(&options.base, "HEAD")?;
+    let analysis = git_ops.parse_diff(&diff)?;
+    let hunks: Vec<_> = analysis
+        .hunks
+        .iter()
+        .filter(|h| h.content.lines().any(|l| l.starts_with('+')))
+        .collect();
+
+    // Local pattern rules are cheap, so they cover every hunk regardless of limits
+    let mut findings: Vec<review::Finding> = hunks.iter().flat_map(|h| review::check_hunk(h)).collect();
+    let mut notes = Vec::new();
+
+    let model_hunks = if options.local_only { 0 } else { hunks.len().min(options.max_hunks) };
+    eprintln!(
+        "Checked {} hunk(s) against {}; sending {} to the model",
+        hunks.len(),
+        options.base,
+        model_hunks
+    );
+
+    let deadline = Instant::now() + options.timeout;
+
+    if !options.local_only && hunks.len() > options.max_hunks {
+        notes.push(format!(
+            "Reviewed the first {} of {} hunks (--max-hunks).",
+            options.max_hunks,
+            hunks.len()
+        ));
+    }
+
+    for (i, hunk) in hunks.iter().take(model_hunks).enumerate() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            notes.push(format!(
+                "Time budget exhausted; {} hunk(s) were not reviewed by the model.",
+                model_hunks - i
+            ));
+            break;
+        }
+
+        let file_context = review::hunk_context(&index, &root.join(&hunk.file_path).to_string_lossy(), hunk)
+            .ok()
+            .flatten();
+
+        let review = orchestrator.code_agent.review_hunk(hunk, file_context.as_deref());
+        match tokio::time::timeout(remaining, review).await {
+            Ok(Ok(mut hunk_findings)) => findings.append(&mut hunk_findings),
+            Ok(Err(e)) => notes.push(format!("{}:{} could not be reviewed: {}", hunk.file_path, hunk.new_start, e)),
+            Err(_) => notes.push(format!("{}:{} timed out", hunk.file_path, hunk.new_start)),
+        }
+    }
+
+    findings.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.file.cmp(&b.file)).then(a.line.cmp(&b.line)));
+
+    let report = match options.format {
+        review::ReportFormat::Sarif => review::to_sarif(&findings, VERSION)?,
+        review::ReportFormat::Markdown => review::to_markdown("Sovereign Review", &findings, &notes),
+    };
+
+    for note in &notes {
+        eprintln!("{}", note);
+    }
+
+    match out {
+        Some(path) => {
+            std::fs::write(path, report)?;
+            eprintln!("Report written to {}", path.display());
+        }
+        None => println!("{}", report),
+    }
+
+    eprintln!("{} finding(s)", findings.len());
+    Ok(options.should_fail(&findings))
+}
+
+async fn run_audit(
+    orchestrator: &Orchestrator,
+    data_dir: &Path,
+    root: &Path,
+    options: &audit::AuditOptions,
+    out: Option<&std::path::Path>,
+) -> Result<bool> {
+    use std::time::Instant;
+
+    let config = config::Config::load(data_dir, Some(root))?;
+    let index = storage::CodebaseIndex::with_tokenizer(data_dir, root, &config.rag.fts_tokenizer)?
+        .with_config(&config.index)?
+        .with_dry_run(orchestrator.dry_run());
+    let reindexed = index.index_directory(false)?;
+    eprintln!("Index updated ({} changed files)", reindexed);
+
+    // Local pattern rules are cheap, so they cover every file regardless of limits
+    let mut findings = Vec::new();
+    let mut targets = Vec::new();
+    for file in index.list_files(options.language.as_deref(), 10_000)? {
+        // The data directory may hold other projects' files too
+        if !std::path::Path::new(&file.path).starts_with(root) {
+            continue;
+        }
+        let Some(content) = index.get_file_content(&file.path)? else {
+            continue;
+        };
+        findings.extend(audit::local_findings(&file.relative_path, &content));
+        let checks = audit::checks_for(&content);
+        if !checks.is_empty() {
+            targets.push((file.relative_path, content, checks));
+        }
+    }
+
+    let mut notes = Vec::new();
+    let model_files = if options.local_only { 0 } else { targets.len().min(options.max_files) };
+    if !options.local_only && targets.len() > options.max_files {
+        notes.push(format!("Audited the first {} of {} files with the model (--max-files).", options.max_files, targets.len()));
+    }
+
+    let deadline = Instant::now() + options.timeout;
+    'files: for (i, (file, content, checks)) in targets.iter().take(model_files).enumerate() {
+        let names: Vec<&str> = checks.iter().map(|c| c.name).collect();
+        eprintln!("[{}/{}] {} ({})", i + 1, model_files, file, names.join(", "));
+
+        for (first, numbered) in audit::numbered_chunks(content) {
+            for check in checks {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    notes.push(format!("Time budget exhausted; {} file(s) were not audited by the model.", model_files - i));
+                    break 'files;
+                }
+
+                let audit = orchestrator.code_agent.audit_chunk(file, first, &numbered, check);
+                match tokio::time::timeout(remaining, audit).await {
+                    Ok(Ok(mut chunk_findings)) => findings.append(&mut chunk_findings),
+                    Ok(Err(e)) => notes.push(format!("{}:{} could not be audited for {}: {}", file, first, check.name, e)),
+                    Err(_) => notes.push(format!("{}:{} timed out", file, first)),
+                }
+            }
+        }
+    }
+
+    audit::rank(&mut findings);
+
+    let report = match options.format {
+        review::ReportFormat::Sarif => review::to_sarif(&findings, VERSION)?,
+        review::ReportFormat::Markdown => review::to_markdown("Sovereign Security Audit", &findings, &notes),
+    };
+
+    for note in &notes {
+        eprintln!("{}", note);
+    }
+
+    match out {
+        Some(path) => {
+            std::fs::write(path, report)?;
+            eprintln!("Report written to {}", path.display());
+        }
+        None => println!("{}", report),
+    }
+
+    eprintln!("{} finding(s)", findings.len());
+    Ok(options.should_fail(&findings))
+}
+
+/// Interactively label search results and grid search the RAG scoring parameters
+async fn run_rag_tune(data_dir: &Path, root: &Path, overrides: &config::Overrides) -> Result<()> {
+    use std::collections::HashSet;
+
+    let config = config::Config::load(data_dir, Some(root))?.with_overrides(overrides);
+    let index = storage::CodebaseIndex::with_tokenizer(data_dir, root, &config.rag.fts_tokenizer)?
+        .with_config(&config.index)?;
+    if index.get_stats()?.total_files == 0 {
+        println!("No codebase indexed. Run: sovereign index {}", root.display());
+        return Ok(());
+    }
+    let current = config.rag;
+
+    // Gather candidates permissively so every config in the grid can be scored
+    let retriever = rag::RagRetriever::new(rag::RagConfig {
+        min_similarity: 0.0,
+        ..current.clone()
+    })
+    .with_embedding_client(embeddings::EmbeddingClient::from_config(&config.embeddings));
+
+    println!("{}", "RAG scoring calibration".cyan());
+    println!("Enter a few queries you would really ask. For each, mark the relevant results.");
+    println!("{}", "Leave the query empty to finish.".bright_black());
+
+    let mut rl = DefaultEditor::new()?;
+    let mut samples: Vec<rag::LabeledQuery> = Vec::new();
+
+    loop {
+        let query = match rl.readline(&format!("\n{} ", "query>".bright_cyan())) {
+            Ok(line) => line.trim().to_string(),
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if query.is_empty() {
+            break;
+        }
+
+        let semantic = match retriever.semantic_search(&query, &index).await {
+            Ok(results) => results,
+            Err(e) => {
+                println!("{}", format!("Semantic search unavailable ({}), using keywords only.", e).yellow());
+                Vec::new()
+            }
+        };
+        let keyword = retriever.keyword_search(&query, &index)?;
+
+        // Show each candidate file once, in the order the current config ranks them
+        let mut shown: Vec<String> = Vec::new();
+        for result in retriever.rank(&query, semantic.clone(), keyword.clone()) {
+            if !shown.contains(&result.chunk.file_path) {
+                shown.push(result.chunk.file_path.clone());
+            }
+        }
+        for result in semantic.iter().chain(keyword.iter()) {
+            if shown.len() >= 10 {
+                break;
+            }
+            if !shown.contains(&result.chunk.file_path) {
+                shown.push(result.chunk.file_path.clone());
+            }
+        }
+
+        if shown.is_empty() {
+            println!("No results for this query.");
+            continue;
+        }
+
+        for (i, path) in shown.iter().enumerate() {
+            let display = std::path::Path::new(path)
+                .strip_prefix(root)
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|_| path.clone());
+            println!("  {:>2}. {}", i + 1, display);
+        }
+
+        let answer = match rl.readline("relevant (e.g. 1,3 or empty for none)> ") {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        let relevant: HashSet<String> = answer
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter_map(|n| n.trim().parse::<usize>().ok())
+            .filter_map(|n| shown.get(n.wrapping_sub(1)).cloned())
+            .collect();
+
+        samples.push(rag::LabeledQuery {
+            query,
+            semantic,
+            keyword,
+            relevant,
+        });
+    }
+
+    if samples.is_empty() {
+        println!("No labeled queries, nothing to tune.");
+        return Ok(());
+    }
+
+    let outcome = rag::tune_config(&current, &samples);
+
+    println!("\nEvaluated {} configurations on {} queries.", outcome.evaluated, samples.len());
+    println!("  Current F1: {:.3}", outcome.baseline_score);
+    println!("  Best F1:    {:.3}", outcome.score);
+    println!("  semantic_weight   = {:.2}", outcome.config.semantic_weight);
+    println!("  min_similarity    = {:.2}", outcome.config.min_similarity);
+    println!("  exact_match_boost = {:.2}", outcome.config.exact_match_boost);
+    println!("  definition_boost  = {:.2}", outcome.config.definition_boost);
 
-    let addr = format!("127.0.0.1:{}", port);
-    let listener = TcpListener::bind(&addr).await?;
+    if outcome.score <= outcome.baseline_score {
+        println!("{}", "Current settings are already the best for these labels.".green());
+        return Ok(());
+    }
+
+    let config_path = config::Config::project_path(root);
+    let confirm = rl
+        .readline(&format!("Write to {}? [y/N] ", config_path.display()))
+        .unwrap_or_default();
+
+    if confirm.trim().eq_ignore_ascii_case("y") {
+        config::Config::write_section(&config_path, "rag", &outcome.config)?;
+        println!("{}", format!("Saved tuned settings to {}", config_path.display()).green());
+    } else {
+        println!("Not saved.");
+    }
+
+    Ok(())
+}
+
+/// Serve static files from the web-ui directory, plus the local analytics API
+/// unless `analytics` is off
+///
+/// With a token, `/api/` requests must send it as `Authorization: Bearer <token>`
+/// or `?token=<token>`. Off loopback the static files need it too; a page opened
+/// with `?token=` gets it as a cookie, so the files it loads are let through.
+async fn serve_web_ui(
+    listener: tokio::net::TcpListener,
+    dir: &Path,
+    data_dir: &Path,
+    token: Option<String>,
+    files_need_token: bool,
+    analytics: bool,
+) -> Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+    // Requested files are checked against it, so none outside it are served
+    let dir = dir.canonicalize()?;
     loop {
         match listener.accept().await {
             Ok((mut stream, _)) => {
                 let dir = dir.clone();
+                let data_dir = data_dir.to_path_buf();
+                let token = token.clone();
                 tokio::spawn(async move {
                     let mut buffer = [0; 4096];
                     if let Ok(n) = stream.read(&mut buffer).await {
@@ -553,14 +2418,34 @@ async fn serve_web_ui(dir: &PathBuf, port: u16) -> Result<()> {
                             .and_then(|line| line.split_whitespace().nth(1))
                             .unwrap_or("/");
 
-                        // Serve the file
-                        let file_path = if path == "/" {
-                            dir.join("index.html")
-                        } else {
-                            dir.join(path.trim_start_matches('/'))
+                        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+                        let header = |name: &str| {
+                            request
+                                .lines()
+                                .take_while(|line| !line.is_empty())
+                                .filter_map(|line| line.split_once(':'))
+                                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                                .map(|(_, value)| value.trim())
                         };
 
-                        let (status, content_type, body) = if file_path.exists() && file_path.is_file() {
+                        let from_query = query.split('&').find_map(|pair| pair.strip_prefix("token="));
+                        let given = header("authorization")
+                            .and_then(|value| value.strip_prefix("Bearer "))
+                            .or(from_query)
+                            .or_else(|| {
+                                header("cookie")?
+                                    .split(';')
+                                    .find_map(|cookie| cookie.trim().strip_prefix(&format!("{}=", WEB_UI_TOKEN_COOKIE)))
+                            })
+                            .map(str::trim);
+                        let authorized = net::token_matches(token.as_deref(), given);
+
+                        let file_path = web_ui_file(&dir, path);
+                        let (status, content_type, body) = if !authorized && (files_need_token || path.starts_with("/api/")) {
+                            ("401 Unauthorized", "text/plain", b"Invalid or missing token".to_vec())
+                        } else if path == "/api/analytics" {
+                            analytics_response(&data_dir, query, analytics)
+                        } else if let Some(file_path) = file_path {
                             let content_type = match file_path.extension().and_then(|e| e.to_str()) {
                                 Some("html") => "text/html; charset=utf-8",
                                 Some("css") => "text/css; charset=utf-8",
@@ -581,11 +2466,20 @@ async fn serve_web_ui(dir: &PathBuf, port: u16) -> Result<()> {
                             ("404 Not Found", "text/plain", b"File not found".to_vec())
                         };
 
+                        let cookie = match (from_query, authorized && token.is_some()) {
+                            (Some(given), true) => format!(
+                                "Set-Cookie: {}={}; HttpOnly; SameSite=Strict; Path=/\r\n",
+                                WEB_UI_TOKEN_COOKIE,
+                                given.trim()
+                            ),
+                            _ => String::new(),
+                        };
                         let response = format!(
-                            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\n\r\n",
+                            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}\r\n",
                             status,
                             content_type,
-                            body.len()
+                            body.len(),
+                            cookie
                         );
 
                         let _ = stream.write_all(response.as_bytes()).await;
@@ -600,3 +2494,28 @@ async fn serve_web_ui(dir: &PathBuf, port: u16) -> Result<()> {
     }
 }
 
+/// The file under `dir`, itself canonical, that a request for `path` names; `None` when
+/// there is none or it lies outside `dir`, as `/../` or a symlink would take it
+fn web_ui_file(dir: &Path, path: &str) -> Option<PathBuf> {
+    let relative = if path == "/" { "index.html" } else { path.trim_start_matches('/') };
+    let file = dir.join(relative).canonicalize().ok()?;
+    (file.starts_with(dir) && file.is_file()).then_some(file)
+}
+
+/// `GET /api/analytics?days=N` for the web UI dashboard
+fn analytics_response(data_dir: &Path, query: &str, enabled: bool) -> (&'static str, &'static str, Vec<u8>) {
+    let days = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("days="))
+        .and_then(|d| d.parse().ok())
+        .unwrap_or(30);
+
+    let Some(store) = storage::AnalyticsStore::open_if_enabled(data_dir, enabled) else {
+        return ("404 Not Found", "text/plain", b"Analytics are disabled".to_vec());
+    };
+
+    match store.summary(days).and_then(|s| Ok(serde_json::to_vec(&s)?)) {
+        Ok(json) => ("200 OK", "application/json", json),
+        Err(_) => ("500 Internal Server Error", "text/plain", b"Error reading analytics".to_vec()),
+    }
+}
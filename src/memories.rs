@@ -0,0 +1,260 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+
+use crate::dry_run;
+use crate::storage::{CrdtMemory, CrdtMemoryStore, Memory, MemoryStore};
+
+/// Characters of an id shown in listings, enough to name a memory
+const SHORT_ID: usize = 8;
+
+/// Characters of a memory shown in listings
+const PREVIEW_CHARS: usize = 80;
+
+/// Memory types, as stored and as given to `--type`
+pub const MEMORY_TYPES: &[&str] = &["conversation", "code_pattern", "decision", "preference", "fact"];
+
+/// Which store a memory lives in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Store {
+    /// `memory.db`, written by the agents
+    Local,
+    /// `memories.automerge`, shared with other devices
+    Synced,
+}
+
+impl Store {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Store::Local => "local",
+            Store::Synced => "synced",
+        }
+    }
+}
+
+/// A memory from either store
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub id: String,
+    pub content: String,
+    pub memory_type: String,
+    pub project: Option<String>,
+    pub tags: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub importance: f32,
+    pub store: Store,
+}
+
+impl Entry {
+    fn short_id(&self) -> &str {
+        &self.id[..self.id.len().min(SHORT_ID)]
+    }
+
+    fn preview(&self) -> String {
+        self.content.lines().next().unwrap_or("").chars().take(PREVIEW_CHARS).collect()
+    }
+
+    /// `3f2a9c1e  [preference] Prefer early returns`
+    pub fn line(&self) -> String {
+        let synced = if self.store == Store::Synced { " (synced)" } else { "" };
+        format!("  {}  [{}] {}{}", self.short_id(), self.memory_type, self.preview(), synced)
+    }
+
+    /// Every field, then the full text
+    pub fn details(&self) -> String {
+        format!(
+            "id:         {}\ntype:       {}\nstore:      {}\nproject:    {}\ncreated:    {}\nimportance: {:.2}\ntags:       {}\n\n{}",
+            self.id,
+            self.memory_type,
+            self.store.as_str(),
+            self.project.as_deref().unwrap_or("-"),
+            self.created_at.format("%Y-%m-%d %H:%M"),
+            self.importance,
+            self.tags.join(", "),
+            self.content
+        )
+    }
+}
+
+impl From<Memory> for Entry {
+    fn from(m: Memory) -> Self {
+        Self {
+            memory_type: m.memory_type.as_str().to_string(),
+            id: m.id,
+            content: m.content,
+            project: m.project,
+            tags: m.tags,
+            created_at: m.created_at,
+            importance: m.importance,
+            store: Store::Local,
+        }
+    }
+}
+
+impl From<CrdtMemory> for Entry {
+    fn from(m: CrdtMemory) -> Self {
+        Self {
+            id: m.id,
+            content: m.content,
+            memory_type: m.memory_type.as_str().to_string(),
+            project: m.project,
+            tags: m.tags,
+            created_at: m.timestamp,
+            importance: m.importance,
+            store: Store::Synced,
+        }
+    }
+}
+
+/// Listing and correcting memories in both stores, by id or its first characters
+///
+/// Used by `sovereign memory` and the `/memory-*` chat commands, so a wrong
+/// preference the detector stored can be fixed wherever it was seen.
+pub struct Memories<'a> {
+    local: &'a MemoryStore,
+    synced: &'a mut CrdtMemoryStore,
+}
+
+impl<'a> Memories<'a> {
+    pub fn new(local: &'a MemoryStore, synced: &'a mut CrdtMemoryStore) -> Self {
+        Self { local, synced }
+    }
+
+    /// Newest first, optionally of one type
+    pub fn list(&self, memory_type: Option<&str>, limit: usize) -> Result<Vec<Entry>> {
+        let mut entries: Vec<Entry> = self.local.all()?.into_iter().map(Entry::from).collect();
+        entries.extend(self.synced.get_all()?.into_iter().map(Entry::from));
+        entries.retain(|e| memory_type.is_none_or(|t| e.memory_type == t));
+        entries.sort_by_key(|e| std::cmp::Reverse(e.created_at));
+        entries.truncate(limit);
+        Ok(entries)
+    }
+
+    /// The memory `id` names in each store holding it; an error if it names
+    /// none, or more than one
+    fn resolve(&self, id: &str) -> Result<Vec<Entry>> {
+        let id = id.trim();
+        if id.is_empty() {
+            bail!("Give the id of a memory (from `sovereign memory list`).");
+        }
+        let mut entries: Vec<Entry> = self.local.find(id)?.into_iter().map(Entry::from).collect();
+        entries.extend(
+            self.synced.get_all()?.into_iter().filter(|m| m.id.starts_with(id)).map(Entry::from),
+        );
+
+        let mut ids: Vec<&str> = entries.iter().map(|e| e.id.as_str()).collect();
+        ids.sort();
+        ids.dedup();
+        match ids.len() {
+            0 => bail!("No memory with id '{}'.", id),
+            1 => Ok(entries),
+            n => bail!("'{}' matches {} memories; give more of the id.", id, n),
+        }
+    }
+
+    pub fn show(&self, id: &str) -> Result<String> {
+        let entries = self.resolve(id)?;
+        Ok(entries.iter().map(Entry::details).collect::<Vec<_>>().join("\n\n---\n\n"))
+    }
+
+    /// The full text of the memory `id` names
+    pub fn content(&self, id: &str) -> Result<String> {
+        Ok(self.resolve(id)?.remove(0).content)
+    }
+
+    pub fn delete(&mut self, id: &str) -> Result<String> {
+        let entries = self.resolve(id)?;
+        let entry = &entries[0];
+        if self.local.dry_run() {
+            return Ok(dry_run::would(format!("delete {}", entry.line().trim())));
+        }
+
+        for entry in &entries {
+            match entry.store {
+                Store::Local => {
+                    self.local.remove(&[entry.id.as_str()], false)?;
+                }
+                Store::Synced => self.synced.delete(&entry.id)?,
+            }
+        }
+        Ok(format!("Deleted {}", entry.line().trim()))
+    }
+
+    pub fn edit(&mut self, id: &str, content: &str) -> Result<String> {
+        let content = content.trim();
+        if content.is_empty() {
+            bail!("A memory cannot be empty; delete it instead.");
+        }
+        let entries = self.resolve(id)?;
+        if self.local.dry_run() {
+            return Ok(dry_run::would(format!("replace the text of {}", entries[0].short_id())));
+        }
+
+        for entry in &entries {
+            match entry.store {
+                Store::Local => {
+                    self.local.update_content(&entry.id, content)?;
+                }
+                Store::Synced => self.synced.update_content(&entry.id, content)?,
+            }
+        }
+        Ok(format!("Updated {}.", entries[0].short_id()))
+    }
+
+    /// Importance from 0 to 1; the retention policy prunes the least important first
+    pub fn set_importance(&mut self, id: &str, importance: f32) -> Result<String> {
+        if !(0.0..=1.0).contains(&importance) {
+            bail!("Importance goes from 0 to 1, not {}.", importance);
+        }
+        let entries = self.resolve(id)?;
+        let short_id = entries[0].short_id();
+        if self.local.dry_run() {
+            return Ok(dry_run::would(format!(
+                "set the importance of {} from {:.2} to {:.2}",
+                short_id, entries[0].importance, importance
+            )));
+        }
+
+        for entry in &entries {
+            match entry.store {
+                Store::Local => {
+                    self.local.set_importance(&entry.id, importance)?;
+                }
+                Store::Synced => self.synced.update_importance(&entry.id, importance)?,
+            }
+        }
+        Ok(format!("Importance of {} set to {:.2}.", short_id, importance))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{CrdtMemoryType, MemoryType};
+
+    #[test]
+    fn test_memories_are_found_and_changed_in_both_stores() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_path_buf();
+        let local = MemoryStore::new(&data_dir).unwrap();
+        let mut synced = CrdtMemoryStore::new(&data_dir).unwrap();
+
+        let wrong = local.remember("Prefer tabs", MemoryType::Preference, None, vec![], 0.6).unwrap();
+        let shared = synced.add("Sync uses automerge", CrdtMemoryType::Fact).unwrap();
+        let mut memories = Memories::new(&local, &mut synced);
+
+        assert_eq!(memories.list(None, 10).unwrap().len(), 2);
+        assert_eq!(memories.list(Some("preference"), 10).unwrap()[0].id, wrong.id);
+        assert!(memories.show("").is_err());
+        assert!(memories.show("no-such-id").is_err());
+
+        memories.edit(&wrong.id[..8], "Prefer spaces").unwrap();
+        memories.set_importance(&shared[..8], 0.9).unwrap();
+        assert!(memories.set_importance(&shared, 2.0).is_err());
+        assert!(memories.show(&wrong.id).unwrap().ends_with("Prefer spaces"));
+        assert!(memories.show(&shared).unwrap().contains("importance: 0.90"));
+
+        memories.delete(&wrong.id).unwrap();
+        memories.delete(&shared).unwrap();
+        assert!(memories.list(None, 10).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,183 @@
+//! Counters for the daemon's Prometheus endpoint: requests and their latency by command,
+//! LLM tokens, watcher events and sync operations. They are kept for the whole process,
+//! as the model clients, the watcher and sync record into them from deep inside.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::agents::COMMANDS;
+use crate::storage::IndexSize;
+
+/// Upper bounds of the request latency buckets, in seconds
+const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+static METRICS: OnceLock<Mutex<Metrics>> = OnceLock::new();
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations at or under each of [`LATENCY_BUCKETS`]
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+#[derive(Default)]
+struct Metrics {
+    /// By command and outcome (`ok` or `error`)
+    requests: BTreeMap<(String, &'static str), u64>,
+    latency: BTreeMap<String, Histogram>,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    watcher_events: u64,
+    /// By operation and direction (`outgoing` when this side started it)
+    sync_operations: BTreeMap<(&'static str, &'static str), u64>,
+}
+
+impl Metrics {
+    fn request(&mut self, command: &str, elapsed: Duration, ok: bool) {
+        let command = command_label(command);
+        *self.requests.entry((command.clone(), if ok { "ok" } else { "error" })).or_default() += 1;
+
+        let seconds = elapsed.as_secs_f64();
+        let histogram = self.latency.entry(command).or_default();
+        for (bucket, &bound) in histogram.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+
+    fn render(&self, index: Option<&IndexSize>) -> String {
+        let mut out = String::new();
+        let _ = self.write(&mut out, index);
+        out
+    }
+
+    fn write(&self, out: &mut String, index: Option<&IndexSize>) -> std::fmt::Result {
+        writeln!(out, "# HELP sovereign_requests_total Requests answered by the orchestrators, by command and outcome")?;
+        writeln!(out, "# TYPE sovereign_requests_total counter")?;
+        for ((command, outcome), count) in &self.requests {
+            writeln!(out, "sovereign_requests_total{{command=\"{}\",outcome=\"{}\"}} {}", command, outcome, count)?;
+        }
+
+        writeln!(out, "# HELP sovereign_request_duration_seconds Time the orchestrators took to answer, by command")?;
+        writeln!(out, "# TYPE sovereign_request_duration_seconds histogram")?;
+        for (command, histogram) in &self.latency {
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                writeln!(out, "sovereign_request_duration_seconds_bucket{{command=\"{}\",le=\"{}\"}} {}", command, bound, count)?;
+            }
+            writeln!(out, "sovereign_request_duration_seconds_bucket{{command=\"{}\",le=\"+Inf\"}} {}", command, histogram.count)?;
+            writeln!(out, "sovereign_request_duration_seconds_sum{{command=\"{}\"}} {}", command, histogram.sum)?;
+            writeln!(out, "sovereign_request_duration_seconds_count{{command=\"{}\"}} {}", command, histogram.count)?;
+        }
+
+        writeln!(out, "# HELP sovereign_llm_tokens_total Tokens the model read and generated, as it reported them")?;
+        writeln!(out, "# TYPE sovereign_llm_tokens_total counter")?;
+        writeln!(out, "sovereign_llm_tokens_total{{kind=\"prompt\"}} {}", self.prompt_tokens)?;
+        writeln!(out, "sovereign_llm_tokens_total{{kind=\"completion\"}} {}", self.completion_tokens)?;
+
+        writeln!(out, "# HELP sovereign_watcher_events_total File changes the watcher was told of")?;
+        writeln!(out, "# TYPE sovereign_watcher_events_total counter")?;
+        writeln!(out, "sovereign_watcher_events_total {}", self.watcher_events)?;
+
+        writeln!(out, "# HELP sovereign_sync_operations_total Memory syncs with peers, by operation and direction")?;
+        writeln!(out, "# TYPE sovereign_sync_operations_total counter")?;
+        for ((operation, direction), count) in &self.sync_operations {
+            writeln!(out, "sovereign_sync_operations_total{{operation=\"{}\",direction=\"{}\"}} {}", operation, direction, count)?;
+        }
+
+        if let Some(index) = index {
+            let gauges = [
+                ("sovereign_index_files", "Files in the index", index.files),
+                ("sovereign_index_bytes", "Bytes of the indexed files", index.bytes),
+                ("sovereign_index_chunks", "Embedded chunks in the index", index.chunks),
+                ("sovereign_index_pending_embeddings", "Changed files waiting to be re-embedded", index.pending_embeddings),
+            ];
+            for (name, help, value) in gauges {
+                writeln!(out, "# HELP {} {}", name, help)?;
+                writeln!(out, "# TYPE {} gauge", name)?;
+                writeln!(out, "{} {}", name, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The command of `input` as a label: the chat command's name, aliases resolved, `chat`
+/// for a message and `unknown` for anything else, so clients cannot make up labels
+fn command_label(input: &str) -> String {
+    let word = input.split_whitespace().next().unwrap_or_default();
+    if !word.starts_with('/') {
+        return "chat".to_string();
+    }
+    COMMANDS.iter().find(|c| c.is_named(word)).map_or("unknown", |c| c.name).to_string()
+}
+
+fn with<T>(f: impl FnOnce(&mut Metrics) -> T) -> T {
+    let mut metrics = METRICS.get_or_init(Mutex::default).lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut metrics)
+}
+
+/// Count a request an orchestrator answered, given as it was sent
+pub fn record_request(input: &str, elapsed: Duration, ok: bool) {
+    with(|m| m.request(input, elapsed, ok));
+}
+
+pub fn record_llm_tokens(prompt: u64, completion: u64) {
+    with(|m| {
+        m.prompt_tokens += prompt;
+        m.completion_tokens += completion;
+    });
+}
+
+pub fn record_watcher_event() {
+    with(|m| m.watcher_events += 1);
+}
+
+/// Count a sync with a peer; `outgoing` when this side started it
+pub fn record_sync(operation: &'static str, outgoing: bool) {
+    let direction = if outgoing { "outgoing" } else { "incoming" };
+    with(|m| *m.sync_operations.entry((operation, direction)).or_default() += 1);
+}
+
+/// Everything counted so far in the Prometheus text format, with the index size if known
+pub fn render(index: Option<&IndexSize>) -> String {
+    with(|m| m.render(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_render_in_the_prometheus_format() {
+        let mut metrics = Metrics::default();
+        metrics.request("/search config loading", Duration::from_millis(80), true);
+        metrics.request("/s cache", Duration::from_secs(3), true);
+        metrics.request("how does indexing work?", Duration::from_secs(12), false);
+        metrics.request("/made-up", Duration::from_millis(1), false);
+        metrics.prompt_tokens = 120;
+        metrics.watcher_events = 2;
+        metrics.sync_operations.insert(("push", "outgoing"), 1);
+        let index = IndexSize { files: 42, bytes: 4096, chunks: 90, pending_embeddings: 3 };
+        let text = metrics.render(Some(&index));
+
+        assert!(text.contains("sovereign_requests_total{command=\"/search\",outcome=\"ok\"} 2\n"), "{}", text);
+        assert!(text.contains("sovereign_requests_total{command=\"chat\",outcome=\"error\"} 1\n"));
+        assert!(text.contains("sovereign_requests_total{command=\"unknown\",outcome=\"error\"} 1\n"));
+        assert!(text.contains("sovereign_request_duration_seconds_bucket{command=\"/search\",le=\"0.1\"} 1\n"));
+        assert!(text.contains("sovereign_request_duration_seconds_bucket{command=\"/search\",le=\"5\"} 2\n"));
+        assert!(text.contains("sovereign_request_duration_seconds_bucket{command=\"chat\",le=\"10\"} 0\n"));
+        assert!(text.contains("sovereign_request_duration_seconds_bucket{command=\"chat\",le=\"+Inf\"} 1\n"));
+        assert!(text.contains("sovereign_request_duration_seconds_count{command=\"/search\"} 2\n"));
+        assert!(text.contains("sovereign_llm_tokens_total{kind=\"prompt\"} 120\n"));
+        assert!(text.contains("sovereign_watcher_events_total 2\n"));
+        assert!(text.contains("sovereign_sync_operations_total{operation=\"push\",direction=\"outgoing\"} 1\n"));
+        assert!(text.contains("# TYPE sovereign_index_files gauge\nsovereign_index_files 42\n"));
+        assert!(!metrics.render(None).contains("sovereign_index_files"));
+    }
+}
@@ -0,0 +1,221 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Context window, in tokens, from which a model counts as long-context
+const LONG_CONTEXT_TOKENS: u32 = 16_384;
+
+/// Something a command may rely on the model being able to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Vision,
+    FillInMiddle,
+    JsonMode,
+    ToolUse,
+    LongContext,
+}
+
+impl Capability {
+    pub fn describe(&self) -> String {
+        match self {
+            Capability::Vision => "image input".to_string(),
+            Capability::FillInMiddle => "fill-in-the-middle completion".to_string(),
+            Capability::JsonMode => "JSON mode".to_string(),
+            Capability::ToolUse => "tool calling".to_string(),
+            Capability::LongContext => format!("a context window of {}k tokens", LONG_CONTEXT_TOKENS / 1024),
+        }
+    }
+}
+
+/// What a model can do, so agents pick a prompting strategy that works with it
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelCapabilities {
+    /// Accepts images
+    pub vision: bool,
+    /// Completes code between a prefix and a suffix
+    pub fim: bool,
+    /// Can be constrained to valid JSON (Ollama's `format: "json"`)
+    pub json_mode: bool,
+    /// Calls tools described in the request
+    pub tools: bool,
+    /// Context window in tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_size: Option<u32>,
+}
+
+impl ModelCapabilities {
+    pub fn has(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Vision => self.vision,
+            Capability::FillInMiddle => self.fim,
+            Capability::JsonMode => self.json_mode,
+            Capability::ToolUse => self.tools,
+            Capability::LongContext => self.context_size.is_some_and(|size| size >= LONG_CONTEXT_TOKENS),
+        }
+    }
+
+    /// What the model can do, e.g. "JSON mode, tool calling, a context window of 32k tokens"
+    pub fn describe(&self) -> String {
+        let mut parts: Vec<String> = [Capability::Vision, Capability::FillInMiddle, Capability::JsonMode, Capability::ToolUse]
+            .into_iter()
+            .filter(|capability| self.has(*capability))
+            .map(|capability| capability.describe())
+            .collect();
+        if let Some(size) = self.context_size {
+            parts.push(format!("a context window of {}k tokens", size / 1024));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Capabilities of known models, keyed by model name prefix
+///
+/// Built-in entries cover common Ollama and DeepSeek models. A `[models.<prefix>]`
+/// table in `sovereign.toml` describes another model, or replaces the built-in
+/// description of one; the longest matching prefix wins:
+///
+/// ```toml
+/// [models."my-finetune"]
+/// fim = true
+/// json_mode = true
+/// context_size = 32768
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ModelRegistry {
+    overrides: HashMap<String, ModelCapabilities>,
+}
+
+impl ModelRegistry {
+    /// Capabilities of `model` (`qwen2.5-coder:14b`), or None for a model the registry doesn't know
+    pub fn capabilities(&self, model: &str) -> Option<ModelCapabilities> {
+        let model = model.to_lowercase();
+        let longest = |entries: Vec<(&str, ModelCapabilities)>| {
+            entries
+                .into_iter()
+                .filter(|(prefix, _)| model.starts_with(&prefix.to_lowercase()))
+                .max_by_key(|(prefix, _)| prefix.len())
+                .map(|(_, capabilities)| capabilities)
+        };
+
+        longest(self.overrides.iter().map(|(prefix, c)| (prefix.as_str(), c.clone())).collect())
+            .or_else(|| longest(builtin_models()))
+    }
+
+    /// Capabilities a command relies on that `model` is known to lack
+    ///
+    /// Unknown models are given the benefit of the doubt.
+    pub fn missing(&self, model: &str, command: &str) -> Vec<Capability> {
+        let Some(capabilities) = self.capabilities(model) else {
+            return Vec::new();
+        };
+        command_needs(command)
+            .iter()
+            .copied()
+            .filter(|need| !capabilities.has(*need))
+            .collect()
+    }
+
+    /// A warning for running `command` with `model`, if it lacks something the command needs
+    pub fn warning(&self, model: &str, command: &str) -> Option<String> {
+        let missing = self.missing(model, command);
+        if missing.is_empty() {
+            return None;
+        }
+        let needs: Vec<String> = missing.iter().map(|c| c.describe()).collect();
+        Some(format!(
+            "Note: {} lacks {}, which {} relies on; results may be poor. \
+             Describe the model under [models] in sovereign.toml if this is wrong.",
+            model,
+            needs.join(" and "),
+            command.replace('_', " ")
+        ))
+    }
+}
+
+/// Capabilities each command relies on, keyed like `[prompts.<command>]`
+fn command_needs(command: &str) -> &'static [Capability] {
+    match command {
+        "analyze_diff" => &[Capability::JsonMode],
+        // Branches and files fill each prompt up to the summarizer's part size
+        "pr_summary" | "summarize" => &[Capability::LongContext],
+        _ => &[],
+    }
+}
+
+fn builtin_models() -> Vec<(&'static str, ModelCapabilities)> {
+    let model = |fim: bool, json_mode: bool, tools: bool, context_size: u32| ModelCapabilities {
+        vision: false,
+        fim,
+        json_mode,
+        tools,
+        context_size: Some(context_size),
+    };
+    let vision = |context_size: u32| ModelCapabilities {
+        vision: true,
+        json_mode: true,
+        context_size: Some(context_size),
+        ..Default::default()
+    };
+
+    vec![
+        ("qwen2.5-coder", model(true, true, true, 32_768)),
+        ("qwen2.5", model(false, true, true, 32_768)),
+        ("deepseek-coder-v2", model(true, true, false, 131_072)),
+        ("deepseek-coder", model(true, true, false, 16_384)),
+        ("codellama", model(true, true, false, 16_384)),
+        ("starcoder2", model(true, false, false, 16_384)),
+        ("codegemma", model(true, true, false, 8_192)),
+        ("llama3.1", model(false, true, true, 131_072)),
+        ("llama3.2", model(false, true, true, 131_072)),
+        ("llama3", model(false, true, false, 8_192)),
+        ("mistral", model(false, true, true, 32_768)),
+        ("deepseek-chat", model(false, true, true, 65_536)),
+        ("deepseek-reasoner", model(false, false, false, 65_536)),
+        ("llama3.2-vision", vision(131_072)),
+        ("llava", vision(4_096)),
+        ("bakllava", vision(4_096)),
+        ("moondream", vision(2_048)),
+        ("minicpm-v", vision(32_768)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_longest_prefix_and_overrides() {
+        let registry = ModelRegistry::default();
+        assert_eq!(registry.capabilities("deepseek-coder-v2:16b").unwrap().context_size, Some(131_072));
+        assert_eq!(registry.capabilities("deepseek-coder:6.7b").unwrap().context_size, Some(16_384));
+        assert!(registry.capabilities("llava-phi3:latest").unwrap().vision);
+        assert!(registry.capabilities("my-finetune").is_none());
+
+        let registry: ModelRegistry = toml::from_str("[qwen2]\njson_mode = true\ncontext_size = 4096").unwrap();
+        let qwen = registry.capabilities("Qwen2.5-coder:14b").unwrap();
+        assert!(qwen.json_mode && !qwen.fim);
+        assert_eq!(registry.missing("qwen2.5-coder:14b", "pr_summary"), vec![Capability::LongContext]);
+    }
+
+    #[test]
+    fn test_warnings_only_for_known_gaps() {
+        let registry = ModelRegistry::default();
+        let warning = registry.warning("starcoder2:7b", "analyze_diff").unwrap();
+        assert!(warning.contains("lacks JSON mode, which analyze diff relies on"));
+        assert!(registry.warning("qwen2.5-coder:14b", "analyze_diff").is_none());
+        assert!(registry.warning("unknown-model", "analyze_diff").is_none());
+        assert!(registry.warning("starcoder2:7b", "explain").is_none());
+    }
+
+    #[test]
+    fn test_describe_lists_what_the_model_can_do() {
+        let registry = ModelRegistry::default();
+        assert_eq!(
+            registry.capabilities("qwen2.5-coder:14b").unwrap().describe(),
+            "fill-in-the-middle completion, JSON mode, tool calling, a context window of 32k tokens"
+        );
+        assert_eq!(registry.capabilities("llava:7b").unwrap().describe(), "image input, JSON mode, a context window of 4k tokens");
+        assert_eq!(ModelCapabilities::default().describe(), "");
+    }
+}
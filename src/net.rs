@@ -0,0 +1,181 @@
+use anyhow::{bail, Result};
+use std::io::{ErrorKind, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use tokio::net::TcpListener;
+
+/// Address listeners bind to unless `--bind` says otherwise
+pub const DEFAULT_BIND: &str = "127.0.0.1";
+/// Environment variable holding the token clients must send to a listener
+/// bound to a non-loopback address
+pub const AUTH_TOKEN_ENV: &str = "SOVEREIGN_TOKEN";
+/// File in the data directory holding the token the HTTP API requires when none is given
+pub const LOCAL_TOKEN_FILE: &str = "api_token";
+/// Ports tried after the requested one is taken
+const PORT_FALLBACK_ATTEMPTS: u16 = 10;
+
+/// Bind `host:port`, moving on to the next ports when it is already in use
+///
+/// A fallback is printed with `label`, so users know where to point clients.
+pub async fn bind_tcp(host: &str, port: u16, label: &str) -> Result<TcpListener> {
+    for offset in 0..=PORT_FALLBACK_ATTEMPTS {
+        let Some(candidate) = port.checked_add(offset) else { break };
+        match TcpListener::bind((host, candidate)).await {
+            Ok(listener) => {
+                if offset > 0 {
+                    println!("  {}: port {} is in use, using {} instead", label, port, candidate);
+                }
+                return Ok(listener);
+            }
+            Err(e) if e.kind() == ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(anyhow::Error::new(e).context(format!("Failed to bind {}:{}", host, candidate))),
+        }
+    }
+
+    bail!(
+        "{}: ports {}-{} on {} are all in use",
+        label,
+        port,
+        port.saturating_add(PORT_FALLBACK_ATTEMPTS),
+        host
+    )
+}
+
+/// Whether `host` only accepts connections from this machine
+pub fn is_loopback(host: &str) -> bool {
+    host.eq_ignore_ascii_case("localhost")
+        || host.parse::<IpAddr>().map(|ip| ip.is_loopback()).unwrap_or(false)
+}
+
+/// `--token`, or `SOVEREIGN_TOKEN` when it is not given
+pub fn resolve_token(token: Option<String>) -> Option<String> {
+    token
+        .or_else(|| std::env::var(AUTH_TOKEN_ENV).ok())
+        .filter(|t| !t.is_empty())
+}
+
+/// Refuse to expose a listener to the network without a token
+pub fn check_bind(host: &str, token: Option<&str>) -> Result<()> {
+    if !is_loopback(host) && token.is_none() {
+        bail!(
+            "Binding to {} exposes Sovereign to the network; set a token with --token or {}",
+            host,
+            AUTH_TOKEN_ENV
+        );
+    }
+    Ok(())
+}
+
+/// The token kept in `data_dir`, made on first use and readable only by the user
+pub fn local_token(data_dir: &Path) -> Result<String> {
+    let path = data_dir.join(LOCAL_TOKEN_FILE);
+    if let Ok(token) = std::fs::read_to_string(&path) {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
+
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    std::fs::create_dir_all(data_dir)?;
+    write_private(&path, token.as_bytes())?;
+    Ok(token)
+}
+
+/// Write `content` to `path`, readable only by the user from the moment the file exists
+pub fn write_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    // A file that was already there keeps its mode when opened; narrow it before writing
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(content)
+}
+
+/// Whether a request's `Origin` header names a page allowed to call the daemon;
+/// requests without one come from outside a browser and are let through
+pub fn origin_allowed(origin: Option<&str>, allowed: &[String]) -> bool {
+    let Some(origin) = origin else { return true };
+    let origin = origin.trim().trim_end_matches('/');
+    allowed.iter().any(|a| a.trim_end_matches('/').eq_ignore_ascii_case(origin))
+}
+
+/// Whether a client's token satisfies the listener's; always true without one
+pub fn token_matches(expected: Option<&str>, given: Option<&str>) -> bool {
+    let Some(expected) = expected else { return true };
+    let Some(given) = given else { return false };
+
+    // Compare every byte, so the time taken does not reveal the matching prefix
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bind_falls_back_to_next_free_port() {
+        let taken = bind_tcp(DEFAULT_BIND, 0, "test").await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        let listener = bind_tcp(DEFAULT_BIND, port, "test").await.unwrap();
+        let bound = listener.local_addr().unwrap().port();
+        assert!(bound > port && bound <= port + PORT_FALLBACK_ATTEMPTS);
+    }
+
+    #[test]
+    fn test_non_loopback_bind_requires_token() {
+        assert!(is_loopback("127.0.0.1") && is_loopback("::1") && is_loopback("localhost"));
+        assert!(check_bind("127.0.0.1", None).is_ok());
+        assert!(check_bind("0.0.0.0", None).is_err());
+        assert!(check_bind("0.0.0.0", Some("s3cret")).is_ok());
+
+        assert!(token_matches(None, None));
+        assert!(token_matches(Some("s3cret"), Some("s3cret")));
+        assert!(!token_matches(Some("s3cret"), Some("s3cre")));
+        assert!(!token_matches(Some("s3cret"), None));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_private_files_are_never_readable_by_others() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let (new, existing) = (dir.path().join("new"), dir.path().join("existing"));
+        std::fs::write(&existing, "old").unwrap();
+        std::fs::set_permissions(&existing, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        for path in [&new, &existing] {
+            write_private(path, b"s3cret").unwrap();
+            assert_eq!(std::fs::read_to_string(path).unwrap(), "s3cret");
+            assert_eq!(std::fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_only_listed_origins_are_allowed() {
+        let allowed = vec!["http://localhost:7657".to_string()];
+        assert!(origin_allowed(None, &allowed));
+        assert!(origin_allowed(Some("http://LOCALHOST:7657/"), &allowed));
+        assert!(!origin_allowed(Some("https://example.com"), &allowed));
+        assert!(!origin_allowed(Some("null"), &allowed));
+
+        let dir = tempfile::tempdir().unwrap();
+        let token = local_token(dir.path()).unwrap();
+        assert_eq!(token.len(), 32);
+        assert_eq!(local_token(dir.path()).unwrap(), token);
+    }
+}
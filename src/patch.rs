@@ -0,0 +1,630 @@
+//! Unified diffs: reading the ones a model writes, applying them with a backup
+//! to undo from, and writing them for previews
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Unchanged lines shown around each change in a diff
+const CONTEXT_LINES: usize = 3;
+
+/// Largest comparison (old lines times new lines) `diff` makes; beyond it the
+/// changed middle of a file is shown as replaced whole
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Applied patches kept for `/undo`
+const MAX_BACKUPS: usize = 20;
+
+/// Lists the files of a backup
+const MANIFEST: &str = "manifest.json";
+
+/// The changes a diff makes to one file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilePatch {
+    /// Path after the change, without the `b/` prefix; empty when the diff named no file
+    pub path: String,
+    /// The diff creates the file (`--- /dev/null`)
+    pub creates: bool,
+    hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Hunk {
+    /// Line the hunk says it starts at; only a hint, as models miscount
+    old_start: usize,
+    lines: Vec<HunkLine>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum HunkLine {
+    Context(String),
+    Removed(String),
+    Added(String),
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Removed(text) => Some(text.as_str()),
+                HunkLine::Added(_) => None,
+            })
+            .collect()
+    }
+
+    fn new_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Added(text) => Some(text.as_str()),
+                HunkLine::Removed(_) => None,
+            })
+            .collect()
+    }
+}
+
+/// The file patches in `text`, which may be wrapped in prose and ``` fences
+///
+/// Hunks without a `---`/`+++` header before them are kept under an empty path,
+/// for the caller to give the file it asked about.
+pub fn parse(text: &str) -> Vec<FilePatch> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut patches: Vec<FilePatch> = Vec::new();
+    let mut in_hunk = false;
+
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+
+        // A removed line can start with `--- ` too; a header is followed by `+++ `
+        let new_header = lines.get(i).and_then(|next| next.strip_prefix("+++ "));
+        if let (Some(old), Some(new)) = (line.strip_prefix("--- "), new_header) {
+            let creates = header_path(old).is_none();
+            let path = header_path(new).or_else(|| header_path(old)).unwrap_or_default();
+            patches.push(FilePatch { path, creates, hunks: Vec::new() });
+            in_hunk = false;
+            i += 1;
+            continue;
+        }
+
+        if line.starts_with("@@") {
+            if patches.is_empty() {
+                patches.push(FilePatch { path: String::new(), creates: false, hunks: Vec::new() });
+            }
+            if let Some(patch) = patches.last_mut() {
+                patch.hunks.push(Hunk { old_start: hunk_start(line), lines: Vec::new() });
+            }
+            in_hunk = true;
+            continue;
+        }
+
+        if !in_hunk {
+            continue;
+        }
+        let Some(hunk) = patches.last_mut().and_then(|p| p.hunks.last_mut()) else {
+            continue;
+        };
+        match line.chars().next() {
+            Some(' ') => hunk.lines.push(HunkLine::Context(line[1..].to_string())),
+            Some('-') => hunk.lines.push(HunkLine::Removed(line[1..].to_string())),
+            Some('+') => hunk.lines.push(HunkLine::Added(line[1..].to_string())),
+            // Blank context lines often lose their leading space
+            None => hunk.lines.push(HunkLine::Context(String::new())),
+            // `\ No newline at end of file`
+            Some('\\') => {}
+            // Prose or a closing fence
+            Some(_) => in_hunk = false,
+        }
+    }
+
+    for patch in &mut patches {
+        for hunk in &mut patch.hunks {
+            // Blank lines after the diff are not part of it
+            while hunk.lines.last() == Some(&HunkLine::Context(String::new())) {
+                hunk.lines.pop();
+            }
+        }
+        patch.hunks.retain(|h| !h.lines.is_empty());
+    }
+    patches.retain(|p| !p.hunks.is_empty());
+    patches
+}
+
+/// `src/lib.rs` from `b/src/lib.rs\t2024-01-01`; `None` for `/dev/null`
+fn header_path(header: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or_default().trim();
+    if path.is_empty() || path == "/dev/null" {
+        return None;
+    }
+    let path = path.strip_prefix("a/").or_else(|| path.strip_prefix("b/")).unwrap_or(path);
+    Some(path.to_string())
+}
+
+/// 12 from `@@ -12,5 +12,6 @@`
+fn hunk_start(header: &str) -> usize {
+    header
+        .trim_start_matches('@')
+        .trim()
+        .strip_prefix('-')
+        .and_then(|range| range.split([',', ' ']).next())
+        .and_then(|start| start.parse().ok())
+        .unwrap_or(1)
+}
+
+impl FilePatch {
+    /// `original` with the hunks applied
+    ///
+    /// Each hunk goes where its old lines match, nearest the line it names, so a
+    /// diff with slightly wrong line numbers still applies. Lines are compared
+    /// ignoring trailing whitespace, and the file keeps its line endings.
+    pub fn apply(&self, original: &str) -> Result<String> {
+        let lines: Vec<&str> = original.lines().collect();
+        let mut output: Vec<&str> = Vec::new();
+        let mut cursor = 0;
+
+        for (n, hunk) in self.hunks.iter().enumerate() {
+            let old = hunk.old_lines();
+            let Some(at) = find(&lines, &old, cursor, hunk.old_start.saturating_sub(1)) else {
+                bail!(
+                    "Change {} of {} does not match the file (it was meant for line {})",
+                    n + 1,
+                    if self.path.is_empty() { "the patch" } else { &self.path },
+                    hunk.old_start
+                );
+            };
+            output.extend(&lines[cursor..at]);
+            output.extend(hunk.new_lines());
+            cursor = at + old.len();
+        }
+        output.extend(&lines[cursor..]);
+
+        let newline = line_ending(original);
+        let mut updated = output.join(newline);
+        if !updated.is_empty() && (original.ends_with('\n') || original.is_empty()) {
+            updated.push_str(newline);
+        }
+        Ok(updated)
+    }
+}
+
+/// The line ending of `text`, taken from its first line; `\n` for a single line
+fn line_ending(text: &str) -> &'static str {
+    match text.find('\n') {
+        Some(end) if text[..end].ends_with('\r') => "\r\n",
+        _ => "\n",
+    }
+}
+
+/// Where `old` occurs in `lines`, at or after `from` and nearest `hint`
+fn find(lines: &[&str], old: &[&str], from: usize, hint: usize) -> Option<usize> {
+    if old.is_empty() {
+        return Some(hint.clamp(from, lines.len()));
+    }
+    let matches_at = |at: usize| old.iter().zip(&lines[at..]).all(|(a, b)| a.trim_end() == b.trim_end());
+    (from..lines.len())
+        .filter(|&at| at + old.len() <= lines.len() && matches_at(at))
+        .min_by_key(|&at| at.abs_diff(hint))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Same,
+    Removed,
+    Added,
+}
+
+/// A unified diff from `old` to `new` of the file at `path`; empty when they are
+/// the same. An empty `old` is shown as a new file.
+pub fn diff(path: &str, old: &str, new: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let ops = line_ops(&old_lines, &new_lines);
+    if ops.iter().all(|(op, _)| *op == Op::Same) {
+        return String::new();
+    }
+
+    let old_header = if old.is_empty() { "/dev/null".to_string() } else { format!("a/{}", path) };
+    let mut output = format!("--- {}\n+++ b/{}\n", old_header, path);
+
+    let changes: Vec<usize> = (0..ops.len()).filter(|&i| ops[i].0 != Op::Same).collect();
+    let mut group_start = 0;
+    for g in 0..changes.len() {
+        // A hunk ends where the next change is too far off to share its context
+        let last = g + 1 == changes.len() || changes[g + 1] - changes[g] > 2 * CONTEXT_LINES;
+        if !last {
+            continue;
+        }
+        let start = changes[group_start].saturating_sub(CONTEXT_LINES);
+        let end = (changes[g] + 1 + CONTEXT_LINES).min(ops.len());
+        group_start = g + 1;
+
+        let before = &ops[..start];
+        let old_start = before.iter().filter(|(op, _)| *op != Op::Added).count() + 1;
+        let new_start = before.iter().filter(|(op, _)| *op != Op::Removed).count() + 1;
+        let hunk = &ops[start..end];
+        let old_count = hunk.iter().filter(|(op, _)| *op != Op::Added).count();
+        let new_count = hunk.iter().filter(|(op, _)| *op != Op::Removed).count();
+
+        // An empty side names the line before it
+        output.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            if old_count == 0 { old_start - 1 } else { old_start },
+            old_count,
+            if new_count == 0 { new_start - 1 } else { new_start },
+            new_count
+        ));
+        for (op, line) in hunk {
+            let marker = match op {
+                Op::Same => ' ',
+                Op::Removed => '-',
+                Op::Added => '+',
+            };
+            output.push_str(&format!("{}{}\n", marker, line));
+        }
+    }
+    output
+}
+
+/// Each line of `old` and `new` as kept, removed or added, by longest common subsequence
+fn line_ops<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Op, &'a str)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut ops: Vec<(Op, &str)> = old[..prefix].iter().map(|line| (Op::Same, *line)).collect();
+    if a.len() * b.len() > MAX_DIFF_CELLS {
+        ops.extend(a.iter().map(|line| (Op::Removed, *line)));
+        ops.extend(b.iter().map(|line| (Op::Added, *line)));
+    } else {
+        // lcs[i][j]: longest common subsequence of a[i..] and b[j..]
+        let width = b.len() + 1;
+        let mut lcs = vec![0u32; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * width + j] = if a[i] == b[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                ops.push((Op::Same, a[i]));
+                i += 1;
+                j += 1;
+            } else if j == b.len() || (i < a.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+                ops.push((Op::Removed, a[i]));
+                i += 1;
+            } else {
+                ops.push((Op::Added, b[j]));
+                j += 1;
+            }
+        }
+    }
+    ops.extend(old[old.len() - suffix..].iter().map(|line| (Op::Same, *line)));
+    ops
+}
+
+/// A file's contents before and after a patch that was checked to apply
+#[derive(Debug, Clone)]
+pub struct FileChange {
+    pub path: PathBuf,
+    /// `None` when the patch creates the file
+    pub original: Option<String>,
+    pub updated: String,
+}
+
+impl FileChange {
+    /// The changes `patches` make, each checked to apply to the file as it is now
+    ///
+    /// Paths are taken relative to `root`, and one that is absolute or leads out of
+    /// it is refused. A single patch, or one that named no file, goes to `target`
+    /// when given, as models often get the path wrong.
+    pub fn from_patches(patches: &[FilePatch], root: &Path, target: Option<&Path>) -> Result<Vec<Self>> {
+        let mut changes: Vec<Self> = Vec::new();
+        for patch in patches {
+            let path = match target {
+                Some(target) if patches.len() == 1 || patch.path.is_empty() => target.to_path_buf(),
+                _ if patch.path.is_empty() => bail!("A change names no file"),
+                _ => within(root, &patch.path)?,
+            };
+            let original = match fs::read_to_string(&path) {
+                Ok(content) => Some(content),
+                Err(_) if patch.creates => None,
+                Err(e) => return Err(e).with_context(|| format!("Could not read {}", path.display())),
+            };
+
+            // Later patches to the same file build on the earlier ones
+            match changes.iter_mut().find(|c| c.path == path) {
+                Some(change) => change.updated = patch.apply(&change.updated)?,
+                None => {
+                    let updated = patch.apply(original.as_deref().unwrap_or_default())?;
+                    changes.push(Self { path, original, updated });
+                }
+            }
+        }
+        changes.retain(|c| c.original.as_deref() != Some(c.updated.as_str()));
+        Ok(changes)
+    }
+
+    /// The change as a unified diff, with the file named `name`
+    pub fn diff(&self, name: &str) -> String {
+        diff(name, self.original.as_deref().unwrap_or_default(), &self.updated)
+    }
+}
+
+/// `relative` joined to `root`, or an error when it names a file outside it,
+/// by being absolute, through `..` or through a symlink
+fn within(root: &Path, relative: &str) -> Result<PathBuf> {
+    let mut depth = 0usize;
+    for component in Path::new(relative).components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            _ => bail!("The patch changes {}, outside the project", relative),
+        }
+    }
+
+    // The nearest existing ancestor must resolve inside the project too
+    let path = root.join(relative);
+    let existing = path.ancestors().find(|p| p.exists()).unwrap_or(root);
+    if let (Ok(resolved), Ok(root)) = (existing.canonicalize(), root.canonicalize()) {
+        if !resolved.starts_with(&root) {
+            bail!("The patch changes {}, outside the project", relative);
+        }
+    }
+    Ok(path)
+}
+
+/// Applied patches with copies of what they replaced, oldest first, for `/undo`
+pub struct Backups {
+    dir: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    files: Vec<BackedUpFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackedUpFile {
+    path: PathBuf,
+    /// The copy in the backup directory; `None` when the patch created the file
+    copy: Option<String>,
+    /// A copy of what the patch wrote, to tell whether the file was edited since
+    #[serde(default)]
+    written: Option<String>,
+}
+
+impl Backups {
+    pub fn new(data_dir: &Path) -> Self {
+        Self { dir: data_dir.join("backups") }
+    }
+
+    /// Back up the files `changes` touch, then write the changes
+    ///
+    /// Fails before writing anything if a file changed since the patch was made.
+    pub fn apply(&self, changes: &[FileChange]) -> Result<()> {
+        for change in changes {
+            if fs::read_to_string(&change.path).ok() != change.original {
+                bail!("{} changed since the patch was made; ask for it again", change.path.display());
+            }
+        }
+
+        // Nanoseconds, so patches applied within a second still undo latest first
+        let id = format!("{}-{}", Utc::now().format("%Y%m%d-%H%M%S%.9f"), &uuid::Uuid::new_v4().simple().to_string()[..8]);
+        let dir = self.dir.join(id);
+        fs::create_dir_all(&dir)?;
+        let mut files = Vec::new();
+        for (n, change) in changes.iter().enumerate() {
+            let copy = match change.original {
+                Some(ref original) => {
+                    let name = n.to_string();
+                    fs::write(dir.join(&name), original)?;
+                    Some(name)
+                }
+                None => None,
+            };
+            let written = format!("{}.written", n);
+            fs::write(dir.join(&written), &change.updated)?;
+            files.push(BackedUpFile { path: change.path.clone(), copy, written: Some(written) });
+        }
+        fs::write(dir.join(MANIFEST), serde_json::to_string_pretty(&Manifest { files })?)?;
+
+        for change in changes {
+            if let Some(parent) = change.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&change.path, &change.updated)
+                .with_context(|| format!("Could not write {}", change.path.display()))?;
+        }
+        self.prune()
+    }
+
+    /// Put back the files of the latest patch applied and forget it; the files
+    /// restored, or `None` when there is nothing to undo
+    ///
+    /// Fails before restoring anything if a file was edited since the patch was
+    /// applied, as those edits would be lost, unless `force`.
+    pub fn undo(&self, force: bool) -> Result<Option<Vec<PathBuf>>> {
+        let Some(id) = self.ids()?.pop() else {
+            return Ok(None);
+        };
+        let dir = self.dir.join(id);
+        let manifest: Manifest = serde_json::from_str(&fs::read_to_string(dir.join(MANIFEST))?)
+            .context("Unreadable backup manifest")?;
+
+        if !force {
+            for file in &manifest.files {
+                let Some(ref written) = file.written else { continue };
+                if fs::read(&file.path).ok() != fs::read(dir.join(written)).ok() {
+                    bail!(
+                        "{} was edited after /apply; /undo --force puts it back and loses those edits",
+                        file.path.display()
+                    );
+                }
+            }
+        }
+
+        for file in &manifest.files {
+            match file.copy {
+                Some(ref copy) => {
+                    fs::copy(dir.join(copy), &file.path)
+                        .with_context(|| format!("Could not restore {}", file.path.display()))?;
+                }
+                None => {
+                    if file.path.exists() {
+                        fs::remove_file(&file.path)?;
+                    }
+                }
+            }
+        }
+        fs::remove_dir_all(&dir)?;
+        Ok(Some(manifest.files.into_iter().map(|f| f.path).collect()))
+    }
+
+    /// Backups, oldest first; their names start with the time they were made
+    fn ids(&self) -> Result<Vec<String>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut ids: Vec<String> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().join(MANIFEST).exists())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn prune(&self) -> Result<()> {
+        let ids = self.ids()?;
+        for id in &ids[..ids.len().saturating_sub(MAX_BACKUPS)] {
+            fs::remove_dir_all(self.dir.join(id))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "fn main() {\n    let cache = Cache::new(2);\n    cache.insert(1);\n}\n\nfn helper() {}\n";
+
+    #[test]
+    fn test_parse_and_apply_a_model_diff_with_wrong_line_numbers() {
+        let reply = "Here is the change:\n```diff\n--- a/src/main.rs\n+++ b/src/main.rs\n@@ -7,3 +7,4 @@\n fn main() {\n-    let cache = Cache::new(2);\n+    let mut cache = Cache::new(4);\n     cache.insert(1);\n+    cache.insert(2);\n```\nThis doubles the capacity.";
+
+        let patches = parse(reply);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path, "src/main.rs");
+        assert_eq!(
+            patches[0].apply(ORIGINAL).unwrap(),
+            "fn main() {\n    let mut cache = Cache::new(4);\n    cache.insert(1);\n    cache.insert(2);\n}\n\nfn helper() {}\n"
+        );
+
+        let stale = parse("@@ -1,1 +1,1 @@\n-fn missing() {}\n+fn found() {}\n");
+        assert_eq!(stale[0].path, "");
+        assert!(stale[0].apply(ORIGINAL).unwrap_err().to_string().starts_with("Change 1 of the patch does not match"));
+    }
+
+    #[test]
+    fn test_diff_round_trips_and_backups_undo() {
+        let updated = ORIGINAL.replace("Cache::new(2)", "Cache::new(8)").replace("fn helper() {}", "fn helper() -> u32 { 1 }");
+        let diff = diff("src/main.rs", ORIGINAL, &updated);
+        assert!(diff.starts_with("--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,6 +1,6 @@\n fn main() {\n-    let cache = Cache::new(2);\n+    let cache = Cache::new(8);\n"));
+        assert_eq!(parse(&diff)[0].apply(ORIGINAL).unwrap(), updated);
+        assert_eq!(super::diff("src/main.rs", ORIGINAL, ORIGINAL), "");
+
+        let project = tempfile::tempdir().unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        let existing = project.path().join("main.rs");
+        let created = project.path().join("src/cache.rs");
+        std::fs::write(&existing, ORIGINAL).unwrap();
+        let changes = vec![
+            FileChange { path: existing.clone(), original: Some(ORIGINAL.to_string()), updated: updated.clone() },
+            FileChange { path: created.clone(), original: None, updated: "pub struct Cache;\n".to_string() },
+        ];
+
+        let backups = Backups::new(data_dir.path());
+        backups.apply(&changes).unwrap();
+        assert_eq!(std::fs::read_to_string(&existing).unwrap(), updated);
+        assert!(created.exists());
+        assert!(backups.apply(&changes).is_err(), "the files no longer match the patch");
+
+        assert_eq!(backups.undo(false).unwrap(), Some(vec![existing.clone(), created.clone()]));
+        assert_eq!(std::fs::read_to_string(&existing).unwrap(), ORIGINAL);
+        assert!(!created.exists());
+        assert_eq!(backups.undo(false).unwrap(), None);
+
+        // Undo goes latest first, however quickly the patches followed each other
+        std::fs::write(&existing, "one\n").unwrap();
+        for (before, after) in [("one\n", "two\n"), ("two\n", "three\n"), ("three\n", "four\n")] {
+            let change = FileChange { path: existing.clone(), original: Some(before.to_string()), updated: after.to_string() };
+            backups.apply(&[change]).unwrap();
+        }
+        for expected in ["three\n", "two\n", "one\n"] {
+            backups.undo(false).unwrap();
+            assert_eq!(std::fs::read_to_string(&existing).unwrap(), expected);
+        }
+
+        // Edits made after /apply are not lost to /undo unless it is forced
+        let change = FileChange { path: existing.clone(), original: Some("one\n".to_string()), updated: "two\n".to_string() };
+        backups.apply(&[change]).unwrap();
+        std::fs::write(&existing, "two, edited\n").unwrap();
+        assert!(backups.undo(false).unwrap_err().to_string().contains("was edited after /apply"));
+        assert_eq!(std::fs::read_to_string(&existing).unwrap(), "two, edited\n");
+        backups.undo(true).unwrap();
+        assert_eq!(std::fs::read_to_string(&existing).unwrap(), "one\n");
+    }
+
+    #[test]
+    fn test_apply_keeps_crlf_line_endings() {
+        let original = ORIGINAL.replace('\n', "\r\n");
+        let patch = &parse("@@ -2,1 +2,1 @@\n-    let cache = Cache::new(2);\n+    let cache = Cache::new(8);\n")[0];
+        assert_eq!(patch.apply(&original).unwrap(), original.replace("Cache::new(2)", "Cache::new(8)"));
+    }
+
+    #[test]
+    fn test_patches_outside_the_project_are_refused() {
+        let project = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(project.path().join("src")).unwrap();
+        std::fs::write(project.path().join("src/main.rs"), ORIGINAL).unwrap();
+        let patch = |path: &str| {
+            let mut patches = parse("@@ -1,1 +1,1 @@\n-fn main() {\n+fn main() -> () {\n");
+            patches[0].path = path.to_string();
+            patches
+        };
+
+        let inside = FileChange::from_patches(&patch("src/../src/main.rs"), project.path(), None).unwrap();
+        assert_eq!(inside[0].path, project.path().join("src/../src/main.rs"));
+
+        for outside in ["/etc/passwd", "../main.rs", "src/../../main.rs"] {
+            let error = FileChange::from_patches(&patch(outside), project.path(), None).unwrap_err();
+            assert!(error.to_string().contains("outside the project"), "{}: {}", outside, error);
+        }
+
+        #[cfg(unix)]
+        {
+            let elsewhere = tempfile::tempdir().unwrap();
+            std::fs::write(elsewhere.path().join("main.rs"), ORIGINAL).unwrap();
+            std::os::unix::fs::symlink(elsewhere.path(), project.path().join("linked")).unwrap();
+            assert!(FileChange::from_patches(&patch("linked/main.rs"), project.path(), None).is_err());
+        }
+    }
+}
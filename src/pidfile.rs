@@ -0,0 +1,239 @@
+//! The daemon's pidfile in the data directory: a second daemon refuses to start while
+//! it names a running daemon, and `daemon stop` signals the daemon it names
+//!
+//! The daemon holds a lock on the pidfile for as long as it runs, so a pid left by a
+//! daemon that died and since given to another process is not taken for a daemon.
+
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const PIDFILE_NAME: &str = "sovereign.pid";
+/// Where a detached daemon's output goes
+const LOG_NAME: &str = "daemon.log";
+/// How long `detach` waits for the daemon to write its pidfile
+const START_WAIT: Duration = Duration::from_secs(30);
+/// How long `stop` waits for the daemon to go
+const STOP_WAIT: Duration = Duration::from_secs(15);
+
+/// This process's claim on the pidfile, removed when dropped
+pub struct Pidfile {
+    path: PathBuf,
+    /// Open, and locked, until the claim is dropped
+    _file: File,
+}
+
+impl Pidfile {
+    /// Write this process's pid to the pidfile; fails while another live daemon holds it
+    pub fn claim(data_dir: &Path) -> Result<Self> {
+        let path = data_dir.join(PIDFILE_NAME);
+        // A pidfile left by a daemon that died is taken over, once
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    // Locked before the pid is written, so the pid is never seen unlocked
+                    lock(&file).with_context(|| format!("Could not lock {}", path.display()))?;
+                    writeln!(file, "{}", std::process::id())?;
+                    return Ok(Self { path, _file: file });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if let Some(pid) = running(data_dir) {
+                        anyhow::bail!("A daemon is already running (pid {}); `sovereign daemon stop` stops it", pid);
+                    }
+                    let _ = fs::remove_file(&path);
+                }
+                Err(e) => return Err(e).with_context(|| format!("Could not write {}", path.display())),
+            }
+        }
+        anyhow::bail!("Could not claim {}", path.display())
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        // Only if it is still ours
+        if read_pid(&self.path) == Some(std::process::id()) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// The pid of the daemon named in the pidfile, if that daemon is still running
+pub fn running(data_dir: &Path) -> Option<u32> {
+    let path = data_dir.join(PIDFILE_NAME);
+    read_pid(&path).filter(|&pid| locked(&path) && alive(pid))
+}
+
+/// Start the daemon again in the background with this process's arguments but
+/// `--detach`, its output going to `daemon.log`; its pid once it has written the pidfile
+pub fn detach(data_dir: &Path) -> Result<u32> {
+    if let Some(pid) = running(data_dir) {
+        anyhow::bail!("A daemon is already running (pid {}); `sovereign daemon stop` stops it", pid);
+    }
+    fs::create_dir_all(data_dir)?;
+    let log_path = log_path(data_dir);
+    let log = OpenOptions::new().create(true).append(true).open(&log_path)?;
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args(std::env::args_os().skip(1).filter(|arg| arg != "--detach"))
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        // The log is not a terminal
+        .env("NO_COLOR", "1");
+    #[cfg(unix)]
+    {
+        // Out of the terminal's process group, so closing the terminal or Ctrl+C leaves it running
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let mut child = command.spawn().context("Could not start the daemon")?;
+
+    let deadline = Instant::now() + START_WAIT;
+    loop {
+        if running(data_dir) == Some(child.id()) {
+            return Ok(child.id());
+        }
+        if let Some(status) = child.try_wait()? {
+            anyhow::bail!("The daemon exited ({}); see {}", status, log_path.display());
+        }
+        if Instant::now() > deadline {
+            anyhow::bail!("The daemon did not start within {} seconds; see {}", START_WAIT.as_secs(), log_path.display());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Send SIGTERM to the daemon named in the pidfile, so it shuts down cleanly, and wait
+/// for it to go; false if none was running
+pub fn stop(data_dir: &Path) -> Result<bool> {
+    let Some(pid) = running(data_dir) else {
+        return Ok(false);
+    };
+    terminate(pid)?;
+
+    let deadline = Instant::now() + STOP_WAIT;
+    while alive(pid) {
+        if Instant::now() > deadline {
+            anyhow::bail!("The daemon (pid {}) is still running after {} seconds", pid, STOP_WAIT.as_secs());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    Ok(true)
+}
+
+pub fn log_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(LOG_NAME)
+}
+
+fn read_pid(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(unix)]
+fn alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks the process exists; EPERM means it does, as another user's
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Without a way to look, a pidfile is taken as stale
+#[cfg(not(unix))]
+fn alive(_pid: u32) -> bool {
+    false
+}
+
+/// Take the lock a running daemon holds on its pidfile
+#[cfg(unix)]
+fn lock(file: &File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn lock(_file: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Whether a running daemon holds the lock on the pidfile at `path`
+#[cfg(unix)]
+fn locked(path: &Path) -> bool {
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    // Taking the lock means nobody holds it; it is let go again with the file
+    lock(&file).err().and_then(|e| e.raw_os_error()) == Some(libc::EWOULDBLOCK)
+}
+
+/// Without locks to look at, only whether the process is alive tells
+#[cfg(not(unix))]
+fn locked(_path: &Path) -> bool {
+    true
+}
+
+#[cfg(unix)]
+fn terminate(pid: u32) -> Result<()> {
+    let pid = libc::pid_t::try_from(pid)?;
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Could not signal the daemon");
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn terminate(pid: u32) -> Result<()> {
+    anyhow::bail!("Cannot signal the daemon (pid {}) on this platform", pid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pidfile_refuses_a_second_daemon_and_takes_over_a_stale_one() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let pidfile = Pidfile::claim(data_dir.path()).unwrap();
+        assert_eq!(running(data_dir.path()), Some(std::process::id()));
+        let refused = Pidfile::claim(data_dir.path()).err().unwrap();
+        assert!(refused.to_string().starts_with("A daemon is already running"), "{}", refused);
+        drop(pidfile);
+        assert_eq!(running(data_dir.path()), None);
+
+        // Left by a process that has exited
+        let mut exited = Command::new("true").spawn().unwrap();
+        exited.wait().unwrap();
+        fs::write(data_dir.path().join(PIDFILE_NAME), format!("{}\n", exited.id())).unwrap();
+        assert_eq!(running(data_dir.path()), None);
+        assert!(!stop(data_dir.path()).unwrap());
+        let _pidfile = Pidfile::claim(data_dir.path()).unwrap();
+        assert_eq!(read_pid(&data_dir.path().join(PIDFILE_NAME)), Some(std::process::id()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_a_reused_pid_is_not_taken_for_the_daemon() {
+        let data_dir = tempfile::tempdir().unwrap();
+        // Alive, but not a daemon holding the pidfile
+        let mut other = Command::new("sleep").arg("30").spawn().unwrap();
+        fs::write(data_dir.path().join(PIDFILE_NAME), format!("{}\n", other.id())).unwrap();
+
+        assert_eq!(running(data_dir.path()), None);
+        assert!(!stop(data_dir.path()).unwrap());
+        assert!(other.try_wait().unwrap().is_none(), "the process was signalled");
+        let _pidfile = Pidfile::claim(data_dir.path()).unwrap();
+
+        other.kill().unwrap();
+        other.wait().unwrap();
+    }
+}
@@ -0,0 +1,200 @@
+/// A clean-up step applied to a model response
+pub type Hook = fn(&str) -> String;
+
+/// Openings of the filler line models put before code ("Here is the code:")
+const FILLER_OPENINGS: &[&str] = &[
+    "here is", "here's", "here are", "below is", "sure", "certainly", "of course", "okay", "absolutely",
+];
+
+/// Longest line treated as filler; longer ones carry an explanation
+const MAX_FILLER_CHARS: usize = 120;
+
+/// Ordered clean-up steps for model responses, so features that consume code
+/// (writing files, applying edits) get a predictable structure
+#[derive(Clone)]
+pub struct PostProcessor {
+    hooks: Vec<Hook>,
+}
+
+impl PostProcessor {
+    /// Responses shown to the user: filler before the code removed, fences repaired
+    pub fn for_display() -> Self {
+        Self {
+            hooks: vec![strip_preamble, fix_fences],
+        }
+    }
+
+    /// Responses whose code is written somewhere: also reduced to one fenced block
+    pub fn for_code() -> Self {
+        Self::for_display().then(single_block)
+    }
+
+    /// Run `hook` after the existing steps
+    pub fn then(mut self, hook: Hook) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    pub fn apply(&self, response: &str) -> String {
+        self.hooks
+            .iter()
+            .fold(response.to_string(), |text, hook| hook(&text))
+    }
+}
+
+/// A fenced code block in a model response
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeBlock {
+    pub language: String,
+    pub code: String,
+}
+
+/// Fenced blocks of a response, in order
+///
+/// A fence with a language tag inside a block starts a new block (the model
+/// forgot to close the previous one), and an unclosed last block runs to the end.
+pub fn code_blocks(response: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<CodeBlock> = None;
+
+    for line in response.lines() {
+        match (fence_tag(line), current.take()) {
+            (Some(tag), Some(block)) => {
+                blocks.push(block);
+                if !tag.is_empty() {
+                    current = Some(CodeBlock { language: tag.to_string(), code: String::new() });
+                }
+            }
+            (Some(tag), None) => current = Some(CodeBlock { language: tag.to_string(), code: String::new() }),
+            (None, Some(mut block)) => {
+                block.code.push_str(line);
+                block.code.push('\n');
+                current = Some(block);
+            }
+            (None, None) => {}
+        }
+    }
+
+    blocks.extend(current);
+    blocks
+}
+
+/// The code to write to a file from a response: its single fenced block, or the
+/// whole response when the model sent bare code; None when there is none
+pub fn extract_code(response: &str) -> Option<String> {
+    code_blocks(&PostProcessor::for_code().apply(response))
+        .into_iter()
+        .next()
+        .map(|block| block.code)
+        .filter(|code| !code.trim().is_empty())
+}
+
+/// Drop filler lines ("Sure! Here is the code:") before the first fence
+pub fn strip_preamble(response: &str) -> String {
+    if !response.contains("```") {
+        return response.to_string();
+    }
+
+    let mut lines = response.lines().peekable();
+    while let Some(line) = lines.peek() {
+        if line.trim().is_empty() || is_filler(line) {
+            lines.next();
+        } else {
+            break;
+        }
+    }
+    lines.collect::<Vec<_>>().join("\n")
+}
+
+/// Normalize fences to three backticks, close a block left open before the next
+/// one starts, and close a block left open at the end
+pub fn fix_fences(response: &str) -> String {
+    let mut lines = Vec::new();
+    let mut open = false;
+
+    for line in response.lines() {
+        match fence_tag(line) {
+            Some(tag) if open && !tag.is_empty() => {
+                lines.push("```".to_string());
+                lines.push(format!("```{}", tag));
+            }
+            Some(tag) => {
+                lines.push(format!("```{}", tag));
+                open = !open;
+            }
+            None => lines.push(line.to_string()),
+        }
+    }
+
+    if open {
+        lines.push("```".to_string());
+    }
+    lines.join("\n")
+}
+
+/// Reduce a response to one fenced block: the first non-empty one, or the whole
+/// response fenced when it has none
+pub fn single_block(response: &str) -> String {
+    let block = code_blocks(response)
+        .into_iter()
+        .find(|block| !block.code.trim().is_empty());
+
+    match block {
+        Some(block) => format!("```{}\n{}```", block.language, block.code),
+        None => format!("```\n{}\n```", response.trim()),
+    }
+}
+
+/// The language tag of a fence line (empty for a bare fence); None for other lines
+fn fence_tag(line: &str) -> Option<&str> {
+    let trimmed = line.trim();
+    let ticks = trimmed.chars().take_while(|&c| c == '`').count();
+    if ticks < 3 {
+        return None;
+    }
+    let tag = trimmed[ticks..].trim();
+    // "```rust title" keeps only the language
+    Some(tag.split_whitespace().next().unwrap_or(""))
+}
+
+fn is_filler(line: &str) -> bool {
+    let lower = line.trim().to_lowercase();
+    lower.len() <= MAX_FILLER_CHARS && FILLER_OPENINGS.iter().any(|opening| lower.starts_with(opening))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_cleanup() {
+        let response = "Sure! Here is the code:\n\n```` rust\nfn main() {}\n````\nIt prints nothing.";
+        assert_eq!(
+            PostProcessor::for_display().apply(response),
+            "```rust\nfn main() {}\n```\nIt prints nothing."
+        );
+
+        // An explanation before the code is kept, and a missing closing fence added
+        let response = "This parses the header first.\n```python\ndef parse(): pass\n```toml\nkey = 1";
+        assert_eq!(
+            PostProcessor::for_display().apply(response),
+            "This parses the header first.\n```python\ndef parse(): pass\n```\n```toml\nkey = 1\n```"
+        );
+
+        assert_eq!(strip_preamble("Sure, that works."), "Sure, that works.");
+    }
+
+    #[test]
+    fn test_code_is_one_block() {
+        let response = "Here's the fix:\n```rust\nfn a() {}\n```\nAnd a test:\n```rust\n#[test]\nfn t() {}\n```";
+        assert_eq!(PostProcessor::for_code().apply(response), "```rust\nfn a() {}\n```");
+        assert_eq!(extract_code(response).as_deref(), Some("fn a() {}\n"));
+
+        assert_eq!(extract_code("fn bare() {}").as_deref(), Some("fn bare() {}\n"));
+        assert_eq!(extract_code("   "), None);
+
+        let blocks = code_blocks("```js\nlet a;\n```\ntext\n```\nplain\n");
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1], CodeBlock { language: String::new(), code: "plain\n".to_string() });
+    }
+}
@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::llm::GenerationOptions;
+
+/// Generation constraints for each prompt template, keyed by command name
+///
+/// Built-in limits cover the commands whose output has a known shape (commit
+/// messages, generated code blocks). A `[prompts.<command>]` table in
+/// `sovereign.toml` replaces the built-in constraints for that command:
+///
+/// ```toml
+/// [prompts.commit]
+/// max_tokens = 120
+/// stop = ["\n\n\n"]
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PromptLibrary {
+    overrides: HashMap<String, GenerationOptions>,
+    /// Set from the top-level `verbosity` key or `/verbosity`
+    #[serde(skip)]
+    verbosity: Verbosity,
+}
+
+/// Commands whose output is parsed or stored, so verbosity never changes their length or style
+const STRUCTURED_COMMANDS: &[&str] = &["expand_query", "analyze_diff", "ci_review", "audit", "file_summary", "glossary", "score_memory", "consolidate", "summarize_chat", "edit", "refactor_plan", "task_plan", "commit_lint"];
+
+/// Token limit for terse answers to commands without a built-in limit
+const TERSE_MAX_TOKENS: u32 = 400;
+
+/// How long and how explanatory generated answers are
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    /// Short answers without preamble; half the usual token limits
+    Terse,
+    #[default]
+    Normal,
+    /// Step-by-step explanations of the reasoning; twice the usual token limits
+    Teaching,
+}
+
+impl Verbosity {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "terse" => Some(Verbosity::Terse),
+            "normal" => Some(Verbosity::Normal),
+            "teaching" => Some(Verbosity::Teaching),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Verbosity::Terse => "terse",
+            Verbosity::Normal => "normal",
+            Verbosity::Teaching => "teaching",
+        }
+    }
+
+    fn instruction(&self) -> Option<&'static str> {
+        match self {
+            Verbosity::Terse => Some("Be terse: give only the answer, in as few words as possible, with no preamble or recap."),
+            Verbosity::Normal => None,
+            Verbosity::Teaching => Some("Teach as you answer: explain the reasoning step by step and the concepts behind it, for someone learning this code."),
+        }
+    }
+
+    fn max_tokens(&self, max_tokens: Option<u32>) -> Option<u32> {
+        match (self, max_tokens) {
+            (Verbosity::Terse, Some(max)) => Some((max / 2).max(32)),
+            (Verbosity::Terse, None) => Some(TERSE_MAX_TOKENS),
+            (Verbosity::Normal, max) => max,
+            (Verbosity::Teaching, max) => max.map(|m| m.saturating_mul(2)),
+        }
+    }
+}
+
+impl PromptLibrary {
+    /// Constraints to use when generating for `command`
+    pub fn options(&self, command: &str) -> GenerationOptions {
+        let mut options = self.overrides
+            .get(command)
+            .cloned()
+            .unwrap_or_else(|| builtin_options(command));
+
+        if !STRUCTURED_COMMANDS.contains(&command) {
+            options.max_tokens = self.verbosity.max_tokens(options.max_tokens);
+        }
+        options
+    }
+
+    /// System prompt for `command`, with the verbosity instruction appended
+    pub fn system(&self, command: &str, system: &str) -> String {
+        match self.verbosity.instruction() {
+            Some(instruction) if !STRUCTURED_COMMANDS.contains(&command) => {
+                format!("{}\n\n{}", system, instruction)
+            }
+            _ => system.to_string(),
+        }
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.verbosity = verbosity;
+    }
+}
+
+fn builtin_options(command: &str) -> GenerationOptions {
+    match command {
+        "commit" => GenerationOptions {
+            max_tokens: Some(256),
+            stop: vec!["\n\n\n".to_string()],
+            ..Default::default()
+        },
+        "analyze_diff" => GenerationOptions {
+            max_tokens: Some(200),
+            json: true,
+            ..Default::default()
+        },
+        "ci_review" | "audit" | "whatchanged" => GenerationOptions {
+            max_tokens: Some(512),
+            ..Default::default()
+        },
+        "pr_summary" => GenerationOptions {
+            max_tokens: Some(1024),
+            ..Default::default()
+        },
+        "generate" => GenerationOptions {
+            max_tokens: Some(2048),
+            stop_after_code_block: true,
+            ..Default::default()
+        },
+        "expand_query" => GenerationOptions {
+            max_tokens: Some(96),
+            ..Default::default()
+        },
+        "score_memory" => GenerationOptions {
+            max_tokens: Some(96),
+            json: true,
+            ..Default::default()
+        },
+        "consolidate" => GenerationOptions {
+            max_tokens: Some(384),
+            json: true,
+            ..Default::default()
+        },
+        "task_plan" => GenerationOptions {
+            max_tokens: Some(512),
+            json: true,
+            ..Default::default()
+        },
+        "refactor_plan" | "commit_lint" => GenerationOptions {
+            max_tokens: Some(384),
+            json: true,
+            ..Default::default()
+        },
+        "summarize_chat" => GenerationOptions {
+            max_tokens: Some(512),
+            ..Default::default()
+        },
+        "file_summary" | "glossary" => GenerationOptions {
+            max_tokens: Some(120),
+            ..Default::default()
+        },
+        "edit" | "refactor" => GenerationOptions {
+            max_tokens: Some(2048),
+            ..Default::default()
+        },
+        "fix" | "test" | "explain_error" => GenerationOptions {
+            max_tokens: Some(2048),
+            ..Default::default()
+        },
+        _ => GenerationOptions::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_override_replaces_builtin() {
+        let library: PromptLibrary = toml::from_str("[commit]\nmax_tokens = 64").unwrap();
+
+        let commit = library.options("commit");
+        assert_eq!(commit.max_tokens, Some(64));
+        assert!(commit.stop.is_empty());
+
+        assert!(library.options("generate").stop_after_code_block);
+        // A refactor may span several files, each in its own block
+        assert!(!library.options("refactor").stop_after_code_block);
+        assert_eq!(library.options("chat"), GenerationOptions::default());
+    }
+
+    #[test]
+    fn test_verbosity_scales_prose_commands_only() {
+        let mut library: PromptLibrary = toml::from_str("[commit]\nmax_tokens = 64").unwrap();
+        library.set_verbosity(Verbosity::Terse);
+
+        assert_eq!(library.options("commit").max_tokens, Some(32));
+        assert_eq!(library.options("chat").max_tokens, Some(TERSE_MAX_TOKENS));
+        assert_eq!(library.options("ci_review").max_tokens, Some(512));
+        assert!(library.system("review", "You review code.").ends_with("with no preamble or recap."));
+        assert_eq!(library.system("expand_query", "Terms only."), "Terms only.");
+
+        library.set_verbosity(Verbosity::Teaching);
+        assert_eq!(library.options("pr_summary").max_tokens, Some(2048));
+        assert_eq!(library.options("chat").max_tokens, None);
+        assert_eq!(Verbosity::parse(" Teaching"), Some(Verbosity::Teaching));
+    }
+
+    #[test]
+    fn test_cut_after_first_code_block() {
+        let options = PromptLibrary::default().options("generate");
+        let text = "Here you go:\n```rust\nfn main() {}\n```\nAnd another:\n```\nx\n```";
+        assert_eq!(options.apply(text), "Here you go:\n```rust\nfn main() {}\n```");
+
+        // An unterminated block is left alone
+        assert_eq!(options.cut_point("```rust\nfn main"), None);
+    }
+}
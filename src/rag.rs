@@ -1,11 +1,14 @@
 use anyhow::Result;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
 use crate::embeddings::{cosine_similarity, EmbeddingClient};
+use crate::storage::codebase::CODE_TOKENIZER;
 use crate::storage::CodebaseIndex;
 
 /// Configuration for RAG retrieval
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RagConfig {
     /// Number of top results to retrieve
     pub top_k: usize,
@@ -19,6 +22,25 @@ pub struct RagConfig {
     pub semantic_weight: f32,
     /// Enable reranking of results
     pub enable_rerank: bool,
+    /// Rerank boost per query term found in the chunk
+    pub exact_match_boost: f32,
+    /// Rerank boost per query term found in a definition (fn/class/struct name)
+    pub definition_boost: f32,
+    /// Maximum rerank boost for small, focused chunks
+    pub size_boost: f32,
+    /// Maximal marginal relevance trade-off: 1.0 ranks by relevance only,
+    /// lower values prefer results unlike those already picked
+    pub mmr_lambda: f32,
+    /// Most chunks taken from a single file (0 = no limit)
+    pub max_chunks_per_file: usize,
+    /// Ask the model for synonyms and likely identifier names before searching
+    pub query_expansion: bool,
+    /// Files directly imported by the top results, added as extra context (0 = off)
+    pub import_context: usize,
+    /// Largest boost for the files the user asks about and edits most (0 = off)
+    pub interaction_boost: f32,
+    /// Full-text tokenizer: `code`, or an FTS5 tokenizer spec such as `porter unicode61`
+    pub fts_tokenizer: String,
 }
 
 impl Default for RagConfig {
@@ -30,6 +52,15 @@ impl Default for RagConfig {
             chunk_overlap: 200,
             semantic_weight: 0.7,
             enable_rerank: true,
+            exact_match_boost: 0.1,
+            definition_boost: 0.2,
+            size_boost: 0.1,
+            mmr_lambda: 0.7,
+            max_chunks_per_file: 2,
+            query_expansion: false,
+            import_context: 2,
+            interaction_boost: 0.1,
+            fts_tokenizer: CODE_TOKENIZER.to_string(),
         }
     }
 }
@@ -43,6 +74,8 @@ pub struct CodeChunk {
     pub end_line: usize,
     pub language: String,
     pub embedding: Option<Vec<f32>>,
+    /// Heading path (`Install > Build`) or key path (`services.web`) of a doc or config chunk
+    pub section: Option<String>,
 }
 
 /// Search result with relevance score
@@ -58,6 +91,8 @@ pub enum MatchType {
     Semantic,
     Keyword,
     Hybrid,
+    /// Pulled in because a result imports it
+    Import,
 }
 
 /// Improved RAG retriever with hybrid search
@@ -74,6 +109,13 @@ impl RagRetriever {
         }
     }
 
+    /// Use a specific embedding client (e.g. one built from `[embeddings]` config)
+    pub fn with_embedding_client(mut self, embedding_client: EmbeddingClient) -> Self {
+        self.embedding_client = embedding_client;
+        self
+    }
+
+    #[cfg(test)]
     pub fn with_defaults() -> Self {
         Self::new(RagConfig::default())
     }
@@ -87,14 +129,20 @@ impl RagRetriever {
             return chunks;
         }
 
-        // Try to chunk at natural boundaries (functions, classes)
-        let boundaries = find_code_boundaries(&lines, language);
+        // Try to chunk at natural boundaries: definitions in code, headings in
+        // markdown, keys in config files
+        let sections: Vec<(usize, Option<String>)> = match language {
+            "markdown" => markdown_sections(&lines),
+            "yaml" | "json" | "toml" => config_sections(&lines, language),
+            _ => find_code_boundaries(&lines, language).into_iter().map(|line| (line, None)).collect(),
+        };
 
-        if !boundaries.is_empty() {
+        if !sections.is_empty() {
             // Use natural boundaries for chunking
-            for window in boundaries.windows(2) {
-                let start = window[0];
-                let end = window[1].min(lines.len());
+            for window in sections.windows(2) {
+                let (start, section) = &window[0];
+                let start = *start;
+                let end = window[1].0.min(lines.len());
 
                 let chunk_content: String = lines[start..end].join("\n");
                 if !chunk_content.trim().is_empty() {
@@ -105,6 +153,7 @@ impl RagRetriever {
                         end_line: end,
                         language: language.to_string(),
                         embedding: None,
+                        section: section.clone(),
                     });
                 }
             }
@@ -123,6 +172,7 @@ impl RagRetriever {
                         end_line: end,
                         language: language.to_string(),
                         embedding: None,
+                        section: None,
                     });
                 }
 
@@ -148,21 +198,76 @@ impl RagRetriever {
         // Get keyword results
         let keyword_results = self.keyword_search(query, index)?;
 
-        // Merge and deduplicate
-        let merged = self.merge_results(semantic_results, keyword_results);
+        let ranked = self.rank(query, semantic_results, keyword_results);
+        let ranked = self.boost_frequent_files(index, ranked);
+        Ok(self.add_imported_files(index, ranked))
+    }
 
-        // Rerank if enabled
-        let final_results = if self.config.enable_rerank {
-            self.rerank_results(query, merged)
-        } else {
-            merged
-        };
+    /// Raise the scores of files the user works with often, then re-sort
+    pub fn boost_frequent_files(&self, index: &CodebaseIndex, mut results: Vec<SearchResult>) -> Vec<SearchResult> {
+        let counts = index.interaction_counts().unwrap_or_default();
+        let max = counts.values().copied().max().unwrap_or(0);
 
-        Ok(final_results
-            .into_iter()
-            .filter(|r| r.score >= self.config.min_similarity)
-            .take(self.config.top_k)
-            .collect())
+        for result in &mut results {
+            let count = counts.get(&result.chunk.file_path).copied().unwrap_or(0);
+            result.score = (result.score + interaction_boost(&self.config, count, max)).min(1.0);
+        }
+
+        results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+        results
+    }
+
+    /// Append the files the best results import, up to `import_context` of them
+    ///
+    /// Each is represented by its first chunk and scored at half its importer.
+    pub fn add_imported_files(&self, index: &CodebaseIndex, mut results: Vec<SearchResult>) -> Vec<SearchResult> {
+        let mut seen: HashSet<String> = results.iter().map(|r| r.chunk.file_path.clone()).collect();
+        let mut extra = Vec::new();
+
+        for result in &results {
+            if extra.len() >= self.config.import_context {
+                break;
+            }
+            let Ok(imported) = index.dependencies(&result.chunk.file_path) else {
+                continue;
+            };
+
+            for path in imported {
+                if extra.len() >= self.config.import_context {
+                    break;
+                }
+                let Ok(Some(file)) = index.get_file(&path) else {
+                    continue;
+                };
+                if !seen.insert(file.path.clone()) {
+                    continue;
+                }
+                let Ok(Some(content)) = index.get_file_content(&file.path) else {
+                    continue;
+                };
+
+                if let Some(chunk) = self.chunk_content(&content, &file.path, &file.language).into_iter().next() {
+                    extra.push(SearchResult {
+                        chunk,
+                        score: result.score * 0.5,
+                        match_type: MatchType::Import,
+                    });
+                }
+            }
+        }
+
+        results.extend(extra);
+        results
+    }
+
+    /// Merge, rerank and filter raw semantic/keyword hits using this retriever's config
+    pub fn rank(
+        &self,
+        query: &str,
+        semantic: Vec<SearchResult>,
+        keyword: Vec<SearchResult>,
+    ) -> Vec<SearchResult> {
+        rank_candidates(&self.config, query, semantic, keyword)
     }
 
     /// Semantic search using embeddings
@@ -174,23 +279,31 @@ impl RagRetriever {
         // Get query embedding
         let query_embedding = self.embedding_client.embed(query).await?;
 
-        // Get all files with embeddings
+        // Vectors from another model are not comparable
+        let model = self.embedding_client.active_model().await;
+        index.check_embedding_model(&model, query_embedding.len())?;
+
+        // Best matching chunks across all files
         let files = index.search_semantic(&query_embedding, self.config.top_k * 2)?;
 
         let results: Vec<SearchResult> = files
             .into_iter()
-            .map(|(file, score)| {
-                let content = std::fs::read_to_string(&file.path).unwrap_or_default();
-                let language = detect_language(&file.path);
+            .map(|(hit, score)| {
+                let file_content = std::fs::read_to_string(&hit.path).unwrap_or_default();
+                let language = detect_language(&hit.path);
+
+                let end_line = hit.last_line(file_content.lines().count());
+                let content = hit.text_of(&file_content);
 
                 SearchResult {
                     chunk: CodeChunk {
-                        file_path: file.path.clone(),
+                        file_path: hit.path,
                         content,
-                        start_line: 1,
-                        end_line: file.lines,
+                        start_line: hit.start_line,
+                        end_line,
                         language,
-                        embedding: file.embedding.clone(),
+                        embedding: Some(hit.embedding),
+                        section: None,
                     },
                     score,
                     match_type: MatchType::Semantic,
@@ -201,18 +314,44 @@ impl RagRetriever {
         Ok(results)
     }
 
-    /// Keyword search using text matching
+    /// Keyword search over the FTS5 index, ranked by bm25
+    ///
+    /// Each hit carries the passage around its best match rather than the
+    /// whole file. Only files without a full-text row are read from disk.
     pub fn keyword_search(
         &self,
         query: &str,
         index: &CodebaseIndex,
     ) -> Result<Vec<SearchResult>> {
         let keywords: Vec<&str> = query.split_whitespace().collect();
-        let files = index.get_all_files()?;
+        let hits = index.search_keywords(&keywords, self.config.top_k * 2)?;
 
-        let mut results = Vec::new();
+        // bm25 is unbounded; scale against the best hit so scores mix with semantic ones
+        let best_rank = hits.iter().map(|h| h.rank).fold(0.0, f64::min);
 
-        for file in files {
+        let mut results: Vec<SearchResult> = hits
+            .into_iter()
+            .map(|hit| {
+                let relative = if best_rank < 0.0 { (hit.rank / best_rank) as f32 } else { 1.0 };
+                let score = calculate_keyword_score(&hit.content, &keywords) * (0.5 + 0.5 * relative);
+
+                SearchResult {
+                    chunk: CodeChunk {
+                        file_path: hit.path,
+                        content: hit.snippet,
+                        start_line: hit.start_line,
+                        end_line: hit.end_line,
+                        language: hit.language,
+                        embedding: None,
+                        section: None,
+                    },
+                    score,
+                    match_type: MatchType::Keyword,
+                }
+            })
+            .collect();
+
+        for file in index.files_missing_fts()? {
             let content = match std::fs::read_to_string(&file.path) {
                 Ok(c) => c,
                 Err(_) => continue,
@@ -229,7 +368,8 @@ impl RagRetriever {
                         start_line: 1,
                         end_line: file.lines,
                         language,
-                        embedding: file.embedding.clone(),
+                        embedding: None,
+                        section: None,
                     },
                     score,
                     match_type: MatchType::Keyword,
@@ -243,119 +383,322 @@ impl RagRetriever {
         Ok(results)
     }
 
-    /// Merge semantic and keyword results
-    fn merge_results(
-        &self,
-        semantic: Vec<SearchResult>,
-        keyword: Vec<SearchResult>,
-    ) -> Vec<SearchResult> {
-        let mut scores: HashMap<String, (f32, f32)> = HashMap::new();
-        let mut chunks: HashMap<String, CodeChunk> = HashMap::new();
-
-        // Add semantic scores
-        for result in &semantic {
-            let key = format!("{}:{}", result.chunk.file_path, result.chunk.start_line);
-            scores.entry(key.clone()).or_insert((0.0, 0.0)).0 = result.score;
-            chunks.insert(key, result.chunk.clone());
-        }
+    /// Build context string from search results
+    pub fn build_context(&self, results: &[SearchResult], max_tokens: usize) -> String {
+        let mut context = String::new();
+        let mut token_count = 0;
+
+        for result in results {
+            // Approximate tokens as words / 0.75
+            let chunk_tokens = result.chunk.content.split_whitespace().count() * 4 / 3;
+
+            if token_count + chunk_tokens > max_tokens {
+                break;
+            }
+
+            // Files pulled in through imports are there for reference, not as answers
+            let pulled_in = if result.match_type == MatchType::Import { ", imported by a match" } else { "" };
+            context.push_str(&format!(
+                "\n--- {} (lines {}-{}{}) ---\n```{}\n{}\n```\n",
+                result.chunk.file_path,
+                result.chunk.start_line,
+                result.chunk.end_line,
+                pulled_in,
+                result.chunk.language,
+                result.chunk.content
+            ));
 
-        // Add keyword scores
-        for result in &keyword {
-            let key = format!("{}:{}", result.chunk.file_path, result.chunk.start_line);
-            scores.entry(key.clone()).or_insert((0.0, 0.0)).1 = result.score;
-            chunks.entry(key).or_insert(result.chunk.clone());
+            token_count += chunk_tokens;
         }
 
-        // Combine scores
-        let semantic_weight = self.config.semantic_weight;
-        let keyword_weight = 1.0 - semantic_weight;
+        context
+    }
+}
 
-        let mut results: Vec<SearchResult> = scores
-            .into_iter()
-            .map(|(key, (sem, kw))| {
-                let combined_score = semantic_weight * sem + keyword_weight * kw;
-                let match_type = if sem > 0.0 && kw > 0.0 {
-                    MatchType::Hybrid
-                } else if sem > 0.0 {
-                    MatchType::Semantic
-                } else {
-                    MatchType::Keyword
-                };
+/// Combine raw semantic and keyword hits into the final ranked list
+pub fn rank_candidates(
+    config: &RagConfig,
+    query: &str,
+    semantic: Vec<SearchResult>,
+    keyword: Vec<SearchResult>,
+) -> Vec<SearchResult> {
+    // Merge and deduplicate
+    let merged = merge_results(config, semantic, keyword);
+
+    // Rerank if enabled
+    let final_results = if config.enable_rerank {
+        rerank_results(config, query, merged)
+    } else {
+        merged
+    };
 
-                SearchResult {
-                    chunk: chunks.remove(&key).unwrap(),
-                    score: combined_score,
-                    match_type,
-                }
-            })
-            .collect();
+    let relevant: Vec<SearchResult> = final_results
+        .into_iter()
+        .filter(|r| r.score >= config.min_similarity)
+        .collect();
 
-        results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
-        results
+    diversify(config, relevant)
+}
+
+/// Pick `top_k` results by maximal marginal relevance, respecting the per-file cap
+///
+/// Candidates must be sorted by score. Each pick maximizes
+/// `lambda * score - (1 - lambda) * max similarity to the picks so far`, so a
+/// file whose best chunk is already in the context has to earn its next slot.
+fn diversify(config: &RagConfig, mut candidates: Vec<SearchResult>) -> Vec<SearchResult> {
+    let lambda = config.mmr_lambda.clamp(0.0, 1.0);
+    let mut selected: Vec<SearchResult> = Vec::new();
+    let mut per_file: HashMap<String, usize> = HashMap::new();
+
+    while selected.len() < config.top_k && !candidates.is_empty() {
+        let mut best: Option<(usize, f32)> = None;
+
+        for (i, candidate) in candidates.iter().enumerate() {
+            let taken = per_file.get(&candidate.chunk.file_path).copied().unwrap_or(0);
+            if config.max_chunks_per_file > 0 && taken >= config.max_chunks_per_file {
+                continue;
+            }
+
+            let redundancy = selected
+                .iter()
+                .map(|s| chunk_similarity(&candidate.chunk, &s.chunk))
+                .fold(0.0, f32::max);
+            let mmr = lambda * candidate.score - (1.0 - lambda) * redundancy;
+
+            if best.map(|(_, b)| mmr > b).unwrap_or(true) {
+                best = Some((i, mmr));
+            }
+        }
+
+        let Some((i, _)) = best else {
+            break;
+        };
+        let picked = candidates.remove(i);
+        *per_file.entry(picked.chunk.file_path.clone()).or_insert(0) += 1;
+        selected.push(picked);
     }
 
-    /// Rerank results based on query relevance
-    fn rerank_results(&self, query: &str, mut results: Vec<SearchResult>) -> Vec<SearchResult> {
-        // Simple reranking based on query term density and position
-        let query_terms: Vec<&str> = query.split_whitespace().collect();
+    selected
+}
 
-        for result in &mut results {
-            let content_lower = result.chunk.content.to_lowercase();
-            let mut boost = 0.0;
+/// Similarity of two chunks: cosine of their embeddings when both have one,
+/// otherwise the overlap of their identifier sets
+fn chunk_similarity(a: &CodeChunk, b: &CodeChunk) -> f32 {
+    if let (Some(ea), Some(eb)) = (&a.embedding, &b.embedding) {
+        return cosine_similarity(ea, eb).max(0.0);
+    }
+
+    let words = |content: &str| -> HashSet<String> {
+        content
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .filter(|w| w.len() > 2)
+            .map(|w| w.to_lowercase())
+            .collect()
+    };
+    let (wa, wb) = (words(&a.content), words(&b.content));
+    let union = wa.union(&wb).count();
+    if union == 0 {
+        return 0.0;
+    }
+    wa.intersection(&wb).count() as f32 / union as f32
+}
 
-            for term in &query_terms {
-                let term_lower = term.to_lowercase();
+/// Merge semantic and keyword results
+fn merge_results(
+    config: &RagConfig,
+    semantic: Vec<SearchResult>,
+    keyword: Vec<SearchResult>,
+) -> Vec<SearchResult> {
+    let mut scores: HashMap<String, (f32, f32)> = HashMap::new();
+    let mut chunks: HashMap<String, CodeChunk> = HashMap::new();
+
+    // Add semantic scores
+    for result in &semantic {
+        let key = format!("{}:{}", result.chunk.file_path, result.chunk.start_line);
+        scores.entry(key.clone()).or_insert((0.0, 0.0)).0 = result.score;
+        chunks.insert(key, result.chunk.clone());
+    }
 
-                // Boost for exact matches
-                if content_lower.contains(&term_lower) {
-                    boost += 0.1;
-                }
+    // Add keyword scores
+    for result in &keyword {
+        let key = format!("{}:{}", result.chunk.file_path, result.chunk.start_line);
+        scores.entry(key.clone()).or_insert((0.0, 0.0)).1 = result.score;
+        chunks.entry(key).or_insert(result.chunk.clone());
+    }
 
-                // Boost for term in function/class names
-                if is_in_definition(&result.chunk.content, &term_lower) {
-                    boost += 0.2;
-                }
+    // Combine scores
+    let semantic_weight = config.semantic_weight;
+    let keyword_weight = 1.0 - semantic_weight;
+
+    let mut results: Vec<SearchResult> = scores
+        .into_iter()
+        .map(|(key, (sem, kw))| {
+            let combined_score = semantic_weight * sem + keyword_weight * kw;
+            let match_type = if sem > 0.0 && kw > 0.0 {
+                MatchType::Hybrid
+            } else if sem > 0.0 {
+                MatchType::Semantic
+            } else {
+                MatchType::Keyword
+            };
+
+            SearchResult {
+                chunk: chunks.remove(&key).unwrap(),
+                score: combined_score,
+                match_type,
             }
+        })
+        .collect();
 
-            // Boost for smaller, more focused chunks
-            let size_factor = 1.0 / (1.0 + (result.chunk.content.len() as f32 / 5000.0));
-            boost += size_factor * 0.1;
+    results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    results
+}
+
+/// Rerank results based on query relevance
+fn rerank_results(config: &RagConfig, query: &str, mut results: Vec<SearchResult>) -> Vec<SearchResult> {
+    // Simple reranking based on query term density and position
+    let query_terms: Vec<&str> = query.split_whitespace().collect();
+
+    for result in &mut results {
+        let content_lower = result.chunk.content.to_lowercase();
+        let mut boost = 0.0;
+
+        for term in &query_terms {
+            let term_lower = term.to_lowercase();
 
-            result.score = (result.score + boost).min(1.0);
+            // Boost for exact matches
+            if content_lower.contains(&term_lower) {
+                boost += config.exact_match_boost;
+            }
+
+            // Boost for term in function/class names
+            if is_in_definition(&result.chunk.content, &term_lower) {
+                boost += config.definition_boost;
+            }
         }
 
-        results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
-        results
+        // Boost for smaller, more focused chunks
+        let size_factor = 1.0 / (1.0 + (result.chunk.content.len() as f32 / 5000.0));
+        boost += size_factor * config.size_boost;
+
+        result.score = (result.score + boost).min(1.0);
     }
 
-    /// Build context string from search results
-    pub fn build_context(&self, results: &[SearchResult], max_tokens: usize) -> String {
-        let mut context = String::new();
-        let mut token_count = 0;
+    results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    results
+}
 
-        for result in results {
-            // Approximate tokens as words / 0.75
-            let chunk_tokens = result.chunk.content.split_whitespace().count() * 4 / 3;
+/// Boost for a file used `count` times when the most used file has `max` uses
+///
+/// Logarithmic, so a handful of uses already counts and heavy use saturates.
+pub fn interaction_boost(config: &RagConfig, count: usize, max: usize) -> f32 {
+    if count == 0 || max == 0 {
+        return 0.0;
+    }
+    config.interaction_boost * ((1 + count) as f32).ln() / ((1 + max) as f32).ln()
+}
 
-            if token_count + chunk_tokens > max_tokens {
-                break;
-            }
+/// A query whose candidate results were labeled relevant/irrelevant by the user
+#[derive(Debug, Clone)]
+pub struct LabeledQuery {
+    pub query: String,
+    pub semantic: Vec<SearchResult>,
+    pub keyword: Vec<SearchResult>,
+    /// File paths the user marked as relevant
+    pub relevant: HashSet<String>,
+}
 
-            context.push_str(&format!(
-                "\n--- {} (lines {}-{}) ---\n{}\n",
-                result.chunk.file_path,
-                result.chunk.start_line,
-                result.chunk.end_line,
-                result.chunk.content
-            ));
+/// Result of a scoring calibration run
+#[derive(Debug, Clone)]
+pub struct TuneOutcome {
+    pub config: RagConfig,
+    /// Mean F1 of the best configuration
+    pub score: f32,
+    /// Mean F1 of the configuration we started from
+    pub baseline_score: f32,
+    pub evaluated: usize,
+}
 
-            token_count += chunk_tokens;
+/// Grid search `semantic_weight`, `min_similarity` and the rerank boosts
+/// against labeled queries, maximizing mean F1 over the returned files
+pub fn tune_config(base: &RagConfig, samples: &[LabeledQuery]) -> TuneOutcome {
+    let baseline_score = evaluate_config(base, samples);
+    let mut best = base.clone();
+    let mut best_score = baseline_score;
+    let mut evaluated = 0;
+
+    for weight_step in 0..=10 {
+        for min_sim_step in 0..=5 {
+            for &exact_match_boost in &[0.0, 0.05, 0.1, 0.2] {
+                for &definition_boost in &[0.0, 0.1, 0.2, 0.3] {
+                    let candidate = RagConfig {
+                        semantic_weight: weight_step as f32 / 10.0,
+                        min_similarity: min_sim_step as f32 / 10.0,
+                        exact_match_boost,
+                        definition_boost,
+                        ..base.clone()
+                    };
+
+                    let score = evaluate_config(&candidate, samples);
+                    evaluated += 1;
+
+                    if score > best_score {
+                        best_score = score;
+                        best = candidate;
+                    }
+                }
+            }
         }
+    }
 
-        context
+    TuneOutcome {
+        config: best,
+        score: best_score,
+        baseline_score,
+        evaluated,
     }
 }
 
+/// Mean F1 of a config over labeled queries
+fn evaluate_config(config: &RagConfig, samples: &[LabeledQuery]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+
+    let total: f32 = samples
+        .iter()
+        .map(|sample| {
+            let ranked = rank_candidates(
+                config,
+                &sample.query,
+                sample.semantic.clone(),
+                sample.keyword.clone(),
+            );
+            let returned: HashSet<&str> =
+                ranked.iter().map(|r| r.chunk.file_path.as_str()).collect();
+            f1_score(&returned, &sample.relevant)
+        })
+        .sum();
+
+    total / samples.len() as f32
+}
+
+fn f1_score(returned: &HashSet<&str>, relevant: &HashSet<String>) -> f32 {
+    if relevant.is_empty() {
+        // Nothing relevant exists: returning nothing is perfect
+        return if returned.is_empty() { 1.0 } else { 0.0 };
+    }
+
+    let hits = returned.iter().filter(|p| relevant.contains(**p)).count() as f32;
+    if hits == 0.0 {
+        return 0.0;
+    }
+
+    let precision = hits / returned.len() as f32;
+    let recall = hits / relevant.len() as f32;
+    2.0 * precision * recall / (precision + recall)
+}
+
 /// Find natural code boundaries (functions, classes)
 fn find_code_boundaries(lines: &[&str], language: &str) -> Vec<usize> {
     let mut boundaries = vec![0];
@@ -364,17 +707,23 @@ fn find_code_boundaries(lines: &[&str], language: &str) -> Vec<usize> {
         "rust" => vec!["fn ", "impl ", "struct ", "enum ", "trait ", "mod "],
         "python" => vec!["def ", "class ", "async def "],
         "javascript" | "typescript" => vec!["function ", "class ", "const ", "export "],
-        "java" | "kotlin" => vec!["public ", "private ", "protected ", "class ", "interface "],
+        "java" => vec!["public ", "private ", "protected ", "class ", "interface "],
         "go" => vec!["func ", "type ", "package "],
+        // Definitions that don't start with a fixed keyword (a C return type,
+        // modifiers before `fun`, shell's `name()`) are found like the index finds them
+        "c" | "cpp" | "csharp" | "ruby" | "php" | "swift" | "kotlin" | "shell" => Vec::new(),
         _ => vec!["fn ", "function ", "def ", "class "],
     };
 
     for (i, line) in lines.iter().enumerate() {
         let trimmed = line.trim();
-        if patterns.iter().any(|p| trimmed.starts_with(p)) {
-            if i > 0 {
-                boundaries.push(i);
-            }
+        let is_definition = if patterns.is_empty() {
+            CodebaseIndex::definition_at(trimmed, language).is_some()
+        } else {
+            patterns.iter().any(|p| trimmed.starts_with(p))
+        };
+        if is_definition && i > 0 {
+            boundaries.push(i);
         }
     }
 
@@ -382,6 +731,136 @@ fn find_code_boundaries(lines: &[&str], language: &str) -> Vec<usize> {
     boundaries
 }
 
+/// Config sections longer than this are split at their nested keys
+const MAX_SECTION_LINES: usize = 50;
+
+/// Start of each markdown section, a heading outside code fences, with the
+/// headings it falls under; ends with the line count like `find_code_boundaries`
+fn markdown_sections(lines: &[&str]) -> Vec<(usize, Option<String>)> {
+    let mut sections = vec![(0, None)];
+    // Enclosing headings as (level, title)
+    let mut headings: Vec<(usize, &str)> = Vec::new();
+    let mut in_fence = false;
+
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        let level = trimmed.chars().take_while(|&c| c == '#').count();
+        if in_fence || !(1..=6).contains(&level) {
+            continue;
+        }
+        let Some(title) = trimmed[level..].strip_prefix(' ') else {
+            continue;
+        };
+
+        headings.retain(|(enclosing, _)| *enclosing < level);
+        headings.push((level, title.trim()));
+        let path = headings.iter().map(|(_, title)| *title).collect::<Vec<_>>().join(" > ");
+        push_section(&mut sections, i, path);
+    }
+
+    sections.push((lines.len(), None));
+    sections
+}
+
+/// Start of each config section with its key path: `[table]` headers in TOML,
+/// top-level keys in YAML and JSON (nested keys too, for long sections)
+fn config_sections(lines: &[&str], language: &str) -> Vec<(usize, Option<String>)> {
+    let mut sections = vec![(0, None)];
+
+    if language == "toml" {
+        for (i, line) in lines.iter().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') && trimmed.ends_with(']') {
+                push_section(&mut sections, i, trimmed.trim_matches(['[', ']']).trim().to_string());
+            }
+        }
+    } else {
+        split_at_keys(lines, 0, lines.len(), language, &[], &mut sections);
+    }
+
+    sections.push((lines.len(), None));
+    sections
+}
+
+/// Add a section for each of the shallowest keys in `lines[start..end]`, splitting
+/// sections longer than `MAX_SECTION_LINES` again at the keys nested in them
+///
+/// The lines before the first key join the section already open (the file's
+/// first lines, or the parent key), so no chunk is a lone `services:` line.
+fn split_at_keys(
+    lines: &[&str],
+    start: usize,
+    end: usize,
+    language: &str,
+    parents: &[&str],
+    sections: &mut Vec<(usize, Option<String>)>,
+) {
+    let keys: Vec<(usize, usize, &str)> = (start..end)
+        .filter_map(|i| config_key(lines[i], language).map(|(indent, key)| (i, indent, key)))
+        .collect();
+    let Some(shallowest) = keys.iter().map(|(_, indent, _)| *indent).min() else {
+        return;
+    };
+    let keys: Vec<(usize, &str)> = keys
+        .into_iter()
+        .filter(|(_, indent, _)| *indent == shallowest)
+        .map(|(line, _, key)| (line, key))
+        .collect();
+
+    for (n, (line, key)) in keys.iter().enumerate() {
+        let section_end = keys.get(n + 1).map_or(end, |(next, _)| *next);
+        let mut path = parents.to_vec();
+        path.push(key);
+
+        match sections.last_mut() {
+            Some(open) if n == 0 => open.1 = Some(path.join(".")),
+            _ => sections.push((*line, Some(path.join(".")))),
+        }
+        if section_end - line > MAX_SECTION_LINES {
+            split_at_keys(lines, line + 1, section_end, language, &path, sections);
+        }
+    }
+}
+
+/// Indentation and name of the key a YAML or JSON line sets, if it sets one
+fn config_key<'a>(line: &'a str, language: &str) -> Option<(usize, &'a str)> {
+    let trimmed = line.trim_start();
+    let indent = line.len() - trimmed.len();
+
+    let key = if language == "json" {
+        let quoted = trimmed.strip_prefix('"')?;
+        &quoted[..quoted.find("\":")?]
+    } else {
+        if trimmed.starts_with(['#', '-', '{', '[']) {
+            return None;
+        }
+        let colon = trimmed.find(':')?;
+        let after = &trimmed[colon + 1..];
+        if !(after.is_empty() || after.starts_with(' ')) {
+            return None;
+        }
+        let key = trimmed[..colon].trim_matches(['"', '\'']);
+        if key.contains(' ') && !trimmed.starts_with(['"', '\'']) {
+            return None;
+        }
+        key
+    };
+    (!key.is_empty()).then_some((indent, key))
+}
+
+/// Start a section at `line`, or name the first one when the file begins with it
+fn push_section(sections: &mut Vec<(usize, Option<String>)>, line: usize, path: String) {
+    if line == 0 {
+        sections[0].1 = Some(path);
+    } else {
+        sections.push((line, Some(path)));
+    }
+}
+
 /// Calculate keyword match score
 fn calculate_keyword_score(content: &str, keywords: &[&str]) -> f32 {
     if keywords.is_empty() {
@@ -442,6 +921,7 @@ fn detect_language(path: &str) -> String {
         "swift" => "swift",
         "scala" => "scala",
         "cs" => "csharp",
+        "sh" | "bash" => "shell",
         _ => "unknown",
     }
     .to_string()
@@ -460,6 +940,54 @@ mod tests {
         assert!(!chunks.is_empty());
     }
 
+    #[test]
+    fn test_docs_and_configs_chunk_by_section() {
+        let retriever = RagRetriever::with_defaults();
+        let sections = |content: &str, language: &str| -> Vec<(usize, Option<String>)> {
+            retriever
+                .chunk_content(content, "f", language)
+                .into_iter()
+                .map(|chunk| (chunk.start_line, chunk.section))
+                .collect()
+        };
+        let named = |line: usize, path: &str| (line, Some(path.to_string()));
+
+        let markdown = "Intro\n# Guide\n## Install\n```sh\n# not a heading\n```\n## Usage\ntext\n# FAQ\n";
+        assert_eq!(sections(markdown, "markdown"), vec![
+            (1, None),
+            named(2, "Guide"),
+            named(3, "Guide > Install"),
+            named(7, "Guide > Usage"),
+            named(9, "FAQ"),
+        ]);
+
+        let toml = "name = \"x\"\n\n[server]\nport = 80\n[[server.routes]]\npath = \"/\"\n";
+        assert_eq!(sections(toml, "toml"), vec![(1, None), named(3, "server"), named(5, "server.routes")]);
+
+        // A long section is split at its nested keys, the parent key joining the first
+        let web = format!("  web:\n    image: nginx\n{}", "    # padding\n".repeat(MAX_SECTION_LINES - 2));
+        let yaml = format!("# compose file\nversion: '3'\nservices:\n{}  db:\n    image: postgres\n", web);
+        assert_eq!(sections(&yaml, "yaml"), vec![
+            named(1, "version"),
+            named(3, "services.web"),
+            named(MAX_SECTION_LINES + 4, "services.db"),
+        ]);
+
+        let json = "{\n  \"name\": \"app\",\n  \"scripts\": {\n    \"test\": \"jest\"\n  }\n}\n";
+        assert_eq!(sections(json, "json"), vec![named(1, "name"), named(3, "scripts")]);
+    }
+
+    #[test]
+    fn test_code_boundaries_for_more_languages() {
+        let c = "#include <stdio.h>\n\nstatic int add(int a, int b) {\n    return a + b;\n}\n\nint main(void) {\n    printf(\"%d\", add(1, 2));\n}\n";
+        let lines: Vec<&str> = c.lines().collect();
+        assert_eq!(find_code_boundaries(&lines, "c"), vec![0, 2, 6, lines.len()]);
+
+        let ruby = "module Shop\n  class Cart\n    def total\n      0\n    end\n  end\nend\n";
+        let lines: Vec<&str> = ruby.lines().collect();
+        assert_eq!(find_code_boundaries(&lines, "ruby"), vec![0, 1, 2, lines.len()]);
+    }
+
     #[test]
     fn test_keyword_score() {
         let content = "fn calculate_total(items: Vec<Item>) -> f32";
@@ -475,4 +1003,140 @@ mod tests {
         assert_eq!(detect_language("app.py"), "python");
         assert_eq!(detect_language("index.ts"), "typescript");
     }
+
+    #[test]
+    fn test_keyword_search_uses_fts_snippets() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let filler = "// filler\n".repeat(200);
+        std::fs::write(
+            root.path().join("cache.rs"),
+            format!("{}fn evict_entries(cache: &mut Cache) {{\n    cache.clear();\n}}\n{}", filler, filler),
+        )
+        .unwrap();
+        std::fs::write(root.path().join("other.rs"), "fn unrelated() {}\n").unwrap();
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+
+        let results = RagRetriever::with_defaults().keyword_search("evict_entries", &index).unwrap();
+        assert_eq!(results.len(), 1);
+
+        let chunk = &results[0].chunk;
+        assert!(chunk.file_path.ends_with("cache.rs"));
+        assert!(chunk.content.contains("fn evict_entries"));
+        assert!(chunk.start_line > 150 && chunk.end_line < 250);
+    }
+
+    #[test]
+    fn test_imported_files_added_as_context() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("src")).unwrap();
+        std::fs::write(root.path().join("src/main.rs"), "mod cache;
+use crate::cache::Cache;
+fn main() {}
+").unwrap();
+        std::fs::write(root.path().join("src/cache.rs"), "pub struct Cache;
+").unwrap();
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+        assert_eq!(index.dependencies("src/main.rs").unwrap(), vec!["src/cache.rs"]);
+        assert_eq!(index.dependents("src/cache.rs").unwrap(), vec!["src/main.rs"]);
+
+        let main_path = root.path().join("src/main.rs").to_string_lossy().to_string();
+        let results = RagRetriever::with_defaults()
+            .add_imported_files(&index, vec![hit(&main_path, "fn main() {}", 0.8, MatchType::Semantic)]);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].match_type, MatchType::Import);
+        assert!(results[1].chunk.file_path.ends_with("cache.rs"));
+        assert!((results[1].score - 0.4).abs() < 1e-6);
+
+        let context = RagRetriever::with_defaults().build_context(&results, 1000);
+        assert!(context.contains("main.rs (lines 1-1) ---\n```rust\nfn main() {}\n```"));
+        assert!(context.contains("cache.rs (lines 1-"));
+        assert!(context.contains(", imported by a match) ---\n```rust\n"));
+    }
+
+    #[test]
+    fn test_frequent_files_ranked_higher() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("a.rs"), "fn a() {}\n").unwrap();
+        std::fs::write(root.path().join("b.rs"), "fn b() {}\n").unwrap();
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+        for _ in 0..3 {
+            index.record_interaction("b.rs", "ask").unwrap();
+        }
+        assert_eq!(index.most_used_files(5).unwrap(), vec![("b.rs".to_string(), 3)]);
+
+        let path = |name: &str| root.path().join(name).to_string_lossy().to_string();
+        let results = RagRetriever::with_defaults().boost_frequent_files(
+            &index,
+            vec![
+                hit(&path("a.rs"), "fn a() {}", 0.5, MatchType::Keyword),
+                hit(&path("b.rs"), "fn b() {}", 0.45, MatchType::Keyword),
+            ],
+        );
+
+        assert!(results[0].chunk.file_path.ends_with("b.rs"));
+        assert!((results[0].score - 0.55).abs() < 1e-6);
+        assert!((results[1].score - 0.5).abs() < 1e-6);
+    }
+
+    fn hit(path: &str, content: &str, score: f32, match_type: MatchType) -> SearchResult {
+        SearchResult {
+            chunk: CodeChunk {
+                file_path: path.to_string(),
+                content: content.to_string(),
+                start_line: 1,
+                end_line: 1,
+                language: "rust".to_string(),
+                embedding: None,
+                section: None,
+            },
+            score,
+            match_type,
+        }
+    }
+
+    #[test]
+    fn test_diversify_spreads_results_across_files() {
+        let config = RagConfig {
+            top_k: 3,
+            ..RagConfig::default()
+        };
+        let candidates = vec![
+            hit("a.rs", "fn load() { read_file() }", 0.9, MatchType::Semantic),
+            hit("a.rs", "fn load_all() { read_file() }", 0.88, MatchType::Semantic),
+            hit("a.rs", "fn load_one() { read_file() }", 0.86, MatchType::Semantic),
+            hit("b.rs", "struct Cache { entries: Vec<Entry> }", 0.7, MatchType::Semantic),
+        ];
+
+        let picked = diversify(&config, candidates);
+        let paths: Vec<&str> = picked.iter().map(|r| r.chunk.file_path.as_str()).collect();
+        assert_eq!(picked.len(), 3);
+        assert_eq!(paths.iter().filter(|p| **p == "a.rs").count(), 2);
+        assert!(paths.contains(&"b.rs"));
+    }
+
+    #[test]
+    fn test_tune_config_improves_on_labels() {
+        // Semantic search likes the wrong file, keyword search finds the right one
+        let sample = LabeledQuery {
+            query: "parse diff".to_string(),
+            semantic: vec![hit("a.rs", "unrelated", 0.9, MatchType::Semantic)],
+            keyword: vec![hit("b.rs", "fn parse_diff() {}", 0.9, MatchType::Keyword)],
+            relevant: ["b.rs".to_string()].into_iter().collect(),
+        };
+
+        let outcome = tune_config(&RagConfig::default(), &[sample]);
+        assert!(outcome.score >= outcome.baseline_score);
+        assert!((outcome.score - 1.0).abs() < 0.001);
+        assert!(outcome.config.semantic_weight < 0.5);
+    }
 }
@@ -0,0 +1,238 @@
+//! Refactors that span files: the model names the symbols a change touches, and
+//! the symbol index finds every file that defines or uses them
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::consistency;
+use crate::llm::LlmClient;
+use crate::prompts::PromptLibrary;
+use crate::storage::{CodebaseIndex, Symbol};
+
+/// Most files one refactor changes; a larger change is better made in steps
+pub const MAX_REFACTOR_FILES: usize = 15;
+
+/// Indexed files listed to the planner, so the files it creates fit the layout
+const MAX_LISTED_FILES: usize = 200;
+
+const PLAN_SYSTEM_PROMPT: &str = r#"You plan a refactoring that spans several files of a codebase.
+Reply with JSON only: {"summary": "...", "symbols": ["..."], "new_files": ["..."]}
+- summary: the change in one or two sentences, precise enough to apply to each file on its own
+- symbols: names of the existing functions, types, methods and modules the change renames,
+  moves or changes the signature of
+- new_files: project-relative paths of files to create, or []"#;
+
+/// A multi-file change worked out before any file is edited
+#[derive(Debug, Clone)]
+pub struct RefactorPlan {
+    pub summary: String,
+    /// Existing symbols the change touches, each defined in the index
+    pub symbols: Vec<String>,
+    /// Files to change: those defining the symbols first, then those using them
+    pub files: Vec<AffectedFile>,
+    /// Project-relative paths of files to create
+    pub new_files: Vec<String>,
+}
+
+/// A file that defines or uses a symbol the refactor touches
+#[derive(Debug, Clone, PartialEq)]
+pub struct AffectedFile {
+    /// Absolute path, as stored in the index
+    pub path: String,
+    pub relative_path: String,
+    /// Lines defining or using the symbols
+    pub lines: Vec<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RawPlan {
+    #[serde(default)]
+    summary: String,
+    #[serde(default)]
+    symbols: Vec<String>,
+    #[serde(default)]
+    new_files: Vec<String>,
+}
+
+impl RefactorPlan {
+    /// Files the refactor writes, existing and new
+    pub fn file_count(&self) -> usize {
+        self.files.len() + self.new_files.len()
+    }
+
+    /// What to ask of the model for one file of the plan; `lines` are where the
+    /// symbols appear in it, empty for a new file
+    pub fn instruction_for(&self, instruction: &str, relative_path: &str, lines: &[usize]) -> String {
+        let mut text = format!(
+            "{}\n\nThe whole change: {}\nIt changes {} files; this is {}",
+            instruction,
+            self.summary,
+            self.file_count(),
+            relative_path
+        );
+        if lines.is_empty() {
+            text.push_str(", a new file. Write all of it.");
+        } else {
+            let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+            text.push_str(&format!(
+                ", where {} appear on lines {}. Change only this file.",
+                self.symbols.join(", "),
+                lines.join(", ")
+            ));
+        }
+        text
+    }
+}
+
+/// Work out the refactor `instruction` describes
+///
+/// Symbols the instruction names are looked up in the index directly; the model
+/// adds the ones it only implies ("the config loader") and names files to create.
+pub async fn plan(
+    index: &CodebaseIndex,
+    llm: &LlmClient,
+    prompts: &PromptLibrary,
+    instruction: &str,
+) -> Result<RefactorPlan> {
+    let named = named_symbols(index, instruction)?;
+    let mut prompt = format!("Refactoring to plan: {}\n", instruction);
+    if !named.is_empty() {
+        prompt.push_str("\nDefinitions it names:\n");
+        for symbol in &named {
+            prompt.push_str(&format!("- {}:{}: {}\n", symbol.relative_path, symbol.start_line, symbol.signature));
+        }
+    }
+    prompt.push_str("\nFiles in the project:\n");
+    for file in index.list_files(None, MAX_LISTED_FILES)? {
+        prompt.push_str(&format!("- {}\n", file.relative_path));
+    }
+
+    let system = prompts.system("refactor_plan", PLAN_SYSTEM_PROMPT);
+    let response = llm.generate_with(&prompt, Some(&system), &prompts.options("refactor_plan")).await?;
+    let raw = parse_plan(&response).unwrap_or_default();
+
+    let mut symbols: Vec<String> = Vec::new();
+    for symbol in &named {
+        if !symbols.contains(&symbol.name) {
+            symbols.push(symbol.name.clone());
+        }
+    }
+    for symbol in &raw.symbols {
+        // `Type::method` and `module.func` name their last segment
+        let name = symbol.rsplit([':', '.']).next().unwrap_or(symbol).trim();
+        if !name.is_empty() && !symbols.iter().any(|s| s == name) && !index.find_definitions(name)?.is_empty() {
+            symbols.push(name.to_string());
+        }
+    }
+
+    let mut new_files = Vec::new();
+    for path in raw.new_files.iter().map(|p| p.trim().trim_start_matches("./")) {
+        if !path.is_empty() && index.get_file(path)?.is_none() {
+            new_files.push(path.to_string());
+        }
+    }
+
+    Ok(RefactorPlan {
+        summary: if raw.summary.trim().is_empty() { instruction.to_string() } else { raw.summary.trim().to_string() },
+        files: affected_files(index, &symbols)?,
+        symbols,
+        new_files,
+    })
+}
+
+/// Indexed definitions of the identifiers `text` mentions: words in backticks, or
+/// with an underscore or a capital letter past the start of the sentence
+fn named_symbols(index: &CodebaseIndex, text: &str) -> Result<Vec<Symbol>> {
+    let mut found: Vec<Symbol> = Vec::new();
+    let words = text.split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '`')).filter(|w| !w.is_empty());
+    for (n, word) in words.enumerate() {
+        let quoted = word.len() > 2 && word.starts_with('`') && word.ends_with('`');
+        let word = word.trim_matches('`');
+        let looks_like_code = word.contains('_') || (n > 0 && word.chars().any(|c| c.is_uppercase()));
+        let starts_like_identifier = word.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_');
+        if !(quoted || looks_like_code) || !starts_like_identifier || found.iter().any(|s| s.name == word) {
+            continue;
+        }
+        found.extend(index.find_definitions(word)?);
+    }
+    Ok(found)
+}
+
+/// Files defining or using `symbols`: the defining files first, then the others by path
+pub fn affected_files(index: &CodebaseIndex, symbols: &[String]) -> Result<Vec<AffectedFile>> {
+    let mut files: Vec<AffectedFile> = Vec::new();
+    for symbol in symbols {
+        for definition in index.find_definitions(symbol)? {
+            add_line(&mut files, &definition.path, &definition.relative_path, definition.start_line);
+        }
+    }
+    let defining = files.len();
+
+    for symbol in symbols {
+        for reference in consistency::find_references(index, symbol)? {
+            if let Some(file) = index.get_file(&reference.file)? {
+                add_line(&mut files, &file.path, &file.relative_path, reference.line);
+            }
+        }
+    }
+
+    files[defining..].sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+    for file in &mut files {
+        file.lines.sort_unstable();
+    }
+    Ok(files)
+}
+
+fn add_line(files: &mut Vec<AffectedFile>, path: &str, relative_path: &str, line: usize) {
+    match files.iter_mut().find(|f| f.path == path) {
+        Some(file) if file.lines.contains(&line) => {}
+        Some(file) => file.lines.push(line),
+        None => files.push(AffectedFile {
+            path: path.to_string(),
+            relative_path: relative_path.to_string(),
+            lines: vec![line],
+        }),
+    }
+}
+
+fn parse_plan(response: &str) -> Option<RawPlan> {
+    let (start, end) = match (response.find('{'), response.rfind('}')) {
+        (Some(start), Some(end)) if start < end => (start, end),
+        _ => return None,
+    };
+    serde_json::from_str(&response[start..=end]).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_affected_files_start_with_the_definition() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("config.rs"), "pub fn load_config() {}\n\npub fn other() {}\n").unwrap();
+        std::fs::write(root.path().join("main.rs"), "fn main() {\n    other();\n    load_config();\n}\n").unwrap();
+        std::fs::write(root.path().join("app.rs"), "fn run() {\n    load_config();\n}\n").unwrap();
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+
+        let named = named_symbols(&index, "Rename `load_config` to read_config and keep Other as is").unwrap();
+        let names: Vec<&str> = named.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["load_config"]);
+
+        let files = affected_files(&index, &["load_config".to_string()]).unwrap();
+        let found: Vec<(&str, &[usize])> = files.iter().map(|f| (f.relative_path.as_str(), f.lines.as_slice())).collect();
+        assert_eq!(found, vec![("config.rs", &[1][..]), ("app.rs", &[2][..]), ("main.rs", &[3][..])]);
+
+        let plan = RefactorPlan {
+            summary: "Rename load_config to read_config.".to_string(),
+            symbols: vec!["load_config".to_string()],
+            files,
+            new_files: vec![],
+        };
+        assert!(plan
+            .instruction_for("rename it", "main.rs", &[3])
+            .ends_with("It changes 3 files; this is main.rs, where load_config appear on lines 3. Change only this file."));
+    }
+}
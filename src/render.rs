@@ -0,0 +1,381 @@
+use colored::Colorize;
+use serde::Serialize;
+use std::io::IsTerminal;
+
+/// Formats command output for the channel it is sent to
+///
+/// Agents return markdown; a renderer turns it into what the client shows:
+/// colours in a terminal, HTML in the web UI, or JSON for scripts.
+pub trait Renderer: Send + Sync {
+    /// Format a command's result
+    fn render(&self, output: &str) -> String;
+
+    /// Format a command's error
+    fn render_error(&self, error: &str) -> String;
+}
+
+/// Output format a client can ask for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Markdown as the agents produce it
+    Markdown,
+    /// Markdown markup removed
+    Plain,
+    /// Markdown shown with terminal colours
+    Ansi,
+    /// `{"text": ..., "code_blocks": [...]}`
+    Json,
+    /// An HTML fragment
+    Html,
+}
+
+impl OutputFormat {
+    pub const NAMES: [&'static str; 5] = ["markdown", "plain", "ansi", "json", "html"];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "markdown" | "md" => Some(Self::Markdown),
+            "plain" | "text" => Some(Self::Plain),
+            "ansi" => Some(Self::Ansi),
+            "json" => Some(Self::Json),
+            "html" => Some(Self::Html),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Markdown => "markdown",
+            Self::Plain => "plain",
+            Self::Ansi => "ansi",
+            Self::Json => "json",
+            Self::Html => "html",
+        }
+    }
+
+    /// Parse a format a client sent, or use `default` when it sent none
+    pub fn requested(name: Option<&str>, default: Self) -> Result<Self, String> {
+        match name {
+            None => Ok(default),
+            Some(name) => Self::parse(name).ok_or_else(|| {
+                format!("Unknown format '{}' (expected one of: {})", name, Self::NAMES.join(", "))
+            }),
+        }
+    }
+
+    /// The CLI's default: colours on a terminal, markdown when piped
+    pub fn for_terminal() -> Self {
+        if std::io::stdout().is_terminal() {
+            Self::Ansi
+        } else {
+            Self::Markdown
+        }
+    }
+
+    pub fn renderer(&self) -> Box<dyn Renderer> {
+        match self {
+            Self::Markdown => Box::new(MarkdownRenderer),
+            Self::Plain => Box::new(PlainRenderer),
+            Self::Ansi => Box::new(AnsiRenderer),
+            Self::Json => Box::new(JsonRenderer),
+            Self::Html => Box::new(HtmlRenderer),
+        }
+    }
+}
+
+pub struct MarkdownRenderer;
+
+impl Renderer for MarkdownRenderer {
+    fn render(&self, output: &str) -> String {
+        output.to_string()
+    }
+
+    fn render_error(&self, error: &str) -> String {
+        format!("Error: {}", error)
+    }
+}
+
+pub struct PlainRenderer;
+
+impl Renderer for PlainRenderer {
+    fn render(&self, output: &str) -> String {
+        let mut lines = Vec::new();
+        for block in blocks(output) {
+            match block {
+                Block::Code { code, .. } => lines.extend(code.lines().map(|line| format!("    {}", line))),
+                Block::Heading { text, .. } => lines.push(inline(text, &|code| code.to_string(), &|bold| bold.to_string())),
+                Block::Text(text) => lines.push(inline(text, &|code| code.to_string(), &|bold| bold.to_string())),
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn render_error(&self, error: &str) -> String {
+        format!("Error: {}", error)
+    }
+}
+
+pub struct AnsiRenderer;
+
+impl Renderer for AnsiRenderer {
+    fn render(&self, output: &str) -> String {
+        let mut lines = Vec::new();
+        for block in blocks(output) {
+            match block {
+                Block::Code { language, code } => {
+                    if !language.is_empty() {
+                        lines.push(format!("  {}", language.bright_black()));
+                    }
+                    if language == "diff" {
+                        lines.extend(code.lines().map(|line| format!("  {}", diff_line(line))));
+                    } else {
+                        lines.extend(code.lines().map(|line| format!("  {}", line.green())));
+                    }
+                }
+                Block::Heading { text, .. } => lines.push(text.bold().cyan().to_string()),
+                Block::Text(text) => lines.push(inline(
+                    text,
+                    &|code| code.cyan().to_string(),
+                    &|bold| bold.bold().to_string(),
+                )),
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn render_error(&self, error: &str) -> String {
+        format!("Error: {}", error).red().to_string()
+    }
+}
+
+/// A line of a unified diff coloured by what it does
+fn diff_line(line: &str) -> String {
+    if line.starts_with("+++") || line.starts_with("---") {
+        line.bold().to_string()
+    } else if line.starts_with('+') {
+        line.green().to_string()
+    } else if line.starts_with('-') {
+        line.red().to_string()
+    } else if line.starts_with("@@") {
+        line.cyan().to_string()
+    } else {
+        line.to_string()
+    }
+}
+
+pub struct JsonRenderer;
+
+#[derive(Serialize)]
+struct CodeBlock<'a> {
+    language: &'a str,
+    code: String,
+}
+
+impl Renderer for JsonRenderer {
+    fn render(&self, output: &str) -> String {
+        let code_blocks: Vec<CodeBlock> = blocks(output)
+            .into_iter()
+            .filter_map(|block| match block {
+                Block::Code { language, code } => Some(CodeBlock { language, code }),
+                _ => None,
+            })
+            .collect();
+        serde_json::json!({ "text": output, "code_blocks": code_blocks }).to_string()
+    }
+
+    fn render_error(&self, error: &str) -> String {
+        serde_json::json!({ "error": error }).to_string()
+    }
+}
+
+pub struct HtmlRenderer;
+
+impl Renderer for HtmlRenderer {
+    fn render(&self, output: &str) -> String {
+        let mut html = String::new();
+        let mut paragraph: Vec<String> = Vec::new();
+        let mut list: Vec<String> = Vec::new();
+
+        let inline_html = |text: &str| {
+            inline(
+                &escape_html(text),
+                &|code| format!("<code>{}</code>", code),
+                &|bold| format!("<strong>{}</strong>", bold),
+            )
+        };
+
+        for block in blocks(output) {
+            let item = match block {
+                Block::Text(text) => list_item(text),
+                _ => None,
+            };
+            if item.is_none() && !list.is_empty() {
+                html.push_str(&format!("<ul>{}</ul>\n", list.join("")));
+                list.clear();
+            }
+            let ends_paragraph = !matches!(block, Block::Text(text) if item.is_none() && !text.trim().is_empty());
+            if ends_paragraph && !paragraph.is_empty() {
+                html.push_str(&format!("<p>{}</p>\n", paragraph.join("<br>")));
+                paragraph.clear();
+            }
+
+            match block {
+                Block::Code { language, code } => {
+                    let class = if language.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" class=\"language-{}\"", escape_html(language))
+                    };
+                    html.push_str(&format!("<pre><code{}>{}</code></pre>\n", class, escape_html(&code)));
+                }
+                Block::Heading { level, text } => {
+                    html.push_str(&format!("<h{0}>{1}</h{0}>\n", level, inline_html(text)));
+                }
+                Block::Text(text) => match item {
+                    Some(item) => list.push(format!("<li>{}</li>", inline_html(item))),
+                    None if !text.trim().is_empty() => paragraph.push(inline_html(text.trim())),
+                    None => {}
+                },
+            }
+        }
+
+        if !list.is_empty() {
+            html.push_str(&format!("<ul>{}</ul>\n", list.join("")));
+        }
+        if !paragraph.is_empty() {
+            html.push_str(&format!("<p>{}</p>\n", paragraph.join("<br>")));
+        }
+        html
+    }
+
+    fn render_error(&self, error: &str) -> String {
+        format!("<p class=\"error\">{}</p>", escape_html(error))
+    }
+}
+
+/// A line-level piece of markdown
+enum Block<'a> {
+    Code { language: &'a str, code: String },
+    Heading { level: usize, text: &'a str },
+    Text(&'a str),
+}
+
+/// Split markdown into fenced code blocks, headings and other lines
+fn blocks(markdown: &str) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+    // Inside a fence: its language and the code so far
+    let mut fence: Option<(&str, String)> = None;
+
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        match fence.take() {
+            Some((language, code)) if trimmed.starts_with("```") => blocks.push(Block::Code { language, code }),
+            Some((language, code)) => fence = Some((language, code + line + "\n")),
+            None => {
+                if let Some(language) = trimmed.strip_prefix("```") {
+                    fence = Some((language.trim(), String::new()));
+                } else if let Some((level, text)) = heading(trimmed) {
+                    blocks.push(Block::Heading { level, text });
+                } else {
+                    blocks.push(Block::Text(line));
+                }
+            }
+        }
+    }
+
+    // An unclosed fence still holds code
+    if let Some((language, code)) = fence {
+        blocks.push(Block::Code { language, code });
+    }
+    blocks
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    let text = line[level..].strip_prefix(' ')?;
+    (1..=6).contains(&level).then(|| (level, text.trim()))
+}
+
+fn list_item(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    trimmed
+        .strip_prefix("- ")
+        .or_else(|| trimmed.strip_prefix("* "))
+        .or_else(|| {
+            let digits = trimmed.chars().take_while(|c| c.is_ascii_digit()).count();
+            (digits > 0).then(|| trimmed[digits..].strip_prefix(". ")).flatten()
+        })
+}
+
+/// Apply `code` to `` `spans` `` and `bold` to `**spans**`; unmatched markers are kept
+fn inline(text: &str, code: &dyn Fn(&str) -> String, bold: &dyn Fn(&str) -> String) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+
+    loop {
+        let next_code = rest.find('`');
+        let next_bold = rest.find("**");
+        let (start, marker) = match (next_code, next_bold) {
+            (Some(c), Some(b)) if b < c => (b, "**"),
+            (Some(c), _) => (c, "`"),
+            (None, Some(b)) => (b, "**"),
+            (None, None) => break,
+        };
+
+        let after = &rest[start + marker.len()..];
+        let Some(end) = after.find(marker) else { break };
+        result.push_str(&rest[..start]);
+        let span = &after[..end];
+        result.push_str(&if marker == "`" { code(span) } else { bold(&inline(span, code, bold)) });
+        rest = &after[end + marker.len()..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANSWER: &str = "## Summary\n\nCall **`parse`** first.\n\n- one\n- two\n\n```rust\nfn parse() -> Vec<u8> {}\n```";
+
+    #[test]
+    fn test_renderers_format_markdown() {
+        assert_eq!(OutputFormat::Markdown.renderer().render(ANSWER), ANSWER);
+
+        assert_eq!(
+            OutputFormat::Plain.renderer().render(ANSWER),
+            "Summary\n\nCall parse first.\n\n- one\n- two\n\n    fn parse() -> Vec<u8> {}"
+        );
+
+        assert_eq!(
+            OutputFormat::Html.renderer().render(ANSWER),
+            "<h2>Summary</h2>\n<p>Call <strong><code>parse</code></strong> first.</p>\n\
+             <ul><li>one</li><li>two</li></ul>\n\
+             <pre><code class=\"language-rust\">fn parse() -&gt; Vec&lt;u8&gt; {}\n</code></pre>\n"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&OutputFormat::Json.renderer().render(ANSWER)).unwrap();
+        assert_eq!(json["text"], ANSWER);
+        assert_eq!(json["code_blocks"][0]["language"], "rust");
+        assert_eq!(json["code_blocks"][0]["code"], "fn parse() -> Vec<u8> {}\n");
+    }
+
+    #[test]
+    fn test_format_names() {
+        for name in OutputFormat::NAMES {
+            assert_eq!(OutputFormat::parse(name).unwrap().as_str(), name);
+        }
+        assert_eq!(OutputFormat::requested(None, OutputFormat::Html), Ok(OutputFormat::Html));
+        assert!(OutputFormat::requested(Some("yaml"), OutputFormat::Html).is_err());
+        assert_eq!(inline("a `b` and **c", &|s| s.to_uppercase(), &|s| s.to_string()), "a B and **c");
+    }
+}
@@ -0,0 +1,587 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::git::DiffHunk;
+use crate::storage::CodebaseIndex;
+
+/// Rule id for findings judged by the LLM reviewer
+pub const LLM_REVIEW_RULE: &str = "sovereign/llm-review";
+
+/// Longest enclosing definition shown whole with a hunk; longer ones are named by signature
+const MAX_CONTEXT_LINES: usize = 60;
+
+/// How a rule produces findings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleSource {
+    /// Deterministic local pattern check
+    Pattern,
+    /// Judged by the language model
+    Llm,
+}
+
+impl RuleSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuleSource::Pattern => "local-pattern",
+            RuleSource::Llm => "llm",
+        }
+    }
+}
+
+/// Metadata for a review rule, reported in SARIF `tool.driver.rules`
+#[derive(Debug, Clone, Copy)]
+pub struct Rule {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub severity: Severity,
+    pub source: RuleSource,
+}
+
+/// Every rule Sovereign can report
+pub const RULES: &[Rule] = &[
+    Rule {
+        id: LLM_REVIEW_RULE,
+        name: "LlmReview",
+        description: "Issue identified by the model while reviewing the change",
+        severity: Severity::Warning,
+        source: RuleSource::Llm,
+    },
+    Rule {
+        id: "sovereign/hardcoded-secret",
+        name: "HardcodedSecret",
+        description: "Credential or private key committed as a literal",
+        severity: Severity::Error,
+        source: RuleSource::Pattern,
+    },
+    Rule {
+        id: "sovereign/dynamic-eval",
+        name: "DynamicEval",
+        description: "Code evaluated from a string at runtime",
+        severity: Severity::Warning,
+        source: RuleSource::Pattern,
+    },
+    Rule {
+        id: "sovereign/sql-string-building",
+        name: "SqlStringBuilding",
+        description: "SQL statement built with string formatting instead of bound parameters",
+        severity: Severity::Warning,
+        source: RuleSource::Pattern,
+    },
+    Rule {
+        id: "sovereign/injection",
+        name: "Injection",
+        description: "Untrusted input built into a query, command line or markup",
+        severity: Severity::Error,
+        source: RuleSource::Llm,
+    },
+    Rule {
+        id: "sovereign/unsafe-deserialization",
+        name: "UnsafeDeserialization",
+        description: "Untrusted data deserialized into objects or code",
+        severity: Severity::Error,
+        source: RuleSource::Llm,
+    },
+    Rule {
+        id: "sovereign/path-traversal",
+        name: "PathTraversal",
+        description: "File path built from untrusted input without confining it to a directory",
+        severity: Severity::Warning,
+        source: RuleSource::Llm,
+    },
+    Rule {
+        id: "sovereign/debug-output",
+        name: "DebugOutput",
+        description: "Leftover debugging statement",
+        severity: Severity::Note,
+        source: RuleSource::Pattern,
+    },
+    Rule {
+        id: "sovereign/unwrap",
+        name: "Unwrap",
+        description: "`.unwrap()` panics on error; prefer propagating it",
+        severity: Severity::Note,
+        source: RuleSource::Pattern,
+    },
+    Rule {
+        id: "sovereign/todo",
+        name: "Todo",
+        description: "New TODO/FIXME marker",
+        severity: Severity::Note,
+        source: RuleSource::Pattern,
+    },
+];
+
+/// Look up a rule by id
+pub fn rule(id: &str) -> Option<&'static Rule> {
+    RULES.iter().find(|r| r.id == id)
+}
+
+/// Severity of a review finding, ordered from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "note" | "info" | "suggestion" => Some(Severity::Note),
+            "warning" | "warn" => Some(Severity::Warning),
+            "error" | "critical" | "high" => Some(Severity::Error),
+            _ => None,
+        }
+    }
+}
+
+/// Report format for `sovereign ci-review` and `sovereign audit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Markdown,
+    Sarif,
+}
+
+impl ReportFormat {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "markdown" | "md" => Some(ReportFormat::Markdown),
+            "sarif" => Some(ReportFormat::Sarif),
+            _ => None,
+        }
+    }
+}
+
+/// Settings for a non-interactive CI review run
+#[derive(Debug, Clone)]
+pub struct CiReviewOptions {
+    /// Ref the current HEAD is compared against
+    pub base: String,
+    pub format: ReportFormat,
+    /// Fail the run if any finding is at least this severe (`None` never fails)
+    pub fail_on: Option<Severity>,
+    /// Total time budget for model calls
+    pub timeout: std::time::Duration,
+    /// Maximum number of hunks sent to the model
+    pub max_hunks: usize,
+    /// Only run the local pattern rules
+    pub local_only: bool,
+}
+
+impl CiReviewOptions {
+    /// Whether the findings should fail the CI job
+    pub fn should_fail(&self, findings: &[Finding]) -> bool {
+        match self.fail_on {
+            Some(threshold) => findings.iter().any(|f| f.severity >= threshold),
+            None => false,
+        }
+    }
+}
+
+/// A single review finding anchored to a file and line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Finding {
+    pub rule_id: String,
+    pub severity: Severity,
+    pub file: String,
+    pub line: u32,
+    pub message: String,
+}
+
+/// Raw finding as the model is asked to produce it
+#[derive(Debug, Deserialize)]
+struct RawFinding {
+    line: Option<u32>,
+    severity: Option<String>,
+    message: String,
+}
+
+/// Render a hunk with new-file line numbers so the model can cite exact lines
+pub fn numbered_hunk(hunk: &DiffHunk) -> String {
+    let mut output = String::new();
+    let mut line_no = hunk.new_start;
+
+    for line in hunk.content.lines() {
+        if line.starts_with('-') {
+            output.push_str(&format!("{:>6} {}\n", "", line));
+        } else {
+            output.push_str(&format!("{:>6} {}\n", line_no, line));
+            line_no += 1;
+        }
+    }
+
+    output
+}
+
+/// What the index holds around a hunk of `path`: the symbols the file defines and
+/// the definitions the changed lines fall in
+pub fn hunk_context(index: &CodebaseIndex, path: &str, hunk: &DiffHunk) -> Result<Option<String>> {
+    let Some(file) = index.get_file(path)? else {
+        return Ok(None);
+    };
+    let mut context = String::new();
+    if !file.symbols.is_empty() {
+        context.push_str(&format!("Symbols defined in this file: {}\n", file.symbols.join(", ")));
+    }
+
+    let content = index.get_file_content(&file.path)?.unwrap_or_default();
+    let lines: Vec<&str> = content.lines().collect();
+    let first = hunk.new_start as usize;
+    let last = first + (hunk.new_count as usize).saturating_sub(1);
+    // Definitions nested in one already shown whole are left out
+    let mut shown_until = 0;
+    for symbol in index.file_symbols(&file.path)? {
+        if symbol.start_line > last || symbol.end_line < first || symbol.end_line <= shown_until {
+            continue;
+        }
+        let length = symbol.end_line + 1 - symbol.start_line;
+        if length <= MAX_CONTEXT_LINES && symbol.end_line <= lines.len() {
+            context.push_str(&format!(
+                "\nThe change is in this definition (lines {}-{}):\n```\n{}\n```\n",
+                symbol.start_line,
+                symbol.end_line,
+                lines[symbol.start_line - 1..symbol.end_line].join("\n")
+            ));
+            shown_until = symbol.end_line;
+        } else {
+            context.push_str(&format!(
+                "\nThe change is inside `{}` (lines {}-{})\n",
+                symbol.signature, symbol.start_line, symbol.end_line
+            ));
+        }
+    }
+
+    Ok((!context.is_empty()).then(|| context.trim_end().to_string()))
+}
+
+/// Parse the JSON array of findings the model returned for one hunk
+///
+/// Lines outside the hunk are clamped to its first line so annotations
+/// always land on changed code.
+pub fn parse_findings(response: &str, hunk: &DiffHunk) -> Vec<Finding> {
+    let (start, end) = match (response.find('['), response.rfind(']')) {
+        (Some(start), Some(end)) if start < end => (start, end),
+        _ => return Vec::new(),
+    };
+
+    let raw: Vec<RawFinding> = match serde_json::from_str(&response[start..=end]) {
+        Ok(raw) => raw,
+        Err(_) => return Vec::new(),
+    };
+
+    let last_line = hunk.new_start + hunk.new_count.saturating_sub(1);
+
+    raw.into_iter()
+        .filter(|f| !f.message.trim().is_empty())
+        .map(|f| {
+            let line = f
+                .line
+                .filter(|l| *l >= hunk.new_start && *l <= last_line)
+                .unwrap_or(hunk.new_start);
+
+            Finding {
+                rule_id: LLM_REVIEW_RULE.to_string(),
+                severity: f
+                    .severity
+                    .as_deref()
+                    .and_then(Severity::from_str)
+                    .unwrap_or(Severity::Warning),
+                file: hunk.file_path.clone(),
+                line,
+                message: f.message.trim().to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Run the local pattern rules over the lines a hunk adds
+pub fn check_hunk(hunk: &DiffHunk) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut line_no = hunk.new_start;
+
+    for line in hunk.content.lines() {
+        if line.starts_with('-') {
+            continue;
+        }
+        if let Some(added) = line.strip_prefix('+') {
+            findings.extend(check_line(&hunk.file_path, line_no, added));
+        }
+        line_no += 1;
+    }
+
+    findings
+}
+
+/// Run the local pattern rules over a single line of code
+pub fn check_line(file: &str, line_no: u32, text: &str) -> Vec<Finding> {
+    let trimmed = text.trim();
+    // ASCII lowering keeps byte offsets aligned with `trimmed`
+    let lower = trimmed.to_ascii_lowercase();
+    let extension = file.rsplit('.').next().unwrap_or("");
+    let is_test_file = file.contains("test") || file.contains("spec");
+
+    let mut hits: Vec<(&str, String)> = Vec::new();
+
+    if looks_like_secret(trimmed, &lower) {
+        hits.push(("sovereign/hardcoded-secret", "Possible hardcoded credential".to_string()));
+    }
+
+    if matches!(extension, "js" | "ts" | "jsx" | "tsx" | "py") && (lower.contains("eval(") || lower.contains("exec(")) {
+        hits.push(("sovereign/dynamic-eval", "Avoid evaluating code from strings".to_string()));
+    }
+
+    let sql_keywords = ["select ", "insert into", "update ", "delete from"];
+    let formats = ["format!(", "f\"", "f'", "${", "\" +", "' +", "%s"];
+    if sql_keywords.iter().any(|k| lower.contains(k)) && formats.iter().any(|f| lower.contains(f)) {
+        hits.push(("sovereign/sql-string-building", "Use bound parameters instead of formatting SQL".to_string()));
+    }
+
+    if trimmed.contains("dbg!(") || trimmed.contains("console.log(") || trimmed == "debugger;" {
+        hits.push(("sovereign/debug-output", "Debugging statement left in".to_string()));
+    }
+
+    if extension == "rs" && !is_test_file && trimmed.contains(".unwrap()") && !trimmed.starts_with("//") {
+        hits.push(("sovereign/unwrap", "`.unwrap()` will panic on error".to_string()));
+    }
+
+    if trimmed.contains("TODO") || trimmed.contains("FIXME") {
+        hits.push(("sovereign/todo", trimmed.chars().take(120).collect()));
+    }
+
+    hits.into_iter()
+        .filter_map(|(id, message)| {
+            rule(id).map(|r| Finding {
+                rule_id: r.id.to_string(),
+                severity: r.severity,
+                file: file.to_string(),
+                line: line_no,
+                message,
+            })
+        })
+        .collect()
+}
+
+fn looks_like_secret(trimmed: &str, lower: &str) -> bool {
+    if trimmed.contains("-----BEGIN") && trimmed.contains("PRIVATE KEY") {
+        return true;
+    }
+
+    // AWS access key ids
+    if let Some(pos) = trimmed.find("AKIA") {
+        let id: String = trimmed[pos..].chars().take_while(|c| c.is_ascii_alphanumeric()).collect();
+        if id.len() == 20 {
+            return true;
+        }
+    }
+
+    // name = "long literal" where the name suggests a credential
+    let names = ["api_key", "apikey", "secret", "password", "passwd", "token"];
+    let Some(name_pos) = names.iter().filter_map(|n| lower.find(n)).min() else {
+        return false;
+    };
+    let rest = &trimmed[name_pos..];
+    let Some(assign) = rest.find(['=', ':']) else {
+        return false;
+    };
+    let value = rest[assign + 1..].trim_start_matches(['=', ' ', '>']);
+    let Some(quote) = value.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+        return false;
+    };
+    let literal: String = value[1..].chars().take_while(|c| *c != quote).collect();
+
+    literal.len() >= 8 && !literal.contains(' ') && !literal.contains("${") && !literal.starts_with('<')
+}
+
+/// Render findings as a SARIF 2.1.0 log
+pub fn to_sarif(findings: &[Finding], version: &str) -> Result<String> {
+    let mut rule_ids: Vec<&str> = findings.iter().map(|f| f.rule_id.as_str()).collect();
+    rule_ids.sort();
+    rule_ids.dedup();
+
+    let rules: Vec<_> = rule_ids
+        .iter()
+        .map(|id| match rule(id) {
+            Some(r) => json!({
+                "id": r.id,
+                "name": r.name,
+                "shortDescription": { "text": r.description },
+                "defaultConfiguration": { "level": r.severity.as_str() },
+                "properties": { "tags": [r.source.as_str()] }
+            }),
+            None => json!({ "id": id }),
+        })
+        .collect();
+
+    let results: Vec<_> = findings
+        .iter()
+        .map(|f| {
+            json!({
+                "ruleId": f.rule_id,
+                "level": f.severity.as_str(),
+                "message": { "text": f.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": f.file },
+                        "region": { "startLine": f.line }
+                    }
+                }]
+            })
+        })
+        .collect();
+
+    let log = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "sovereign",
+                    "version": version,
+                    "rules": rules
+                }
+            },
+            "results": results
+        }]
+    });
+
+    Ok(serde_json::to_string_pretty(&log)?)
+}
+
+/// Render findings as a Markdown report under `title`, suitable for a PR comment
+pub fn to_markdown(title: &str, findings: &[Finding], notes: &[String]) -> String {
+    let mut output = format!("## {}\n\n", title);
+
+    if findings.is_empty() {
+        output.push_str("No findings.\n");
+    } else {
+        output.push_str("| Severity | Location | Finding |\n|----------|----------|---------|\n");
+        for f in findings {
+            output.push_str(&format!(
+                "| {} | `{}:{}` | {} |\n",
+                f.severity.as_str(),
+                f.file,
+                f.line,
+                f.message.replace('|', "\\|").replace('\n', " ")
+            ));
+        }
+    }
+
+    if !notes.is_empty() {
+        output.push('\n');
+        for note in notes {
+            output.push_str(&format!("> {}\n", note));
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hunk() -> DiffHunk {
+        DiffHunk {
+            file_path: "src/lib.rs".to_string(),
+            old_start: 10,
+            old_count: 2,
+            new_start: 10,
+            new_count: 3,
+            content: " fn a() {}\n-fn b() {}\n+fn b(x: u32) {}\n+fn c() {}\n".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_numbered_hunk_uses_new_file_lines() {
+        let numbered = numbered_hunk(&hunk());
+        assert!(numbered.contains("    10  fn a() {}"));
+        assert!(numbered.contains("    11 +fn b(x: u32) {}"));
+        assert!(numbered.contains("    12 +fn c() {}"));
+    }
+
+    #[test]
+    fn test_check_line_patterns() {
+        let ids = |file: &str, text: &str| -> Vec<String> {
+            check_line(file, 1, text).into_iter().map(|f| f.rule_id).collect()
+        };
+
+        assert_eq!(ids("src/config.rs", r#"let api_key = "sk-9f8e7d6c5b4a";"#), vec!["sovereign/hardcoded-secret"]);
+        assert!(ids("src/config.rs", r#"let api_key = std::env::var("API_KEY")?;"#).is_empty());
+        assert_eq!(ids("src/db.rs", r#"let q = format!("SELECT * FROM t WHERE id = {}", id);"#), vec!["sovereign/sql-string-building"]);
+        assert_eq!(ids("src/main.rs", "let x = parse(s).unwrap();"), vec!["sovereign/unwrap"]);
+        assert!(ids("tests/parse_test.rs", "let x = parse(s).unwrap();").is_empty());
+    }
+
+    #[test]
+    fn test_sarif_includes_rule_metadata() {
+        let findings = check_hunk(&hunk());
+        assert!(findings.is_empty());
+
+        let finding = Finding {
+            rule_id: "sovereign/todo".to_string(),
+            severity: Severity::Note,
+            file: "src/lib.rs".to_string(),
+            line: 3,
+            message: "TODO".to_string(),
+        };
+        let sarif: serde_json::Value = serde_json::from_str(&to_sarif(&[finding], "0.0.0").unwrap()).unwrap();
+        let rule = &sarif["runs"][0]["tool"]["driver"]["rules"][0];
+        assert_eq!(rule["id"], "sovereign/todo");
+        assert_eq!(rule["properties"]["tags"][0], "local-pattern");
+    }
+
+    #[test]
+    fn test_hunk_context_shows_the_enclosing_definition() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(
+            root.path().join("lib.rs"),
+            "pub struct Store {}\n\nimpl Store {\n    pub fn get(&self) -> u32 {\n        1\n    }\n}\n",
+        )
+        .unwrap();
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+
+        let change = DiffHunk {
+            file_path: "lib.rs".to_string(),
+            old_start: 5,
+            old_count: 1,
+            new_start: 5,
+            new_count: 1,
+            content: "-        0\n+        1\n".to_string(),
+        };
+        let context = hunk_context(&index, "lib.rs", &change).unwrap().unwrap();
+        assert!(context.starts_with("Symbols defined in this file: "), "{}", context);
+        assert!(
+            context.ends_with("The change is in this definition (lines 3-7):\n```\nimpl Store {\n    pub fn get(&self) -> u32 {\n        1\n    }\n}\n```"),
+            "{}",
+            context
+        );
+        assert!(hunk_context(&index, "missing.rs", &change).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_parse_findings_clamps_lines() {
+        let response = r#"Here you go:
+[{"line": 11, "severity": "error", "message": "unused parameter"},
+ {"line": 99, "severity": "nit", "message": "naming"}]"#;
+        let findings = parse_findings(response, &hunk());
+
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].line, 11);
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert_eq!(findings[1].line, 10);
+        assert_eq!(findings[1].severity, Severity::Warning);
+    }
+}
@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+
+use crate::llm::{OllamaClient, Servers};
+use crate::prompts::PromptLibrary;
+use crate::storage::memory::MemoryType;
+
+/// Characters of a memory shown to the model
+const MAX_SCORED_CHARS: usize = 1500;
+
+/// Topic tags kept from one reply
+const MAX_TOPICS: usize = 3;
+
+const SCORE_SYSTEM_PROMPT: &str = r#"You rate notes a coding assistant keeps about its user and their projects.
+Reply with JSON only: {"importance": 0.0-1.0, "topics": ["..."], "language": "..."}
+- importance: how useful the note will be in later sessions. Decisions, conventions
+  and facts about the project score high; small talk and one-off lookups score low.
+- topics: up to three lowercase words or short phrases naming what it is about.
+- language: the programming language it concerns, lowercase, or null."#;
+
+/// `[scoring]` section of `sovereign.toml`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScoringConfig {
+    /// Ask a model to score and tag each memory as it is stored
+    pub enabled: bool,
+    /// Ollama model to ask; a small one keeps chat responsive (default: the chat model)
+    pub model: Option<String>,
+}
+
+/// Importance and tags for a new memory
+#[derive(Debug, Clone, PartialEq)]
+pub struct Score {
+    pub importance: f32,
+    /// `topic:` and `lang:` tags, added to the ones found in the text
+    pub tags: Vec<String>,
+}
+
+impl Score {
+    /// Add these tags to `tags`, skipping ones already there
+    pub fn add_tags(&self, tags: &mut Vec<String>) {
+        for tag in &self.tags {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RawScore {
+    importance: Option<f32>,
+    #[serde(default)]
+    topics: Vec<String>,
+    language: Option<String>,
+}
+
+/// Scores memories with a small LLM pass as they are created, so retrieval and
+/// pruning go by what a memory says rather than which agent stored it
+#[derive(Debug, Clone)]
+pub struct MemoryScorer {
+    llm: OllamaClient,
+    prompts: PromptLibrary,
+}
+
+impl MemoryScorer {
+    /// A scorer if `[scoring]` is enabled; `chat_model` is asked unless another model is set
+    pub fn from_config(config: &ScoringConfig, chat_model: &str, servers: &Servers, prompts: PromptLibrary) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let model = config.model.as_deref().unwrap_or(chat_model);
+        Some(Self { llm: OllamaClient::new(model).with_servers(servers), prompts })
+    }
+
+    /// Importance and tags for `content`; `fallback` importance and no tags when the
+    /// model can't be reached or its reply makes no sense, so storing never fails for it
+    pub async fn score(&self, content: &str, memory_type: MemoryType, fallback: f32) -> Score {
+        let prompt = format!(
+            "Type: {}\nNote:\n{}",
+            memory_type.as_str(),
+            content.chars().take(MAX_SCORED_CHARS).collect::<String>()
+        );
+        let system = self.prompts.system("score_memory", SCORE_SYSTEM_PROMPT);
+        match self.llm.generate_with(&prompt, Some(&system), &self.prompts.options("score_memory")).await {
+            Ok(response) => parse_score(&response, fallback),
+            Err(_) => Score { importance: fallback, tags: Vec::new() },
+        }
+    }
+}
+
+/// Importance and tags for a memory, from `scorer` when there is one
+pub async fn score_memory(scorer: Option<&MemoryScorer>, content: &str, memory_type: MemoryType, fallback: f32) -> Score {
+    match scorer {
+        Some(scorer) => scorer.score(content, memory_type, fallback).await,
+        None => Score { importance: fallback, tags: Vec::new() },
+    }
+}
+
+/// The score in the model's reply; an importance outside 0..=1 or missing is
+/// replaced by `fallback`
+pub fn parse_score(response: &str, fallback: f32) -> Score {
+    let raw = match (response.find('{'), response.rfind('}')) {
+        (Some(start), Some(end)) if start < end => serde_json::from_str::<RawScore>(&response[start..=end]).ok(),
+        _ => None,
+    };
+    let Some(raw) = raw else {
+        return Score { importance: fallback, tags: Vec::new() };
+    };
+
+    let importance = raw.importance.filter(|i| (0.0..=1.0).contains(i)).unwrap_or(fallback);
+    let mut tags: Vec<String> = Vec::new();
+    for topic in raw.topics.iter().map(|t| tag_value(t)).filter(|t| !t.is_empty()).take(MAX_TOPICS) {
+        let tag = format!("topic:{}", topic);
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    if let Some(language) = raw.language.as_deref().map(tag_value).filter(|l| !l.is_empty() && l != "null") {
+        tags.push(format!("lang:{}", language));
+    }
+
+    Score { importance, tags }
+}
+
+/// `Error Handling` as `error-handling`
+fn tag_value(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join("-").to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn test_parse_score_reads_tags_and_rejects_nonsense() {
+        let reply = r#"Sure: {"importance": 0.9, "topics": ["Error Handling", "sync", "sync"], "language": "Rust"}"#;
+        assert_eq!(
+            parse_score(reply, 0.5),
+            Score {
+                importance: 0.9,
+                tags: vec!["topic:error-handling".to_string(), "topic:sync".to_string(), "lang:rust".to_string()]
+            }
+        );
+
+        assert_eq!(parse_score(r#"{"importance": 7, "language": null}"#, 0.5), Score { importance: 0.5, tags: vec![] });
+        assert_eq!(parse_score("I can't rate that.", 0.6), Score { importance: 0.6, tags: vec![] });
+    }
+
+    #[tokio::test]
+    async fn test_scorer_asks_the_model() {
+        test_support::reply_to("Peers sync over automerge", r#"{"importance": 0.8, "topics": ["sync"], "language": "rust"}"#);
+        let config = ScoringConfig { enabled: true, model: None };
+        let scorer = MemoryScorer::from_config(&config, test_support::MOCK_MODEL, &test_support::servers(), PromptLibrary::default()).unwrap();
+
+        let score = scorer.score("Peers sync over automerge documents", MemoryType::Fact, 0.5).await;
+        assert_eq!(score.importance, 0.8);
+        assert_eq!(score.tags, vec!["topic:sync".to_string(), "lang:rust".to_string()]);
+        assert!(MemoryScorer::from_config(&ScoringConfig::default(), "m", &Servers::default(), PromptLibrary::default()).is_none());
+    }
+}
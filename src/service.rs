@@ -0,0 +1,292 @@
+//! Running the daemon on login: a systemd user unit on Linux, a launchd agent on macOS
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::net;
+
+/// First comment of the unit, naming what wrote it, so reinstalling knows it may replace it
+const MARKER: &str = "Installed by sovereign install-service";
+/// Suffix of the copy kept of a unit that `--force` replaced or removed
+const BACKUP_SUFFIX: &str = ".sovereign-backup";
+const SYSTEMD_UNIT: &str = "sovereign.service";
+const LAUNCHD_LABEL: &str = "dev.sovereign.daemon";
+
+/// The service manager of this platform
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Manager {
+    Systemd,
+    Launchd,
+}
+
+impl Manager {
+    pub fn current() -> Result<Self> {
+        if cfg!(target_os = "macos") {
+            Ok(Self::Launchd)
+        } else if cfg!(target_os = "linux") {
+            Ok(Self::Systemd)
+        } else {
+            anyhow::bail!("Services are only installed with systemd (Linux) or launchd (macOS)")
+        }
+    }
+
+    /// Where the unit or agent is installed for the current user
+    pub fn unit_path(self) -> Result<PathBuf> {
+        let home = dirs::home_dir().context("No home directory")?;
+        Ok(match self {
+            // systemd looks in $XDG_CONFIG_HOME, which `dirs` follows
+            Self::Systemd => dirs::config_dir().unwrap_or_else(|| home.join(".config")).join("systemd/user").join(SYSTEMD_UNIT),
+            Self::Launchd => home.join("Library/LaunchAgents").join(format!("{}.plist", LAUNCHD_LABEL)),
+        })
+    }
+}
+
+/// How the service runs the daemon: this binary, its arguments and the environment
+/// it needs, such as `DEEPSEEK_API_KEY`
+#[derive(Debug, Clone)]
+pub struct ServiceCommand {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+}
+
+impl ServiceCommand {
+    fn words(&self) -> impl Iterator<Item = String> + '_ {
+        std::iter::once(self.program.to_string_lossy().to_string()).chain(self.args.iter().cloned())
+    }
+}
+
+/// A systemd user unit starting the daemon on login and again if it fails; SIGHUP reloads it
+pub fn systemd_unit(command: &ServiceCommand) -> String {
+    let exec: Vec<String> = command.words().map(|word| systemd_quote(&word)).collect();
+    let env: String = command
+        .env
+        .iter()
+        .map(|(name, value)| format!("Environment={}\n", systemd_string(&format!("{}={}", name, value))))
+        .collect();
+    format!(
+        "# {}
+[Unit]
+Description=Sovereign daemon
+After=network-online.target
+
+[Service]
+{}ExecStart={}
+ExecReload=/bin/kill -HUP $MAINPID
+Restart=on-failure
+RestartSec=5
+
+[Install]
+WantedBy=default.target
+",
+        MARKER,
+        env,
+        exec.join(" ")
+    )
+}
+
+/// A launchd agent starting the daemon on login and again if it fails, its output
+/// going to `log`
+pub fn launchd_plist(command: &ServiceCommand, log: &Path) -> String {
+    let arguments: String = command.words().map(|word| format!("        <string>{}</string>\n", xml_escape(&word))).collect();
+    let env = if command.env.is_empty() {
+        String::new()
+    } else {
+        let variables: String = command
+            .env
+            .iter()
+            .map(|(name, value)| format!("        <key>{}</key>\n        <string>{}</string>\n", xml_escape(name), xml_escape(value)))
+            .collect();
+        format!("    <key>EnvironmentVariables</key>\n    <dict>\n{}    </dict>\n", variables)
+    };
+    let log = xml_escape(&log.to_string_lossy());
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<!-- {} -->
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+{}    </array>
+{}    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>StandardOutPath</key>
+    <string>{}</string>
+    <key>StandardErrorPath</key>
+    <string>{}</string>
+</dict>
+</plist>
+"#,
+        MARKER, LAUNCHD_LABEL, arguments, env, log, log
+    )
+}
+
+/// Write the unit for `manager` and start it, now and on every login; its path
+///
+/// A unit this did not write is only replaced with `force`, and is kept beside it.
+pub fn install(manager: Manager, command: &ServiceCommand, log: &Path, force: bool) -> Result<PathBuf> {
+    let path = manager.unit_path()?;
+    let content = match manager {
+        Manager::Systemd => systemd_unit(command),
+        Manager::Launchd => launchd_plist(command, log),
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    claim(&path, force, "replace")?;
+
+    match manager {
+        Manager::Systemd => {
+            write_unit(&path, &content)?;
+            run("systemctl", &["--user", "daemon-reload"])?;
+            // Restarted, so a reinstall takes the new options
+            run("systemctl", &["--user", "enable", SYSTEMD_UNIT])?;
+            run("systemctl", &["--user", "restart", SYSTEMD_UNIT])?;
+        }
+        Manager::Launchd => {
+            // An agent already loaded keeps its old options until it is unloaded
+            if path.exists() {
+                let _ = run("launchctl", &["unload", &path.to_string_lossy()]);
+            }
+            write_unit(&path, &content)?;
+            run("launchctl", &["load", "-w", &path.to_string_lossy()])?;
+        }
+    }
+    Ok(path)
+}
+
+/// Stop the service and remove its unit; `None` when none was installed
+///
+/// A unit this did not write is only removed with `force`, and is kept beside it.
+pub fn uninstall(manager: Manager, force: bool) -> Result<Option<PathBuf>> {
+    let path = manager.unit_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    claim(&path, force, "remove")?;
+    match manager {
+        Manager::Systemd => {
+            run("systemctl", &["--user", "disable", "--now", SYSTEMD_UNIT])?;
+            std::fs::remove_file(&path)?;
+            run("systemctl", &["--user", "daemon-reload"])?;
+        }
+        Manager::Launchd => {
+            run("launchctl", &["unload", "-w", &path.to_string_lossy()])?;
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(Some(path))
+}
+
+/// Refuse to `action` a unit at `path` that this did not write, unless `force`,
+/// in which case a copy of it is kept
+fn claim(path: &Path, force: bool, action: &str) -> Result<()> {
+    let Ok(current) = std::fs::read_to_string(path) else {
+        return Ok(());
+    };
+    if current.contains(MARKER) {
+        return Ok(());
+    }
+    if !force {
+        anyhow::bail!("{} was not installed by sovereign; pass --force to {} it (a copy is kept)", path.display(), action);
+    }
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(BACKUP_SUFFIX);
+    std::fs::copy(path, PathBuf::from(backup))?;
+    Ok(())
+}
+
+/// Write a unit, readable only by the user since it may hold an API key
+fn write_unit(path: &Path, content: &str) -> Result<()> {
+    net::write_private(path, content.as_bytes()).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Could not run {}", program))?;
+    if !output.status.success() {
+        anyhow::bail!("`{} {}` failed: {}", program, args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+/// `word` as one argument of an `ExecStart=` line, with `%` and `$` kept from expansion
+fn systemd_quote(word: &str) -> String {
+    systemd_string(&word.replace('$', "$$"))
+}
+
+/// `text` quoted for a unit file, with `%` kept from expansion; `Environment=` expands nothing else
+fn systemd_string(text: &str) -> String {
+    let escaped = text.replace('\\', "\\\\").replace('"', "\\\"").replace('%', "%%");
+    format!("\"{}\"", escaped)
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_files_quote_the_daemon_command() {
+        let command = ServiceCommand {
+            program: PathBuf::from("/opt/sovereign/bin/sovereign"),
+            args: vec![
+                "--data-dir".to_string(),
+                "/home/me/\"100%\" data".to_string(),
+                "daemon".to_string(),
+                "--watch".to_string(),
+                "/home/me/R&D <x>".to_string(),
+            ],
+            env: vec![("DEEPSEEK_API_KEY".to_string(), "sk-$100%".to_string())],
+        };
+
+        let unit = systemd_unit(&command);
+        assert!(unit.starts_with("# Installed by sovereign install-service\n"));
+        assert!(unit.contains(
+            "ExecStart=\"/opt/sovereign/bin/sovereign\" \"--data-dir\" \"/home/me/\\\"100%%\\\" data\" \"daemon\" \"--watch\" \"/home/me/R&D <x>\"\n"
+        ), "{}", unit);
+        assert!(unit.contains("\nWantedBy=default.target\n"));
+        assert!(unit.contains("[Service]\nEnvironment=\"DEEPSEEK_API_KEY=sk-$100%%\"\nExecStart="), "{}", unit);
+
+        let plist = launchd_plist(&command, Path::new("/home/me/daemon.log"));
+        assert!(plist.contains("        <string>/home/me/&quot;100%&quot; data</string>\n"), "{}", plist);
+        assert!(plist.contains("        <string>/home/me/R&amp;D &lt;x&gt;</string>\n    </array>\n"));
+        assert!(plist.contains("    <key>StandardOutPath</key>\n    <string>/home/me/daemon.log</string>\n"));
+        assert!(plist.contains(
+            "    <key>EnvironmentVariables</key>\n    <dict>\n        <key>DEEPSEEK_API_KEY</key>\n        <string>sk-$100%</string>\n    </dict>\n"
+        ));
+    }
+
+    #[test]
+    fn test_units_it_did_not_write_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sovereign.service");
+        assert!(claim(&path, false, "replace").is_ok(), "nothing is there yet");
+
+        std::fs::write(&path, "[Service]\nExecStart=/usr/local/bin/my-sovereign\n").unwrap();
+        let refused = claim(&path, false, "remove").unwrap_err().to_string();
+        assert!(refused.contains("pass --force to remove it"), "{}", refused);
+        claim(&path, true, "remove").unwrap();
+        let backup = std::fs::read_to_string(dir.path().join("sovereign.service.sovereign-backup")).unwrap();
+        assert_eq!(backup, "[Service]\nExecStart=/usr/local/bin/my-sovereign\n");
+
+        // Its own units are replaced without asking
+        let command = ServiceCommand { program: PathBuf::from("/bin/sovereign"), args: vec![], env: vec![] };
+        write_unit(&path, &systemd_unit(&command)).unwrap();
+        assert!(claim(&path, false, "replace").is_ok());
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::config::Config;
+use crate::llm::ChatMessage;
+use crate::storage::CodebaseIndex;
+
+/// Bumped when the bundle layout changes incompatibly
+pub const SHARE_FORMAT_VERSION: u32 = 1;
+
+/// Everything a teammate needs to reproduce and continue a session
+///
+/// Code is referenced by path, line range and file hash rather than copied,
+/// so a bundle stays small and carries no source beyond what the
+/// conversation itself quoted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareBundle {
+    pub format_version: u32,
+    pub created_at: DateTime<Utc>,
+    pub sovereign_version: String,
+    pub model: ModelInfo,
+    pub project: ProjectInfo,
+    pub config: Config,
+    pub conversation: Vec<ChatMessage>,
+    pub retrievals: Vec<Retrieval>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelInfo {
+    pub chat_model: String,
+    pub backend: String,
+    pub embedding_model: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProjectInfo {
+    /// Directory name of the project root
+    pub name: Option<String>,
+    pub branch: Option<String>,
+    pub commit: Option<String>,
+}
+
+/// A question and the code it pulled into the prompt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Retrieval {
+    pub query: String,
+    pub chunks: Vec<ChunkReference>,
+}
+
+/// A retrieved piece of code, identified without its content
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkReference {
+    /// Path relative to the project root
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines: Option<(usize, usize)>,
+    /// SHA-256 of the whole file when it was retrieved
+    pub hash: String,
+}
+
+/// Whether a referenced file still matches what the session saw
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkStatus {
+    Unchanged,
+    Changed,
+    Missing,
+}
+
+impl ChunkStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ChunkStatus::Unchanged => "unchanged",
+            ChunkStatus::Changed => "changed",
+            ChunkStatus::Missing => "missing",
+        }
+    }
+}
+
+impl ShareBundle {
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let bundle: Self = serde_json::from_str(&content)
+            .with_context(|| format!("{} is not a Sovereign share bundle", path.display()))?;
+
+        if bundle.format_version > SHARE_FORMAT_VERSION {
+            anyhow::bail!(
+                "Bundle format {} is newer than this version of Sovereign supports ({})",
+                bundle.format_version,
+                SHARE_FORMAT_VERSION
+            );
+        }
+
+        Ok(bundle)
+    }
+
+    /// Compare every referenced file against the local index
+    pub fn verify_context(&self, index: &CodebaseIndex) -> Vec<(ChunkReference, ChunkStatus)> {
+        let mut seen = std::collections::HashSet::new();
+
+        self.retrievals
+            .iter()
+            .flat_map(|r| r.chunks.iter())
+            .filter(|chunk| seen.insert((chunk.path.clone(), chunk.lines)))
+            .map(|chunk| {
+                let status = match index.get_file(&chunk.path).ok().flatten() {
+                    Some(file) if file.hash == chunk.hash => ChunkStatus::Unchanged,
+                    Some(_) => ChunkStatus::Changed,
+                    None => ChunkStatus::Missing,
+                };
+                (chunk.clone(), status)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.json");
+
+        let bundle = ShareBundle {
+            format_version: SHARE_FORMAT_VERSION,
+            created_at: Utc::now(),
+            sovereign_version: "0.1.0".to_string(),
+            model: ModelInfo {
+                chat_model: "qwen2.5-coder:7b".to_string(),
+                backend: "ollama".to_string(),
+                embedding_model: "nomic-embed-text".to_string(),
+            },
+            project: ProjectInfo::default(),
+            config: Config::default(),
+            conversation: vec![ChatMessage {
+                role: "user".to_string(),
+                content: "How is the index stored?".to_string(),
+            }],
+            retrievals: vec![Retrieval {
+                query: "index storage".to_string(),
+                chunks: vec![ChunkReference {
+                    path: "src/storage/codebase.rs".to_string(),
+                    lines: Some((50, 90)),
+                    hash: "abc".to_string(),
+                }],
+            }],
+        };
+        bundle.save(&path).unwrap();
+
+        let loaded = ShareBundle::load(&path).unwrap();
+        assert_eq!(loaded.conversation.len(), 1);
+        assert_eq!(loaded.retrievals[0].chunks, bundle.retrievals[0].chunks);
+        assert_eq!(loaded.model.chat_model, "qwen2.5-coder:7b");
+    }
+}
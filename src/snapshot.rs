@@ -0,0 +1,143 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+use crate::git::GitOps;
+use crate::storage::excludes::IndexConfig;
+use crate::storage::CodebaseIndex;
+
+/// Written once every file of a snapshot is in place, so an interrupted one is rebuilt
+const COMPLETE_MARKER: &str = ".sovereign-snapshot";
+
+/// The index of a project as it was at a git commit, for asking how older code worked
+pub struct Snapshot {
+    /// The tag, branch or revision asked for (`v1.2.0`)
+    pub reference: String,
+    pub commit: String,
+    pub index: CodebaseIndex,
+}
+
+impl Snapshot {
+    /// Open the snapshot of `reference` in the repository at `root`
+    ///
+    /// The first time a commit is asked for, its indexable files are written
+    /// under `<data dir>/snapshots/<project>-<commit>` with `git show` and indexed; later
+    /// calls reuse them.
+    pub fn open(
+        data_dir: &Path,
+        root: &Path,
+        reference: &str,
+        fts_tokenizer: &str,
+        config: &IndexConfig,
+    ) -> Result<Self> {
+        let git = GitOps::new(root);
+        if !git.is_git_repo() {
+            anyhow::bail!("{} is not a git repository", root.display());
+        }
+        let commit = git.resolve_commit(reference)?;
+        // Projects in subdirectories of one repository get snapshots of their own
+        let project = hex::encode(&Sha256::digest(root.to_string_lossy().as_bytes())[..4]);
+        let dir = data_dir.join("snapshots").join(format!("{}-{}", project, commit));
+
+        let written = !dir.join(COMPLETE_MARKER).exists();
+        if written {
+            write_files(&git, &commit, &dir)?;
+        }
+
+        let index = CodebaseIndex::with_tokenizer(data_dir, &dir, fts_tokenizer)?.with_config(config)?;
+        if written || CodebaseIndex::find_indexed_root(data_dir, &dir)?.as_deref() != Some(dir.as_path()) {
+            index.index_directory(false)?;
+        }
+
+        Ok(Self { reference: reference.to_string(), commit, index })
+    }
+
+    pub fn short_commit(&self) -> &str {
+        &self.commit[..self.commit.len().min(7)]
+    }
+}
+
+/// Write the files of `commit` that the index would read into `dir`
+fn write_files(git: &GitOps, commit: &str, dir: &Path) -> Result<()> {
+    if dir.exists() {
+        std::fs::remove_dir_all(dir)?;
+    }
+    std::fs::create_dir_all(dir)?;
+
+    let paths: Vec<String> = git
+        .list_files_at(commit)?
+        .into_iter()
+        .filter(|path| CodebaseIndex::is_indexable(Path::new(path)) || path.ends_with(".sovereignignore"))
+        .collect();
+    println!("  Writing {} files of {} for its snapshot...", paths.len(), &commit[..commit.len().min(7)]);
+
+    for path in paths {
+        let target = dir.join(&path);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(target, git.show_file_at(commit, &path)?)?;
+    }
+
+    std::fs::write(dir.join(COMPLETE_MARKER), commit)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::codebase::CODE_TOKENIZER;
+    use std::process::Command;
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@t", "-C"])
+            .arg(dir)
+            .args(args)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?}", args);
+    }
+
+    #[test]
+    fn test_snapshot_indexes_the_old_tree() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let repo = tempfile::tempdir().unwrap();
+        git(repo.path(), &["init", "-q"]);
+        std::fs::write(repo.path().join("retry.rs"), "fn retry_with_backoff() {}\n").unwrap();
+        std::fs::write(repo.path().join("logo.png"), [0u8, 1, 2]).unwrap();
+        git(repo.path(), &["add", "."]);
+        git(repo.path(), &["commit", "-q", "-m", "first"]);
+        git(repo.path(), &["tag", "v1"]);
+        std::fs::write(repo.path().join("retry.rs"), "fn retry_with_jitter() {}\n").unwrap();
+        git(repo.path(), &["commit", "-q", "-am", "second"]);
+
+        let config = IndexConfig::default();
+        let snapshot = Snapshot::open(data_dir.path(), repo.path(), "v1", CODE_TOKENIZER, &config).unwrap();
+        assert_eq!(snapshot.short_commit().len(), 7);
+        let matches = snapshot.index.search("retry_with_backoff", 5).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].file.relative_path, "retry.rs");
+        assert!(snapshot.index.search("retry_with_jitter", 5).unwrap().is_empty());
+
+        // The project and its snapshot share relative paths but not files
+        let current = CodebaseIndex::new(data_dir.path(), repo.path()).unwrap();
+        current.index_directory(false).unwrap();
+        assert_eq!(current.get_file_content("retry.rs").unwrap().unwrap(), "fn retry_with_jitter() {}\n");
+        assert_eq!(snapshot.index.get_file_content("retry.rs").unwrap().unwrap(), "fn retry_with_backoff() {}\n");
+        let dir = std::fs::read_dir(data_dir.path().join("snapshots")).unwrap().next().unwrap().unwrap().path();
+        assert!(dir.to_string_lossy().ends_with(&snapshot.commit));
+        let written: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| !name.starts_with('.'))
+            .collect();
+        assert_eq!(written, vec!["retry.rs"]);
+
+        // Opened again from the files already written
+        let again = Snapshot::open(data_dir.path(), repo.path(), "v1", CODE_TOKENIZER, &config).unwrap();
+        assert_eq!(again.commit, snapshot.commit);
+        assert!(Snapshot::open(data_dir.path(), repo.path(), "v9", CODE_TOKENIZER, &config).is_err());
+    }
+}
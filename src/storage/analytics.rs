@@ -0,0 +1,209 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::path::Path;
+
+use super::db::Migration;
+
+/// Local-only usage statistics
+///
+/// Only the command name, latency and outcome are recorded — never arguments,
+/// prompts or code — and nothing leaves `analytics.db` in the data directory.
+pub struct AnalyticsStore {
+    conn: Connection,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandStats {
+    pub command: String,
+    pub count: usize,
+    pub errors: usize,
+    pub avg_latency_ms: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DailyUsage {
+    /// YYYY-MM-DD (UTC)
+    pub date: String,
+    pub commands: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexSnapshot {
+    pub recorded_at: String,
+    pub files: usize,
+    pub lines: usize,
+}
+
+/// Dashboard data for the last `days` days
+#[derive(Debug, Clone, Serialize)]
+pub struct AnalyticsSummary {
+    pub days: u32,
+    pub commands: Vec<CommandStats>,
+    pub daily: Vec<DailyUsage>,
+    pub index_size: Vec<IndexSnapshot>,
+}
+
+/// Schema of `analytics.db`, oldest step first; append to change it
+const MIGRATIONS: &[Migration] = &[
+    Migration { description: "command events and index snapshots", apply: AnalyticsStore::create_tables },
+];
+
+impl AnalyticsStore {
+    pub fn new(data_dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(data_dir)?;
+        let db_path = data_dir.join("analytics.db");
+        let conn = super::db::open(&db_path)?;
+
+        super::db::migrate(&conn, "analytics.db", MIGRATIONS)?;
+
+        Ok(Self { conn })
+    }
+
+    /// Schema version 1: the tables as created by versions before migrations
+    fn create_tables(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS command_events (
+                command TEXT NOT NULL,
+                latency_ms INTEGER NOT NULL,
+                success INTEGER NOT NULL,
+                recorded_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_command_events_time ON command_events(recorded_at)",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS index_snapshots (
+                files INTEGER NOT NULL,
+                lines INTEGER NOT NULL,
+                recorded_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        Ok(())
+    }
+
+    /// Open the store unless analytics were switched off (`--no-analytics`)
+    pub fn open_if_enabled(data_dir: &Path, enabled: bool) -> Option<Self> {
+        if !enabled {
+            return None;
+        }
+        Self::new(data_dir).ok()
+    }
+
+    pub fn record_command(&self, command: &str, latency_ms: u64, success: bool) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO command_events (command, latency_ms, success, recorded_at) VALUES (?1, ?2, ?3, ?4)",
+            params![command, latency_ms as i64, success, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    pub fn record_index_size(&self, files: usize, lines: usize) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO index_snapshots (files, lines, recorded_at) VALUES (?1, ?2, ?3)",
+            params![files as i64, lines as i64, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    pub fn summary(&self, days: u32) -> Result<AnalyticsSummary> {
+        let since = (Utc::now() - Duration::days(days as i64)).to_rfc3339();
+
+        let commands = self.conn
+            .prepare(
+                "SELECT command, COUNT(*), SUM(CASE WHEN success THEN 0 ELSE 1 END), AVG(latency_ms)
+                 FROM command_events WHERE recorded_at >= ?1
+                 GROUP BY command ORDER BY COUNT(*) DESC",
+            )?
+            .query_map(params![since], |row| {
+                Ok(CommandStats {
+                    command: row.get(0)?,
+                    count: row.get::<_, i64>(1)? as usize,
+                    errors: row.get::<_, i64>(2)? as usize,
+                    avg_latency_ms: row.get(3)?,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        let daily = self.conn
+            .prepare(
+                "SELECT substr(recorded_at, 1, 10) AS day, COUNT(*)
+                 FROM command_events WHERE recorded_at >= ?1
+                 GROUP BY day ORDER BY day",
+            )?
+            .query_map(params![since], |row| {
+                Ok(DailyUsage {
+                    date: row.get(0)?,
+                    commands: row.get::<_, i64>(1)? as usize,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        let index_size = self.conn
+            .prepare(
+                "SELECT recorded_at, files, lines FROM index_snapshots
+                 WHERE recorded_at >= ?1 ORDER BY recorded_at",
+            )?
+            .query_map(params![since], |row| {
+                Ok(IndexSnapshot {
+                    recorded_at: row.get(0)?,
+                    files: row.get::<_, i64>(1)? as usize,
+                    lines: row.get::<_, i64>(2)? as usize,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(AnalyticsSummary {
+            days,
+            commands,
+            daily,
+            index_size,
+        })
+    }
+}
+
+/// Name under which an input is recorded: the slash command, or "chat"
+pub fn command_name(input: &str) -> &str {
+    let input = input.trim();
+    if input.starts_with('/') {
+        input.split_whitespace().next().unwrap_or("/")
+    } else {
+        "chat"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_groups_by_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = AnalyticsStore::new(dir.path()).unwrap();
+
+        store.record_command(command_name("/search config loader"), 120, true).unwrap();
+        store.record_command(command_name("/search cache"), 80, false).unwrap();
+        store.record_command(command_name("how does sync work?"), 900, true).unwrap();
+        store.record_index_size(42, 1000).unwrap();
+
+        let summary = store.summary(7).unwrap();
+        assert_eq!(summary.commands[0].command, "/search");
+        assert_eq!(summary.commands[0].count, 2);
+        assert_eq!(summary.commands[0].errors, 1);
+        assert!((summary.commands[0].avg_latency_ms - 100.0).abs() < f64::EPSILON);
+        assert_eq!(summary.commands[1].command, "chat");
+        assert_eq!(summary.daily.iter().map(|d| d.commands).sum::<usize>(), 3);
+        assert_eq!(summary.index_size[0].files, 42);
+    }
+}
@@ -1,11 +1,20 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use ignore::WalkBuilder;
-use rusqlite::{params, Connection};
+use rusqlite::{params, params_from_iter, Connection};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::fs;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use super::db::Migration;
+use crate::dry_run;
+use crate::jobs;
+use super::excludes::{IndexConfig, IndexExcludes, DEFAULT_MAX_FILE_SIZE};
+use super::imports;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IndexedFile {
@@ -18,29 +27,487 @@ pub struct IndexedFile {
     pub summary: Option<String>,
     pub symbols: Vec<String>,
     pub indexed_at: DateTime<Utc>,
-    #[serde(skip)]
+}
+
+/// A definition found in an indexed file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Symbol {
+    pub name: String,
+    /// `fn`, `struct`, `class`, `def`, ... as in the file's symbol list
+    pub kind: String,
+    /// Absolute path; empty until the symbol is stored with its file
+    pub path: String,
+    pub relative_path: String,
+    /// First line of the definition (1-based)
+    pub start_line: usize,
+    /// Last line of the definition's body
+    pub end_line: usize,
+    /// The definition's first line, without an opening brace
+    pub signature: String,
+}
+
+/// Embedding vector for one chunk of a file
+#[derive(Debug, Clone)]
+pub struct ChunkEmbedding {
+    pub path: String,
+    pub chunk_index: usize,
+    /// First line of the chunk (1-based)
+    pub start_line: usize,
+    /// Last line of the chunk; 0 for vectors that cover the whole file
+    pub end_line: usize,
+    /// SHA-256 of the chunk text, so unchanged chunks keep their vector when the file changes
+    pub content_hash: String,
+    pub embedding: Vec<f32>,
+}
+
+impl ChunkEmbedding {
+    /// Last line of the chunk in a file of `lines` lines
+    pub fn last_line(&self, lines: usize) -> usize {
+        if self.end_line == 0 {
+            lines
+        } else {
+            self.end_line
+        }
+    }
+
+    /// The chunk's text, cut from `content`, the whole file it was embedded from
+    pub fn text_of(&self, content: &str) -> String {
+        line_span(content, self.start_line, self.last_line(content.lines().count()))
+    }
+}
+
+/// Lines `start..=end` (1-based) of `content`
+pub fn line_span(content: &str, start: usize, end: usize) -> String {
+    content
+        .lines()
+        .skip(start.saturating_sub(1))
+        .take(end.saturating_sub(start) + 1)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A full-text match ranked by FTS5 bm25
+#[derive(Debug, Clone)]
+pub struct KeywordHit {
+    pub path: String,
+    pub language: String,
+    /// bm25 rank; more negative is more relevant
+    pub rank: f64,
+    /// Stored content of the whole file
+    pub content: String,
+    /// Whole lines around the best matching passage
+    pub snippet: String,
+    /// First line of the snippet (1-based)
+    pub start_line: usize,
+    /// Last line of the snippet
+    pub end_line: usize,
+}
+
+/// Most lines in the excerpt of a `search` match
+const MAX_EXCERPT_LINES: usize = 3;
+
+/// A file matched by `search`, with the passage that matched best
+#[derive(Debug, Clone)]
+pub struct TextMatch {
+    pub file: IndexedFile,
+    /// Whole lines of the passage, query terms wrapped in `**`
+    pub excerpt: String,
+    /// First line of the excerpt (1-based)
+    pub start_line: usize,
+    /// Last line of the excerpt
+    pub end_line: usize,
+}
+
+/// A previously generated `/ask` answer, kept to serve repeated questions
+#[derive(Debug, Clone)]
+pub struct CachedAnswer {
+    pub question: String,
+    /// Rendered answer, including its source list
+    pub answer: String,
+    /// Question embedding, when an embedding model was available
     pub embedding: Option<Vec<f32>>,
+    /// (relative path, hash) of each cited file when the answer was generated
+    pub sources: Vec<(String, String)>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Scope of a search, from `language:`, `path:` and `symbol:` in the query
+///
+/// Files must match one value of every filter that has any.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchFilters {
+    pub languages: Vec<String>,
+    /// Relative path prefixes; `*` matches any characters
+    pub paths: Vec<String>,
+    /// Names of symbols the file defines; `*` matches any characters
+    pub symbols: Vec<String>,
+}
+
+impl SearchFilters {
+    pub fn is_empty(&self) -> bool {
+        self.languages.is_empty() && self.paths.is_empty() && self.symbols.is_empty()
+    }
+
+    /// Conditions on the `files` table aliased `f`, each starting with AND,
+    /// and their parameters, numbered from `first`
+    fn sql(&self, first: usize) -> (String, Vec<String>) {
+        let mut clause = String::new();
+        let mut values: Vec<String> = Vec::new();
+        let mut any_of = |patterns: Vec<String>, condition: &dyn Fn(usize) -> String| {
+            if patterns.is_empty() {
+                return;
+            }
+            let conditions: Vec<String> = patterns
+                .into_iter()
+                .map(|pattern| {
+                    values.push(pattern);
+                    condition(first + values.len() - 1)
+                })
+                .collect();
+            clause.push_str(&format!(" AND ({})", conditions.join(" OR ")));
+        };
+
+        any_of(
+            self.languages.iter().map(|l| l.to_lowercase()).collect(),
+            &|n| format!("lower(f.language) = ?{}", n),
+        );
+        any_of(
+            self.paths.iter().map(|p| format!("{}%", like_pattern(p.trim_start_matches("./")))).collect(),
+            &|n| format!("f.relative_path LIKE ?{} ESCAPE '\\'", n),
+        );
+        any_of(
+            self.symbols.iter().map(|s| like_pattern(s)).collect(),
+            &|n| format!("EXISTS (SELECT 1 FROM symbols s WHERE s.path = f.path AND s.name LIKE ?{} ESCAPE '\\')", n),
+        );
+
+        (clause, values)
+    }
+}
+
+/// A LIKE pattern matching `text` literally, except that `*` matches any characters
+fn like_pattern(text: &str) -> String {
+    like_escape(text).replace('*', "%")
+}
+
+/// `text` with the LIKE wildcards and the escape character escaped, for `ESCAPE '\'`
+fn like_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// A domain term of a project and what it means there, from `sovereign glossary`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GlossaryEntry {
+    pub term: String,
+    pub definition: String,
+    /// Relative paths of the code the definition was based on
+    pub sources: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CodebaseStats {
     pub total_files: usize,
     pub total_lines: usize,
+    /// Binary or oversized files indexed without their content
+    pub metadata_only_files: usize,
     pub languages: Vec<(String, usize)>,
     pub last_indexed: Option<DateTime<Utc>>,
 }
 
+/// How big the index is, every project together
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndexSize {
+    pub files: usize,
+    pub bytes: usize,
+    pub chunks: usize,
+    pub pending_embeddings: usize,
+}
+
+/// Leading bytes checked for NUL when telling binary files from text
+const BINARY_SNIFF_BYTES: usize = 8000;
+/// Files read and parsed in parallel, then written in one transaction
+const INDEX_BATCH: usize = 512;
+
+/// Schema of `codebase.db`, oldest step first; append to change it
+const MIGRATIONS: &[Migration] = &[
+    Migration { description: "initial tables", apply: CodebaseIndex::create_tables },
+    Migration { description: "embedding model and dimension", apply: CodebaseIndex::migrate_embedding_columns },
+    Migration { description: "chunked embeddings", apply: CodebaseIndex::migrate_embedding_chunks },
+    Migration { description: "symbols of files indexed before them", apply: CodebaseIndex::backfill_symbols },
+    Migration { description: "project glossary", apply: CodebaseIndex::create_glossary },
+    Migration { description: "per-branch vectors", apply: CodebaseIndex::create_branch_tables },
+];
+
+/// Files an indexing run changed, as paths relative to the project root
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndexChanges {
+    pub added: Vec<String>,
+    pub modified: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// Full-text tokenizer that also indexes the words inside identifiers, so
+/// "get staged diff" finds `getStagedDiff` as well as `get_staged_diff`
+pub const CODE_TOKENIZER: &str = "code";
+
 pub struct CodebaseIndex {
     conn: Connection,
     root_path: PathBuf,
+    fts_tokenizer: String,
+    excludes: IndexExcludes,
+    max_file_size: u64,
+    /// Git branch checked out in the project, if it is on one
+    branch: Option<String>,
+    /// Changes are only described (`--dry-run`)
+    dry_run: bool,
 }
 
 impl CodebaseIndex {
-    pub fn new(data_dir: &PathBuf, root_path: &Path) -> Result<Self> {
+    /// Open the index with the default `code` tokenizer; outside tests it comes from config
+    #[cfg(test)]
+    pub fn new(data_dir: &Path, root_path: &Path) -> Result<Self> {
+        Self::with_tokenizer(data_dir, root_path, CODE_TOKENIZER)
+    }
+
+    /// Open the index with a full-text tokenizer: `code`, or any FTS5 tokenizer
+    /// spec such as `porter unicode61` or `trigram`
+    pub fn with_tokenizer(data_dir: &Path, root_path: &Path, fts_tokenizer: &str) -> Result<Self> {
         std::fs::create_dir_all(data_dir)?;
         let db_path = data_dir.join("codebase.db");
-        let conn = Connection::open(&db_path)?;
+        let conn = super::db::open(&db_path)?;
+
+        super::db::migrate(&conn, "codebase.db", MIGRATIONS)?;
+        Self::ensure_fts(&conn, fts_tokenizer)?;
+
+        Ok(Self {
+            conn,
+            root_path: root_path.to_path_buf(),
+            fts_tokenizer: fts_tokenizer.to_string(),
+            excludes: IndexExcludes::load(root_path, &[])?,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            branch: None,
+            dry_run: false,
+        })
+    }
 
+    /// Apply the `[index]` section of `sovereign.toml`: `exclude` patterns and `max_file_size`
+    pub fn with_config(mut self, config: &IndexConfig) -> Result<Self> {
+        self.excludes = IndexExcludes::load(&self.root_path, &config.exclude)?;
+        self.max_file_size = config.max_file_size;
+        Ok(self)
+    }
+
+    /// Set the git branch checked out in the project
+    ///
+    /// When it differs from the branch the project was last indexed on,
+    /// indexing keeps the vectors of the files that differ under the old
+    /// branch, and puts them back when a file returns to that content, so
+    /// switching between branches does not re-embed what was embedded before.
+    pub fn with_branch(mut self, branch: Option<String>) -> Self {
+        self.branch = branch;
+        self
+    }
+
+    /// Describe changes to the index instead of making them
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// The branch the project was checked out on when last indexed
+    pub fn indexed_branch(&self) -> Result<Option<String>> {
+        Ok(self.conn
+            .query_row(
+                "SELECT branch FROM indexed_branches WHERE root = ?1",
+                params![self.root_path.to_string_lossy()],
+                |row| row.get(0),
+            )
+            .ok())
+    }
+
+    /// The branch last indexed, when another one is checked out now
+    pub fn switched_from(&self) -> Result<Option<String>> {
+        let Some(ref branch) = self.branch else {
+            return Ok(None);
+        };
+        Ok(self.indexed_branch()?.filter(|indexed| indexed != branch))
+    }
+
+    /// Forget the vectors kept for branches of this project other than `branches`,
+    /// e.g. once they are deleted
+    pub fn drop_branch_vectors(&self, branches: &[String]) -> Result<usize> {
+        let kept: HashSet<&str> = branches.iter().map(String::as_str).collect();
+        let stored: Vec<String> = self.conn
+            .prepare("SELECT DISTINCT branch FROM branch_vectors WHERE root = ?1")?
+            .query_map(params![self.root_path.to_string_lossy()], |row| row.get(0))?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        let mut removed = 0;
+        for branch in stored.iter().filter(|b| !kept.contains(b.as_str())) {
+            removed += self.conn.execute(
+                "DELETE FROM branch_vectors WHERE root = ?1 AND branch = ?2",
+                params![self.root_path.to_string_lossy(), branch],
+            )?;
+        }
+        Ok(removed)
+    }
+
+    /// Keep the current vectors of `path`, whose content hashes to `file_hash`
+    /// on `branch`, for when the file has that content again
+    fn save_branch_vectors(&self, branch: &str, path: &str, file_hash: &str) -> Result<()> {
+        let root = self.root_path.to_string_lossy();
+        self.conn.execute(
+            "DELETE FROM branch_vectors WHERE root = ?1 AND branch = ?2 AND path = ?3",
+            params![root, branch, path],
+        )?;
+        // Queued vectors were built from older content than `file_hash`
+        self.conn.execute(
+            "INSERT INTO branch_vectors
+                (root, branch, path, file_hash, chunk_index, start_line, end_line, content_hash, model, dimension, embedding)
+             SELECT ?1, ?2, path, ?4, chunk_index, start_line, end_line, content_hash, model, dimension, embedding
+             FROM embeddings WHERE path = ?3 AND path NOT IN (SELECT path FROM embedding_queue)",
+            params![root, branch, path, file_hash],
+        )?;
+        Ok(())
+    }
+
+    /// Put back vectors kept for `path` with the content hashing to `file_hash`;
+    /// false when there are none
+    fn restore_branch_vectors(&self, path: &str, file_hash: &str) -> Result<bool> {
+        let branch: Option<String> = self.conn
+            .query_row(
+                "SELECT branch FROM branch_vectors WHERE path = ?1 AND file_hash = ?2 LIMIT 1",
+                params![path, file_hash],
+                |row| row.get(0),
+            )
+            .ok();
+        let Some(branch) = branch else {
+            return Ok(false);
+        };
+
+        self.delete_embeddings(path)?;
+        self.conn.execute(
+            "INSERT INTO embeddings
+                (path, embedding, chunk_index, created_at, model, dimension, start_line, end_line, content_hash)
+             SELECT path, embedding, chunk_index, ?4, model, dimension, start_line, end_line, content_hash
+             FROM branch_vectors WHERE path = ?1 AND file_hash = ?2 AND branch = ?3",
+            params![path, file_hash, branch, Utc::now().to_rfc3339()],
+        )?;
+        self.dequeue_embedding(path)?;
+        Ok(true)
+    }
+
+    /// The indexed project root that is `dir` or its nearest ancestor, if any
+    ///
+    /// A root is recognised by a file whose stored path is the root joined with
+    /// its relative path, so an indexed parent is not mistaken for a subdirectory.
+    pub fn find_indexed_root(data_dir: &Path, dir: &Path) -> Result<Option<PathBuf>> {
+        let db_path = data_dir.join("codebase.db");
+        if !db_path.exists() {
+            return Ok(None);
+        }
+        let conn = super::db::open(&db_path)?;
+
+        for root in dir.ancestors() {
+            let prefix = root.join("").to_string_lossy().to_string();
+            let found = conn
+                .query_row(
+                    "SELECT 1 FROM files WHERE path LIKE ?1 ESCAPE '\\' AND path = ?2 || relative_path LIMIT 1",
+                    params![format!("{}%", like_escape(&prefix)), prefix],
+                    |_| Ok(()),
+                )
+                .is_ok();
+            if found {
+                return Ok(Some(root.to_path_buf()));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// How big `codebase.db` is, without opening a project; zeros before anything is indexed
+    pub fn size(data_dir: &Path) -> Result<IndexSize> {
+        let db_path = data_dir.join("codebase.db");
+        if !db_path.exists() {
+            return Ok(IndexSize::default());
+        }
+        let conn = super::db::open(&db_path)?;
+        let count = |sql: &str| -> Result<usize> { Ok(conn.query_row(sql, [], |row| row.get::<_, i64>(0))? as usize) };
+
+        Ok(IndexSize {
+            files: count("SELECT COUNT(*) FROM files")?,
+            bytes: count("SELECT COALESCE(SUM(size), 0) FROM files")?,
+            chunks: count("SELECT COUNT(*) FROM embeddings")?,
+            pending_embeddings: count("SELECT COUNT(*) FROM embedding_queue")?,
+        })
+    }
+
+    /// LIKE pattern for the stored paths of this project's files; relative paths
+    /// are only unique within a project (and its snapshots share the database)
+    fn root_pattern(&self) -> String {
+        format!("{}%", like_escape(&self.root_path.join("").to_string_lossy()))
+    }
+
+    /// Whether `.sovereignignore` or `index.exclude` keeps a path out of the index
+    pub fn is_excluded(&self, path: &str) -> bool {
+        self.excludes.is_excluded(Path::new(path), false)
+    }
+
+    /// Create the full-text table, rebuilding it from the stored content when
+    /// it was created with another tokenizer (or before the `terms` column)
+    fn ensure_fts(conn: &Connection, tokenizer: &str) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS index_settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        // `code` and `unicode61` share an FTS5 definition, so the setting is kept separately
+        let definition = fts_definition(tokenizer);
+        let existing: Option<String> = conn
+            .query_row("SELECT sql FROM sqlite_master WHERE name = 'files_fts'", [], |row| row.get(0))
+            .ok();
+        let built_with: Option<String> = conn
+            .query_row("SELECT value FROM index_settings WHERE key = 'fts_tokenizer'", [], |row| row.get(0))
+            .ok();
+
+        if existing.as_deref().is_some_and(|sql| sql.ends_with(&definition))
+            && built_with.as_deref() == Some(tokenizer)
+        {
+            return Ok(());
+        }
+
+        let tx = conn.unchecked_transaction()?;
+        if existing.is_some() {
+            tx.execute("DROP TABLE files_fts", [])?;
+        }
+        tx.execute(&format!("CREATE VIRTUAL TABLE files_fts USING {}", definition), [])
+            .with_context(|| format!("Unsupported full-text tokenizer '{}'", tokenizer))?;
+
+        let rows: Vec<(String, String, String)> = tx
+            .prepare("SELECT path, content, symbols FROM files WHERE content IS NOT NULL")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .filter_map(|r| r.ok())
+            .collect();
+        for (path, content, symbols) in rows {
+            tx.execute(
+                "INSERT INTO files_fts (path, content, symbols, terms) VALUES (?1, ?2, ?3, ?4)",
+                params![path, content, symbols, fts_terms(tokenizer, &content)],
+            )?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO index_settings (key, value) VALUES ('fts_tokenizer', ?1)",
+            params![tokenizer],
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Schema version 1: every table, as created by versions before migrations
+    fn create_tables(conn: &Connection) -> Result<()> {
         conn.execute(
             "CREATE TABLE IF NOT EXISTS files (
                 path TEXT PRIMARY KEY,
@@ -61,78 +528,586 @@ impl CodebaseIndex {
             [],
         )?;
 
+        // Embeddings table for semantic search, one row per chunk
         conn.execute(
-            "CREATE VIRTUAL TABLE IF NOT EXISTS files_fts USING fts5(path, content, symbols)",
+            "CREATE TABLE IF NOT EXISTS embeddings (
+                path TEXT NOT NULL,
+                embedding BLOB NOT NULL,
+                chunk_index INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL,
+                model TEXT NOT NULL DEFAULT 'nomic-embed-text',
+                dimension INTEGER NOT NULL DEFAULT 0,
+                start_line INTEGER NOT NULL DEFAULT 1,
+                end_line INTEGER NOT NULL DEFAULT 0,
+                content_hash TEXT NOT NULL DEFAULT '',
+                PRIMARY KEY (path, chunk_index)
+            )",
             [],
-        ).ok(); // Ignore if already exists
+        )?;
 
-        // Embeddings table for semantic search
+        // Definitions with line ranges, for /def and symbol lookups
         conn.execute(
-            "CREATE TABLE IF NOT EXISTS embeddings (
+            "CREATE TABLE IF NOT EXISTS symbols (
+                path TEXT NOT NULL,
+                name TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                start_line INTEGER NOT NULL,
+                end_line INTEGER NOT NULL,
+                signature TEXT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute("CREATE INDEX IF NOT EXISTS idx_symbols_name ON symbols(name)", [])?;
+        conn.execute("CREATE INDEX IF NOT EXISTS idx_symbols_path ON symbols(path)", [])?;
+
+        // Files whose content changed since their vectors were built
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS embedding_queue (
                 path TEXT PRIMARY KEY,
+                queued_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        // Import specifiers as written in each file, and the files they resolve to
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS imports (
+                path TEXT NOT NULL,
+                specifier TEXT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute("CREATE INDEX IF NOT EXISTS idx_imports_path ON imports(path)", [])?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS dependencies (
+                source TEXT NOT NULL,
+                target TEXT NOT NULL,
+                PRIMARY KEY (source, target)
+            )",
+            [],
+        )?;
+        conn.execute("CREATE INDEX IF NOT EXISTS idx_dependencies_target ON dependencies(target)", [])?;
+
+        // How often the user asked about, edited or opened each file
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS file_interactions (
+                path TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                count INTEGER NOT NULL DEFAULT 0,
+                last_at TEXT NOT NULL,
+                PRIMARY KEY (path, kind)
+            )",
+            [],
+        )?;
+
+        // Answers to earlier questions, per project root
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS answer_cache (
+                root TEXT NOT NULL,
+                question TEXT NOT NULL,
+                answer TEXT NOT NULL,
+                embedding BLOB,
+                sources TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                PRIMARY KEY (root, question)
+            )",
+            [],
+        )?;
+
+        Ok(())
+    }
+
+    /// Fill the symbols table for files indexed before it existed
+    fn backfill_symbols(conn: &Connection) -> Result<()> {
+        let files: Vec<(String, String, String)> = conn
+            .prepare(
+                "SELECT path, language, COALESCE(content, '') FROM files
+                 WHERE symbols != '[]' AND path NOT IN (SELECT DISTINCT path FROM symbols)",
+            )?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        if files.is_empty() {
+            return Ok(());
+        }
+
+        let tx = conn.unchecked_transaction()?;
+        for (path, language, content) in files {
+            Self::store_symbols(&tx, &path, &Self::extract_definitions(&content, &language))?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn store_symbols(conn: &Connection, path: &str, symbols: &[Symbol]) -> Result<()> {
+        conn.execute("DELETE FROM symbols WHERE path = ?1", params![path])?;
+        for symbol in symbols {
+            conn.execute(
+                "INSERT INTO symbols (path, name, kind, start_line, end_line, signature)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    path,
+                    symbol.name,
+                    symbol.kind,
+                    symbol.start_line as i64,
+                    symbol.end_line as i64,
+                    symbol.signature,
+                ],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Project glossary, per project root
+    fn create_glossary(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS glossary (
+                root TEXT NOT NULL,
+                term TEXT NOT NULL,
+                definition TEXT NOT NULL,
+                sources TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                PRIMARY KEY (root, term)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// The branch each project was last indexed on, and the vectors of files as
+    /// they were on branches switched away from
+    fn create_branch_tables(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS indexed_branches (
+                root TEXT PRIMARY KEY,
+                branch TEXT NOT NULL,
+                indexed_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS branch_vectors (
+                root TEXT NOT NULL,
+                branch TEXT NOT NULL,
+                path TEXT NOT NULL,
+                file_hash TEXT NOT NULL,
+                chunk_index INTEGER NOT NULL,
+                start_line INTEGER NOT NULL,
+                end_line INTEGER NOT NULL,
+                content_hash TEXT NOT NULL,
+                model TEXT NOT NULL,
+                dimension INTEGER NOT NULL,
                 embedding BLOB NOT NULL,
-                chunk_index INTEGER DEFAULT 0,
-                created_at TEXT NOT NULL
+                PRIMARY KEY (root, branch, path, chunk_index)
             )",
             [],
         )?;
+        conn.execute("CREATE INDEX IF NOT EXISTS idx_branch_vectors_file ON branch_vectors(path, file_hash)", [])?;
+        Ok(())
+    }
 
-        Ok(Self {
-            conn,
-            root_path: root_path.to_path_buf(),
-        })
+    /// Add model/dimension tracking to embeddings tables created before it existed
+    fn migrate_embedding_columns(conn: &Connection) -> Result<()> {
+        let columns: Vec<String> = conn
+            .prepare("PRAGMA table_info(embeddings)")?
+            .query_map([], |row| row.get::<_, String>(1))?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        if !columns.iter().any(|c| c == "model") {
+            // Only nomic-embed-text existed before models were tracked
+            conn.execute(
+                "ALTER TABLE embeddings ADD COLUMN model TEXT NOT NULL DEFAULT 'nomic-embed-text'",
+                [],
+            )?;
+        }
+        if !columns.iter().any(|c| c == "dimension") {
+            conn.execute(
+                "ALTER TABLE embeddings ADD COLUMN dimension INTEGER NOT NULL DEFAULT 0",
+                [],
+            )?;
+            conn.execute(
+                "UPDATE embeddings SET dimension = length(embedding) / 4",
+                [],
+            )?;
+        }
+        // Only applies to tables that already have line ranges; older ones are rebuilt
+        // with the column by `migrate_embedding_chunks`
+        if columns.iter().any(|c| c == "start_line") && !columns.iter().any(|c| c == "content_hash") {
+            conn.execute(
+                "ALTER TABLE embeddings ADD COLUMN content_hash TEXT NOT NULL DEFAULT ''",
+                [],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Rebuild embeddings tables keyed by path alone so each file can hold several chunks.
+    /// Existing whole-file vectors become chunk 0 with `end_line = 0` (meaning "whole file").
+    fn migrate_embedding_chunks(conn: &Connection) -> Result<()> {
+        let has_line_range = conn
+            .prepare("PRAGMA table_info(embeddings)")?
+            .query_map([], |row| row.get::<_, String>(1))?
+            .filter_map(|r| r.ok())
+            .any(|c| c == "start_line");
+
+        if has_line_range {
+            return Ok(());
+        }
+
+        conn.execute_batch(
+            "CREATE TABLE embeddings_chunked (
+                 path TEXT NOT NULL,
+                 embedding BLOB NOT NULL,
+                 chunk_index INTEGER NOT NULL DEFAULT 0,
+                 created_at TEXT NOT NULL,
+                 model TEXT NOT NULL DEFAULT 'nomic-embed-text',
+                 dimension INTEGER NOT NULL DEFAULT 0,
+                 start_line INTEGER NOT NULL DEFAULT 1,
+                 end_line INTEGER NOT NULL DEFAULT 0,
+                 content_hash TEXT NOT NULL DEFAULT '',
+                 PRIMARY KEY (path, chunk_index)
+             );
+             INSERT INTO embeddings_chunked (path, embedding, chunk_index, created_at, model, dimension)
+                 SELECT path, embedding, 0, created_at, model, dimension FROM embeddings;
+             DROP TABLE embeddings;
+             ALTER TABLE embeddings_chunked RENAME TO embeddings;",
+        )?;
+
+        Ok(())
+    }
+
+    pub fn store_embedding(&self, chunk: &ChunkEmbedding, model: &str) -> Result<()> {
+        self.check_embedding_model(model, chunk.embedding.len())?;
+
+        let embedding_bytes: Vec<u8> = chunk
+            .embedding
+            .iter()
+            .flat_map(|f| f.to_le_bytes())
+            .collect();
+
+        self.conn.execute(
+            "INSERT OR REPLACE INTO embeddings
+                (path, embedding, chunk_index, created_at, model, dimension, start_line, end_line, content_hash)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                chunk.path,
+                embedding_bytes,
+                chunk.chunk_index as i64,
+                chrono::Utc::now().to_rfc3339(),
+                model,
+                chunk.embedding.len() as i64,
+                chunk.start_line as i64,
+                chunk.end_line as i64,
+                chunk.content_hash,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Remove every chunk vector of a file (before re-embedding it)
+    pub fn delete_embeddings(&self, path: &str) -> Result<usize> {
+        Ok(self.conn.execute("DELETE FROM embeddings WHERE path = ?1", params![path])?)
+    }
+
+    /// Distinct (model, dimension) pairs of this project's stored embeddings
+    pub fn embedding_models(&self) -> Result<Vec<(String, usize)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT model, dimension FROM embeddings WHERE path LIKE ?1 ESCAPE '\\' GROUP BY model, dimension"
+        )?;
+
+        let models = stmt
+            .query_map(params![self.root_pattern()], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as usize))
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(models)
+    }
+
+    /// Refuse to mix vectors from different embedding models in one project
+    pub fn check_embedding_model(&self, model: &str, dimension: usize) -> Result<()> {
+        for (stored_model, stored_dimension) in self.embedding_models()? {
+            if stored_model != model || stored_dimension != dimension {
+                anyhow::bail!(
+                    "Stored embeddings come from '{}' ({} dimensions) but the active model is '{}' ({} dimensions). \
+                     Run `/embed --reembed` to rebuild them with the new model.",
+                    stored_model, stored_dimension, model, dimension
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete this project's stored embeddings, e.g. before re-embedding with another
+    /// model; other projects sharing the database keep theirs
+    pub fn clear_embeddings(&self) -> Result<usize> {
+        let pattern = self.root_pattern();
+        // Vectors kept for other branches come from the same model
+        self.conn.execute("DELETE FROM branch_vectors WHERE path LIKE ?1 ESCAPE '\\'", params![pattern])?;
+        Ok(self.conn.execute("DELETE FROM embeddings WHERE path LIKE ?1 ESCAPE '\\'", params![pattern])?)
+    }
+
+    /// Current vectors of this project's files; those queued for re-embedding are stale and left out
+    pub fn get_all_embeddings(&self) -> Result<Vec<ChunkEmbedding>> {
+        self.query_embeddings(
+            "SELECT path, chunk_index, start_line, end_line, content_hash, embedding FROM embeddings
+             WHERE path LIKE ?1 ESCAPE '\\' AND path NOT IN (SELECT path FROM embedding_queue)",
+            params![self.root_pattern()],
+        )
+    }
+
+    /// Stored vectors of one file by chunk hash, including stale ones, so that
+    /// re-embedding a changed file only has to embed the chunks that changed
+    pub fn chunk_embeddings(&self, path: &str) -> Result<HashMap<String, Vec<f32>>> {
+        let chunks = self.query_embeddings(
+            "SELECT path, chunk_index, start_line, end_line, content_hash, embedding FROM embeddings
+             WHERE path = ?1 AND content_hash != ''",
+            params![path],
+        )?;
+
+        Ok(chunks.into_iter().map(|c| (c.content_hash, c.embedding)).collect())
+    }
+
+    fn query_embeddings<P: rusqlite::Params>(&self, sql: &str, params: P) -> Result<Vec<ChunkEmbedding>> {
+        let mut stmt = self.conn.prepare(sql)?;
+
+        let results = stmt
+            .query_map(params, |row| {
+                let embedding_bytes: Vec<u8> = row.get(5)?;
+
+                // Convert bytes back to f32
+                let embedding: Vec<f32> = embedding_bytes
+                    .chunks(4)
+                    .map(|chunk| {
+                        let bytes: [u8; 4] = chunk.try_into().unwrap_or([0; 4]);
+                        f32::from_le_bytes(bytes)
+                    })
+                    .collect();
+
+                Ok(ChunkEmbedding {
+                    path: row.get(0)?,
+                    chunk_index: row.get::<_, i64>(1)? as usize,
+                    start_line: row.get::<_, i64>(2)? as usize,
+                    end_line: row.get::<_, i64>(3)? as usize,
+                    content_hash: row.get(4)?,
+                    embedding,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(results)
+    }
+
+    /// Queue a changed file for re-embedding
+    ///
+    /// Its old vectors stay stored but unused until then, so chunks whose text
+    /// did not change can be reused. New files are only queued once the project
+    /// has embeddings at all, so indexing a project that never ran `/embed` does
+    /// not build a backlog.
+    fn invalidate_embeddings(&self, path: &str, is_new: bool) -> Result<()> {
+        let had_embedding = self.conn
+            .query_row("SELECT 1 FROM embeddings WHERE path = ?1", params![path], |_| Ok(()))
+            .is_ok();
+
+        if had_embedding || (is_new && self.has_project_embeddings()) {
+            self.conn.execute(
+                "INSERT OR REPLACE INTO embedding_queue (path, queued_at) VALUES (?1, ?2)",
+                params![path, Utc::now().to_rfc3339()],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn has_project_embeddings(&self) -> bool {
+        self.conn
+            .query_row(
+                "SELECT 1 FROM embeddings WHERE path LIKE ?1 ESCAPE '\\' LIMIT 1",
+                params![self.root_pattern()],
+                |_| Ok(()),
+            )
+            .is_ok()
+    }
+
+    /// Paths of this project waiting to be re-embedded, oldest first
+    pub fn pending_embeddings(&self, limit: usize) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path FROM embedding_queue WHERE path LIKE ?1 ESCAPE '\\' ORDER BY queued_at LIMIT ?2"
+        )?;
+
+        let paths = stmt
+            .query_map(params![self.root_pattern(), limit as i64], |row| row.get(0))?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(paths)
+    }
+
+    /// Number of this project's files waiting to be re-embedded
+    pub fn pending_embedding_count(&self) -> Result<usize> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM embedding_queue WHERE path LIKE ?1 ESCAPE '\\'",
+            params![self.root_pattern()],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    /// Remove a file from the re-embed queue once its vectors are current
+    pub fn dequeue_embedding(&self, path: &str) -> Result<()> {
+        self.conn.execute("DELETE FROM embedding_queue WHERE path = ?1", params![path])?;
+        Ok(())
+    }
+
+    /// Count one interaction (`ask`, `edit`, `view`) with an indexed file
+    pub fn record_interaction(&self, path: &str, kind: &str) -> Result<()> {
+        let Some(file) = self.get_file(path)? else {
+            return Ok(());
+        };
+
+        self.conn.execute(
+            "INSERT INTO file_interactions (path, kind, count, last_at) VALUES (?1, ?2, 1, ?3)
+             ON CONFLICT(path, kind) DO UPDATE SET count = count + 1, last_at = ?3",
+            params![file.path, kind, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// Total interactions per file of this project, keyed by absolute path
+    pub fn interaction_counts(&self) -> Result<HashMap<String, usize>> {
+        let pattern = self.root_pattern();
+
+        let counts = self.conn
+            .prepare("SELECT path, SUM(count) FROM file_interactions WHERE path LIKE ?1 ESCAPE '\\' GROUP BY path")?
+            .query_map(params![pattern], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as usize)))?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(counts)
     }
 
-    pub fn store_embedding(&self, path: &str, embedding: &[f32]) -> Result<()> {
-        let embedding_bytes: Vec<u8> = embedding
-            .iter()
-            .flat_map(|f| f.to_le_bytes())
+    /// Files the user works with most, as (relative path, interactions)
+    pub fn most_used_files(&self, limit: usize) -> Result<Vec<(String, usize)>> {
+        let mut counts: Vec<(String, usize)> = self
+            .interaction_counts()?
+            .into_iter()
+            .filter_map(|(path, count)| Some((self.get_file(&path).ok()??.relative_path, count)))
             .collect();
 
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts.truncate(limit);
+        Ok(counts)
+    }
+
+    pub fn store_answer(&self, cached: &CachedAnswer) -> Result<()> {
+        let embedding_bytes: Option<Vec<u8>> = cached
+            .embedding
+            .as_ref()
+            .map(|e| e.iter().flat_map(|f| f.to_le_bytes()).collect());
+
         self.conn.execute(
-            "INSERT OR REPLACE INTO embeddings (path, embedding, created_at)
-             VALUES (?1, ?2, ?3)",
+            "INSERT OR REPLACE INTO answer_cache (root, question, answer, embedding, sources, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
             params![
-                path,
+                self.root_path.to_string_lossy(),
+                cached.question,
+                cached.answer,
                 embedding_bytes,
-                chrono::Utc::now().to_rfc3339(),
+                serde_json::to_string(&cached.sources)?,
+                cached.created_at.to_rfc3339(),
             ],
         )?;
 
         Ok(())
     }
 
-    pub fn get_all_embeddings(&self) -> Result<Vec<(String, Vec<f32>)>> {
+    /// Cached answers for this project, most recent first
+    pub fn cached_answers(&self, limit: usize) -> Result<Vec<CachedAnswer>> {
         let mut stmt = self.conn.prepare(
-            "SELECT path, embedding FROM embeddings"
+            "SELECT question, answer, embedding, sources, created_at FROM answer_cache
+             WHERE root = ?1 ORDER BY created_at DESC LIMIT ?2",
         )?;
 
-        let results = stmt
-            .query_map([], |row| {
-                let path: String = row.get(0)?;
-                let embedding_bytes: Vec<u8> = row.get(1)?;
+        let answers = stmt
+            .query_map(params![self.root_path.to_string_lossy(), limit as i64], |row| {
+                let embedding_bytes: Option<Vec<u8>> = row.get(2)?;
+                let embedding = embedding_bytes.map(|bytes| {
+                    bytes
+                        .chunks(4)
+                        .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap_or([0; 4])))
+                        .collect()
+                });
+                let sources_json: String = row.get(3)?;
+                let created_str: String = row.get(4)?;
+
+                Ok(CachedAnswer {
+                    question: row.get(0)?,
+                    answer: row.get(1)?,
+                    embedding,
+                    sources: serde_json::from_str(&sources_json).unwrap_or_default(),
+                    created_at: DateTime::parse_from_rfc3339(&created_str)
+                        .map(|dt| dt.with_timezone(&Utc))
+                        .unwrap_or_else(|_| Utc::now()),
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
 
-                // Convert bytes back to f32
-                let embedding: Vec<f32> = embedding_bytes
-                    .chunks(4)
-                    .map(|chunk| {
-                        let bytes: [u8; 4] = chunk.try_into().unwrap_or([0; 4]);
-                        f32::from_le_bytes(bytes)
-                    })
-                    .collect();
+        Ok(answers)
+    }
+
+    /// Add or redefine a glossary term of this project
+    pub fn store_glossary_entry(&self, entry: &GlossaryEntry) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO glossary (root, term, definition, sources, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                self.root_path.to_string_lossy(),
+                entry.term,
+                entry.definition,
+                serde_json::to_string(&entry.sources)?,
+                Utc::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
 
-                Ok((path, embedding))
+    /// This project's glossary, alphabetically
+    pub fn glossary(&self) -> Result<Vec<GlossaryEntry>> {
+        let entries = self.conn
+            .prepare("SELECT term, definition, sources FROM glossary WHERE root = ?1 ORDER BY lower(term)")?
+            .query_map(params![self.root_path.to_string_lossy()], |row| {
+                let sources_json: String = row.get(2)?;
+                Ok(GlossaryEntry {
+                    term: row.get(0)?,
+                    definition: row.get(1)?,
+                    sources: serde_json::from_str(&sources_json).unwrap_or_default(),
+                })
             })?
             .filter_map(|r| r.ok())
             .collect();
+        Ok(entries)
+    }
 
-        Ok(results)
+    /// Whether every file a cached answer cited is unchanged since it was generated
+    pub fn answer_is_current(&self, cached: &CachedAnswer) -> bool {
+        cached.sources.iter().all(|(path, hash)| {
+            matches!(self.get_file(path), Ok(Some(file)) if file.hash == *hash)
+        })
     }
 
+    /// Whether the file has vectors built from its current content
     pub fn has_embedding(&self, path: &str) -> bool {
         self.conn
             .query_row(
-                "SELECT 1 FROM embeddings WHERE path = ?1",
+                "SELECT 1 FROM embeddings WHERE path = ?1 AND path NOT IN (SELECT path FROM embedding_queue)",
                 params![path],
                 |_| Ok(()),
             )
@@ -140,72 +1115,354 @@ impl CodebaseIndex {
     }
 
     pub fn index_directory(&self, show_progress: bool) -> Result<usize> {
-        let mut count = 0;
+        let changes = self.index_changes(show_progress)?;
+        Ok(changes.added.len() + changes.modified.len())
+    }
+
+    /// Index the directory and report which files were added, changed or removed
+    ///
+    /// Files are read, hashed and parsed on all cores in batches of `INDEX_BATCH`;
+    /// each batch is then written in a single transaction.
+    pub fn index_changes(&self, show_progress: bool) -> Result<IndexChanges> {
+        if self.dry_run {
+            let changes = self.planned_changes()?;
+            dry_run::report(format!(
+                "index {} new and {} changed files, and remove {} from the index{}",
+                changes.added.len(),
+                changes.modified.len(),
+                changes.removed.len(),
+                if changes == IndexChanges::default() { "" } else { ":" }
+            ));
+            for (mark, paths) in [("+", &changes.added), ("~", &changes.modified), ("-", &changes.removed)] {
+                for path in paths {
+                    eprintln!("  {} {}", mark, path);
+                }
+            }
+            return Ok(changes);
+        }
+
+        let mut changes = IndexChanges::default();
+        let existing = self.file_hashes()?;
+        let leaving = self.switched_from()?;
+        let workers = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+
+        let files = self.indexable_files();
+        let show_batches = show_progress && files.len() > INDEX_BATCH;
+
+        for batch in files.chunks(INDEX_BATCH) {
+            let parsed = self.parse_files(batch, &existing, workers);
+            if parsed.is_empty() {
+                continue;
+            }
+
+            let tx = self.conn.unchecked_transaction()?;
+            for file in parsed {
+                let previous_hash = existing.get(&file.indexed.path).map(String::as_str);
+                let added = previous_hash.is_none();
+                let relative_path = file.indexed.relative_path.clone();
+                // An unwritable file is skipped, as an unreadable one is
+                if self.store_file(file, previous_hash, leaving.as_deref()).is_err() {
+                    continue;
+                }
+                if added {
+                    changes.added.push(relative_path);
+                } else {
+                    changes.modified.push(relative_path);
+                }
+            }
+            tx.commit()?;
+
+            if show_batches {
+                jobs::progress(format!("Indexed {} files...", changes.added.len() + changes.modified.len()));
+            }
+        }
+
+        // Files deleted (or excluded) since the last run would otherwise stay searchable forever
+        let keep = |path: &str| Path::new(path).exists() && !self.is_excluded(path);
+        if let Some(ref branch) = leaving {
+            for (path, hash) in existing.iter().filter(|(path, _)| !keep(path)) {
+                self.save_branch_vectors(branch, path, hash)?;
+            }
+        }
+        let pruned = self.prune(keep)?;
+        if show_progress && !pruned.is_empty() {
+            println!("  Removed {} deleted or excluded files from the index", pruned.len());
+        }
+        changes.removed = pruned.iter().map(|path| self.relative(path)).collect();
+
+        // Imports resolve against the whole file set, so the graph is rebuilt after the walk
+        self.rebuild_dependencies()?;
+
+        if let Some(ref branch) = self.branch {
+            self.conn.execute(
+                "INSERT OR REPLACE INTO indexed_branches (root, branch, indexed_at) VALUES (?1, ?2, ?3)",
+                params![self.root_path.to_string_lossy(), branch, Utc::now().to_rfc3339()],
+            )?;
+        }
+
+        Ok(changes)
+    }
+
+    /// What `index_changes` would add, modify and remove, without writing anything
+    pub fn planned_changes(&self) -> Result<IndexChanges> {
+        let mut changes = IndexChanges::default();
+        let existing = self.file_hashes()?;
+        let workers = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+
+        for batch in self.indexable_files().chunks(INDEX_BATCH) {
+            for file in self.parse_files(batch, &existing, workers) {
+                if existing.contains_key(&file.indexed.path) {
+                    changes.modified.push(file.indexed.relative_path);
+                } else {
+                    changes.added.push(file.indexed.relative_path);
+                }
+            }
+        }
+
+        let keep = |path: &str| Path::new(path).exists() && !self.is_excluded(path);
+        changes.removed = self.stale_files(keep)?.iter().map(|path| self.relative(path)).collect();
+        Ok(changes)
+    }
+
+    /// `path` relative to the project root
+    fn relative(&self, path: &str) -> String {
+        Path::new(path)
+            .strip_prefix(&self.root_path)
+            .unwrap_or(Path::new(path))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Files under the root that indexing would visit, with their language
+    fn indexable_files(&self) -> Vec<(PathBuf, String)> {
+        let excludes = self.excludes.clone();
         let walker = WalkBuilder::new(&self.root_path)
             .hidden(false)
             .git_ignore(true)
             .git_global(true)
             .git_exclude(true)
+            .filter_entry(move |entry| {
+                let is_dir = entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
+                !excludes.is_excluded(entry.path(), is_dir)
+            })
             .build();
 
-        for entry in walker.flatten() {
-            if entry.file_type().map(|ft| ft.is_file()).unwrap_or(false) {
-                let path = entry.path();
-                if let Some(lang) = Self::detect_language(path) {
-                    if let Ok(_indexed) = self.index_file(path, &lang) {
-                        count += 1;
-                        if show_progress && count % 100 == 0 {
-                            println!("  Indexed {} files...", count);
+        walker
+            .flatten()
+            .filter(|entry| entry.file_type().map(|ft| ft.is_file()).unwrap_or(false))
+            .filter_map(|entry| {
+                let lang = Self::detect_language(entry.path())?;
+                Some((entry.into_path(), lang))
+            })
+            .collect()
+    }
+
+    /// Remove every indexed file of this project that indexing would no longer
+    /// visit: deleted, newly ignored, or no longer a supported language
+    pub fn prune_unindexable(&self) -> Result<usize> {
+        let visited: HashSet<String> = self
+            .indexable_files()
+            .into_iter()
+            .map(|(path, _)| path.to_string_lossy().to_string())
+            .collect();
+
+        if self.dry_run {
+            let stale = self.stale_files(|path| visited.contains(path))?;
+            dry_run::report(format!("remove {} files that are no longer indexable from the index", stale.len()));
+            for path in &stale {
+                eprintln!("  - {}", self.relative(path));
+            }
+            return Ok(stale.len());
+        }
+
+        let pruned = self.prune(|path| visited.contains(path))?;
+        self.rebuild_dependencies()?;
+        Ok(pruned.len())
+    }
+
+    /// Remove this project's files for which `keep` is false from every table,
+    /// returning their paths
+    fn prune(&self, keep: impl Fn(&str) -> bool) -> Result<Vec<String>> {
+        let stale = self.stale_files(keep)?;
+        if stale.is_empty() {
+            return Ok(stale);
+        }
+
+        let tx = self.conn.unchecked_transaction()?;
+        for path in &stale {
+            for sql in [
+                "DELETE FROM files WHERE path = ?1",
+                "DELETE FROM files_fts WHERE path = ?1",
+                "DELETE FROM embeddings WHERE path = ?1",
+                "DELETE FROM embedding_queue WHERE path = ?1",
+                "DELETE FROM imports WHERE path = ?1",
+                "DELETE FROM dependencies WHERE source = ?1 OR target = ?1",
+                "DELETE FROM symbols WHERE path = ?1",
+                "DELETE FROM file_interactions WHERE path = ?1",
+            ] {
+                tx.execute(sql, params![path])?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(stale)
+    }
+
+    /// This project's indexed files for which `keep` is false
+    fn stale_files(&self, keep: impl Fn(&str) -> bool) -> Result<Vec<String>> {
+        let pattern = self.root_pattern();
+        let stale = self.conn
+            .prepare("SELECT path FROM files WHERE path LIKE ?1 ESCAPE '\\'")?
+            .query_map(params![pattern], |row| row.get::<_, String>(0))?
+            .filter_map(|r| r.ok())
+            .filter(|path| !keep(path))
+            .collect();
+        Ok(stale)
+    }
+
+    /// Resolve every stored import of this project into file-to-file edges
+    pub fn rebuild_dependencies(&self) -> Result<usize> {
+        // Trailing separator so /src/app does not also match /src/app2
+        let pattern = self.root_pattern();
+
+        let files: Vec<(String, String, String)> = self.conn
+            .prepare("SELECT path, relative_path, language FROM files WHERE path LIKE ?1 ESCAPE '\\'")?
+            .query_map(params![pattern], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        let relative: HashSet<String> = files.iter().map(|(_, rel, _)| rel.replace('\\', "/")).collect();
+        let absolute: HashMap<String, String> = files
+            .iter()
+            .map(|(path, rel, _)| (rel.replace('\\', "/"), path.clone()))
+            .collect();
+
+        let mut edges: HashSet<(String, String)> = HashSet::new();
+        let mut stmt = self.conn.prepare("SELECT specifier FROM imports WHERE path = ?1")?;
+        for (path, rel, language) in &files {
+            let specifiers: Vec<String> = stmt
+                .query_map(params![path], |row| row.get(0))?
+                .filter_map(|r| r.ok())
+                .collect();
+
+            let rel = rel.replace('\\', "/");
+            for spec in specifiers {
+                for target in imports::resolve_import(&rel, &spec, language, &relative) {
+                    if target != rel {
+                        if let Some(target_path) = absolute.get(&target) {
+                            edges.insert((path.clone(), target_path.clone()));
                         }
                     }
                 }
             }
         }
 
-        Ok(count)
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM dependencies WHERE source LIKE ?1 ESCAPE '\\'", params![pattern])?;
+        for (source, target) in &edges {
+            tx.execute(
+                "INSERT OR IGNORE INTO dependencies (source, target) VALUES (?1, ?2)",
+                params![source, target],
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(edges.len())
     }
 
-    fn index_file(&self, path: &Path, language: &str) -> Result<IndexedFile> {
-        let content = fs::read_to_string(path).unwrap_or_default();
-        let hash = Self::compute_hash(&content);
+    /// Files that `path` imports, as relative paths
+    pub fn dependencies(&self, path: &str) -> Result<Vec<String>> {
+        self.dependency_query(
+            "SELECT f.relative_path FROM dependencies d JOIN files f ON f.path = d.target
+             WHERE d.source = ?1 ORDER BY f.relative_path",
+            path,
+        )
+    }
 
-        // Check if file already indexed with same hash
-        let existing_hash: Option<String> = self.conn
-            .query_row(
-                "SELECT hash FROM files WHERE path = ?1",
-                params![path.to_string_lossy().to_string()],
-                |row| row.get(0),
-            )
-            .ok();
+    /// Files that import `path`, as relative paths
+    pub fn dependents(&self, path: &str) -> Result<Vec<String>> {
+        self.dependency_query(
+            "SELECT f.relative_path FROM dependencies d JOIN files f ON f.path = d.source
+             WHERE d.target = ?1 ORDER BY f.relative_path",
+            path,
+        )
+    }
 
-        if existing_hash.as_ref() == Some(&hash) {
-            // File unchanged, skip
-            return Err(anyhow::anyhow!("File unchanged"));
-        }
+    fn dependency_query(&self, sql: &str, path: &str) -> Result<Vec<String>> {
+        let file = self.get_file(path)?
+            .ok_or_else(|| anyhow::anyhow!("File not found in index: {}", path))?;
 
-        let relative_path = path
-            .strip_prefix(&self.root_path)
-            .unwrap_or(path)
-            .to_string_lossy()
-            .to_string();
+        let paths = self.conn
+            .prepare(sql)?
+            .query_map(params![file.path], |row| row.get(0))?
+            .filter_map(|r| r.ok())
+            .collect();
 
-        let symbols = Self::extract_symbols(&content, language);
-        let size = content.len() as u64;
-        let lines = content.lines().count();
+        Ok(paths)
+    }
 
-        let indexed = IndexedFile {
-            path: path.to_string_lossy().to_string(),
-            relative_path,
-            language: language.to_string(),
-            size,
-            lines,
-            hash,
-            summary: None,
-            symbols,
-            indexed_at: Utc::now(),
-            embedding: None,
-        };
+    /// Content hashes of this project's indexed files, keyed by absolute path
+    fn file_hashes(&self) -> Result<HashMap<String, String>> {
+        let pattern = self.root_pattern();
+        let hashes = self.conn
+            .prepare("SELECT path, hash FROM files WHERE path LIKE ?1 ESCAPE '\\'")?
+            .query_map(params![pattern], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(hashes)
+    }
+
+    /// Read and parse the files of `batch` whose hash changed, on `workers` threads,
+    /// in the order given; unreadable files are left out
+    fn parse_files(
+        &self,
+        batch: &[(PathBuf, String)],
+        existing: &HashMap<String, String>,
+        workers: usize,
+    ) -> Vec<ParsedFile> {
+        // The connection stays on this thread; workers only touch the filesystem
+        let root_path = self.root_path.as_path();
+        let max_file_size = self.max_file_size;
+        let tokenizer = self.fts_tokenizer.as_str();
+        let next = AtomicUsize::new(0);
+
+        let mut parsed: Vec<(usize, ParsedFile)> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers.clamp(1, batch.len().max(1)))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some((path, language)) = batch.get(i) else { break };
+                            let previous = existing.get(path.to_string_lossy().as_ref());
+                            let file = ParsedFile::read(path, language, root_path, max_file_size, tokenizer, previous);
+                            if let Ok(Some(file)) = file {
+                                done.push((i, file));
+                            }
+                        }
+                        done
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap_or_default())
+                .collect()
+        });
 
+        parsed.sort_by_key(|(i, _)| *i);
+        parsed.into_iter().map(|(_, file)| file).collect()
+    }
+
+    /// Write a parsed file, replacing what was stored for it (with `previous_hash`);
+    /// `leaving` is the branch switched away from, if the project changed branch
+    fn store_file(&self, file: ParsedFile, previous_hash: Option<&str>, leaving: Option<&str>) -> Result<()> {
+        let ParsedFile { indexed, content, definitions, imports, terms } = file;
+        let is_new = previous_hash.is_none();
+        if let (Some(branch), Some(hash)) = (leaving, previous_hash) {
+            self.save_branch_vectors(branch, &indexed.path, hash)?;
+        }
         let symbols_json = serde_json::to_string(&indexed.symbols)?;
 
         self.conn.execute(
@@ -224,14 +1481,53 @@ impl CodebaseIndex {
             ],
         )?;
 
-        // Update FTS index
+        // Update FTS index (FTS5 has no unique key, so drop the old row first)
         self.conn.execute(
-            "INSERT OR REPLACE INTO files_fts (path, content, symbols)
-             VALUES (?1, ?2, ?3)",
-            params![indexed.path, content, symbols_json],
+            "DELETE FROM files_fts WHERE path = ?1",
+            params![indexed.path],
         ).ok();
+        if content.is_some() {
+            self.conn.execute(
+                "INSERT INTO files_fts (path, content, symbols, terms)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![indexed.path, content, symbols_json, terms],
+            ).ok();
+        }
+
+        Self::store_symbols(&self.conn, &indexed.path, &definitions)?;
+
+        // Edges are resolved from these once the whole directory is indexed
+        self.conn.execute("DELETE FROM imports WHERE path = ?1", params![indexed.path])?;
+        for specifier in imports {
+            self.conn.execute(
+                "INSERT INTO imports (path, specifier) VALUES (?1, ?2)",
+                params![indexed.path, specifier],
+            )?;
+        }
+
+        // A changed file that was indexed before is one the user edited, unless
+        // checking out another branch changed it
+        if !is_new && leaving.is_none() {
+            self.record_interaction(&indexed.path, "edit")?;
+        }
+
+        // Vectors built from the old content are stale now, unless this content
+        // was embedded on another branch
+        if content.is_some() {
+            if !self.restore_branch_vectors(&indexed.path, &indexed.hash)? {
+                self.invalidate_embeddings(&indexed.path, is_new)?;
+            }
+        } else {
+            self.delete_embeddings(&indexed.path)?;
+            self.dequeue_embedding(&indexed.path)?;
+        }
+
+        Ok(())
+    }
 
-        Ok(indexed)
+    /// Whether a file of this name would be indexed, judging by its extension
+    pub fn is_indexable(path: &Path) -> bool {
+        Self::detect_language(path).is_some()
     }
 
     fn detect_language(path: &Path) -> Option<String> {
@@ -266,90 +1562,154 @@ impl CodebaseIndex {
         Some(lang.to_string())
     }
 
-    fn compute_hash(content: &str) -> String {
+    pub(crate) fn compute_hash(content: &str) -> String {
         let mut hasher = Sha256::new();
         hasher.update(content.as_bytes());
         hex::encode(hasher.finalize())
     }
 
-    fn extract_symbols(content: &str, language: &str) -> Vec<String> {
+    pub(crate) fn extract_symbols(content: &str, language: &str) -> Vec<String> {
+        Self::extract_definitions(content, language)
+            .into_iter()
+            .map(|d| format!("{}:{}", d.kind, d.name))
+            .collect()
+    }
+
+    /// Definitions in `content` with the lines they span
+    pub(crate) fn extract_definitions(content: &str, language: &str) -> Vec<Symbol> {
+        let lines: Vec<&str> = content.lines().collect();
         let mut symbols = Vec::new();
 
         // Simple regex-free symbol extraction
-        for line in content.lines() {
+        for (i, line) in lines.iter().enumerate() {
             let trimmed = line.trim();
 
-            match language {
-                "rust" => {
-                    if trimmed.starts_with("fn ") || trimmed.starts_with("pub fn ") {
-                        if let Some(name) = Self::extract_fn_name(trimmed, "fn ") {
-                            symbols.push(format!("fn:{}", name));
-                        }
-                    } else if trimmed.starts_with("struct ") || trimmed.starts_with("pub struct ") {
-                        if let Some(name) = Self::extract_after(trimmed, "struct ") {
-                            symbols.push(format!("struct:{}", name));
-                        }
-                    } else if trimmed.starts_with("enum ") || trimmed.starts_with("pub enum ") {
-                        if let Some(name) = Self::extract_after(trimmed, "enum ") {
-                            symbols.push(format!("enum:{}", name));
-                        }
-                    } else if trimmed.starts_with("impl ") {
-                        if let Some(name) = Self::extract_after(trimmed, "impl ") {
-                            symbols.push(format!("impl:{}", name));
-                        }
-                    }
+            if let Some((kind, name)) = Self::definition_at(trimmed, language) {
+                symbols.push(Symbol {
+                    name,
+                    kind: kind.to_string(),
+                    path: String::new(),
+                    relative_path: String::new(),
+                    start_line: i + 1,
+                    end_line: definition_end(&lines, i, language) + 1,
+                    signature: trimmed.trim_end_matches('{').trim_end().to_string(),
+                });
+            }
+        }
+
+        symbols
+    }
+
+    /// Kind and name of the definition starting on this (trimmed) line, if any
+    pub(crate) fn definition_at(trimmed: &str, language: &str) -> Option<(&'static str, String)> {
+        match language {
+            "rust" => {
+                if trimmed.starts_with("fn ") || trimmed.starts_with("pub fn ") {
+                    Self::extract_fn_name(trimmed, "fn ").map(|name| ("fn", name))
+                } else if trimmed.starts_with("struct ") || trimmed.starts_with("pub struct ") {
+                    Self::extract_after(trimmed, "struct ").map(|name| ("struct", name))
+                } else if trimmed.starts_with("enum ") || trimmed.starts_with("pub enum ") {
+                    Self::extract_after(trimmed, "enum ").map(|name| ("enum", name))
+                } else if trimmed.starts_with("impl ") {
+                    Self::extract_after(trimmed, "impl ").map(|name| ("impl", name))
+                } else {
+                    None
                 }
-                "python" => {
-                    if trimmed.starts_with("def ") {
-                        if let Some(name) = Self::extract_fn_name(trimmed, "def ") {
-                            symbols.push(format!("def:{}", name));
-                        }
-                    } else if trimmed.starts_with("class ") {
-                        if let Some(name) = Self::extract_after(trimmed, "class ") {
-                            symbols.push(format!("class:{}", name));
-                        }
-                    }
+            }
+            "python" => {
+                if trimmed.starts_with("def ") {
+                    Self::extract_fn_name(trimmed, "def ").map(|name| ("def", name))
+                } else if trimmed.starts_with("class ") {
+                    Self::extract_after(trimmed, "class ").map(|name| ("class", name))
+                } else {
+                    None
                 }
-                "javascript" | "typescript" => {
-                    if trimmed.starts_with("function ") {
-                        if let Some(name) = Self::extract_fn_name(trimmed, "function ") {
-                            symbols.push(format!("function:{}", name));
-                        }
-                    } else if trimmed.starts_with("class ") {
-                        if let Some(name) = Self::extract_after(trimmed, "class ") {
-                            symbols.push(format!("class:{}", name));
-                        }
-                    } else if trimmed.contains("const ") && trimmed.contains(" = ") {
-                        if let Some(name) = Self::extract_const_name(trimmed) {
-                            symbols.push(format!("const:{}", name));
-                        }
-                    }
+            }
+            "javascript" | "typescript" => {
+                if trimmed.starts_with("function ") {
+                    Self::extract_fn_name(trimmed, "function ").map(|name| ("function", name))
+                } else if trimmed.starts_with("class ") {
+                    Self::extract_after(trimmed, "class ").map(|name| ("class", name))
+                } else if trimmed.contains("const ") && trimmed.contains(" = ") {
+                    Self::extract_const_name(trimmed).map(|name| ("const", name))
+                } else {
+                    None
                 }
-                "go" => {
-                    if trimmed.starts_with("func ") {
-                        if let Some(name) = Self::extract_fn_name(trimmed, "func ") {
-                            symbols.push(format!("func:{}", name));
-                        }
-                    } else if trimmed.starts_with("type ") && trimmed.contains(" struct") {
-                        if let Some(name) = Self::extract_after(trimmed, "type ") {
-                            symbols.push(format!("struct:{}", name));
-                        }
-                    }
+            }
+            "go" => {
+                if trimmed.starts_with("func ") {
+                    Self::extract_fn_name(trimmed, "func ").map(|name| ("func", name))
+                } else if trimmed.starts_with("type ") && trimmed.contains(" struct") {
+                    Self::extract_after(trimmed, "type ").map(|name| ("struct", name))
+                } else {
+                    None
                 }
-                "java" | "kotlin" => {
-                    if (trimmed.contains("class ") || trimmed.contains("interface "))
-                        && !trimmed.starts_with("//")
-                    {
-                        if let Some(name) = Self::extract_java_class(trimmed) {
-                            symbols.push(format!("class:{}", name));
-                        }
-                    }
+            }
+            "java" => {
+                if (trimmed.contains("class ") || trimmed.contains("interface "))
+                    && !trimmed.starts_with("//")
+                {
+                    Self::extract_java_class(trimmed).map(|name| ("class", name))
+                } else {
+                    None
                 }
-                _ => {}
             }
+            "kotlin" => declared_name(trimmed, &["fun", "class", "interface", "object"]),
+            "swift" => declared_name(trimmed, &["func", "class", "struct", "enum", "protocol", "extension"]),
+            "php" => declared_name(trimmed, &["function", "class", "interface", "trait", "enum"]),
+            // `class << self` opens the singleton class, not a new one
+            "ruby" if trimmed.starts_with("class <<") => None,
+            "ruby" => declared_name(trimmed, &["def", "class", "module"]),
+            "c" | "cpp" | "csharp" => {
+                // Prototypes, forward declarations and statements end with `;`
+                if trimmed.ends_with(';') {
+                    return None;
+                }
+                let types: &[&'static str] = match language {
+                    "c" => &["struct", "union", "enum"],
+                    "cpp" => &["class", "struct", "union", "enum", "namespace"],
+                    _ => &["class", "interface", "struct", "enum", "record", "namespace"],
+                };
+                // `struct node *next_node(...)` is a function returning a struct
+                Self::extract_c_function(trimmed)
+                    .map(|name| ("function", name))
+                    .or_else(|| declared_name(trimmed, types))
+            }
+            "shell" => {
+                if trimmed.starts_with("function ") {
+                    declared_name(trimmed, &["function"])
+                } else {
+                    let name = trimmed.split("()").next().filter(|_| trimmed.contains("()"))?.trim();
+                    let valid = !name.is_empty()
+                        && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.');
+                    valid.then(|| ("function", name.to_string()))
+                }
+            }
+            _ => None,
         }
+    }
 
-        symbols
+    /// Name of the function a C-family line defines: `static int *parse(...)`,
+    /// `std::string Config::name() const`, `public async Task<int> Run(...)`
+    ///
+    /// There must be a return type or modifier before the name, which tells
+    /// definitions apart from calls.
+    fn extract_c_function(line: &str) -> Option<String> {
+        if line.starts_with('#') || line.starts_with("//") || line.starts_with('*') || line.starts_with("/*") {
+            return None;
+        }
+        let head = &line[..line.find('(')?];
+        if head.contains(['=', '"', '.']) || head.contains("->") {
+            return None;
+        }
+        let words: Vec<&str> = head.split_whitespace().collect();
+        if words.len() < 2 || words.iter().any(|w| NOT_FUNCTION_WORDS.contains(w)) {
+            return None;
+        }
+        let name = words.last()?.rsplit("::").next()?.trim_start_matches(['*', '&']);
+        let valid = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+        valid.then(|| name.to_string())
     }
 
     fn extract_fn_name(line: &str, prefix: &str) -> Option<String> {
@@ -414,89 +1774,332 @@ impl CodebaseIndex {
         None
     }
 
-    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<IndexedFile>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT f.path, f.relative_path, f.language, f.size, f.hash, f.summary, f.symbols, f.indexed_at, f.content
-             FROM files f
-             JOIN files_fts fts ON f.path = fts.path
-             WHERE files_fts MATCH ?1
-             LIMIT ?2",
-        )?;
+    /// This project's files matching an FTS5 query, best bm25 rank first, with the lines that matched
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<TextMatch>> {
+        self.search_filtered(query, &SearchFilters::default(), limit)
+    }
 
-        let files = stmt
-            .query_map(params![query, limit as i64], |row| {
+    /// This project's files matching an FTS5 query within `filters`, best bm25 rank first
+    pub fn search_filtered(&self, query: &str, filters: &SearchFilters, limit: usize) -> Result<Vec<TextMatch>> {
+        let root = self.root_pattern();
+        let (conditions, values) = filters.sql(3);
+
+        // snippet() finds the best passage, verbatim so its lines can be located;
+        // highlight() marks the query terms within those lines
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT f.path, f.relative_path, f.language, f.size, f.hash, f.summary, f.symbols, f.indexed_at, f.content,
+                    bm25(files_fts) AS rank,
+                    snippet(files_fts, 1, '', '', '', 16),
+                    highlight(files_fts, 1, char(2), char(3))
+             FROM files_fts
+             JOIN files f ON f.path = files_fts.path
+             WHERE files_fts MATCH ?1 AND f.path LIKE ?2 ESCAPE '\\'{}
+             ORDER BY rank
+             LIMIT {}",
+            conditions, limit as i64
+        ))?;
+
+        let mut seen = std::collections::HashSet::new();
+        let matches = stmt
+            .query_map(params_from_iter([query.to_string(), root].into_iter().chain(values)), |row| {
                 let symbols_json: String = row.get(6)?;
                 let symbols: Vec<String> = serde_json::from_str(&symbols_json).unwrap_or_default();
                 let indexed_str: String = row.get(7)?;
                 let indexed_at = DateTime::parse_from_rfc3339(&indexed_str)
                     .map(|dt| dt.with_timezone(&Utc))
                     .unwrap_or_else(|_| Utc::now());
-                let content: Option<String> = row.get(8).ok();
-                let lines = content.map(|c| c.lines().count()).unwrap_or(0);
+                let content: String = row.get::<_, Option<String>>(8)?.unwrap_or_default();
+                let fragment: String = row.get::<_, Option<String>>(10)?.unwrap_or_default();
+                let highlighted: String = row.get::<_, Option<String>>(11)?.unwrap_or_default();
+
+                // The passage narrowed to the lines from its first to its last match
+                let (first, last) = fragment_lines(&content, &fragment);
+                let passage: Vec<&str> = highlighted.lines().skip(first - 1).take(last + 1 - first).collect();
+                let matched = |line: &&str| line.contains('\u{2}');
+                let start = passage.iter().position(matched).unwrap_or(0);
+                let end = passage.iter().rposition(matched).unwrap_or(start).min(start + MAX_EXCERPT_LINES - 1);
+                let excerpt = passage
+                    .get(start..=end)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|line| line.trim_end().replace(['\u{2}', '\u{3}'], "**"))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let (start_line, end_line) = (first + start, first + end);
+
+                let file = IndexedFile {
+                    path: row.get(0)?,
+                    relative_path: row.get(1)?,
+                    language: row.get(2)?,
+                    size: row.get(3)?,
+                    lines: content.lines().count(),
+                    hash: row.get(4)?,
+                    summary: row.get(5)?,
+                    symbols,
+                    indexed_at,
+                };
+                Ok(TextMatch { file, excerpt, start_line, end_line })
+            })?
+            .filter_map(|r| r.ok())
+            // Databases from before FTS rows were replaced may hold duplicates
+            .filter(|m| seen.insert(m.file.path.clone()))
+            .collect();
+
+        Ok(matches)
+    }
+
+    /// Files matching any of `terms`, best bm25 rank first, with the matching passage
+    pub fn search_keywords(&self, terms: &[&str], limit: usize) -> Result<Vec<KeywordHit>> {
+        let Some(query) = fts_any_query(terms) else {
+            return Ok(Vec::new());
+        };
+
+        let mut stmt = self.conn.prepare(
+            "SELECT f.path, f.language, f.content, bm25(files_fts) AS rank,
+                    snippet(files_fts, 1, '', '', '', 48)
+             FROM files_fts
+             JOIN files f ON f.path = files_fts.path
+             WHERE files_fts MATCH ?1
+             ORDER BY rank
+             LIMIT ?2",
+        )?;
+
+        let mut seen = std::collections::HashSet::new();
+        let hits = stmt
+            .query_map(params![query, limit as i64], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                    row.get::<_, f64>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            })?
+            .filter_map(|r| r.ok())
+            // Databases from before FTS rows were replaced may hold duplicates
+            .filter(|(path, ..)| seen.insert(path.clone()))
+            .map(|(path, language, content, rank, fragment)| {
+                let (start_line, end_line) = fragment_lines(&content, &fragment);
+                let snippet = content
+                    .lines()
+                    .skip(start_line - 1)
+                    .take(end_line + 1 - start_line)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                KeywordHit { path, language, rank, content, snippet, start_line, end_line }
+            })
+            .collect();
+
+        Ok(hits)
+    }
+
+    /// Indexed files that have no full-text row (e.g. the FTS insert failed)
+    pub fn files_missing_fts(&self) -> Result<Vec<IndexedFile>> {
+        let paths: Vec<String> = self.conn
+            .prepare("SELECT path FROM files WHERE path NOT IN (SELECT path FROM files_fts)")?
+            .query_map([], |row| row.get(0))?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        let mut files = Vec::new();
+        for path in paths {
+            if let Some(file) = self.get_file(&path)? {
+                files.push(file);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Files of this project defining symbols that match `symbol`, best match first
+    pub fn search_by_symbol(&self, symbol: &str, limit: usize) -> Result<Vec<IndexedFile>> {
+        let mut seen = HashSet::new();
+        let mut files = Vec::new();
+
+        for (matched, _) in self.match_symbols(symbol, usize::MAX)? {
+            if files.len() >= limit {
+                break;
+            }
+            if seen.insert(matched.path.clone()) {
+                if let Some(file) = self.get_file(&matched.path)? {
+                    files.push(file);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Symbols of this project matching `query`, ranked exact > prefix > fuzzy
+    ///
+    /// Matching ignores case and `snake_case`/`camelCase` differences, so
+    /// `get staged diff`, `GetStagedDiff` and `get_staged_diff` are the same name.
+    pub fn match_symbols(&self, query: &str, limit: usize) -> Result<Vec<(Symbol, f32)>> {
+        let root = self.root_pattern();
+        let mut matches: Vec<(Symbol, f32)> = self.conn
+            .prepare(
+                "SELECT s.path, f.relative_path, s.name, s.kind, s.start_line, s.end_line, s.signature
+                 FROM symbols s
+                 JOIN files f ON f.path = s.path
+                 WHERE s.path LIKE ?1 ESCAPE '\\'",
+            )?
+            .query_map(params![root], symbol_from_row)?
+            .filter_map(|r| r.ok())
+            .filter_map(|symbol| symbol_match_score(query, &symbol.name).map(|score| (symbol, score)))
+            .collect();
+
+        matches.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.0.relative_path.cmp(&b.0.relative_path))
+                .then(a.0.start_line.cmp(&b.0.start_line))
+        });
+        matches.truncate(limit);
+        Ok(matches)
+    }
+
+    /// This project's files whose relative path fuzzily matches `query`, best first
+    pub fn match_paths(&self, query: &str, limit: usize) -> Result<Vec<(String, f32)>> {
+        let mut matches: Vec<(String, f32)> = self.conn
+            .prepare("SELECT relative_path FROM files WHERE path LIKE ?1 ESCAPE '\\'")?
+            .query_map(params![self.root_pattern()], |row| row.get::<_, String>(0))?
+            .filter_map(|r| r.ok())
+            .filter_map(|path| path_match_score(query, &path).map(|score| (path, score)))
+            .collect();
+
+        matches.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.0.len().cmp(&b.0.len()))
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        matches.truncate(limit);
+        Ok(matches)
+    }
+
+    /// Innermost definition in a file whose line range contains `line`
+    pub fn enclosing_symbol(&self, path: &str, line: usize) -> Result<Option<Symbol>> {
+        let symbol = self.conn
+            .query_row(
+                "SELECT s.path, f.relative_path, s.name, s.kind, s.start_line, s.end_line, s.signature
+                 FROM symbols s
+                 JOIN files f ON f.path = s.path
+                 WHERE s.path = ?1 AND s.start_line <= ?2 AND s.end_line >= ?2
+                 ORDER BY s.end_line - s.start_line
+                 LIMIT 1",
+                params![path, line as i64],
+                symbol_from_row,
+            )
+            .ok();
+        Ok(symbol)
+    }
 
-                Ok(IndexedFile {
-                    path: row.get(0)?,
-                    relative_path: row.get(1)?,
-                    language: row.get(2)?,
-                    size: row.get(3)?,
-                    lines,
-                    hash: row.get(4)?,
-                    summary: row.get(5)?,
-                    symbols,
-                    indexed_at,
-                    embedding: None,
-                })
-            })?
+    /// Definitions in one file, in line order
+    pub fn file_symbols(&self, path: &str) -> Result<Vec<Symbol>> {
+        let symbols = self.conn
+            .prepare(
+                "SELECT s.path, f.relative_path, s.name, s.kind, s.start_line, s.end_line, s.signature
+                 FROM symbols s
+                 JOIN files f ON f.path = s.path
+                 WHERE f.path = ?1 OR (f.relative_path = ?1 AND f.path LIKE ?2 ESCAPE '\\')
+                 ORDER BY s.start_line",
+            )?
+            .query_map(params![path, self.root_pattern()], symbol_from_row)?
             .filter_map(|r| r.ok())
             .collect();
 
-        Ok(files)
+        Ok(symbols)
     }
 
-    pub fn search_by_symbol(&self, symbol: &str, limit: usize) -> Result<Vec<IndexedFile>> {
-        let pattern = format!("%{}%", symbol);
-        let mut stmt = self.conn.prepare(
-            "SELECT path, relative_path, language, size, hash, summary, symbols, indexed_at, content
-             FROM files
-             WHERE symbols LIKE ?1
-             LIMIT ?2",
-        )?;
+    /// Absolute paths of this project's files within `filters`
+    pub fn filtered_paths(&self, filters: &SearchFilters) -> Result<HashSet<String>> {
+        let root = self.root_pattern();
+        let (conditions, values) = filters.sql(2);
+        let paths = self.conn
+            .prepare(&format!("SELECT f.path FROM files f WHERE f.path LIKE ?1 ESCAPE '\\'{}", conditions))?
+            .query_map(params_from_iter(std::iter::once(root).chain(values)), |row| row.get(0))?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(paths)
+    }
 
-        let files = stmt
-            .query_map(params![pattern, limit as i64], |row| {
-                let symbols_json: String = row.get(6)?;
-                let symbols: Vec<String> = serde_json::from_str(&symbols_json).unwrap_or_default();
-                let indexed_str: String = row.get(7)?;
-                let indexed_at = DateTime::parse_from_rfc3339(&indexed_str)
-                    .map(|dt| dt.with_timezone(&Utc))
-                    .unwrap_or_else(|_| Utc::now());
-                let content: Option<String> = row.get(8).ok();
-                let lines = content.map(|c| c.lines().count()).unwrap_or(0);
+    /// Call `visit` with the relative path and content of each of this project's files
+    /// within `filters` (containing `needle`, if given), in path order, until it returns false
+    pub fn each_file_content(
+        &self,
+        filters: &SearchFilters,
+        needle: Option<&str>,
+        mut visit: impl FnMut(&str, &str) -> bool,
+    ) -> Result<()> {
+        let mut values = vec![self.root_pattern()];
+        let mut sql = "SELECT f.relative_path, f.content FROM files f WHERE f.path LIKE ?1 ESCAPE '\\' AND f.content IS NOT NULL".to_string();
+        if let Some(needle) = needle {
+            values.push(needle.to_string());
+            sql.push_str(" AND instr(f.content, ?2) > 0");
+        }
+        let (conditions, filter_values) = filters.sql(values.len() + 1);
+        sql.push_str(&conditions);
+        sql.push_str(" ORDER BY f.relative_path");
+        values.extend(filter_values);
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut rows = stmt.query(params_from_iter(values))?;
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(0)?;
+            let content: String = row.get(1)?;
+            if !visit(&path, &content) {
+                break;
+            }
+        }
+        Ok(())
+    }
 
-                Ok(IndexedFile {
-                    path: row.get(0)?,
-                    relative_path: row.get(1)?,
-                    language: row.get(2)?,
-                    size: row.get(3)?,
-                    lines,
-                    hash: row.get(4)?,
-                    summary: row.get(5)?,
-                    symbols,
-                    indexed_at,
-                    embedding: None,
-                })
-            })?
+    /// Type-like definitions of this project (structs, classes, enums, traits, ...)
+    pub fn type_definitions(&self) -> Result<Vec<Symbol>> {
+        let root = self.root_pattern();
+        let symbols = self.conn
+            .prepare(
+                "SELECT s.path, f.relative_path, s.name, s.kind, s.start_line, s.end_line, s.signature
+                 FROM symbols s
+                 JOIN files f ON f.path = s.path
+                 WHERE s.path LIKE ?1 ESCAPE '\\'
+                 ORDER BY f.relative_path, s.start_line",
+            )?
+            .query_map(params![root], symbol_from_row)?
             .filter_map(|r| r.ok())
+            .filter(|symbol| TYPE_KINDS.contains(&symbol.kind.as_str()))
             .collect();
 
-        Ok(files)
+        Ok(symbols)
+    }
+
+    /// Whether an absolute path lies under this index's project root
+    pub fn is_in_project(&self, path: &str) -> bool {
+        Path::new(path).starts_with(&self.root_path)
+    }
+
+    /// Definitions of this project named exactly `name`, type definitions before `impl` blocks
+    pub fn find_definitions(&self, name: &str) -> Result<Vec<Symbol>> {
+        let root = self.root_pattern();
+        let symbols = self.conn
+            .prepare(
+                "SELECT s.path, f.relative_path, s.name, s.kind, s.start_line, s.end_line, s.signature
+                 FROM symbols s
+                 JOIN files f ON f.path = s.path
+                 WHERE s.name = ?1 AND s.path LIKE ?2 ESCAPE '\\'
+                 ORDER BY s.kind = 'impl', f.relative_path, s.start_line",
+            )?
+            .query_map(params![name, root], symbol_from_row)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(symbols)
     }
 
     pub fn get_file(&self, path: &str) -> Result<Option<IndexedFile>> {
         let result = self.conn.query_row(
             "SELECT path, relative_path, language, size, hash, summary, symbols, indexed_at, content
-             FROM files WHERE path = ?1 OR relative_path = ?1",
-            params![path],
+             FROM files WHERE path = ?1 OR (relative_path = ?1 AND path LIKE ?2 ESCAPE '\\')",
+            params![path, self.root_pattern()],
             |row| {
                 let symbols_json: String = row.get(6)?;
                 let symbols: Vec<String> = serde_json::from_str(&symbols_json).unwrap_or_default();
@@ -517,7 +2120,6 @@ impl CodebaseIndex {
                     summary: row.get(5)?,
                     symbols,
                     indexed_at,
-                    embedding: None,
                 })
             },
         );
@@ -532,14 +2134,49 @@ impl CodebaseIndex {
     pub fn get_file_content(&self, path: &str) -> Result<Option<String>> {
         let content: Option<String> = self.conn
             .query_row(
-                "SELECT content FROM files WHERE path = ?1 OR relative_path = ?1",
-                params![path],
+                "SELECT content FROM files WHERE path = ?1 OR (relative_path = ?1 AND path LIKE ?2 ESCAPE '\\')",
+                params![path, self.root_pattern()],
                 |row| row.get(0),
             )
             .ok();
         Ok(content)
     }
 
+    /// Number of this project's files whose content contains `needle`
+    pub fn count_files_containing(&self, needle: &str) -> Result<usize> {
+        let pattern = self.root_pattern();
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM files WHERE path LIKE ?1 ESCAPE '\\' AND instr(content, ?2) > 0",
+            params![pattern, needle],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    /// Files of this project with text but no summary yet, as (path, relative path)
+    pub fn unsummarized_files(&self) -> Result<Vec<(String, String)>> {
+        let pattern = self.root_pattern();
+        let files = self.conn
+            .prepare(
+                "SELECT path, relative_path FROM files
+                 WHERE path LIKE ?1 ESCAPE '\\' AND content IS NOT NULL AND summary IS NULL
+                 ORDER BY relative_path",
+            )?
+            .query_map(params![pattern], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(files)
+    }
+
+    /// Store a file's summary; it is cleared when the file changes and is indexed again
+    pub fn set_summary(&self, path: &str, summary: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE files SET summary = ?2 WHERE path = ?1",
+            params![path, summary],
+        )?;
+        Ok(())
+    }
+
     pub fn get_stats(&self) -> Result<CodebaseStats> {
         let total_files: i64 = self.conn.query_row(
             "SELECT COUNT(*) FROM files",
@@ -578,9 +2215,16 @@ impl CodebaseIndex {
                 .ok()
         });
 
+        let metadata_only_files: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM files WHERE content IS NULL",
+            [],
+            |row| row.get(0),
+        )?;
+
         Ok(CodebaseStats {
             total_files: total_files as usize,
             total_lines: total_lines as usize,
+            metadata_only_files: metadata_only_files as usize,
             languages,
             last_indexed,
         })
@@ -623,7 +2267,6 @@ impl CodebaseIndex {
                     summary: row.get(5)?,
                     symbols,
                     indexed_at,
-                    embedding: None,
                 })
             })?
             .filter_map(|r| r.ok())
@@ -637,30 +2280,965 @@ impl CodebaseIndex {
         self.list_files(None, 10000)
     }
 
-    /// Semantic search using embeddings
-    pub fn search_semantic(&self, query_embedding: &[f32], limit: usize) -> Result<Vec<(IndexedFile, f32)>> {
+    /// Semantic search using embeddings, returning the best matching chunks
+    pub fn search_semantic(&self, query_embedding: &[f32], limit: usize) -> Result<Vec<(ChunkEmbedding, f32)>> {
         use crate::embeddings::cosine_similarity;
 
-        let embeddings = self.get_all_embeddings()?;
-
-        let mut results: Vec<(String, f32)> = embeddings
-            .iter()
-            .map(|(path, emb)| (path.clone(), cosine_similarity(query_embedding, emb)))
+        let mut results: Vec<(ChunkEmbedding, f32)> = self
+            .get_all_embeddings()?
+            .into_iter()
+            .map(|chunk| {
+                let score = cosine_similarity(query_embedding, &chunk.embedding);
+                (chunk, score)
+            })
             .collect();
 
         results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
         results.truncate(limit);
 
-        let mut files_with_scores = Vec::new();
-        for (path, score) in results {
-            if let Ok(Some(mut file)) = self.get_file(&path) {
-                // Get the embedding for this file
-                let emb = embeddings.iter().find(|(p, _)| p == &path).map(|(_, e)| e.clone());
-                file.embedding = emb;
-                files_with_scores.push((file, score));
+        Ok(results)
+    }
+}
+
+/// Symbol kinds that name a type rather than a function or constant
+const TYPE_KINDS: &[&str] = &["struct", "enum", "trait", "class", "interface", "type", "protocol", "object", "module", "record"];
+
+fn symbol_from_row(row: &rusqlite::Row) -> rusqlite::Result<Symbol> {
+    Ok(Symbol {
+        path: row.get(0)?,
+        relative_path: row.get(1)?,
+        name: row.get(2)?,
+        kind: row.get(3)?,
+        start_line: row.get::<_, i64>(4)? as usize,
+        end_line: row.get::<_, i64>(5)? as usize,
+        signature: row.get(6)?,
+    })
+}
+
+/// How well `name` matches a symbol query, from 1.0 (identical) down; None if it does not
+///
+/// Exact matches beat style-insensitive ones, then prefixes, substrings,
+/// near-misses (typos) and finally abbreviations (`gsd` for `getStagedDiff`).
+pub(crate) fn symbol_match_score(query: &str, name: &str) -> Option<f32> {
+    let query = query.trim();
+    if query == name {
+        return Some(1.0);
+    }
+
+    let wanted: String = split_identifier(query).concat();
+    let candidate: String = split_identifier(name).concat();
+    if wanted.is_empty() || candidate.is_empty() {
+        return None;
+    }
+    // Longer matches of a short query are less likely to be what was meant
+    let coverage = wanted.len() as f32 / candidate.len().max(wanted.len()) as f32;
+
+    if wanted == candidate {
+        Some(0.9)
+    } else if candidate.starts_with(&wanted) {
+        Some(0.7 + 0.1 * coverage)
+    } else if candidate.contains(&wanted) {
+        Some(0.5 + 0.1 * coverage)
+    } else if wanted.len() >= 4 && edit_distance(&wanted, &candidate) <= (wanted.len() / 4).max(1) {
+        Some(0.4)
+    } else if wanted.len() >= 2
+        && wanted.chars().next() == candidate.chars().next()
+        && is_subsequence(&wanted, &candidate)
+    {
+        Some(0.2 + 0.1 * coverage)
+    } else {
+        None
+    }
+}
+
+/// How well a relative path matches a fuzzy file query, from 1.0 down; None if it does not
+///
+/// Like fzf, the query's characters must appear in the path in order, ignoring
+/// case and spaces. Characters matched at the start of a segment or word, in an
+/// unbroken run, or in the file name count for more, so `orch` finds
+/// `agents/orchestrator.rs` before `docs/research.md`.
+pub(crate) fn path_match_score(query: &str, path: &str) -> Option<f32> {
+    const MAX_CHAR_SCORE: f32 = 5.0;
+
+    let query: Vec<char> = query.chars().filter(|c| !c.is_whitespace()).map(|c| c.to_ascii_lowercase()).collect();
+    let chars: Vec<char> = path.chars().collect();
+    let lower: Vec<char> = chars.iter().map(|c| c.to_ascii_lowercase()).collect();
+    let first = *query.first()?;
+    let name_start = chars.iter().rposition(|&c| c == '/').map_or(0, |i| i + 1);
+
+    let is_boundary = |i: usize| {
+        i == 0
+            || matches!(chars[i - 1], '/' | '_' | '-' | '.' | ' ')
+            || (chars[i - 1].is_lowercase() && chars[i].is_uppercase())
+    };
+
+    // Matching greedily from each place the first character occurs, keep the best alignment
+    let mut best: Option<f32> = None;
+    for start in (0..lower.len()).filter(|&i| lower[i] == first) {
+        let mut total = 0.0;
+        let mut previous: Option<usize> = None;
+        let mut position = start;
+        let mut matched = 0;
+
+        for &wanted in &query {
+            let Some(offset) = lower[position..].iter().position(|&c| c == wanted) else { break };
+            let i = position + offset;
+            total += 1.0;
+            if is_boundary(i) {
+                total += 2.0;
+            }
+            if previous == Some(i.wrapping_sub(1)) {
+                total += 1.5;
+            }
+            if i >= name_start {
+                total += 0.5;
+            }
+            previous = Some(i);
+            position = i + 1;
+            matched += 1;
+        }
+
+        if matched == query.len() {
+            let score = total / (MAX_CHAR_SCORE * query.len() as f32);
+            best = Some(best.map_or(score, |b: f32| b.max(score)));
+        }
+    }
+
+    best
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
+/// Most lines between a definition's first line and its opening brace or `;`
+const MAX_SIGNATURE_LINES: usize = 10;
+
+/// Words before a `(` that mean a C-family line is a statement or a type, not a function
+const NOT_FUNCTION_WORDS: &[&str] = &[
+    "return", "if", "else", "while", "for", "foreach", "switch", "case", "do", "new", "delete", "throw",
+    "sizeof", "using", "await", "yield", "goto", "class", "interface", "record", "namespace",
+];
+
+/// First words of Ruby lines that open a block closed by `end`
+const RUBY_BLOCK_OPENERS: &[&str] = &[
+    "def", "class", "module", "if", "unless", "while", "until", "case", "begin", "for",
+];
+
+/// Kind and name of a declaration like `public static func parse(`: the first of
+/// `keywords` among the line's words, and the identifier after it
+///
+/// Only modifiers (lowercase words and `@annotations`) may come before the
+/// keyword, so the keyword inside a call or a string is not taken for a
+/// declaration. The name skips generics (`fun <T> wrap`), receivers
+/// (`fun String.trimAll`, `def self.load`) and a second keyword (`enum class Color`).
+fn declared_name(line: &str, keywords: &[&'static str]) -> Option<(&'static str, String)> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let position = words.iter().position(|word| keywords.contains(word))?;
+    let is_modifier = |word: &&str| word.starts_with('@') || word.chars().all(|c| c.is_ascii_lowercase());
+    if !words[..position].iter().all(is_modifier) {
+        return None;
+    }
+
+    let kind = keywords.iter().find(|k| **k == words[position])?;
+    let word = words[position + 1..]
+        .iter()
+        .find(|word| !word.starts_with('<') && !word.ends_with('>') && !keywords.contains(word))?;
+    let declared = word.split(['(', '<', ':', '{']).next()?;
+    let name: String = declared
+        .rsplit('.')
+        .next()?
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '?' || *c == '!')
+        .collect();
+    (!name.is_empty()).then_some((*kind, name))
+}
+
+/// Index of the last line of the definition starting at `start`
+///
+/// Python bodies end where the indentation returns to the definition's level,
+/// Ruby bodies at the `end` that balances their opening keyword; elsewhere the
+/// body ends where its braces balance, or at a `;` before any brace
+/// (`struct Unit;`, trait methods without a body).
+fn definition_end(lines: &[&str], start: usize, language: &str) -> usize {
+    let indent = |line: &str| line.len() - line.trim_start().len();
+
+    if language == "python" {
+        let level = indent(lines[start]);
+        let mut end = start;
+        for (i, line) in lines.iter().enumerate().skip(start + 1) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if indent(line) <= level {
+                break;
+            }
+            end = i;
+        }
+        return end;
+    }
+
+    if language == "ruby" {
+        let mut depth = 0i32;
+        for (i, line) in lines.iter().enumerate().skip(start) {
+            let trimmed = line.trim();
+            let first = trimmed.split_whitespace().next().unwrap_or("");
+            if RUBY_BLOCK_OPENERS.contains(&first) || trimmed.ends_with(" do") || trimmed.contains(" do |") {
+                depth += 1;
+            }
+            if first == "end" || first.starts_with("end.") || trimmed.ends_with("; end") {
+                depth -= 1;
+            }
+            if depth <= 0 {
+                return i;
+            }
+        }
+        return lines.len().saturating_sub(1);
+    }
+
+    let mut depth = 0i32;
+    let mut opened = false;
+    for (i, line) in lines.iter().enumerate().skip(start) {
+        for c in line.chars() {
+            match c {
+                '{' => {
+                    depth += 1;
+                    opened = true;
+                }
+                '}' => depth -= 1,
+                ';' if !opened => return i,
+                _ => {}
+            }
+        }
+        if opened && depth <= 0 {
+            return i;
+        }
+        if !opened && i - start >= MAX_SIGNATURE_LINES {
+            return start;
+        }
+    }
+
+    if opened { lines.len().saturating_sub(1) } else { start }
+}
+
+/// A file read and parsed off the database thread, ready to be stored
+struct ParsedFile {
+    indexed: IndexedFile,
+    /// None for binary files and files over `max_file_size`
+    content: Option<String>,
+    definitions: Vec<Symbol>,
+    imports: Vec<String>,
+    terms: String,
+}
+
+impl ParsedFile {
+    /// Read and parse a file, or None if its hash is still `previous_hash`
+    ///
+    /// Binary files and files over `max_file_size` are stored without content,
+    /// so they are listed but not searched, parsed for symbols, or embedded.
+    fn read(
+        path: &Path,
+        language: &str,
+        root_path: &Path,
+        max_file_size: u64,
+        tokenizer: &str,
+        previous_hash: Option<&String>,
+    ) -> Result<Option<Self>> {
+        let metadata = fs::metadata(path)?;
+        let size = metadata.len();
+
+        let (content, hash) = if max_file_size > 0 && size > max_file_size {
+            // Hashing a huge file costs as much as storing it; size and mtime show a change
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_nanos())
+                .unwrap_or(0);
+            (None, CodebaseIndex::compute_hash(&format!("oversized:{}:{}", size, modified)))
+        } else {
+            let bytes = fs::read(path)?;
+            let hash = hex::encode(Sha256::digest(&bytes));
+            (text_content(bytes), hash)
+        };
+
+        if previous_hash == Some(&hash) {
+            return Ok(None);
+        }
+
+        let text = content.as_deref().unwrap_or("");
+        let definitions = CodebaseIndex::extract_definitions(text, language);
+        let symbols = definitions.iter().map(|d| format!("{}:{}", d.kind, d.name)).collect();
+
+        let indexed = IndexedFile {
+            path: path.to_string_lossy().to_string(),
+            relative_path: path.strip_prefix(root_path).unwrap_or(path).to_string_lossy().to_string(),
+            language: language.to_string(),
+            size,
+            lines: text.lines().count(),
+            hash,
+            summary: None,
+            symbols,
+            indexed_at: Utc::now(),
+        };
+
+        Ok(Some(Self {
+            indexed,
+            imports: imports::extract_imports(text, language),
+            terms: fts_terms(tokenizer, text),
+            definitions,
+            content,
+        }))
+    }
+}
+
+/// The file as text, or None when it looks binary: a NUL byte near the start, or not UTF-8
+fn text_content(bytes: Vec<u8>) -> Option<String> {
+    if bytes[..bytes.len().min(BINARY_SNIFF_BYTES)].contains(&0) {
+        return None;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// FTS5 column definition for a tokenizer setting
+fn fts_definition(tokenizer: &str) -> String {
+    let tokenize = if tokenizer == CODE_TOKENIZER { "unicode61" } else { tokenizer };
+    format!(
+        "fts5(path, content, symbols, terms, tokenize = \"{}\")",
+        tokenize.replace('"', "\"\"")
+    )
+}
+
+/// Words of the compound identifiers in `content`, for the `terms` column
+///
+/// unicode61 already splits `get_staged_diff` and `a::b`, but not `getStagedDiff`.
+fn fts_terms(tokenizer: &str, content: &str) -> String {
+    if tokenizer != CODE_TOKENIZER {
+        return String::new();
+    }
+
+    let mut seen = HashSet::new();
+    let mut terms = Vec::new();
+    for identifier in content.split(|c: char| !c.is_alphanumeric() && c != '_') {
+        if !seen.insert(identifier) {
+            continue;
+        }
+        let parts = split_identifier(identifier);
+        if parts.len() > 1 {
+            terms.extend(parts);
+        }
+    }
+    terms.join(" ")
+}
+
+/// Lowercase words of an identifier: `getHTTPResponse_v2` -> get, http, response, v2
+pub(crate) fn split_identifier(identifier: &str) -> Vec<String> {
+    let chars: Vec<char> = identifier.chars().collect();
+    let mut parts = Vec::new();
+    let mut current = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !current.is_empty() {
+                parts.push(std::mem::take(&mut current).to_lowercase());
+            }
+            continue;
+        }
+
+        // A capital starts a word after a lowercase letter or digit, and
+        // ends an acronym when a lowercase letter follows ("HTTPServer")
+        let boundary = c.is_uppercase() && !current.is_empty() && {
+            let prev = chars[i - 1];
+            prev.is_lowercase()
+                || prev.is_ascii_digit()
+                || (prev.is_uppercase() && chars.get(i + 1).is_some_and(|n| n.is_lowercase()))
+        };
+        if boundary {
+            parts.push(std::mem::take(&mut current).to_lowercase());
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        parts.push(current.to_lowercase());
+    }
+
+    parts
+}
+
+/// FTS5 query matching any of the terms
+///
+/// Each term is quoted, so punctuation cannot form syntax and `a::b` paths
+/// become phrase queries; the words of compound identifiers are added as well.
+fn fts_any_query(terms: &[&str]) -> Option<String> {
+    let mut quoted: Vec<String> = Vec::new();
+    for term in terms.iter().map(|t| t.trim()).filter(|t| t.chars().any(|c| c.is_alphanumeric())) {
+        let mut alternatives = vec![term.to_string()];
+        let parts = split_identifier(term);
+        if parts.len() > 1 {
+            alternatives.extend(parts);
+        }
+
+        for alternative in alternatives {
+            let alternative = format!("\"{}\"", alternative.replace('"', "\"\""));
+            if !quoted.contains(&alternative) {
+                quoted.push(alternative);
             }
         }
+    }
+
+    if quoted.is_empty() {
+        None
+    } else {
+        Some(quoted.join(" OR "))
+    }
+}
+
+/// Line range of an FTS snippet within the content it was cut from
+///
+/// With empty markers and ellipsis, `snippet()` returns a verbatim slice of the
+/// column, so its byte offset gives the lines. Falls back to the whole file.
+fn fragment_lines(content: &str, fragment: &str) -> (usize, usize) {
+    let total = content.lines().count().max(1);
+    let fragment = fragment.trim();
+
+    match content.find(fragment).filter(|_| !fragment.is_empty()) {
+        Some(offset) => {
+            let start = content[..offset].matches('\n').count() + 1;
+            let end = start + fragment.matches('\n').count();
+            (start, end.min(total))
+        }
+        None => (1, total),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_file_keeps_chunk_vectors_for_reuse() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("lib.rs");
+        std::fs::write(&file, "fn a() {}\n").unwrap();
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+
+        let path = file.to_string_lossy().to_string();
+        let hash = CodebaseIndex::compute_hash("fn a() {}");
+        index.store_embedding(
+            &ChunkEmbedding {
+                path: path.clone(),
+                chunk_index: 0,
+                start_line: 1,
+                end_line: 1,
+                content_hash: hash.clone(),
+                embedding: vec![0.5, 0.5],
+            },
+            "test-model",
+        ).unwrap();
+        assert!(index.has_embedding(&path));
+
+        std::fs::write(&file, "fn a() {}\n\nfn b() {}\n").unwrap();
+        index.index_directory(false).unwrap();
+
+        // Stale vectors are no longer searched, but stay available by chunk hash
+        assert!(!index.has_embedding(&path));
+        assert!(index.get_all_embeddings().unwrap().is_empty());
+        assert_eq!(index.pending_embeddings(10).unwrap(), vec![path.clone()]);
+        assert_eq!(index.chunk_embeddings(&path).unwrap().get(&hash), Some(&vec![0.5, 0.5]));
+    }
+
+    #[test]
+    fn test_switching_back_to_a_branch_restores_its_vectors() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("lib.rs");
+        let only_on_main = root.path().join("old.rs");
+        std::fs::write(&file, "fn a() {}\n").unwrap();
+        std::fs::write(&only_on_main, "fn old() {}\n").unwrap();
+        let open = |branch: &str| {
+            CodebaseIndex::new(data_dir.path(), root.path())
+                .unwrap()
+                .with_branch(Some(branch.to_string()))
+        };
+
+        let index = open("main");
+        index.index_directory(false).unwrap();
+        for path in [&file, &only_on_main] {
+            let chunk = ChunkEmbedding {
+                path: path.to_string_lossy().to_string(),
+                chunk_index: 0,
+                start_line: 1,
+                end_line: 1,
+                content_hash: String::new(),
+                embedding: vec![0.5, 0.5],
+            };
+            index.store_embedding(&chunk, "test-model").unwrap();
+        }
+
+        // Checking out `feature` changes one file and deletes the other
+        std::fs::write(&file, "fn a() { feature() }\n").unwrap();
+        std::fs::remove_file(&only_on_main).unwrap();
+        let index = open("feature");
+        assert_eq!(index.switched_from().unwrap().as_deref(), Some("main"));
+        index.index_directory(false).unwrap();
+        assert_eq!(index.indexed_branch().unwrap().as_deref(), Some("feature"));
+        assert_eq!(index.pending_embedding_count().unwrap(), 1);
+        assert!(index.most_used_files(5).unwrap().is_empty());
+
+        // Back on `main`, both files get their vectors without re-embedding
+        std::fs::write(&file, "fn a() {}\n").unwrap();
+        std::fs::write(&only_on_main, "fn old() {}\n").unwrap();
+        let index = open("main");
+        index.index_directory(false).unwrap();
+        assert_eq!(index.pending_embedding_count().unwrap(), 0);
+        assert_eq!(index.get_all_embeddings().unwrap().len(), 2);
+
+        assert_eq!(index.drop_branch_vectors(&["feature".to_string()]).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_deleted_and_ignored_files_are_pruned() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("keep.rs"), "fn keep() {}\n").unwrap();
+        std::fs::write(root.path().join("gone.rs"), "fn gone() {}\n").unwrap();
+        std::fs::write(root.path().join("fixture.rs"), "fn fixture() {}\n").unwrap();
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+        assert_eq!(index.get_stats().unwrap().total_files, 3);
+
+        std::fs::remove_file(root.path().join("gone.rs")).unwrap();
+        std::fs::write(root.path().join("keep.rs"), "fn keep() { kept() }\n").unwrap();
+        // A dry run plans the same changes without making them
+        let planned = index.planned_changes().unwrap();
+        assert!(index.get_file("gone.rs").unwrap().is_some());
+        let changes = index.index_changes(false).unwrap();
+        assert_eq!(changes, planned);
+        assert_eq!(changes.modified, vec!["keep.rs".to_string()]);
+        assert_eq!(changes.removed, vec!["gone.rs".to_string()]);
+        assert!(index.get_file("gone.rs").unwrap().is_none());
+        assert!(index.search_keywords(&["gone"], 5).unwrap().is_empty());
+        assert!(index.find_definitions("gone").unwrap().is_empty());
+
+        // Ignored files stay until an explicit prune
+        std::fs::write(root.path().join(".ignore"), "fixture.rs\n").unwrap();
+        index.index_directory(false).unwrap();
+        assert!(index.get_file("fixture.rs").unwrap().is_some());
+        assert_eq!(index.prune_unindexable().unwrap(), 1);
+        assert!(index.get_file("fixture.rs").unwrap().is_none());
+        assert!(index.get_file("keep.rs").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_excluded_files_are_not_indexed() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("generated")).unwrap();
+        std::fs::write(root.path().join("generated/api.rs"), "fn api() {}\n").unwrap();
+        std::fs::write(root.path().join("fixtures.json"), "{}\n").unwrap();
+        std::fs::write(root.path().join("main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(root.path().join(".sovereignignore"), "generated/\n").unwrap();
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+        assert!(index.get_file("generated/api.rs").unwrap().is_none());
+        assert!(index.get_file("fixtures.json").unwrap().is_some());
+
+        // Newly excluded files leave the index on the next run
+        let config = IndexConfig { exclude: vec!["*.json".to_string()], ..Default::default() };
+        let index = index.with_config(&config).unwrap();
+        index.index_directory(false).unwrap();
+        assert!(index.get_file("fixtures.json").unwrap().is_none());
+        assert!(index.get_file("main.rs").unwrap().is_some());
+        assert!(index.is_excluded(&root.path().join("generated/api.rs").to_string_lossy()));
+    }
+
+    #[test]
+    fn test_indexed_root_is_found_from_subdirectories() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("src/agents")).unwrap();
+        std::fs::write(root.path().join("src/agents/chat.rs"), "fn chat() {}\n").unwrap();
+
+        let data = data_dir.path();
+        assert!(CodebaseIndex::find_indexed_root(data, root.path()).unwrap().is_none());
+
+        let index = CodebaseIndex::new(data, root.path()).unwrap();
+        index.index_directory(false).unwrap();
+
+        let found = CodebaseIndex::find_indexed_root(data, &root.path().join("src/agents")).unwrap();
+        assert_eq!(found.as_deref(), Some(root.path()));
+        assert!(CodebaseIndex::find_indexed_root(data, Path::new("/")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_embedding_queue_is_per_project_not_per_prefix() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let parent = tempfile::tempdir().unwrap();
+        let (app, app2) = (parent.path().join("app"), parent.path().join("app2"));
+        std::fs::create_dir_all(&app).unwrap();
+        std::fs::create_dir_all(&app2).unwrap();
+
+        let data = data_dir.path().to_path_buf();
+        let index = CodebaseIndex::new(&data, &app).unwrap();
+        let sibling = CodebaseIndex::new(&data, &app2).unwrap();
+        let queued = app2.join("main.rs").to_string_lossy().to_string();
+        sibling.conn.execute(
+            "INSERT INTO embedding_queue (path, queued_at) VALUES (?1, ?2)",
+            params![queued, Utc::now().to_rfc3339()],
+        ).unwrap();
+
+        assert_eq!(sibling.pending_embedding_count().unwrap(), 1);
+        assert_eq!(index.pending_embedding_count().unwrap(), 0);
+        assert!(index.pending_embeddings(10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_reembedding_one_project_keeps_the_others_vectors() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let parent = tempfile::tempdir().unwrap();
+        let (app, other) = (parent.path().join("app"), parent.path().join("other"));
+        std::fs::create_dir_all(&app).unwrap();
+        std::fs::create_dir_all(&other).unwrap();
+
+        let data = data_dir.path().to_path_buf();
+        let index = CodebaseIndex::new(&data, &app).unwrap();
+        let sibling = CodebaseIndex::new(&data, &other).unwrap();
+        let chunk = |root: &Path| ChunkEmbedding {
+            path: root.join("lib.rs").to_string_lossy().to_string(),
+            chunk_index: 0,
+            start_line: 1,
+            end_line: 1,
+            content_hash: "hash".to_string(),
+            embedding: vec![0.5, 0.5],
+        };
+        sibling.store_embedding(&chunk(&other), "old-model").unwrap();
+
+        // Another project's model does not stand in the way of this one's
+        index.store_embedding(&chunk(&app), "new-model").unwrap();
+        assert_eq!(index.embedding_models().unwrap(), vec![("new-model".to_string(), 2)]);
+
+        assert_eq!(index.clear_embeddings().unwrap(), 1);
+        assert_eq!(sibling.embedding_models().unwrap(), vec![("old-model".to_string(), 2)]);
+    }
+
+    #[test]
+    fn test_wildcards_in_a_root_match_only_themselves() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let parent = tempfile::tempdir().unwrap();
+        let (underscore, dash) = (parent.path().join("my_app"), parent.path().join("my-app"));
+        std::fs::create_dir_all(&underscore).unwrap();
+        std::fs::create_dir_all(&dash).unwrap();
+
+        let data = data_dir.path().to_path_buf();
+        let index = CodebaseIndex::new(&data, &underscore).unwrap();
+        let sibling = CodebaseIndex::new(&data, &dash).unwrap();
+        let queued = dash.join("main.rs").to_string_lossy().to_string();
+        sibling.conn.execute(
+            "INSERT INTO embedding_queue (path, queued_at) VALUES (?1, ?2)",
+            params![queued, Utc::now().to_rfc3339()],
+        ).unwrap();
+
+        assert_eq!(sibling.pending_embedding_count().unwrap(), 1);
+        assert_eq!(index.pending_embedding_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_database_from_before_migrations_is_upgraded() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let conn = Connection::open(data_dir.path().join("codebase.db")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE embeddings (
+                 path TEXT PRIMARY KEY,
+                 embedding BLOB NOT NULL,
+                 chunk_index INTEGER DEFAULT 0,
+                 created_at TEXT NOT NULL
+             );
+             INSERT INTO embeddings (path, embedding, created_at)
+                 VALUES ('/old/a.rs', x'0000803f00000040', '2024-01-01T00:00:00Z');",
+        ).unwrap();
+        drop(conn);
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        assert_eq!(super::super::db::schema_version(&index.conn).unwrap(), MIGRATIONS.len());
+        let row: (String, i64, i64, i64) = index.conn
+            .query_row("SELECT model, dimension, chunk_index, end_line FROM embeddings", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap();
+        assert_eq!(row, ("nomic-embed-text".to_string(), 2, 0, 0));
+    }
+
+    #[test]
+    fn test_summaries_resume_and_clear_on_change() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("a.rs"), "fn a() {}\n").unwrap();
+        std::fs::write(root.path().join("b.rs"), "fn b() {}\n").unwrap();
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+
+        let pending = index.unsummarized_files().unwrap();
+        assert_eq!(pending.iter().map(|(_, rel)| rel.as_str()).collect::<Vec<_>>(), vec!["a.rs", "b.rs"]);
+        index.set_summary(&pending[0].0, "Defines a.").unwrap();
+        assert_eq!(index.unsummarized_files().unwrap().len(), 1);
+        assert_eq!(index.get_file("a.rs").unwrap().unwrap().summary.as_deref(), Some("Defines a."));
+
+        // A changed file needs a new summary; an unchanged one keeps its own
+        std::fs::write(root.path().join("a.rs"), "fn a() { changed() }\n").unwrap();
+        index.index_changes(false).unwrap();
+        assert_eq!(index.unsummarized_files().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_parallel_indexing_spans_batches() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let count = INDEX_BATCH + 40;
+        for i in 0..count {
+            std::fs::write(root.path().join(format!("m{}.rs", i)), format!("pub fn f{}() {{}}\n", i)).unwrap();
+        }
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        let changes = index.index_changes(false).unwrap();
+        assert_eq!(changes.added.len(), count);
+        assert!(changes.modified.is_empty());
+        assert_eq!(index.get_file("m7.rs").unwrap().unwrap().symbols, vec!["fn:f7".to_string()]);
+        assert!(index.get_file_content("m540.rs").unwrap().unwrap().contains("f540"));
+
+        std::fs::write(root.path().join("m3.rs"), "pub fn g() {}\n").unwrap();
+        let changes = index.index_changes(false).unwrap();
+        assert_eq!(changes.modified, vec!["m3.rs".to_string()]);
+        assert!(changes.added.is_empty());
+    }
+
+    #[test]
+    fn test_binary_and_oversized_files_keep_only_metadata() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("data.json"), format!("[{}]", "1,".repeat(600))).unwrap();
+        std::fs::write(root.path().join("blob.rs"), b"fn a() {}\0\x89PNG").unwrap();
+        std::fs::write(root.path().join("main.rs"), "fn main() {}\n").unwrap();
+
+        let config = IndexConfig { max_file_size: 1000, ..Default::default() };
+        let index = CodebaseIndex::new(data_dir.path(), root.path())
+            .unwrap()
+            .with_config(&config)
+            .unwrap();
+        index.index_directory(false).unwrap();
+
+        let data = index.get_file("data.json").unwrap().unwrap();
+        assert_eq!(data.size, 1202);
+        assert_eq!(data.lines, 0);
+        assert!(index.get_file_content("data.json").unwrap().is_none());
+        assert!(index.get_file_content("blob.rs").unwrap().is_none());
+        assert!(index.get_file("blob.rs").unwrap().unwrap().symbols.is_empty());
+        assert!(index.get_file_content("main.rs").unwrap().is_some());
+        assert_eq!(index.get_stats().unwrap().metadata_only_files, 2);
+
+        // Unchanged oversized files are not re-indexed
+        assert_eq!(index.index_directory(false).unwrap(), 0);
+        assert!(text_content(b"plain text".to_vec()).is_some());
+        assert!(text_content(vec![0xff, 0xfe]).is_none());
+    }
+
+    #[test]
+    fn test_code_tokenizer_matches_identifier_words() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("git.ts"), "export function getStagedDiff() {}\n").unwrap();
+        std::fs::write(root.path().join("main.rs"), "use crate::storage::CodebaseIndex;\n").unwrap();
+
+        // Start from a plain unicode61 table to check it is rebuilt
+        let index = CodebaseIndex::with_tokenizer(data_dir.path(), root.path(), "unicode61").unwrap();
+        index.index_directory(false).unwrap();
+        assert!(index.search_keywords(&["staged"], 5).unwrap().is_empty());
+        drop(index);
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        let hits = index.search_keywords(&["get", "staged", "diff"], 5).unwrap();
+        assert!(hits[0].path.ends_with("git.ts"));
+
+        let hits = index.search_keywords(&["crate::storage::CodebaseIndex"], 5).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].path.ends_with("main.rs"));
+
+        assert_eq!(split_identifier("getHTTPResponse_v2"), vec!["get", "http", "response", "v2"]);
+    }
+
+    #[test]
+    fn test_search_ranks_and_highlights_matches() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let filler = "// filler\n".repeat(50);
+        std::fs::write(root.path().join("once.rs"), format!("{}fn retry() {{}}\n{}", filler, filler)).unwrap();
+        std::fs::write(root.path().join("often.rs"), "fn retry() {\n    retry();\n    retry();\n}\n").unwrap();
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+
+        let matches = index.search("retry", 10).unwrap();
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].file.relative_path, "often.rs");
+
+        let once = &matches[1];
+        assert_eq!((once.start_line, once.end_line), (51, 51));
+        assert_eq!(once.excerpt, "fn **retry**() {}");
+        assert_eq!(once.file.lines, 101);
+    }
+
+    #[test]
+    fn test_search_within_filters() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("src/agents")).unwrap();
+        std::fs::write(root.path().join("src/agents/orchestrator.rs"), "struct Orchestrator;\n// retry here\n").unwrap();
+        std::fs::write(root.path().join("src/agents/chat.rs"), "struct Chat;\n// retry there\n").unwrap();
+        std::fs::write(root.path().join("src/net.rs"), "// retry_count\nfn retry() {}\n").unwrap();
+        std::fs::write(root.path().join("retry.py"), "def retry(): pass\n").unwrap();
+
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+        let found = |filters: &SearchFilters| {
+            let mut paths: Vec<String> = index
+                .search_filtered("retry", filters, 10)
+                .unwrap()
+                .into_iter()
+                .map(|m| m.file.relative_path)
+                .collect();
+            paths.sort();
+            paths
+        };
+
+        let rust = SearchFilters { languages: vec!["Rust".to_string()], ..Default::default() };
+        assert_eq!(found(&rust).len(), 3);
+        let agents = SearchFilters { paths: vec!["./src/agents".to_string()], ..rust.clone() };
+        assert_eq!(found(&agents), vec!["src/agents/chat.rs", "src/agents/orchestrator.rs"]);
+        let orchestrator = SearchFilters { symbols: vec!["Orch*".to_string()], ..agents.clone() };
+        assert_eq!(found(&orchestrator), vec!["src/agents/orchestrator.rs"]);
+
+        // `_` is literal, not a LIKE wildcard
+        let underscore = SearchFilters { paths: vec!["src_".to_string()], ..Default::default() };
+        assert!(found(&underscore).is_empty());
+        assert_eq!(index.filtered_paths(&orchestrator).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_definitions_have_line_ranges() {
+        let rust = "pub struct Unit;\n\nimpl Unit {\n    fn new() -> Self {\n        Unit\n    }\n}\n";
+        let ranges: Vec<(String, usize, usize)> = CodebaseIndex::extract_definitions(rust, "rust")
+            .into_iter()
+            .map(|s| (format!("{}:{}", s.kind, s.name), s.start_line, s.end_line))
+            .collect();
+        assert_eq!(ranges, vec![
+            ("struct:Unit".to_string(), 1, 1),
+            ("impl:Unit".to_string(), 3, 7),
+            ("fn:new".to_string(), 4, 6),
+        ]);
+
+        let python = "class Store:\n    def get(self):\n        return 1\n\n    def put(self):\n        pass\n\nx = 1\n";
+        let defs = CodebaseIndex::extract_definitions(python, "python");
+        assert_eq!((defs[0].start_line, defs[0].end_line), (1, 6));
+        assert_eq!((defs[1].start_line, defs[1].end_line), (2, 3));
+
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("lib.rs"), rust).unwrap();
+        let index = CodebaseIndex::new(data_dir.path(), root.path()).unwrap();
+        index.index_directory(false).unwrap();
+
+        let found = index.find_definitions("Unit").unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].kind, "struct");
+        assert_eq!(found[0].relative_path, "lib.rs");
+        assert_eq!(found[1].signature, "impl Unit");
+        assert_eq!(index.search_by_symbol("ne", 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_definitions_in_more_languages() {
+        let symbols = |content: &str, language: &str| CodebaseIndex::extract_symbols(content, language);
+
+        let c = "struct node;\nstruct node *next_node(struct node *n);\n\nstatic struct node *next_node(struct node *n) {\n    return n->next;\n}\n\ntypedef struct list {\n    int len;\n} list_t;\n";
+        assert_eq!(symbols(c, "c"), vec!["function:next_node", "struct:list"]);
+
+        let cpp = "namespace app {\nenum class Color {\n    Red,\n};\nstd::string Config::name() const {\n    if (ok(x)) {\n        call(x);\n    }\n}\n}\n";
+        assert_eq!(symbols(cpp, "cpp"), vec!["namespace:app", "enum:Color", "function:name"]);
+
+        let csharp = "public sealed class Store : IStore\n{\n    public async Task<int> Count(string key)\n    {\n        return await Query(key);\n    }\n}\n";
+        assert_eq!(symbols(csharp, "csharp"), vec!["class:Store", "function:Count"]);
+
+        let kotlin = "data class User(val name: String)\nfun <T> wrap(x: T) = listOf(x)\nprivate fun String.shout(): String {\n    return uppercase()\n}\n";
+        assert_eq!(symbols(kotlin, "kotlin"), vec!["class:User", "fun:wrap", "fun:shout"]);
+
+        let swift = "public struct Point {\n    @discardableResult\n    public mutating func move(by d: Int) -> Int {\n        return d\n    }\n}\nextension Point: Equatable {}\n";
+        assert_eq!(symbols(swift, "swift"), vec!["struct:Point", "func:move", "extension:Point"]);
+
+        let php = "<?php\nfinal class Cart {\n    public static function total(array $items): int {\n        return array_sum(array_map(function ($i) { return $i; }, $items));\n    }\n}\n";
+        assert_eq!(symbols(php, "php"), vec!["class:Cart", "function:total"]);
+
+        let shell = "#!/bin/sh\nfunction build {\n  make\n}\nclean_up() {\n  rm -rf out\n}\necho \"done()\"\n";
+        assert_eq!(symbols(shell, "shell"), vec!["function:build", "function:clean_up"]);
+
+        let ruby = "module Shop\n  class << self\n    def self.load(path)\n      items.each do |item|\n        puts item\n      end\n    end\n  end\n\n  def empty?; end\nend\n";
+        let defs = CodebaseIndex::extract_definitions(ruby, "ruby");
+        let ranges: Vec<(String, usize, usize)> = defs
+            .into_iter()
+            .map(|s| (format!("{}:{}", s.kind, s.name), s.start_line, s.end_line))
+            .collect();
+        assert_eq!(ranges, vec![
+            ("module:Shop".to_string(), 1, 11),
+            ("def:load".to_string(), 3, 7),
+            ("def:empty?".to_string(), 10, 10),
+        ]);
+    }
+
+    #[test]
+    fn test_path_match_ranking() {
+        let score = |query: &str, path: &str| path_match_score(query, path);
+
+        assert!(score("orch", "src/agents/orchestrator.rs") > score("orch", "docs/old/research.md"));
+        assert!(score("main", "src/main.rs") > score("main", "src/domain.rs"));
+        assert!(score("st/cb", "src/storage/codebase.rs").is_some());
+        assert!(score("Code Base", "src/storage/codebase.rs").is_some());
+        assert_eq!(score("zq", "src/storage/codebase.rs"), None);
+        assert_eq!(score("", "src/main.rs"), None);
+    }
 
-        Ok(files_with_scores)
+    #[test]
+    fn test_symbol_match_ranking() {
+        let score = |query: &str, name: &str| symbol_match_score(query, name);
+
+        assert_eq!(score("parse_diff", "parse_diff"), Some(1.0));
+        assert_eq!(score("ParseDiff", "parse_diff"), Some(0.9));
+        assert_eq!(score("get staged diff", "getStagedDiff"), Some(0.9));
+        assert!(score("parse", "parse_diff") > score("diff", "parse_diff"));
+        assert!(score("diff", "parse_diff") > score("prase_diff", "parse_diff"));
+        assert!(score("prase_diff", "parse_diff") > score("pd", "parse_diff"));
+        assert!(score("pd", "parse_diff").is_some());
+        assert_eq!(score("config", "parse_diff"), None);
     }
 }
@@ -1,14 +1,18 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use automerge::{AutoCommit, ObjType, Prop, ReadDoc, ROOT};
 use automerge::transaction::Transactable;
 use chrono::{DateTime, Utc};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
+use crate::dry_run;
+
 /// CRDT-based memory store using Automerge for conflict-free sync
 pub struct CrdtMemoryStore {
     doc: AutoCommit,
     path: PathBuf,
+    /// Changes are only described (`--dry-run`)
+    dry_run: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -57,29 +61,112 @@ impl CrdtMemoryType {
     }
 }
 
+/// One step in the evolution of the document layout
+struct LayoutMigration {
+    description: &'static str,
+    apply: fn(&mut AutoCommit) -> Result<()>,
+}
+
+/// Layout of `memories.automerge`, oldest step first; append to change it
+///
+/// The number of steps applied is kept in `metadata.schema_version`. Merging
+/// a peer's document can bring back data in an older layout, so steps must
+/// leave already-migrated data alone.
+const LAYOUT_MIGRATIONS: &[LayoutMigration] = &[
+    LayoutMigration { description: "memories list and metadata map", apply: create_layout },
+];
+
+/// Layout version 1: the root objects, as created by versions before migrations
+fn create_layout(doc: &mut AutoCommit) -> Result<()> {
+    if doc.get(ROOT, "memories")?.is_none() {
+        doc.put_object(ROOT, "memories", ObjType::List)?;
+    }
+    if doc.get(ROOT, "metadata")?.is_none() {
+        doc.put_object(ROOT, "metadata", ObjType::Map)?;
+    }
+    Ok(())
+}
+
+/// The number of layout migrations applied to a document
+fn layout_version(doc: &AutoCommit) -> Result<usize> {
+    let Some((_, metadata)) = doc.get(ROOT, "metadata")? else { return Ok(0) };
+    match doc.get(&metadata, "schema_version")? {
+        Some((automerge::Value::Scalar(s), _)) => Ok(s.to_u64().unwrap_or(0) as usize),
+        _ => Ok(0),
+    }
+}
+
+/// Refuse documents written by a newer build, whose layout this one can't read
+fn check_layout_version(doc: &AutoCommit, name: &str) -> Result<()> {
+    let version = layout_version(doc)?;
+    if version > LAYOUT_MIGRATIONS.len() {
+        bail!(
+            "{} has layout version {}, but this build of Sovereign only knows up to {}; upgrade Sovereign to open it",
+            name,
+            version,
+            LAYOUT_MIGRATIONS.len()
+        );
+    }
+    Ok(())
+}
+
 impl CrdtMemoryStore {
-    pub fn new(data_dir: &PathBuf) -> Result<Self> {
+    pub fn new(data_dir: &Path) -> Result<Self> {
         let path = data_dir.join("memories.automerge");
+        let existed = path.exists();
 
-        let doc = if path.exists() {
+        let doc = if existed {
             // Load existing document
             let bytes = std::fs::read(&path)?;
             AutoCommit::load(&bytes)?
         } else {
-            // Create new document with memories list
-            let mut doc = AutoCommit::new();
-            doc.put_object(ROOT, "memories", ObjType::List)?;
-            doc.put_object(ROOT, "metadata", ObjType::Map)?;
-            doc
+            AutoCommit::new()
         };
 
-        Ok(Self { doc, path })
+        let mut store = Self { doc, path, dry_run: false };
+        if store.migrate()? && existed {
+            store.save()?;
+        }
+        Ok(store)
+    }
+
+    /// Describe changes instead of making them
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Apply the layout migrations the document has not seen yet; true if any ran
+    fn migrate(&mut self) -> Result<bool> {
+        check_layout_version(&self.doc, "memories.automerge")?;
+        let version = layout_version(&self.doc)?;
+        if version == LAYOUT_MIGRATIONS.len() {
+            return Ok(false);
+        }
+
+        for (step, migration) in LAYOUT_MIGRATIONS.iter().enumerate().skip(version) {
+            (migration.apply)(&mut self.doc).with_context(|| {
+                format!("Migrating memories.automerge to layout version {} ({})", step + 1, migration.description)
+            })?;
+        }
+
+        let metadata = self.doc.get(ROOT, "metadata")?
+            .ok_or_else(|| anyhow::anyhow!("Metadata map not found"))?;
+        self.doc.put(&metadata.1, "schema_version", LAYOUT_MIGRATIONS.len() as u64)?;
+        Ok(true)
     }
 
-    /// Save the document to disk
+    /// Save the document to disk, through a temporary file so an interrupted save
+    /// leaves the previous one whole
     pub fn save(&mut self) -> Result<()> {
+        if self.dry_run {
+            dry_run::report(format!("save the memories to {}", self.path.display()));
+            return Ok(());
+        }
         let bytes = self.doc.save();
-        std::fs::write(&self.path, bytes)?;
+        let partial = self.path.with_extension("automerge.partial");
+        std::fs::write(&partial, bytes)?;
+        std::fs::rename(&partial, &self.path)?;
         Ok(())
     }
 
@@ -141,13 +228,12 @@ impl CrdtMemoryStore {
     #[allow(dead_code)]
     pub fn get_recent(&self, limit: usize) -> Result<Vec<CrdtMemory>> {
         let mut memories = self.get_all()?;
-        memories.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        memories.sort_by_key(|m| std::cmp::Reverse(m.timestamp));
         memories.truncate(limit);
         Ok(memories)
     }
 
     /// Get all memories
-    #[allow(dead_code)]
     pub fn get_all(&self) -> Result<Vec<CrdtMemory>> {
         let memories_list = self.doc.get(ROOT, "memories")?
             .ok_or_else(|| anyhow::anyhow!("Memories list not found"))?;
@@ -190,7 +276,6 @@ impl CrdtMemoryStore {
     }
 
     /// Update memory importance
-    #[allow(dead_code)]
     pub fn update_importance(&mut self, id: &str, importance: f32) -> Result<()> {
         let memories_list = self.doc.get(ROOT, "memories")?
             .ok_or_else(|| anyhow::anyhow!("Memories list not found"))?;
@@ -212,6 +297,28 @@ impl CrdtMemoryStore {
         Err(anyhow::anyhow!("Memory not found: {}", id))
     }
 
+    /// Replace a memory's text
+    pub fn update_content(&mut self, id: &str, content: &str) -> Result<()> {
+        let memories_list = self.doc.get(ROOT, "memories")?
+            .ok_or_else(|| anyhow::anyhow!("Memories list not found"))?;
+
+        let len = self.doc.length(&memories_list.1);
+
+        for i in 0..len {
+            if let Some((_, mem_obj)) = self.doc.get(&memories_list.1, Prop::Seq(i))? {
+                if let Some((automerge::Value::Scalar(s), _)) = self.doc.get(&mem_obj, "id")? {
+                    if s.to_str() == Some(id) {
+                        self.doc.put(&mem_obj, "content", content)?;
+                        self.save()?;
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        Err(anyhow::anyhow!("Memory not found: {}", id))
+    }
+
     /// Add tag to memory
     #[allow(dead_code)]
     pub fn add_tag(&mut self, id: &str, tag: &str) -> Result<()> {
@@ -239,7 +346,6 @@ impl CrdtMemoryStore {
     }
 
     /// Delete a memory
-    #[allow(dead_code)]
     pub fn delete(&mut self, id: &str) -> Result<()> {
         let memories_list = self.doc.get(ROOT, "memories")?
             .ok_or_else(|| anyhow::anyhow!("Memories list not found"))?;
@@ -264,7 +370,17 @@ impl CrdtMemoryStore {
     /// Merge with another document (for sync)
     pub fn merge(&mut self, other_bytes: &[u8]) -> Result<()> {
         let mut other = AutoCommit::load(other_bytes)?;
+        check_layout_version(&other, "The peer's memories")?;
+        // Merge into a copy so a dry run can say what the peer brings
+        if self.dry_run {
+            let before = self.count()?;
+            let mut preview = Self { doc: self.doc.fork(), path: self.path.clone(), dry_run: true };
+            preview.doc.merge(&mut other)?;
+            dry_run::report(format!("merge the peer's memories: {} -> {}", before, preview.count()?));
+            return Ok(());
+        }
         self.doc.merge(&mut other)?;
+        self.migrate()?;
         self.save()?;
         Ok(())
     }
@@ -294,6 +410,7 @@ impl CrdtMemoryStore {
     #[allow(dead_code)]
     pub fn apply_sync_changes(&mut self, changes: &[u8]) -> Result<()> {
         self.doc.load_incremental(changes)?;
+        self.migrate()?;
         self.save()?;
         Ok(())
     }
@@ -372,7 +489,7 @@ mod tests {
     #[test]
     fn test_crdt_memory_basic() {
         let dir = tempdir().unwrap();
-        let mut store = CrdtMemoryStore::new(&dir.path().to_path_buf()).unwrap();
+        let mut store = CrdtMemoryStore::new(dir.path()).unwrap();
 
         // Add memory
         let id = store.add("Test memory", CrdtMemoryType::Fact).unwrap();
@@ -390,13 +507,13 @@ mod tests {
         let dir2 = tempdir().unwrap();
 
         // Create store and add initial memory
-        let mut store1 = CrdtMemoryStore::new(&dir1.path().to_path_buf()).unwrap();
+        let mut store1 = CrdtMemoryStore::new(dir1.path()).unwrap();
         store1.add("Initial shared memory", CrdtMemoryType::Fact).unwrap();
 
         // Export and create second store from same state (simulating device sync)
         let initial_bytes = store1.export();
         std::fs::write(dir2.path().join("memories.automerge"), &initial_bytes).unwrap();
-        let mut store2 = CrdtMemoryStore::new(&dir2.path().to_path_buf()).unwrap();
+        let mut store2 = CrdtMemoryStore::new(dir2.path()).unwrap();
 
         // Now add different memories to each (concurrent edits)
         store1.add("Memory from device 1", CrdtMemoryType::Fact).unwrap();
@@ -416,4 +533,30 @@ mod tests {
         assert_eq!(memories1.len(), 3);
         assert_eq!(memories2.len(), 3);
     }
+
+    #[test]
+    fn test_layout_is_versioned() {
+        let dir = tempdir().unwrap();
+        let data_dir = dir.path().to_path_buf();
+
+        // A document written before layouts were versioned
+        let mut legacy = AutoCommit::new();
+        legacy.put_object(ROOT, "memories", ObjType::List).unwrap();
+        legacy.put_object(ROOT, "metadata", ObjType::Map).unwrap();
+        std::fs::write(data_dir.join("memories.automerge"), legacy.save()).unwrap();
+
+        let mut store = CrdtMemoryStore::new(&data_dir).unwrap();
+        assert_eq!(layout_version(&store.doc).unwrap(), LAYOUT_MIGRATIONS.len());
+        store.add("Kept", CrdtMemoryType::Fact).unwrap();
+
+        // A document from a newer build is refused, on load and on merge
+        let mut newer = AutoCommit::load(&store.export()).unwrap();
+        let metadata = newer.get(ROOT, "metadata").unwrap().unwrap().1;
+        newer.put(&metadata, "schema_version", LAYOUT_MIGRATIONS.len() as u64 + 1).unwrap();
+        assert!(store.merge(&newer.save()).is_err());
+        assert_eq!(store.count().unwrap(), 1);
+
+        std::fs::write(data_dir.join("memories.automerge"), newer.save()).unwrap();
+        assert!(CrdtMemoryStore::new(&data_dir).is_err());
+    }
 }
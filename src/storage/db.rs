@@ -0,0 +1,128 @@
+use anyhow::{bail, Context, Result};
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+use std::path::Path;
+use std::time::Duration;
+
+/// How long a statement waits for another connection's lock before failing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Open a database for the stores, in WAL mode with a busy timeout
+///
+/// With WAL, readers never block the writer and the writer never blocks readers;
+/// the timeout makes a second writer (the daemon's watcher re-indexing while
+/// a CLI command stores a memory) wait its turn instead of failing with
+/// "database is locked".
+pub fn open(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    // Safe with WAL: a crash can lose the last commits, never corrupt the file
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    Ok(conn)
+}
+
+/// One step in the evolution of a database's schema
+///
+/// A store lists its migrations in order; the position of the last one applied
+/// (1-based) is kept in the database's `user_version`. Steps are never edited
+/// or reordered once released: schema changes are made by appending a step.
+pub struct Migration {
+    pub description: &'static str,
+    pub apply: fn(&Connection) -> Result<()>,
+}
+
+/// Apply the migrations `conn` has not seen yet, recording the new schema version
+///
+/// All pending steps run in one write transaction, so a failed step leaves the
+/// database as it was and two processes opening it at once cannot both apply a step.
+/// Databases created before versioning start at 0, so the first steps must
+/// tolerate tables that already exist.
+pub fn migrate(conn: &Connection, name: &str, migrations: &[Migration]) -> Result<()> {
+    if schema_version(conn)? == migrations.len() {
+        return Ok(());
+    }
+
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let version = schema_version(&tx)?;
+    if version > migrations.len() {
+        bail!(
+            "{} has schema version {}, but this build of Sovereign only knows up to {}; upgrade Sovereign to open it",
+            name,
+            version,
+            migrations.len()
+        );
+    }
+
+    for (step, migration) in migrations.iter().enumerate().skip(version) {
+        (migration.apply)(&tx)
+            .with_context(|| format!("Migrating {} to schema version {} ({})", name, step + 1, migration.description))?;
+    }
+    tx.pragma_update(None, "user_version", migrations.len() as i64)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// The number of migrations applied to a database
+pub fn schema_version(conn: &Connection) -> Result<usize> {
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    Ok(version as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writers_wait_instead_of_failing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.db");
+        let first = open(&path).unwrap();
+        let second = open(&path).unwrap();
+
+        let mode: String = first.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+        assert_eq!(mode, "wal");
+        first.execute("CREATE TABLE t (n INTEGER)", []).unwrap();
+
+        // A reader mid-transaction does not block the other connection's writes
+        let read = first.unchecked_transaction().unwrap();
+        let _: i64 = read.query_row("SELECT COUNT(*) FROM t", [], |row| row.get(0)).unwrap();
+        second.execute("INSERT INTO t (n) VALUES (1)", []).unwrap();
+        read.commit().unwrap();
+
+        let count: i64 = first.query_row("SELECT COUNT(*) FROM t", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_migrations_apply_once_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = open(&dir.path().join("test.db")).unwrap();
+        let mut migrations = vec![
+            Migration {
+                description: "notes table",
+                apply: |conn| Ok(conn.execute_batch("CREATE TABLE notes (body TEXT NOT NULL)")?),
+            },
+            Migration {
+                description: "note titles",
+                apply: |conn| Ok(conn.execute_batch("ALTER TABLE notes ADD COLUMN title TEXT")?),
+            },
+        ];
+
+        migrate(&conn, "test.db", &migrations).unwrap();
+        migrate(&conn, "test.db", &migrations).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), 2);
+        conn.execute("INSERT INTO notes (body, title) VALUES ('b', 't')", []).unwrap();
+
+        // A failing step rolls back with the version unchanged
+        migrations.push(Migration {
+            description: "broken",
+            apply: |conn| Ok(conn.execute_batch("ALTER TABLE missing ADD COLUMN x TEXT")?),
+        });
+        let error = migrate(&conn, "test.db", &migrations).unwrap_err();
+        assert!(format!("{:#}", error).contains("schema version 3 (broken)"));
+        assert_eq!(schema_version(&conn).unwrap(), 2);
+
+        // A database from a newer build is refused
+        assert!(migrate(&conn, "test.db", &migrations[..1]).is_err());
+    }
+}
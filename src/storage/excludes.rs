@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// File at a project root listing paths to keep out of the index, in `.gitignore` syntax
+pub const SOVEREIGN_IGNORE_FILE: &str = ".sovereignignore";
+
+/// Files larger than this many bytes are indexed without their content by default
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// `[index]` section of `sovereign.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndexConfig {
+    /// Patterns in `.gitignore` syntax, relative to the project root, for
+    /// generated code, fixtures or vendored files that git tracks
+    pub exclude: Vec<String>,
+    /// Larger files keep only their path, language and size in the index;
+    /// 0 stores every file in full
+    pub max_file_size: u64,
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self {
+            exclude: Vec::new(),
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+        }
+    }
+}
+
+/// Paths excluded by a project's `.sovereignignore` and `index.exclude`
+///
+/// Applied on top of `.gitignore` by the indexer, the watcher and embedding,
+/// so an excluded file is never indexed, re-indexed on change, or embedded.
+#[derive(Clone)]
+pub struct IndexExcludes {
+    matcher: Gitignore,
+}
+
+impl IndexExcludes {
+    pub fn load(root: &Path, patterns: &[String]) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new(root);
+
+        let ignore_file = root.join(SOVEREIGN_IGNORE_FILE);
+        if ignore_file.exists() {
+            if let Some(err) = builder.add(&ignore_file) {
+                return Err(err).with_context(|| format!("Failed to read {}", ignore_file.display()));
+            }
+        }
+
+        for pattern in patterns {
+            builder
+                .add_line(None, pattern)
+                .with_context(|| format!("Invalid index.exclude pattern {:?}", pattern))?;
+        }
+
+        Ok(Self {
+            matcher: builder.build()?,
+        })
+    }
+
+    /// Whether `path`, or a directory containing it, is excluded; paths
+    /// outside the project root never are
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        path.starts_with(self.matcher.path())
+            && self.matcher.matched_path_or_any_parents(path, is_dir).is_ignore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_file_and_config_patterns_combine() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join(SOVEREIGN_IGNORE_FILE), "fixtures/\n*.generated.rs\n!keep.generated.rs\n").unwrap();
+
+        let excludes = IndexExcludes::load(root.path(), &["vendor/**".to_string()]).unwrap();
+        let excluded = |rel: &str| excludes.is_excluded(&root.path().join(rel), false);
+
+        assert!(excluded("fixtures/big.json"));
+        assert!(excluded("src/api.generated.rs"));
+        assert!(excluded("vendor/lib/mod.rs"));
+        assert!(!excluded("src/keep.generated.rs"));
+        assert!(!excluded("src/main.rs"));
+        assert!(!excludes.is_excluded(Path::new("/elsewhere/fixtures/a.rs"), false));
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+/// Extensions tried, in order, for extensionless JavaScript/TypeScript imports
+const JS_EXTENSIONS: &[&str] = &["ts", "tsx", "js", "jsx", "mjs", "cjs"];
+
+/// Module specifiers imported by a file, as written in the source
+///
+/// Regex-free like symbol extraction: recognises the common single-line forms
+/// of `use`/`mod`, `import`/`from`, `require(...)` and `#include "..."`.
+pub fn extract_imports(content: &str, language: &str) -> Vec<String> {
+    let mut imports = Vec::new();
+    let mut in_go_block = false;
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+
+        match language {
+            "rust" => {
+                let stmt = trimmed.strip_prefix("pub ").unwrap_or(trimmed);
+                let stmt = stmt.strip_prefix("pub(crate) ").unwrap_or(stmt);
+                if let Some(path) = stmt.strip_prefix("use ") {
+                    imports.extend(expand_use_tree(path.trim_end_matches(';').trim()));
+                } else if let Some(name) = stmt.strip_prefix("mod ") {
+                    // `mod foo;` declares a file; `mod foo {` is inline
+                    if let Some(name) = name.trim().strip_suffix(';') {
+                        imports.push(format!("mod {}", name.trim()));
+                    }
+                }
+            }
+            "python" => {
+                if let Some(rest) = trimmed.strip_prefix("from ") {
+                    if let Some((module, names)) = rest.split_once(" import ") {
+                        let module = module.trim();
+                        imports.push(module.to_string());
+                        // `from pkg import mod` may name submodules
+                        for name in names.trim_matches(|c| c == '(' || c == ')').split(',') {
+                            let name = name.split_whitespace().next().unwrap_or("");
+                            if !name.is_empty() && name != "*" {
+                                let sep = if module.ends_with('.') { "" } else { "." };
+                                imports.push(format!("{}{}{}", module, sep, name));
+                            }
+                        }
+                    }
+                } else if let Some(rest) = trimmed.strip_prefix("import ") {
+                    for module in rest.split(',') {
+                        if let Some(module) = module.split_whitespace().next() {
+                            imports.push(module.to_string());
+                        }
+                    }
+                }
+            }
+            "javascript" | "typescript" => {
+                let is_import = trimmed.starts_with("import ") || trimmed.starts_with("export ") || trimmed.contains("require(");
+                let spec = quoted_after(trimmed, " from ")
+                    .or_else(|| quoted_after(trimmed, "require("))
+                    .or_else(|| quoted_after(trimmed, "import "));
+                if let Some(spec) = spec.filter(|_| is_import) {
+                    imports.push(spec);
+                }
+            }
+            "go" => {
+                if trimmed.starts_with("import (") {
+                    in_go_block = true;
+                } else if in_go_block && trimmed.starts_with(')') {
+                    in_go_block = false;
+                } else if in_go_block || trimmed.starts_with("import ") {
+                    if let Some(spec) = quoted_after(trimmed, "") {
+                        imports.push(spec);
+                    }
+                }
+            }
+            "java" | "kotlin" | "scala" => {
+                if let Some(rest) = trimmed.strip_prefix("import ") {
+                    let rest = rest.strip_prefix("static ").unwrap_or(rest);
+                    imports.push(rest.trim_end_matches(';').trim().to_string());
+                }
+            }
+            "c" | "cpp" => {
+                if let Some(rest) = trimmed.strip_prefix("#include") {
+                    // Only local includes; <system> headers are never in the index
+                    if let Some(spec) = quoted_after(rest, "") {
+                        imports.push(spec);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    imports.dedup();
+    imports
+}
+
+/// Indexed files (relative paths) that an import specifier refers to
+pub fn resolve_import(source: &str, spec: &str, language: &str, files: &HashSet<String>) -> Vec<String> {
+    let dir = Path::new(source).parent().unwrap_or(Path::new(""));
+    let exists = |candidate: &PathBuf| -> Option<String> {
+        let candidate = normalize(candidate);
+        files.contains(&candidate).then_some(candidate)
+    };
+
+    match language {
+        "rust" => resolve_rust(source, spec, files),
+        "python" => {
+            let leading_dots = spec.chars().take_while(|c| *c == '.').count();
+            let module = spec[leading_dots..].replace('.', "/");
+            let mut bases: Vec<PathBuf> = Vec::new();
+            if leading_dots > 0 {
+                let mut base = dir.to_path_buf();
+                for _ in 1..leading_dots {
+                    base.pop();
+                }
+                bases.push(base);
+            } else {
+                // Absolute imports resolve from the root or a src/ layout
+                bases.push(PathBuf::new());
+                bases.push(PathBuf::from("src"));
+            }
+
+            bases
+                .iter()
+                .flat_map(|base| {
+                    let module_path = base.join(&module);
+                    [
+                        module_path.with_extension("py"),
+                        module_path.join("__init__.py"),
+                    ]
+                })
+                .filter_map(|candidate| exists(&candidate))
+                .take(1)
+                .collect()
+        }
+        "javascript" | "typescript" => {
+            // Package imports are outside the project
+            if !spec.starts_with('.') {
+                return Vec::new();
+            }
+            let target = dir.join(spec);
+            let mut candidates = vec![target.clone()];
+            for ext in JS_EXTENSIONS {
+                candidates.push(PathBuf::from(format!("{}.{}", target.display(), ext)));
+            }
+            for ext in JS_EXTENSIONS {
+                candidates.push(target.join(format!("index.{}", ext)));
+            }
+            candidates.iter().filter_map(exists).take(1).collect()
+        }
+        "go" => {
+            // Match the package directory by the trailing components of its import path
+            let parts: Vec<&str> = spec.split('/').collect();
+            (parts.len().min(2)..=parts.len())
+                .rev()
+                .map(|n| parts[parts.len() - n..].join("/"))
+                .map(|suffix| {
+                    let mut matched: Vec<String> = files
+                        .iter()
+                        .filter(|f| f.ends_with(".go") && !f.ends_with("_test.go"))
+                        .filter(|f| {
+                            let parent = Path::new(f.as_str()).parent().map(normalize).unwrap_or_default();
+                            parent == suffix || parent.ends_with(&format!("/{}", suffix))
+                        })
+                        .cloned()
+                        .collect();
+                    matched.sort();
+                    matched
+                })
+                .find(|matched| !matched.is_empty())
+                .unwrap_or_default()
+        }
+        "java" | "kotlin" | "scala" => {
+            // `com.acme.Foo` is com/acme/Foo.*; `com.acme.*` is every file in com/acme
+            let wildcard = spec.ends_with(".*");
+            let class_path = spec.trim_end_matches(".*").replace('.', "/");
+            let ends_with_path = |p: String| p == class_path || p.ends_with(&format!("/{}", class_path));
+
+            let mut matched: Vec<String> = files
+                .iter()
+                .filter(|f| {
+                    let path = Path::new(f.as_str());
+                    if wildcard {
+                        path.parent().map(|p| ends_with_path(normalize(p))).unwrap_or(false)
+                    } else {
+                        ends_with_path(normalize(&path.with_extension("")))
+                    }
+                })
+                .cloned()
+                .collect();
+            matched.sort();
+            matched
+        }
+        "c" | "cpp" => {
+            if let Some(found) = exists(&dir.join(spec)) {
+                return vec![found];
+            }
+            let mut matched: Vec<String> = files
+                .iter()
+                .filter(|f| f.as_str() == spec || f.ends_with(&format!("/{}", spec)))
+                .cloned()
+                .collect();
+            matched.sort();
+            matched.truncate(1);
+            matched
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Split `a::{b, c::D}` into `a::b` and `a::c::D`
+fn expand_use_tree(tree: &str) -> Vec<String> {
+    match (tree.find('{'), tree.rfind('}')) {
+        (Some(open), Some(close)) if open < close => {
+            let prefix = &tree[..open];
+            let mut items = Vec::new();
+            let mut depth = 0;
+            let mut start = open + 1;
+            for (i, c) in tree[..close].char_indices().skip(open + 1) {
+                match c {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    ',' if depth == 0 => {
+                        items.push(&tree[start..i]);
+                        start = i + 1;
+                    }
+                    _ => {}
+                }
+            }
+            items.push(&tree[start..close]);
+
+            items
+                .into_iter()
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .flat_map(|item| expand_use_tree(&format!("{}{}", prefix, item)))
+                .collect()
+        }
+        _ => vec![tree.split(" as ").next().unwrap_or(tree).trim().to_string()],
+    }
+}
+
+fn resolve_rust(source: &str, spec: &str, files: &HashSet<String>) -> Vec<String> {
+    let source_path = Path::new(source);
+    let dir = source_path.parent().unwrap_or(Path::new(""));
+    let file_name = source_path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let stem = source_path.file_stem().and_then(|n| n.to_str()).unwrap_or("");
+
+    // Directory holding the current module's children
+    let module_dir = if matches!(file_name, "main.rs" | "lib.rs" | "mod.rs") {
+        dir.to_path_buf()
+    } else {
+        dir.join(stem)
+    };
+
+    let module_file = |base: &Path, segments: &[&str]| -> Option<String> {
+        // The longest prefix of the path that is a module file; the rest are items
+        (1..=segments.len()).rev().find_map(|n| {
+            let path = segments[..n].iter().fold(base.to_path_buf(), |p, s| p.join(s));
+            [path.with_extension("rs"), path.join("mod.rs")]
+                .into_iter()
+                .map(|c| normalize(&c))
+                .find(|c| files.contains(c))
+        })
+    };
+
+    if let Some(name) = spec.strip_prefix("mod ") {
+        return module_file(&module_dir, &[name]).into_iter().collect();
+    }
+
+    let segments: Vec<&str> = spec.split("::").collect();
+    let resolved = match segments.first().copied() {
+        Some("crate") => crate_root(source, files).and_then(|root| module_file(&root, &segments[1..])),
+        Some("self") => module_file(&module_dir, &segments[1..]),
+        Some("super") => {
+            let supers = segments.iter().take_while(|s| **s == "super").count();
+            let mut base = module_dir.clone();
+            for _ in 0..supers {
+                base.pop();
+            }
+            module_file(&base, &segments[supers..])
+        }
+        _ => None,
+    };
+
+    resolved.into_iter().collect()
+}
+
+/// Directory of the crate root (`main.rs`/`lib.rs`) that `source` belongs to
+fn crate_root(source: &str, files: &HashSet<String>) -> Option<PathBuf> {
+    let mut dir = Path::new(source).parent();
+    while let Some(d) = dir {
+        for root in ["lib.rs", "main.rs"] {
+            if files.contains(&normalize(&d.join(root))) {
+                return Some(d.to_path_buf());
+            }
+        }
+        dir = d.parent();
+    }
+    None
+}
+
+/// First string literal after `marker`
+fn quoted_after(line: &str, marker: &str) -> Option<String> {
+    let rest = &line[line.find(marker)? + marker.len()..];
+    let quote_start = rest.find(['"', '\'', '`'])?;
+    let quote = rest[quote_start..].chars().next()?;
+    let inner = &rest[quote_start + 1..];
+    let end = inner.find(quote)?;
+    let spec = &inner[..end];
+    (!spec.is_empty()).then(|| spec.to_string())
+}
+
+/// Collapse `.` and `..` and use `/` separators, matching stored relative paths
+fn normalize(path: &Path) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                parts.pop();
+            }
+            other => parts.push(other.as_os_str().to_string_lossy().to_string()),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(paths: &[&str]) -> HashSet<String> {
+        paths.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_rust_use_and_mod() {
+        let source = "mod storage;\nuse crate::storage::{CodebaseIndex, memory::MemoryType};\nuse super::tools::ToolRunner;\nuse std::fs;\n";
+        let imports = extract_imports(source, "rust");
+        assert_eq!(
+            imports,
+            vec!["mod storage", "crate::storage::CodebaseIndex", "crate::storage::memory::MemoryType", "super::tools::ToolRunner", "std::fs"]
+        );
+
+        let files = files(&["src/main.rs", "src/storage/mod.rs", "src/storage/memory.rs", "src/agents/chat.rs", "src/tools.rs"]);
+        assert_eq!(resolve_import("src/main.rs", "mod storage", "rust", &files), vec!["src/storage/mod.rs"]);
+        assert_eq!(
+            resolve_import("src/main.rs", "crate::storage::memory::MemoryType", "rust", &files),
+            vec!["src/storage/memory.rs"]
+        );
+        assert_eq!(resolve_import("src/agents/chat.rs", "super::super::tools::ToolRunner", "rust", &files), vec!["src/tools.rs"]);
+        assert_eq!(resolve_import("src/agents/chat.rs", "crate::tools::ToolRunner", "rust", &files), vec!["src/tools.rs"]);
+        assert!(resolve_import("src/main.rs", "std::fs", "rust", &files).is_empty());
+    }
+
+    #[test]
+    fn test_python_and_javascript() {
+        let imports = extract_imports("from .models import User\nimport app.db, os\n", "python");
+        assert_eq!(imports, vec![".models", ".models.User", "app.db", "os"]);
+
+        let files = files(&["app/models.py", "app/views.py", "app/db/__init__.py", "web/src/api/client.ts", "web/src/app.tsx"]);
+        assert_eq!(resolve_import("app/views.py", ".models", "python", &files), vec!["app/models.py"]);
+        assert_eq!(resolve_import("app/views.py", "app.db", "python", &files), vec!["app/db/__init__.py"]);
+
+        let js = extract_imports("import { get } from './api/client';\nconst x = require(\"lodash\");\n", "typescript");
+        assert_eq!(js, vec!["./api/client", "lodash"]);
+        assert_eq!(resolve_import("web/src/app.tsx", "./api/client", "typescript", &files), vec!["web/src/api/client.ts"]);
+        assert!(resolve_import("web/src/app.tsx", "lodash", "typescript", &files).is_empty());
+    }
+}
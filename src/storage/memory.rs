@@ -1,10 +1,15 @@
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, Row};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::rc::Rc;
 use uuid::Uuid;
 
+use super::db::Migration;
+use crate::dry_run;
+use crate::embeddings::cosine_similarity;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Memory {
     pub id: String,
@@ -48,16 +53,49 @@ impl MemoryType {
     }
 }
 
+/// Memories in `memory.db`; clones share one connection, each with its own default project
+#[derive(Clone)]
 pub struct MemoryStore {
-    conn: Connection,
+    conn: Rc<Connection>,
+    /// Project recorded on memories stored without an explicit one
+    default_project: Option<String>,
+    /// Changes are only described (`--dry-run`)
+    dry_run: bool,
 }
 
+/// Schema of `memory.db`, oldest step first; append to change it
+const MIGRATIONS: &[Migration] = &[
+    Migration { description: "memories and their embeddings", apply: MemoryStore::create_tables },
+    Migration { description: "archive of pruned memories", apply: MemoryStore::create_archive },
+];
+
 impl MemoryStore {
     pub fn new(data_dir: &PathBuf) -> Result<Self> {
         std::fs::create_dir_all(data_dir)?;
         let db_path = data_dir.join("memory.db");
-        let conn = Connection::open(&db_path)?;
+        let conn = super::db::open(&db_path)?;
+
+        super::db::migrate(&conn, "memory.db", MIGRATIONS)?;
+
+        Ok(Self {
+            conn: Rc::new(conn),
+            default_project: None,
+            dry_run: false,
+        })
+    }
+
+    /// Describe changes instead of making them
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
 
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Schema version 1: the tables as created by versions before migrations
+    fn create_tables(conn: &Connection) -> Result<()> {
         conn.execute(
             "CREATE TABLE IF NOT EXISTS memories (
                 id TEXT PRIMARY KEY,
@@ -81,10 +119,48 @@ impl MemoryStore {
             [],
         )?;
 
-        Ok(Self { conn })
+        // One vector per memory, for retrieving memories relevant to a message
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS memory_embeddings (
+                memory_id TEXT PRIMARY KEY,
+                model TEXT NOT NULL,
+                embedding BLOB NOT NULL
+            )",
+            [],
+        )?;
+
+        Ok(())
+    }
+
+    /// Schema version 2: memories removed by the retention policy, kept out of every query
+    fn create_archive(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS archived_memories (
+                id TEXT PRIMARY KEY,
+                content TEXT NOT NULL,
+                memory_type TEXT NOT NULL,
+                project TEXT,
+                tags TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                importance REAL NOT NULL,
+                archived_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_default_project(&mut self, project: &str) {
+        self.default_project = Some(project.to_string());
     }
 
     pub fn store(&self, memory: &Memory) -> Result<()> {
+        if self.dry_run {
+            let preview: String = memory.content.chars().take(60).collect();
+            dry_run::report(format!("remember ({}): {}", memory.memory_type.as_str(), preview));
+            return Ok(());
+        }
+
         let tags_json = serde_json::to_string(&memory.tags)?;
 
         self.conn.execute(
@@ -112,12 +188,21 @@ impl MemoryStore {
         tags: Vec<String>,
         importance: f32,
     ) -> Result<Memory> {
+        let project = project.map(|s| s.to_string()).or_else(|| self.default_project.clone());
+
+        let mut all_tags = tags;
+        for tag in extract_tags(content, project.as_deref()) {
+            if !all_tags.contains(&tag) {
+                all_tags.push(tag);
+            }
+        }
+
         let memory = Memory {
             id: Uuid::new_v4().to_string(),
             content: content.to_string(),
             memory_type,
-            project: project.map(|s| s.to_string()),
-            tags,
+            project,
+            tags: all_tags,
             created_at: Utc::now(),
             importance,
         };
@@ -264,6 +349,261 @@ impl MemoryStore {
         Ok(memories)
     }
 
+    /// Memories created at or after `since`, oldest first
+    pub fn get_since(&self, since: DateTime<Utc>, limit: usize) -> Result<Vec<Memory>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, content, memory_type, project, tags, created_at, importance
+             FROM memories
+             WHERE created_at >= ?1
+             ORDER BY created_at ASC
+             LIMIT ?2",
+        )?;
+
+        let memories = stmt
+            .query_map(params![since.to_rfc3339(), limit as i64], |row| {
+                let tags_json: String = row.get(4)?;
+                let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
+                let created_str: String = row.get(5)?;
+                let created_at = DateTime::parse_from_rfc3339(&created_str)
+                    .map(|dt| dt.with_timezone(&Utc))
+                    .unwrap_or_else(|_| Utc::now());
+
+                Ok(Memory {
+                    id: row.get(0)?,
+                    content: row.get(1)?,
+                    memory_type: MemoryType::from_str(&row.get::<_, String>(2)?),
+                    project: row.get(3)?,
+                    tags,
+                    created_at,
+                    importance: row.get(6)?,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(memories)
+    }
+
+    /// Memories carrying `tag` exactly, e.g. `lang:rust` or `symbol:CodebaseIndex`
+    pub fn get_by_tag(&self, tag: &str, limit: usize) -> Result<Vec<Memory>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, content, memory_type, project, tags, created_at, importance
+             FROM memories
+             WHERE tags LIKE ?1
+             ORDER BY importance DESC, created_at DESC
+             LIMIT ?2",
+        )?;
+
+        // Tags are stored as a JSON array, so the quotes anchor a whole tag
+        let pattern = format!("%{}%", serde_json::to_string(tag)?);
+        let memories = stmt
+            .query_map(params![pattern, limit as i64], |row| {
+                let tags_json: String = row.get(4)?;
+                let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
+                let created_str: String = row.get(5)?;
+                let created_at = DateTime::parse_from_rfc3339(&created_str)
+                    .map(|dt| dt.with_timezone(&Utc))
+                    .unwrap_or_else(|_| Utc::now());
+
+                Ok(Memory {
+                    id: row.get(0)?,
+                    content: row.get(1)?,
+                    memory_type: MemoryType::from_str(&row.get::<_, String>(2)?),
+                    project: row.get(3)?,
+                    tags,
+                    created_at,
+                    importance: row.get(6)?,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(memories)
+    }
+
+    pub fn store_embedding(&self, memory_id: &str, model: &str, embedding: &[f32]) -> Result<()> {
+        // The memory itself was not stored
+        if self.dry_run {
+            return Ok(());
+        }
+        let bytes: Vec<u8> = embedding.iter().flat_map(|f| f.to_le_bytes()).collect();
+        self.conn.execute(
+            "INSERT OR REPLACE INTO memory_embeddings (memory_id, model, embedding) VALUES (?1, ?2, ?3)",
+            params![memory_id, model, bytes],
+        )?;
+        Ok(())
+    }
+
+    /// Memories with no vector from `model` yet, newest first
+    pub fn unembedded(&self, model: &str, limit: usize) -> Result<Vec<Memory>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT m.id, m.content, m.memory_type, m.project, m.tags, m.created_at, m.importance
+             FROM memories m
+             LEFT JOIN memory_embeddings e ON e.memory_id = m.id AND e.model = ?1
+             WHERE e.memory_id IS NULL
+             ORDER BY m.created_at DESC
+             LIMIT ?2",
+        )?;
+
+        let memories = stmt
+            .query_map(params![model, limit as i64], |row| {
+                let tags_json: String = row.get(4)?;
+                let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
+                let created_str: String = row.get(5)?;
+                let created_at = DateTime::parse_from_rfc3339(&created_str)
+                    .map(|dt| dt.with_timezone(&Utc))
+                    .unwrap_or_else(|_| Utc::now());
+
+                Ok(Memory {
+                    id: row.get(0)?,
+                    content: row.get(1)?,
+                    memory_type: MemoryType::from_str(&row.get::<_, String>(2)?),
+                    project: row.get(3)?,
+                    tags,
+                    created_at,
+                    importance: row.get(6)?,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(memories)
+    }
+
+    /// Memories most similar to `query_embedding`, at least `min_similarity`, best first
+    pub fn search_similar(
+        &self,
+        query_embedding: &[f32],
+        model: &str,
+        limit: usize,
+        min_similarity: f32,
+    ) -> Result<Vec<(Memory, f32)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT m.id, m.content, m.memory_type, m.project, m.tags, m.created_at, m.importance, e.embedding
+             FROM memories m
+             JOIN memory_embeddings e ON e.memory_id = m.id
+             WHERE e.model = ?1",
+        )?;
+
+        let mut scored: Vec<(Memory, f32)> = stmt
+            .query_map(params![model], |row| {
+                let tags_json: String = row.get(4)?;
+                let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
+                let created_str: String = row.get(5)?;
+                let created_at = DateTime::parse_from_rfc3339(&created_str)
+                    .map(|dt| dt.with_timezone(&Utc))
+                    .unwrap_or_else(|_| Utc::now());
+                let bytes: Vec<u8> = row.get(7)?;
+                let embedding: Vec<f32> = bytes
+                    .chunks(4)
+                    .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap_or([0; 4])))
+                    .collect();
+
+                Ok((
+                    Memory {
+                        id: row.get(0)?,
+                        content: row.get(1)?,
+                        memory_type: MemoryType::from_str(&row.get::<_, String>(2)?),
+                        project: row.get(3)?,
+                        tags,
+                        created_at,
+                        importance: row.get(6)?,
+                    },
+                    embedding,
+                ))
+            })?
+            .filter_map(|r| r.ok())
+            .filter(|(_, embedding)| embedding.len() == query_embedding.len())
+            .map(|(memory, embedding)| {
+                let similarity = cosine_similarity(query_embedding, &embedding);
+                (memory, similarity)
+            })
+            .filter(|(_, similarity)| *similarity >= min_similarity)
+            .collect();
+
+        scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        scored.truncate(limit);
+        Ok(scored)
+    }
+
+    /// Every memory, oldest first
+    pub fn all(&self) -> Result<Vec<Memory>> {
+        let memories = self.conn
+            .prepare(
+                "SELECT id, content, memory_type, project, tags, created_at, importance
+                 FROM memories
+                 ORDER BY created_at ASC",
+            )?
+            .query_map([], memory_from_row)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(memories)
+    }
+
+    /// Remove memories and their embeddings, moving them to the archive first
+    /// when `archive` is set; returns how many were removed
+    pub fn remove(&self, ids: &[&str], archive: bool) -> Result<usize> {
+        if self.dry_run {
+            let action = if archive { "archive" } else { "delete" };
+            dry_run::report(format!("{} {} memories", action, ids.len()));
+            return Ok(0);
+        }
+
+        let tx = self.conn.unchecked_transaction()?;
+        let now = Utc::now().to_rfc3339();
+        let mut removed = 0;
+        for id in ids {
+            if archive {
+                tx.execute(
+                    "INSERT OR REPLACE INTO archived_memories
+                     SELECT id, content, memory_type, project, tags, created_at, importance, ?2
+                     FROM memories WHERE id = ?1",
+                    params![id, now],
+                )?;
+            }
+            tx.execute("DELETE FROM memory_embeddings WHERE memory_id = ?1", params![id])?;
+            removed += tx.execute("DELETE FROM memories WHERE id = ?1", params![id])?;
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    /// Memories whose id starts with `prefix`
+    pub fn find(&self, prefix: &str) -> Result<Vec<Memory>> {
+        let memories = self.conn
+            .prepare(
+                "SELECT id, content, memory_type, project, tags, created_at, importance
+                 FROM memories
+                 WHERE id LIKE ?1
+                 ORDER BY created_at DESC",
+            )?
+            .query_map(params![format!("{}%", prefix.replace(['%', '_'], ""))], memory_from_row)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(memories)
+    }
+
+    /// Replace a memory's text; its embedding is dropped so it is embedded again
+    pub fn update_content(&self, id: &str, content: &str) -> Result<bool> {
+        let updated = self.conn.execute("UPDATE memories SET content = ?2 WHERE id = ?1", params![id, content])?;
+        self.conn.execute("DELETE FROM memory_embeddings WHERE memory_id = ?1", params![id])?;
+        Ok(updated > 0)
+    }
+
+    pub fn set_importance(&self, id: &str, importance: f32) -> Result<bool> {
+        let updated = self.conn.execute("UPDATE memories SET importance = ?2 WHERE id = ?1", params![id, importance])?;
+        Ok(updated > 0)
+    }
+
+    /// Memories the retention policy has archived
+    #[cfg(test)]
+    pub fn archived_count(&self) -> Result<usize> {
+        let count: i64 = self.conn.query_row("SELECT COUNT(*) FROM archived_memories", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
     #[allow(dead_code)]
     pub fn count(&self) -> Result<usize> {
         let count: i64 = self.conn.query_row(
@@ -274,3 +614,192 @@ impl MemoryStore {
         Ok(count as usize)
     }
 }
+
+fn memory_from_row(row: &Row) -> rusqlite::Result<Memory> {
+    let tags_json: String = row.get(4)?;
+    let created_str: String = row.get(5)?;
+    Ok(Memory {
+        id: row.get(0)?,
+        content: row.get(1)?,
+        memory_type: MemoryType::from_str(&row.get::<_, String>(2)?),
+        project: row.get(3)?,
+        tags: serde_json::from_str(&tags_json).unwrap_or_default(),
+        created_at: DateTime::parse_from_rfc3339(&created_str)
+            .map(|dt| dt.with_timezone(&Utc))
+            .unwrap_or_else(|_| Utc::now()),
+        importance: row.get(6)?,
+    })
+}
+
+/// File extensions and phrases that mark a memory as being about a language
+const LANGUAGE_MARKERS: &[(&str, &[&str], &[&str])] = &[
+    ("rust", &["rs"], &["rust", "cargo", "fn ", "impl ", "let mut"]),
+    ("python", &["py"], &["python", "def ", "pip install"]),
+    ("javascript", &["js", "jsx", "mjs"], &["javascript", "npm ", "node.js"]),
+    ("typescript", &["ts", "tsx"], &["typescript"]),
+    ("go", &["go"], &["golang", "func ", "go mod"]),
+    ("java", &["java"], &["java ", "public class"]),
+    ("c", &["c", "h"], &["#include"]),
+    ("cpp", &["cpp", "cc", "hpp"], &["c++", "std::"]),
+    ("ruby", &["rb"], &["ruby", "gem install"]),
+    ("sql", &["sql"], &["select * from", "create table"]),
+    ("shell", &["sh", "bash"], &["#!/bin"]),
+];
+
+/// Words too common to say anything about what a memory is about
+pub(crate) const STOPWORDS: &[&str] = &[
+    "about", "after", "also", "assistant", "because", "before", "being", "could", "does",
+    "from", "have", "here", "into", "just", "like", "make", "more", "code", "need", "only",
+    "other", "should", "some", "than", "that", "their", "them", "then", "there", "these",
+    "they", "this", "user", "using", "want", "what", "when", "where", "which", "while",
+    "will", "with", "would", "your",
+];
+
+const MAX_SYMBOL_TAGS: usize = 5;
+const MAX_KEYWORD_TAGS: usize = 3;
+
+/// Tags derived from a memory's text with a cheap local pass (no model call)
+///
+/// Produces `project:<name>`, `lang:<language>`, `symbol:<identifier>` for
+/// code-looking identifiers, and a few plain keywords that recur in the text.
+pub fn extract_tags(content: &str, project: Option<&str>) -> Vec<String> {
+    let mut tags = Vec::new();
+
+    if let Some(project) = project {
+        tags.push(format!("project:{}", project));
+    }
+
+    let lower = content.to_lowercase();
+    let fence_languages: Vec<&str> = content
+        .split("```")
+        .skip(1)
+        .step_by(2)
+        .filter_map(|block| block.lines().next())
+        .map(|info| info.trim())
+        .collect();
+    let extensions: Vec<&str> = lower
+        .split(|c: char| c.is_whitespace() || matches!(c, '`' | '(' | ')' | ',' | ';' | '"' | '\''))
+        .filter_map(|token| token.trim_end_matches(['.', ':']).rsplit_once('.'))
+        .filter(|(stem, _)| !stem.is_empty())
+        .map(|(_, ext)| ext)
+        .collect();
+
+    for (language, exts, phrases) in LANGUAGE_MARKERS {
+        let fenced = fence_languages.iter().any(|f| f.eq_ignore_ascii_case(language));
+        if fenced || exts.iter().any(|e| extensions.contains(e)) || phrases.iter().any(|p| lower.contains(p)) {
+            tags.push(format!("lang:{}", language));
+        }
+    }
+
+    let mut symbols: Vec<String> = Vec::new();
+    let mut word_counts: Vec<(String, usize)> = Vec::new();
+    let words = content.split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '`'));
+
+    for raw in words {
+        let backticked = raw.starts_with('`') && raw.ends_with('`') && raw.len() > 2;
+        let word = raw.trim_matches('`');
+        if word.is_empty() || word.chars().next().map(|c| c.is_ascii_digit()).unwrap_or(true) {
+            continue;
+        }
+
+        if backticked || looks_like_identifier(word) {
+            if symbols.len() < MAX_SYMBOL_TAGS && !symbols.iter().any(|s| s == word) {
+                symbols.push(word.to_string());
+            }
+            continue;
+        }
+
+        let word = word.to_lowercase();
+        if word.len() < 4 || STOPWORDS.contains(&word.as_str()) {
+            continue;
+        }
+        match word_counts.iter_mut().find(|(w, _)| *w == word) {
+            Some((_, count)) => *count += 1,
+            None => word_counts.push((word, 1)),
+        }
+    }
+
+    tags.extend(symbols.into_iter().map(|s| format!("symbol:{}", s)));
+
+    // Stable sort keeps first-mention order among equally frequent words
+    word_counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    tags.extend(
+        word_counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .take(MAX_KEYWORD_TAGS)
+            .map(|(word, _)| word),
+    );
+
+    tags
+}
+
+/// snake_case with an underscore, or CamelCase with an inner capital
+fn looks_like_identifier(word: &str) -> bool {
+    if word.len() < 3 {
+        return false;
+    }
+    let snake = word.trim_matches('_').contains('_');
+    let camel = word.chars().next().map(|c| c.is_ascii_uppercase()).unwrap_or(false)
+        && word.chars().skip(1).any(|c| c.is_ascii_uppercase())
+        && word.chars().any(|c| c.is_ascii_lowercase());
+    snake || camel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_tags() {
+        let tags = extract_tags(
+            "Refactoring: make `load_config` return Result in src/config.rs; config loading \
+             should cache the parsed config in CodebaseIndex",
+            Some("sovereign"),
+        );
+
+        assert_eq!(tags[0], "project:sovereign");
+        assert!(tags.contains(&"lang:rust".to_string()));
+        assert!(tags.contains(&"symbol:load_config".to_string()));
+        assert!(tags.contains(&"symbol:CodebaseIndex".to_string()));
+        assert!(tags.contains(&"config".to_string()));
+        assert!(!tags.contains(&"lang:python".to_string()));
+    }
+
+    #[test]
+    fn test_search_similar_memories() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MemoryStore::new(&dir.path().to_path_buf()).unwrap();
+
+        let sync = store.remember("Sync uses automerge", MemoryType::Fact, None, vec![], 0.5).unwrap();
+        let style = store.remember("Prefer early returns", MemoryType::Preference, None, vec![], 0.8).unwrap();
+        store.remember("Not embedded yet", MemoryType::Fact, None, vec![], 0.5).unwrap();
+
+        store.store_embedding(&sync.id, "nomic-embed-text", &[1.0, 0.0, 0.0]).unwrap();
+        store.store_embedding(&style.id, "nomic-embed-text", &[0.0, 1.0, 0.0]).unwrap();
+        assert_eq!(store.unembedded("nomic-embed-text", 10).unwrap().len(), 1);
+
+        let found = store.search_similar(&[0.9, 0.1, 0.0], "nomic-embed-text", 5, 0.5).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0.id, sync.id);
+
+        // Vectors from another model are not compared
+        assert!(store.search_similar(&[0.9, 0.1, 0.0], "other-model", 5, 0.0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_remember_merges_tags_and_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = MemoryStore::new(&dir.path().to_path_buf()).unwrap();
+        store.set_default_project("sovereign");
+
+        let memory = store
+            .remember("Prefer `anyhow::Result` in main.rs", MemoryType::Preference, None, vec!["preference".to_string()], 0.8)
+            .unwrap();
+        assert_eq!(memory.project.as_deref(), Some("sovereign"));
+        assert_eq!(memory.tags[0], "preference");
+
+        assert_eq!(store.get_by_tag("lang:rust", 10).unwrap().len(), 1);
+        assert!(store.get_by_tag("lang:rus", 10).unwrap().is_empty());
+    }
+}
@@ -1,15 +1,25 @@
 pub mod memory;
 pub mod codebase;
 pub mod crdt_memory;
+pub mod analytics;
+pub mod imports;
+pub mod excludes;
+pub mod transcripts;
+pub mod retention;
+pub mod db;
 
 pub use memory::MemoryStore;
 pub use codebase::CodebaseIndex;
 pub use crdt_memory::CrdtMemoryStore;
+pub use analytics::AnalyticsStore;
+pub use transcripts::TranscriptStore;
 
 // Re-export types that are part of the public API
 #[allow(unused_imports)]
 pub use memory::{Memory, MemoryType};
 #[allow(unused_imports)]
-pub use codebase::{CodebaseStats, IndexedFile};
+pub use codebase::{CachedAnswer, ChunkEmbedding, CodebaseStats, GlossaryEntry, IndexChanges, IndexSize, IndexedFile, KeywordHit, SearchFilters, Symbol};
 #[allow(unused_imports)]
 pub use crdt_memory::{CrdtMemory, CrdtMemoryType};
+#[allow(unused_imports)]
+pub use transcripts::Exchange;
@@ -0,0 +1,185 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::memory::{Memory, MemoryStore};
+
+/// `[retention]` section of `sovereign.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Days for a memory's importance to halve; 0 turns decay off
+    pub half_life_days: f64,
+    /// Memory types whose importance does not decay
+    pub no_decay: Vec<String>,
+    /// Memories whose decayed importance falls below this are pruned
+    pub min_importance: f32,
+    /// Memories younger than this are never pruned, though they count towards `max_per_type`
+    pub min_age_days: u32,
+    /// Most memories kept of a type (`conversation = 500`); the least important
+    /// beyond it are pruned
+    pub max_per_type: BTreeMap<String, usize>,
+    /// Move pruned memories to the archive instead of deleting them
+    pub archive: bool,
+    /// Prune when chat starts and while the daemon runs
+    pub auto_prune: bool,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            half_life_days: 30.0,
+            no_decay: vec!["preference".to_string(), "decision".to_string()],
+            min_importance: 0.1,
+            min_age_days: 7,
+            max_per_type: BTreeMap::from([("conversation".to_string(), 500)]),
+            archive: true,
+            auto_prune: true,
+        }
+    }
+}
+
+/// Why the policy prunes a memory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PruneReason {
+    /// Its importance decayed below `min_importance`
+    Faded,
+    /// More memories of its type are kept than `max_per_type` allows
+    OverLimit,
+}
+
+impl PruneReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PruneReason::Faded => "faded",
+            PruneReason::OverLimit => "over limit",
+        }
+    }
+}
+
+/// A memory the policy prunes
+#[derive(Debug, Clone)]
+pub struct Pruned {
+    pub memory: Memory,
+    /// Importance after decay, when it was pruned
+    pub importance: f32,
+    pub reason: PruneReason,
+}
+
+impl RetentionConfig {
+    /// Importance of `memory` at `now`, halving every `half_life_days` since it was stored
+    pub fn decayed_importance(&self, memory: &Memory, now: DateTime<Utc>) -> f32 {
+        if self.half_life_days <= 0.0 || self.no_decay.iter().any(|t| t == memory.memory_type.as_str()) {
+            return memory.importance;
+        }
+        let age_days = (now - memory.created_at).num_seconds().max(0) as f64 / 86_400.0;
+        memory.importance * 0.5f64.powf(age_days / self.half_life_days) as f32
+    }
+
+    /// The memories of `memories` to prune at `now`, with why
+    pub fn plan(&self, memories: &[Memory], now: DateTime<Utc>) -> Vec<Pruned> {
+        let protected_since = now - Duration::days(self.min_age_days as i64);
+        let mut kept: Vec<(&Memory, f32)> = Vec::new();
+        let mut pruned = Vec::new();
+
+        for memory in memories {
+            let importance = self.decayed_importance(memory, now);
+            if memory.created_at < protected_since && importance < self.min_importance {
+                pruned.push(Pruned { memory: memory.clone(), importance, reason: PruneReason::Faded });
+            } else {
+                kept.push((memory, importance));
+            }
+        }
+
+        for (memory_type, &max) in &self.max_per_type {
+            let mut of_type: Vec<&(&Memory, f32)> =
+                kept.iter().filter(|(m, _)| m.memory_type.as_str() == memory_type).collect();
+            // Most important first; among equals, the newest
+            of_type.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.0.created_at.cmp(&a.0.created_at)));
+            pruned.extend(
+                of_type
+                    .into_iter()
+                    .skip(max)
+                    .filter(|(m, _)| m.created_at < protected_since)
+                    .map(|&(memory, importance)| Pruned {
+                        memory: memory.clone(),
+                        importance,
+                        reason: PruneReason::OverLimit,
+                    }),
+            );
+        }
+
+        pruned
+    }
+
+    /// Prune `store` by the policy, archiving or deleting; returns what was pruned
+    pub fn prune(&self, store: &MemoryStore, now: DateTime<Utc>) -> Result<Vec<Pruned>> {
+        let pruned = self.plan(&store.all()?, now);
+        if !pruned.is_empty() {
+            let ids: Vec<&str> = pruned.iter().map(|p| p.memory.id.as_str()).collect();
+            store.remove(&ids, self.archive)?;
+        }
+        Ok(pruned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryType;
+
+    fn memory(content: &str, memory_type: MemoryType, days_old: i64, importance: f32) -> Memory {
+        Memory {
+            id: content.to_string(),
+            content: content.to_string(),
+            memory_type,
+            project: None,
+            tags: vec![],
+            created_at: Utc::now() - Duration::days(days_old),
+            importance,
+        }
+    }
+
+    #[test]
+    fn test_plan_prunes_faded_and_excess_memories() {
+        let config = RetentionConfig {
+            max_per_type: BTreeMap::from([("conversation".to_string(), 2)]),
+            ..Default::default()
+        };
+        let memories = vec![
+            memory("old chat", MemoryType::Conversation, 90, 0.5),
+            memory("old preference", MemoryType::Preference, 400, 0.5),
+            memory("chat a", MemoryType::Conversation, 20, 0.6),
+            memory("chat b", MemoryType::Conversation, 10, 0.6),
+            memory("chat c", MemoryType::Conversation, 15, 0.6),
+            memory("new chat", MemoryType::Conversation, 0, 0.05),
+        ];
+
+        let now = Utc::now();
+        let pruned: Vec<(String, PruneReason)> =
+            config.plan(&memories, now).into_iter().map(|p| (p.memory.id, p.reason)).collect();
+
+        // 0.5 halves three times in 90 days; preferences never fade; new memories are kept
+        assert_eq!(
+            pruned,
+            vec![("old chat".to_string(), PruneReason::Faded), ("chat a".to_string(), PruneReason::OverLimit)]
+        );
+        assert!((config.decayed_importance(&memories[0], now) - 0.0625).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_prune_archives_memories() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MemoryStore::new(&dir.path().to_path_buf()).unwrap();
+        store.store(&memory("old chat", MemoryType::Conversation, 120, 0.5)).unwrap();
+        store.store(&memory("decision", MemoryType::Decision, 120, 0.5)).unwrap();
+        store.store_embedding("old chat", "nomic-embed-text", &[1.0, 0.0]).unwrap();
+
+        let pruned = RetentionConfig::default().prune(&store, Utc::now()).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(store.count().unwrap(), 1);
+        assert_eq!(store.archived_count().unwrap(), 1);
+        assert!(store.search_similar(&[1.0, 0.0], "nomic-embed-text", 5, 0.0).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,231 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, Row};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use super::db::Migration;
+use crate::dry_run;
+use crate::embeddings::cosine_similarity;
+
+/// Characters of an exchange that are embedded; long answers are cut
+const MAX_EMBEDDED_CHARS: usize = 2000;
+
+/// One chat exchange, kept in full
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exchange {
+    pub id: String,
+    pub project: Option<String>,
+    pub question: String,
+    pub answer: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Exchange {
+    /// Text embedded for retrieval: what was asked and the start of the answer
+    pub fn embedding_text(&self) -> String {
+        format!("{}\n{}", self.question, self.answer)
+            .chars()
+            .take(MAX_EMBEDDED_CHARS)
+            .collect()
+    }
+}
+
+/// Full chat transcripts per project, kept so earlier explanations can be
+/// retrieved (`/ask --include-chats`) instead of generated again
+///
+/// Memories keep a 100-character condensation of each exchange; this keeps
+/// the whole text in `transcripts.db` in the data directory.
+pub struct TranscriptStore {
+    conn: Connection,
+    /// Changes are only described (`--dry-run`)
+    dry_run: bool,
+}
+
+/// Schema of `transcripts.db`, oldest step first; append to change it
+const MIGRATIONS: &[Migration] = &[
+    Migration { description: "exchanges and their embeddings", apply: TranscriptStore::create_tables },
+];
+
+impl TranscriptStore {
+    pub fn new(data_dir: &PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(data_dir)?;
+        let conn = super::db::open(&data_dir.join("transcripts.db"))?;
+
+        super::db::migrate(&conn, "transcripts.db", MIGRATIONS)?;
+
+        Ok(Self { conn, dry_run: false })
+    }
+
+    /// Describe changes instead of making them
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Schema version 1: the tables as created by versions before migrations
+    fn create_tables(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS exchanges (
+                id TEXT PRIMARY KEY,
+                project TEXT,
+                question TEXT NOT NULL,
+                answer TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_exchanges_project ON exchanges(project)",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS exchange_embeddings (
+                exchange_id TEXT PRIMARY KEY,
+                model TEXT NOT NULL,
+                embedding BLOB NOT NULL
+            )",
+            [],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn record(&self, project: Option<&str>, question: &str, answer: &str) -> Result<Exchange> {
+        let exchange = Exchange {
+            id: Uuid::new_v4().to_string(),
+            project: project.map(String::from),
+            question: question.to_string(),
+            answer: answer.to_string(),
+            created_at: Utc::now(),
+        };
+        if self.dry_run {
+            dry_run::report("record this exchange in the transcript");
+            return Ok(exchange);
+        }
+
+        self.conn.execute(
+            "INSERT INTO exchanges (id, project, question, answer, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                exchange.id,
+                exchange.project,
+                exchange.question,
+                exchange.answer,
+                exchange.created_at.to_rfc3339(),
+            ],
+        )?;
+
+        Ok(exchange)
+    }
+
+    pub fn store_embedding(&self, exchange_id: &str, model: &str, embedding: &[f32]) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        let bytes: Vec<u8> = embedding.iter().flat_map(|f| f.to_le_bytes()).collect();
+        self.conn.execute(
+            "INSERT OR REPLACE INTO exchange_embeddings (exchange_id, model, embedding) VALUES (?1, ?2, ?3)",
+            params![exchange_id, model, bytes],
+        )?;
+        Ok(())
+    }
+
+    /// Exchanges of a project with no vector from `model` yet, newest first
+    pub fn unembedded(&self, project: Option<&str>, model: &str, limit: usize) -> Result<Vec<Exchange>> {
+        let exchanges = self.conn
+            .prepare(
+                "SELECT x.id, x.project, x.question, x.answer, x.created_at
+                 FROM exchanges x
+                 LEFT JOIN exchange_embeddings e ON e.exchange_id = x.id AND e.model = ?2
+                 WHERE x.project IS ?1 AND e.exchange_id IS NULL
+                 ORDER BY x.created_at DESC
+                 LIMIT ?3",
+            )?
+            .query_map(params![project, model, limit as i64], exchange_from_row)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(exchanges)
+    }
+
+    /// A project's exchanges most similar to `query_embedding`, at least `min_similarity`, best first
+    pub fn search_similar(
+        &self,
+        project: Option<&str>,
+        query_embedding: &[f32],
+        model: &str,
+        limit: usize,
+        min_similarity: f32,
+    ) -> Result<Vec<(Exchange, f32)>> {
+        let mut scored: Vec<(Exchange, f32)> = self.conn
+            .prepare(
+                "SELECT x.id, x.project, x.question, x.answer, x.created_at, e.embedding
+                 FROM exchanges x
+                 JOIN exchange_embeddings e ON e.exchange_id = x.id
+                 WHERE x.project IS ?1 AND e.model = ?2",
+            )?
+            .query_map(params![project, model], |row| {
+                let bytes: Vec<u8> = row.get(5)?;
+                let embedding: Vec<f32> = bytes
+                    .chunks(4)
+                    .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap_or([0; 4])))
+                    .collect();
+                Ok((exchange_from_row(row)?, embedding))
+            })?
+            .filter_map(|r| r.ok())
+            .filter(|(_, embedding)| embedding.len() == query_embedding.len())
+            .map(|(exchange, embedding)| {
+                let similarity = cosine_similarity(query_embedding, &embedding);
+                (exchange, similarity)
+            })
+            .filter(|(_, similarity)| *similarity >= min_similarity)
+            .collect();
+
+        scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        scored.truncate(limit);
+        Ok(scored)
+    }
+}
+
+fn exchange_from_row(row: &Row) -> rusqlite::Result<Exchange> {
+    let created_str: String = row.get(4)?;
+    let created_at = DateTime::parse_from_rfc3339(&created_str)
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now());
+
+    Ok(Exchange {
+        id: row.get(0)?,
+        project: row.get(1)?,
+        question: row.get(2)?,
+        answer: row.get(3)?,
+        created_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcripts_are_searched_per_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = TranscriptStore::new(&dir.path().to_path_buf()).unwrap();
+
+        let long_answer = "The index is a SQLite database. ".repeat(20);
+        let stored = store.record(Some("sovereign"), "How is the index stored?", &long_answer).unwrap();
+        let other = store.record(Some("other"), "How is the index stored?", "In memory").unwrap();
+
+        assert_eq!(store.unembedded(Some("sovereign"), "test-model", 10).unwrap(), vec![stored.clone()]);
+
+        store.store_embedding(&stored.id, "test-model", &[1.0, 0.0]).unwrap();
+        store.store_embedding(&other.id, "test-model", &[1.0, 0.0]).unwrap();
+        assert!(store.unembedded(Some("sovereign"), "test-model", 10).unwrap().is_empty());
+
+        let found = store.search_similar(Some("sovereign"), &[0.9, 0.1], "test-model", 5, 0.5).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0.answer, long_answer);
+        assert!(store.search_similar(Some("sovereign"), &[0.0, 1.0], "test-model", 5, 0.5).unwrap().is_empty());
+    }
+}
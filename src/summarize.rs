@@ -0,0 +1,193 @@
+use anyhow::Result;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use std::future::Future;
+
+/// Characters of input sent in one prompt; larger inputs are summarized in parts
+pub const DEFAULT_CHUNK_CHARS: usize = 6000;
+
+/// Part summaries generated at once
+const CONCURRENCY: usize = 4;
+
+/// Rounds of summarizing part summaries before what is left is cut to size
+const MAX_ROUNDS: usize = 3;
+
+/// One part of an input too large for a single prompt
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    /// Position of the part, from 1
+    pub index: usize,
+    pub total: usize,
+    /// First line of the part in the input (1-based)
+    pub start_line: usize,
+    /// Last line of the part
+    pub end_line: usize,
+    pub text: String,
+}
+
+impl Part {
+    /// "part 2 of 5, lines 120-245", for prompts
+    pub fn describe(&self) -> String {
+        format!("part {} of {}, lines {}-{}", self.index, self.total, self.start_line, self.end_line)
+    }
+}
+
+/// Map-reduce summarization for inputs that do not fit in one prompt
+///
+/// The input is split into parts at natural boundaries (files of a diff,
+/// definitions of a source file), the parts are summarized concurrently, and
+/// the caller combines the part summaries in its own final prompt. Summaries
+/// that together are still too large are summarized again.
+#[derive(Debug, Clone)]
+pub struct MapReduce {
+    chunk_chars: usize,
+}
+
+impl Default for MapReduce {
+    fn default() -> Self {
+        Self::new(DEFAULT_CHUNK_CHARS)
+    }
+}
+
+impl MapReduce {
+    pub fn new(chunk_chars: usize) -> Self {
+        Self { chunk_chars: chunk_chars.max(1) }
+    }
+
+    pub fn fits(&self, text: &str) -> bool {
+        text.len() <= self.chunk_chars
+    }
+
+    /// Split `text` into parts of at most `chunk_chars`, cutting before a line
+    /// where `boundary` holds when that leaves the part at least half full
+    pub fn split(&self, text: &str, boundary: &dyn Fn(&str) -> bool) -> Vec<Part> {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        let mut size = 0;
+        // Latest boundary in the current part, with the size of the part before it
+        let mut last_boundary: Option<(usize, usize)> = None;
+
+        for (i, line) in lines.iter().enumerate() {
+            let line_size = line.len() + 1;
+            if i > start && size + line_size > self.chunk_chars {
+                let cut = match last_boundary {
+                    Some((at, before)) if before >= self.chunk_chars / 2 => at,
+                    _ => i,
+                };
+                ranges.push((start, cut));
+                start = cut;
+                size = lines[cut..i].iter().map(|l| l.len() + 1).sum();
+                last_boundary = None;
+            }
+            if i > start && boundary(line) {
+                last_boundary = Some((i, size));
+            }
+            size += line_size;
+        }
+        if start < lines.len() {
+            ranges.push((start, lines.len()));
+        }
+
+        let total = ranges.len();
+        ranges
+            .into_iter()
+            .enumerate()
+            .map(|(n, (start, end))| Part {
+                index: n + 1,
+                total,
+                start_line: start + 1,
+                end_line: end,
+                // A single line longer than a part is cut
+                text: truncate_chars(&lines[start..end].join("\n"), self.chunk_chars),
+            })
+            .collect()
+    }
+
+    /// None when `text` fits in one prompt; otherwise the summaries `summarize`
+    /// writes of its parts, generated concurrently and joined in order
+    pub async fn summarize_parts<F, Fut>(
+        &self,
+        text: &str,
+        boundary: &dyn Fn(&str) -> bool,
+        summarize: F,
+    ) -> Result<Option<String>>
+    where
+        F: Fn(Part) -> Fut,
+        Fut: Future<Output = Result<String>>,
+    {
+        if self.fits(text) {
+            return Ok(None);
+        }
+
+        let mut text = text.to_string();
+        let mut boundary = boundary;
+        let is_part_heading = |line: &str| line.starts_with("Part ");
+
+        for _ in 0..MAX_ROUNDS {
+            let summaries: Vec<String> = stream::iter(self.split(&text, boundary))
+                .map(|part| {
+                    let heading = format!("Part {}/{} (lines {}-{}):", part.index, part.total, part.start_line, part.end_line);
+                    let summary = summarize(part);
+                    async move { Ok::<_, anyhow::Error>(format!("{}\n{}", heading, summary.await?.trim())) }
+                })
+                .buffered(CONCURRENCY)
+                .try_collect()
+                .await?;
+
+            text = summaries.join("\n\n");
+            if self.fits(&text) {
+                return Ok(Some(text));
+            }
+            // Later rounds summarize the summaries, one part heading at a time
+            boundary = &is_part_heading;
+        }
+
+        Ok(Some(truncate_chars(&text, self.chunk_chars)))
+    }
+}
+
+/// At most `max` bytes of `text`, cut at a character boundary
+fn truncate_chars(text: &str, max: usize) -> String {
+    if text.len() <= max {
+        return text.to_string();
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_prefers_boundaries() {
+        let diff = "diff --git a\n+aaaaaaaaa\ndiff --git b\n+bbbbbbbbb\n";
+        let parts = MapReduce::new(40).split(diff, &|line| line.starts_with("diff --git"));
+        let ranges: Vec<(usize, usize)> = parts.iter().map(|p| (p.start_line, p.end_line)).collect();
+        assert_eq!(ranges, vec![(1, 2), (3, 4)]);
+        assert_eq!(parts[1].describe(), "part 2 of 2, lines 3-4");
+        assert!(parts.iter().all(|p| p.text.len() <= 40));
+
+        // Without a boundary far enough in, parts are cut at the size limit
+        let parts = MapReduce::new(10).split("aaaa\nbbbb\ncccc\n", &|_| false);
+        assert_eq!(parts.iter().map(|p| p.text.as_str()).collect::<Vec<_>>(), vec!["aaaa\nbbbb", "cccc"]);
+    }
+
+    #[tokio::test]
+    async fn test_parts_are_summarized_in_order() {
+        let map_reduce = MapReduce::new(100);
+        let summarize = |part: Part| async move { Ok(format!("{} lines", part.text.lines().count())) };
+
+        assert_eq!(map_reduce.summarize_parts("short", &|_| false, summarize).await.unwrap(), None);
+
+        let text = format!("{}\n", "x".repeat(39)).repeat(6);
+        let summary = map_reduce.summarize_parts(&text, &|_| false, summarize).await.unwrap().unwrap();
+        assert_eq!(
+            summary,
+            "Part 1/3 (lines 1-2):\n2 lines\n\nPart 2/3 (lines 3-4):\n2 lines\n\nPart 3/3 (lines 5-6):\n2 lines"
+        );
+    }
+}
@@ -1,19 +1,50 @@
 use anyhow::Result;
 use std::path::PathBuf;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpStream;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use crate::dry_run;
+use crate::metrics;
+use crate::net;
+
 /// Simple P2P Sync Service for local network sync
 /// Uses direct TCP connections for simplicity and reliability
 pub struct P2PSync {
     data_dir: PathBuf,
     port: u16,
+    /// Sent before each command, and required from peers by `start_server`
+    token: Option<String>,
+    /// Changes are only described (`--dry-run`)
+    dry_run: bool,
 }
 
 impl P2PSync {
     /// Create a new P2P sync service
     pub fn new(data_dir: PathBuf, port: u16) -> Self {
-        Self { data_dir, port }
+        Self { data_dir, port, token: None, dry_run: false }
+    }
+
+    /// Authenticate to peers, and require peers to authenticate, with `token`
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// Describe what syncing would change instead of changing it
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Connect to a peer and send the `AUTH` preamble when a token is set
+    async fn connect(&self, peer_addr: &str) -> Result<TcpStream> {
+        let mut stream = TcpStream::connect(peer_addr).await?;
+        if let Some(token) = &self.token {
+            stream.write_all(b"AUTH").await?;
+            stream.write_all(&(token.len() as u64).to_be_bytes()).await?;
+            stream.write_all(token.as_bytes()).await?;
+        }
+        Ok(stream)
     }
 
     /// Get the local sync file path
@@ -21,20 +52,22 @@ impl P2PSync {
         self.data_dir.join("memories.automerge")
     }
 
-    /// Start listening for sync requests
+    /// Start listening for sync requests on `host`; any address but loopback needs a token
     #[allow(dead_code)]
-    pub async fn start_server(&self) -> Result<()> {
-        let addr = format!("0.0.0.0:{}", self.port);
-        let listener = TcpListener::bind(&addr).await?;
-        println!("  Sync server listening on {}", addr);
+    pub async fn start_server(&self, host: &str) -> Result<()> {
+        net::check_bind(host, self.token.as_deref())?;
+        let listener = net::bind_tcp(host, self.port, "Sync server").await?;
+        println!("  Sync server listening on {}", listener.local_addr()?);
 
         loop {
             let (socket, peer_addr) = listener.accept().await?;
             println!("  Sync connection from {}", peer_addr);
 
             let sync_file = self.sync_file();
+            let token = self.token.clone();
+            let dry_run = self.dry_run;
             tokio::spawn(async move {
-                if let Err(e) = handle_sync_connection(socket, sync_file).await {
+                if let Err(e) = handle_sync_connection(socket, sync_file, token, dry_run).await {
                     eprintln!("  Sync error: {}", e);
                 }
             });
@@ -43,14 +76,21 @@ impl P2PSync {
 
     /// Send local data to a peer
     pub async fn push_to_peer(&self, peer_addr: &str) -> Result<SyncResult> {
-        let mut stream = TcpStream::connect(peer_addr).await?;
-
         // Read local CRDT document
         let local_data = if self.sync_file().exists() {
             std::fs::read(self.sync_file())?
         } else {
             return Err(anyhow::anyhow!("No local sync data found"));
         };
+        if self.dry_run {
+            return Ok(SyncResult {
+                bytes_sent: 0,
+                bytes_received: 0,
+                status: dry_run::would(format!("push {} bytes to {}", local_data.len(), peer_addr)),
+            });
+        }
+
+        let mut stream = self.connect(peer_addr).await?;
 
         // Send PUSH command
         stream.write_all(b"PUSH").await?;
@@ -62,6 +102,7 @@ impl P2PSync {
         stream.read_exact(&mut response).await?;
 
         if &response == b"OK  " {
+            metrics::record_sync("push", true);
             Ok(SyncResult {
                 bytes_sent: local_data.len(),
                 bytes_received: 0,
@@ -74,7 +115,7 @@ impl P2PSync {
 
     /// Pull data from a peer
     pub async fn pull_from_peer(&self, peer_addr: &str) -> Result<(Vec<u8>, SyncResult)> {
-        let mut stream = TcpStream::connect(peer_addr).await?;
+        let mut stream = self.connect(peer_addr).await?;
 
         // Send PULL command
         stream.write_all(b"PULL").await?;
@@ -87,6 +128,7 @@ impl P2PSync {
         // Read data
         let mut data = vec![0u8; len];
         stream.read_exact(&mut data).await?;
+        metrics::record_sync("pull", true);
 
         Ok((data, SyncResult {
             bytes_sent: 4,
@@ -97,8 +139,6 @@ impl P2PSync {
 
     /// Sync with a peer (bidirectional merge)
     pub async fn sync_with_peer(&self, peer_addr: &str) -> Result<(Vec<u8>, SyncResult)> {
-        let mut stream = TcpStream::connect(peer_addr).await?;
-
         // Read local CRDT document
         let local_data = if self.sync_file().exists() {
             std::fs::read(self.sync_file())?
@@ -106,6 +146,15 @@ impl P2PSync {
             vec![]
         };
 
+        // Pulling changes nothing on the peer, so a dry run still shows what it has
+        if self.dry_run {
+            let (remote_data, mut result) = self.pull_from_peer(peer_addr).await?;
+            result.status = dry_run::would(format!("send {} bytes to {}", local_data.len(), peer_addr));
+            return Ok((remote_data, result));
+        }
+
+        let mut stream = self.connect(peer_addr).await?;
+
         // Send SYNC command with our data
         stream.write_all(b"SYNC").await?;
         stream.write_all(&(local_data.len() as u64).to_be_bytes()).await?;
@@ -118,6 +167,7 @@ impl P2PSync {
 
         let mut remote_data = vec![0u8; len];
         stream.read_exact(&mut remote_data).await?;
+        metrics::record_sync("sync", true);
 
         Ok((remote_data, SyncResult {
             bytes_sent: local_data.len(),
@@ -141,10 +191,29 @@ impl P2PSync {
 }
 
 #[allow(dead_code)]
-async fn handle_sync_connection(mut socket: TcpStream, sync_file: PathBuf) -> Result<()> {
+async fn handle_sync_connection(mut socket: TcpStream, sync_file: PathBuf, token: Option<String>, dry_run: bool) -> Result<()> {
     let mut cmd = [0u8; 4];
     socket.read_exact(&mut cmd).await?;
 
+    // Peers authenticate with an `AUTH` preamble before the command
+    let mut given = None;
+    if &cmd == b"AUTH" {
+        let mut len_bytes = [0u8; 8];
+        socket.read_exact(&mut len_bytes).await?;
+        let len = u64::from_be_bytes(len_bytes) as usize;
+        if len > 1024 {
+            return Err(anyhow::anyhow!("Token too long"));
+        }
+
+        let mut bytes = vec![0u8; len];
+        socket.read_exact(&mut bytes).await?;
+        given = Some(String::from_utf8_lossy(&bytes).to_string());
+        socket.read_exact(&mut cmd).await?;
+    }
+    if !net::token_matches(token.as_deref(), given.as_deref()) {
+        return Err(anyhow::anyhow!("Invalid or missing token"));
+    }
+
     match &cmd {
         b"PUSH" => {
             // Receive data from peer
@@ -157,11 +226,16 @@ async fn handle_sync_connection(mut socket: TcpStream, sync_file: PathBuf) -> Re
 
             // Save to temp file and merge
             let temp_file = sync_file.with_extension("incoming");
-            std::fs::write(&temp_file, &data)?;
+            if dry_run {
+                dry_run::report(dry_run::describe_write(&temp_file, &String::from_utf8_lossy(&data)));
+            } else {
+                std::fs::write(&temp_file, &data)?;
+            }
 
             // TODO: Merge with local using CRDT
             // For now, just acknowledge
             socket.write_all(b"OK  ").await?;
+            metrics::record_sync("push", false);
         }
         b"PULL" => {
             // Send our data to peer
@@ -173,6 +247,7 @@ async fn handle_sync_connection(mut socket: TcpStream, sync_file: PathBuf) -> Re
 
             socket.write_all(&(data.len() as u64).to_be_bytes()).await?;
             socket.write_all(&data).await?;
+            metrics::record_sync("pull", false);
         }
         b"SYNC" => {
             // Bidirectional sync
@@ -194,6 +269,8 @@ async fn handle_sync_connection(mut socket: TcpStream, sync_file: PathBuf) -> Re
             socket.write_all(&(local_data.len() as u64).to_be_bytes()).await?;
             socket.write_all(&local_data).await?;
 
+            metrics::record_sync("sync", false);
+
             // TODO: Actually merge the CRDTs
         }
         _ => {
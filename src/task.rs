@@ -0,0 +1,212 @@
+//! Plan-and-execute tasks: the model breaks a goal into tool steps, which run in
+//! order and stop for confirmation before a file is written or a command run
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::llm::LlmClient;
+use crate::patch::FileChange;
+use crate::prompts::PromptLibrary;
+
+/// Most steps a plan may have; the rest are dropped
+pub const MAX_TASK_STEPS: usize = 10;
+
+/// Most characters of one step's output handed on to later edits
+const MAX_NOTE_CHARS: usize = 2000;
+
+const PLAN_SYSTEM_PROMPT: &str = r#"You plan how to reach a goal in a codebase using these tools:
+- search <description>: find the code a description is about
+- grep <regex>: find lines matching a regular expression
+- read <file>: read a file
+- edit <file>: change a file as "instruction" says
+- run <command>: run a shell command in the project root, such as its tests
+Reply with JSON only: {"steps": [{"tool": "...", "input": "...", "instruction": "..."}]}
+Use at most 10 steps. Read a file before editing it, and run the tests after editing."#;
+
+/// What a step does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tool {
+    Search,
+    Grep,
+    Read,
+    Edit,
+    Run,
+}
+
+impl Tool {
+    /// Whether the step writes files or runs commands, and so waits to be confirmed
+    pub fn needs_confirmation(&self) -> bool {
+        matches!(self, Tool::Edit | Tool::Run)
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Tool::Search => "search",
+            Tool::Grep => "grep",
+            Tool::Read => "read",
+            Tool::Edit => "edit",
+            Tool::Run => "run",
+        }
+    }
+}
+
+/// One step of a plan
+#[derive(Debug, Clone, Deserialize)]
+pub struct TaskStep {
+    pub tool: Tool,
+    /// Query, pattern, file or command, depending on the tool
+    #[serde(default)]
+    pub input: String,
+    /// What to change, for edits
+    #[serde(default)]
+    pub instruction: String,
+}
+
+impl TaskStep {
+    pub fn describe(&self) -> String {
+        match self.tool {
+            Tool::Edit => format!("edit {}: {}", self.input, self.instruction),
+            _ => format!("{} {}", self.tool.as_str(), self.input),
+        }
+    }
+}
+
+/// A goal and how far its plan has got
+#[derive(Debug, Clone)]
+pub struct Task {
+    pub goal: String,
+    pub steps: Vec<TaskStep>,
+    /// Index of the next step to run
+    pub next: usize,
+    /// Changes the edit step waiting for confirmation would write
+    pub pending: Vec<FileChange>,
+    /// One line per finished step
+    outcomes: Vec<String>,
+    /// What the finished steps found, for the edits after them
+    notes: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RawPlan {
+    #[serde(default)]
+    steps: Vec<serde_json::Value>,
+}
+
+impl Task {
+    pub fn new(goal: &str, steps: Vec<TaskStep>) -> Self {
+        Self {
+            goal: goal.to_string(),
+            steps,
+            next: 0,
+            pending: Vec::new(),
+            outcomes: Vec::new(),
+            notes: String::new(),
+        }
+    }
+
+    /// The step to run next, if any are left
+    pub fn current(&self) -> Option<&TaskStep> {
+        self.steps.get(self.next)
+    }
+
+    /// Finish the current step with a one-line `outcome`; `output` is what it found
+    pub fn record(&mut self, outcome: &str, output: &str) {
+        let Some(step) = self.current() else {
+            return;
+        };
+        let description = step.describe();
+        self.outcomes.push(format!("{}. {} - {}", self.next + 1, description, outcome));
+        if !output.trim().is_empty() {
+            let output: String = output.chars().take(MAX_NOTE_CHARS).collect();
+            self.notes.push_str(&format!("{}:\n{}\n\n", description, output.trim_end()));
+        }
+        self.pending.clear();
+        self.next += 1;
+    }
+
+    /// The instruction for an edit step, with the goal and what earlier steps found
+    pub fn edit_instruction(&self, step: &TaskStep) -> String {
+        let mut instruction = format!("{}\n\nThis is a step towards: {}", step.instruction, self.goal);
+        if !self.notes.is_empty() {
+            instruction.push_str(&format!("\n\nWhat the earlier steps found:\n{}", self.notes.trim_end()));
+        }
+        instruction
+    }
+
+    /// The plan with a mark on each finished step
+    pub fn plan_listing(&self) -> String {
+        self.steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| format!("  {} {}. {}", if i < self.next { "x" } else { " " }, i + 1, step.describe()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// The finished steps and how each went
+    pub fn report(&self) -> String {
+        if self.outcomes.is_empty() {
+            return "No steps ran.".to_string();
+        }
+        self.outcomes.join("\n")
+    }
+}
+
+/// Ask the model for the steps that reach `goal`; `files` are the indexed files
+pub async fn plan(llm: &LlmClient, prompts: &PromptLibrary, goal: &str, files: &[String]) -> Result<Vec<TaskStep>> {
+    let mut prompt = format!("Goal: {}\n\nFiles in the project:\n", goal);
+    for file in files {
+        prompt.push_str(&format!("- {}\n", file));
+    }
+
+    let system = prompts.system("task_plan", PLAN_SYSTEM_PROMPT);
+    let response = llm.generate_with(&prompt, Some(&system), &prompts.options("task_plan")).await?;
+    Ok(parse_plan(&response))
+}
+
+/// Steps of a JSON plan; steps naming an unknown tool or no input are left out
+fn parse_plan(response: &str) -> Vec<TaskStep> {
+    let (start, end) = match (response.find('{'), response.rfind('}')) {
+        (Some(start), Some(end)) if start < end => (start, end),
+        _ => return Vec::new(),
+    };
+    let raw: RawPlan = serde_json::from_str(&response[start..=end]).unwrap_or_default();
+    raw.steps
+        .into_iter()
+        .filter_map(|step| serde_json::from_value::<TaskStep>(step).ok())
+        .filter(|step| !step.input.trim().is_empty())
+        .take(MAX_TASK_STEPS)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_skips_unknown_tools_and_hands_findings_to_edits() {
+        let steps = parse_plan(
+            r#"Here is the plan:
+{"steps": [
+  {"tool": "read", "input": "src/cache.rs"},
+  {"tool": "deploy", "input": "production"},
+  {"tool": "edit", "input": "src/cache.rs", "instruction": "make the capacity configurable"},
+  {"tool": "run", "input": ""}
+]}"#,
+        );
+        let tools: Vec<Tool> = steps.iter().map(|s| s.tool).collect();
+        assert_eq!(tools, vec![Tool::Read, Tool::Edit]);
+
+        let mut task = Task::new("configurable cache size", steps);
+        task.record("12 lines", "pub struct Cache {");
+        let edit = task.current().unwrap().clone();
+        assert!(edit.tool.needs_confirmation());
+        assert_eq!(
+            task.edit_instruction(&edit),
+            "make the capacity configurable\n\nThis is a step towards: configurable cache size\n\n\
+             What the earlier steps found:\nread src/cache.rs:\npub struct Cache {"
+        );
+        assert_eq!(task.report(), "1. read src/cache.rs - 12 lines");
+    }
+}
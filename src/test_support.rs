@@ -0,0 +1,293 @@
+//! A stand-in Ollama and DeepSeek server for tests
+//!
+//! `mock_ollama()` starts one HTTP server per test process, and `servers()` and
+//! `overrides()` point clients at it, so the real clients run end to end without
+//! a model. It answers `/api/tags`, `/api/embeddings`,
+//! `/api/generate`, `/api/chat` and `/chat/completions`, streaming a reply a
+//! word at a time when asked to, and records every request it serves. Prompts
+//! given to `stall_on` are never answered, for tests that cancel them.
+
+use serde_json::{json, Value};
+use std::net::TcpListener as StdTcpListener;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::Overrides;
+use crate::llm::Servers;
+
+/// Model the server says it has
+pub const MOCK_MODEL: &str = "mock-coder";
+/// Embedding model the server says it has
+pub const MOCK_EMBEDDING_MODEL: &str = "nomic-embed-text";
+/// Reply to any prompt without a canned reply of its own
+pub const MOCK_REPLY: &str = "This is a canned answer from the mock model.";
+/// Length of the vectors `/api/embeddings` returns
+pub const EMBEDDING_DIMENSIONS: usize = 64;
+
+static URL: OnceLock<String> = OnceLock::new();
+static STATE: Mutex<State> = Mutex::new(State { requests: Vec::new(), replies: Vec::new(), stalls: Vec::new() });
+
+struct State {
+    requests: Vec<MockRequest>,
+    /// Prompt text to look for, and the reply to give when it is found
+    replies: Vec<(String, String)>,
+    /// Prompt text that gets no answer until the client hangs up
+    stalls: Vec<String>,
+}
+
+/// A request the server answered
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub path: String,
+    pub body: Value,
+}
+
+impl MockRequest {
+    /// Every prompt, system prompt and message the request carried; for
+    /// `/api/embeddings`, the text to embed
+    pub fn prompt(&self) -> String {
+        let mut parts: Vec<&str> = ["system", "prompt"].iter().filter_map(|key| self.body[key].as_str()).collect();
+        if let Some(messages) = self.body["messages"].as_array() {
+            parts.extend(messages.iter().filter_map(|m| m["content"].as_str()));
+        }
+        parts.join("\n")
+    }
+}
+
+/// URL of the shared mock server, started on first use
+///
+/// Every test in the process talks to the same server, so give canned replies
+/// and look up requests by text unique to the test.
+pub fn mock_ollama() -> &'static str {
+    URL.get_or_init(|| {
+        let listener = StdTcpListener::bind("127.0.0.1:0").expect("bind the mock server");
+        listener.set_nonblocking(true).expect("make the mock server non-blocking");
+        let url = format!("http://{}", listener.local_addr().expect("mock server address"));
+
+        // Its own runtime, so it outlives the runtime of whichever test started it
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("mock server runtime");
+            runtime.block_on(async move {
+                let listener = TcpListener::from_std(listener).expect("mock server listener");
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(async move {
+                        let _ = serve(stream).await;
+                    });
+                }
+            });
+        });
+        url
+    })
+}
+
+/// Both backends served by the mock server
+pub fn servers() -> Servers {
+    let url = mock_ollama();
+    Servers { ollama: Some(url.to_string()), deepseek: Some(url.to_string()) }
+}
+
+/// Overrides that point every client at the mock server
+pub fn overrides() -> Overrides {
+    Overrides { servers: servers(), ..Default::default() }
+}
+
+/// Answer prompts containing `needle` with `reply` instead of `MOCK_REPLY`
+pub fn reply_to(needle: &str, reply: &str) {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    state.replies.push((needle.to_string(), reply.to_string()));
+}
+
+/// Never answer prompts containing `needle`, as a model stuck generating would not
+pub fn stall_on(needle: &str) {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    state.stalls.push(needle.to_string());
+}
+
+/// Requests whose prompt, or text to embed, contains `needle`, oldest first
+pub fn requests_containing(needle: &str) -> Vec<MockRequest> {
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    state
+        .requests
+        .iter()
+        .filter(|r| r.prompt().contains(needle))
+        .cloned()
+        .collect()
+}
+
+/// The vector `/api/embeddings` returns for `text`: its words hashed into
+/// buckets and normalized, so texts sharing words come out similar
+pub fn embedding(text: &str) -> Vec<f32> {
+    let mut vector = vec![0.0f32; EMBEDDING_DIMENSIONS];
+    for word in text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()) {
+        // FNV-1a, stable across runs unlike the std hasher
+        let hash = word
+            .to_lowercase()
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325u64, |h, b| (h ^ b as u64).wrapping_mul(0x0100_0000_01b3));
+        vector[(hash % EMBEDDING_DIMENSIONS as u64) as usize] += 1.0;
+    }
+
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+    vector
+}
+
+/// Read one request, answer it and close the connection
+async fn serve(mut stream: TcpStream) -> std::io::Result<()> {
+    let Some((method, path, body)) = read_request(&mut stream).await? else {
+        return Ok(());
+    };
+    let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    let request = MockRequest { path: path.clone(), body };
+    let stalled = {
+        let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+        state.requests.push(request.clone());
+        state.stalls.iter().any(|needle| request.prompt().contains(needle.as_str()))
+    };
+    if stalled {
+        // Until the client gives up and closes the connection
+        let _ = stream.read(&mut [0u8; 1]).await;
+        return Ok(());
+    }
+
+    let stream_reply = request.body["stream"].as_bool().unwrap_or(false);
+    match (method.as_str(), path.as_str()) {
+        ("GET", "/api/tags") => {
+            let models = json!({ "models": [{ "name": MOCK_MODEL }, { "name": MOCK_EMBEDDING_MODEL }] });
+            write_json(&mut stream, &models).await
+        }
+        ("POST", "/api/embeddings") => {
+            let text = request.body["prompt"].as_str().unwrap_or_default();
+            write_json(&mut stream, &json!({ "embedding": embedding(text) })).await
+        }
+        ("POST", "/api/generate") => {
+            let reply = reply_for(&request);
+            if stream_reply {
+                let mut chunks: Vec<Value> = words(&reply).map(|word| json!({ "response": word, "done": false })).collect();
+                chunks.push(json!({ "response": "", "done": true }));
+                write_stream(&mut stream, "application/x-ndjson", chunks.iter().map(|c| format!("{}\n", c))).await
+            } else {
+                write_json(&mut stream, &json!({ "response": reply, "done": true })).await
+            }
+        }
+        ("POST", "/api/chat") => {
+            let reply = reply_for(&request);
+            let message = |content: &str| json!({ "role": "assistant", "content": content });
+            if stream_reply {
+                let mut chunks: Vec<Value> = words(&reply).map(|word| json!({ "message": message(word), "done": false })).collect();
+                chunks.push(json!({ "message": message(""), "done": true }));
+                write_stream(&mut stream, "application/x-ndjson", chunks.iter().map(|c| format!("{}\n", c))).await
+            } else {
+                write_json(&mut stream, &json!({ "message": message(&reply), "done": true })).await
+            }
+        }
+        ("POST", "/chat/completions") => {
+            let reply = reply_for(&request);
+            if stream_reply {
+                let mut events: Vec<String> = words(&reply)
+                    .map(|word| json!({ "choices": [{ "delta": { "content": word } }] }).to_string())
+                    .collect();
+                events.push("[DONE]".to_string());
+                write_stream(&mut stream, "text/event-stream", events.iter().map(|e| format!("data: {}\n\n", e))).await
+            } else {
+                let choice = json!({ "message": { "role": "assistant", "content": reply }, "finish_reason": "stop" });
+                write_json(&mut stream, &json!({ "choices": [choice] })).await
+            }
+        }
+        _ => write_response(&mut stream, "404 Not Found", "application/json", br#"{"error":"not found"}"#).await,
+    }
+}
+
+/// The method, path and body of the request on `stream`; `None` if it closes first
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Option<(String, String, Vec<u8>)>> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    let header_end = loop {
+        if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        data.extend_from_slice(&buf[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&data[..header_end]).to_string();
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+    let content_length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+
+    let mut body = data[header_end..].to_vec();
+    while body.len() < content_length {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        body.extend_from_slice(&buf[..n]);
+    }
+    Ok(Some((method, path, body)))
+}
+
+/// The canned reply for the first needle the prompt contains; `{}` when JSON
+/// is asked for and nothing was canned
+fn reply_for(request: &MockRequest) -> String {
+    let prompt = request.prompt();
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    match state.replies.iter().find(|(needle, _)| prompt.contains(needle.as_str())) {
+        Some((_, reply)) => reply.clone(),
+        None if request.body["format"] == "json" => "{}".to_string(),
+        None => MOCK_REPLY.to_string(),
+    }
+}
+
+/// `reply` a word at a time, each with the space after it
+fn words(reply: &str) -> impl Iterator<Item = &str> {
+    reply.split_inclusive(' ')
+}
+
+async fn write_json(stream: &mut TcpStream, value: &Value) -> std::io::Result<()> {
+    write_response(stream, "200 OK", "application/json", value.to_string().as_bytes()).await
+}
+
+async fn write_response(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> std::io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.shutdown().await
+}
+
+/// Send `lines` one write at a time, so clients see them arrive in separate chunks
+async fn write_stream(
+    stream: &mut TcpStream,
+    content_type: &str,
+    lines: impl Iterator<Item = String>,
+) -> std::io::Result<()> {
+    let head = format!("HTTP/1.1 200 OK\r\nContent-Type: {}\r\nConnection: close\r\n\r\n", content_type);
+    stream.write_all(head.as_bytes()).await?;
+
+    for line in lines {
+        stream.write_all(line.as_bytes()).await?;
+        stream.flush().await?;
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+    stream.shutdown().await
+}
@@ -0,0 +1,222 @@
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::postprocess;
+use crate::storage::CodebaseIndex;
+
+/// Test framework and conventions a project already uses, so generated tests fit in
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestConventions {
+    pub language: String,
+    pub framework: String,
+    pub notes: Vec<String>,
+}
+
+impl TestConventions {
+    /// Detect the conventions for `language` from the indexed files of the project
+    pub fn detect(index: &CodebaseIndex, language: &str) -> Option<Self> {
+        Self::from_evidence(language, |needle| index.count_files_containing(needle).unwrap_or(0))
+    }
+
+    /// Pick a framework from `count`, the number of files containing a snippet;
+    /// None when the project shows no sign of one
+    pub fn from_evidence(language: &str, count: impl Fn(&str) -> usize) -> Option<Self> {
+        let any = |needles: &[&str]| needles.iter().map(|n| count(n)).sum::<usize>();
+        let mut notes = Vec::new();
+
+        let framework = match language {
+            "rust" => {
+                if any(&["#[test]", "#[tokio::test]"]) == 0 {
+                    return None;
+                }
+                if count("#[tokio::test]") > 0 {
+                    notes.push("async functions are tested with #[tokio::test]".to_string());
+                }
+                if count("#[cfg(test)]") > 0 {
+                    notes.push("unit tests live in a #[cfg(test)] mod tests at the bottom of the file they test, with use super::*".to_string());
+                }
+                if count("tempfile::") > 0 {
+                    notes.push("tests that touch the filesystem use tempfile::tempdir()".to_string());
+                }
+                if count("#[rstest]") > 0 {
+                    notes.push("parameterized cases use #[rstest]".to_string());
+                }
+                if count("proptest!") > 0 {
+                    notes.push("property tests use proptest!".to_string());
+                }
+                "Rust's built-in #[test]"
+            }
+            "python" => {
+                let pytest = any(&["import pytest", "@pytest.", "[tool.pytest"]);
+                let unittest = any(&["unittest.TestCase"]);
+                if pytest == 0 && unittest == 0 {
+                    return None;
+                }
+                if pytest >= unittest {
+                    if count("@pytest.fixture") > 0 {
+                        notes.push("shared setup uses @pytest.fixture".to_string());
+                    }
+                    if count("@pytest.mark.parametrize") > 0 {
+                        notes.push("parameterized cases use @pytest.mark.parametrize".to_string());
+                    }
+                    notes.push("plain test_ functions with bare assert statements".to_string());
+                    "pytest"
+                } else {
+                    notes.push("test classes subclass unittest.TestCase and use self.assert* methods".to_string());
+                    "unittest"
+                }
+            }
+            "javascript" | "typescript" => {
+                let vitest = any(&["from 'vitest'", "from \"vitest\"", "\"vitest\":"]);
+                let jest = any(&["\"jest\":", "@jest/globals", "jest.fn(", "jest.mock("]);
+                let mocha = any(&["\"mocha\":"]);
+                let framework = if vitest > 0 && vitest >= jest && vitest >= mocha {
+                    notes.push("describe, it, expect and vi are imported from 'vitest'".to_string());
+                    "Vitest"
+                } else if jest > 0 && jest >= mocha {
+                    notes.push("mocks use jest.fn() and jest.mock()".to_string());
+                    "Jest"
+                } else if mocha > 0 {
+                    if count("from 'chai'") + count("require('chai')") > 0 {
+                        notes.push("assertions use chai's expect".to_string());
+                    }
+                    "Mocha"
+                } else {
+                    return None;
+                };
+                if count("@testing-library/") > 0 {
+                    notes.push("components are tested with Testing Library queries".to_string());
+                }
+                framework
+            }
+            "go" => {
+                if count("\"testing\"") == 0 {
+                    return None;
+                }
+                if count("github.com/stretchr/testify") > 0 {
+                    notes.push("assertions use testify's assert and require".to_string());
+                }
+                if count("t.Run(") > 0 {
+                    notes.push("cases are table-driven subtests run with t.Run".to_string());
+                }
+                "Go's testing package"
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            language: language.to_string(),
+            framework: framework.to_string(),
+            notes,
+        })
+    }
+
+    /// Instructions for the test-writing prompt
+    pub fn prompt_section(&self) -> String {
+        let mut section = format!(
+            "This {} project tests with {}. Follow its conventions:\n",
+            self.language, self.framework
+        );
+        for note in &self.notes {
+            section.push_str(&format!("- {}\n", note));
+        }
+        section
+    }
+}
+
+/// Best guess at the language of a code snippet, from keywords only it uses
+pub fn guess_language(code: &str) -> Option<&'static str> {
+    let has = |needles: &[&str]| needles.iter().any(|n| code.contains(n));
+
+    if has(&["fn ", "impl ", "let mut ", "pub struct ", "::new("]) && !has(&["function "]) {
+        Some("rust")
+    } else if has(&["def ", "import ", "self."]) && code.contains(':') && !has(&["{", ";"]) {
+        Some("python")
+    } else if has(&["func ", "package "]) && has(&[":="]) {
+        Some("go")
+    } else if has(&[": string", ": number", "interface ", "<T>"]) && has(&["function", "=>", "const "]) {
+        Some("typescript")
+    } else if has(&["function", "=>", "const ", "require("]) {
+        Some("javascript")
+    } else {
+        None
+    }
+}
+
+/// Fenced code blocks in a model response tagged `rust`/`rs`, or untagged
+pub fn rust_blocks(response: &str) -> Vec<String> {
+    postprocess::code_blocks(response)
+        .into_iter()
+        .filter(|block| matches!(block.language.as_str(), "" | "rust" | "rs"))
+        .map(|block| block.code)
+        .filter(|code| !code.trim().is_empty())
+        .collect()
+}
+
+/// Check that Rust code parses by formatting it with `rustfmt`
+///
+/// Returns None when `rustfmt` is not installed, otherwise the first error it reported.
+pub async fn check_rust_parses(code: &str) -> Option<Result<(), String>> {
+    let mut child = Command::new("rustfmt")
+        .args(["--edition", "2021", "--emit", "stdout"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .ok()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(code.as_bytes()).await;
+    }
+
+    let output = child.wait_with_output().await.ok()?;
+    if output.status.success() {
+        return Some(Ok(()));
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let error = stderr
+        .lines()
+        .find(|line| line.starts_with("error"))
+        .unwrap_or("rustfmt could not parse the code")
+        .to_string();
+    Some(Err(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn detect(language: &str, files: &[(&str, usize)]) -> Option<TestConventions> {
+        let counts: HashMap<&str, usize> = files.iter().cloned().collect();
+        TestConventions::from_evidence(language, |needle| counts.get(needle).copied().unwrap_or(0))
+    }
+
+    #[test]
+    fn test_frameworks_are_detected_from_evidence() {
+        let rust = detect("rust", &[("#[test]", 12), ("#[tokio::test]", 2), ("#[cfg(test)]", 10)]).unwrap();
+        assert_eq!(rust.framework, "Rust's built-in #[test]");
+        assert!(rust.notes.iter().any(|n| n.contains("#[tokio::test]")));
+        assert!(rust.prompt_section().contains("- unit tests live in a #[cfg(test)] mod tests"));
+
+        assert_eq!(detect("python", &[("import pytest", 3), ("unittest.TestCase", 1)]).unwrap().framework, "pytest");
+        assert_eq!(detect("python", &[("unittest.TestCase", 4)]).unwrap().framework, "unittest");
+        assert_eq!(detect("typescript", &[("from 'vitest'", 5), ("\"jest\":", 1)]).unwrap().framework, "Vitest");
+        assert_eq!(detect("javascript", &[("jest.fn(", 2)]).unwrap().framework, "Jest");
+        assert!(detect("python", &[]).is_none());
+        assert!(detect("ruby", &[("#[test]", 3)]).is_none());
+    }
+
+    #[test]
+    fn test_rust_blocks_and_language_guess() {
+        let response = "Here you go:\n```rust\n#[test]\nfn adds() {}\n```\n```toml\n[dev-dependencies]\n```\n```\nfn other() {}\n```\n";
+        assert_eq!(rust_blocks(response), vec!["#[test]\nfn adds() {}\n", "fn other() {}\n"]);
+
+        assert_eq!(guess_language("pub fn add(a: i32) -> i32 { a }"), Some("rust"));
+        assert_eq!(guess_language("def add(a, b):\n    return a + b"), Some("python"));
+        assert_eq!(guess_language("const add = (a: number) => a;"), Some("typescript"));
+        assert_eq!(guess_language("x + y"), None);
+    }
+}
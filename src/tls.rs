@@ -0,0 +1,221 @@
+//! TLS for the daemon's TCP, WebSocket and HTTP listeners, with a self-signed
+//! certificate made on first use when none is given, and for the CLI reaching them
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::crypto::CryptoProvider;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{crypto, ClientConfig, DigitallySignedStruct, ServerConfig, SignatureScheme};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use crate::net;
+
+/// Where the self-signed certificate and its key are kept, in the data directory
+const SELF_SIGNED_DIR: &str = "tls";
+
+/// A client connection, encrypted or not
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// The certificate a listener presents
+#[derive(Clone)]
+pub struct Tls {
+    acceptor: TlsAcceptor,
+    pub cert_path: PathBuf,
+    /// SHA-256 of the certificate, for clients to check it against
+    pub fingerprint: String,
+}
+
+impl Tls {
+    /// Load `cert` and `key` (PEM files)
+    pub fn load(cert: &Path, key: &Path) -> Result<Self> {
+        let certs = read_certs(cert)?;
+        let fingerprint = fingerprint(&certs[0]);
+        let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut read_pem(key)?.as_slice())
+            .with_context(|| format!("Failed to read the private key from {}", key.display()))?
+            .with_context(|| format!("{} holds no private key", key.display()))?;
+
+        let config = ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .context("The certificate does not match its key")?;
+        Ok(Self { acceptor: TlsAcceptor::from(Arc::new(config)), cert_path: cert.to_path_buf(), fingerprint })
+    }
+
+    /// The self-signed certificate in `data_dir`, made for `hosts` if there is none yet
+    pub fn self_signed(data_dir: &Path, hosts: &[String]) -> Result<Self> {
+        let dir = data_dir.join(SELF_SIGNED_DIR);
+        let (cert, key) = (self_signed_cert(data_dir), dir.join("key.pem"));
+        if !cert.exists() || !key.exists() {
+            let certified = rcgen::generate_simple_self_signed(hosts.to_vec()).context("Failed to make a certificate")?;
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(&cert, certified.cert.pem())?;
+            net::write_private(&key, certified.key_pair.serialize_pem().as_bytes())?;
+        }
+        Self::load(&cert, &key)
+    }
+}
+
+/// Where the self-signed certificate in `data_dir` is kept
+pub fn self_signed_cert(data_dir: &Path) -> PathBuf {
+    data_dir.join(SELF_SIGNED_DIR).join("cert.pem")
+}
+
+/// SHA-256 of the first certificate in `cert` (PEM), the one a listener presents
+pub fn cert_fingerprint(cert: &Path) -> Result<String> {
+    Ok(fingerprint(&read_certs(cert)?[0]))
+}
+
+/// Names a self-signed certificate is made for: loopback, this machine's name and
+/// the address listened on
+pub fn default_hosts(bind: &str) -> Vec<String> {
+    let mut hosts = vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()];
+    if let Some(name) = hostname::get().ok().and_then(|h| h.into_string().ok()) {
+        hosts.push(name);
+    }
+    if !matches!(bind, "0.0.0.0" | "::") {
+        hosts.push(bind.to_string());
+    }
+    hosts.dedup();
+    hosts
+}
+
+/// `stream`, after the TLS handshake when the listener has a certificate
+pub async fn accept(stream: TcpStream, tls: Option<&Tls>) -> Result<Box<dyn Connection>> {
+    match tls {
+        Some(tls) => Ok(Box::new(tls.acceptor.accept(stream).await.context("TLS handshake failed")?)),
+        None => Ok(Box::new(stream)),
+    }
+}
+
+/// Connect to `addr`, over TLS when `fingerprint` is given, trusting only the
+/// certificate with that SHA-256
+pub async fn connect(addr: &str, fingerprint: Option<&str>) -> Result<Box<dyn Connection>> {
+    let stream = TcpStream::connect(addr).await?;
+    let Some(fingerprint) = fingerprint else {
+        return Ok(Box::new(stream));
+    };
+
+    let provider = Arc::new(crypto::ring::default_provider());
+    let verifier = PinnedCertificate { fingerprint: fingerprint.to_string(), provider: provider.clone() };
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    // The certificate is pinned, so the name only has to be one rustls accepts
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host).trim_start_matches('[').trim_end_matches(']');
+    let name = ServerName::try_from(host.to_string()).or_else(|_| ServerName::try_from("localhost"))?;
+    let stream = TlsConnector::from(Arc::new(config)).connect(name, stream).await.context("TLS handshake failed")?;
+    Ok(Box::new(stream))
+}
+
+/// Trusts the one certificate whose SHA-256 the daemon printed, self-signed or not
+#[derive(Debug)]
+struct PinnedCertificate {
+    fingerprint: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        let presented = fingerprint(end_entity);
+        if same_fingerprint(&presented, &self.fingerprint) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(tokio_rustls::rustls::Error::General(format!(
+                "the daemon's certificate has SHA-256 {}, not {}",
+                presented, self.fingerprint
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// `AB:CD:...`, as browsers and `openssl x509 -fingerprint -sha256` show it
+fn fingerprint(cert: &CertificateDer) -> String {
+    Sha256::digest(cert.as_ref()).iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":")
+}
+
+/// Whether two fingerprints match, whatever their case or separators
+fn same_fingerprint(a: &str, b: &str) -> bool {
+    let hex = |f: &str| f.chars().filter(char::is_ascii_hexdigit).map(|c| c.to_ascii_uppercase()).collect::<String>();
+    hex(a) == hex(b)
+}
+
+/// The certificates in `cert` (PEM), at least one
+fn read_certs(cert: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut read_pem(cert)?.as_slice())
+        .collect::<Result<Vec<CertificateDer<'static>>, _>>()
+        .with_context(|| format!("Failed to read certificates from {}", cert.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("{} holds no certificate", cert.display());
+    }
+    Ok(certs)
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_signed_certificate_is_made_once() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let hosts = default_hosts("192.168.1.20");
+        assert!(hosts.contains(&"localhost".to_string()) && hosts.contains(&"192.168.1.20".to_string()));
+
+        let first = Tls::self_signed(data_dir.path(), &hosts).unwrap();
+        assert_eq!(first.fingerprint.len(), 32 * 3 - 1);
+        let again = Tls::self_signed(data_dir.path(), &hosts).unwrap();
+        assert_eq!(first.fingerprint, again.fingerprint);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let key = std::fs::metadata(data_dir.path().join("tls/key.pem")).unwrap();
+            assert_eq!(key.permissions().mode() & 0o777, 0o600);
+        }
+
+        let cert = data_dir.path().join("tls/cert.pem");
+        assert!(Tls::load(&cert, &cert).is_err(), "a certificate is not a key");
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 600;
+
+/// `[tools]` section of `sovereign.toml`: where build/test commands run
+///
+/// ```toml
+/// [tools]
+/// container = "myproject-dev"   # docker exec into this running container
+/// workdir = "/workspace"        # directory inside the container
+/// # devcontainer = true         # or use the project's .devcontainer via the devcontainer CLI
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolsConfig {
+    /// Name or id of a running container to `docker exec` into
+    pub container: Option<String>,
+    /// Working directory inside the container (default: the container's own)
+    pub workdir: Option<String>,
+    /// Run through `devcontainer exec` using the project's devcontainer.json
+    pub devcontainer: bool,
+    /// Kill commands that run longer than this many seconds
+    pub timeout_secs: u64,
+}
+
+impl Default for ToolsConfig {
+    fn default() -> Self {
+        Self {
+            container: None,
+            workdir: None,
+            devcontainer: false,
+            timeout_secs: DEFAULT_TIMEOUT_SECS,
+        }
+    }
+}
+
+/// Where a tool command is executed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecutionTarget {
+    /// Directly on this machine, in the project root
+    Host,
+    /// Inside a running container via `docker exec`
+    Docker {
+        container: String,
+        workdir: Option<String>,
+    },
+    /// Inside the project's devcontainer via the `devcontainer` CLI
+    Devcontainer,
+}
+
+impl ExecutionTarget {
+    pub fn describe(&self) -> String {
+        match self {
+            ExecutionTarget::Host => "host".to_string(),
+            ExecutionTarget::Docker { container, .. } => format!("container {}", container),
+            ExecutionTarget::Devcontainer => "devcontainer".to_string(),
+        }
+    }
+}
+
+/// Result of running a tool command
+#[derive(Debug, Clone)]
+pub struct ToolOutput {
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl ToolOutput {
+    /// Combined output suitable for showing to the user or an LLM
+    pub fn combined(&self) -> String {
+        match (self.stdout.trim().is_empty(), self.stderr.trim().is_empty()) {
+            (false, false) => format!("{}\n{}", self.stdout.trim_end(), self.stderr.trim_end()),
+            (false, true) => self.stdout.trim_end().to_string(),
+            (true, false) => self.stderr.trim_end().to_string(),
+            (true, true) => String::new(),
+        }
+    }
+}
+
+/// Runs build/test commands for a project, on the host or in its container
+#[derive(Debug, Clone)]
+pub struct ToolRunner {
+    project_root: PathBuf,
+    target: ExecutionTarget,
+    timeout: Duration,
+}
+
+impl ToolRunner {
+    pub fn new(project_root: &Path, config: &ToolsConfig) -> Self {
+        let target = if let Some(ref container) = config.container {
+            ExecutionTarget::Docker {
+                container: container.clone(),
+                workdir: config.workdir.clone(),
+            }
+        } else if config.devcontainer {
+            ExecutionTarget::Devcontainer
+        } else {
+            ExecutionTarget::Host
+        };
+
+        Self {
+            project_root: project_root.to_path_buf(),
+            target,
+            timeout: Duration::from_secs(config.timeout_secs),
+        }
+    }
+
+    pub fn target(&self) -> &ExecutionTarget {
+        &self.target
+    }
+
+    /// Program and arguments that run `command` through a shell on the configured target
+    pub fn command_line(&self, command: &str) -> (String, Vec<String>) {
+        let shell = vec!["sh".to_string(), "-c".to_string(), command.to_string()];
+
+        match &self.target {
+            ExecutionTarget::Host => {
+                if cfg!(windows) {
+                    ("cmd".to_string(), vec!["/C".to_string(), command.to_string()])
+                } else {
+                    ("sh".to_string(), vec!["-c".to_string(), command.to_string()])
+                }
+            }
+            ExecutionTarget::Docker { container, workdir } => {
+                let mut args = vec!["exec".to_string()];
+                if let Some(dir) = workdir {
+                    args.push("-w".to_string());
+                    args.push(dir.clone());
+                }
+                args.push(container.clone());
+                args.extend(shell);
+                ("docker".to_string(), args)
+            }
+            ExecutionTarget::Devcontainer => {
+                let mut args = vec![
+                    "exec".to_string(),
+                    "--workspace-folder".to_string(),
+                    self.project_root.to_string_lossy().to_string(),
+                ];
+                args.extend(shell);
+                ("devcontainer".to_string(), args)
+            }
+        }
+    }
+
+    /// Run a shell command on the configured target and capture its output
+    pub async fn run(&self, command: &str) -> Result<ToolOutput> {
+        let (program, args) = self.command_line(command);
+
+        let child = Command::new(&program)
+            .args(&args)
+            .current_dir(&self.project_root)
+            .kill_on_drop(true)
+            .output();
+
+        let output = tokio::time::timeout(self.timeout, child)
+            .await
+            .map_err(|_| anyhow::anyhow!("`{}` timed out after {}s", command, self.timeout.as_secs()))?
+            .with_context(|| format!("Failed to start {} (running on {})", program, self.target.describe()))?;
+
+        Ok(ToolOutput {
+            success: output.status.success(),
+            exit_code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_docker_command_line() {
+        let config = ToolsConfig {
+            container: Some("dev".to_string()),
+            workdir: Some("/workspace".to_string()),
+            ..ToolsConfig::default()
+        };
+        let runner = ToolRunner::new(Path::new("/repo"), &config);
+
+        let (program, args) = runner.command_line("cargo test");
+        assert_eq!(program, "docker");
+        assert_eq!(args, vec!["exec", "-w", "/workspace", "dev", "sh", "-c", "cargo test"]);
+    }
+
+    #[test]
+    fn test_devcontainer_command_line() {
+        let config = ToolsConfig {
+            devcontainer: true,
+            ..ToolsConfig::default()
+        };
+        let runner = ToolRunner::new(Path::new("/repo"), &config);
+
+        let (program, args) = runner.command_line("npm test");
+        assert_eq!(program, "devcontainer");
+        assert_eq!(args, vec!["exec", "--workspace-folder", "/repo", "sh", "-c", "npm test"]);
+    }
+}
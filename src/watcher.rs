@@ -1,47 +1,89 @@
 use anyhow::Result;
 use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
 
-const DEBOUNCE_DELAY: Duration = Duration::from_millis(500);
+use crate::git::GitOps;
+use crate::metrics;
+use crate::storage::excludes::IndexExcludes;
 
-/// Message sent to orchestrator for reindexing
-pub struct IndexMessage {
-    pub path: PathBuf,
-    pub response_tx: oneshot::Sender<Result<String, String>>,
-}
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(500);
+/// How long `shutdown` waits for a re-index in flight before abandoning it
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// File watcher for automatic re-indexing on file changes
 pub struct FileWatcher {
     watcher: RecommendedWatcher,
     watched_paths: HashSet<PathBuf>,
+    /// `.sovereignignore` and `index.exclude` rules per watched root
+    excludes: Arc<Mutex<HashMap<PathBuf, IndexExcludes>>>,
+    /// The git HEAD file of each watched root that is in a repository, and the
+    /// root; a change to it is a branch switch
+    heads: Arc<Mutex<HashMap<PathBuf, PathBuf>>>,
+    /// Git directories outside their root, watched for HEAD alone
+    git_dirs: HashMap<PathBuf, PathBuf>,
+    processor: JoinHandle<()>,
 }
 
 impl FileWatcher {
     pub fn new(request_tx: mpsc::Sender<super::daemon::OrchestratorMessage>) -> Result<Self> {
         let (tx, mut rx) = mpsc::channel::<Event>(100);
+        let excludes: Arc<Mutex<HashMap<PathBuf, IndexExcludes>>> = Arc::default();
+        let processor_excludes = excludes.clone();
+        let heads: Arc<Mutex<HashMap<PathBuf, PathBuf>>> = Arc::default();
+        let processor_heads = heads.clone();
 
-        // Spawn the event processor
-        tokio::spawn(async move {
+        // Spawn the event processor; it ends once the notify watcher, and with it `tx`, is dropped
+        let processor = tokio::spawn(async move {
             let mut pending_paths: HashSet<PathBuf> = HashSet::new();
+            // Roots whose checked-out branch changed
+            let mut switched_roots: HashSet<PathBuf> = HashSet::new();
             let mut last_event = Instant::now();
 
             loop {
                 tokio::select! {
-                    Some(event) = rx.recv() => {
+                    event = rx.recv() => {
+                        let Some(event) = event else { break };
+                        let excludes = processor_excludes.lock().unwrap_or_else(|e| e.into_inner());
+                        let heads = processor_heads.lock().unwrap_or_else(|e| e.into_inner());
                         for path in event.paths {
-                            if should_index(&path) {
+                            if let Some(root) = heads.get(&path) {
+                                switched_roots.insert(root.clone());
+                                continue;
+                            }
+                            let excluded = excludes.values().any(|e| e.is_excluded(&path, path.is_dir()));
+                            if should_index(&path) && !excluded {
                                 pending_paths.insert(path);
                             }
                         }
                         last_event = Instant::now();
                     }
                     _ = tokio::time::sleep(DEBOUNCE_DELAY) => {
-                        if !pending_paths.is_empty() && last_event.elapsed() >= DEBOUNCE_DELAY {
+                        if last_event.elapsed() < DEBOUNCE_DELAY {
+                            continue;
+                        }
+                        if !switched_roots.is_empty() {
+                            // Re-indexing the root also covers its files the checkout changed
+                            for root in switched_roots.drain() {
+                                println!("  Branch switched in {}, re-indexing...", root.display());
+                                pending_paths.retain(|path| !path.starts_with(&root));
+                                reindex(&request_tx, &root).await;
+                            }
+                        }
+                        if !pending_paths.is_empty() {
+                            let roots: Vec<PathBuf> = processor_excludes
+                                .lock()
+                                .unwrap_or_else(|e| e.into_inner())
+                                .keys()
+                                .cloned()
+                                .collect();
+
                             // Process pending changes
-                            process_changes(&request_tx, &pending_paths).await;
+                            process_changes(&request_tx, &pending_paths, &roots).await;
                             pending_paths.clear();
                         }
                     }
@@ -53,7 +95,10 @@ impl FileWatcher {
             if let Ok(event) = res {
                 match event.kind {
                     EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {
-                        let _ = tx.blocking_send(event);
+                        metrics::record_watcher_event();
+                        // Never block or panic in notify's thread: a full queue already
+                        // holds a change that re-indexes the whole root
+                        let _ = tx.try_send(event);
                     }
                     _ => {}
                 }
@@ -63,24 +108,88 @@ impl FileWatcher {
         Ok(Self {
             watcher,
             watched_paths: HashSet::new(),
+            excludes,
+            heads,
+            git_dirs: HashMap::new(),
+            processor,
         })
     }
 
-    /// Watch a directory for changes
-    pub fn watch(&mut self, path: &Path) -> Result<()> {
+    /// Stop watching everything and wait for the event processor to finish
+    /// a re-index in flight, for at most `SHUTDOWN_TIMEOUT`
+    pub async fn shutdown(self) {
+        let Self { watcher, processor, .. } = self;
+        drop(watcher);
+
+        let abort = processor.abort_handle();
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, processor).await.is_err() {
+            eprintln!("  File watcher did not stop in time, abandoning pending re-index");
+            abort.abort();
+        }
+    }
+
+    /// Watch a directory for changes, ignoring paths its `.sovereignignore`
+    /// or the `index.exclude` patterns exclude, and its git HEAD for branch switches
+    pub fn watch(&mut self, path: &Path, exclude_patterns: &[String]) -> Result<()> {
         let canonical = path.canonicalize()?;
 
         if self.watched_paths.contains(&canonical) {
             return Ok(());
         }
 
+        let excludes = IndexExcludes::load(&canonical, exclude_patterns)?;
         self.watcher.watch(&canonical, RecursiveMode::Recursive)?;
         self.watched_paths.insert(canonical.clone());
+        self.excludes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(canonical.clone(), excludes);
+        self.watch_head(&canonical);
 
         println!("  Watching: {}", canonical.display());
         Ok(())
     }
 
+    /// Watch the HEAD file of the repository `root` is in, if any
+    ///
+    /// Git replaces HEAD rather than writing to it, so the directory holding it
+    /// is watched; it is already covered when it lies under `root`.
+    fn watch_head(&mut self, root: &Path) {
+        let git = GitOps::new(root);
+        if !git.is_git_repo() {
+            return;
+        }
+        let Ok(head) = git.head_file() else { return };
+        let Some(git_dir) = head.parent().map(Path::to_path_buf) else { return };
+
+        if !git_dir.starts_with(root) {
+            if let Err(e) = self.watcher.watch(&git_dir, RecursiveMode::NonRecursive) {
+                eprintln!("  Not watching {} for branch switches: {}", git_dir.display(), e);
+                return;
+            }
+            self.git_dirs.insert(root.to_path_buf(), git_dir);
+        }
+        self.heads
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(head, root.to_path_buf());
+    }
+
+    /// Exclude what `exclude_patterns` and the `.sovereignignore` say now from a watched
+    /// directory, as after a reload of sovereign.toml
+    pub fn set_excludes(&mut self, path: &Path, exclude_patterns: &[String]) -> Result<()> {
+        let canonical = path.canonicalize()?;
+        if !self.watched_paths.contains(&canonical) {
+            return Ok(());
+        }
+        let excludes = IndexExcludes::load(&canonical, exclude_patterns)?;
+        self.excludes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(canonical, excludes);
+        Ok(())
+    }
+
     /// Stop watching a directory
     pub fn unwatch(&mut self, path: &Path) -> Result<()> {
         let canonical = path.canonicalize()?;
@@ -91,6 +200,20 @@ impl FileWatcher {
 
         self.watcher.unwatch(&canonical)?;
         self.watched_paths.remove(&canonical);
+        self.excludes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&canonical);
+        self.heads
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|_, root| *root != canonical);
+        if let Some(git_dir) = self.git_dirs.remove(&canonical) {
+            // Another watched root may share the repository
+            if !self.git_dirs.values().any(|dir| *dir == git_dir) {
+                let _ = self.watcher.unwatch(&git_dir);
+            }
+        }
 
         println!("  Stopped watching: {}", canonical.display());
         Ok(())
@@ -147,6 +270,7 @@ fn should_index(path: &Path) -> bool {
 async fn process_changes(
     request_tx: &mpsc::Sender<super::daemon::OrchestratorMessage>,
     paths: &HashSet<PathBuf>,
+    watched_roots: &[PathBuf],
 ) {
     if paths.is_empty() {
         return;
@@ -156,49 +280,55 @@ async fn process_changes(
 
     // Find the common root directory
     if let Some(first_path) = paths.iter().next() {
-        // Find the project root (look for common markers)
-        let mut root = first_path.clone();
-        while let Some(parent) = root.parent() {
-            if parent.join("Cargo.toml").exists()
-                || parent.join("package.json").exists()
-                || parent.join(".git").exists()
-                || parent.join("pyproject.toml").exists()
-            {
-                root = parent.to_path_buf();
-                break;
-            }
-            root = parent.to_path_buf();
-        }
-
-        // Send index command through channel
-        let (response_tx, response_rx) = oneshot::channel();
-        let msg = super::daemon::OrchestratorMessage {
-            input: format!("/index {}", root.display()),
-            response_tx,
+        // Re-index the watched directory the change is in, so it stays the indexed project
+        let root = match watched_roots.iter().find(|r| first_path.starts_with(r)) {
+            Some(root) => root.clone(),
+            None => project_root(first_path),
         };
+        reindex(request_tx, &root).await;
+    }
+}
 
-        if request_tx.send(msg).await.is_ok() {
-            match response_rx.await {
-                Ok(Ok(result)) => {
-                    println!("  Re-indexed: {}", result);
-                }
-                Ok(Err(e)) => {
-                    eprintln!("  Re-index error: {}", e);
-                }
-                Err(_) => {
-                    eprintln!("  Re-index error: response channel closed");
-                }
+/// Ask the orchestrator to re-index `root` and wait for it to finish
+async fn reindex(request_tx: &mpsc::Sender<super::daemon::OrchestratorMessage>, root: &Path) {
+    // Send index command through channel
+    let (response_tx, response_rx) = oneshot::channel();
+    let msg = super::daemon::OrchestratorMessage {
+        input: format!("/index {}", root.display()),
+        session: None,
+        tokens: None,
+        id: None,
+        progress: None,
+        response_tx,
+    };
+
+    if request_tx.send(msg).await.is_ok() {
+        match response_rx.await {
+            Ok(Ok(result)) => {
+                println!("  Re-indexed: {}", result);
+            }
+            Ok(Err(e)) => {
+                eprintln!("  Re-index error: {}", e);
+            }
+            Err(_) => {
+                eprintln!("  Re-index error: response channel closed");
             }
         }
     }
 }
 
-/// Simple incremental indexer for single file updates
-pub struct IncrementalIndexer;
-
-impl IncrementalIndexer {
-    /// Check if a file should be indexed
-    pub fn should_index(path: &Path) -> bool {
-        should_index(path)
+/// The nearest ancestor of `path` with a project marker such as `Cargo.toml` or `.git`
+fn project_root(path: &Path) -> PathBuf {
+    let mut root = path.to_path_buf();
+    while let Some(parent) = root.parent() {
+        if parent.join("Cargo.toml").exists()
+            || parent.join("package.json").exists()
+            || parent.join(".git").exists()
+            || parent.join("pyproject.toml").exists()
+        {
+            return parent.to_path_buf();
+        }
+        root = parent.to_path_buf();
     }
+    root
 }